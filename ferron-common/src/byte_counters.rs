@@ -0,0 +1,61 @@
+use std::sync::{
+  atomic::{AtomicU64, Ordering},
+  Arc,
+};
+
+/// Live counters of the bytes received in the request body and sent in the response body.
+///
+/// The counters are updated by the server while the bodies are transferred, so they can be read
+/// at any time by the modules implementing quotas or billing. Clones share the same counters.
+#[derive(Clone, Default)]
+pub struct RequestByteCounters {
+  bytes_received: Arc<AtomicU64>,
+  bytes_sent: Arc<AtomicU64>,
+}
+
+impl RequestByteCounters {
+  /// Creates a new `RequestByteCounters` instance with both counters set to zero.
+  ///
+  /// # Returns
+  ///
+  /// A new `RequestByteCounters` instance.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Retrieves the number of request body bytes received so far.
+  ///
+  /// # Returns
+  ///
+  /// The number of bytes received.
+  pub fn bytes_received(&self) -> u64 {
+    self.bytes_received.load(Ordering::Relaxed)
+  }
+
+  /// Retrieves the number of response body bytes sent so far.
+  ///
+  /// # Returns
+  ///
+  /// The number of bytes sent.
+  pub fn bytes_sent(&self) -> u64 {
+    self.bytes_sent.load(Ordering::Relaxed)
+  }
+
+  /// Adds to the number of request body bytes received. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `bytes`: The number of bytes received.
+  pub fn add_bytes_received(&self, bytes: u64) {
+    self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+  }
+
+  /// Adds to the number of response body bytes sent. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `bytes`: The number of bytes sent.
+  pub fn add_bytes_sent(&self, bytes: u64) {
+    self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+  }
+}
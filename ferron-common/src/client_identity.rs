@@ -0,0 +1,61 @@
+/// The identity of a client authenticated with a TLS client certificate or a raw public key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientIdentity {
+  public_key_pin: String,
+  subject: Option<String>,
+  subject_alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+  /// Creates a new `ClientIdentity` instance.
+  ///
+  /// # Parameters
+  ///
+  /// - `public_key_pin`: The SHA-256 pin of the client's public key, in the `sha256/<base64>` format.
+  /// - `subject`: The subject of the client certificate, or `None` if the client used a raw public key.
+  /// - `subject_alt_names`: The subject alternative names of the client certificate, in the `<type>:<value>` format.
+  ///
+  /// # Returns
+  ///
+  /// A new `ClientIdentity` instance with the provided parameters.
+  pub fn new(
+    public_key_pin: String,
+    subject: Option<String>,
+    subject_alt_names: Vec<String>,
+  ) -> Self {
+    Self {
+      public_key_pin,
+      subject,
+      subject_alt_names,
+    }
+  }
+
+  /// Retrieves the SHA-256 pin of the client's public key (Subject Public Key Info).
+  ///
+  /// # Returns
+  ///
+  /// The public key pin, in the `sha256/<base64>` format.
+  pub fn public_key_pin(&self) -> &str {
+    &self.public_key_pin
+  }
+
+  /// Retrieves the subject of the client certificate.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the subject distinguished name, or `None` if the client used a raw public key.
+  pub fn subject(&self) -> Option<&str> {
+    self.subject.as_deref()
+  }
+
+  /// Retrieves the subject alternative names of the client certificate.
+  ///
+  /// # Returns
+  ///
+  /// A slice containing the subject alternative names in the `<type>:<value>` format (for example,
+  /// `DNS:client.example.com`, `email:user@example.com`, `IP:192.0.2.1` or `URI:spiffe://example.com/service`).
+  /// The slice is empty if the certificate has no subject alternative names, or if the client used a raw public key.
+  pub fn subject_alt_names(&self) -> &[String] {
+    &self.subject_alt_names
+  }
+}
@@ -0,0 +1,83 @@
+use hyper::{body::Bytes, header, HeaderMap, Method, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+/// Computes a strong entity tag for the content generated by a module.
+///
+/// # Parameters
+///
+/// - `content`: The content of the response body.
+///
+/// # Returns
+///
+/// A quoted entity tag, which can be used as the value of the `ETag` header.
+pub fn content_etag(content: &[u8]) -> String {
+  let hash = Sha256::digest(content);
+  let opaque_tag: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+  format!("\"{}\"", opaque_tag)
+}
+
+/// Checks if the entity tag matches any of the entity tags in the `If-None-Match` header, using the weak comparison.
+///
+/// # Parameters
+///
+/// - `if_none_match`: The value of the `If-None-Match` header.
+/// - `etag`: The entity tag of the response.
+///
+/// # Returns
+///
+/// `true` if the entity tag matches, `false` otherwise.
+pub fn if_none_match_matches(if_none_match: &str, etag: &str) -> bool {
+  let if_none_match = if_none_match.trim();
+  if if_none_match == "*" {
+    return true;
+  }
+  let opaque_tag = |etag: &str| {
+    let etag = etag.trim();
+    etag.strip_prefix("W/").unwrap_or(etag).to_string()
+  };
+  let etag = opaque_tag(etag);
+  if_none_match
+    .split(',')
+    .any(|header_etag| opaque_tag(header_etag) == etag)
+}
+
+/// Attaches the entity tag to the response with the content generated by a module, and answers the conditional
+/// request with "304 Not Modified" if the client already has the same content.
+pub(crate) fn validate_response(
+  request_method: Option<&Method>,
+  request_headers: Option<&HeaderMap>,
+  response: Response<Bytes>,
+) -> Response<Bytes> {
+  let (mut response_parts, content) = response.into_parts();
+  if response_parts.status != StatusCode::OK {
+    return Response::from_parts(response_parts, content);
+  }
+
+  let etag = match response_parts
+    .headers
+    .get(header::ETAG)
+    .and_then(|etag| etag.to_str().ok())
+  {
+    Some(etag) => etag.to_string(),
+    None => {
+      let etag = content_etag(&content);
+      if let Ok(etag_value) = etag.parse() {
+        response_parts.headers.insert(header::ETAG, etag_value);
+      }
+      etag
+    }
+  };
+
+  let is_conditional_request = matches!(request_method, Some(&Method::GET) | Some(&Method::HEAD))
+    && request_headers
+      .and_then(|headers| headers.get(header::IF_NONE_MATCH))
+      .and_then(|if_none_match| if_none_match.to_str().ok())
+      .is_some_and(|if_none_match| if_none_match_matches(if_none_match, &etag));
+  if is_conditional_request {
+    response_parts.status = StatusCode::NOT_MODIFIED;
+    response_parts.headers.remove(header::CONTENT_LENGTH);
+    return Response::from_parts(response_parts, Bytes::new());
+  }
+
+  Response::from_parts(response_parts, content)
+}
@@ -1,14 +1,30 @@
-use std::{collections::HashMap, error::Error, future::Future, net::SocketAddr, pin::Pin};
+use std::{
+  collections::HashMap,
+  error::Error,
+  future::Future,
+  net::SocketAddr,
+  pin::Pin,
+  sync::{Arc, Mutex},
+};
 
 use async_channel::Sender;
 use async_trait::async_trait;
-use http_body_util::combinators::BoxBody;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
 use hyper::{body::Bytes, upgrade::Upgraded, HeaderMap, Request, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use tokio::runtime::Handle;
 use yaml_rust2::Yaml;
 
+mod byte_counters;
+mod client_identity;
+mod etag;
 mod log;
+mod module_abi;
+mod request_body;
+mod request_variables;
+mod response_body_filter;
+mod subrequest;
+mod task_scheduler;
 mod with_runtime;
 
 /// Contains information about a network socket, including remote and local addresses,
@@ -20,8 +36,20 @@ pub struct SocketData {
   pub local_addr: SocketAddr,
   /// Indicates if the connection is encrypted.
   pub encrypted: bool,
+  // Additional data set by the server for the modules (for example, the forward proxy user).
+  // The data is kept in the extensions, so that more data can be added without changing the public fields.
+  extensions: hyper::http::Extensions,
 }
 
+// The user authenticated with the "Proxy-Authorization" header, stored in the socket data extensions
+#[derive(Clone)]
+struct ProxyAuthUser(String);
+
+// The address of the peer the connection was accepted from, stored in the socket data extensions
+// when the remote address is replaced with the client address forwarded by a proxy
+#[derive(Clone, Copy)]
+struct PeerAddr(SocketAddr);
+
 impl SocketData {
   /// Creates a new `SocketData` instance.
   ///
@@ -39,13 +67,112 @@ impl SocketData {
       remote_addr,
       local_addr,
       encrypted,
+      extensions: hyper::http::Extensions::new(),
+    }
+  }
+
+  /// Sets the user authenticated with the "Proxy-Authorization" header for the forward proxy request. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `auth_user`: A string representing the authenticated user.
+  pub fn set_proxy_auth_user(&mut self, auth_user: String) {
+    self.extensions.insert(ProxyAuthUser(auth_user));
+  }
+
+  /// Retrieves the user authenticated with the "Proxy-Authorization" header for the forward proxy request
+  /// (for example, in `connect_proxy_request_handler`).
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the authenticated user, or `None` if the forward proxy request isn't authenticated.
+  pub fn get_proxy_auth_user(&self) -> Option<&str> {
+    self
+      .extensions
+      .get::<ProxyAuthUser>()
+      .map(|auth_user| auth_user.0.as_str())
+  }
+
+  /// Replaces the remote address with the client address forwarded by a proxy. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `remote_addr`: The client address forwarded by the proxy.
+  pub fn set_forwarded_remote_addr(&mut self, remote_addr: SocketAddr) {
+    if self.extensions.get::<PeerAddr>().is_none() {
+      self.extensions.insert(PeerAddr(self.remote_addr));
     }
+    self.remote_addr = remote_addr;
+  }
+
+  /// Retrieves the address of the peer the connection was accepted from (for example, a proxy).
+  /// It differs from `remote_addr` if the remote address was replaced with the client address forwarded by a proxy.
+  ///
+  /// # Returns
+  ///
+  /// The address of the peer the connection was accepted from.
+  pub fn get_peer_addr(&self) -> SocketAddr {
+    self
+      .extensions
+      .get::<PeerAddr>()
+      .map_or(self.remote_addr, |peer_addr| peer_addr.0)
   }
 }
 
+/// Live counters of the request and response body bytes. This is a type alias for `crate::byte_counters::RequestByteCounters`.
+pub type RequestByteCounters = crate::byte_counters::RequestByteCounters;
+
+/// The variables set for a request. This is a type alias for `crate::request_variables::RequestVariables`.
+pub type RequestVariables = crate::request_variables::RequestVariables;
+
+/// The identity of a client authenticated with TLS. This is a type alias for `crate::client_identity::ClientIdentity`.
+pub type ClientIdentity = crate::client_identity::ClientIdentity;
+
+/// A handler of the subrequests sent through the server's handler chain. This is a type alias for `crate::subrequest::SubrequestHandler`.
+pub type SubrequestHandler = crate::subrequest::SubrequestHandler;
+
+/// A request body read to the end, which can be inspected and replayed. This is a type alias for `crate::request_body::BufferedRequestBody`.
+pub type BufferedRequestBody = crate::request_body::BufferedRequestBody;
+
+/// The buffer of the request bodies provided by the server. This is a type alias for `crate::request_body::RequestBodyBuffer`.
+pub type RequestBodyBuffer = crate::request_body::RequestBodyBuffer;
+
+/// The error returned when the request body is too large to be buffered. This is a type alias for `crate::request_body::RequestBodyTooLarge`.
+pub type RequestBodyTooLarge = crate::request_body::RequestBodyTooLarge;
+
+/// The storage of the buffered request bodies kept outside of the memory, implemented by the server.
+pub use crate::request_body::BufferedBodyStorage;
+
+/// The response body filters added by the modules for the request. This is a type alias for `crate::response_body_filter::ResponseBodyFilters`.
+pub type ResponseBodyFilters = crate::response_body_filter::ResponseBodyFilters;
+
+/// A streaming transformation of the response bodies, implemented by the modules.
+pub use crate::response_body_filter::ResponseBodyFilter;
+
+/// A scheduler of the background jobs of the modules. This is a type alias for `crate::task_scheduler::TaskScheduler`.
+pub type TaskScheduler = crate::task_scheduler::TaskScheduler;
+
+/// A background job scheduled with the `TaskScheduler`. This is a type alias for `crate::task_scheduler::ScheduledTask`.
+pub type ScheduledTask = crate::task_scheduler::ScheduledTask;
+
+/// Functions for the entity tag validation of the content generated by the modules.
+pub use crate::etag::{content_etag, if_none_match_matches};
+
+/// The module ABI version, the capabilities the modules can require, and the modules' ABI declaration.
+pub use crate::module_abi::{
+  ModuleAbi, MODULE_ABI_VERSION, MODULE_CAPABILITY_BYTE_COUNTERS,
+  MODULE_CAPABILITY_CLIENT_IDENTITY, MODULE_CAPABILITY_CONFIG_VALIDATION,
+  MODULE_CAPABILITY_REQUEST_BODY_BUFFERING, MODULE_CAPABILITY_REQUEST_VARIABLES,
+  MODULE_CAPABILITY_RESPONSE_BODY_FILTERS, MODULE_CAPABILITY_SUBREQUESTS,
+  SUPPORTED_MODULE_CAPABILITIES,
+};
+
 /// Represents a log message. This is a type alias for `crate::log::LogMessage`.
 pub type LogMessage = crate::log::LogMessage;
 
+/// Represents the level of an error log message. This is a type alias for `crate::log::ErrorLogLevel`.
+pub type ErrorLogLevel = crate::log::ErrorLogLevel;
+
 /// Represents the server configuration object. This is a type alias for `Yaml` from the `yaml_rust2` crate.
 pub type ServerConfig = Yaml;
 
@@ -108,6 +235,123 @@ impl RequestData {
     }
   }
 
+  /// Retrieves the live counters of the bytes received in the request body and sent in the response body.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the byte counters, or `None` if the request isn't counted by the server.
+  pub fn get_byte_counters(&self) -> Option<&RequestByteCounters> {
+    self.hyper_request.extensions().get::<RequestByteCounters>()
+  }
+
+  /// Retrieves the variables set for the request, which can be substituted in the server configuration.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the request variables, or `None` if the server doesn't support variables for the request.
+  pub fn get_variables(&self) -> Option<&RequestVariables> {
+    self.hyper_request.extensions().get::<RequestVariables>()
+  }
+
+  /// Retrieves the identity of the client authenticated with a TLS client certificate or a raw public key.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the client identity, or `None` if the client isn't authenticated with TLS.
+  pub fn get_client_identity(&self) -> Option<&ClientIdentity> {
+    self.hyper_request.extensions().get::<ClientIdentity>()
+  }
+
+  /// Retrieves the handler of the subrequests, which are sent through the server's handler chain.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the subrequest handler, or `None` if the server doesn't support subrequests for the request.
+  pub fn get_subrequest_handler(&self) -> Option<&SubrequestHandler> {
+    self.hyper_request.extensions().get::<SubrequestHandler>()
+  }
+
+  /// Reads the request body to the end, so that it can be inspected, and replaces it with a body replaying the buffered data
+  /// for the next handlers. The body larger than the memory limit of the server is stored in a temporary file.
+  /// If the body was already buffered (for example, by a previous module), the buffered body is reused.
+  ///
+  /// The "Content-Length" header is set to the size of the buffered body, and the "Transfer-Encoding" header is removed.
+  ///
+  /// # Parameters
+  ///
+  /// - `max_size`: The maximum size of the body.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the buffered body, or an error if the body can't be read or it exceeds the maximum size
+  /// (`RequestBodyTooLarge`). If the "Content-Length" header exceeds the maximum size, the body isn't read.
+  /// Otherwise, the body can't be read anymore after an error.
+  pub async fn buffer_body(
+    &mut self,
+    max_size: u64,
+  ) -> Result<BufferedRequestBody, Box<dyn Error + Send + Sync>> {
+    let buffered_body = match self.get_buffered_body() {
+      Some(buffered_body) => {
+        if buffered_body.size() > max_size {
+          Err(RequestBodyTooLarge { max_size })?
+        }
+        buffered_body.clone()
+      }
+      None => {
+        let content_length = self
+          .hyper_request
+          .headers()
+          .get(hyper::header::CONTENT_LENGTH)
+          .and_then(|content_length| content_length.to_str().ok())
+          .and_then(|content_length| content_length.parse::<u64>().ok());
+        if content_length.is_some_and(|content_length| content_length > max_size) {
+          Err(RequestBodyTooLarge { max_size })?
+        }
+        let body = std::mem::replace(
+          self.hyper_request.body_mut(),
+          Empty::new().map_err(|e| match e {}).boxed(),
+        );
+        let buffered_body = match self.hyper_request.extensions().get::<RequestBodyBuffer>() {
+          Some(request_body_buffer) => request_body_buffer.buffer(body, max_size).await?,
+          None => crate::request_body::buffer_body_in_memory(body, max_size).await?,
+        };
+        self
+          .hyper_request
+          .extensions_mut()
+          .insert(buffered_body.clone());
+        buffered_body
+      }
+    };
+
+    *self.hyper_request.body_mut() = buffered_body.to_body();
+    let headers = self.hyper_request.headers_mut();
+    headers.remove(hyper::header::TRANSFER_ENCODING);
+    headers.insert(
+      hyper::header::CONTENT_LENGTH,
+      hyper::header::HeaderValue::from(buffered_body.size()),
+    );
+    Ok(buffered_body)
+  }
+
+  /// Retrieves the request body buffered with `buffer_body`, for example by a previous module.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the buffered body, or `None` if the body wasn't buffered.
+  pub fn get_buffered_body(&self) -> Option<&BufferedRequestBody> {
+    self.hyper_request.extensions().get::<BufferedRequestBody>()
+  }
+
+  /// Retrieves the response body filters for the request. The filters added by the modules transform the response body
+  /// chunk by chunk, after the response modifying handlers run.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the response body filters, or `None` if the server doesn't support response body filters for the request.
+  pub fn get_response_body_filters(&self) -> Option<&ResponseBodyFilters> {
+    self.hyper_request.extensions().get::<ResponseBodyFilters>()
+  }
+
   /// Provides a reference to the underlying Hyper `Request` object.
   ///
   /// # Returns
@@ -139,6 +383,9 @@ impl RequestData {
 /// Facilitates logging of error messages through a provided logger sender.
 pub struct ErrorLogger {
   logger: Option<Sender<LogMessage>>,
+  source: Option<Arc<str>>,
+  captured_messages: Option<Arc<Mutex<Vec<String>>>>,
+  level_override: Option<ErrorLogLevel>,
 }
 
 impl ErrorLogger {
@@ -154,6 +401,9 @@ impl ErrorLogger {
   pub fn new(logger: Sender<LogMessage>) -> Self {
     ErrorLogger {
       logger: Some(logger),
+      source: None,
+      captured_messages: None,
+      level_override: None,
     }
   }
 
@@ -163,10 +413,72 @@ impl ErrorLogger {
   ///
   /// A new `ErrorLogger` instance not associated with any logger.
   pub fn without_logger() -> Self {
-    ErrorLogger { logger: None }
+    ErrorLogger {
+      logger: None,
+      source: None,
+      captured_messages: None,
+      level_override: None,
+    }
   }
 
-  /// Logs an error message asynchronously.
+  /// Creates a new `ErrorLogger` instance, which attributes the logged messages to the specified source.
+  /// The source is used for the deduplication and the rate limiting of the error log messages.
+  ///
+  /// # Parameters
+  ///
+  /// - `source`: The name of the source of the log messages (for example, the name of the module).
+  ///
+  /// # Returns
+  ///
+  /// A new `ErrorLogger` instance associated with the same logger.
+  pub fn with_source(&self, source: Arc<str>) -> Self {
+    ErrorLogger {
+      logger: self.logger.clone(),
+      source: Some(source),
+      captured_messages: self.captured_messages.clone(),
+      level_override: self.level_override,
+    }
+  }
+
+  /// Creates a new `ErrorLogger` instance, which also records the logged messages in the specified list,
+  /// even if there is no underlying logger. The recorded messages are shown in the extended error diagnostics.
+  ///
+  /// # Parameters
+  ///
+  /// - `captured_messages`: The list, to which the logged messages are appended.
+  ///
+  /// # Returns
+  ///
+  /// A new `ErrorLogger` instance associated with the same logger.
+  pub fn with_captured_messages(&self, captured_messages: Arc<Mutex<Vec<String>>>) -> Self {
+    ErrorLogger {
+      logger: self.logger.clone(),
+      source: self.source.clone(),
+      captured_messages: Some(captured_messages),
+      level_override: self.level_override,
+    }
+  }
+
+  /// Creates a new `ErrorLogger` instance, which overrides the minimum error log level for the logged messages.
+  /// This allows the verbose logging to be enabled for specific requests only.
+  ///
+  /// # Parameters
+  ///
+  /// - `level_override`: The most verbose level of the messages, which are written regardless of the configured minimum level.
+  ///
+  /// # Returns
+  ///
+  /// A new `ErrorLogger` instance associated with the same logger.
+  pub fn with_level_override(&self, level_override: ErrorLogLevel) -> Self {
+    ErrorLogger {
+      logger: self.logger.clone(),
+      source: self.source.clone(),
+      captured_messages: self.captured_messages.clone(),
+      level_override: Some(level_override),
+    }
+  }
+
+  /// Logs an error message asynchronously, at the `Error` level.
   ///
   /// # Parameters
   ///
@@ -184,11 +496,70 @@ impl ErrorLogger {
   /// # }
   /// ```
   pub async fn log(&self, message: &str) {
+    self.log_with_level(ErrorLogLevel::Error, message).await
+  }
+
+  /// Logs a warning message asynchronously, at the `Warn` level.
+  ///
+  /// # Parameters
+  ///
+  /// - `message`: A string slice containing the warning message to be logged.
+  pub async fn warn(&self, message: &str) {
+    self.log_with_level(ErrorLogLevel::Warn, message).await
+  }
+
+  /// Logs an informational message asynchronously, at the `Info` level.
+  ///
+  /// # Parameters
+  ///
+  /// - `message`: A string slice containing the informational message to be logged.
+  pub async fn info(&self, message: &str) {
+    self.log_with_level(ErrorLogLevel::Info, message).await
+  }
+
+  /// Logs a debug message asynchronously, at the `Debug` level.
+  ///
+  /// # Parameters
+  ///
+  /// - `message`: A string slice containing the debug message to be logged.
+  pub async fn debug(&self, message: &str) {
+    self.log_with_level(ErrorLogLevel::Debug, message).await
+  }
+
+  /// Logs a trace message asynchronously, at the `Trace` level.
+  ///
+  /// # Parameters
+  ///
+  /// - `message`: A string slice containing the trace message to be logged.
+  pub async fn trace(&self, message: &str) {
+    self.log_with_level(ErrorLogLevel::Trace, message).await
+  }
+
+  /// Logs a message asynchronously at the specified level. The server writes the message only if the level
+  /// is not more verbose than the minimum level configured globally or for the source of the message.
+  /// Only the errors and the warnings are recorded for the extended error diagnostics.
+  ///
+  /// # Parameters
+  ///
+  /// - `level`: The level of the message.
+  /// - `message`: A string slice containing the message to be logged.
+  pub async fn log_with_level(&self, level: ErrorLogLevel, message: &str) {
+    if level <= ErrorLogLevel::Warn {
+      if let Some(captured_messages) = &self.captured_messages {
+        if let Ok(mut captured_messages) = captured_messages.lock() {
+          captured_messages.push(String::from(message));
+        }
+      }
+    }
     if let Some(logger) = &self.logger {
-      logger
-        .send(LogMessage::new(String::from(message), true))
-        .await
-        .unwrap_or_default();
+      let mut log_message = LogMessage::new(String::from(message), true).with_level(level);
+      if let Some(source) = &self.source {
+        log_message = log_message.with_source(source.clone());
+      }
+      if let Some(level_override) = self.level_override {
+        log_message = log_message.with_level_override(level_override);
+      }
+      logger.send(log_message).await.unwrap_or_default();
     }
   }
 }
@@ -202,6 +573,9 @@ impl Clone for ErrorLogger {
   fn clone(&self) -> Self {
     ErrorLogger {
       logger: self.logger.clone(),
+      source: self.source.clone(),
+      captured_messages: self.captured_messages.clone(),
+      level_override: self.level_override,
     }
   }
 }
@@ -320,6 +694,30 @@ impl ResponseDataBuilder {
     self
   }
 
+  /// Sets the response with the content generated by the module (for example, a directory listing or a status page)
+  /// for the `ResponseData`, validated with an entity tag. A strong `ETag` computed from the content is attached
+  /// to a "200 OK" response, unless the response already has one. If the `If-None-Match` header of a `GET`
+  /// or `HEAD` request matches the entity tag, a "304 Not Modified" response is set instead, so the clients
+  /// polling for the dynamic but stable content don't download it again.
+  ///
+  /// # Parameters
+  ///
+  /// - `response`: A `Response` object with the complete content of the response body.
+  ///
+  /// # Returns
+  ///
+  /// The updated `ResponseDataBuilder` instance with the validated response.
+  pub fn validated_response(mut self, response: Response<Bytes>) -> Self {
+    let response = crate::etag::validate_response(
+      self.request.as_ref().map(|request| request.method()),
+      self.request.as_ref().map(|request| request.headers()),
+      response,
+    );
+    self.response =
+      Some(response.map(|content| Full::new(content).map_err(|e| match e {}).boxed()));
+    self
+  }
+
   /// Sets the status code for the `ResponseData`.
   ///
   /// # Parameters
@@ -479,6 +877,9 @@ impl ServerConfigRoot {
 pub trait ServerModuleHandlers {
   /// Handles an incoming request.
   ///
+  /// The request handlers are executed in the module order. The first module that returns a response (or a status code) handles the request,
+  /// and the request isn't passed to the modules after it. A module declines the request by returning a `ResponseData` with the request only.
+  ///
   /// # Parameters
   ///
   /// - `request`: A `RequestData` object containing the incoming request and associated data.
@@ -624,6 +1025,10 @@ pub trait ServerModuleHandlers {
 }
 
 /// Represents a server module that can provide handlers for processing requests.
+///
+/// The lifecycle hooks run on the server runtime, in the order the modules are loaded.
+/// Their default implementations do nothing.
+#[async_trait]
 pub trait ServerModule {
   /// Retrieves the handlers associated with the server module.
   ///
@@ -635,4 +1040,64 @@ pub trait ServerModule {
   ///
   /// A boxed object implementing `ServerModuleHandlers` that can be sent across threads.
   fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send>;
+
+  /// Called once the server has started, before the server accepts the connections.
+  /// The module can establish the connection pools, warm up the caches, or schedule its background jobs here.
+  ///
+  /// # Parameters
+  ///
+  /// - `config`: A reference to the global server configuration.
+  /// - `error_logger`: A reference to the `ErrorLogger` for logging errors.
+  /// - `task_scheduler`: A reference to the `TaskScheduler` for scheduling the background jobs, which are cancelled when the module is shut down.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` indicating success or failure. The server doesn't start if the hook fails.
+  async fn on_startup(
+    &self,
+    _config: &ServerConfigRoot,
+    _error_logger: &ErrorLogger,
+    _task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  /// Called when the module is initialized for the reloaded configuration, before the module handles the requests.
+  /// The hook is called instead of `on_startup` for the modules replacing the modules of the previous configuration.
+  ///
+  /// # Parameters
+  ///
+  /// - `config`: A reference to the reloaded global server configuration.
+  /// - `error_logger`: A reference to the `ErrorLogger` for logging errors.
+  /// - `task_scheduler`: A reference to the `TaskScheduler` for scheduling the background jobs, which are cancelled when the module is shut down.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` indicating success or failure. The reloaded configuration is rejected if the hook fails.
+  async fn on_config_reload(
+    &self,
+    _config: &ServerConfigRoot,
+    _error_logger: &ErrorLogger,
+    _task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  /// Called when the module stops handling the new requests, either because the server shuts down or restarts,
+  /// or because the module was replaced after the configuration reload. The module can flush its state here.
+  /// The requests already being handled by the module may still be in progress, while the module's background jobs are already cancelled.
+  ///
+  /// # Parameters
+  ///
+  /// - `error_logger`: A reference to the `ErrorLogger` for logging errors.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` indicating success or failure. The failures are logged.
+  async fn on_shutdown(
+    &self,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
 }
@@ -1,7 +1,66 @@
-/// Represents a log message with its content and error status.
+use std::sync::Arc;
+
+/// Represents the level of an error log message, from the most severe to the most verbose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorLogLevel {
+  /// An error, which prevented the request from being processed.
+  Error,
+  /// An unexpected condition, which didn't prevent the request from being processed.
+  Warn,
+  /// An informational message.
+  Info,
+  /// A message useful for debugging the configuration.
+  Debug,
+  /// A very detailed message, useful for debugging the modules.
+  Trace,
+}
+
+impl ErrorLogLevel {
+  /// Parses the name of the log level.
+  ///
+  /// # Parameters
+  ///
+  /// - `level`: The name of the log level (`error`, `warn`, `info`, `debug` or `trace`).
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the log level, or `None` if the name is not recognized.
+  pub fn parse(level: &str) -> Option<Self> {
+    match level {
+      "error" => Some(Self::Error),
+      "warn" => Some(Self::Warn),
+      "info" => Some(Self::Info),
+      "debug" => Some(Self::Debug),
+      "trace" => Some(Self::Trace),
+      _ => None,
+    }
+  }
+
+  /// Retrieves the name of the log level.
+  ///
+  /// # Returns
+  ///
+  /// The name of the log level, as accepted by `ErrorLogLevel::parse`.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Error => "error",
+      Self::Warn => "warn",
+      Self::Info => "info",
+      Self::Debug => "debug",
+      Self::Trace => "trace",
+    }
+  }
+}
+
+/// Represents a log message with its content, error status and source.
 pub struct LogMessage {
   is_error: bool,
   message: String,
+  source: Option<Arc<str>>,
+  level: ErrorLogLevel,
+  level_override: Option<ErrorLogLevel>,
+  access_log_sink: Option<usize>,
+  fields: Vec<(String, String)>,
 }
 
 impl LogMessage {
@@ -16,7 +75,135 @@ impl LogMessage {
   ///
   /// A `LogMessage` object containing the specified message and error status.
   pub fn new(message: String, is_error: bool) -> Self {
-    LogMessage { is_error, message }
+    LogMessage {
+      is_error,
+      message,
+      source: None,
+      level: ErrorLogLevel::Error,
+      level_override: None,
+      access_log_sink: None,
+      fields: Vec::new(),
+    }
+  }
+
+  /// Sets the source of the log message (for example, the name of the module that logged the message).
+  /// The messages without a source are attributed to the server itself.
+  ///
+  /// # Parameters
+  ///
+  /// - `source`: The name of the source of the log message.
+  ///
+  /// # Returns
+  ///
+  /// The `LogMessage` object with the specified source.
+  pub fn with_source(mut self, source: Arc<str>) -> Self {
+    self.source = Some(source);
+    self
+  }
+
+  /// Retrieves the source of the log message.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the name of the source of the log message, if it was set.
+  pub fn get_source(&self) -> Option<&str> {
+    self.source.as_deref()
+  }
+
+  /// Sets the level of the error log message. The error log messages have the `Error` level by default.
+  ///
+  /// # Parameters
+  ///
+  /// - `level`: The level of the error log message.
+  ///
+  /// # Returns
+  ///
+  /// The `LogMessage` object with the specified level.
+  pub fn with_level(mut self, level: ErrorLogLevel) -> Self {
+    self.level = level;
+    self
+  }
+
+  /// Retrieves the level of the error log message.
+  ///
+  /// # Returns
+  ///
+  /// The level of the error log message.
+  pub fn get_level(&self) -> ErrorLogLevel {
+    self.level
+  }
+
+  /// Sets the error log level override of the log message. The message is written if its level
+  /// is not more verbose than either the configured minimum level or the override.
+  ///
+  /// # Parameters
+  ///
+  /// - `level_override`: The minimum error log level overridden for the request, in which the message was logged.
+  ///
+  /// # Returns
+  ///
+  /// The `LogMessage` object with the specified error log level override.
+  pub fn with_level_override(mut self, level_override: ErrorLogLevel) -> Self {
+    self.level_override = Some(level_override);
+    self
+  }
+
+  /// Retrieves the error log level override of the log message.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the error log level override, if it was set.
+  pub fn get_level_override(&self) -> Option<ErrorLogLevel> {
+    self.level_override
+  }
+
+  /// Sets the access log sink the message is written to (the index of the sink in the "logging" section's "access" list).
+  /// The access log messages without a sink are written to the access log file ("logFilePath" property).
+  ///
+  /// # Parameters
+  ///
+  /// - `access_log_sink`: The index of the access log sink.
+  ///
+  /// # Returns
+  ///
+  /// The `LogMessage` object with the specified access log sink.
+  pub fn with_access_log_sink(mut self, access_log_sink: usize) -> Self {
+    self.access_log_sink = Some(access_log_sink);
+    self
+  }
+
+  /// Retrieves the access log sink the message is written to.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the index of the access log sink, if it was set.
+  pub fn get_access_log_sink(&self) -> Option<usize> {
+    self.access_log_sink
+  }
+
+  /// Adds a structured field to the log message. The structured fields are preserved by the log sinks
+  /// supporting them (for example, the systemd journal), and ignored by the other ones.
+  ///
+  /// # Parameters
+  ///
+  /// - `name`: The name of the field (uppercase letters, digits and underscores, for example `HTTP_STATUS`).
+  /// - `value`: The value of the field.
+  ///
+  /// # Returns
+  ///
+  /// The `LogMessage` object with the added field.
+  pub fn with_field(mut self, name: &str, value: String) -> Self {
+    self.fields.push((name.to_string(), value));
+    self
+  }
+
+  /// Takes the structured fields out of the log message.
+  ///
+  /// # Returns
+  ///
+  /// A `Vec` containing the names and the values of the structured fields.
+  pub fn take_fields(&mut self) -> Vec<(String, String)> {
+    std::mem::take(&mut self.fields)
   }
 
   /// Consumes the `LogMessage` and returns its components.
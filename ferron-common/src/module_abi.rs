@@ -0,0 +1,82 @@
+/// The version of the module ABI. It's increased when the interface between the server and the modules
+/// (the traits, the types passed to the modules, or the functions exported by the modules) changes incompatibly.
+pub const MODULE_ABI_VERSION: u32 = 3;
+
+/// The module exports the `server_module_validate_config` function, which validates the module's configuration properties.
+pub const MODULE_CAPABILITY_CONFIG_VALIDATION: u64 = 1 << 0;
+
+/// The module reads or sets the request variables (see `RequestData::get_variables`).
+pub const MODULE_CAPABILITY_REQUEST_VARIABLES: u64 = 1 << 1;
+
+/// The module reads the byte counters of the request (see `RequestData::get_byte_counters`).
+pub const MODULE_CAPABILITY_BYTE_COUNTERS: u64 = 1 << 2;
+
+/// The module reads the identity of the client authenticated with TLS (see `RequestData::get_client_identity`).
+pub const MODULE_CAPABILITY_CLIENT_IDENTITY: u64 = 1 << 3;
+
+/// The module sends the subrequests through the server's handler chain (see `RequestData::get_subrequest_handler`).
+pub const MODULE_CAPABILITY_SUBREQUESTS: u64 = 1 << 4;
+
+/// The module buffers the request body for the inspection (see `RequestData::buffer_body`).
+pub const MODULE_CAPABILITY_REQUEST_BODY_BUFFERING: u64 = 1 << 5;
+
+/// The module transforms the response bodies with the streaming filters (see `RequestData::get_response_body_filters`).
+pub const MODULE_CAPABILITY_RESPONSE_BODY_FILTERS: u64 = 1 << 6;
+
+/// All the capabilities supported by the server built with this version of `ferron-common`.
+pub const SUPPORTED_MODULE_CAPABILITIES: u64 = MODULE_CAPABILITY_CONFIG_VALIDATION
+  | MODULE_CAPABILITY_REQUEST_VARIABLES
+  | MODULE_CAPABILITY_BYTE_COUNTERS
+  | MODULE_CAPABILITY_CLIENT_IDENTITY
+  | MODULE_CAPABILITY_SUBREQUESTS
+  | MODULE_CAPABILITY_REQUEST_BODY_BUFFERING
+  | MODULE_CAPABILITY_RESPONSE_BODY_FILTERS;
+
+/// The ABI declaration of a module, returned by the `server_module_abi` function exported by the module.
+/// The declaration has the C layout, so that the server can read it even if the module was built
+/// against an incompatible version of `ferron-common`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModuleAbi {
+  /// The version of the module ABI the module was built against.
+  pub abi_version: u32,
+  /// The capabilities required by the module, as a combination of the `MODULE_CAPABILITY_*` flags.
+  pub capabilities: u64,
+}
+
+impl ModuleAbi {
+  /// Creates a new `ModuleAbi` instance for the current module ABI version.
+  ///
+  /// # Parameters
+  ///
+  /// - `capabilities`: The capabilities required by the module, as a combination of the `MODULE_CAPABILITY_*` flags.
+  ///
+  /// # Returns
+  ///
+  /// A new `ModuleAbi` instance with the current module ABI version and the provided capabilities.
+  pub const fn new(capabilities: u64) -> Self {
+    Self {
+      abi_version: MODULE_ABI_VERSION,
+      capabilities,
+    }
+  }
+}
+
+/// Declares the module ABI version and the capabilities required by the module, by exporting
+/// the `server_module_abi` function. The server refuses to load the modules without this declaration.
+///
+/// # Example
+///
+/// ```ignore
+/// ferron_common::declare_module_abi!(ferron_common::MODULE_CAPABILITY_CONFIG_VALIDATION);
+/// ```
+#[macro_export]
+macro_rules! declare_module_abi {
+  ($capabilities:expr) => {
+    /// Returns the module ABI version and the capabilities required by the module.
+    #[no_mangle]
+    pub extern "C" fn server_module_abi() -> $crate::ModuleAbi {
+      $crate::ModuleAbi::new($capabilities)
+    }
+  };
+}
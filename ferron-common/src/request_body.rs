@@ -0,0 +1,307 @@
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+
+/// The size of the chunks, in which the request body stored outside of the memory is read.
+const STORED_BODY_CHUNK_SIZE: usize = 65536;
+
+type BufferFuture =
+  Pin<Box<dyn Future<Output = Result<BufferedRequestBody, Box<dyn Error + Send + Sync>>> + Send>>;
+
+type ReadFuture = Pin<Box<dyn Future<Output = io::Result<Bytes>> + Send>>;
+
+/// The storage of a buffered request body kept outside of the memory (for example, in a temporary file).
+/// It's implemented by the server.
+#[async_trait]
+pub trait BufferedBodyStorage: Send + Sync {
+  /// Retrieves the size of the stored body.
+  ///
+  /// # Returns
+  ///
+  /// The size of the stored body in bytes.
+  fn size(&self) -> u64;
+
+  /// Reads a part of the stored body.
+  ///
+  /// # Parameters
+  ///
+  /// - `offset`: The offset of the part in the body.
+  /// - `length`: The maximum length of the part.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the part of the body, which is shorter than the requested length only at the end of the body.
+  async fn read_at(&self, offset: u64, length: usize) -> io::Result<Bytes>;
+}
+
+#[derive(Clone)]
+enum BufferedBodyData {
+  Memory(Bytes),
+  Stored(Arc<dyn BufferedBodyStorage>),
+}
+
+/// A request body read to the end, which can be inspected and replayed any number of times.
+/// Small bodies are kept in memory, while larger bodies are stored by the server (for example, in temporary files).
+/// Clones share the same data.
+#[derive(Clone)]
+pub struct BufferedRequestBody {
+  data: BufferedBodyData,
+}
+
+impl BufferedRequestBody {
+  /// Creates a new `BufferedRequestBody` instance kept in memory.
+  ///
+  /// # Parameters
+  ///
+  /// - `data`: The body data.
+  ///
+  /// # Returns
+  ///
+  /// A new `BufferedRequestBody` instance.
+  pub fn from_bytes(data: Bytes) -> Self {
+    Self {
+      data: BufferedBodyData::Memory(data),
+    }
+  }
+
+  /// Creates a new `BufferedRequestBody` instance kept in a storage outside of the memory. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `storage`: The storage of the body data.
+  ///
+  /// # Returns
+  ///
+  /// A new `BufferedRequestBody` instance.
+  pub fn from_storage(storage: Arc<dyn BufferedBodyStorage>) -> Self {
+    Self {
+      data: BufferedBodyData::Stored(storage),
+    }
+  }
+
+  /// Retrieves the size of the body.
+  ///
+  /// # Returns
+  ///
+  /// The size of the body in bytes.
+  pub fn size(&self) -> u64 {
+    match &self.data {
+      BufferedBodyData::Memory(data) => data.len() as u64,
+      BufferedBodyData::Stored(storage) => storage.size(),
+    }
+  }
+
+  /// Retrieves the body data, if the body is kept in memory.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing a reference to the body data, or `None` if the body is stored outside of the memory.
+  pub fn as_bytes(&self) -> Option<&Bytes> {
+    match &self.data {
+      BufferedBodyData::Memory(data) => Some(data),
+      BufferedBodyData::Stored(_) => None,
+    }
+  }
+
+  /// Reads the beginning of the body, for example for the inspection of a large body.
+  ///
+  /// # Parameters
+  ///
+  /// - `max_length`: The maximum length of the data read.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing up to `max_length` bytes from the beginning of the body.
+  pub async fn read_to_bytes(&self, max_length: usize) -> io::Result<Bytes> {
+    match &self.data {
+      BufferedBodyData::Memory(data) => Ok(data.slice(..data.len().min(max_length))),
+      BufferedBodyData::Stored(storage) => {
+        let length = (storage.size().min(max_length as u64)) as usize;
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+          let chunk = storage
+            .read_at(
+              data.len() as u64,
+              STORED_BODY_CHUNK_SIZE.min(length - data.len()),
+            )
+            .await?;
+          if chunk.is_empty() {
+            break;
+          }
+          data.extend_from_slice(&chunk);
+        }
+        Ok(Bytes::from(data))
+      }
+    }
+  }
+
+  /// Creates a body replaying the buffered data, for example to pass the request to the next handlers
+  /// or to the backend server. If the stored data can't be read, the body ends early.
+  ///
+  /// # Returns
+  ///
+  /// A boxed body replaying the buffered data.
+  pub fn to_body(&self) -> BoxBody<Bytes, hyper::Error> {
+    match &self.data {
+      BufferedBodyData::Memory(data) => Full::new(data.clone()).map_err(|e| match e {}).boxed(),
+      BufferedBodyData::Stored(storage) => ReplayedBody {
+        storage: storage.clone(),
+        offset: 0,
+        pending_read: Mutex::new(None),
+      }
+      .boxed(),
+    }
+  }
+}
+
+// A body replaying the data stored outside of the memory. The pending read is wrapped in a mutex,
+// because the boxed request bodies have to be shareable between the threads.
+struct ReplayedBody {
+  storage: Arc<dyn BufferedBodyStorage>,
+  offset: u64,
+  pending_read: Mutex<Option<ReadFuture>>,
+}
+
+impl Body for ReplayedBody {
+  type Data = Bytes;
+  type Error = hyper::Error;
+
+  fn poll_frame(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let size = self.storage.size();
+    if self.offset >= size {
+      return Poll::Ready(None);
+    }
+    let offset = self.offset;
+    let storage = self.storage.clone();
+    let poll_result = {
+      let pending_read = match self.pending_read.get_mut() {
+        Ok(pending_read) => pending_read,
+        Err(err) => err.into_inner(),
+      };
+      let read_future = pending_read.get_or_insert_with(|| {
+        let length = STORED_BODY_CHUNK_SIZE.min((size - offset) as usize);
+        Box::pin(async move { storage.read_at(offset, length).await })
+      });
+      let poll_result = read_future.as_mut().poll(cx);
+      if poll_result.is_ready() {
+        *pending_read = None;
+      }
+      poll_result
+    };
+    match poll_result {
+      Poll::Pending => Poll::Pending,
+      Poll::Ready(Ok(chunk)) if !chunk.is_empty() => {
+        self.offset += chunk.len() as u64;
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+      }
+      // The body ends early, if the stored data can't be read
+      Poll::Ready(_) => {
+        self.offset = size;
+        Poll::Ready(None)
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.offset >= self.storage.size()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    SizeHint::with_exact(self.storage.size().saturating_sub(self.offset))
+  }
+}
+
+/// The buffer of the request bodies, which reads the bodies to the end and stores the large bodies outside of the memory.
+/// It's provided by the server in the request extensions. Clones share the same buffer.
+#[derive(Clone)]
+pub struct RequestBodyBuffer {
+  handler: Arc<dyn Fn(BoxBody<Bytes, hyper::Error>, u64) -> BufferFuture + Send + Sync>,
+}
+
+impl RequestBodyBuffer {
+  /// Creates a new `RequestBodyBuffer` instance. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `handler`: A function reading the body up to the maximum size, and returning a future resolving to the buffered body.
+  ///   The future fails with `RequestBodyTooLarge` if the body exceeds the maximum size.
+  ///
+  /// # Returns
+  ///
+  /// A new `RequestBodyBuffer` instance.
+  pub fn new<F, Fut>(handler: F) -> Self
+  where
+    F: Fn(BoxBody<Bytes, hyper::Error>, u64) -> Fut + Send + Sync + 'static,
+    Fut:
+      Future<Output = Result<BufferedRequestBody, Box<dyn Error + Send + Sync>>> + Send + 'static,
+  {
+    Self {
+      handler: Arc::new(move |body, max_size| Box::pin(handler(body, max_size))),
+    }
+  }
+
+  /// Reads the body to the end.
+  ///
+  /// # Parameters
+  ///
+  /// - `body`: The body to read.
+  /// - `max_size`: The maximum size of the body.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the buffered body, or an error if the body can't be read or it exceeds the maximum size.
+  pub async fn buffer(
+    &self,
+    body: BoxBody<Bytes, hyper::Error>,
+    max_size: u64,
+  ) -> Result<BufferedRequestBody, Box<dyn Error + Send + Sync>> {
+    (self.handler)(body, max_size).await
+  }
+}
+
+/// The error returned when the request body exceeds the maximum size of the buffered body.
+#[derive(Debug)]
+pub struct RequestBodyTooLarge {
+  /// The maximum size of the buffered body.
+  pub max_size: u64,
+}
+
+impl fmt::Display for RequestBodyTooLarge {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "The request body exceeds the maximum size of {} bytes",
+      self.max_size
+    )
+  }
+}
+
+impl Error for RequestBodyTooLarge {}
+
+// Read the body to the end in memory, if the server doesn't provide the request body buffer
+pub(crate) async fn buffer_body_in_memory(
+  mut body: BoxBody<Bytes, hyper::Error>,
+  max_size: u64,
+) -> Result<BufferedRequestBody, Box<dyn Error + Send + Sync>> {
+  let mut data = Vec::new();
+  while let Some(frame) = body.frame().await {
+    if let Ok(chunk) = frame?.into_data() {
+      if data.len() as u64 + chunk.len() as u64 > max_size {
+        Err(RequestBodyTooLarge { max_size })?
+      }
+      data.extend_from_slice(&chunk);
+    }
+  }
+  Ok(BufferedRequestBody::from_bytes(Bytes::from(data)))
+}
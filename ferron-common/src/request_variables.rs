@@ -0,0 +1,54 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+/// The variables set for a request, which can be substituted in the server configuration (for example,
+/// in the URL rewrite replacements, the header values and the access log format).
+///
+/// The variables are set by the server from the configuration and by the modules (for example, the reverse proxy
+/// sets the `upstream_response_time` variable). Clones share the same variables.
+#[derive(Clone, Default)]
+pub struct RequestVariables {
+  variables: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl RequestVariables {
+  /// Creates a new `RequestVariables` instance without any variables.
+  ///
+  /// # Returns
+  ///
+  /// A new `RequestVariables` instance.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Retrieves the value of a variable.
+  ///
+  /// # Parameters
+  ///
+  /// - `name`: The name of the variable, without the `$` prefix.
+  ///
+  /// # Returns
+  ///
+  /// An `Option` containing the value of the variable, or `None` if the variable isn't set.
+  pub fn get(&self, name: &str) -> Option<String> {
+    self
+      .variables
+      .read()
+      .ok()
+      .and_then(|variables| variables.get(name).cloned())
+  }
+
+  /// Sets the value of a variable, replacing the previous value.
+  ///
+  /// # Parameters
+  ///
+  /// - `name`: The name of the variable, without the `$` prefix.
+  /// - `value`: The value of the variable.
+  pub fn set(&self, name: &str, value: String) {
+    if let Ok(mut variables) = self.variables.write() {
+      variables.insert(name.to_string(), value);
+    }
+  }
+}
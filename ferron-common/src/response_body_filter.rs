@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Response, StatusCode};
+
+/// A streaming transformation of the response bodies (for example, the HTML injection, the link rewriting,
+/// the compression, or the text substitution). The body is passed through the filter chunk by chunk, without buffering it.
+pub trait ResponseBodyFilter: Send {
+  /// Prepares the filter for the response. The filter can check the response headers (for example, the content type)
+  /// and adjust them (for example, set the "Content-Encoding" header). The filter isn't used, if it returns `false`.
+  ///
+  /// # Parameters
+  ///
+  /// - `status`: The status code of the response.
+  /// - `headers`: A mutable reference to the response headers.
+  ///
+  /// # Returns
+  ///
+  /// `true` if the response body is filtered, `false` otherwise.
+  fn prepare(&mut self, _status: StatusCode, _headers: &mut HeaderMap) -> bool {
+    true
+  }
+
+  /// Transforms a chunk of the body.
+  ///
+  /// # Parameters
+  ///
+  /// - `chunk`: The chunk of the body.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the transformed chunk. The filter can return an empty chunk to hold the data back
+  /// (for example, a text split between the chunks), and send it with the next chunks or at the end of the body.
+  /// An error ends the response body.
+  fn filter(&mut self, chunk: Bytes) -> Result<Bytes, Box<dyn Error + Send + Sync>>;
+
+  /// Ends the transformation, after the whole body was passed through the filter.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the data held back by the filter, sent at the end of the body.
+  fn finish(&mut self) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    Ok(Bytes::new())
+  }
+
+  /// Checks if the filter keeps the length of the body, so that the "Content-Length" header is kept.
+  ///
+  /// # Returns
+  ///
+  /// `true` if the filtered body has the same length as the original body, `false` otherwise.
+  fn preserves_length(&self) -> bool {
+    false
+  }
+}
+
+/// The response body filters added by the modules for the request. It's provided by the server in the request extensions,
+/// and the server passes the response body through the filters, in the order they were added, after the response modifying handlers run.
+/// Clones share the same filters.
+#[derive(Clone, Default)]
+pub struct ResponseBodyFilters {
+  filters: Arc<Mutex<Vec<Box<dyn ResponseBodyFilter>>>>,
+}
+
+impl ResponseBodyFilters {
+  /// Creates a new `ResponseBodyFilters` instance. This is called by the server.
+  ///
+  /// # Returns
+  ///
+  /// A new `ResponseBodyFilters` instance without filters.
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a filter to the end of the filter chain.
+  ///
+  /// # Parameters
+  ///
+  /// - `filter`: The response body filter.
+  pub fn add(&self, filter: Box<dyn ResponseBodyFilter>) {
+    match self.filters.lock() {
+      Ok(mut filters) => filters.push(filter),
+      Err(err) => err.into_inner().push(filter),
+    }
+  }
+
+  /// Checks if there are no filters in the filter chain.
+  ///
+  /// # Returns
+  ///
+  /// `true` if no filters were added, `false` otherwise.
+  pub fn is_empty(&self) -> bool {
+    match self.filters.lock() {
+      Ok(filters) => filters.is_empty(),
+      Err(err) => err.into_inner().is_empty(),
+    }
+  }
+
+  /// Passes the response body through the filters, which are removed from the filter chain. This is called by the server.
+  ///
+  /// The responses without the content (for example, "204 No Content" or "304 Not Modified") and the partial responses
+  /// aren't filtered. If any filter changes the length of the body, the "Content-Length" header is removed, so that
+  /// the body is sent with the chunked transfer encoding (or until the connection is closed in HTTP/1.0).
+  /// The ranges of the filtered body aren't served, and its entity tag is made weak.
+  ///
+  /// # Parameters
+  ///
+  /// - `response`: The response to filter.
+  ///
+  /// # Returns
+  ///
+  /// The response with the filtered body.
+  pub fn apply(
+    &self,
+    response: Response<BoxBody<Bytes, io::Error>>,
+  ) -> Response<BoxBody<Bytes, io::Error>> {
+    let filters = match self.filters.lock() {
+      Ok(mut filters) => std::mem::take(&mut *filters),
+      Err(err) => std::mem::take(&mut *err.into_inner()),
+    };
+    let status = response.status();
+    if filters.is_empty()
+      || status.is_informational()
+      || status == StatusCode::NO_CONTENT
+      || status == StatusCode::NOT_MODIFIED
+      || status == StatusCode::PARTIAL_CONTENT
+    {
+      return response;
+    }
+
+    let (mut response_parts, response_body) = response.into_parts();
+    let filters = filters
+      .into_iter()
+      .filter_map(|mut filter| {
+        filter
+          .prepare(status, &mut response_parts.headers)
+          .then_some(filter)
+      })
+      .collect::<Vec<_>>();
+    if filters.is_empty() {
+      return Response::from_parts(response_parts, response_body);
+    }
+
+    let headers = &mut response_parts.headers;
+    if !filters.iter().all(|filter| filter.preserves_length()) {
+      headers.remove(header::CONTENT_LENGTH);
+    }
+    headers.remove(header::ACCEPT_RANGES);
+    if let Some(etag) = headers.get(header::ETAG) {
+      if !etag.as_bytes().starts_with(b"W/") {
+        let mut weak_etag = b"W/".to_vec();
+        weak_etag.extend_from_slice(etag.as_bytes());
+        match HeaderValue::from_bytes(&weak_etag) {
+          Ok(weak_etag) => headers.insert(header::ETAG, weak_etag),
+          Err(_) => headers.remove(header::ETAG),
+        };
+      }
+    }
+
+    Response::from_parts(
+      response_parts,
+      FilteredBody {
+        inner: response_body,
+        filters: Mutex::new(filters),
+        trailers: None,
+        finished: false,
+      }
+      .boxed(),
+    )
+  }
+}
+
+// A body passing the data frames through the filter chain. The filters are wrapped in a mutex,
+// because the boxed response bodies have to be shareable between the threads.
+struct FilteredBody {
+  inner: BoxBody<Bytes, io::Error>,
+  filters: Mutex<Vec<Box<dyn ResponseBodyFilter>>>,
+  trailers: Option<Frame<Bytes>>,
+  finished: bool,
+}
+
+impl FilteredBody {
+  // Pass the chunk through all the filters
+  fn filter(&mut self, chunk: Bytes) -> Result<Bytes, io::Error> {
+    let filters = self
+      .filters
+      .get_mut()
+      .unwrap_or_else(|err| err.into_inner());
+    let mut chunk = chunk;
+    for filter in filters.iter_mut() {
+      if chunk.is_empty() {
+        break;
+      }
+      chunk = filter.filter(chunk).map_err(io::Error::other)?;
+    }
+    Ok(chunk)
+  }
+
+  // End all the filters. The data held back by a filter is passed through the next filters before they end.
+  fn finish(&mut self) -> Result<Bytes, io::Error> {
+    let filters = self
+      .filters
+      .get_mut()
+      .unwrap_or_else(|err| err.into_inner());
+    let mut chunk = Bytes::new();
+    for filter in filters.iter_mut() {
+      let filtered = match chunk.is_empty() {
+        true => Bytes::new(),
+        false => filter.filter(chunk).map_err(io::Error::other)?,
+      };
+      let remaining = filter.finish().map_err(io::Error::other)?;
+      chunk = match (filtered.is_empty(), remaining.is_empty()) {
+        (_, true) => filtered,
+        (true, false) => remaining,
+        (false, false) => {
+          let mut joined = filtered.to_vec();
+          joined.extend_from_slice(&remaining);
+          Bytes::from(joined)
+        }
+      };
+    }
+    Ok(chunk)
+  }
+}
+
+impl Body for FilteredBody {
+  type Data = Bytes;
+  type Error = io::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    loop {
+      if this.finished {
+        return Poll::Ready(this.trailers.take().map(Ok));
+      }
+      match Pin::new(&mut this.inner).poll_frame(cx) {
+        Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+          Ok(data) => match this.filter(data) {
+            // Empty chunks are dropped, since the filters might hold back the data until the end of the body
+            Ok(filtered) if !filtered.is_empty() => {
+              return Poll::Ready(Some(Ok(Frame::data(filtered))))
+            }
+            Ok(_) => (),
+            Err(err) => {
+              this.finished = true;
+              return Poll::Ready(Some(Err(err)));
+            }
+          },
+          Err(frame) => {
+            // The trailers end the body, so the filters are ended before they're sent
+            this.trailers = Some(frame);
+            this.finished = true;
+            match this.finish() {
+              Ok(filtered) if !filtered.is_empty() => {
+                return Poll::Ready(Some(Ok(Frame::data(filtered))))
+              }
+              Ok(_) => (),
+              Err(err) => {
+                this.trailers = None;
+                return Poll::Ready(Some(Err(err)));
+              }
+            }
+          }
+        },
+        Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+        Poll::Ready(None) => {
+          this.finished = true;
+          match this.finish() {
+            Ok(filtered) if !filtered.is_empty() => {
+              return Poll::Ready(Some(Ok(Frame::data(filtered))))
+            }
+            Ok(_) => (),
+            Err(err) => return Poll::Ready(Some(Err(err))),
+          }
+        }
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    // The end of the stream is polled, so that the filters are ended
+    self.finished && self.trailers.is_none()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    // The filters can change the length of the body
+    SizeHint::default()
+  }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::{HyperRequest, HyperResponse};
+
+/// The maximum nesting level of the subrequests, which prevents infinite recursion when a subrequest triggers itself.
+const MAX_SUBREQUEST_DEPTH: usize = 8;
+
+type SubrequestFuture =
+  Pin<Box<dyn Future<Output = Result<HyperResponse, Box<dyn Error + Send + Sync>>> + Send>>;
+
+/// A handler of the subrequests, which modules can send through the server's full handler chain.
+///
+/// The subrequests are handled like the requests received on the same connection, so they pass through
+/// all the modules (for example, the cache or the reverse proxy). Clones share the same handler.
+#[derive(Clone)]
+pub struct SubrequestHandler {
+  handler: Arc<dyn Fn(HyperRequest) -> SubrequestFuture + Send + Sync>,
+  depth: usize,
+}
+
+impl SubrequestHandler {
+  /// Creates a new `SubrequestHandler` instance. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `handler`: A function handling the subrequest and returning a future resolving to the response.
+  ///
+  /// # Returns
+  ///
+  /// A new `SubrequestHandler` instance.
+  pub fn new<F, Fut>(handler: F) -> Self
+  where
+    F: Fn(HyperRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<HyperResponse, Box<dyn Error + Send + Sync>>> + Send + 'static,
+  {
+    Self {
+      handler: Arc::new(move |request| Box::pin(handler(request))),
+      depth: 0,
+    }
+  }
+
+  /// Retrieves the nesting level of the request. The requests received from the client have the level of zero.
+  ///
+  /// # Returns
+  ///
+  /// The nesting level of the request.
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// Sends a subrequest through the server's handler chain.
+  ///
+  /// # Parameters
+  ///
+  /// - `request`: The subrequest to send.
+  ///
+  /// # Returns
+  ///
+  /// A `Result` containing the response to the subrequest, or an error if the subrequest couldn't be handled
+  /// or the subrequests are nested too deeply.
+  pub async fn send(
+    &self,
+    mut request: HyperRequest,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    if self.depth >= MAX_SUBREQUEST_DEPTH {
+      Err(format!(
+        "The subrequests are nested more than {} levels deep",
+        MAX_SUBREQUEST_DEPTH
+      ))?
+    }
+    request.extensions_mut().insert(SubrequestHandler {
+      handler: self.handler.clone(),
+      depth: self.depth + 1,
+    });
+    (self.handler)(request).await
+  }
+}
@@ -0,0 +1,203 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+
+use crate::{ErrorLogger, WithRuntime};
+
+/// A scheduler of the background jobs of the modules, which runs the jobs on the server runtime.
+///
+/// The jobs are run even by the dynamically loaded modules, which can't spawn the tasks themselves, because
+/// their copy of Tokio isn't associated with the server runtime. The jobs are cancelled when the modules
+/// are shut down (when the server shuts down or restarts, or after the configuration reload replacing the modules).
+/// Clones share the same cancellation, and the jobs are also cancelled once all the clones are dropped.
+#[derive(Clone)]
+pub struct TaskScheduler {
+  handle: Handle,
+  error_logger: ErrorLogger,
+  cancellation: Arc<watch::Sender<bool>>,
+}
+
+impl TaskScheduler {
+  /// Creates a new `TaskScheduler` instance. This is called by the server.
+  ///
+  /// # Parameters
+  ///
+  /// - `handle`: A `Handle` to the Tokio runtime, on which the jobs run.
+  /// - `error_logger`: The `ErrorLogger` for logging the failures of the jobs.
+  ///
+  /// # Returns
+  ///
+  /// A new `TaskScheduler` instance.
+  pub fn new(handle: Handle, error_logger: ErrorLogger) -> Self {
+    Self {
+      handle,
+      error_logger,
+      cancellation: Arc::new(watch::Sender::new(false)),
+    }
+  }
+
+  /// Creates a new `TaskScheduler` instance sharing the cancellation, which attributes the failures of the jobs to the specified source.
+  ///
+  /// # Parameters
+  ///
+  /// - `source`: The name of the source of the jobs (for example, the name of the module).
+  ///
+  /// # Returns
+  ///
+  /// A new `TaskScheduler` instance sharing the cancellation with this instance.
+  pub fn with_source(&self, source: Arc<str>) -> Self {
+    Self {
+      handle: self.handle.clone(),
+      error_logger: self.error_logger.with_source(source),
+      cancellation: self.cancellation.clone(),
+    }
+  }
+
+  /// Schedules a job run once after a delay.
+  ///
+  /// # Parameters
+  ///
+  /// - `name`: The name of the job, used in the error log messages.
+  /// - `delay`: The delay before the job runs.
+  /// - `jitter`: The maximum random duration added to the delay, so that the jobs scheduled together don't run at once.
+  /// - `job`: A function returning the future running the job. The job's failure is logged.
+  ///
+  /// # Returns
+  ///
+  /// A `ScheduledTask` for cancelling the job. The job isn't cancelled when the `ScheduledTask` is dropped.
+  pub fn schedule_delayed<F, Fut>(
+    &self,
+    name: &str,
+    delay: Duration,
+    jitter: Duration,
+    job: F,
+  ) -> ScheduledTask
+  where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'static,
+  {
+    let name = name.to_string();
+    let error_logger = self.error_logger.clone();
+    self.spawn_cancellable(async move {
+      tokio::time::sleep(delay + random_jitter(jitter)).await;
+      if let Err(err) = job().await {
+        error_logger
+          .log(&format!("The \"{}\" background job failed: {}", name, err))
+          .await;
+      }
+    })
+  }
+
+  /// Schedules a job run periodically. The first run is delayed by the random jitter only,
+  /// and the next runs are delayed by the interval and the random jitter after the previous run finishes.
+  ///
+  /// # Parameters
+  ///
+  /// - `name`: The name of the job, used in the error log messages.
+  /// - `interval`: The interval between the end of a run and the start of the next run.
+  /// - `jitter`: The maximum random duration added to the delays, so that the jobs scheduled together don't run at once.
+  /// - `job`: A function returning the future running the job once. The job's failures are logged, and don't stop the next runs.
+  ///
+  /// # Returns
+  ///
+  /// A `ScheduledTask` for cancelling the job. The job isn't cancelled when the `ScheduledTask` is dropped.
+  pub fn schedule_periodic<F, Fut>(
+    &self,
+    name: &str,
+    interval: Duration,
+    jitter: Duration,
+    mut job: F,
+  ) -> ScheduledTask
+  where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send + 'static,
+  {
+    let name = name.to_string();
+    let error_logger = self.error_logger.clone();
+    self.spawn_cancellable(async move {
+      let mut delay = random_jitter(jitter);
+      loop {
+        tokio::time::sleep(delay).await;
+        if let Err(err) = job().await {
+          error_logger
+            .log(&format!("The \"{}\" background job failed: {}", name, err))
+            .await;
+        }
+        delay = interval + random_jitter(jitter);
+      }
+    })
+  }
+
+  /// Cancels all the jobs scheduled with this scheduler and its clones, including the jobs being run.
+  /// The jobs scheduled later are cancelled immediately. This is called by the server.
+  pub fn shutdown(&self) {
+    self.cancellation.send_replace(true);
+  }
+
+  /// Checks if the scheduler was shut down.
+  ///
+  /// # Returns
+  ///
+  /// `true` if the jobs of the scheduler are cancelled, `false` otherwise.
+  pub fn is_shut_down(&self) -> bool {
+    *self.cancellation.borrow()
+  }
+
+  // Spawn the job on the server runtime, until it finishes or it's cancelled
+  fn spawn_cancellable<Fut>(&self, job: Fut) -> ScheduledTask
+  where
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    let mut cancellation = self.cancellation.subscribe();
+    let join_handle = self
+      .handle
+      .spawn(WithRuntime::new(self.handle.clone(), async move {
+        tokio::select! {
+          biased;
+          _ = cancellation.wait_for(|cancelled| *cancelled) => (),
+          _ = job => (),
+        }
+      }));
+    ScheduledTask {
+      abort_handle: join_handle.abort_handle(),
+    }
+  }
+}
+
+/// A job scheduled with the `TaskScheduler`.
+pub struct ScheduledTask {
+  abort_handle: AbortHandle,
+}
+
+impl ScheduledTask {
+  /// Cancels the job. If the job is being run, it's stopped at its next await point.
+  pub fn cancel(&self) {
+    self.abort_handle.abort();
+  }
+
+  /// Checks if the job has finished, either because it was run or because it was cancelled.
+  ///
+  /// # Returns
+  ///
+  /// `true` if the job has finished, `false` otherwise.
+  pub fn is_finished(&self) -> bool {
+    self.abort_handle.is_finished()
+  }
+}
+
+// Get a random duration up to the maximum jitter. The randomly keyed hasher is used as the source of the randomness.
+fn random_jitter(jitter: Duration) -> Duration {
+  let jitter_nanos = jitter.as_nanos().min(u64::MAX as u128) as u64;
+  if jitter_nanos == 0 {
+    return Duration::ZERO;
+  }
+  let random = RandomState::new().build_hasher().finish();
+  Duration::from_nanos(random % jitter_nanos.saturating_add(1))
+}
@@ -23,6 +23,10 @@ static GLOBAL: MiMalloc = MiMalloc;
 // Define a struct for the module implementation
 struct ExampleModule;
 
+// Declare the module ABI version and the capabilities required by the module, so that the server can refuse
+// to load the module if it was built against an incompatible version of "ferron-common"
+ferron_common::declare_module_abi!(ferron_common::MODULE_CAPABILITY_CONFIG_VALIDATION);
+
 /// Validates the server configuration.
 /// Since this module has no configurable properties, it always returns Ok(()).
 #[no_mangle]
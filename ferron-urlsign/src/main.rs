@@ -0,0 +1,52 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use hmac::{Hmac, Mac};
+use mimalloc::MiMalloc;
+use rpassword::prompt_password;
+use sha2::Sha256;
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+/// A tool for generating signed, expiring URLs for Ferron
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+  /// The URL path to sign (for example "/downloads/file.zip")
+  #[arg()]
+  path: String,
+
+  /// The validity period of the signed URL in seconds
+  #[arg(short, long, default_value_t = 3600)]
+  expires_in: u64,
+}
+
+fn main() {
+  let args = Args::parse();
+
+  if !args.path.starts_with('/') {
+    eprintln!("The URL path must start with a slash!");
+    std::process::exit(1);
+  }
+
+  let secret = prompt_password("Secret: ").unwrap();
+
+  let expires = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+    + args.expires_in;
+
+  // The signed message must match the one verified by the server
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+  mac.update(format!("{}\n{}", args.path, expires).as_bytes());
+  let signature = mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+
+  println!("{}?exp={}&sig={}", args.path, expires, signature);
+}
@@ -2,6 +2,10 @@
 #[path = "server.rs"]
 mod ferron_server;
 
+// Import master process module from "master.rs"
+#[path = "master.rs"]
+mod ferron_master;
+
 // Import request handler module from "request_handler.rs"
 #[path = "request_handler.rs"]
 mod ferron_request_handler;
@@ -9,50 +13,134 @@ mod ferron_request_handler;
 // Import resources from "res" directory
 #[path = "res"]
 mod ferron_res {
+  pub mod builtin_icon;
   pub mod server_software;
 }
 
 // Import utility modules from "util" directory
 #[path = "util"]
 mod ferron_util {
+  pub mod access_policy;
+  pub mod admin_api;
   pub mod anti_xss;
+  pub mod auto_ban;
+  pub mod bandwidth_throttle;
+  pub mod blocking_budget;
+  pub mod bot_detection;
+  pub mod byte_ranges;
+  pub mod cache_prewarm;
+  pub mod certificate_checks;
   pub mod cgi_response;
+  pub mod client_auth;
+  pub mod client_hints;
+  pub mod coalesced_read;
   pub mod combine_config;
+  pub mod concurrency_limiter;
+  pub mod conditional_requests;
+  pub mod config_export;
+  pub mod connection_drain;
+  pub mod connection_pool;
   pub mod copy_move;
+  pub mod counting_body;
+  pub mod deployment;
+  pub mod diagnostics;
+  pub mod dns_resolver;
+  pub mod drop_privileges;
+  pub mod error_diagnostics;
   pub mod error_pages;
+  pub mod esi;
+  pub mod experiments;
+  pub mod expression;
+  pub mod fair_queue;
   pub mod fcgi_decoder;
   pub mod fcgi_encoder;
   pub mod fcgi_name_value_pair;
   pub mod fcgi_record;
+  pub mod fetch_url;
+  pub mod file_body;
+  pub mod forward_proxy_auth;
+  pub mod forwarded;
   pub mod generate_directory_listing;
+  pub mod geoip;
+  pub mod hop_by_hop;
+  pub mod hotlink_protection;
+  pub mod hsts;
+  pub mod immutable_assets;
   pub mod ip_blocklist;
   pub mod ip_match;
+  pub mod ip_prefix_trie;
+  pub mod language_negotiation;
+  pub mod listener_stats;
   pub mod load_config;
+  pub mod load_listeners;
   pub mod load_tls;
+  pub mod log_file;
+  pub mod log_privacy;
+  pub mod log_sinks;
+  pub mod log_throttle;
+  pub mod lua_scripting;
   pub mod match_hostname;
   pub mod match_location;
+  pub mod metrics;
+  pub mod min_transfer_rate;
+  pub mod module_abi;
+  pub mod module_chain;
+  pub mod module_interactions;
+  pub mod module_lifecycle;
+  pub mod monitored_module;
   pub mod no_server_verifier;
+  pub mod noise_requests;
   pub mod non_standard_code_structs;
+  pub mod ocsp_stapling;
+  pub mod proxy_wasm;
   pub mod read_to_end_move;
+  pub mod redirect_map;
+  pub mod response_fairness;
+  pub mod retry_budget;
   pub mod sizify;
   pub mod sni;
   pub mod split_stream_by_map;
+  pub mod ssrf_guard;
+  pub mod storage;
+  pub mod temp_files;
+  pub mod timeout_body;
+  pub mod timeout_stream;
+  pub mod tls_policy;
+  pub mod tracked_body;
   pub mod ttl_cache;
+  pub mod upstream_health;
+  pub mod upstream_integrity;
+  pub mod upstream_proxy;
   pub mod url_rewrite_structs;
   pub mod url_sanitizer;
+  pub mod url_signature;
+  pub mod user_directory;
   pub mod validate_config;
+  pub mod variable_substitution;
+  pub mod waf;
+  pub mod webdav_copy;
+  pub mod webdav_locks;
+  pub mod xml;
 }
 
 // Import project modules from "modules" directory
 #[path = "modules"]
 mod ferron_modules {
   pub mod blocklist;
+  pub mod bot_mitigation;
   pub mod default_handler_checks;
+  pub mod header_rules;
+  pub mod hotlink_protection;
+  pub mod metrics;
   pub mod non_standard_codes;
+  pub mod redirect_maps;
   pub mod redirect_trailing_slashes;
   pub mod redirects;
+  pub mod signed_urls;
   pub mod static_file_serving;
+  pub mod static_responses;
   pub mod url_rewrite;
+  pub mod waf;
   pub mod x_forwarded_for;
 }
 
@@ -64,8 +152,11 @@ mod ferron_optional_modules {
   pub mod fauth;
   pub mod fcgi;
   pub mod fproxy;
+  pub mod lua;
   pub mod rproxy;
   pub mod scgi;
+  pub mod wasm;
+  pub mod webdav;
 }
 
 // Standard library imports
@@ -74,9 +165,17 @@ use std::{error::Error, path::PathBuf};
 
 // External crate imports
 use clap::Parser;
-use ferron_common::{ServerConfig, ServerConfigRoot, ServerModule};
-use ferron_server::start_server;
-use ferron_util::load_config::load_config;
+use ferron_common::{
+  ModuleAbi, ServerConfig, ServerConfigRoot, ServerModule, MODULE_CAPABILITY_CONFIG_VALIDATION,
+};
+use ferron_master::{start_master, WORKER_PROCESS_ENV};
+use ferron_server::{start_server, validate_server_configuration, ServerConfiguration};
+use ferron_util::config_export::export_config;
+use ferron_util::diagnostics::create_diagnostics_bundle;
+use ferron_util::load_config::{load_config, load_config_with_origins};
+use ferron_util::module_abi::check_module_abi;
+use ferron_util::module_interactions::analyze_module_interactions;
+use ferron_util::monitored_module::MonitoredModule;
 use libloading::{library_filename, Library, Symbol};
 use mimalloc::MiMalloc;
 
@@ -93,16 +192,30 @@ struct Args {
   /// The path to the server configuration file
   #[arg(short, long, default_value_t = String::from("./ferron.yaml"))]
   config: String,
+
+  /// Gather the redacted configuration, the module list, the recent error log entries, the metrics,
+  /// and the environment information into an archive for attaching to bug reports, and exit
+  #[arg(long)]
+  diagnose: bool,
+
+  /// Validate the configuration, report the module combinations known to cause problems
+  /// (for example, the web application firewall running after the reverse proxy) for each host and location, and exit
+  #[arg(long)]
+  validate_interactions: bool,
+
+  /// Validate the configuration, print the effective configuration of the global scope, each host and each location
+  /// as JSON (with the default values applied, the property sources annotated, and the secrets redacted), and exit
+  #[arg(long)]
+  export_config: bool,
 }
 
-// Function to execute before starting the server
+// Load the server configuration and initialize the modules for it
 #[allow(clippy::type_complexity)]
-fn before_starting_server(
-  args: &Args,
-  first_start: bool,
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
+fn load_server_configuration(
+  config_path: &str,
+) -> Result<ServerConfiguration, Box<dyn Error + Send + Sync>> {
   // Load the configuration
-  let yaml_config = load_config(PathBuf::from(args.config.clone()))?;
+  let (yaml_config, config_origins) = load_config_with_origins(PathBuf::from(config_path))?;
 
   let mut module_error = None;
   let mut module_libs = Vec::new();
@@ -112,7 +225,8 @@ fn before_starting_server(
     for module_name_yaml in modules.iter() {
       if let Some(module_name) = module_name_yaml.as_str() {
         let lib = match module_name {
-          "rproxy" | "fproxy" | "cache" | "cgi" | "scgi" | "fcgi" | "fauth" => None,
+          "rproxy" | "fproxy" | "cache" | "cgi" | "scgi" | "fcgi" | "fauth" | "webdav" | "lua"
+          | "wasm" => None,
           _ => Some(
             match unsafe {
               Library::new(library_filename(format!(
@@ -144,6 +258,30 @@ fn before_starting_server(
   // Iterate over loaded module libraries and initialize them
   for (lib, module_name) in module_libs.iter() {
     if let Some(lib) = lib {
+      // Negotiate the module ABI before calling any other function of the module,
+      // since the functions of an incompatible module can't be called safely
+      let module_abi: Symbol<extern "C" fn() -> ModuleAbi> = match unsafe {
+        lib.get(b"server_module_abi")
+      } {
+        Ok(module_abi) => module_abi,
+        Err(_) => {
+          module_error = Some(anyhow::anyhow!(
+              "Cannot load module \"{}\": The module doesn't declare its module ABI version. Rebuild the module against the server's version of \"ferron-common\"",
+              module_name
+            ));
+          break;
+        }
+      };
+      let module_abi = module_abi();
+      if let Err(err) = check_module_abi(&module_abi) {
+        module_error = Some(anyhow::anyhow!(
+          "Cannot load module \"{}\": {}",
+          module_name,
+          err
+        ));
+        break;
+      }
+
       // Retrieve the module initialization function
       let module_init: Symbol<
         fn(
@@ -163,7 +301,7 @@ fn before_starting_server(
 
       // Initialize the module
       external_modules.push(match module_init(&yaml_config) {
-        Ok(module) => module,
+        Ok(module) => MonitoredModule::wrap(module_name, module),
         Err(err) => {
           module_error = Some(anyhow::anyhow!(
             "Cannot initialize module \"{}\": {}",
@@ -174,7 +312,10 @@ fn before_starting_server(
         }
       });
 
-      // Retrieve the module configuration validation function
+      // Retrieve the module configuration validation function, if the module validates its configuration
+      if module_abi.capabilities & MODULE_CAPABILITY_CONFIG_VALIDATION == 0 {
+        continue;
+      }
       let module_validate_config: Symbol<
         fn(&ServerConfigRoot, bool, bool) -> Result<(), Box<dyn Error + Send + Sync>>,
       > = match unsafe { lib.get(b"server_module_validate_config") } {
@@ -188,13 +329,13 @@ fn before_starting_server(
           break;
         }
       };
-      module_config_validation_functions.push(module_validate_config);
+      module_config_validation_functions.push(*module_validate_config);
     } else {
       match module_name as &str {
         "rproxy" => {
           external_modules.push(
             match ferron_optional_modules::rproxy::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -211,7 +352,7 @@ fn before_starting_server(
         "fproxy" => {
           external_modules.push(
             match ferron_optional_modules::fproxy::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -228,7 +369,7 @@ fn before_starting_server(
         "cache" => {
           external_modules.push(
             match ferron_optional_modules::cache::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -245,7 +386,7 @@ fn before_starting_server(
         "cgi" => {
           external_modules.push(
             match ferron_optional_modules::cgi::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -262,7 +403,7 @@ fn before_starting_server(
         "scgi" => {
           external_modules.push(
             match ferron_optional_modules::scgi::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -279,7 +420,7 @@ fn before_starting_server(
         "fcgi" => {
           external_modules.push(
             match ferron_optional_modules::fcgi::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -296,7 +437,58 @@ fn before_starting_server(
         "fauth" => {
           external_modules.push(
             match ferron_optional_modules::fauth::server_module_init(&yaml_config) {
-              Ok(module) => module,
+              Ok(module) => MonitoredModule::wrap(module_name, module),
+              Err(err) => {
+                module_error = Some(anyhow::anyhow!(
+                  "Cannot initialize optional built-in module \"{}\": {}",
+                  module_name,
+                  err
+                ));
+                break;
+              }
+            },
+          );
+
+          modules_optional_builtin.push(module_name.clone());
+        }
+        "webdav" => {
+          external_modules.push(
+            match ferron_optional_modules::webdav::server_module_init(&yaml_config) {
+              Ok(module) => MonitoredModule::wrap(module_name, module),
+              Err(err) => {
+                module_error = Some(anyhow::anyhow!(
+                  "Cannot initialize optional built-in module \"{}\": {}",
+                  module_name,
+                  err
+                ));
+                break;
+              }
+            },
+          );
+
+          modules_optional_builtin.push(module_name.clone());
+        }
+        "lua" => {
+          external_modules.push(
+            match ferron_optional_modules::lua::server_module_init(&yaml_config) {
+              Ok(module) => MonitoredModule::wrap(module_name, module),
+              Err(err) => {
+                module_error = Some(anyhow::anyhow!(
+                  "Cannot initialize optional built-in module \"{}\": {}",
+                  module_name,
+                  err
+                ));
+                break;
+              }
+            },
+          );
+
+          modules_optional_builtin.push(module_name.clone());
+        }
+        "wasm" => {
+          external_modules.push(
+            match ferron_optional_modules::wasm::server_module_init(&yaml_config) {
+              Ok(module) => MonitoredModule::wrap(module_name, module),
               Err(err) => {
                 module_error = Some(anyhow::anyhow!(
                   "Cannot initialize optional built-in module \"{}\": {}",
@@ -323,8 +515,16 @@ fn before_starting_server(
 
   // Add modules (both built-in and loaded)
   let mut modules = Vec::new();
-  match ferron_modules::x_forwarded_for::server_module_init() {
-    Ok(module) => modules.push(module),
+  match ferron_modules::x_forwarded_for::server_module_init(&yaml_config) {
+    Ok(module) => modules.push(MonitoredModule::wrap("x_forwarded_for", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::header_rules::server_module_init(&yaml_config) {
+    Ok(module) => modules.push(MonitoredModule::wrap("header_rules", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -332,7 +532,7 @@ fn before_starting_server(
     }
   };
   match ferron_modules::redirects::server_module_init() {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("redirects", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -340,7 +540,63 @@ fn before_starting_server(
     }
   };
   match ferron_modules::blocklist::server_module_init(&yaml_config) {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("blocklist", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::bot_mitigation::server_module_init(&yaml_config) {
+    Ok(module) => modules.push(MonitoredModule::wrap("bot_mitigation", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::waf::server_module_init(&yaml_config) {
+    Ok(module) => modules.push(MonitoredModule::wrap("waf", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::metrics::server_module_init() {
+    Ok(module) => modules.push(MonitoredModule::wrap("metrics", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::static_responses::server_module_init() {
+    Ok(module) => modules.push(MonitoredModule::wrap("static_responses", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::redirect_maps::server_module_init() {
+    Ok(module) => modules.push(MonitoredModule::wrap("redirect_maps", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::signed_urls::server_module_init() {
+    Ok(module) => modules.push(MonitoredModule::wrap("signed_urls", module)),
+    Err(err) => {
+      if module_error.is_none() {
+        module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
+      }
+    }
+  };
+  match ferron_modules::hotlink_protection::server_module_init() {
+    Ok(module) => modules.push(MonitoredModule::wrap("hotlink_protection", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -348,7 +604,7 @@ fn before_starting_server(
     }
   };
   match ferron_modules::url_rewrite::server_module_init(&yaml_config) {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("url_rewrite", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -356,7 +612,7 @@ fn before_starting_server(
     }
   };
   match ferron_modules::non_standard_codes::server_module_init(&yaml_config) {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("non_standard_codes", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -364,7 +620,7 @@ fn before_starting_server(
     }
   };
   match ferron_modules::redirect_trailing_slashes::server_module_init() {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("redirect_trailing_slashes", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -373,15 +629,15 @@ fn before_starting_server(
   };
   modules.append(&mut external_modules);
   match ferron_modules::default_handler_checks::server_module_init() {
-    Ok(module) => modules.push(module),
+    Ok(module) => modules.push(MonitoredModule::wrap("default_handler_checks", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
       }
     }
   };
-  match ferron_modules::static_file_serving::server_module_init() {
-    Ok(module) => modules.push(module),
+  match ferron_modules::static_file_serving::server_module_init(&yaml_config) {
+    Ok(module) => modules.push(MonitoredModule::wrap("static_file_serving", module)),
     Err(err) => {
       if module_error.is_none() {
         module_error = Some(anyhow::anyhow!("Cannot load a built-in module: {}", err));
@@ -389,20 +645,148 @@ fn before_starting_server(
     }
   };
 
-  // Start the server with configuration and loaded modules
-  start_server(
-    Arc::new(yaml_config),
+  let module_libraries = module_libs.into_iter().filter_map(|(lib, _)| lib).collect();
+
+  Ok(ServerConfiguration {
+    yaml_config: Arc::new(yaml_config),
+    config_origins,
     modules,
     module_config_validation_functions,
     module_error,
     modules_optional_builtin,
+    module_libraries,
+  })
+}
+
+// Function to execute before starting the server
+fn before_starting_server(
+  args: &Args,
+  first_start: bool,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+  let configuration = load_server_configuration(&args.config)?;
+
+  // Start the server with configuration and loaded modules.
+  // The configuration loader is used to reload the configuration without restarting the server.
+  let config_path = args.config.clone();
+  start_server(
+    configuration,
+    Arc::new(move || load_server_configuration(&config_path)),
     first_start,
   )
 }
 
 // Entry point of the application
+// Create the diagnostic bundle for attaching to bug reports in the current working directory
+fn diagnose(args: &Args) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let module_list = match load_server_configuration(&args.config) {
+    Ok(server_configuration) => {
+      let mut module_list = String::new();
+      for module in server_configuration.modules.iter() {
+        module_list.push_str(&format!("{}\n", module.get_name()));
+      }
+      if !server_configuration.modules_optional_builtin.is_empty() {
+        module_list.push_str(&format!(
+          "\nOptional built-in modules: {}\n",
+          server_configuration.modules_optional_builtin.join(", ")
+        ));
+      }
+      if let Some(module_error) = server_configuration.module_error {
+        module_list.push_str(&format!("\nModule error: {}\n", module_error));
+      }
+      module_list
+    }
+    Err(err) => format!("Cannot load the modules: {}\n", err),
+  };
+
+  let bundle_path =
+    create_diagnostics_bundle(&args.config, &module_list, &std::env::current_dir()?)?;
+  println!(
+    "The diagnostic bundle was written to {}",
+    bundle_path.display()
+  );
+  Ok(())
+}
+
+// Validate the configuration and report the known-bad module combinations.
+// Returns true if no known-bad module combinations were found.
+fn validate_interactions(args: &Args) -> Result<bool, Box<dyn Error + Send + Sync>> {
+  let configuration = load_server_configuration(&args.config)?;
+  validate_server_configuration(&configuration)?;
+
+  let loaded_module_names = configuration
+    .modules
+    .iter()
+    .map(|module| module.get_name())
+    .collect::<Vec<_>>();
+  let warnings = analyze_module_interactions(&configuration.yaml_config, &loaded_module_names);
+  for warning in warnings.iter() {
+    println!("WARNING: {}", warning);
+  }
+  if warnings.is_empty() {
+    println!("No known-bad module combinations were found");
+  }
+  Ok(warnings.is_empty())
+}
+
+// Validate the configuration and print it in the normalized JSON format for the external tooling
+fn print_exported_config(args: &Args) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let configuration = load_server_configuration(&args.config)?;
+  validate_server_configuration(&configuration)?;
+  println!(
+    "{}",
+    serde_json::to_string_pretty(&export_config(&configuration.yaml_config))?
+  );
+  Ok(())
+}
+
 fn main() {
   let args = &Args::parse(); // Parse command-line arguments
+
+  // Export the configuration instead of starting the server
+  if args.export_config {
+    if let Err(err) = print_exported_config(args) {
+      eprintln!("FATAL ERROR: {}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  // Validate the module combinations instead of starting the server
+  if args.validate_interactions {
+    match validate_interactions(args) {
+      Ok(true) => return,
+      Ok(false) => std::process::exit(1),
+      Err(err) => {
+        eprintln!("FATAL ERROR: {}", err);
+        std::process::exit(1);
+      }
+    }
+  }
+
+  // Create the diagnostic bundle instead of starting the server
+  if args.diagnose {
+    if let Err(err) = diagnose(args) {
+      eprintln!("FATAL ERROR: Cannot create the diagnostic bundle: {}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  // Start the master process instead of the server if worker processes are enabled
+  if std::env::var_os(WORKER_PROCESS_ENV).is_none() {
+    if let Ok(yaml_config) = load_config(PathBuf::from(args.config.clone())) {
+      if let Some(worker_processes) = yaml_config["global"]["workerProcesses"].as_i64() {
+        if worker_processes > 0 {
+          if let Err(err) = start_master(&args.config, worker_processes as usize) {
+            eprintln!("FATAL ERROR: {}", err);
+            std::process::exit(1);
+          }
+          return;
+        }
+      }
+    }
+  }
+
   let mut first_start = true;
   loop {
     match before_starting_server(args, first_start) {
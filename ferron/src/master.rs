@@ -0,0 +1,290 @@
+use std::error::Error;
+
+#[cfg(unix)]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::process::ExitStatus;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use tokio::process::Command;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(unix)]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+#[cfg(unix)]
+use crate::ferron_util::load_config::load_config;
+#[cfg(unix)]
+use crate::ferron_util::load_listeners::{bind_listener, get_systemd_listeners, load_listeners};
+
+// The environment variable that marks a process as a worker process spawned by the master process
+pub const WORKER_PROCESS_ENV: &str = "FERRON_WORKER_PROCESS";
+
+// If a worker process exits earlier than this after being started, it's restarted after this delay (to avoid restart loops)
+#[cfg(unix)]
+const WORKER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[cfg(unix)]
+enum WorkerEvent {
+  Exited(u64, usize, Option<ExitStatus>, Duration),
+  Restart(u64, usize),
+}
+
+// Obtain the listening sockets for the worker processes. Sockets that are already open are reused,
+// so that the ports stay open while the server is reloaded.
+#[cfg(unix)]
+async fn obtain_listeners(
+  config_path: &str,
+  previous_listeners: &[(SocketAddr, TcpListener)],
+) -> Result<Vec<(SocketAddr, TcpListener)>, Box<dyn Error + Send + Sync>> {
+  let yaml_config = load_config(PathBuf::from(config_path))?;
+  let mut listeners = Vec::new();
+
+  for listener_config in load_listeners(&yaml_config["global"])? {
+    if listeners
+      .iter()
+      .any(|(address, _)| *address == listener_config.address)
+    {
+      continue;
+    }
+    match previous_listeners
+      .iter()
+      .find(|(address, _)| *address == listener_config.address)
+    {
+      Some((address, listener)) => listeners.push((*address, listener.try_clone()?)),
+      None => {
+        let listener = match bind_listener(&listener_config) {
+          Ok(listener) => listener.into_std()?,
+          Err(err) => Err(anyhow::anyhow!(
+            "Cannot listen to {}: {}",
+            listener_config.address,
+            err
+          ))?,
+        };
+        listeners.push((listener_config.address, listener));
+      }
+    }
+  }
+
+  if listeners.is_empty() {
+    Err(anyhow::anyhow!("No server is listening"))?
+  }
+
+  Ok(listeners)
+}
+
+// Spawn a worker process. The listening sockets are passed to the worker process in the same way as with systemd socket activation.
+#[cfg(unix)]
+fn spawn_worker(
+  listeners: &[(SocketAddr, TcpListener)],
+  generation: u64,
+  slot: usize,
+  worker_event_tx: UnboundedSender<WorkerEvent>,
+) -> Result<u32, Box<dyn Error + Send + Sync>> {
+  let listener_fds: Vec<RawFd> = listeners
+    .iter()
+    .map(|(_, listener)| listener.as_raw_fd())
+    .collect();
+  let listener_count = listener_fds.len() as RawFd;
+  let mut temporary_fds = vec![-1; listener_fds.len()];
+
+  let mut command = Command::new(std::env::current_exe()?);
+  command
+    .args(std::env::args_os().skip(1))
+    .env(WORKER_PROCESS_ENV, "1")
+    .env("LISTEN_FDS", listener_count.to_string())
+    .env_remove("LISTEN_PID");
+
+  // Safety: only async-signal-safe functions are called in the child process, and no memory is allocated there
+  unsafe {
+    command.pre_exec(move || {
+      // Move the sockets out of the way first, so that they aren't overwritten when they are placed at the target file descriptors
+      for (index, fd) in listener_fds.iter().enumerate() {
+        let temporary_fd = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 3 + listener_count);
+        if temporary_fd == -1 {
+          return Err(std::io::Error::last_os_error());
+        }
+        temporary_fds[index] = temporary_fd;
+      }
+      for (index, temporary_fd) in temporary_fds.iter().enumerate() {
+        if libc::dup2(*temporary_fd, 3 + index as RawFd) == -1 {
+          return Err(std::io::Error::last_os_error());
+        }
+      }
+      Ok(())
+    });
+  }
+
+  let mut child = command.spawn()?;
+  let pid = match child.id() {
+    Some(pid) => pid,
+    None => Err(anyhow::anyhow!("Cannot obtain the worker process ID"))?,
+  };
+  let started = Instant::now();
+
+  tokio::spawn(async move {
+    let exit_status = child.wait().await.ok();
+    worker_event_tx
+      .send(WorkerEvent::Exited(
+        generation,
+        slot,
+        exit_status,
+        started.elapsed(),
+      ))
+      .unwrap_or_default();
+  });
+
+  Ok(pid)
+}
+
+// Send a signal to worker processes
+#[cfg(unix)]
+fn signal_workers<'a>(pids: impl Iterator<Item = &'a u32>, signal: libc::c_int) {
+  for pid in pids {
+    // Safety: the call doesn't involve any pointers
+    unsafe {
+      libc::kill(*pid as libc::pid_t, signal);
+    }
+  }
+}
+
+// Run the master process, which supervises the worker processes. Crashed worker processes are restarted,
+// and when the configuration is reloaded, a new generation of worker processes is started before the old one is stopped.
+#[cfg(unix)]
+pub fn start_master(
+  config_path: &str,
+  worker_processes: usize,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()?;
+
+  runtime.block_on(async move {
+    let mut hangup_signal = signal(SignalKind::hangup())?;
+    let mut terminate_signal = signal(SignalKind::terminate())?;
+    let mut interrupt_signal = signal(SignalKind::interrupt())?;
+    let (worker_event_tx, mut worker_event_rx) = unbounded_channel();
+
+    let mut listeners = Vec::new();
+    for listener in get_systemd_listeners()? {
+      listeners.push((listener.local_addr()?, listener));
+    }
+    let socket_activated = !listeners.is_empty();
+    if !socket_activated {
+      listeners = obtain_listeners(config_path, &[]).await?;
+    }
+
+    let mut generation = 0;
+    let mut workers: HashMap<(u64, usize), u32> = HashMap::new();
+    for slot in 0..worker_processes {
+      let pid = spawn_worker(&listeners, generation, slot, worker_event_tx.clone())?;
+      workers.insert((generation, slot), pid);
+    }
+    println!(
+      "Started {} worker processes, with the master process PID {}",
+      worker_processes,
+      std::process::id()
+    );
+
+    let mut shutting_down = false;
+    loop {
+      tokio::select! {
+        _ = hangup_signal.recv(), if !shutting_down => {
+          println!("Reloading the server configuration...");
+          if !socket_activated {
+            match obtain_listeners(config_path, &listeners).await {
+              Ok(new_listeners) => listeners = new_listeners,
+              Err(err) => {
+                eprintln!("Cannot reload the server configuration: {}", err);
+                continue;
+              }
+            }
+          }
+          let old_pids = workers.values().copied().collect::<Vec<_>>();
+          generation += 1;
+          for slot in 0..worker_processes {
+            match spawn_worker(&listeners, generation, slot, worker_event_tx.clone()) {
+              Ok(pid) => {
+                workers.insert((generation, slot), pid);
+              }
+              Err(err) => eprintln!("Cannot start a worker process: {}", err),
+            }
+          }
+          signal_workers(old_pids.iter(), libc::SIGTERM);
+        }
+        _ = terminate_signal.recv() => {
+          shutting_down = true;
+          signal_workers(workers.values(), libc::SIGTERM);
+        }
+        _ = interrupt_signal.recv() => {
+          shutting_down = true;
+          signal_workers(workers.values(), libc::SIGTERM);
+        }
+        Some(worker_event) = worker_event_rx.recv() => match worker_event {
+          WorkerEvent::Exited(worker_generation, slot, exit_status, uptime) => {
+            workers.remove(&(worker_generation, slot));
+            if !shutting_down && worker_generation == generation {
+              match exit_status {
+                Some(exit_status) => eprintln!(
+                  "Worker process {} exited ({}), restarting...",
+                  slot, exit_status
+                ),
+                None => eprintln!("Worker process {} exited, restarting...", slot),
+              }
+              if uptime < WORKER_RESTART_DELAY {
+                let worker_event_tx = worker_event_tx.clone();
+                tokio::spawn(async move {
+                  tokio::time::sleep(WORKER_RESTART_DELAY).await;
+                  worker_event_tx
+                    .send(WorkerEvent::Restart(worker_generation, slot))
+                    .unwrap_or_default();
+                });
+              } else {
+                match spawn_worker(&listeners, generation, slot, worker_event_tx.clone()) {
+                  Ok(pid) => {
+                    workers.insert((generation, slot), pid);
+                  }
+                  Err(err) => eprintln!("Cannot restart a worker process: {}", err),
+                }
+              }
+            }
+          }
+          WorkerEvent::Restart(worker_generation, slot) => {
+            if !shutting_down && worker_generation == generation {
+              match spawn_worker(&listeners, generation, slot, worker_event_tx.clone()) {
+                Ok(pid) => {
+                  workers.insert((generation, slot), pid);
+                }
+                Err(err) => eprintln!("Cannot restart a worker process: {}", err),
+              }
+            }
+          }
+        }
+      }
+
+      if shutting_down && workers.is_empty() {
+        break;
+      }
+    }
+
+    Ok(())
+  })
+}
+
+#[cfg(not(unix))]
+pub fn start_master(
+  _config_path: &str,
+  _worker_processes: usize,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  Err(anyhow::anyhow!(
+    "Worker processes are not supported on this platform"
+  ))?
+}
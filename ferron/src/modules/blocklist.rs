@@ -1,20 +1,29 @@
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use ferron_common::{
   ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
-  ServerModule, ServerModuleHandlers, SocketData,
+  ServerModule, ServerModuleHandlers, SocketData, TaskScheduler,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use hyper::StatusCode;
 use hyper_tungstenite::HyperWebsocket;
 use tokio::runtime::Handle;
+use tokio::sync::{Mutex, RwLock};
+use yaml_rust2::Yaml;
 
+use crate::ferron_util::access_policy::{AccessAction, AccessDecision, AccessDecisionLog};
+use crate::ferron_util::fetch_url::fetch_url;
 use crate::ferron_util::ip_blocklist::IpBlockList;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
 
 struct BlockListModule {
   blocklist: Arc<IpBlockList>,
+  prefix_blocklist: Arc<RwLock<IpPrefixTrie>>,
+  blocklist_sources: Arc<Vec<Yaml>>,
+  refresh_interval: Duration,
 }
 
 pub fn server_module_init(
@@ -35,25 +44,156 @@ pub fn server_module_init(
   let mut blocklist = IpBlockList::new();
   blocklist.load_from_vec(blocklist_str_vec);
 
-  Ok(Box::new(BlockListModule::new(Arc::new(blocklist))))
+  // Load the IP reputation and datacenter lists. Local files are loaded immediately,
+  // while the lists from URLs are downloaded in the background.
+  let blocklist_sources = match config["global"]["blocklistSources"].as_vec() {
+    Some(blocklist_sources) => blocklist_sources.clone(),
+    None => Vec::new(),
+  };
+
+  let mut prefix_blocklist = IpPrefixTrie::new();
+  for blocklist_source in blocklist_sources.iter() {
+    if let Some(file) = blocklist_source["file"].as_str() {
+      match std::fs::read_to_string(file) {
+        Ok(text) => {
+          prefix_blocklist.load_from_text(&text);
+        }
+        Err(err) => Err(anyhow::anyhow!(
+          "Cannot load the \"{}\" block list: {}",
+          file,
+          err
+        ))?,
+      }
+    }
+  }
+  let refresh_interval = Duration::from_secs(
+    config["global"]["blocklistRefreshInterval"]
+      .as_i64()
+      .unwrap_or(3600) as u64,
+  );
+
+  Ok(Box::new(BlockListModule::new(
+    Arc::new(blocklist),
+    Arc::new(RwLock::new(prefix_blocklist)),
+    blocklist_sources,
+    refresh_interval,
+  )))
+}
+
+// Reload the block lists. If a list can't be obtained, its previous version is kept.
+async fn update_blocklists(
+  prefix_blocklist: Arc<RwLock<IpPrefixTrie>>,
+  blocklist_sources: Arc<Vec<Yaml>>,
+  blocklist_texts: Arc<Mutex<Vec<Option<String>>>>,
+  error_logger: ErrorLogger,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let mut blocklist_texts = blocklist_texts.lock().await;
+  for (index, blocklist_source) in blocklist_sources.iter().enumerate() {
+    if let Some(url) = blocklist_source["url"].as_str() {
+      match fetch_url(url).await {
+        Ok(bytes) => blocklist_texts[index] = Some(String::from_utf8_lossy(&bytes).to_string()),
+        Err(err) => {
+          error_logger
+            .log(&format!(
+              "Cannot download the \"{}\" block list: {}",
+              url, err
+            ))
+            .await
+        }
+      }
+    } else if let Some(file) = blocklist_source["file"].as_str() {
+      match tokio::fs::read_to_string(file).await {
+        Ok(text) => blocklist_texts[index] = Some(text),
+        Err(err) => {
+          error_logger
+            .log(&format!("Cannot load the \"{}\" block list: {}", file, err))
+            .await
+        }
+      }
+    }
+  }
+
+  let mut new_prefix_blocklist = IpPrefixTrie::new();
+  for blocklist_text in blocklist_texts.iter().flatten() {
+    new_prefix_blocklist.load_from_text(blocklist_text);
+  }
+  *prefix_blocklist.write().await = new_prefix_blocklist;
+  Ok(())
 }
 
 impl BlockListModule {
-  fn new(blocklist: Arc<IpBlockList>) -> Self {
-    BlockListModule { blocklist }
+  fn new(
+    blocklist: Arc<IpBlockList>,
+    prefix_blocklist: Arc<RwLock<IpPrefixTrie>>,
+    blocklist_sources: Vec<Yaml>,
+    refresh_interval: Duration,
+  ) -> Self {
+    BlockListModule {
+      blocklist,
+      prefix_blocklist,
+      blocklist_sources: Arc::new(blocklist_sources),
+      refresh_interval,
+    }
+  }
+
+  // Schedule the periodic reloading of the block lists, until the module is shut down (for example after the configuration is reloaded)
+  fn schedule_updates(&self, task_scheduler: &TaskScheduler, error_logger: &ErrorLogger) {
+    if self.blocklist_sources.is_empty() {
+      return;
+    }
+    let prefix_blocklist = self.prefix_blocklist.clone();
+    let blocklist_sources = self.blocklist_sources.clone();
+    let blocklist_texts = Arc::new(Mutex::new(vec![None; blocklist_sources.len()]));
+    let error_logger = error_logger.clone();
+    task_scheduler.schedule_periodic(
+      "block list update",
+      self.refresh_interval,
+      Duration::ZERO,
+      move || {
+        update_blocklists(
+          prefix_blocklist.clone(),
+          blocklist_sources.clone(),
+          blocklist_texts.clone(),
+          error_logger.clone(),
+        )
+      },
+    );
   }
 }
 
+#[async_trait]
 impl ServerModule for BlockListModule {
   fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
     Box::new(BlockListModuleHandlers {
       blocklist: self.blocklist.clone(),
+      prefix_blocklist: self.prefix_blocklist.clone(),
       handle,
     })
   }
+
+  async fn on_startup(
+    &self,
+    _config: &ServerConfigRoot,
+    error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.schedule_updates(task_scheduler, error_logger);
+    Ok(())
+  }
+
+  async fn on_config_reload(
+    &self,
+    _config: &ServerConfigRoot,
+    error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.schedule_updates(task_scheduler, error_logger);
+    Ok(())
+  }
 }
 struct BlockListModuleHandlers {
   blocklist: Arc<IpBlockList>,
+  prefix_blocklist: Arc<RwLock<IpPrefixTrie>>,
   handle: Handle,
 }
 
@@ -67,7 +207,17 @@ impl ServerModuleHandlers for BlockListModuleHandlers {
     _error_logger: &ErrorLogger,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
-      if self.blocklist.is_blocked(socket_data.remote_addr.ip()) {
+      let remote_ip = socket_data.remote_addr.ip();
+      if self.blocklist.is_blocked(remote_ip)
+        || self.prefix_blocklist.read().await.contains(remote_ip)
+      {
+        if let Some(access_decisions) = request
+          .get_hyper_request()
+          .extensions()
+          .get::<AccessDecisionLog>()
+        {
+          access_decisions.record(AccessDecision::new(AccessAction::Deny, "blocklist"));
+        }
         return Ok(
           ResponseData::builder(request)
             .status(StatusCode::FORBIDDEN)
@@ -0,0 +1,399 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::Bytes;
+use hyper::{header, Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+use crate::ferron_util::auto_ban::TEMPORARY_BANS;
+use crate::ferron_util::bot_detection::{
+  create_challenge_token, verify_challenge_token, BotDetectionReason, BotDetector, BotTracker,
+  CHALLENGE_COOKIE_NAME,
+};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::storage::STORAGE;
+
+// The default number of "404 Not Found" responses, after which the client is treated as a bot
+const DEFAULT_NOT_FOUND_THRESHOLD: u64 = 20;
+// The default length of the window, in which the "404 Not Found" responses are counted, in seconds
+const DEFAULT_NOT_FOUND_WINDOW: u64 = 60;
+// The default delay of the responses to the bots in the tarpit, in milliseconds
+const DEFAULT_TARPIT_DELAY: u64 = 10000;
+// The default time, for which the client stays flagged as a bot or banned, in seconds
+const DEFAULT_PENALTY_DURATION: u64 = 600;
+// The default validity of the passed challenge, in seconds
+const DEFAULT_CHALLENGE_VALIDITY: u64 = 3600;
+
+pub fn server_module_init(
+  config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  let user_agent_patterns: Vec<&str> = match config["global"]["botUserAgents"].as_vec() {
+    Some(user_agent_patterns) => user_agent_patterns
+      .iter()
+      .filter_map(|user_agent_pattern| user_agent_pattern.as_str())
+      .collect(),
+    None => Vec::new(),
+  };
+  let detector = BotDetector::new(&user_agent_patterns)
+    .map_err(|err| anyhow::anyhow!("Invalid bot user agent pattern: {}", err))?;
+
+  // The challenge tokens are signed with a random secret, so the passed challenges don't survive the restart
+  let challenge_secret = rand::random::<[u8; 32]>();
+
+  Ok(Box::new(BotMitigationModule::new(
+    Arc::new(detector),
+    Arc::new(BotTracker::new(&STORAGE)),
+    Arc::new(challenge_secret),
+  )))
+}
+
+struct BotMitigationModule {
+  detector: Arc<BotDetector>,
+  tracker: Arc<BotTracker>,
+  challenge_secret: Arc<[u8; 32]>,
+}
+
+impl BotMitigationModule {
+  fn new(
+    detector: Arc<BotDetector>,
+    tracker: Arc<BotTracker>,
+    challenge_secret: Arc<[u8; 32]>,
+  ) -> Self {
+    BotMitigationModule {
+      detector,
+      tracker,
+      challenge_secret,
+    }
+  }
+}
+
+impl ServerModule for BotMitigationModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(BotMitigationModuleHandlers {
+      detector: self.detector.clone(),
+      tracker: self.tracker.clone(),
+      challenge_secret: self.challenge_secret.clone(),
+      not_found_tracking: None,
+      handle,
+    })
+  }
+}
+
+// The "404 Not Found" response tracking parameters for the current request
+struct NotFoundTracking {
+  ip: IpAddr,
+  threshold: u64,
+  window: Duration,
+  penalty_duration: Duration,
+  error_logger: ErrorLogger,
+}
+
+struct BotMitigationModuleHandlers {
+  detector: Arc<BotDetector>,
+  tracker: Arc<BotTracker>,
+  challenge_secret: Arc<[u8; 32]>,
+  not_found_tracking: Option<NotFoundTracking>,
+  handle: Handle,
+}
+
+// Get the value of the cookie from the request headers
+fn get_cookie<'a>(headers: &'a hyper::HeaderMap, name: &str) -> Option<&'a str> {
+  for cookie_header in headers.get_all(header::COOKIE) {
+    if let Ok(cookie_header) = cookie_header.to_str() {
+      for cookie in cookie_header.split(';') {
+        if let Some((cookie_name, cookie_value)) = cookie.trim().split_once('=') {
+          if cookie_name == name {
+            return Some(cookie_value);
+          }
+        }
+      }
+    }
+  }
+  None
+}
+
+// Generate the JavaScript challenge page, which sets the challenge cookie and reloads the page
+fn javascript_challenge_page(cookie: &str) -> String {
+  format!(
+    "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"UTF-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
+<title>Checking your browser</title>
+</head>
+<body>
+<h1>Checking your browser</h1>
+<p>This page will reload automatically.</p>
+<noscript><p>Enable JavaScript to continue.</p></noscript>
+<script>document.cookie = \"{}\"; window.location.reload();</script>
+</body>
+</html>",
+    cookie
+  )
+}
+
+#[async_trait]
+impl ServerModuleHandlers for BotMitigationModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      self.not_found_tracking = None;
+      if config.get("botMitigation").as_bool() != Some(true) {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      let ip = socket_data.remote_addr.ip().to_canonical();
+      let penalty_duration = Duration::from_secs(
+        config
+          .get("botPenaltyDuration")
+          .as_i64()
+          .map(|penalty_duration| penalty_duration as u64)
+          .unwrap_or(DEFAULT_PENALTY_DURATION),
+      );
+      self.not_found_tracking = Some(NotFoundTracking {
+        ip,
+        threshold: config
+          .get("botNotFoundThreshold")
+          .as_i64()
+          .map(|threshold| threshold as u64)
+          .unwrap_or(DEFAULT_NOT_FOUND_THRESHOLD),
+        window: Duration::from_secs(
+          config
+            .get("botNotFoundWindow")
+            .as_i64()
+            .map(|window| window as u64)
+            .unwrap_or(DEFAULT_NOT_FOUND_WINDOW),
+        ),
+        penalty_duration,
+        error_logger: error_logger.clone(),
+      });
+
+      let hyper_request = request.get_hyper_request();
+      let user_agent = hyper_request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok());
+      let reason = match self.detector.detect(user_agent, hyper_request.uri().path()) {
+        Some(reason) => reason,
+        None if self.tracker.is_flagged(ip).await => BotDetectionReason::NotFound,
+        None => return Ok(ResponseData::builder(request).build()),
+      };
+
+      let action = config
+        .get("botAction")
+        .as_str()
+        .unwrap_or("block")
+        .to_string();
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0);
+
+      // The clients, which have passed the challenge, aren't challenged again
+      let is_challenge = action == "cookieChallenge" || action == "javascriptChallenge";
+      if is_challenge
+        && get_cookie(hyper_request.headers(), CHALLENGE_COOKIE_NAME)
+          .is_some_and(|token| verify_challenge_token(&*self.challenge_secret, ip, token, now))
+      {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      // In the observe mode, the detections are logged and counted, but the action isn't performed
+      let observe = config.get("botMode").as_str() == Some("observe");
+      METRICS.increment_counter(
+        "ferron_bot_detections_total",
+        &[
+          ("reason", reason.as_str()),
+          ("action", &action),
+          ("mode", if observe { "observe" } else { "enforce" }),
+        ],
+      );
+      error_logger
+        .warn(&format!(
+          "Bot detected ({}) in the request from {} to \"{}\", action: {}{}",
+          reason.as_str(),
+          ip,
+          hyper_request.uri().path(),
+          action,
+          if observe {
+            " (not enforced, the bot mitigation is in the observe mode)"
+          } else {
+            ""
+          }
+        ))
+        .await;
+      if observe {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      match action.as_str() {
+        "tarpit" => {
+          // The request is processed normally, but only after a delay, which slows down the scanning
+          let tarpit_delay = config
+            .get("botTarpitDelay")
+            .as_i64()
+            .map(|tarpit_delay| tarpit_delay as u64)
+            .unwrap_or(DEFAULT_TARPIT_DELAY);
+          tokio::time::sleep(Duration::from_millis(tarpit_delay)).await;
+          Ok(ResponseData::builder(request).build())
+        }
+        "cookieChallenge" | "javascriptChallenge" => {
+          let challenge_validity = config
+            .get("botChallengeValidity")
+            .as_i64()
+            .map(|challenge_validity| challenge_validity as u64)
+            .unwrap_or(DEFAULT_CHALLENGE_VALIDITY);
+          let token = create_challenge_token(&*self.challenge_secret, ip, now + challenge_validity);
+          let cookie = format!(
+            "{}={}; Path=/; Max-Age={}; SameSite=Lax{}",
+            CHALLENGE_COOKIE_NAME,
+            token,
+            challenge_validity,
+            if socket_data.encrypted {
+              "; Secure"
+            } else {
+              ""
+            }
+          );
+          let response = if action == "cookieChallenge" {
+            // The client is redirected to the same URL with the cookie set. The clients not supporting cookies don't pass.
+            let location = hyper_request
+              .uri()
+              .path_and_query()
+              .map(|path_and_query| path_and_query.to_string())
+              .unwrap_or_else(|| String::from("/"));
+            Response::builder()
+              .status(StatusCode::TEMPORARY_REDIRECT)
+              .header(header::LOCATION, location)
+              .header(header::SET_COOKIE, format!("{}; HttpOnly", cookie))
+              .header(header::CACHE_CONTROL, "no-store")
+              .body(Empty::new().map_err(|e| match e {}).boxed())?
+          } else {
+            // The cookie is set by a script. The clients not executing JavaScript don't pass.
+            Response::builder()
+              .status(StatusCode::SERVICE_UNAVAILABLE)
+              .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+              .header(header::CACHE_CONTROL, "no-store")
+              .body(
+                Full::new(Bytes::from(javascript_challenge_page(&cookie)))
+                  .map_err(|e| match e {})
+                  .boxed(),
+              )?
+          };
+          Ok(ResponseData::builder(request).response(response).build())
+        }
+        "ban" => {
+          // The connections from the banned client are closed immediately after being accepted
+          TEMPORARY_BANS.ban(ip, penalty_duration).await;
+          Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::FORBIDDEN)
+              .build(),
+          )
+        }
+        _ => Ok(
+          ResponseData::builder(request)
+            .status(StatusCode::FORBIDDEN)
+            .build(),
+        ),
+      }
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    if response.status() == StatusCode::NOT_FOUND {
+      if let Some(not_found_tracking) = self.not_found_tracking.take() {
+        if self
+          .tracker
+          .record_not_found(
+            not_found_tracking.ip,
+            not_found_tracking.threshold,
+            not_found_tracking.window,
+            not_found_tracking.penalty_duration,
+          )
+          .await
+        {
+          not_found_tracking
+            .error_logger
+            .warn(&format!(
+              "Client {} flagged as a bot after {} \"404 Not Found\" responses",
+              not_found_tracking.ip, not_found_tracking.threshold
+            ))
+            .await;
+        }
+      }
+    }
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
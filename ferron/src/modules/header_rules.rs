@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::expression::{Expression, ExpressionContext};
+use crate::ferron_util::variable_substitution::substitute_variables;
+
+// Compile the conditions of the header rules, so that they don't have to be compiled for each request
+fn compile_header_rule_conditions(
+  header_rules: &Yaml,
+  conditions: &mut HashMap<String, Arc<Expression>>,
+) -> Result<(), anyhow::Error> {
+  if let Some(header_rules) = header_rules.as_vec() {
+    for header_rule in header_rules {
+      if let Some(condition) = header_rule["condition"].as_str() {
+        if !conditions.contains_key(condition) {
+          let expression = Expression::compile(condition).map_err(|err| {
+            anyhow::anyhow!("Invalid header rule condition \"{}\": {}", condition, err)
+          })?;
+          conditions.insert(condition.to_string(), Arc::new(expression));
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+pub fn server_module_init(
+  config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  let mut conditions = HashMap::new();
+  compile_header_rule_conditions(&config["global"]["headerRules"], &mut conditions)?;
+  if let Some(hosts) = config["hosts"].as_vec() {
+    for host_yaml in hosts.iter() {
+      compile_header_rule_conditions(&host_yaml["headerRules"], &mut conditions)?;
+      if let Some(locations) = host_yaml["locations"].as_vec() {
+        for location_yaml in locations.iter() {
+          compile_header_rule_conditions(&location_yaml["headerRules"], &mut conditions)?;
+        }
+      }
+    }
+  }
+
+  Ok(Box::new(HeaderRulesModule::new(Arc::new(conditions))))
+}
+
+struct HeaderRulesModule {
+  conditions: Arc<HashMap<String, Arc<Expression>>>,
+}
+
+impl HeaderRulesModule {
+  fn new(conditions: Arc<HashMap<String, Arc<Expression>>>) -> Self {
+    HeaderRulesModule { conditions }
+  }
+}
+
+impl ServerModule for HeaderRulesModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(HeaderRulesModuleHandlers {
+      conditions: self.conditions.clone(),
+      header_operations: Vec::new(),
+      handle,
+    })
+  }
+}
+
+// A change of a request or response header, determined from the header rules matching the request
+enum HeaderOperation {
+  Set(HeaderName, HeaderValue),
+  Add(HeaderName, HeaderValue),
+  Remove(HeaderName),
+}
+
+impl HeaderOperation {
+  fn apply(self, headers: &mut HeaderMap) {
+    match self {
+      HeaderOperation::Set(header_name, header_value) => {
+        headers.insert(header_name, header_value);
+      }
+      HeaderOperation::Add(header_name, header_value) => {
+        headers.append(header_name, header_value);
+      }
+      HeaderOperation::Remove(header_name) => {
+        headers.remove(header_name);
+      }
+    }
+  }
+}
+
+// Determine the header operations from the "remove", "set" and "add" properties of the header rule.
+// The headers are removed before the headers from the same rule are set or added.
+// The variables in the header values (for example, "$remote_addr") are substituted.
+fn header_operations(
+  header_rule: &Yaml,
+  remove_headers_key: &str,
+  set_headers_key: &str,
+  add_headers_key: &str,
+  resolve_variable: &impl Fn(&str) -> Option<String>,
+) -> Vec<HeaderOperation> {
+  let mut header_operations = Vec::new();
+  if let Some(remove_headers) = header_rule[remove_headers_key].as_vec() {
+    for header_name in remove_headers {
+      if let Some(Ok(header_name)) = header_name.as_str().map(HeaderName::from_str) {
+        header_operations.push(HeaderOperation::Remove(header_name));
+      }
+    }
+  }
+  for (headers_key, is_added) in [(set_headers_key, false), (add_headers_key, true)] {
+    if let Some(headers) = header_rule[headers_key].as_hash() {
+      for (header_name, header_value) in headers {
+        if let (Some(header_name), Some(header_value)) =
+          (header_name.as_str(), header_value.as_str())
+        {
+          if let (Ok(header_name), Ok(header_value)) = (
+            HeaderName::from_str(header_name),
+            HeaderValue::from_str(&substitute_variables(header_value, resolve_variable)),
+          ) {
+            header_operations.push(match is_added {
+              true => HeaderOperation::Add(header_name, header_value),
+              false => HeaderOperation::Set(header_name, header_value),
+            });
+          }
+        }
+      }
+    }
+  }
+  header_operations
+}
+
+struct HeaderRulesModuleHandlers {
+  conditions: Arc<HashMap<String, Arc<Expression>>>,
+  header_operations: Vec<HeaderOperation>,
+  handle: Handle,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for HeaderRulesModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    mut request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      if let Some(header_rules) = config.get("headerRules").as_vec() {
+        let mut request_header_operations = Vec::new();
+        let expression_context = ExpressionContext::new(request.get_hyper_request(), socket_data);
+        // The request ID is generated once, so that it's the same in the request and response headers
+        let request_id = format!("{:032x}", rand::random::<u128>());
+        let resolve_variable = |name: &str| match name {
+          "request_id" => Some(request_id.clone()),
+          _ => expression_context.variable(name),
+        };
+        for header_rule in header_rules {
+          if let Some(condition) = header_rule["condition"].as_str() {
+            match self.conditions.get(condition) {
+              Some(condition) if condition.evaluate(&expression_context) => (),
+              _ => continue,
+            }
+          }
+
+          request_header_operations.append(&mut header_operations(
+            header_rule,
+            "removeRequestHeaders",
+            "setRequestHeaders",
+            "addRequestHeaders",
+            &resolve_variable,
+          ));
+          self.header_operations.append(&mut header_operations(
+            header_rule,
+            "removeHeaders",
+            "setHeaders",
+            "addHeaders",
+            &resolve_variable,
+          ));
+        }
+
+        // The request headers are changed before the other modules handle the request
+        let request_headers = request.get_mut_hyper_request().headers_mut();
+        for header_operation in request_header_operations {
+          header_operation.apply(request_headers);
+        }
+      }
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    mut response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    let headers = response.headers_mut();
+    for header_operation in self.header_operations.drain(..) {
+      header_operation.apply(headers);
+    }
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
+  ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Empty};
+use hyper::{header, Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+use crate::ferron_util::hotlink_protection::is_hotlink_allowed;
+
+pub fn server_module_init(
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(HotlinkProtectionModule::new()))
+}
+
+struct HotlinkProtectionModule;
+
+impl HotlinkProtectionModule {
+  fn new() -> Self {
+    HotlinkProtectionModule
+  }
+}
+
+impl ServerModule for HotlinkProtectionModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(HotlinkProtectionModuleHandlers { handle })
+  }
+}
+
+struct HotlinkProtectionModuleHandlers {
+  handle: Handle,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for HotlinkProtectionModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let hyper_request = request.get_hyper_request();
+      let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+      if !is_hotlink_allowed(config, hyper_request.uri(), hyper_request.headers(), now) {
+        return match config.get("hotlinkRedirect").as_str() {
+          Some(placeholder) => Ok(
+            ResponseData::builder(request)
+              .response(
+                Response::builder()
+                  .status(StatusCode::FOUND)
+                  .header(header::LOCATION, placeholder)
+                  .header(header::CACHE_CONTROL, "no-store")
+                  .body(Empty::new().map_err(|e| match e {}).boxed())?,
+              )
+              .build(),
+          ),
+          None => Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::FORBIDDEN)
+              .build(),
+          ),
+        };
+      }
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
@@ -0,0 +1,130 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
+  ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{header, Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+use crate::ferron_util::metrics::METRICS;
+
+struct MetricsModule;
+
+pub fn server_module_init(
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(MetricsModule::new()))
+}
+
+impl MetricsModule {
+  fn new() -> Self {
+    MetricsModule
+  }
+}
+
+impl ServerModule for MetricsModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(MetricsModuleHandlers { handle })
+  }
+}
+struct MetricsModuleHandlers {
+  handle: Handle,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for MetricsModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      if let Some(metrics_path) = config.get("metricsPath").as_str() {
+        if request.get_hyper_request().uri().path() == metrics_path {
+          return Ok(
+            ResponseData::builder(request)
+              .response(
+                Response::builder()
+                  .status(StatusCode::OK)
+                  .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                  .header(header::CACHE_CONTROL, "no-store")
+                  .body(
+                    Full::new(Bytes::from(METRICS.render()))
+                      .map_err(|e| match e {})
+                      .boxed(),
+                  )?,
+              )
+              .build(),
+          );
+        }
+      }
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
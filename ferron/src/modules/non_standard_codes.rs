@@ -2,10 +2,11 @@ use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::ferron_util::blocking_budget::spawn_blocking_budgeted;
 use crate::ferron_util::ip_blocklist::IpBlockList;
 use crate::ferron_util::ip_match::ip_match;
 use crate::ferron_util::match_hostname::match_hostname;
-use crate::ferron_util::match_location::match_location;
+use crate::ferron_util::match_location::{select_location, LocationMatcher};
 use crate::ferron_util::non_standard_code_structs::{
   NonStandardCode, NonStandardCodesLocationWrap, NonStandardCodesWrap,
 };
@@ -134,14 +135,11 @@ pub fn server_module_init(
       let mut locations = Vec::new();
       if let Some(locations_yaml) = host_yaml["locations"].as_vec() {
         for location_yaml in locations_yaml.iter() {
-          if let Some(path_str) = location_yaml["path"].as_str() {
-            let path = String::from(path_str);
-            if let Some(non_standard_codes_list_yaml) = location_yaml["nonStandardCodes"].as_vec() {
-              locations.push(NonStandardCodesLocationWrap::new(
-                path,
-                non_standard_codes_config_init(non_standard_codes_list_yaml)?,
-              ));
-            }
+          if let Some(non_standard_codes_list_yaml) = location_yaml["nonStandardCodes"].as_vec() {
+            locations.push(NonStandardCodesLocationWrap::new(
+              LocationMatcher::from_yaml(location_yaml)?,
+              non_standard_codes_config_init(non_standard_codes_list_yaml)?,
+            ));
           }
         }
       }
@@ -247,10 +245,7 @@ impl ServerModuleHandlers for NonStandardCodesModuleHandlers {
             None => None,
           },
           match hyper_request.headers().get(header::HOST) {
-            Some(value) => match value.to_str() {
-              Ok(value) => Some(value),
-              Err(_) => None,
-            },
+            Some(value) => value.to_str().ok(),
             None => None,
           },
         ) && match &host_non_standard_codes_list_wrap.ip {
@@ -260,11 +255,19 @@ impl ServerModuleHandlers for NonStandardCodesModuleHandlers {
           host_non_standard_codes_list =
             host_non_standard_codes_list_wrap.non_standard_codes.iter();
           if let Ok(path_decoded) = urlencoding::decode(request.get_hyper_request().uri().path()) {
-            for location_wrap in host_non_standard_codes_list_wrap.locations.iter() {
-              if match_location(&location_wrap.path, &path_decoded) {
-                location_non_standard_codes_list = location_wrap.non_standard_codes.iter();
-                break;
-              }
+            let location_wrap = select_location(
+              host_non_standard_codes_list_wrap
+                .locations
+                .iter()
+                .filter_map(|location_wrap| {
+                  location_wrap
+                    .matcher
+                    .matches(&path_decoded)
+                    .map(|location_match| (location_wrap, location_match))
+                }),
+            );
+            if let Some(location_wrap) = location_wrap {
+              location_non_standard_codes_list = location_wrap.non_standard_codes.iter();
             }
           }
           break;
@@ -417,10 +420,11 @@ impl ServerModuleHandlers for NonStandardCodesModuleHandlers {
                           let password_cloned = password.clone();
                           let password_hash_db_cloned = password_hash_db.to_string();
                           // Offload verifying the hash into a separate blocking thread.
-                          let password_valid = tokio::task::spawn_blocking(move || {
-                            verify_password(password_cloned, &password_hash_db_cloned).is_ok()
-                          })
-                          .await?;
+                          let password_valid =
+                            spawn_blocking_budgeted("non_standard_codes", move || {
+                              verify_password(password_cloned, &password_hash_db_cloned).is_ok()
+                            })
+                            .await?;
                           if password_valid {
                             authorized_user = Some(&username);
                             break;
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
+  ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Empty};
+use hyper::{header, Response};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::fs;
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+
+use crate::ferron_util::redirect_map::RedirectMap;
+
+// How often the redirect map files are checked for modifications
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct CachedRedirectMap {
+  redirect_map: Arc<RedirectMap>,
+  modified: Option<SystemTime>,
+  checked: Instant,
+}
+
+struct RedirectMapsModule {
+  redirect_maps: Arc<RwLock<HashMap<String, CachedRedirectMap>>>,
+}
+
+pub fn server_module_init(
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(RedirectMapsModule::new()))
+}
+
+impl RedirectMapsModule {
+  fn new() -> Self {
+    RedirectMapsModule {
+      redirect_maps: Arc::new(RwLock::new(HashMap::new())),
+    }
+  }
+}
+
+impl ServerModule for RedirectMapsModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(RedirectMapsModuleHandlers {
+      redirect_maps: self.redirect_maps.clone(),
+      handle,
+    })
+  }
+}
+struct RedirectMapsModuleHandlers {
+  redirect_maps: Arc<RwLock<HashMap<String, CachedRedirectMap>>>,
+  handle: Handle,
+}
+
+// Obtain the redirect map loaded from a file, reloading it if the file has been modified
+async fn get_redirect_map(
+  redirect_maps: &RwLock<HashMap<String, CachedRedirectMap>>,
+  file: &str,
+  error_logger: &ErrorLogger,
+) -> Result<Arc<RedirectMap>, Box<dyn Error + Send + Sync>> {
+  if let Some(cached_redirect_map) = redirect_maps.read().await.get(file) {
+    if cached_redirect_map.checked.elapsed() < RELOAD_CHECK_INTERVAL {
+      return Ok(cached_redirect_map.redirect_map.clone());
+    }
+  }
+
+  let mut redirect_maps = redirect_maps.write().await;
+  let modified = fs::metadata(file)
+    .await
+    .and_then(|metadata| metadata.modified())
+    .ok();
+  if let Some(cached_redirect_map) = redirect_maps.get_mut(file) {
+    // The redirect map might have been checked by another request in the meantime
+    if cached_redirect_map.checked.elapsed() < RELOAD_CHECK_INTERVAL
+      || (modified.is_some() && cached_redirect_map.modified == modified)
+    {
+      cached_redirect_map.checked = Instant::now();
+      return Ok(cached_redirect_map.redirect_map.clone());
+    }
+  }
+
+  let load_result = match fs::read_to_string(file).await {
+    Ok(text) => RedirectMap::load_from_text(&text),
+    Err(err) => Err(anyhow::anyhow!(err)),
+  };
+  match load_result {
+    Ok(redirect_map) => {
+      let redirect_map = Arc::new(redirect_map);
+      redirect_maps.insert(
+        file.to_string(),
+        CachedRedirectMap {
+          redirect_map: redirect_map.clone(),
+          modified,
+          checked: Instant::now(),
+        },
+      );
+      Ok(redirect_map)
+    }
+    Err(err) => match redirect_maps.get_mut(file) {
+      // Keep using the previously loaded redirect map, if the modified one can't be loaded
+      Some(cached_redirect_map) => {
+        error_logger
+          .warn(&format!(
+            "Cannot reload the \"{}\" redirect map: {}",
+            file, err
+          ))
+          .await;
+        cached_redirect_map.modified = modified;
+        cached_redirect_map.checked = Instant::now();
+        Ok(cached_redirect_map.redirect_map.clone())
+      }
+      None => Err(anyhow::anyhow!(
+        "Cannot load the \"{}\" redirect map: {}",
+        file,
+        err
+      ))?,
+    },
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for RedirectMapsModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      if let Some(redirect_map_file) = config.get("redirectMap").as_str() {
+        let redirect_map =
+          get_redirect_map(&self.redirect_maps, redirect_map_file, error_logger).await?;
+        let request_uri = request.get_hyper_request().uri();
+        if let Some((target, status_code)) = redirect_map.get(request_uri.path()) {
+          // Preserve the query string, unless the redirect target has its own one
+          let location = match request_uri.query() {
+            Some(query) if !target.contains('?') => format!("{}?{}", target, query),
+            _ => target.to_string(),
+          };
+          return Ok(
+            ResponseData::builder(request)
+              .response(
+                Response::builder()
+                  .status(status_code)
+                  .header(header::LOCATION, location)
+                  .body(Empty::new().map_err(|e| match e {}).boxed())?,
+              )
+              .build(),
+          );
+        }
+      }
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
@@ -1,5 +1,5 @@
 use std::error::Error;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -10,13 +10,14 @@ use ferron_common::{
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use http_body_util::{BodyExt, Empty};
-use hyper::{header, Response, StatusCode};
+use hyper::{header, Method, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use tokio::fs;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 
 use crate::ferron_util::ttl_cache::TtlCache;
+use crate::ferron_util::user_directory::{resolve_user_directory, UserDirectory};
 
 pub fn server_module_init(
 ) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
@@ -58,7 +59,12 @@ impl ServerModuleHandlers for RedirectTrailingSlashesModuleHandlers {
     _error_logger: &ErrorLogger,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
-      if config.get("disableTrailingSlashRedirects").as_bool() != Some(true) {
+      // Only the GET and HEAD requests are redirected, so that the methods operating on collections
+      // (like the WebDAV ones) can address the directories without the trailing slashes
+      let method = request.get_hyper_request().method();
+      if config.get("disableTrailingSlashRedirects").as_bool() != Some(true)
+        && (method == Method::GET || method == Method::HEAD)
+      {
         if let Some(wwwroot) = config.get("wwwroot").as_str() {
           let hyper_request = request.get_hyper_request();
 
@@ -117,8 +123,14 @@ impl ServerModuleHandlers for RedirectTrailingSlashesModuleHandlers {
               } else {
                 drop(read_rwlock);
 
-                let path = Path::new(wwwroot);
-                let mut relative_path = &request_path[1..];
+                // Map the "/~user/..." paths to the users' public directories
+                let (path, mut relative_path) = match resolve_user_directory(config, request_path) {
+                  UserDirectory::Resolved {
+                    root,
+                    relative_path,
+                  } => (root, relative_path),
+                  _ => (PathBuf::from(wwwroot), &request_path[1..]),
+                };
                 while relative_path.as_bytes().first().copied() == Some(b'/') {
                   relative_path = &relative_path[1..];
                 }
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
+  ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use hyper::StatusCode;
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+use crate::ferron_util::url_signature::{parse_signature_query, verify_url_signature};
+
+pub fn server_module_init(
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(SignedUrlsModule::new()))
+}
+
+struct SignedUrlsModule;
+
+impl SignedUrlsModule {
+  fn new() -> Self {
+    SignedUrlsModule
+  }
+}
+
+impl ServerModule for SignedUrlsModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(SignedUrlsModuleHandlers { handle })
+  }
+}
+
+struct SignedUrlsModuleHandlers {
+  handle: Handle,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for SignedUrlsModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      if let Some(secret) = config.get("signedUrlSecret").as_str() {
+        let hyper_request = request.get_hyper_request();
+        let request_path = hyper_request.uri().path();
+        let (signature, expires) = parse_signature_query(hyper_request.uri().query().unwrap_or(""));
+
+        let is_valid = match (signature, expires) {
+          (Some(signature), Some(expires)) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            if expires < now {
+              error_logger
+                .info(&format!("Signed URL for \"{}\" has expired", request_path))
+                .await;
+              false
+            } else {
+              verify_url_signature(secret.as_bytes(), request_path, expires, signature)
+            }
+          }
+          _ => false,
+        };
+
+        if !is_valid {
+          return Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::FORBIDDEN)
+              .build(),
+          );
+        }
+      }
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
@@ -1,8 +1,7 @@
 use std::error::Error;
 use std::fmt::Write;
 use std::io::SeekFrom;
-use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -11,50 +10,73 @@ use async_trait::async_trait;
 use chrono::offset::Local;
 use chrono::DateTime;
 use ferron_common::{
-  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
-  ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
-use futures_util::TryStreamExt;
 use hashlink::LruCache;
-use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use http_body_util::{BodyExt, Empty};
 use hyper::body::Bytes;
-use hyper::{body::Frame, Response, StatusCode};
-use hyper::{header, HeaderMap, Method};
+use hyper::{header, header::HeaderValue, HeaderMap, Method};
+use hyper::{Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use sha2::{Digest, Sha256};
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
-use tokio_util::io::ReaderStream;
 
-use crate::ferron_util::generate_directory_listing::generate_directory_listing;
+use crate::ferron_util::blocking_budget::{run_budgeted, spawn_blocking_budgeted, BudgetedReader};
+use crate::ferron_util::byte_ranges::{
+  if_range_matches, multipart_byteranges_body, parse_byte_ranges, ByteRanges,
+};
+use crate::ferron_util::coalesced_read::{COALESCED_FILE_READS, COALESCED_READ_MIN_SIZE};
+use crate::ferron_util::conditional_requests::{
+  etag_list_matches, last_modified, not_modified_since,
+};
+use crate::ferron_util::file_body::{file_body, file_buffer_size};
+use crate::ferron_util::forwarded::TrustedProxies;
+use crate::ferron_util::generate_directory_listing::{
+  generate_directory_listing, DirectoryListingSort,
+};
+use crate::ferron_util::immutable_assets::immutable_asset_cache_control;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+use crate::ferron_util::language_negotiation::{
+  client_country, get_language_variant_path, parse_accept_language, select_language,
+};
 use crate::ferron_util::ttl_cache::TtlCache;
+use crate::ferron_util::user_directory::{
+  is_inside_user_directory, resolve_user_directory, UserDirectory,
+};
 
 pub fn server_module_init(
+  config: &ServerConfig,
 ) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
   let pathbuf_cache = Arc::new(RwLock::new(TtlCache::new(Duration::from_millis(100))));
   let etag_cache = Arc::new(RwLock::new(LruCache::new(1000)));
   Ok(Box::new(StaticFileServingModule::new(
     pathbuf_cache,
     etag_cache,
+    Arc::new(TrustedProxies::from_config(config)),
   )))
 }
 
 struct StaticFileServingModule {
   pathbuf_cache: Arc<RwLock<TtlCache<String, PathBuf>>>,
   etag_cache: Arc<RwLock<LruCache<String, String>>>,
+  trusted_proxies: Arc<TrustedProxies>,
 }
 
 impl StaticFileServingModule {
   fn new(
     pathbuf_cache: Arc<RwLock<TtlCache<String, PathBuf>>>,
     etag_cache: Arc<RwLock<LruCache<String, String>>>,
+    trusted_proxies: Arc<TrustedProxies>,
   ) -> Self {
     StaticFileServingModule {
       pathbuf_cache,
       etag_cache,
+      trusted_proxies,
     }
   }
 }
@@ -64,6 +86,7 @@ impl ServerModule for StaticFileServingModule {
     Box::new(StaticFileServingModuleHandlers {
       pathbuf_cache: self.pathbuf_cache.clone(),
       etag_cache: self.etag_cache.clone(),
+      trusted_proxies: self.trusted_proxies.clone(),
       handle,
     })
   }
@@ -71,38 +94,17 @@ impl ServerModule for StaticFileServingModule {
 struct StaticFileServingModuleHandlers {
   pathbuf_cache: Arc<RwLock<TtlCache<String, PathBuf>>>,
   etag_cache: Arc<RwLock<LruCache<String, String>>>,
+  trusted_proxies: Arc<TrustedProxies>,
   handle: Handle,
 }
 
-fn parse_range_header(range_str: &str, default_end: u64) -> Option<(u64, u64)> {
-  if let Some(range_part) = range_str.strip_prefix("bytes=") {
-    let parts: Vec<&str> = range_part.split('-').collect();
-    if parts.len() == 2 {
-      if parts[0].is_empty() {
-        if let Ok(end) = u64::from_str(parts[1]) {
-          return Some((default_end - end + 1, default_end));
-        }
-      } else if parts[1].is_empty() {
-        if let Ok(start) = u64::from_str(parts[0]) {
-          return Some((start, default_end));
-        }
-      } else if !parts[0].is_empty() && !parts[1].is_empty() {
-        if let (Ok(start), Ok(end)) = (u64::from_str(parts[0]), u64::from_str(parts[1])) {
-          return Some((start, end));
-        }
-      }
-    }
-  }
-  None
-}
-
 #[async_trait]
 impl ServerModuleHandlers for StaticFileServingModuleHandlers {
   async fn request_handler(
     &mut self,
     request: RequestData,
     config: &ServerConfigRoot,
-    _socket_data: &SocketData,
+    socket_data: &SocketData,
     _error_logger: &ErrorLogger,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
@@ -132,6 +134,23 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
           request_path
         );
 
+        // Map the "/~user/..." paths to the users' public directories
+        let (root_pathbuf, root_relative_path, user_directory_root) =
+          match resolve_user_directory(config, request_path) {
+            UserDirectory::None => (PathBuf::from(wwwroot), &request_path[1..], None),
+            UserDirectory::Denied => {
+              return Ok(
+                ResponseData::builder(request)
+                  .status(StatusCode::NOT_FOUND)
+                  .build(),
+              );
+            }
+            UserDirectory::Resolved {
+              root,
+              relative_path,
+            } => (root.clone(), relative_path, Some(root)),
+          };
+
         let rwlock_read = self.pathbuf_cache.read().await;
         let joined_pathbuf_option = rwlock_read.get(&cache_key);
         drop(rwlock_read);
@@ -140,8 +159,8 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
         let mut joined_pathbuf = match joined_pathbuf_option {
           Some(joined_pathbuf) => joined_pathbuf,
           None => {
-            let path = Path::new(wwwroot);
-            let mut relative_path = &request_path[1..];
+            let path = root_pathbuf.as_path();
+            let mut relative_path = root_relative_path;
             while relative_path.as_bytes().first().copied() == Some(b'/') {
               relative_path = &relative_path[1..];
             }
@@ -161,7 +180,7 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
           }
         };
 
-        match fs::metadata(&joined_pathbuf).await {
+        match run_budgeted("static_file_serving", fs::metadata(&joined_pathbuf)).await {
           Ok(mut metadata) => {
             if !joined_pathbuf_cached {
               if metadata.is_dir() {
@@ -199,9 +218,99 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
             }
 
             if metadata.is_file() {
+              // The headers sent with all the responses for the file
+              let mut file_headers = HeaderMap::new();
+
+              // Select a language variant of the file
+              if let Some(language_variants) = config.get("languageVariants").as_vec() {
+                let available_languages = language_variants
+                  .iter()
+                  .filter_map(|language| language.as_str().map(String::from))
+                  .collect::<Vec<_>>();
+                let country_header_name = config.get("languageVariantCountryHeader");
+                let country_header_name = country_header_name.as_str();
+
+                let vary = match country_header_name {
+                  Some(country_header_name) => format!("Accept-Language, {}", country_header_name),
+                  None => String::from("Accept-Language"),
+                };
+                file_headers.insert(header::VARY, HeaderValue::from_str(&vary)?);
+
+                let accepted_languages = match hyper_request.headers().get(header::ACCEPT_LANGUAGE)
+                {
+                  Some(value) => parse_accept_language(value.to_str().unwrap_or_default()),
+                  None => Vec::new(),
+                };
+                let mut selected_language =
+                  select_language(&accepted_languages, &available_languages);
+
+                // Fall back to the language mapped to the client's country
+                if selected_language.is_none() {
+                  let trusted_proxies = self
+                    .trusted_proxies
+                    .get(&config.get("trustedProxies"))
+                    .unwrap_or_else(|| Arc::new(IpPrefixTrie::new()));
+                  let geoip_country = request
+                    .get_variables()
+                    .and_then(|variables| variables.get("geoip_country"));
+                  if let Some(country) = client_country(
+                    geoip_country,
+                    hyper_request.headers(),
+                    country_header_name,
+                    socket_data.get_peer_addr().ip(),
+                    &trusted_proxies,
+                  ) {
+                    selected_language = config.get("languageVariantCountries")[country.as_str()]
+                      .as_str()
+                      .map(String::from);
+                  }
+                }
+
+                if let Some(selected_language) = selected_language {
+                  if let Some(variant_pathbuf) =
+                    get_language_variant_path(&joined_pathbuf, &selected_language)
+                  {
+                    if let Ok(variant_metadata) = fs::metadata(&variant_pathbuf).await {
+                      if variant_metadata.is_file() {
+                        metadata = variant_metadata;
+                        joined_pathbuf = variant_pathbuf;
+                        file_headers.insert(
+                          header::CONTENT_LANGUAGE,
+                          HeaderValue::from_str(&selected_language)?,
+                        );
+                      }
+                    }
+                  }
+                }
+              }
+
+              // The file served from a user's directory (also the selected language variant) can't be a link
+              // pointing outside of the directory
+              if let Some(user_directory_root) = &user_directory_root {
+                if !is_inside_user_directory(user_directory_root, &joined_pathbuf).await {
+                  return Ok(
+                    ResponseData::builder(request)
+                      .status(StatusCode::FORBIDDEN)
+                      .build(),
+                  );
+                }
+              }
+
+              let last_modified_option = last_modified(&metadata);
+
+              // The content-addressed assets never change, so the conditional requests aren't processed for them
+              let immutable_cache_control = immutable_asset_cache_control(&joined_pathbuf, config)?;
+              let is_immutable_asset = immutable_cache_control.is_some();
+              if let Some(immutable_cache_control) = immutable_cache_control {
+                file_headers.insert(
+                  header::CACHE_CONTROL,
+                  HeaderValue::from_str(&immutable_cache_control)?,
+                );
+              }
+
               // Handle ETags
               let mut etag_option = None;
-              if config.get("enableETag").as_bool() != Some(false) {
+              if config.get("enableETag").as_bool() != Some(false) && !is_immutable_asset {
                 let etag_cache_key = format!(
                   "{}-{}-{}",
                   joined_pathbuf.to_string_lossy(),
@@ -222,7 +331,7 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                   Some(etag) => etag,
                   None => {
                     let etag_cache_key_clone = etag_cache_key.clone();
-                    let etag = tokio::task::spawn_blocking(move || {
+                    let etag = spawn_blocking_budgeted("static_file_serving", move || {
                       let mut hasher = Sha256::new();
                       hasher.update(etag_cache_key_clone);
                       hasher
@@ -248,13 +357,21 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 {
                   match if_none_match_value.to_str() {
                     Ok(if_none_match) => {
-                      if if_none_match == etag {
+                      if etag_list_matches(if_none_match, &etag, true) {
+                        let mut response_builder = Response::builder()
+                          .status(StatusCode::NOT_MODIFIED)
+                          .header(header::ETAG, etag);
+                        if let Some(last_modified) = &last_modified_option {
+                          response_builder =
+                            response_builder.header(header::LAST_MODIFIED, last_modified);
+                        }
+                        if let Some(headers) = response_builder.headers_mut() {
+                          headers.extend(file_headers);
+                        }
                         return Ok(
                           ResponseData::builder(request)
                             .response(
-                              Response::builder()
-                                .status(StatusCode::NOT_MODIFIED)
-                                .header(header::ETAG, etag)
+                              response_builder
                                 .body(Empty::new().map_err(|e| match e {}).boxed())?,
                             )
                             .build(),
@@ -274,7 +391,7 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 if let Some(if_match_value) = hyper_request.headers().get(header::IF_MATCH) {
                   match if_match_value.to_str() {
                     Ok(if_match) => {
-                      if if_match != "*" && if_match != etag {
+                      if !etag_list_matches(if_match, &etag, false) {
                         let mut header_map = HeaderMap::new();
                         header_map.insert(header::ETAG, if_match_value.clone());
                         return Ok(
@@ -297,6 +414,38 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 etag_option = Some(etag);
               }
 
+              // The "If-Modified-Since" header is ignored if the "If-None-Match" header is present
+              if !is_immutable_asset && !hyper_request.headers().contains_key(header::IF_NONE_MATCH)
+              {
+                if let Some(if_modified_since_value) =
+                  hyper_request.headers().get(header::IF_MODIFIED_SINCE)
+                {
+                  if not_modified_since(
+                    if_modified_since_value.to_str().unwrap_or_default(),
+                    metadata.modified().ok(),
+                  ) {
+                    let mut response_builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+                    if let Some(etag) = etag_option {
+                      response_builder = response_builder.header(header::ETAG, etag);
+                    }
+                    if let Some(last_modified) = last_modified_option {
+                      response_builder =
+                        response_builder.header(header::LAST_MODIFIED, last_modified);
+                    }
+                    if let Some(headers) = response_builder.headers_mut() {
+                      headers.extend(file_headers);
+                    }
+                    return Ok(
+                      ResponseData::builder(request)
+                        .response(
+                          response_builder.body(Empty::new().map_err(|e| match e {}).boxed())?,
+                        )
+                        .build(),
+                    );
+                  }
+                }
+              }
+
               let content_type_option = new_mime_guess::from_path(&joined_pathbuf)
                 .first()
                 .map(|mime_type| mime_type.to_string());
@@ -315,47 +464,72 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 None => None,
               };
 
-              if let Some(range_header) = range_header {
-                let file_length = metadata.len();
-                if file_length == 0 {
-                  return Ok(
-                    ResponseData::builder(request)
-                      .status(StatusCode::RANGE_NOT_SATISFIABLE)
-                      .build(),
-                  );
+              // The ranges are only sent if the "If-Range" validator (if any) matches the file
+              let if_range_matched = match hyper_request.headers().get(header::IF_RANGE) {
+                Some(value) => if_range_matches(
+                  value.to_str().unwrap_or_default(),
+                  etag_option.as_deref(),
+                  metadata.modified().ok(),
+                ),
+                None => true,
+              };
+              let file_length = metadata.len();
+              let buffer_size = file_buffer_size(config);
+              let byte_ranges = match range_header {
+                Some(range_header) if if_range_matched => {
+                  parse_byte_ranges(range_header, file_length)
                 }
-                if let Some((range_begin, range_end)) =
-                  parse_range_header(range_header, file_length - 1)
-                {
-                  if range_end > file_length - 1
-                    || range_begin > file_length - 1
-                    || range_begin > range_end
-                  {
-                    return Ok(
-                      ResponseData::builder(request)
-                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
-                        .build(),
-                    );
-                  }
+                _ => ByteRanges::Ignored,
+              };
+
+              if byte_ranges == ByteRanges::Unsatisfiable {
+                let mut header_map = HeaderMap::new();
+                header_map.insert(
+                  header::CONTENT_RANGE,
+                  HeaderValue::from_str(&format!("bytes */{}", file_length))?,
+                );
+                return Ok(
+                  ResponseData::builder(request)
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .headers(header_map)
+                    .build(),
+                );
+              }
 
-                  let request_method = hyper_request.method();
-                  let content_length = range_end - range_begin + 1;
+              if let ByteRanges::Satisfiable(ranges) = byte_ranges {
+                let request_method = hyper_request.method();
+
+                if ranges.len() > 1 {
+                  // Multiple ranges are sent as a "multipart/byteranges" response
+                  let boundary = format!("{:016x}", rand::random::<u64>());
+                  let (content_length, multipart_body) = multipart_byteranges_body(
+                    joined_pathbuf.clone(),
+                    ranges,
+                    content_type_option.as_deref(),
+                    file_length,
+                    &boundary,
+                    buffer_size,
+                  );
 
-                  // Build response
                   let mut response_builder = Response::builder()
                     .status(StatusCode::PARTIAL_CONTENT)
                     .header(header::CONTENT_LENGTH, content_length)
                     .header(
-                      header::CONTENT_RANGE,
-                      format!("bytes {}-{}/{}", range_begin, range_end, file_length),
+                      header::CONTENT_TYPE,
+                      format!("multipart/byteranges; boundary={}", boundary),
                     );
 
                   if let Some(etag) = etag_option {
                     response_builder = response_builder.header(header::ETAG, etag);
                   }
 
-                  if let Some(content_type) = content_type_option {
-                    response_builder = response_builder.header(header::CONTENT_TYPE, content_type);
+                  if let Some(last_modified) = last_modified_option {
+                    response_builder =
+                      response_builder.header(header::LAST_MODIFIED, last_modified);
+                  }
+
+                  if let Some(headers) = response_builder.headers_mut() {
+                    headers.extend(file_headers);
                   }
 
                   let response = match request_method {
@@ -363,8 +537,71 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                       response_builder.body(Empty::new().map_err(|e| match e {}).boxed())?
                     }
                     _ => {
-                      // Open file for reading
-                      let mut file = match fs::File::open(joined_pathbuf).await {
+                      // Check if the file can be opened, since the parts are read only when the body is sent
+                      if let Err(err) = fs::File::open(&joined_pathbuf).await {
+                        match err.kind() {
+                          tokio::io::ErrorKind::NotFound | tokio::io::ErrorKind::NotADirectory => {
+                            return Ok(
+                              ResponseData::builder(request)
+                                .status(StatusCode::NOT_FOUND)
+                                .build(),
+                            );
+                          }
+                          tokio::io::ErrorKind::PermissionDenied => {
+                            return Ok(
+                              ResponseData::builder(request)
+                                .status(StatusCode::FORBIDDEN)
+                                .build(),
+                            );
+                          }
+                          _ => Err(err)?,
+                        }
+                      }
+                      response_builder.body(multipart_body)?
+                    }
+                  };
+
+                  return Ok(ResponseData::builder(request).response(response).build());
+                }
+
+                let (range_begin, range_end) = ranges[0];
+                let content_length = range_end - range_begin + 1;
+
+                // Build response
+                let mut response_builder = Response::builder()
+                  .status(StatusCode::PARTIAL_CONTENT)
+                  .header(header::CONTENT_LENGTH, content_length)
+                  .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range_begin, range_end, file_length),
+                  );
+
+                if let Some(etag) = etag_option {
+                  response_builder = response_builder.header(header::ETAG, etag);
+                }
+
+                if let Some(last_modified) = last_modified_option {
+                  response_builder = response_builder.header(header::LAST_MODIFIED, last_modified);
+                }
+
+                if let Some(headers) = response_builder.headers_mut() {
+                  headers.extend(file_headers);
+                }
+
+                if let Some(content_type) = content_type_option {
+                  response_builder = response_builder.header(header::CONTENT_TYPE, content_type);
+                }
+
+                let response = match request_method {
+                  &Method::HEAD => {
+                    response_builder.body(Empty::new().map_err(|e| match e {}).boxed())?
+                  }
+                  _ => {
+                    // Open file for reading
+                    let mut file =
+                      match run_budgeted("static_file_serving", fs::File::open(joined_pathbuf))
+                        .await
+                      {
                         Ok(file) => file,
                         Err(err) => match err.kind() {
                           tokio::io::ErrorKind::NotFound | tokio::io::ErrorKind::NotADirectory => {
@@ -385,30 +622,21 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                         },
                       };
 
-                      // Seek and limit the file reader
-                      file.seek(SeekFrom::Start(range_begin)).await?;
-                      let file_limited = file.take(content_length);
-
-                      // Use BufReader for better performance.
-                      let file_bufreader = BufReader::with_capacity(12800, file_limited);
+                    // Seek and limit the file reader
+                    file.seek(SeekFrom::Start(range_begin)).await?;
+                    let file_limited = file.take(content_length);
 
-                      // Construct a boxed body
-                      let reader_stream = ReaderStream::new(file_bufreader);
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      let boxed_body = stream_body.boxed();
+                    // Construct a boxed body
+                    let boxed_body = file_body(
+                      BudgetedReader::new("static_file_serving", file_limited),
+                      buffer_size,
+                    );
 
-                      response_builder.body(boxed_body)?
-                    }
-                  };
+                    response_builder.body(boxed_body)?
+                  }
+                };
 
-                  return Ok(ResponseData::builder(request).response(response).build());
-                } else {
-                  return Ok(
-                    ResponseData::builder(request)
-                      .status(StatusCode::RANGE_NOT_SATISFIABLE)
-                      .build(),
-                  );
-                }
+                return Ok(ResponseData::builder(request).response(response).build());
               } else {
                 let mut use_gzip = false;
                 let mut use_deflate = false;
@@ -602,6 +830,14 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                   response_builder = response_builder.header(header::ETAG, etag);
                 }
 
+                if let Some(last_modified) = last_modified_option {
+                  response_builder = response_builder.header(header::LAST_MODIFIED, last_modified);
+                }
+
+                if let Some(headers) = response_builder.headers_mut() {
+                  headers.extend(file_headers);
+                }
+
                 if let Some(content_type) = content_type_option {
                   response_builder = response_builder.header(header::CONTENT_TYPE, content_type);
                 }
@@ -626,51 +862,72 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                   }
                   _ => {
                     // Open file for reading
-                    let file = match fs::File::open(joined_pathbuf).await {
-                      Ok(file) => file,
-                      Err(err) => match err.kind() {
-                        tokio::io::ErrorKind::NotFound | tokio::io::ErrorKind::NotADirectory => {
-                          return Ok(
-                            ResponseData::builder(request)
-                              .status(StatusCode::NOT_FOUND)
-                              .build(),
-                          );
-                        }
-                        tokio::io::ErrorKind::PermissionDenied => {
-                          return Ok(
-                            ResponseData::builder(request)
-                              .status(StatusCode::FORBIDDEN)
-                              .build(),
-                          );
-                        }
-                        _ => Err(err)?,
-                      },
-                    };
-
-                    // Use BufReader for better performance.
-                    let file_bufreader = BufReader::with_capacity(12800, file);
+                    let file =
+                      match run_budgeted("static_file_serving", fs::File::open(&joined_pathbuf))
+                        .await
+                      {
+                        Ok(file) => file,
+                        Err(err) => match err.kind() {
+                          tokio::io::ErrorKind::NotFound | tokio::io::ErrorKind::NotADirectory => {
+                            return Ok(
+                              ResponseData::builder(request)
+                                .status(StatusCode::NOT_FOUND)
+                                .build(),
+                            );
+                          }
+                          tokio::io::ErrorKind::PermissionDenied => {
+                            return Ok(
+                              ResponseData::builder(request)
+                                .status(StatusCode::FORBIDDEN)
+                                .build(),
+                            );
+                          }
+                          _ => Err(err)?,
+                        },
+                      };
 
-                    // Construct a boxed body
+                    // Construct a boxed body. The compressors read the file through a BufReader,
+                    // while the uncompressed files are read directly into the response chunks.
                     let boxed_body = if use_brotli {
-                      let reader_stream = ReaderStream::new(BrotliEncoder::new(file_bufreader));
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      stream_body.boxed()
+                      let file_bufreader = BufReader::with_capacity(
+                        buffer_size,
+                        BudgetedReader::new("static_file_serving", file),
+                      );
+                      file_body(BrotliEncoder::new(file_bufreader), buffer_size)
                     } else if use_zstd {
-                      let reader_stream = ReaderStream::new(ZstdEncoder::new(file_bufreader));
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      stream_body.boxed()
+                      let file_bufreader = BufReader::with_capacity(
+                        buffer_size,
+                        BudgetedReader::new("static_file_serving", file),
+                      );
+                      file_body(ZstdEncoder::new(file_bufreader), buffer_size)
                     } else if use_deflate {
-                      let reader_stream = ReaderStream::new(DeflateEncoder::new(file_bufreader));
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      stream_body.boxed()
+                      let file_bufreader = BufReader::with_capacity(
+                        buffer_size,
+                        BudgetedReader::new("static_file_serving", file),
+                      );
+                      file_body(DeflateEncoder::new(file_bufreader), buffer_size)
                     } else if use_gzip {
-                      let reader_stream = ReaderStream::new(GzipEncoder::new(file_bufreader));
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      stream_body.boxed()
+                      let file_bufreader = BufReader::with_capacity(
+                        buffer_size,
+                        BudgetedReader::new("static_file_serving", file),
+                      );
+                      file_body(GzipEncoder::new(file_bufreader), buffer_size)
+                    } else if config.get("enableFileReadCoalescing").as_bool() == Some(true)
+                      && content_length >= COALESCED_READ_MIN_SIZE
+                    {
+                      // The concurrent responses sending the same large file share the file reads
+                      COALESCED_FILE_READS.file_body(
+                        &joined_pathbuf,
+                        file,
+                        content_length,
+                        metadata.modified().ok(),
+                        buffer_size,
+                      )
                     } else {
-                      let reader_stream = ReaderStream::new(file_bufreader);
-                      let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-                      stream_body.boxed()
+                      file_body(
+                        BudgetedReader::new("static_file_serving", file),
+                        buffer_size,
+                      )
                     };
 
                     response_builder.body(boxed_body)?
@@ -680,41 +937,67 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 return Ok(ResponseData::builder(request).response(response).build());
               }
             } else if metadata.is_dir() {
+              if let Some(user_directory_root) = &user_directory_root {
+                if !is_inside_user_directory(user_directory_root, &joined_pathbuf).await {
+                  return Ok(
+                    ResponseData::builder(request)
+                      .status(StatusCode::FORBIDDEN)
+                      .build(),
+                  );
+                }
+              }
+
               if config.get("enableDirectoryListing").as_bool() == Some(true) {
                 let joined_maindesc_pathbuf = joined_pathbuf.join(".maindesc");
-                let directory = match fs::read_dir(joined_pathbuf).await {
-                  Ok(directory) => directory,
-                  Err(err) => match err.kind() {
-                    tokio::io::ErrorKind::NotFound => {
-                      return Ok(
-                        ResponseData::builder(request)
-                          .status(StatusCode::NOT_FOUND)
-                          .build(),
-                      );
-                    }
-                    tokio::io::ErrorKind::PermissionDenied => {
-                      return Ok(
-                        ResponseData::builder(request)
-                          .status(StatusCode::FORBIDDEN)
-                          .build(),
-                      );
-                    }
-                    _ => Err(err)?,
-                  },
-                };
+                let directory =
+                  match run_budgeted("static_file_serving", fs::read_dir(joined_pathbuf)).await {
+                    Ok(directory) => directory,
+                    Err(err) => match err.kind() {
+                      tokio::io::ErrorKind::NotFound => {
+                        return Ok(
+                          ResponseData::builder(request)
+                            .status(StatusCode::NOT_FOUND)
+                            .build(),
+                        );
+                      }
+                      tokio::io::ErrorKind::PermissionDenied => {
+                        return Ok(
+                          ResponseData::builder(request)
+                            .status(StatusCode::FORBIDDEN)
+                            .build(),
+                        );
+                      }
+                      _ => Err(err)?,
+                    },
+                  };
 
-                let description = match fs::read_to_string(joined_maindesc_pathbuf).await {
-                  Ok(contents) => Some(contents),
-                  Err(_) => None,
+                // The description in a user's directory can't be read from outside of the directory
+                let description = match &user_directory_root {
+                  Some(user_directory_root)
+                    if !is_inside_user_directory(user_directory_root, &joined_maindesc_pathbuf)
+                      .await =>
+                  {
+                    None
+                  }
+                  _ => fs::read_to_string(joined_maindesc_pathbuf).await.ok(),
                 };
 
-                let directory_listing_html =
-                  generate_directory_listing(directory, request_path, description).await?;
-                let content_length: Option<u64> = match directory_listing_html.len().try_into() {
-                  Ok(content_length) => Some(content_length),
-                  Err(_) => None,
+                // Use a custom directory listing template, if it's configured
+                let template = match config.get("directoryListingTemplate").as_str() {
+                  Some(template_path) => Some(fs::read_to_string(template_path).await?),
+                  None => None,
                 };
 
+                let directory_listing_html = generate_directory_listing(
+                  directory,
+                  request_path,
+                  description,
+                  DirectoryListingSort::from_query(hyper_request.uri().query()),
+                  template,
+                )
+                .await?;
+                let content_length: Option<u64> = directory_listing_html.len().try_into().ok();
+
                 let mut response_builder = Response::builder().status(StatusCode::OK);
 
                 if let Some(content_length) = content_length {
@@ -722,13 +1005,15 @@ impl ServerModuleHandlers for StaticFileServingModuleHandlers {
                 }
                 response_builder = response_builder.header(header::CONTENT_TYPE, "text/html");
 
-                let response = response_builder.body(
-                  Full::new(Bytes::from(directory_listing_html))
-                    .map_err(|e| match e {})
-                    .boxed(),
-                )?;
+                // The directory listings are validated with the entity tags, so the clients polling the directory
+                // don't download the listing again if the directory hasn't changed
+                let response = response_builder.body(Bytes::from(directory_listing_html))?;
 
-                return Ok(ResponseData::builder(request).response(response).build());
+                return Ok(
+                  ResponseData::builder(request)
+                    .validated_response(response)
+                    .build(),
+                );
               } else {
                 return Ok(
                   ResponseData::builder(request)
@@ -0,0 +1,151 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
+  ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+struct StaticResponsesModule;
+
+pub fn server_module_init(
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(StaticResponsesModule::new()))
+}
+
+impl StaticResponsesModule {
+  fn new() -> Self {
+    StaticResponsesModule
+  }
+}
+
+impl ServerModule for StaticResponsesModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(StaticResponsesModuleHandlers { handle })
+  }
+}
+struct StaticResponsesModuleHandlers {
+  handle: Handle,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for StaticResponsesModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let respond_yaml = config.get("respond");
+      if respond_yaml.is_badvalue() {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      // The "respond" property is either a status code, or a hash with the status code, the body and the headers
+      let (status_code, body) = match respond_yaml.as_i64() {
+        Some(status_code) => (status_code, None),
+        None => (
+          respond_yaml["status"].as_i64().unwrap_or(200),
+          respond_yaml["body"].as_str(),
+        ),
+      };
+
+      let mut response_builder =
+        Response::builder().status(StatusCode::from_u16(status_code.try_into()?)?);
+      if let Some(headers_hash) = respond_yaml["headers"].as_hash() {
+        for (header_name, header_value) in headers_hash.iter() {
+          if let Some(header_name) = header_name.as_str() {
+            if let Some(header_value) = header_value.as_str() {
+              response_builder = response_builder.header(
+                HeaderName::from_bytes(header_name.as_bytes())?,
+                HeaderValue::from_str(header_value)?,
+              );
+            }
+          }
+        }
+      }
+      if body.is_some()
+        && response_builder
+          .headers_ref()
+          .is_some_and(|headers| !headers.contains_key(header::CONTENT_TYPE))
+      {
+        response_builder = response_builder.header(header::CONTENT_TYPE, "text/plain");
+      }
+
+      Ok(
+        ResponseData::builder(request)
+          .validated_response(
+            response_builder.body(Bytes::from(body.unwrap_or_default().to_string()))?,
+          )
+          .build(),
+      )
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
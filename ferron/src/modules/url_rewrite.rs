@@ -2,12 +2,14 @@ use std::error::Error;
 use std::path::Path;
 use std::sync::Arc;
 
+use crate::ferron_util::expression::{Expression, ExpressionContext};
 use crate::ferron_util::ip_match::ip_match;
 use crate::ferron_util::match_hostname::match_hostname;
-use crate::ferron_util::match_location::match_location;
+use crate::ferron_util::match_location::{select_location, LocationMatcher};
 use crate::ferron_util::url_rewrite_structs::{
   UrlRewriteMapEntry, UrlRewriteMapLocationWrap, UrlRewriteMapWrap,
 };
+use crate::ferron_util::variable_substitution::substitute_variables;
 
 use async_trait::async_trait;
 use fancy_regex::RegexBuilder;
@@ -54,6 +56,12 @@ fn url_rewrite_config_init(rewrite_map: &[Yaml]) -> Result<Vec<UrlRewriteMapEntr
     let allow_double_slashes = rewrite_map_entry["allowDoubleSlashes"]
       .as_bool()
       .unwrap_or(false);
+    let condition = match rewrite_map_entry["condition"].as_str() {
+      Some(condition) => Some(Expression::compile(condition).map_err(|err| {
+        anyhow::anyhow!("Invalid URL rewrite condition \"{}\": {}", condition, err)
+      })?),
+      None => None,
+    };
     rewrite_map_vec.push(UrlRewriteMapEntry::new(
       regex,
       replacement,
@@ -61,6 +69,7 @@ fn url_rewrite_config_init(rewrite_map: &[Yaml]) -> Result<Vec<UrlRewriteMapEntr
       is_not_file,
       last,
       allow_double_slashes,
+      condition,
     ));
   }
 
@@ -83,14 +92,11 @@ pub fn server_module_init(
       let mut locations = Vec::new();
       if let Some(locations_yaml) = host_yaml["locations"].as_vec() {
         for location_yaml in locations_yaml.iter() {
-          if let Some(path_str) = location_yaml["path"].as_str() {
-            let path = String::from(path_str);
-            if let Some(rewrite_map_yaml) = location_yaml["rewriteMap"].as_vec() {
-              locations.push(UrlRewriteMapLocationWrap::new(
-                path,
-                url_rewrite_config_init(rewrite_map_yaml)?,
-              ));
-            }
+          if let Some(rewrite_map_yaml) = location_yaml["rewriteMap"].as_vec() {
+            locations.push(UrlRewriteMapLocationWrap::new(
+              LocationMatcher::from_yaml(location_yaml)?,
+              url_rewrite_config_init(rewrite_map_yaml)?,
+            ));
           }
         }
       }
@@ -170,10 +176,7 @@ impl ServerModuleHandlers for UrlRewriteModuleHandlers {
             None => None,
           },
           match hyper_request.headers().get(header::HOST) {
-            Some(value) => match value.to_str() {
-              Ok(value) => Some(value),
-              Err(_) => None,
-            },
+            Some(value) => value.to_str().ok(),
             None => None,
           },
         ) && match &host_url_rewrite_map_wrap.ip {
@@ -182,11 +185,17 @@ impl ServerModuleHandlers for UrlRewriteModuleHandlers {
         } {
           host_url_rewrite_map = host_url_rewrite_map_wrap.rewrite_map.iter();
           if let Ok(path_decoded) = urlencoding::decode(request.get_hyper_request().uri().path()) {
-            for location_wrap in host_url_rewrite_map_wrap.locations.iter() {
-              if match_location(&location_wrap.path, &path_decoded) {
-                location_url_rewrite_map = location_wrap.rewrite_map.iter();
-                break;
-              }
+            let location_wrap =
+              select_location(host_url_rewrite_map_wrap.locations.iter().filter_map(
+                |location_wrap| {
+                  location_wrap
+                    .matcher
+                    .matches(&path_decoded)
+                    .map(|location_match| (location_wrap, location_match))
+                },
+              ));
+            if let Some(location_wrap) = location_wrap {
+              location_url_rewrite_map = location_wrap.rewrite_map.iter();
             }
           }
           break;
@@ -216,7 +225,15 @@ impl ServerModuleHandlers for UrlRewriteModuleHandlers {
         );
       }
 
+      let expression_context = ExpressionContext::new(hyper_request, socket_data);
       for url_rewrite_map_entry in combined_url_rewrite_map {
+        // The conditions are evaluated for the original request
+        if let Some(condition) = &url_rewrite_map_entry.condition {
+          if !condition.evaluate(&expression_context) {
+            continue;
+          }
+        }
+
         // Check if it's a file or a directory according to the rewrite map configuration
         if url_rewrite_map_entry.is_not_directory || url_rewrite_map_entry.is_not_file {
           if let Some(wwwroot) = config.get("wwwroot").as_str() {
@@ -246,11 +263,17 @@ impl ServerModuleHandlers for UrlRewriteModuleHandlers {
           }
         }
 
-        // Actual URL rewriting
+        // Actual URL rewriting. The request variables (for example, "$host") are substituted in the replacement
+        // before the capture groups, so the "$" characters in the variable values are escaped.
+        let replacement = substitute_variables(&url_rewrite_map_entry.replacement, |name| {
+          expression_context
+            .variable(name)
+            .map(|value| value.replace('$', "$$"))
+        });
         let old_rewritten_url = rewritten_url;
         rewritten_url = url_rewrite_map_entry
           .regex
-          .replace(&old_rewritten_url, &url_rewrite_map_entry.replacement)
+          .replace(&old_rewritten_url, &replacement)
           .to_string();
 
         let mut rewritten_url_bytes = rewritten_url.bytes();
@@ -272,7 +295,7 @@ impl ServerModuleHandlers for UrlRewriteModuleHandlers {
       } else {
         if config.get("enableRewriteLogging").as_bool() == Some(true) {
           error_logger
-            .log(&format!(
+            .info(&format!(
               "URL rewritten from \"{}\" to \"{}\"",
               original_url, rewritten_url
             ))
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use futures_util::stream::{self, StreamExt};
+use http_body_util::{BodyExt, BodyStream, Empty, StreamBody};
+use hyper::body::Body;
+use hyper::{header, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::waf::{
+  load_waf_rule_sets, parse_waf_args, WafInspection, WafMatch, WafRule, WafTarget, WafValue,
+  DEFAULT_SCORE_THRESHOLD,
+};
+
+// The default maximum size of the request body part inspected by the WAF
+const DEFAULT_BODY_INSPECTION_LIMIT: usize = 65536;
+
+// Check if the rule sets used in the configuration are defined
+fn check_waf_rule_set_names(
+  config: &Yaml,
+  rule_sets: &HashMap<String, Arc<Vec<WafRule>>>,
+) -> Result<(), anyhow::Error> {
+  if let Some(rule_set_names) = config["wafRuleSets"].as_vec() {
+    for rule_set_name in rule_set_names {
+      if let Some(rule_set_name) = rule_set_name.as_str() {
+        if !rule_sets.contains_key(rule_set_name) {
+          Err(anyhow::anyhow!(
+            "The \"{}\" WAF rule set doesn't exist",
+            rule_set_name
+          ))?
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+pub fn server_module_init(
+  config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  let rule_sets = load_waf_rule_sets(&config["global"]["wafRules"])?;
+  check_waf_rule_set_names(&config["global"], &rule_sets)?;
+  if let Some(hosts) = config["hosts"].as_vec() {
+    for host_yaml in hosts.iter() {
+      check_waf_rule_set_names(host_yaml, &rule_sets)?;
+      if let Some(locations) = host_yaml["locations"].as_vec() {
+        for location_yaml in locations.iter() {
+          check_waf_rule_set_names(location_yaml, &rule_sets)?;
+        }
+      }
+    }
+  }
+
+  Ok(Box::new(WafModule::new(Arc::new(rule_sets))))
+}
+
+struct WafModule {
+  rule_sets: Arc<HashMap<String, Arc<Vec<WafRule>>>>,
+}
+
+impl WafModule {
+  fn new(rule_sets: Arc<HashMap<String, Arc<Vec<WafRule>>>>) -> Self {
+    WafModule { rule_sets }
+  }
+}
+
+impl ServerModule for WafModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(WafModuleHandlers {
+      rule_sets: self.rule_sets.clone(),
+      handle,
+    })
+  }
+}
+
+struct WafModuleHandlers {
+  rule_sets: Arc<HashMap<String, Arc<Vec<WafRule>>>>,
+  handle: Handle,
+}
+
+// Log the WAF rule matches and count them in the metrics
+async fn report_waf_matches(
+  matches: Vec<WafMatch>,
+  request_description: &str,
+  error_logger: &ErrorLogger,
+) {
+  for waf_match in matches {
+    let rule_id = waf_match.rule_id.to_string();
+    let mode = if waf_match.observe {
+      "observe"
+    } else {
+      "enforce"
+    };
+    METRICS.increment_counter(
+      "ferron_waf_rule_matches_total",
+      &[
+        ("rule", &rule_id),
+        ("action", waf_match.action.as_str()),
+        ("mode", mode),
+      ],
+    );
+    error_logger
+      .warn(&format!(
+        "WAF rule {} (\"{}\") matched the {} of the request {}, action: {}{}",
+        waf_match.rule_id,
+        waf_match.message,
+        waf_match.location,
+        request_description,
+        waf_match.action.as_str(),
+        if waf_match.observe {
+          " (not enforced, the rule is in the observe mode)"
+        } else {
+          ""
+        }
+      ))
+      .await;
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for WafModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    mut request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let rules: Vec<Arc<Vec<WafRule>>> = match config.get("wafRuleSets").as_vec() {
+        Some(rule_set_names) => rule_set_names
+          .iter()
+          .filter_map(|rule_set_name| self.rule_sets.get(rule_set_name.as_str()?).cloned())
+          .collect(),
+        None => Vec::new(),
+      };
+      if rules.is_empty() {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      // In the detection mode, the matches are logged, but the requests aren't blocked
+      let detection_only = config.get("wafMode").as_str() == Some("detect");
+      let score_threshold = config
+        .get("wafScoreThreshold")
+        .as_i64()
+        .map(|score_threshold| score_threshold as u64)
+        .unwrap_or(DEFAULT_SCORE_THRESHOLD);
+      let body_inspection_limit = config
+        .get("wafBodyInspectionLimit")
+        .as_i64()
+        .map(|body_inspection_limit| body_inspection_limit as usize)
+        .unwrap_or(DEFAULT_BODY_INSPECTION_LIMIT);
+      let mut inspection = WafInspection::new(rules, score_threshold);
+
+      let hyper_request = request.get_hyper_request();
+      let request_description = format!(
+        "from {} to \"{}\"",
+        socket_data.remote_addr.ip().to_canonical(),
+        hyper_request.uri().path()
+      );
+      let is_form = hyper_request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+          content_type
+            .to_lowercase()
+            .starts_with("application/x-www-form-urlencoded")
+        });
+
+      // The request line and the headers are inspected first
+      let mut values = vec![
+        WafValue::new(
+          WafTarget::Method,
+          None,
+          hyper_request.method().to_string(),
+        ),
+        WafValue::new(
+          WafTarget::Path,
+          None,
+          hyper_request.uri().path().to_string(),
+        ),
+      ];
+      if let Some(query) = hyper_request.uri().query() {
+        values.push(WafValue::new(WafTarget::Query, None, query.to_string()));
+        values.append(&mut parse_waf_args(query));
+      }
+      for (header_name, header_value) in hyper_request.headers() {
+        values.push(WafValue::new(
+          WafTarget::Headers,
+          Some(header_name.as_str()),
+          String::from_utf8_lossy(header_value.as_bytes()).into_owned(),
+        ));
+      }
+      let (matches, mut blocked) = inspection.inspect(&values);
+      report_waf_matches(matches, &request_description, error_logger).await;
+
+      // The beginning of the request body is buffered for the inspection, and then passed on along with the rest of the body
+      if !blocked
+        && body_inspection_limit > 0
+        && inspection.inspects_body()
+        && !hyper_request.body().is_end_stream()
+      {
+        let body = std::mem::replace(
+          request.get_mut_hyper_request().body_mut(),
+          Empty::new().map_err(|e| match e {}).boxed(),
+        );
+        let mut body_stream = BodyStream::new(body);
+        let mut buffered_frames = Vec::new();
+        let mut buffered_body = Vec::new();
+        while buffered_body.len() < body_inspection_limit {
+          match body_stream.next().await {
+            Some(frame) => {
+              let frame = frame?;
+              if let Some(data) = frame.data_ref() {
+                buffered_body.extend_from_slice(data);
+              }
+              buffered_frames.push(Ok(frame));
+            }
+            None => break,
+          }
+        }
+        buffered_body.truncate(body_inspection_limit);
+        *request.get_mut_hyper_request().body_mut() =
+          BodyExt::boxed(StreamBody::new(
+            stream::iter(buffered_frames).chain(body_stream),
+          ));
+
+        let body_text = String::from_utf8_lossy(&buffered_body);
+        let mut values = Vec::new();
+        if is_form {
+          values = parse_waf_args(&body_text);
+        }
+        values.push(WafValue::new(WafTarget::Body, None, body_text.into_owned()));
+        let (matches, body_blocked) = inspection.inspect(&values);
+        report_waf_matches(matches, &request_description, error_logger).await;
+        blocked = body_blocked;
+      }
+
+      if blocked {
+        if detection_only {
+          error_logger
+            .warn(&format!(
+              "WAF would block the request {} (anomaly score {}), but the WAF is in the detection mode",
+              request_description,
+              inspection.score()
+            ))
+            .await;
+        } else {
+          METRICS.increment_counter("ferron_waf_blocked_requests_total", &[]);
+          error_logger
+            .log(&format!(
+              "WAF blocked the request {} (anomaly score {})",
+              request_description,
+              inspection.score()
+            ))
+            .await;
+          return Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::FORBIDDEN)
+              .build(),
+          );
+        }
+      }
+
+      Ok(ResponseData::builder(request).build())
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
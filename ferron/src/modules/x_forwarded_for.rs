@@ -1,35 +1,49 @@
 use std::error::Error;
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use ferron_common::{
-  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfigRoot, ServerModule,
-  ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use hyper::StatusCode;
 use hyper_tungstenite::HyperWebsocket;
 use tokio::runtime::Handle;
 
-struct XForwardedForModule;
+use crate::ferron_util::forwarded::{
+  parse_client_ip_chain, select_client_ip, ClientIpHeader, TrustedProxies,
+};
+
+struct XForwardedForModule {
+  trusted_proxies: Arc<TrustedProxies>,
+}
 
 pub fn server_module_init(
+  config: &ServerConfig,
 ) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
-  Ok(Box::new(XForwardedForModule::new()))
+  Ok(Box::new(XForwardedForModule::new(Arc::new(
+    TrustedProxies::from_config(config),
+  ))))
 }
 
 impl XForwardedForModule {
-  fn new() -> Self {
-    XForwardedForModule
+  fn new(trusted_proxies: Arc<TrustedProxies>) -> Self {
+    XForwardedForModule { trusted_proxies }
   }
 }
 
 impl ServerModule for XForwardedForModule {
   fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
-    Box::new(XForwardedForModuleHandlers { handle })
+    Box::new(XForwardedForModuleHandlers {
+      trusted_proxies: self.trusted_proxies.clone(),
+      handle,
+    })
   }
 }
 struct XForwardedForModuleHandlers {
+  trusted_proxies: Arc<TrustedProxies>,
   handle: Handle,
 }
 
@@ -44,33 +58,43 @@ impl ServerModuleHandlers for XForwardedForModuleHandlers {
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
       if config.get("enableIPSpoofing").as_bool() == Some(true) {
-        let hyper_request = request.get_hyper_request();
+        // If the trusted proxies are configured, the forwarded addresses are accepted only from them
+        let trusted_proxies = self.trusted_proxies.get(&config.get("trustedProxies"));
+        if trusted_proxies
+          .as_ref()
+          .is_some_and(|trusted_proxies| !trusted_proxies.contains(socket_data.remote_addr.ip()))
+        {
+          return Ok(ResponseData::builder(request).build());
+        }
 
-        if let Some(x_forwarded_for_value) = hyper_request.headers().get("x-forwarded-for") {
-          let x_forwarded_for = x_forwarded_for_value.to_str()?;
-
-          let prepared_remote_ip_str = match x_forwarded_for.split(",").nth(0) {
-            Some(ip_address_str) => ip_address_str.replace(" ", ""),
-            None => {
-              return Ok(
-                ResponseData::builder(request)
-                  .status(StatusCode::BAD_REQUEST)
-                  .build(),
-              );
-            }
-          };
+        let hyper_request = request.get_hyper_request();
 
-          let prepared_remote_ip: IpAddr = match prepared_remote_ip_str.parse() {
-            Ok(ip_address) => ip_address,
-            Err(_) => {
-              return Ok(
-                ResponseData::builder(request)
-                  .status(StatusCode::BAD_REQUEST)
-                  .build(),
-              );
-            }
+        // Only the configured header is used, so that the client can't spoof its address with the other headers
+        let client_ip_header =
+          match ClientIpHeader::from_config(config.get("trustedClientIpHeader").as_str()) {
+            Some(client_ip_header) => client_ip_header,
+            None => Err(anyhow::anyhow!("Unsupported trusted client IP header"))?,
           };
-
+        let chain = parse_client_ip_chain(hyper_request.headers(), client_ip_header);
+
+        let chain = match chain {
+          Some(chain) if chain.is_empty() => return Ok(ResponseData::builder(request).build()),
+          Some(chain) => chain,
+          None => {
+            return Ok(
+              ResponseData::builder(request)
+                .status(StatusCode::BAD_REQUEST)
+                .build(),
+            );
+          }
+        };
+
+        let prepared_remote_ip = match trusted_proxies {
+          Some(trusted_proxies) => select_client_ip(&chain, &trusted_proxies),
+          None => chain.first().copied().flatten(),
+        };
+
+        if let Some(prepared_remote_ip) = prepared_remote_ip {
           let new_socket_addr = SocketAddr::new(prepared_remote_ip, socket_data.remote_addr.port());
 
           return Ok(
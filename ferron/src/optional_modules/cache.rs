@@ -1,27 +1,44 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use cache_control::{Cachability, CacheControl};
 use ferron_common::{
   ErrorLogger, HyperUpgraded, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
-  ServerModule, ServerModuleHandlers, SocketData,
+  ServerModule, ServerModuleHandlers, SocketData, SubrequestHandler,
 };
 use ferron_common::{HyperResponse, WithRuntime};
+use futures_util::future::join_all;
 use futures_util::{StreamExt, TryStreamExt};
-use http_body_util::{BodyExt, Full, StreamBody};
-use hyper::body::{Bytes, Frame};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
 use hyper::header::HeaderValue;
-use hyper::{header, HeaderMap, Method, Response, StatusCode};
+use hyper::{header, HeaderMap, Method, Request, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use itertools::Itertools;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 
+use crate::ferron_util::admin_api::cache_purge_generation;
+use crate::ferron_util::byte_ranges::parse_content_range;
+use crate::ferron_util::esi::{parse_esi, resolve_esi_url, EsiSegment};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::storage::{unix_time_millis, StorageBackend, STORAGE};
+
 const CACHE_HEADER_NAME: &str = "X-Ferron-Cache";
 const DEFAULT_MAX_AGE: u64 = 300;
+const SURROGATE_CONTROL_HEADER_NAME: &str = "Surrogate-Control";
+// How long the partial response of an interrupted cache fill is kept for resuming the fill
+const PARTIAL_FILL_LIFETIME: Duration = Duration::from_secs(3600);
+// The prefixes of the keys of the cached responses and of their varying request headers in the shared storage
+const CACHE_KEY_PREFIX: &str = "ferron:cache:";
+const VARY_KEY_PREFIX: &str = "ferron:cache-vary:";
 
 pub fn server_module_init(
   _config: &ServerConfig,
@@ -29,48 +46,361 @@ pub fn server_module_init(
   Ok(Box::new(CacheModule::new(
     Arc::new(RwLock::new(HashMap::new())),
     Arc::new(RwLock::new(HashMap::new())),
+    Arc::new(RwLock::new(HashMap::new())),
+    Arc::new(AtomicU64::new(cache_purge_generation())),
   )))
 }
 
-#[allow(clippy::type_complexity)]
+// A cached response
+struct CacheEntry {
+  status_code: StatusCode,
+  headers: HeaderMap,
+  body: Vec<u8>,
+  timestamp: Instant,
+  // The time the response is fresh for
+  max_age: Duration,
+  // The host the response was cached for, used in the cache metrics
+  host: String,
+}
+
+impl CacheEntry {
+  fn is_fresh(&self) -> bool {
+    self.timestamp.elapsed() <= self.max_age
+  }
+
+  // Encode the cached response for the shared storage. The response is encoded as the creation time (in milliseconds
+  // since the Unix epoch), the freshness lifetime (in milliseconds), the status code, the host, the headers, and the body.
+  fn encode(&self) -> Vec<u8> {
+    let created_at = unix_time_millis().saturating_sub(self.timestamp.elapsed().as_millis() as u64);
+    let mut encoded = Vec::with_capacity(self.body.len() + 1024);
+    encoded.extend_from_slice(&created_at.to_be_bytes());
+    encoded.extend_from_slice(&(self.max_age.as_millis() as u64).to_be_bytes());
+    encoded.extend_from_slice(&self.status_code.as_u16().to_be_bytes());
+    let push_bytes = |encoded: &mut Vec<u8>, bytes: &[u8]| {
+      encoded.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+      encoded.extend_from_slice(bytes);
+    };
+    push_bytes(&mut encoded, self.host.as_bytes());
+    encoded.extend_from_slice(&(self.headers.len() as u32).to_be_bytes());
+    for (header_name, header_value) in self.headers.iter() {
+      push_bytes(&mut encoded, header_name.as_str().as_bytes());
+      push_bytes(&mut encoded, header_value.as_bytes());
+    }
+    encoded.extend_from_slice(&self.body);
+    encoded
+  }
+
+  // Decode the cached response read from the shared storage
+  fn decode(encoded: &[u8]) -> Option<Self> {
+    let mut offset = 0;
+    let created_at = u64::from_be_bytes(take_encoded(encoded, &mut offset, 8)?.try_into().ok()?);
+    let max_age = Duration::from_millis(u64::from_be_bytes(
+      take_encoded(encoded, &mut offset, 8)?.try_into().ok()?,
+    ));
+    let status_code = StatusCode::from_u16(u16::from_be_bytes(
+      take_encoded(encoded, &mut offset, 2)?.try_into().ok()?,
+    ))
+    .ok()?;
+    let host = String::from_utf8(take_encoded_bytes(encoded, &mut offset)?.to_vec()).ok()?;
+    let header_count = u32::from_be_bytes(take_encoded(encoded, &mut offset, 4)?.try_into().ok()?);
+    let mut headers = HeaderMap::new();
+    for _ in 0..header_count {
+      let header_name =
+        header::HeaderName::from_bytes(take_encoded_bytes(encoded, &mut offset)?).ok()?;
+      let header_value = HeaderValue::from_bytes(take_encoded_bytes(encoded, &mut offset)?).ok()?;
+      headers.append(header_name, header_value);
+    }
+    let age = Duration::from_millis(unix_time_millis().saturating_sub(created_at));
+    Some(Self {
+      status_code,
+      headers,
+      body: encoded[offset..].to_vec(),
+      timestamp: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+      max_age,
+      host,
+    })
+  }
+}
+
+// Take the bytes of the specified length from the encoded cached response
+fn take_encoded<'a>(encoded: &'a [u8], offset: &mut usize, length: usize) -> Option<&'a [u8]> {
+  let bytes = encoded.get(*offset..*offset + length)?;
+  *offset += length;
+  Some(bytes)
+}
+
+// Take the bytes prefixed with their length from the encoded cached response
+fn take_encoded_bytes<'a>(encoded: &'a [u8], offset: &mut usize) -> Option<&'a [u8]> {
+  let length = u32::from_be_bytes(take_encoded(encoded, offset, 4)?.try_into().ok()?) as usize;
+  take_encoded(encoded, offset, length)
+}
+
+// Get the time the response is fresh for, from its "Cache-Control" header
+fn freshness_lifetime(cache_control: Option<&CacheControl>) -> Duration {
+  cache_control
+    .and_then(|cache_control| cache_control.s_max_age.or(cache_control.max_age))
+    .unwrap_or(Duration::from_secs(DEFAULT_MAX_AGE))
+}
+
+// The partial response of an interrupted cache fill, which is completed with a range request on the next cache miss
+struct PartialCacheEntry {
+  headers: HeaderMap,
+  body: Vec<u8>,
+  // The validator (an entity tag or a modification date) sent in the "If-Range" header, so that the origin server sends
+  // the complete response instead, if the response has changed
+  validator: HeaderValue,
+  timestamp: Instant,
+}
+
+// A cache fill, which stores the response in the cache once the response body is received
+struct CacheFill {
+  handle: Handle,
+  cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
+  partial_cache: Arc<RwLock<HashMap<String, PartialCacheEntry>>>,
+  cache_key: String,
+  status_code: StatusCode,
+  headers: HeaderMap,
+  cache_control: Option<CacheControl>,
+  host: String,
+  // The validator of the response, if the fill can be resumed after it's interrupted
+  resume_validator: Option<HeaderValue>,
+}
+
+impl CacheFill {
+  // Store the complete response in the cache, or keep the partial response, if the fill was interrupted and can be resumed
+  fn finish(self, body: Vec<u8>, complete: bool) {
+    let handle = self.handle.clone();
+    handle.spawn(async move {
+      if complete {
+        let cache_entry = CacheEntry {
+          status_code: self.status_code,
+          headers: self.headers,
+          body,
+          timestamp: Instant::now(),
+          max_age: freshness_lifetime(self.cache_control.as_ref()),
+          host: self.host,
+        };
+        record_cache_store(&cache_entry);
+
+        // The response is also stored in the shared storage, so that it's served by other servers or after the restart
+        if STORAGE.is_shared() {
+          STORAGE
+            .set(
+              &format!("{}{}", CACHE_KEY_PREFIX, self.cache_key),
+              &cache_entry.encode(),
+              Some(cache_entry.max_age),
+            )
+            .await
+            .unwrap_or_default();
+        }
+
+        self.partial_cache.write().await.remove(&self.cache_key);
+        let mut rwlock_write = self.cache.write().await;
+        rwlock_write.retain(|_, cached_entry| {
+          let is_fresh = cached_entry.is_fresh();
+          if !is_fresh {
+            record_cache_eviction(cached_entry, "expired");
+          }
+          is_fresh
+        });
+        if let Some(replaced_entry) = rwlock_write.insert(self.cache_key, cache_entry) {
+          record_cache_eviction(&replaced_entry, "replaced");
+        }
+      } else if let Some(validator) = self.resume_validator {
+        if body.is_empty() {
+          return;
+        }
+        METRICS.increment_counter(
+          "ferron_cache_interrupted_fills_total",
+          &[("host", self.host.as_str())],
+        );
+        let mut rwlock_write = self.partial_cache.write().await;
+        rwlock_write
+          .retain(|_, partial_entry| partial_entry.timestamp.elapsed() <= PARTIAL_FILL_LIFETIME);
+        rwlock_write.insert(
+          self.cache_key,
+          PartialCacheEntry {
+            headers: self.headers,
+            body,
+            validator,
+            timestamp: Instant::now(),
+          },
+        );
+      }
+    });
+  }
+}
+
+// A response body wrapper, which records the response body sent to the client for the cache fill
+struct CacheFillBody {
+  inner: BoxBody<Bytes, std::io::Error>,
+  buffer: Vec<u8>,
+  maximum_size: Option<u64>,
+  // The length from the "Content-Length" header. The body may not be polled to the end once this many bytes are sent.
+  expected_length: Option<u64>,
+  fill: Option<CacheFill>,
+}
+
+impl Body for CacheFillBody {
+  type Data = Bytes;
+  type Error = std::io::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    let frame = Pin::new(&mut this.inner).poll_frame(cx);
+    match &frame {
+      Poll::Ready(Some(Ok(frame))) => {
+        if let (Some(bytes), Some(_)) = (frame.data_ref(), &this.fill) {
+          this.buffer.extend_from_slice(bytes);
+          if this
+            .maximum_size
+            .is_some_and(|maximum_size| this.buffer.len() as u64 > maximum_size)
+          {
+            // The response is too large to be cached
+            this.fill = None;
+            this.buffer = Vec::new();
+          } else if this.expected_length == Some(this.buffer.len() as u64) {
+            if let Some(fill) = this.fill.take() {
+              fill.finish(std::mem::take(&mut this.buffer), true);
+            }
+          }
+        }
+      }
+      Poll::Ready(Some(Err(_))) => {
+        if let Some(fill) = this.fill.take() {
+          fill.finish(std::mem::take(&mut this.buffer), false);
+        }
+      }
+      Poll::Ready(None) => {
+        if let Some(fill) = this.fill.take() {
+          fill.finish(std::mem::take(&mut this.buffer), true);
+        }
+      }
+      Poll::Pending => (),
+    }
+    frame
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+impl Drop for CacheFillBody {
+  fn drop(&mut self) {
+    // The body is dropped before it's fully received, if the client disconnects
+    if let Some(fill) = self.fill.take() {
+      let complete = self.inner.is_end_stream();
+      fill.finish(std::mem::take(&mut self.buffer), complete);
+    }
+  }
+}
+
+// Continue the interrupted cache fill with the rest of the response, received from the origin server with a range request
+fn resume_cache_fill(
+  response: HyperResponse,
+  partial_entry: PartialCacheEntry,
+  host: &str,
+) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+  if response.status() != StatusCode::PARTIAL_CONTENT {
+    // The origin server sent the complete response instead (for example, because the response has changed)
+    return Ok(response);
+  }
+  let offset = partial_entry.body.len() as u64;
+  let complete_length = match response
+    .headers()
+    .get(header::CONTENT_RANGE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(parse_content_range)
+  {
+    Some((first, _, complete_length)) if first == offset => complete_length,
+    _ => Err(anyhow::anyhow!(
+      "The partial response doesn't continue the interrupted cache fill"
+    ))?,
+  };
+  METRICS.increment_counter("ferron_cache_resumed_fills_total", &[("host", host)]);
+
+  let mut headers = partial_entry.headers;
+  match complete_length {
+    Some(complete_length) => {
+      headers.insert(header::CONTENT_LENGTH, HeaderValue::from(complete_length));
+    }
+    None => {
+      headers.remove(header::CONTENT_LENGTH);
+    }
+  }
+  let partial_stream =
+    futures_util::stream::once(async move { Ok(Bytes::from(partial_entry.body)) });
+  let response_stream = response.into_body().into_data_stream();
+  let stream_body = StreamBody::new(partial_stream.chain(response_stream).map_ok(Frame::data));
+  let mut response = Response::new(BodyExt::boxed(stream_body));
+  *response.headers_mut() = headers;
+  Ok(response)
+}
+
+// Count a cache lookup, with the result being "hit", "miss", "stale" or "bypass"
+fn record_cache_request(host: &str, result: &str) {
+  METRICS.increment_counter(
+    "ferron_cache_requests_total",
+    &[("host", host), ("result", result)],
+  );
+}
+
+// Count a response stored in the cache, and update the cache utilization
+fn record_cache_store(entry: &CacheEntry) {
+  let labels = [("host", entry.host.as_str())];
+  METRICS.increment_counter("ferron_cache_stores_total", &labels);
+  METRICS.add_to_counter(
+    "ferron_cache_fill_bytes_total",
+    &labels,
+    entry.body.len() as u64,
+  );
+  METRICS.add_to_gauge("ferron_cache_entries", &labels, 1);
+  METRICS.add_to_gauge("ferron_cache_size_bytes", &labels, entry.body.len() as i64);
+}
+
+// Count a response removed from the cache, with the reason being "expired", "replaced" or "purged", and update the cache utilization
+fn record_cache_eviction(entry: &CacheEntry, reason: &str) {
+  METRICS.increment_counter(
+    "ferron_cache_evictions_total",
+    &[("host", entry.host.as_str()), ("reason", reason)],
+  );
+  let labels = [("host", entry.host.as_str())];
+  METRICS.add_to_gauge("ferron_cache_entries", &labels, -1);
+  METRICS.add_to_gauge(
+    "ferron_cache_size_bytes",
+    &labels,
+    -(entry.body.len() as i64),
+  );
+}
+
 struct CacheModule {
-  cache: Arc<
-    RwLock<
-      HashMap<
-        String,
-        (
-          StatusCode,
-          HeaderMap,
-          Vec<u8>,
-          Instant,
-          Option<CacheControl>,
-        ),
-      >,
-    >,
-  >,
+  cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
   vary_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+  partial_cache: Arc<RwLock<HashMap<String, PartialCacheEntry>>>,
+  purge_generation: Arc<AtomicU64>,
 }
 
 impl CacheModule {
-  #[allow(clippy::type_complexity)]
   fn new(
-    cache: Arc<
-      RwLock<
-        HashMap<
-          String,
-          (
-            StatusCode,
-            HeaderMap,
-            Vec<u8>,
-            Instant,
-            Option<CacheControl>,
-          ),
-        >,
-      >,
-    >,
+    cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
     vary_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    partial_cache: Arc<RwLock<HashMap<String, PartialCacheEntry>>>,
+    purge_generation: Arc<AtomicU64>,
   ) -> Self {
-    CacheModule { cache, vary_cache }
+    CacheModule {
+      cache,
+      vary_cache,
+      partial_cache,
+      purge_generation,
+    }
   }
 }
 
@@ -79,57 +409,407 @@ impl ServerModule for CacheModule {
     Box::new(CacheModuleHandlers {
       cache: self.cache.clone(),
       vary_cache: self.vary_cache.clone(),
+      partial_cache: self.partial_cache.clone(),
+      purge_generation: self.purge_generation.clone(),
       cache_vary_headers_configured: Vec::new(),
       cache_ignore_headers_configured: Vec::new(),
       maximum_cached_response_size: None,
+      resume_fills: false,
+      resumed_fill: None,
       cache_key: None,
       request_headers: HeaderMap::new(),
       has_authorization: false,
       cached: false,
       no_store: false,
+      host: String::new(),
+      esi_subrequest_handler: None,
+      request_path: String::new(),
       handle,
     })
   }
 }
 
-#[allow(clippy::type_complexity)]
 struct CacheModuleHandlers {
   handle: Handle,
-  cache: Arc<
-    RwLock<
-      HashMap<
-        String,
-        (
-          StatusCode,
-          HeaderMap,
-          Vec<u8>,
-          Instant,
-          Option<CacheControl>,
-        ),
-      >,
-    >,
-  >,
+  cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
   vary_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+  partial_cache: Arc<RwLock<HashMap<String, PartialCacheEntry>>>,
+  // The cache purge generation the cache was last purged for
+  purge_generation: Arc<AtomicU64>,
   cache_vary_headers_configured: Vec<String>,
   cache_ignore_headers_configured: Vec<String>,
   maximum_cached_response_size: Option<u64>,
+  resume_fills: bool,
+  // The partial response of the interrupted cache fill resumed with the request
+  resumed_fill: Option<PartialCacheEntry>,
   cache_key: Option<String>,
   request_headers: HeaderMap<HeaderValue>,
   has_authorization: bool,
   cached: bool,
   no_store: bool,
+  host: String,
+  // The handler of the subrequests for the fragments included with ESI, if ESI processing is enabled
+  esi_subrequest_handler: Option<SubrequestHandler>,
+  request_path: String,
+}
+
+impl CacheModuleHandlers {
+  // Read the varying request headers of the response from the shared storage, if the response isn't cached in memory
+  async fn load_shared_vary(&self, cache_key: &str) -> Option<Vec<String>> {
+    if !STORAGE.is_shared() {
+      return None;
+    }
+    let stored_vary = STORAGE
+      .get(&format!("{}{}", VARY_KEY_PREFIX, cache_key))
+      .await
+      .ok()??;
+    let processed_vary = String::from_utf8_lossy(&stored_vary)
+      .split('\n')
+      .filter(|header_name| !header_name.is_empty())
+      .map(|header_name| header_name.to_string())
+      .collect::<Vec<_>>();
+    self
+      .vary_cache
+      .write()
+      .await
+      .insert(cache_key.to_string(), processed_vary.clone());
+    Some(processed_vary)
+  }
+
+  // Read the cached response from the shared storage into the memory, if it isn't cached in memory
+  async fn load_shared_entry(&self, cache_key_with_vary: &str) {
+    if !STORAGE.is_shared() {
+      return;
+    }
+    let cache_entry = match STORAGE
+      .get(&format!("{}{}", CACHE_KEY_PREFIX, cache_key_with_vary))
+      .await
+    {
+      Ok(Some(stored_entry)) => match CacheEntry::decode(&stored_entry) {
+        Some(cache_entry) if cache_entry.is_fresh() => cache_entry,
+        _ => return,
+      },
+      _ => return,
+    };
+    record_cache_store(&cache_entry);
+    if let Some(replaced_entry) = self
+      .cache
+      .write()
+      .await
+      .insert(cache_key_with_vary.to_string(), cache_entry)
+    {
+      record_cache_eviction(&replaced_entry, "replaced");
+    }
+  }
+
+  // Store the response in the cache, if it's cacheable, and mark the response with the cache status
+  async fn cache_response(
+    &mut self,
+    mut response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    if self.no_store {
+      response
+        .headers_mut()
+        .insert(CACHE_HEADER_NAME, HeaderValue::from_str("BYPASS")?);
+      Ok(response)
+    } else if self.cached {
+      response
+        .headers_mut()
+        .insert(CACHE_HEADER_NAME, HeaderValue::from_str("HIT")?);
+      Ok(response)
+    } else if let Some(cache_key) = &self.cache_key {
+      let response = match self.resumed_fill.take() {
+        Some(partial_entry) => resume_cache_fill(response, partial_entry, &self.host)?,
+        None => response,
+      };
+      let (mut response_parts, mut response_body) = response.into_parts();
+      let response_cache_control = match response_parts.headers.get(header::CACHE_CONTROL) {
+        Some(value) => CacheControl::from_value(&String::from_utf8_lossy(value.as_bytes())),
+        None => None,
+      };
+
+      let should_cache_response = match &response_cache_control {
+        Some(response_cache_control) => {
+          let is_private = response_cache_control.cachability == Some(Cachability::Private);
+          let is_public = response_cache_control.cachability == Some(Cachability::Public);
+
+          !response_cache_control.no_store
+            && !is_private
+            && (is_public
+              || (!self.has_authorization
+                && (response_cache_control.max_age.is_some()
+                  || response_cache_control.s_max_age.is_some())))
+        }
+        None => false,
+      };
+
+      if should_cache_response {
+        let mut response_vary = match response_parts.headers.get(header::VARY) {
+          Some(value) => String::from_utf8_lossy(value.as_bytes())
+            .split(",")
+            .map(|s| s.trim().to_owned())
+            .collect(),
+          None => Vec::new(),
+        };
+
+        let mut processed_vary_orig = self.cache_vary_headers_configured.clone();
+        processed_vary_orig.append(&mut response_vary);
+
+        let processed_vary = processed_vary_orig
+          .iter()
+          .unique()
+          .map(|s| s.to_owned())
+          .collect::<Vec<String>>();
+
+        if !processed_vary.contains(&"*".to_string()) {
+          let cache_key_with_vary = format!(
+            "{}\n{}",
+            &cache_key,
+            processed_vary
+              .iter()
+              .map(|header_name| {
+                match self.request_headers.get(header_name) {
+                  Some(header_value) => format!(
+                    "{}: {}",
+                    header_name,
+                    String::from_utf8_lossy(header_value.as_bytes()).into_owned()
+                  ),
+                  None => "".to_string(),
+                }
+              })
+              .collect::<Vec<String>>()
+              .join("\n")
+          );
+
+          if STORAGE.is_shared() {
+            STORAGE
+              .set(
+                &format!("{}{}", VARY_KEY_PREFIX, cache_key),
+                processed_vary.join("\n").as_bytes(),
+                Some(freshness_lifetime(response_cache_control.as_ref())),
+              )
+              .await
+              .unwrap_or_default();
+          }
+          let mut rwlock_write = self.vary_cache.write().await;
+          rwlock_write.insert(cache_key.clone(), processed_vary);
+          drop(rwlock_write);
+
+          let mut written_headers = response_parts.headers.clone();
+          for header in self.cache_ignore_headers_configured.iter() {
+            while written_headers.remove(header).is_some() {}
+          }
+
+          // The interrupted fill can be resumed with a range request, if the response isn't content-encoded and has a strong validator
+          let resume_validator = match self.resume_fills
+            && response_parts.status == StatusCode::OK
+            && !response_parts
+              .headers
+              .contains_key(header::CONTENT_ENCODING)
+          {
+            true => response_parts
+              .headers
+              .get(header::ETAG)
+              .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+              .or_else(|| response_parts.headers.get(header::LAST_MODIFIED))
+              .cloned(),
+            false => None,
+          };
+
+          // The response is stored in the cache once its body is sent to the client
+          response_body = BodyExt::boxed(CacheFillBody {
+            inner: response_body,
+            buffer: Vec::new(),
+            maximum_size: self.maximum_cached_response_size,
+            expected_length: response_parts
+              .headers
+              .get(header::CONTENT_LENGTH)
+              .and_then(|value| value.to_str().ok())
+              .and_then(|value| value.parse::<u64>().ok()),
+            fill: Some(CacheFill {
+              handle: self.handle.clone(),
+              cache: self.cache.clone(),
+              partial_cache: self.partial_cache.clone(),
+              cache_key: cache_key_with_vary,
+              status_code: response_parts.status,
+              headers: written_headers,
+              cache_control: response_cache_control,
+              host: self.host.clone(),
+              resume_validator,
+            }),
+          });
+        }
+      }
+
+      response_parts
+        .headers
+        .insert(CACHE_HEADER_NAME, HeaderValue::from_str("MISS")?);
+      let response = Response::from_parts(response_parts, response_body);
+      Ok(response)
+    } else {
+      Ok(response)
+    }
+  }
+
+  // Assemble the response with ESI markup, replacing the "esi:include" tags with the fragments fetched through subrequests
+  async fn assemble_esi_response(
+    &self,
+    response: HyperResponse,
+    subrequest_handler: &SubrequestHandler,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    let (mut response_parts, response_body) = response.into_parts();
+    let document = response_body.collect().await?.to_bytes();
+    let request_headers = &self.request_headers;
+    let request_path = self.request_path.as_str();
+
+    // The fragments are fetched concurrently
+    let assembled_segments = join_all(parse_esi(&document).into_iter().map(|segment| async move {
+      match segment {
+        EsiSegment::Text(text) => Ok(text),
+        EsiSegment::Include {
+          src,
+          alt,
+          continue_on_error,
+        } => {
+          let mut fragment =
+            fetch_esi_fragment(subrequest_handler, request_headers, request_path, &src).await;
+          if let (Err(_), Some(alt)) = (&fragment, alt) {
+            fragment =
+              fetch_esi_fragment(subrequest_handler, request_headers, request_path, &alt).await;
+          }
+          match fragment {
+            Err(_) if continue_on_error => Ok(Bytes::new()),
+            fragment => fragment,
+          }
+        }
+      }
+    }))
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+    let assembled_document = assembled_segments.concat();
+
+    // The assembled response differs from the cached one, so the validators of the cached response don't apply to it
+    response_parts.headers.remove(SURROGATE_CONTROL_HEADER_NAME);
+    response_parts.headers.remove(header::ETAG);
+    response_parts.headers.remove(header::LAST_MODIFIED);
+    response_parts.headers.remove(header::ACCEPT_RANGES);
+    response_parts.headers.insert(
+      header::CONTENT_LENGTH,
+      HeaderValue::from(assembled_document.len()),
+    );
+
+    Ok(Response::from_parts(
+      response_parts,
+      Full::new(Bytes::from(assembled_document))
+        .map_err(|e| match e {})
+        .boxed(),
+    ))
+  }
+}
+
+// Check if the response is marked for ESI processing with the "Surrogate-Control: content=\"ESI/1.0\"" header.
+// The compressed responses aren't processed.
+fn is_esi_response(response: &HyperResponse) -> bool {
+  !response.headers().contains_key(header::CONTENT_ENCODING)
+    && response
+      .headers()
+      .get_all(SURROGATE_CONTROL_HEADER_NAME)
+      .iter()
+      .any(|value| {
+        String::from_utf8_lossy(value.as_bytes())
+          .to_ascii_lowercase()
+          .contains("esi/1.0")
+      })
+}
+
+// Fetch the fragment included with ESI through a subrequest, which carries the headers of the original request
+async fn fetch_esi_fragment(
+  subrequest_handler: &SubrequestHandler,
+  request_headers: &HeaderMap,
+  request_path: &str,
+  src: &str,
+) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+  let fragment_url = match resolve_esi_url(src, request_path) {
+    Some(fragment_url) => fragment_url,
+    None => Err(anyhow::anyhow!("Invalid ESI fragment URL: {}", src))?,
+  };
+
+  let mut fragment_request = Request::builder()
+    .method(Method::GET)
+    .uri(
+      fragment_url
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str()),
+    )
+    .body(Empty::new().map_err(|e| match e {}).boxed())?;
+  let fragment_request_headers = fragment_request.headers_mut();
+  *fragment_request_headers = request_headers.clone();
+  // The fragment is included as-is, so it can't be a partial, compressed or "304 Not Modified" response
+  for header_name in [
+    header::IF_MATCH,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    header::IF_UNMODIFIED_SINCE,
+    header::IF_RANGE,
+    header::RANGE,
+    header::ACCEPT_ENCODING,
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+  ] {
+    fragment_request_headers.remove(header_name);
+  }
+  if let Some(authority) = fragment_url.authority() {
+    fragment_request_headers.insert(header::HOST, HeaderValue::from_str(authority.as_str())?);
+  }
+
+  let fragment_response = subrequest_handler.send(fragment_request).await?;
+  if !fragment_response.status().is_success() {
+    Err(anyhow::anyhow!(
+      "Couldn't fetch the ESI fragment \"{}\" (status code {})",
+      src,
+      fragment_response.status()
+    ))?
+  }
+  Ok(fragment_response.into_body().collect().await?.to_bytes())
 }
 
 #[async_trait]
 impl ServerModuleHandlers for CacheModuleHandlers {
   async fn request_handler(
     &mut self,
-    request: RequestData,
+    mut request: RequestData,
     config: &ServerConfigRoot,
     socket_data: &SocketData,
     _error_logger: &ErrorLogger,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
+      // Purge the cache, if it was requested through the admin API since the last request
+      let purge_generation = cache_purge_generation();
+      if self
+        .purge_generation
+        .swap(purge_generation, Ordering::Relaxed)
+        != purge_generation
+      {
+        let mut rwlock_write = self.cache.write().await;
+        for (_, purged_entry) in rwlock_write.drain() {
+          record_cache_eviction(&purged_entry, "purged");
+        }
+        drop(rwlock_write);
+        self.vary_cache.write().await.clear();
+        self.partial_cache.write().await.clear();
+        if STORAGE.is_shared() {
+          for prefix in [CACHE_KEY_PREFIX, VARY_KEY_PREFIX] {
+            if let Ok(entries) = STORAGE.scan(prefix).await {
+              for (key, _) in entries {
+                STORAGE.delete(&key).await.unwrap_or_default();
+              }
+            }
+          }
+        }
+      }
+
       self.cache_vary_headers_configured = match config.get("cacheVaryHeaders").as_vec() {
         Some(vector) => {
           let mut new_vector = Vec::new();
@@ -158,8 +838,22 @@ impl ServerModuleHandlers for CacheModuleHandlers {
         .get("maximumCachedResponseSize")
         .as_i64()
         .map(|f| f as u64);
+      self.resume_fills = config.get("cacheResumeFills").as_bool() == Some(true);
+      self.host = config.get("domain").as_str().unwrap_or("*").to_string();
 
       let hyper_request = request.get_hyper_request();
+
+      // The cached responses can include fragments with ESI (Edge Side Includes),
+      // which are fetched for each request through subrequests
+      self.esi_subrequest_handler = match config.get("enableEsi").as_bool() == Some(true)
+        && hyper_request.method() == Method::GET
+      {
+        true => request.get_subrequest_handler().cloned(),
+        false => None,
+      };
+      self.request_path = hyper_request.uri().path().to_string();
+      self.request_headers = hyper_request.headers().clone();
+
       let cache_key = format!(
         "{} {}{}{}{}",
         hyper_request.method().as_str(),
@@ -204,12 +898,16 @@ impl ServerModuleHandlers for CacheModuleHandlers {
 
       if no_store {
         self.no_store = true;
+        record_cache_request(&self.host, "bypass");
         return Ok(ResponseData::builder(request).build());
       }
 
+      let mut cache_result = "miss";
       if !no_cache {
-        let rwlock_read = self.vary_cache.read().await;
-        let processed_vary = rwlock_read.get(&cache_key);
+        let processed_vary = match self.vary_cache.read().await.get(&cache_key).cloned() {
+          Some(processed_vary) => Some(processed_vary),
+          None => self.load_shared_vary(&cache_key).await,
+        };
         if let Some(processed_vary) = processed_vary {
           let cache_key_with_vary = format!(
             "{}\n{}",
@@ -230,36 +928,23 @@ impl ServerModuleHandlers for CacheModuleHandlers {
               .join("\n")
           );
 
-          drop(rwlock_read);
+          if !self.cache.read().await.contains_key(&cache_key_with_vary) {
+            self.load_shared_entry(&cache_key_with_vary).await;
+          }
 
           let rwlock_read = self.cache.read().await;
           let cached_entry_option = rwlock_read.get(&cache_key_with_vary);
 
-          if let Some((status_code, headers, body, timestamp, response_cache_control)) =
-            cached_entry_option
-          {
-            let max_age = match response_cache_control {
-              Some(response_cache_control) => match response_cache_control.s_max_age {
-                Some(s_max_age) => Some(s_max_age),
-                None => response_cache_control.max_age,
-              },
-              None => None,
-            };
-
-            let mut cached = true;
-
-            if timestamp.elapsed() > max_age.unwrap_or(Duration::from_secs(DEFAULT_MAX_AGE)) {
-              cached = false;
-            }
-
-            if cached {
+          if let Some(cached_entry) = cached_entry_option {
+            if cached_entry.is_fresh() {
               self.cached = true;
-              let mut hyper_response_builder = Response::builder().status(status_code);
-              for (header_name, header_value) in headers.iter() {
+              record_cache_request(&self.host, "hit");
+              let mut hyper_response_builder = Response::builder().status(cached_entry.status_code);
+              for (header_name, header_value) in cached_entry.headers.iter() {
                 hyper_response_builder = hyper_response_builder.header(header_name, header_value);
               }
               let hyper_response = hyper_response_builder.body(
-                Full::new(Bytes::from(body.clone()))
+                Full::new(Bytes::from(cached_entry.body.clone()))
                   .map_err(|e| match e {})
                   .boxed(),
               )?;
@@ -269,18 +954,40 @@ impl ServerModuleHandlers for CacheModuleHandlers {
                   .build(),
               );
             } else {
-              drop(rwlock_read);
+              cache_result = "stale";
             }
           }
-        } else {
           drop(rwlock_read);
+
+          // Resume the interrupted cache fill, unless the client requests a range itself
+          if self.resume_fills
+            && hyper_request.method() == Method::GET
+            && !hyper_request.headers().contains_key(header::RANGE)
+          {
+            self.resumed_fill = self
+              .partial_cache
+              .write()
+              .await
+              .remove(&cache_key_with_vary)
+              .filter(|partial_entry| partial_entry.timestamp.elapsed() <= PARTIAL_FILL_LIFETIME);
+          }
         }
       }
 
-      self.request_headers = hyper_request.headers().clone();
+      record_cache_request(&self.host, cache_result);
       self.cache_key = Some(cache_key);
       self.has_authorization = hyper_request.headers().contains_key(header::AUTHORIZATION);
 
+      // Request only the rest of the response received before the fill was interrupted
+      if let Some(partial_entry) = &self.resumed_fill {
+        let request_headers = request.get_mut_hyper_request().headers_mut();
+        request_headers.insert(
+          header::RANGE,
+          HeaderValue::from_str(&format!("bytes={}-", partial_entry.body.len()))?,
+        );
+        request_headers.insert(header::IF_RANGE, partial_entry.validator.clone());
+      }
+
       Ok(ResponseData::builder(request).build())
     })
     .await
@@ -298,163 +1005,17 @@ impl ServerModuleHandlers for CacheModuleHandlers {
 
   async fn response_modifying_handler(
     &mut self,
-    mut response: HyperResponse,
+    response: HyperResponse,
   ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
-      if self.no_store {
-        response
-          .headers_mut()
-          .insert(CACHE_HEADER_NAME, HeaderValue::from_str("BYPASS")?);
-        Ok(response)
-      } else if self.cached {
-        response
-          .headers_mut()
-          .insert(CACHE_HEADER_NAME, HeaderValue::from_str("HIT")?);
-        Ok(response)
-      } else if let Some(cache_key) = &self.cache_key {
-        let (mut response_parts, mut response_body) = response.into_parts();
-        let response_cache_control = match response_parts.headers.get(header::CACHE_CONTROL) {
-          Some(value) => CacheControl::from_value(&String::from_utf8_lossy(value.as_bytes())),
-          None => None,
-        };
-
-        let should_cache_response = match &response_cache_control {
-          Some(response_cache_control) => {
-            let is_private = response_cache_control.cachability == Some(Cachability::Private);
-            let is_public = response_cache_control.cachability == Some(Cachability::Public);
-
-            !response_cache_control.no_store
-              && !is_private
-              && (is_public
-                || (!self.has_authorization
-                  && (response_cache_control.max_age.is_some()
-                    || response_cache_control.s_max_age.is_some())))
-          }
-          None => false,
-        };
-
-        if should_cache_response {
-          let mut response_body_buffer = Vec::new();
-          let mut maximum_cached_response_size_exceeded = false;
-
-          while let Some(frame) = response_body.frame().await {
-            let frame_unwrapped = frame?;
-            if frame_unwrapped.is_data() {
-              if let Some(bytes) = frame_unwrapped.data_ref() {
-                response_body_buffer.extend_from_slice(bytes);
-                if let Some(maximum_cached_response_size) = self.maximum_cached_response_size {
-                  if response_body_buffer.len() > maximum_cached_response_size.try_into()? {
-                    maximum_cached_response_size_exceeded = true;
-                    break;
-                  }
-                }
-              }
-            }
-          }
-
-          if maximum_cached_response_size_exceeded {
-            let cached_stream =
-              futures_util::stream::once(async move { Ok(Bytes::from(response_body_buffer)) });
-            let response_stream = response_body.into_data_stream();
-            let chained_stream = cached_stream.chain(response_stream);
-            let stream_body = StreamBody::new(chained_stream.map_ok(Frame::data));
-            let response_body = BodyExt::boxed(stream_body);
-            response_parts
-              .headers
-              .insert(CACHE_HEADER_NAME, HeaderValue::from_str("MISS")?);
-            let response = Response::from_parts(response_parts, response_body);
-            Ok(response)
-          } else {
-            let mut response_vary = match response_parts.headers.get(header::VARY) {
-              Some(value) => String::from_utf8_lossy(value.as_bytes())
-                .split(",")
-                .map(|s| s.trim().to_owned())
-                .collect(),
-              None => Vec::new(),
-            };
-
-            let mut processed_vary_orig = self.cache_vary_headers_configured.clone();
-            processed_vary_orig.append(&mut response_vary);
-
-            let processed_vary = processed_vary_orig
-              .iter()
-              .unique()
-              .map(|s| s.to_owned())
-              .collect::<Vec<String>>();
-
-            if !processed_vary.contains(&"*".to_string()) {
-              let cache_key_with_vary = format!(
-                "{}\n{}",
-                &cache_key,
-                processed_vary
-                  .iter()
-                  .map(|header_name| {
-                    match self.request_headers.get(header_name) {
-                      Some(header_value) => format!(
-                        "{}: {}",
-                        header_name,
-                        String::from_utf8_lossy(header_value.as_bytes()).into_owned()
-                      ),
-                      None => "".to_string(),
-                    }
-                  })
-                  .collect::<Vec<String>>()
-                  .join("\n")
-              );
-
-              let mut rwlock_write = self.vary_cache.write().await;
-              rwlock_write.insert(cache_key.clone(), processed_vary);
-              drop(rwlock_write);
-
-              let mut written_headers = response_parts.headers.clone();
-              for header in self.cache_ignore_headers_configured.iter() {
-                while written_headers.remove(header).is_some() {}
-              }
-
-              let mut rwlock_write = self.cache.write().await;
-              rwlock_write.retain(|_, (_, _, _, timestamp, response_cache_control)| {
-                let max_age = match response_cache_control {
-                  Some(response_cache_control) => match response_cache_control.s_max_age {
-                    Some(s_max_age) => Some(s_max_age),
-                    None => response_cache_control.max_age,
-                  },
-                  None => None,
-                };
-
-                timestamp.elapsed() <= max_age.unwrap_or(Duration::from_secs(DEFAULT_MAX_AGE))
-              });
-              rwlock_write.insert(
-                cache_key_with_vary,
-                (
-                  response_parts.status,
-                  written_headers,
-                  response_body_buffer.clone(),
-                  Instant::now(),
-                  response_cache_control,
-                ),
-              );
-              drop(rwlock_write);
-            }
-
-            let cached_stream =
-              futures_util::stream::once(async move { Ok(Bytes::from(response_body_buffer)) });
-            let stream_body = StreamBody::new(cached_stream.map_ok(Frame::data));
-            let response_body = BodyExt::boxed(stream_body);
-            response_parts
-              .headers
-              .insert(CACHE_HEADER_NAME, HeaderValue::from_str("MISS")?);
-            let response = Response::from_parts(response_parts, response_body);
-            Ok(response)
-          }
-        } else {
-          response_parts
-            .headers
-            .insert(CACHE_HEADER_NAME, HeaderValue::from_str("MISS")?);
-          let response = Response::from_parts(response_parts, response_body);
-          Ok(response)
+      let response = self.cache_response(response).await?;
+      match self.esi_subrequest_handler.clone() {
+        Some(subrequest_handler) if is_esi_response(&response) => {
+          self
+            .assemble_esi_response(response, &subrequest_handler)
+            .await
         }
-      } else {
-        Ok(response)
+        _ => Ok(response),
       }
     })
     .await
@@ -2,33 +2,36 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::process::Stdio;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use ferron_common::{
-  ErrorLogger, HyperRequest, HyperResponse, RequestData, ResponseData, ServerConfig,
-  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use futures_util::TryStreamExt;
 use hashlink::LinkedHashMap;
 use http_body_util::{BodyExt, StreamBody};
 use httparse::EMPTY_HEADER;
-use hyper::body::Frame;
+use hyper::body::{Body, Frame};
 use hyper::{header, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use tokio::fs;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::ferron_res::server_software::SERVER_SOFTWARE;
+use crate::ferron_util::blocking_budget::run_budgeted;
 use crate::ferron_util::cgi_response::CgiResponse;
 use crate::ferron_util::copy_move::Copier;
+use crate::ferron_util::temp_files::{buffer_body, DEFAULT_BODY_MEMORY_LIMIT};
 use crate::ferron_util::ttl_cache::TtlCache;
 
 pub fn server_module_init(
@@ -150,7 +153,7 @@ impl ServerModuleHandlers for CgiModuleHandlers {
             let mut execute_pathbuf: Option<PathBuf> = None;
             let mut execute_path_info: Option<String> = None;
 
-            match fs::metadata(&joined_pathbuf).await {
+            match run_budgeted("cgi", fs::metadata(&joined_pathbuf)).await {
               Ok(metadata) => {
                 if metadata.is_file() {
                   let mut request_path_normalized = match cfg!(windows) {
@@ -178,7 +181,7 @@ impl ServerModuleHandlers for CgiModuleHandlers {
                   let indexes = vec!["index.php", "index.cgi"];
                   for index in indexes {
                     let temp_joined_pathbuf = joined_pathbuf.join(index);
-                    match fs::metadata(&temp_joined_pathbuf).await {
+                    match run_budgeted("cgi", fs::metadata(&temp_joined_pathbuf)).await {
                       Ok(temp_metadata) => {
                         if temp_metadata.is_file() {
                           let request_path_normalized = match cfg!(windows) {
@@ -216,7 +219,7 @@ impl ServerModuleHandlers for CgiModuleHandlers {
                     if !temp_pathbuf.pop() {
                       break;
                     }
-                    match fs::metadata(&temp_pathbuf).await {
+                    match run_budgeted("cgi", fs::metadata(&temp_pathbuf)).await {
                       Ok(metadata) => {
                         if metadata.is_file() {
                           let temp_path = temp_pathbuf.as_path();
@@ -528,9 +531,13 @@ async fn execute_cgi_with_environment_variables(
     );
   }
 
+  let mut content_length_set = false;
   for (header_name, header_value) in hyper_request.headers().iter() {
     let env_header_name = match *header_name {
-      header::CONTENT_LENGTH => "CONTENT_LENGTH".to_string(),
+      header::CONTENT_LENGTH => {
+        content_length_set = true;
+        "CONTENT_LENGTH".to_string()
+      }
       header::CONTENT_TYPE => "CONTENT_TYPE".to_string(),
       _ => {
         let mut result = String::new();
@@ -555,9 +562,27 @@ async fn execute_cgi_with_environment_variables(
   }
 
   let (hyper_request, _) = request.into_parts();
+  let (_, body) = hyper_request.into_parts();
+
+  // The CGI program needs the length of the request body before reading it, so the request body
+  // without the "Content-Length" header (for example, a chunked one) is buffered
+  let body_reader: Pin<Box<dyn AsyncRead + Send>> = match content_length_set || body.is_end_stream()
+  {
+    true => Box::pin(StreamReader::new(
+      body.into_data_stream().map_err(std::io::Error::other),
+    )),
+    false => {
+      let buffered_body = buffer_body(body, DEFAULT_BODY_MEMORY_LIMIT, "cgi").await?;
+      environment_variables.insert(
+        "CONTENT_LENGTH".to_string(),
+        buffered_body.size().to_string(),
+      );
+      buffered_body.into_reader()
+    }
+  };
 
   execute_cgi(
-    hyper_request,
+    body_reader,
     error_logger,
     execute_pathbuf,
     cgi_interpreters,
@@ -567,14 +592,12 @@ async fn execute_cgi_with_environment_variables(
 }
 
 async fn execute_cgi(
-  hyper_request: HyperRequest,
+  cgi_stdin_reader: Pin<Box<dyn AsyncRead + Send>>,
   error_logger: &ErrorLogger,
   execute_pathbuf: PathBuf,
   cgi_interpreters: HashMap<String, Vec<String>>,
   environment_variables: LinkedHashMap<String, String>,
 ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
-  let (_, body) = hyper_request.into_parts();
-
   let executable_params = match get_executable(&execute_pathbuf).await {
     Ok(params) => params,
     Err(err) => {
@@ -615,12 +638,6 @@ async fn execute_cgi(
 
   let mut child = command.spawn()?;
 
-  let cgi_stdin_reader = StreamReader::new(
-    body
-      .into_data_stream()
-      .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
-  );
-
   let stdin = match child.stdin.take() {
     Some(stdin) => stdin,
     None => Err(anyhow::anyhow!(
@@ -683,10 +700,8 @@ async fn execute_cgi(
       break;
     }
     match &header.name.to_lowercase() as &str {
-      "location" => {
-        if !(300..=399).contains(&status_code) {
-          status_code = 302;
-        }
+      "location" if !(300..=399).contains(&status_code) => {
+        status_code = 302;
       }
       "status" => {
         let header_value_cow = String::from_utf8_lossy(header.value);
@@ -776,7 +791,7 @@ async fn get_executable(
 ) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
   use std::os::unix::fs::PermissionsExt;
 
-  let metadata = fs::metadata(&execute_pathbuf).await?;
+  let metadata = run_budgeted("cgi", fs::metadata(&execute_pathbuf)).await?;
   let permissions = metadata.permissions();
   let is_executable = permissions.mode() & 0o111 != 0;
 
@@ -796,7 +811,7 @@ async fn get_executable(
   use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
 
   let mut magic_signature_buffer = [0u8; 2];
-  let mut open_file = fs::File::open(&execute_pathbuf).await?;
+  let mut open_file = run_budgeted("cgi", fs::File::open(&execute_pathbuf)).await?;
   if open_file
     .read_exact(&mut magic_signature_buffer)
     .await
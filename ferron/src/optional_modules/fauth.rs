@@ -23,11 +23,14 @@ use rustls::pki_types::ServerName;
 use rustls::RootCertStore;
 use rustls_native_certs::load_native_certs;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 use tokio_rustls::TlsConnector;
 
+use crate::ferron_util::dns_resolver::connect_tcp;
+use crate::ferron_util::hop_by_hop::strip_hop_by_hop_headers;
+use crate::ferron_util::ssrf_guard::SsrfGuard;
+
 const DEFAULT_CONCURRENT_CONNECTIONS_PER_HOST: u32 = 32;
 
 pub fn server_module_init(
@@ -197,6 +200,9 @@ impl ServerModuleHandlers for ForwardedAuthenticationModuleHandlers {
           }
         }
 
+        // The hop-by-hop headers apply only to the client's connection
+        strip_hop_by_hop_headers(&mut auth_hyper_request_parts.headers, false);
+
         // Connection header to enable HTTP/1.1 keep-alive
         auth_hyper_request_parts
           .headers
@@ -283,7 +289,15 @@ impl ServerModuleHandlers for ForwardedAuthenticationModuleHandlers {
           drop(rwlock_read);
         }
 
-        let stream = match TcpStream::connect(&addr).await {
+        // The address of the forwarded authentication server is validated before connecting to it
+        let stream_result = match SsrfGuard::from_config(config) {
+          Some(ssrf_guard) => match ssrf_guard.resolve(host, port).await {
+            Ok(auth_server_address) => connect_tcp(&auth_server_address.to_string()).await,
+            Err(err) => Err(err),
+          },
+          None => connect_tcp(&addr).await,
+        };
+        let stream = match stream_result {
           Ok(stream) => stream,
           Err(err) => {
             match err.kind() {
@@ -499,7 +513,7 @@ async fn http_forwarded_auth(
         } else {
           response = ResponseData::builder_without_request()
           .response(proxy_response.map(|b| {
-            b.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            b.map_err(|e| std::io::Error::other(e.to_string()))
               .boxed()
           }))
           .parallel_fn(async move {
@@ -568,10 +582,7 @@ async fn http_forwarded_auth_kept_alive(
     ResponseData::builder(original_request).build()
   } else {
     ResponseData::builder_without_request()
-      .response(proxy_response.map(|b| {
-        b.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-          .boxed()
-      }))
+      .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
       .build()
   };
 
@@ -3,13 +3,14 @@
 use std::env;
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use ferron_common::{
-  ErrorLogger, HyperRequest, HyperResponse, RequestData, ResponseData, ServerConfig,
-  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use futures_util::future::Either;
@@ -22,7 +23,6 @@ use hyper::{header, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use tokio::fs;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 use tokio_util::codec::{FramedRead, FramedWrite};
@@ -31,12 +31,14 @@ use tokio_util::io::{ReaderStream, SinkWriter, StreamReader};
 use crate::ferron_res::server_software::SERVER_SOFTWARE;
 use crate::ferron_util::cgi_response::CgiResponse;
 use crate::ferron_util::copy_move::Copier;
+use crate::ferron_util::dns_resolver;
 use crate::ferron_util::fcgi_decoder::{FcgiDecodedData, FcgiDecoder};
 use crate::ferron_util::fcgi_encoder::FcgiEncoder;
 use crate::ferron_util::fcgi_name_value_pair::construct_fastcgi_name_value_pair;
 use crate::ferron_util::fcgi_record::construct_fastcgi_record;
 use crate::ferron_util::read_to_end_move::ReadToEndFuture;
 use crate::ferron_util::split_stream_by_map::SplitStreamByMapExt;
+use crate::ferron_util::temp_files::{buffer_body, DEFAULT_BODY_MEMORY_LIMIT};
 use crate::ferron_util::ttl_cache::TtlCache;
 
 pub fn server_module_init(
@@ -580,29 +582,34 @@ async fn execute_fastcgi_with_environment_variables(
     );
   }
 
-  if !content_length_set {
-    environment_variables.insert("CONTENT_LENGTH".to_string(), "0".to_string());
-  }
-
   let (hyper_request, _) = request.into_parts();
+  let (_, body) = hyper_request.into_parts();
 
-  execute_fastcgi(
-    hyper_request,
-    error_logger,
-    fastcgi_to,
-    environment_variables,
-  )
-  .await
+  // The FastCGI server needs the length of the request body before the body is sent, so the request body
+  // without the "Content-Length" header (for example, a chunked one) is buffered
+  let body_reader: Pin<Box<dyn AsyncRead + Send>> = match content_length_set {
+    true => Box::pin(StreamReader::new(
+      body.into_data_stream().map_err(std::io::Error::other),
+    )),
+    false => {
+      let buffered_body = buffer_body(body, DEFAULT_BODY_MEMORY_LIMIT, "fcgi").await?;
+      environment_variables.insert(
+        "CONTENT_LENGTH".to_string(),
+        buffered_body.size().to_string(),
+      );
+      buffered_body.into_reader()
+    }
+  };
+
+  execute_fastcgi(body_reader, error_logger, fastcgi_to, environment_variables).await
 }
 
 async fn execute_fastcgi(
-  hyper_request: HyperRequest,
+  cgi_stdin_reader: Pin<Box<dyn AsyncRead + Send>>,
   error_logger: &ErrorLogger,
   fastcgi_to: &str,
   mut environment_variables: LinkedHashMap<String, String>,
 ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
-  let (_, body) = hyper_request.into_parts();
-
   // Insert other environment variables
   for (key, value) in env::vars_os() {
     let key_string = key.to_string_lossy().to_string();
@@ -710,12 +717,6 @@ async fn execute_fastcgi(
   let params_packet_terminating = construct_fastcgi_record(4, 1, &[]);
   socket_writer.write_all(&params_packet_terminating).await?;
 
-  let cgi_stdin_reader = StreamReader::new(
-    body
-      .into_data_stream()
-      .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
-  );
-
   // Emulated standard input, standard output, and standard error
   type EitherStream = Either<Result<Bytes, std::io::Error>, Result<Bytes, std::io::Error>>;
   let stdin = SinkWriter::new(FramedWrite::new(socket_writer, FcgiEncoder::new()));
@@ -813,10 +814,8 @@ async fn execute_fastcgi(
       break;
     }
     match &header.name.to_lowercase() as &str {
-      "location" => {
-        if !(300..=399).contains(&status_code) {
-          status_code = 302;
-        }
+      "location" if !(300..=399).contains(&status_code) => {
+        status_code = 302;
       }
       "status" => {
         let header_value_cow = String::from_utf8_lossy(header.value);
@@ -900,7 +899,7 @@ async fn connect_tcp(
   ),
   tokio::io::Error,
 > {
-  let socket = TcpStream::connect(addr).await?;
+  let socket = dns_resolver::connect_tcp(addr).await?;
   socket.set_nodelay(true)?;
 
   let (socket_reader_set, socket_writer_set) = tokio::io::split(socket);
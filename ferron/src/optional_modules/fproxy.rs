@@ -8,15 +8,23 @@ use ferron_common::{
 };
 use ferron_common::{HyperResponse, WithRuntime};
 use http_body_util::combinators::BoxBody;
-use http_body_util::BodyExt;
+use http_body_util::{BodyExt, Empty};
 use hyper::body::Bytes;
-use hyper::{header, Request, StatusCode, Uri};
+use hyper::{header, Request, Response, StatusCode, Uri};
 use hyper_tungstenite::HyperWebsocket;
 use hyper_util::rt::TokioIo;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::runtime::Handle;
 
+use crate::ferron_util::forward_proxy_auth::{
+  authorize_proxy_request, proxy_user_key, proxy_user_usage, ProxyAuthenticationChallenge,
+  ProxyUserUsage,
+};
+use crate::ferron_util::hop_by_hop::strip_hop_by_hop_headers;
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::ssrf_guard::SsrfGuard;
+use crate::ferron_util::upstream_proxy::{connect_tcp_via, UpstreamProxy};
+
 pub fn server_module_init(
   _config: &ServerConfig,
 ) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
@@ -56,8 +64,8 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
   async fn proxy_request_handler(
     &mut self,
     request: RequestData,
-    _config: &ServerConfigRoot,
-    _socket_data: &SocketData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
     error_logger: &ErrorLogger,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
@@ -89,11 +97,67 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
 
       let port = hyper_request_parts.uri.port_u16().unwrap_or(80);
 
-      let addr = format!("{}:{}", host, port);
-      let stream = match TcpStream::connect(addr).await {
+      if let Err(denial) = authorize_proxy_request(
+        &hyper_request_parts.headers,
+        hyper_request_parts.method.as_str(),
+        &hyper_request_parts.uri.to_string(),
+        (host, port),
+        config,
+        socket_data.remote_addr.ip(),
+        error_logger,
+      )
+      .await?
+      {
+        if denial.headers.is_empty() {
+          return Ok(
+            ResponseData::builder_without_request()
+              .status(denial.status)
+              .build(),
+          );
+        }
+        // The authentication challenge is sent without the error page, as the error pages don't keep the response extensions
+        let mut response = Response::builder()
+          .status(denial.status)
+          .extension(ProxyAuthenticationChallenge)
+          .body(Empty::new().map_err(|e| match e {}).boxed())?;
+        *response.headers_mut() = denial.headers;
+        return Ok(
+          ResponseData::builder_without_request()
+            .response(response)
+            .build(),
+        );
+      }
+
+      let upstream_proxy = match config.get("upstreamProxy").as_str() {
+        Some(upstream_proxy) => Some(UpstreamProxy::parse(upstream_proxy)?),
+        None => None,
+      };
+      // The destination is resolved and validated before connecting, and the validated address is connected to
+      let stream_result = match SsrfGuard::from_config(config) {
+        Some(ssrf_guard) => match ssrf_guard.resolve(host, port).await {
+          Ok(destination_address) => {
+            connect_tcp_via(upstream_proxy.as_ref(), &destination_address.to_string()).await
+          }
+          Err(err) => Err(err),
+        },
+        None => connect_tcp_via(upstream_proxy.as_ref(), &format!("{}:{}", host, port)).await,
+      };
+      let stream = match stream_result {
         Ok(stream) => stream,
         Err(err) => {
           match err.kind() {
+            tokio::io::ErrorKind::PermissionDenied => {
+              METRICS.increment_counter(
+                "ferron_forward_proxy_denials_total",
+                &[("reason", "blocked_destination")],
+              );
+              error_logger.log(&format!("Forbidden: {}", err)).await;
+              return Ok(
+                ResponseData::builder_without_request()
+                  .status(StatusCode::FORBIDDEN)
+                  .build(),
+              );
+            }
             tokio::io::ErrorKind::ConnectionRefused
             | tokio::io::ErrorKind::NotFound
             | tokio::io::ErrorKind::HostUnreachable => {
@@ -149,6 +213,9 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
         }
       ))?;
 
+      // The hop-by-hop headers (including "Proxy-Authorization") apply only to the client's connection
+      strip_hop_by_hop_headers(&mut hyper_request_parts.headers, false);
+
       // Connection header to disable HTTP/1.1 keep-alive
       hyper_request_parts
         .headers
@@ -179,12 +246,16 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
     &mut self,
     upgraded_request: HyperUpgraded,
     connect_address: &str,
-    _config: &ServerConfigRoot,
-    _socket_data: &SocketData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
     error_logger: &ErrorLogger,
   ) -> Result<(), Box<dyn Error + Send + Sync>> {
     WithRuntime::new(self.handle.clone(), async move {
-      let mut stream = match TcpStream::connect(connect_address).await {
+      let upstream_proxy = match config.get("upstreamProxy").as_str() {
+        Some(upstream_proxy) => Some(UpstreamProxy::parse(upstream_proxy)?),
+        None => None,
+      };
+      let mut stream = match connect_tcp_via(upstream_proxy.as_ref(), connect_address).await {
         Ok(stream) => stream,
         Err(err) => {
           error_logger
@@ -208,9 +279,35 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
 
       let mut upgraded = TokioIo::new(upgraded_request);
 
-      tokio::io::copy_bidirectional(&mut upgraded, &mut stream)
-        .await
-        .unwrap_or_default();
+      // The bandwidth budget is shared between all the tunnels of the forward proxy user
+      let bandwidth_per_user = config
+        .get("forwardProxyBandwidthPerUser")
+        .as_i64()
+        .filter(|bandwidth_per_user| *bandwidth_per_user > 0)
+        .map(|bandwidth_per_user| bandwidth_per_user as u64);
+      let user_usage = bandwidth_per_user.and_then(|_| {
+        proxy_user_usage(&proxy_user_key(
+          socket_data.get_proxy_auth_user(),
+          socket_data.remote_addr.ip(),
+        ))
+      });
+
+      match (bandwidth_per_user, user_usage) {
+        (Some(bandwidth_per_user), Some(user_usage)) => {
+          let (client_read, client_write) = tokio::io::split(upgraded);
+          let (server_read, server_write) = stream.into_split();
+          tokio::try_join!(
+            copy_throttled(client_read, server_write, &user_usage, bandwidth_per_user),
+            copy_throttled(server_read, client_write, &user_usage, bandwidth_per_user)
+          )
+          .unwrap_or_default();
+        }
+        _ => {
+          tokio::io::copy_bidirectional(&mut upgraded, &mut stream)
+            .await
+            .unwrap_or_default();
+        }
+      }
 
       Ok(())
     })
@@ -241,6 +338,24 @@ impl ServerModuleHandlers for ForwardProxyModuleHandlers {
   }
 }
 
+// Copy the data in one direction of the tunnel, within the bandwidth budget of the forward proxy user
+async fn copy_throttled(
+  mut reader: impl AsyncRead + Unpin,
+  mut writer: impl AsyncWrite + Unpin,
+  user_usage: &ProxyUserUsage,
+  bandwidth_per_user: u64,
+) -> Result<(), std::io::Error> {
+  let mut buffer = vec![0u8; bandwidth_per_user.clamp(1, 16384) as usize];
+  loop {
+    let read_bytes = reader.read(&mut buffer).await?;
+    if read_bytes == 0 {
+      return writer.shutdown().await;
+    }
+    user_usage.throttle(read_bytes, bandwidth_per_user).await;
+    writer.write_all(&buffer[..read_bytes]).await?;
+  }
+}
+
 async fn http_proxy(
   stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
   proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
@@ -282,7 +397,7 @@ async fn http_proxy(
 
         response = ResponseData::builder_without_request()
                   .response(proxy_response.map(|b| {
-                    b.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+                    b.map_err(|e| std::io::Error::other(e.to_string()))
                       .boxed()
                   }))
                   .parallel_fn(async move {
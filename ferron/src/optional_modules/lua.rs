@@ -0,0 +1,266 @@
+// The "lua" module runs the Lua scripting hooks, inspired by OpenResty's phase handlers.
+
+use std::error::Error;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use hyper::{header, Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use mlua::{AnyUserData, Lua};
+use tokio::runtime::Handle;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::lua_scripting::{
+  check_lua_hook, create_lua_state, run_lua_hook, LuaBodyFilter, LuaRequest, LuaResponse,
+};
+
+// The configuration properties with the Lua code of the hooks, in the order the hooks are run
+const LUA_HOOK_PROPERTIES: [&str; 4] = [
+  "luaRewrite",
+  "luaAccess",
+  "luaHeaderFilter",
+  "luaBodyFilter",
+];
+
+// Check the Lua code of the hooks, so that the syntax errors are reported when the server starts
+fn check_lua_hooks(config: &Yaml) -> Result<(), anyhow::Error> {
+  for property in LUA_HOOK_PROPERTIES {
+    if let Some(code) = config[property].as_str() {
+      check_lua_hook(property, code)
+        .map_err(|err| anyhow::anyhow!("Invalid Lua code in \"{}\": {}", property, err))?;
+    }
+  }
+  Ok(())
+}
+
+pub fn server_module_init(
+  config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  check_lua_hooks(&config["global"])?;
+  if let Some(hosts) = config["hosts"].as_vec() {
+    for host_yaml in hosts.iter() {
+      check_lua_hooks(host_yaml)?;
+      if let Some(locations) = host_yaml["locations"].as_vec() {
+        for location_yaml in locations.iter() {
+          check_lua_hooks(location_yaml)?;
+        }
+      }
+    }
+  }
+
+  Ok(Box::new(LuaModule::new()))
+}
+
+struct LuaModule;
+
+impl LuaModule {
+  fn new() -> Self {
+    LuaModule
+  }
+}
+
+impl ServerModule for LuaModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(LuaModuleHandlers {
+      handle,
+      lua: None,
+      header_filter_hook: None,
+      body_filter_hook: None,
+    })
+  }
+}
+
+struct LuaModuleHandlers {
+  handle: Handle,
+  // The hooks of a request share the Lua state, so that the global variables set by earlier hooks can be read by later ones
+  lua: Option<Lua>,
+  header_filter_hook: Option<String>,
+  body_filter_hook: Option<String>,
+}
+
+#[async_trait]
+impl ServerModuleHandlers for LuaModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    mut request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let rewrite_hook = config.get("luaRewrite");
+      let access_hook = config.get("luaAccess");
+      self.header_filter_hook = config.get("luaHeaderFilter").as_str().map(String::from);
+      self.body_filter_hook = config.get("luaBodyFilter").as_str().map(String::from);
+      if rewrite_hook.as_str().is_none()
+        && access_hook.as_str().is_none()
+        && self.header_filter_hook.is_none()
+        && self.body_filter_hook.is_none()
+      {
+        return Ok(ResponseData::builder(request).build());
+      }
+
+      let lua = create_lua_state()?;
+      let hyper_request = request.get_hyper_request();
+      lua.globals().set(
+        "request",
+        LuaRequest {
+          method: hyper_request.method().to_string(),
+          uri: hyper_request.uri().clone(),
+          headers: hyper_request.headers().clone(),
+          remote_address: socket_data.remote_addr.ip().to_canonical().to_string(),
+          encrypted: socket_data.encrypted,
+          auth_user: request.get_auth_user().map(String::from),
+        },
+      )?;
+
+      if let Some(rewrite_hook) = rewrite_hook.as_str() {
+        run_lua_hook::<()>(&lua, "luaRewrite", rewrite_hook)?;
+      }
+
+      // The access hook can deny the request by returning the status code, and optionally the response body
+      let access_response = match access_hook.as_str() {
+        Some(access_hook) => {
+          let (status, body): (Option<u16>, Option<mlua::String>) =
+            run_lua_hook(&lua, "luaAccess", access_hook)?;
+          status.map(|status| {
+            (
+              status,
+              body.map(|body| Bytes::copy_from_slice(body.as_bytes())),
+            )
+          })
+        }
+        None => None,
+      };
+
+      // The URL and the headers changed by the hooks are applied to the request
+      let lua_request = lua
+        .globals()
+        .get::<_, AnyUserData>("request")?
+        .borrow::<LuaRequest>()?
+        .clone();
+      let hyper_request = request.get_mut_hyper_request();
+      *hyper_request.uri_mut() = lua_request.uri;
+      *hyper_request.headers_mut() = lua_request.headers;
+
+      self.lua = Some(lua);
+
+      match access_response {
+        Some((status, Some(body))) => Ok(
+          ResponseData::builder(request)
+            .validated_response(
+              Response::builder()
+                .status(StatusCode::from_u16(status)?)
+                .header(header::CONTENT_TYPE, "text/plain")
+                .body(body)?,
+            )
+            .build(),
+        ),
+        Some((status, None)) => Ok(
+          ResponseData::builder(request)
+            .status(StatusCode::from_u16(status)?)
+            .build(),
+        ),
+        None => Ok(ResponseData::builder(request).build()),
+      }
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    let lua = match self.lua.take() {
+      Some(lua) => lua,
+      None => return Ok(response),
+    };
+    let (mut response_parts, response_body) = response.into_parts();
+
+    if let Some(header_filter_hook) = self.header_filter_hook.take() {
+      lua.globals().set(
+        "response",
+        LuaResponse {
+          status: response_parts.status,
+          headers: response_parts.headers.clone(),
+        },
+      )?;
+      run_lua_hook::<()>(&lua, "luaHeaderFilter", &header_filter_hook)?;
+      let lua_response = lua
+        .globals()
+        .get::<_, AnyUserData>("response")?
+        .take::<LuaResponse>()?;
+      response_parts.status = lua_response.status;
+      response_parts.headers = lua_response.headers;
+    }
+
+    let response_body = match self.body_filter_hook.take() {
+      Some(body_filter_hook) => {
+        // The body filter can change the content, so the length and the entity tag no longer apply
+        response_parts.headers.remove(header::CONTENT_LENGTH);
+        response_parts.headers.remove(header::ETAG);
+        LuaBodyFilter::new(response_body, lua, body_filter_hook).boxed()
+      }
+      None => response_body,
+    };
+
+    Ok(Response::from_parts(response_parts, response_body))
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
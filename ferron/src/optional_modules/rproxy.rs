@@ -1,38 +1,60 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use ferron_common::{
-  ErrorLogger, HyperUpgraded, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
-  ServerModule, ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperUpgraded, RequestData, RequestVariables, ResponseData, ServerConfig,
+  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData, TaskScheduler,
 };
-use ferron_common::{HyperResponse, WithRuntime};
+use ferron_common::{HyperRequest, HyperResponse, WithRuntime};
 use futures_util::{SinkExt, StreamExt};
 use http::uri::{PathAndQuery, Scheme};
 use http_body_util::combinators::BoxBody;
-use http_body_util::BodyExt;
-use hyper::body::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Body, Bytes};
 use hyper::client::conn::http1::SendRequest;
-use hyper::{header, Request, StatusCode, Uri};
+use hyper::client::conn::http2;
+use hyper::upgrade::OnUpgrade;
+use hyper::{header, Method, Request, StatusCode, Uri, Version};
 use hyper_tungstenite::HyperWebsocket;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use rustls::pki_types::ServerName;
 use rustls::RootCertStore;
 use rustls_native_certs::load_native_certs;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 use tokio_rustls::TlsConnector;
 use tokio_tungstenite::Connector;
+use yaml_rust2::Yaml;
 
+use crate::ferron_util::admin_api::is_backend_drained;
+use crate::ferron_util::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+use crate::ferron_util::expression::ExpressionContext;
+use crate::ferron_util::hop_by_hop::strip_hop_by_hop_headers;
+use crate::ferron_util::metrics::METRICS;
 use crate::ferron_util::no_server_verifier::NoServerVerifier;
+use crate::ferron_util::retry_budget::RetryBudget;
 use crate::ferron_util::ttl_cache::TtlCache;
+use crate::ferron_util::upstream_health::{
+  record_backend_ejection, record_backend_failure, record_backend_restoration,
+  record_backend_success,
+};
+use crate::ferron_util::upstream_integrity::{has_verifiable_body, IntegrityBody};
+use crate::ferron_util::upstream_proxy::{connect_tcp_via, UpstreamProxy};
+use crate::ferron_util::variable_substitution::substitute_variables;
 
-const DEFAULT_CONCURRENT_CONNECTIONS_PER_HOST: u32 = 32;
+const DEFAULT_MAX_IDLE_CONNECTIONS_PER_UPSTREAM: usize = 32;
+const DEFAULT_IDLE_CONNECTION_TIMEOUT: u64 = 60000;
+const DEFAULT_RETRY_BUDGET_PERCENT: u64 = 20;
+const MIN_RETRIES_PER_BUDGET_WINDOW: u64 = 10;
+const RETRY_BUDGET_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_RETRY_ON: [&str; 2] = ["error", "timeout"];
 
 pub fn server_module_init(
   config: &ServerConfig,
@@ -57,278 +79,510 @@ pub fn server_module_init(
     }
   }
 
-  let mut connections_vec = Vec::new();
-  for _ in 0..DEFAULT_CONCURRENT_CONNECTIONS_PER_HOST {
-    connections_vec.push(RwLock::new(HashMap::new()));
-  }
+  // The keep-alive connections to the backend servers are reused for the subsequent requests
+  let connection_pool_config = ConnectionPoolConfig {
+    max_idle_connections: config["global"]["proxyMaxIdleConnections"].as_i64().map_or(
+      DEFAULT_MAX_IDLE_CONNECTIONS_PER_UPSTREAM,
+      |max_idle_connections| max_idle_connections.max(0) as usize,
+    ),
+    idle_timeout: match &config["global"]["proxyIdleTimeout"] {
+      Yaml::Null => None,
+      idle_timeout => Some(Duration::from_millis(
+        idle_timeout
+          .as_i64()
+          .map_or(DEFAULT_IDLE_CONNECTION_TIMEOUT, |idle_timeout| {
+            idle_timeout.max(0) as u64
+          }),
+      )),
+    },
+    max_requests: config["global"]["proxyMaxRequestsPerConnection"]
+      .as_i64()
+      .map(|max_requests| max_requests.max(0) as u64),
+  };
+
   Ok(Box::new(ReverseProxyModule::new(
     Arc::new(roots),
-    Arc::new(connections_vec),
+    Arc::new(ConnectionPool::new(connection_pool_config)),
+    Arc::new(RwLock::new(HashMap::new())),
     Arc::new(RwLock::new(TtlCache::new(Duration::from_millis(
       config["global"]["loadBalancerHealthCheckWindow"]
         .as_i64()
         .unwrap_or(5000) as u64,
     )))),
+    Arc::new(RetryBudget::new(
+      config["global"]["proxyRetryBudget"]
+        .as_i64()
+        .map_or(DEFAULT_RETRY_BUDGET_PERCENT, |percent| {
+          percent.max(0) as u64
+        }),
+      MIN_RETRIES_PER_BUDGET_WINDOW,
+      RETRY_BUDGET_WINDOW,
+    )),
   )))
 }
 
 #[allow(clippy::type_complexity)]
 struct ReverseProxyModule {
   roots: Arc<RootCertStore>,
-  connections: Arc<Vec<RwLock<HashMap<String, SendRequest<BoxBody<Bytes, hyper::Error>>>>>>,
+  connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  http2_connections: Arc<RwLock<HashMap<String, http2::SendRequest<BoxBody<Bytes, hyper::Error>>>>>,
   failed_backends: Arc<RwLock<TtlCache<String, u64>>>,
+  retry_budget: Arc<RetryBudget>,
 }
 
 impl ReverseProxyModule {
   #[allow(clippy::type_complexity)]
   fn new(
     roots: Arc<RootCertStore>,
-    connections: Arc<Vec<RwLock<HashMap<String, SendRequest<BoxBody<Bytes, hyper::Error>>>>>>,
+    connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+    http2_connections: Arc<
+      RwLock<HashMap<String, http2::SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+    >,
     failed_backends: Arc<RwLock<TtlCache<String, u64>>>,
+    retry_budget: Arc<RetryBudget>,
   ) -> Self {
     ReverseProxyModule {
       roots,
-      connections,
+      connection_pool,
+      http2_connections,
       failed_backends,
+      retry_budget,
+    }
+  }
+
+  // Schedule the periodic closing of the expired idle connections to the backend servers,
+  // until the module is shut down (for example after the configuration is reloaded)
+  fn schedule_idle_connection_expiry(&self, task_scheduler: &TaskScheduler) {
+    if let Some(expiry_check_interval) = self.connection_pool.expiry_check_interval() {
+      let connection_pool = self.connection_pool.clone();
+      task_scheduler.schedule_periodic(
+        "idle backend connection expiry",
+        expiry_check_interval,
+        Duration::ZERO,
+        move || {
+          connection_pool.close_expired();
+          async { Ok(()) }
+        },
+      );
     }
   }
 }
 
+#[async_trait]
 impl ServerModule for ReverseProxyModule {
   fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
     Box::new(ReverseProxyModuleHandlers {
       roots: self.roots.clone(),
-      connections: self.connections.clone(),
+      connection_pool: self.connection_pool.clone(),
+      http2_connections: self.http2_connections.clone(),
       failed_backends: self.failed_backends.clone(),
+      retry_budget: self.retry_budget.clone(),
       handle,
     })
   }
+
+  async fn on_startup(
+    &self,
+    _config: &ServerConfigRoot,
+    _error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.schedule_idle_connection_expiry(task_scheduler);
+    Ok(())
+  }
+
+  async fn on_config_reload(
+    &self,
+    _config: &ServerConfigRoot,
+    _error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.schedule_idle_connection_expiry(task_scheduler);
+    Ok(())
+  }
 }
 
 #[allow(clippy::type_complexity)]
 struct ReverseProxyModuleHandlers {
   handle: Handle,
   roots: Arc<RootCertStore>,
-  connections: Arc<Vec<RwLock<HashMap<String, SendRequest<BoxBody<Bytes, hyper::Error>>>>>>,
+  connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  http2_connections: Arc<RwLock<HashMap<String, http2::SendRequest<BoxBody<Bytes, hyper::Error>>>>>,
   failed_backends: Arc<RwLock<TtlCache<String, u64>>>,
+  retry_budget: Arc<RetryBudget>,
 }
 
-#[async_trait]
-impl ServerModuleHandlers for ReverseProxyModuleHandlers {
-  async fn request_handler(
-    &mut self,
-    request: RequestData,
-    config: &ServerConfigRoot,
+impl ReverseProxyModuleHandlers {
+  // Send the request to the backend server. If the backend server doesn't send the response head
+  // within the per-try timeout, the request is aborted. The request variables (for example, "$host")
+  // are substituted in the backend server URL, and the time it took the backend server to send
+  // the response head is recorded in the "upstream_response_time" variable. If the response integrity verification
+  // is enabled, the response body is checked against the declared length and digest.
+  #[allow(clippy::too_many_arguments)]
+  async fn proxy_request_with_timeout(
+    &self,
+    proxy_to: String,
+    hyper_request: HyperRequest,
+    try_timeout: Option<Duration>,
     socket_data: &SocketData,
     error_logger: &ErrorLogger,
+    enable_health_check: bool,
+    disable_certificate_verification: bool,
+    upstream_proxy: Option<&UpstreamProxy>,
+    verify_response_integrity: bool,
   ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
-    WithRuntime::new(self.handle.clone(), async move {
-      let enable_health_check = config
-        .get("enableLoadBalancerHealthCheck")
-        .as_bool()
-        .unwrap_or(false);
-      let health_check_max_fails = config
-        .get("loadBalancerHealthCheckMaximumFails")
-        .as_i64()
-        .unwrap_or(3) as u64;
-      let disable_certificate_verification = config
-        .get("disableProxyCertificateVerification")
-        .as_bool()
-        .unwrap_or(false);
-      if let Some(proxy_to) = determine_proxy_to(
-        config,
-        socket_data.encrypted,
-        &self.failed_backends,
+    let is_head_request = hyper_request.method() == Method::HEAD;
+    let expression_context = ExpressionContext::new(&hyper_request, socket_data);
+    let proxy_to = substitute_variables(&proxy_to, |name| expression_context.variable(name));
+    let variables = hyper_request
+      .extensions()
+      .get::<RequestVariables>()
+      .cloned();
+    let upstream_start = Instant::now();
+    let result = self
+      .proxy_request_with_timeout_inner(
+        proxy_to.clone(),
+        hyper_request,
+        try_timeout,
+        socket_data,
+        error_logger,
         enable_health_check,
-        health_check_max_fails,
+        disable_certificate_verification,
+        upstream_proxy,
       )
-      .await
-      {
-        let (hyper_request, _auth_user) = request.into_parts();
-        let (mut hyper_request_parts, request_body) = hyper_request.into_parts();
-
-        let proxy_request_url = proxy_to.parse::<hyper::Uri>()?;
-        let scheme_str = proxy_request_url.scheme_str();
-        let mut encrypted = false;
-
-        match scheme_str {
-          Some("http") => {
-            encrypted = false;
+      .await;
+    // The responses generated by Ferron, when the backend server couldn't be reached, count as the backend server failures
+    let result = match result {
+      Ok(response_data) => {
+        let (response_data, retry_condition) = with_retry_condition(response_data);
+        match retry_condition {
+          Some(error @ ("error" | "timeout")) => {
+            record_backend_failure(&proxy_to, upstream_start.elapsed(), error)
           }
-          Some("https") => {
-            encrypted = true;
+          _ => record_backend_success(&proxy_to, upstream_start.elapsed()),
+        }
+        Ok(match verify_response_integrity {
+          true => {
+            with_integrity_verification(response_data, is_head_request, &proxy_to, error_logger)
           }
-          _ => Err(anyhow::anyhow!(
-            "Only HTTP and HTTPS reverse proxy URLs are supported."
-          ))?,
-        };
+          false => response_data,
+        })
+      }
+      Err(err) => {
+        record_backend_failure(&proxy_to, upstream_start.elapsed(), "error");
+        Err(err)
+      }
+    };
+    let upstream_duration = upstream_start.elapsed().as_secs_f64();
+    METRICS.observe_histogram(
+      "ferron_upstream_response_duration_seconds",
+      &[],
+      upstream_duration,
+    );
+    if let Some(variables) = variables {
+      // The times of the retried requests are separated with commas
+      let upstream_response_time = format!("{:.3}", upstream_duration);
+      variables.set(
+        "upstream_response_time",
+        match variables.get("upstream_response_time") {
+          Some(previous_times) => format!("{}, {}", previous_times, upstream_response_time),
+          None => upstream_response_time,
+        },
+      );
+    }
+    result
+  }
 
-        let host = match proxy_request_url.host() {
-          Some(host) => host,
-          None => Err(anyhow::anyhow!(
-            "The reverse proxy URL doesn't include the host"
-          ))?,
-        };
+  #[allow(clippy::too_many_arguments)]
+  async fn proxy_request_with_timeout_inner(
+    &self,
+    proxy_to: String,
+    hyper_request: HyperRequest,
+    try_timeout: Option<Duration>,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+    enable_health_check: bool,
+    disable_certificate_verification: bool,
+    upstream_proxy: Option<&UpstreamProxy>,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let proxy_future = self.proxy_request_to_backend(
+      proxy_to.clone(),
+      hyper_request,
+      socket_data,
+      error_logger,
+      enable_health_check,
+      disable_certificate_verification,
+      upstream_proxy,
+    );
+    match try_timeout {
+      Some(try_timeout) => match tokio::time::timeout(try_timeout, proxy_future).await {
+        Ok(result) => result,
+        Err(_) => {
+          if enable_health_check {
+            let mut failed_backends_write = self.failed_backends.write().await;
+            let failed_attempts = failed_backends_write.get(&proxy_to);
+            failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
+          }
+          error_logger
+            .log("Gateway timeout: the backend server didn't respond in time")
+            .await;
+          Ok(
+            ResponseData::builder_without_request()
+              .status(StatusCode::GATEWAY_TIMEOUT)
+              .build(),
+          )
+        }
+      },
+      None => proxy_future.await,
+    }
+  }
 
-        let port = proxy_request_url.port_u16().unwrap_or(match scheme_str {
-          Some("http") => 80,
-          Some("https") => 443,
-          _ => 80,
-        });
+  // Send the request to the backend server
+  #[allow(clippy::too_many_arguments)]
+  async fn proxy_request_to_backend(
+    &self,
+    proxy_to: String,
+    mut hyper_request: HyperRequest,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+    enable_health_check: bool,
+    disable_certificate_verification: bool,
+    upstream_proxy: Option<&UpstreamProxy>,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let proxy_request_url = proxy_to.parse::<hyper::Uri>()?;
+    let scheme_str = proxy_request_url.scheme_str();
+    let mut encrypted = false;
+    let mut http2 = false;
+
+    // The "h2c" and "h2" schemes (named after the HTTP/2 protocol identifiers) select HTTP/2 for the backend server,
+    // which is required for proxying gRPC services
+    match scheme_str {
+      Some("http") => {
+        encrypted = false;
+      }
+      Some("https") => {
+        encrypted = true;
+      }
+      Some("h2c") => {
+        encrypted = false;
+        http2 = true;
+      }
+      Some("h2") => {
+        encrypted = true;
+        http2 = true;
+      }
+      _ => Err(anyhow::anyhow!(
+        "Only HTTP, HTTPS, HTTP/2 cleartext (\"h2c\") and HTTP/2 over TLS (\"h2\") reverse proxy URLs are supported."
+      ))?,
+    };
 
-        let addr = format!("{}:{}", host, port);
-        let authority = proxy_request_url.authority().cloned();
+    // The HTTP/1.1 upgrades other than WebSocket ones (which are handled by the WebSocket request handler)
+    // are passed through to the backend server. HTTP/2 doesn't support them.
+    let client_upgrade = match !http2 && is_http_upgrade_request(&hyper_request) {
+      true => Some(hyper::upgrade::on(&mut hyper_request)),
+      false => None,
+    };
+    let (mut hyper_request_parts, request_body) = hyper_request.into_parts();
 
-        let hyper_request_path = hyper_request_parts.uri.path();
+    // The hop-by-hop headers apply only to the client's connection
+    strip_hop_by_hop_headers(&mut hyper_request_parts.headers, client_upgrade.is_some());
 
-        let path = match hyper_request_path.as_bytes().first() {
-          Some(b'/') => {
-            let mut proxy_request_path = proxy_request_url.path();
-            while proxy_request_path.as_bytes().last().copied() == Some(b'/') {
-              proxy_request_path = &proxy_request_path[..(proxy_request_path.len() - 1)];
-            }
-            format!("{}{}", proxy_request_path, hyper_request_path)
-          }
-          _ => hyper_request_path.to_string(),
-        };
+    let host = match proxy_request_url.host() {
+      Some(host) => host,
+      None => Err(anyhow::anyhow!(
+        "The reverse proxy URL doesn't include the host"
+      ))?,
+    };
 
-        hyper_request_parts.uri = Uri::from_str(&format!(
-          "{}{}",
-          path,
-          match hyper_request_parts.uri.query() {
-            Some(query) => format!("?{}", query),
-            None => "".to_string(),
-          }
-        ))?;
+    let port = proxy_request_url
+      .port_u16()
+      .unwrap_or(if encrypted { 443 } else { 80 });
 
-        let original_host = hyper_request_parts.headers.get(header::HOST).cloned();
+    let addr = format!("{}:{}", host, port);
+    let authority = proxy_request_url.authority().cloned();
 
-        // Host header for host identification
-        match authority {
-          Some(authority) => {
-            hyper_request_parts
-              .headers
-              .insert(header::HOST, authority.to_string().parse()?);
-          }
-          None => {
-            hyper_request_parts.headers.remove(header::HOST);
-          }
-        }
+    let hyper_request_path = hyper_request_parts.uri.path();
 
-        // Connection header to enable HTTP/1.1 keep-alive
-        hyper_request_parts
-          .headers
-          .insert(header::CONNECTION, "keep-alive".parse()?);
-
-        // X-Forwarded-* headers to send the client's data to a server that's behind the reverse proxy
-        hyper_request_parts.headers.insert(
-          "x-forwarded-for",
-          socket_data
-            .remote_addr
-            .ip()
-            .to_canonical()
-            .to_string()
-            .parse()?,
-        );
+    let path = match hyper_request_path.as_bytes().first() {
+      Some(b'/') => {
+        let mut proxy_request_path = proxy_request_url.path();
+        while proxy_request_path.as_bytes().last().copied() == Some(b'/') {
+          proxy_request_path = &proxy_request_path[..(proxy_request_path.len() - 1)];
+        }
+        format!("{}{}", proxy_request_path, hyper_request_path)
+      }
+      _ => hyper_request_path.to_string(),
+    };
 
-        if socket_data.encrypted {
-          hyper_request_parts
-            .headers
-            .insert("x-forwarded-proto", "https".parse()?);
-        } else {
+    let path_and_query = format!(
+      "{}{}",
+      path,
+      match hyper_request_parts.uri.query() {
+        Some(query) => format!("?{}", query),
+        None => "".to_string(),
+      }
+    );
+
+    let original_host = hyper_request_parts.headers.get(header::HOST).cloned();
+
+    if http2 {
+      // HTTP/2 identifies the host with the ":authority" pseudo-header, which is taken from the request URL
+      hyper_request_parts.uri = Uri::from_str(&format!(
+        "{}://{}{}",
+        if encrypted { "https" } else { "http" },
+        authority.map_or(addr.clone(), |authority| authority.to_string()),
+        path_and_query
+      ))?;
+      hyper_request_parts.version = Version::HTTP_2;
+      hyper_request_parts.headers.remove(header::HOST);
+    } else {
+      hyper_request_parts.uri = Uri::from_str(&path_and_query)?;
+
+      // Host header for host identification
+      match authority {
+        Some(authority) => {
           hyper_request_parts
             .headers
-            .insert("x-forwarded-proto", "http".parse()?);
+            .insert(header::HOST, authority.to_string().parse()?);
+        }
+        None => {
+          hyper_request_parts.headers.remove(header::HOST);
         }
+      }
 
-        if let Some(original_host) = original_host {
-          hyper_request_parts
-            .headers
-            .insert("x-forwarded-host", original_host);
+      // Connection header to enable HTTP/1.1 keep-alive, or to request the protocol upgrade
+      hyper_request_parts.headers.insert(
+        header::CONNECTION,
+        match client_upgrade {
+          Some(_) => "upgrade",
+          None => "keep-alive",
         }
+        .parse()?,
+      );
+    }
 
-        let proxy_request = Request::from_parts(hyper_request_parts, request_body);
+    // X-Forwarded-* headers to send the client's data to a server that's behind the reverse proxy
+    hyper_request_parts.headers.insert(
+      "x-forwarded-for",
+      socket_data
+        .remote_addr
+        .ip()
+        .to_canonical()
+        .to_string()
+        .parse()?,
+    );
 
-        let connections = &self.connections[rand::random_range(..self.connections.len())];
+    if socket_data.encrypted {
+      hyper_request_parts
+        .headers
+        .insert("x-forwarded-proto", "https".parse()?);
+    } else {
+      hyper_request_parts
+        .headers
+        .insert("x-forwarded-proto", "http".parse()?);
+    }
 
-        let rwlock_read = connections.read().await;
-        let sender_read_option = rwlock_read.get(&addr);
+    if let Some(original_host) = original_host {
+      hyper_request_parts
+        .headers
+        .insert("x-forwarded-host", original_host);
+    }
 
-        if let Some(sender_read) = sender_read_option {
-          if !sender_read.is_closed() {
-            drop(rwlock_read);
-            let mut rwlock_write = connections.write().await;
-            let sender_option = rwlock_write.get_mut(&addr);
+    let mut proxy_request = Request::from_parts(hyper_request_parts, request_body);
+
+    // The connections opened through an upstream proxy aren't shared with the direct ones
+    let connection_key = match upstream_proxy {
+      Some(upstream_proxy) => format!(
+        "{}://{} via {}",
+        scheme_str.unwrap_or_default(),
+        addr,
+        upstream_proxy
+      ),
+      None => format!("{}://{}", scheme_str.unwrap_or_default(), addr),
+    };
 
-            if let Some(sender) = sender_option {
-              if !sender.is_closed() {
-                let result = http_proxy_kept_alive(sender, proxy_request, error_logger).await;
-                drop(rwlock_write);
-                return result;
-              } else {
-                drop(rwlock_write);
-              }
-            } else {
-              drop(rwlock_write);
+    // The HTTP/2 connections are multiplexed, so one connection to the backend server is shared by the requests
+    if http2 {
+      let sender_option = self
+        .http2_connections
+        .read()
+        .await
+        .get(&connection_key)
+        .cloned();
+      if let Some(mut sender) = sender_option {
+        match http2_proxy_kept_alive(&mut sender, proxy_request, error_logger).await {
+          Ok(response) => return Ok(response),
+          Err(unsent_proxy_request) => {
+            // The connection is closing (for example, because the backend server sent GOAWAY),
+            // so the request is sent over a new connection
+            let mut rwlock_write = self.http2_connections.write().await;
+            if rwlock_write
+              .get(&connection_key)
+              .is_some_and(|pooled_sender| pooled_sender.is_closed())
+            {
+              rwlock_write.remove(&connection_key);
             }
-          } else {
-            drop(rwlock_read);
+            drop(rwlock_write);
+            proxy_request = unsent_proxy_request;
           }
-        } else {
-          drop(rwlock_read);
         }
+      }
+    }
 
-        let stream = match TcpStream::connect(&addr).await {
-          Ok(stream) => stream,
-          Err(err) => {
-            if enable_health_check {
-              let mut failed_backends_write = self.failed_backends.write().await;
-              let proxy_to = proxy_to.clone();
-              let failed_attempts = failed_backends_write.get(&proxy_to);
-              failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
-            }
-            match err.kind() {
-              tokio::io::ErrorKind::ConnectionRefused
-              | tokio::io::ErrorKind::NotFound
-              | tokio::io::ErrorKind::HostUnreachable => {
-                error_logger
-                  .log(&format!("Service unavailable: {}", err))
-                  .await;
-                return Ok(
-                  ResponseData::builder_without_request()
-                    .status(StatusCode::SERVICE_UNAVAILABLE)
-                    .build(),
-                );
-              }
-              tokio::io::ErrorKind::TimedOut => {
-                error_logger.log(&format!("Gateway timeout: {}", err)).await;
-                return Ok(
-                  ResponseData::builder_without_request()
-                    .status(StatusCode::GATEWAY_TIMEOUT)
-                    .build(),
-                );
-              }
-              _ => {
-                error_logger.log(&format!("Bad gateway: {}", err)).await;
-                return Ok(
-                  ResponseData::builder_without_request()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .build(),
-                );
-              }
-            };
-          }
-        };
+    // The upgraded connections can't be reused, so a new connection is opened for each upgrade
+    if !http2 && client_upgrade.is_none() {
+      while let Some((sender, requests)) = self.connection_pool.take(&connection_key, |sender| {
+        !sender.is_closed() && sender.is_ready()
+      }) {
+        match http_proxy_kept_alive(
+          self.connection_pool.clone(),
+          connection_key.clone(),
+          sender,
+          requests,
+          proxy_request,
+          error_logger,
+        )
+        .await
+        {
+          Ok(response) => return Ok(response),
+          // The backend server has closed the connection before the request was sent,
+          // so the request is sent over another connection
+          Err(unsent_proxy_request) => proxy_request = unsent_proxy_request,
+        }
+      }
+    }
 
-        match stream.set_nodelay(true) {
-          Ok(_) => (),
-          Err(err) => {
-            if enable_health_check {
-              let mut failed_backends_write = self.failed_backends.write().await;
-              let proxy_to = proxy_to.clone();
-              let failed_attempts = failed_backends_write.get(&proxy_to);
-              failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
-            }
+    let stream = match connect_tcp_via(upstream_proxy, &addr).await {
+      Ok(stream) => stream,
+      Err(err) => {
+        if enable_health_check {
+          let mut failed_backends_write = self.failed_backends.write().await;
+          let proxy_to = proxy_to.clone();
+          let failed_attempts = failed_backends_write.get(&proxy_to);
+          failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
+        }
+        match err.kind() {
+          tokio::io::ErrorKind::ConnectionRefused
+          | tokio::io::ErrorKind::NotFound
+          | tokio::io::ErrorKind::HostUnreachable => {
+            error_logger
+              .log(&format!("Service unavailable: {}", err))
+              .await;
+            return Ok(
+              ResponseData::builder_without_request()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .build(),
+            );
+          }
+          tokio::io::ErrorKind::TimedOut => {
+            error_logger.log(&format!("Gateway timeout: {}", err)).await;
+            return Ok(
+              ResponseData::builder_without_request()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .build(),
+            );
+          }
+          _ => {
             error_logger.log(&format!("Bad gateway: {}", err)).await;
             return Ok(
               ResponseData::builder_without_request()
@@ -337,17 +591,55 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
             );
           }
         };
+      }
+    };
 
-        let failed_backends_option_borrowed = if enable_health_check {
-          Some(&*self.failed_backends)
-        } else {
-          None
-        };
+    match stream.set_nodelay(true) {
+      Ok(_) => (),
+      Err(err) => {
+        if enable_health_check {
+          let mut failed_backends_write = self.failed_backends.write().await;
+          let proxy_to = proxy_to.clone();
+          let failed_attempts = failed_backends_write.get(&proxy_to);
+          failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
+        }
+        error_logger.log(&format!("Bad gateway: {}", err)).await;
+        return Ok(
+          ResponseData::builder_without_request()
+            .status(StatusCode::BAD_GATEWAY)
+            .build(),
+        );
+      }
+    };
+
+    let failed_backends_option_borrowed = if enable_health_check {
+      Some(&*self.failed_backends)
+    } else {
+      None
+    };
+
+    if !encrypted {
+      if http2 {
+        return http2_proxy(
+          &self.http2_connections,
+          connection_key,
+          stream,
+          proxy_request,
+          error_logger,
+          proxy_to,
+          failed_backends_option_borrowed,
+        )
+        .await;
+      }
 
-        if !encrypted {
+      match client_upgrade {
+        Some(client_upgrade) => {
+          http_proxy_upgrade(stream, proxy_request, client_upgrade, error_logger).await
+        }
+        None => {
           http_proxy(
-            connections,
-            addr,
+            self.connection_pool.clone(),
+            connection_key,
             stream,
             proxy_request,
             error_logger,
@@ -355,39 +647,73 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
             failed_backends_option_borrowed,
           )
           .await
-        } else {
-          let tls_client_config = (if disable_certificate_verification {
-            rustls::ClientConfig::builder()
-              .dangerous()
-              .with_custom_certificate_verifier(Arc::new(NoServerVerifier::new()))
-          } else {
-            rustls::ClientConfig::builder().with_root_certificates(self.roots.clone())
-          })
-          .with_no_client_auth();
-          let connector = TlsConnector::from(Arc::new(tls_client_config));
-          let domain = ServerName::try_from(host)?.to_owned();
-
-          let tls_stream = match connector.connect(domain, stream).await {
-            Ok(stream) => stream,
-            Err(err) => {
-              if enable_health_check {
-                let mut failed_backends_write = self.failed_backends.write().await;
-                let proxy_to = proxy_to.clone();
-                let failed_attempts = failed_backends_write.get(&proxy_to);
-                failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
-              }
-              error_logger.log(&format!("Bad gateway: {}", err)).await;
-              return Ok(
-                ResponseData::builder_without_request()
-                  .status(StatusCode::BAD_GATEWAY)
-                  .build(),
-              );
-            }
-          };
+        }
+      }
+    } else {
+      let mut tls_client_config = (if disable_certificate_verification {
+        rustls::ClientConfig::builder()
+          .dangerous()
+          .with_custom_certificate_verifier(Arc::new(NoServerVerifier::new()))
+      } else {
+        rustls::ClientConfig::builder().with_root_certificates(self.roots.clone())
+      })
+      .with_no_client_auth();
+      if http2 {
+        tls_client_config.alpn_protocols = vec![b"h2".to_vec()];
+      }
+      let connector = TlsConnector::from(Arc::new(tls_client_config));
+      let domain = ServerName::try_from(host)?.to_owned();
+
+      let tls_stream = match connector.connect(domain, stream).await {
+        Ok(stream) => stream,
+        Err(err) => {
+          if enable_health_check {
+            let mut failed_backends_write = self.failed_backends.write().await;
+            let proxy_to = proxy_to.clone();
+            let failed_attempts = failed_backends_write.get(&proxy_to);
+            failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
+          }
+          error_logger.log(&format!("Bad gateway: {}", err)).await;
+          return Ok(
+            ResponseData::builder_without_request()
+              .status(StatusCode::BAD_GATEWAY)
+              .build(),
+          );
+        }
+      };
+
+      if http2 {
+        if tls_stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+          error_logger
+            .log("Bad gateway: the backend server doesn't support HTTP/2")
+            .await;
+          return Ok(
+            ResponseData::builder_without_request()
+              .status(StatusCode::BAD_GATEWAY)
+              .build(),
+          );
+        }
 
+        return http2_proxy(
+          &self.http2_connections,
+          connection_key,
+          tls_stream,
+          proxy_request,
+          error_logger,
+          proxy_to,
+          failed_backends_option_borrowed,
+        )
+        .await;
+      }
+
+      match client_upgrade {
+        Some(client_upgrade) => {
+          http_proxy_upgrade(tls_stream, proxy_request, client_upgrade, error_logger).await
+        }
+        None => {
           http_proxy(
-            connections,
-            addr,
+            self.connection_pool.clone(),
+            connection_key,
             tls_stream,
             proxy_request,
             error_logger,
@@ -396,8 +722,141 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
           )
           .await
         }
-      } else {
-        Ok(ResponseData::builder(request).build())
+      }
+    }
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for ReverseProxyModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let enable_health_check = config
+        .get("enableLoadBalancerHealthCheck")
+        .as_bool()
+        .unwrap_or(false);
+      let health_check_max_fails = config
+        .get("loadBalancerHealthCheckMaximumFails")
+        .as_i64()
+        .unwrap_or(3) as u64;
+      let disable_certificate_verification = config
+        .get("disableProxyCertificateVerification")
+        .as_bool()
+        .unwrap_or(false);
+      let max_retries = config.get("proxyMaxRetries").as_i64().unwrap_or(0).max(0) as u64;
+      let retry_on_yaml = config.get("proxyRetryOn");
+      let retry_on = match retry_on_yaml.as_vec() {
+        Some(retry_on) => retry_on
+          .iter()
+          .filter_map(|condition| condition.as_str())
+          .collect::<Vec<_>>(),
+        None => DEFAULT_RETRY_ON.to_vec(),
+      };
+      let try_timeout = config
+        .get("proxyTryTimeout")
+        .as_i64()
+        .map(|try_timeout| Duration::from_millis(try_timeout.max(0) as u64));
+      let upstream_proxy = match config.get("upstreamProxy").as_str() {
+        Some(upstream_proxy) => Some(UpstreamProxy::parse(upstream_proxy)?),
+        None => None,
+      };
+      let verify_response_integrity = config
+        .get("proxyVerifyResponseIntegrity")
+        .as_bool()
+        .unwrap_or(false);
+
+      let mut proxy_to = match determine_proxy_to(
+        config,
+        socket_data.encrypted,
+        &self.failed_backends,
+        enable_health_check,
+        health_check_max_fails,
+        &[],
+      )
+      .await
+      {
+        Some(proxy_to) => proxy_to,
+        None => return Ok(ResponseData::builder(request).build()),
+      };
+
+      let (hyper_request, _auth_user) = request.into_parts();
+
+      // Only the requests with idempotent methods and without a body can be safely sent again
+      if max_retries == 0 || !is_retryable_request(&hyper_request) {
+        return self
+          .proxy_request_with_timeout(
+            proxy_to,
+            hyper_request,
+            try_timeout,
+            socket_data,
+            error_logger,
+            enable_health_check,
+            disable_certificate_verification,
+            upstream_proxy.as_ref(),
+            verify_response_integrity,
+          )
+          .await;
+      }
+
+      self.retry_budget.record_request();
+      let (hyper_request_parts, _) = hyper_request.into_parts();
+      let mut tried_backends = Vec::new();
+      let mut retries = 0;
+      loop {
+        let hyper_request = Request::from_parts(
+          hyper_request_parts.clone(),
+          Empty::new().map_err(|e| match e {}).boxed(),
+        );
+        let response_data = self
+          .proxy_request_with_timeout(
+            proxy_to.clone(),
+            hyper_request,
+            try_timeout,
+            socket_data,
+            error_logger,
+            enable_health_check,
+            disable_certificate_verification,
+            upstream_proxy.as_ref(),
+            verify_response_integrity,
+          )
+          .await?;
+        let (response_data, retry_condition) = with_retry_condition(response_data);
+        if retries >= max_retries
+          || !retry_condition.is_some_and(|retry_condition| retry_on.contains(&retry_condition))
+          || !self.retry_budget.try_retry()
+        {
+          return Ok(response_data);
+        }
+
+        // The request is retried on the next backend server in the load balancing group.
+        // If all the backend servers were tried already, any of them can be chosen again.
+        tried_backends.push(proxy_to);
+        proxy_to = match determine_proxy_to(
+          config,
+          socket_data.encrypted,
+          &self.failed_backends,
+          enable_health_check,
+          health_check_max_fails,
+          &tried_backends,
+        )
+        .await
+        {
+          Some(proxy_to) => proxy_to,
+          None => return Ok(response_data),
+        };
+        retries += 1;
+        error_logger
+          .log(&format!(
+            "Retrying the proxied request (retry {} of {})",
+            retries, max_retries
+          ))
+          .await;
       }
     })
     .await
@@ -470,6 +929,7 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
         &self.failed_backends,
         enable_health_check,
         health_check_max_fails,
+        &[],
       )
       .await
       {
@@ -543,10 +1003,33 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
 
         let client_bi_stream = websocket.await?;
 
-        let (proxy_bi_stream, _) = match tokio_tungstenite::connect_async_tls_with_config(
+        // The connection is opened separately, so that the host name is resolved using the configured DNS resolver
+        let proxy_address = format!(
+          "{}:{}",
+          proxy_request_url.host().unwrap_or_default(),
+          proxy_request_url
+            .port_u16()
+            .unwrap_or(if encrypted { 443 } else { 80 })
+        );
+        let upstream_proxy = match config.get("upstreamProxy").as_str() {
+          Some(upstream_proxy) => Some(UpstreamProxy::parse(upstream_proxy)?),
+          None => None,
+        };
+        let proxy_stream = match connect_tcp_via(upstream_proxy.as_ref(), &proxy_address).await {
+          Ok(proxy_stream) => proxy_stream,
+          Err(err) => {
+            error_logger
+              .log(&format!("Cannot connect to WebSocket server: {}", err))
+              .await;
+            return Ok(());
+          }
+        };
+        proxy_stream.set_nodelay(true)?;
+
+        let (proxy_bi_stream, _) = match tokio_tungstenite::client_async_tls_with_config(
           proxy_request_url,
+          proxy_stream,
           None,
-          true,
           Some(connector),
         )
         .await
@@ -563,12 +1046,15 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
         let (mut client_sink, mut client_stream) = client_bi_stream.split();
         let (mut proxy_sink, mut proxy_stream) = proxy_bi_stream.split();
 
+        // The messages (including the ping, pong and close frames) are forwarded in both directions.
+        // When one side closes the connection, the other side is sent a close frame too.
         let client_to_proxy = async {
           while let Some(Ok(value)) = client_stream.next().await {
             if proxy_sink.send(value).await.is_err() {
               break;
             }
           }
+          proxy_sink.close().await.unwrap_or_default();
         };
 
         let proxy_to_client = async {
@@ -577,6 +1063,7 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
               break;
             }
           }
+          client_sink.close().await.unwrap_or_default();
         };
 
         tokio::pin!(client_to_proxy);
@@ -621,12 +1108,45 @@ impl ServerModuleHandlers for ReverseProxyModuleHandlers {
   }
 }
 
+// Remove the backends drained through the admin API from the load balancer backends.
+// If all the backends are drained, none of them is removed.
+fn without_drained_backends(backends: &[Yaml]) -> Vec<Yaml> {
+  let backends_not_drained = backends
+    .iter()
+    .filter(|backend| !backend.as_str().is_some_and(is_backend_drained))
+    .cloned()
+    .collect::<Vec<_>>();
+  match backends_not_drained.is_empty() {
+    true => backends.to_vec(),
+    false => backends_not_drained,
+  }
+}
+
+// Remove the backends, to which the request was already sent, from the load balancer backends.
+// If all the backends were tried, none of them is removed.
+fn without_tried_backends(backends: Vec<Yaml>, tried_backends: &[String]) -> Vec<Yaml> {
+  let backends_not_tried = backends
+    .iter()
+    .filter(|backend| {
+      !backend
+        .as_str()
+        .is_some_and(|backend| tried_backends.iter().any(|tried| tried == backend))
+    })
+    .cloned()
+    .collect::<Vec<_>>();
+  match backends_not_tried.is_empty() {
+    true => backends,
+    false => backends_not_tried,
+  }
+}
+
 async fn determine_proxy_to(
   config: &ServerConfigRoot,
   encrypted: bool,
   failed_backends: &RwLock<TtlCache<String, u64>>,
   enable_health_check: bool,
   health_check_max_fails: u64,
+  tried_backends: &[String],
 ) -> Option<String> {
   let mut proxy_to = None;
   // When the array is supplied with non-string values, the reverse proxy may have undesirable behavior
@@ -635,6 +1155,10 @@ async fn determine_proxy_to(
   if encrypted {
     let secure_proxy_to_yaml = config.get("secureProxyTo");
     if let Some(secure_proxy_to_vector) = secure_proxy_to_yaml.as_vec() {
+      let secure_proxy_to_vector = &without_tried_backends(
+        without_drained_backends(secure_proxy_to_vector),
+        tried_backends,
+      );
       if enable_health_check {
         let mut secure_proxy_to_vector = secure_proxy_to_vector.clone();
         loop {
@@ -646,11 +1170,16 @@ async fn determine_proxy_to(
               let failed_backend_fails =
                 match failed_backends_read.get(&secure_proxy_to.to_string()) {
                   Some(fails) => fails,
-                  None => break,
+                  None => {
+                    record_backend_restoration(secure_proxy_to);
+                    break;
+                  }
                 };
               if failed_backend_fails > health_check_max_fails {
+                record_backend_ejection(secure_proxy_to, failed_backend_fails);
                 secure_proxy_to_vector.remove(index);
               } else {
+                record_backend_restoration(secure_proxy_to);
                 break;
               }
             }
@@ -673,6 +1202,8 @@ async fn determine_proxy_to(
   if proxy_to.is_none() {
     let proxy_to_yaml = config.get("proxyTo");
     if let Some(proxy_to_vector) = proxy_to_yaml.as_vec() {
+      let proxy_to_vector =
+        &without_tried_backends(without_drained_backends(proxy_to_vector), tried_backends);
       if enable_health_check {
         let mut proxy_to_vector = proxy_to_vector.clone();
         loop {
@@ -683,11 +1214,16 @@ async fn determine_proxy_to(
               let failed_backends_read = failed_backends.read().await;
               let failed_backend_fails = match failed_backends_read.get(&proxy_to_str.to_string()) {
                 Some(fails) => fails,
-                None => break,
+                None => {
+                  record_backend_restoration(proxy_to_str);
+                  break;
+                }
               };
               if failed_backend_fails > health_check_max_fails {
+                record_backend_ejection(proxy_to_str, failed_backend_fails);
                 proxy_to_vector.remove(index);
               } else {
+                record_backend_restoration(proxy_to_str);
                 break;
               }
             }
@@ -711,8 +1247,8 @@ async fn determine_proxy_to(
 }
 
 async fn http_proxy(
-  connections: &RwLock<HashMap<String, SendRequest<BoxBody<Bytes, hyper::Error>>>>,
-  connect_addr: String,
+  connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  connection_key: String,
   stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
   proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
   error_logger: &ErrorLogger,
@@ -721,7 +1257,7 @@ async fn http_proxy(
 ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
   let io = TokioIo::new(stream);
 
-  let (mut sender, conn) = match hyper::client::conn::http1::handshake(io).await {
+  let (sender, conn) = match hyper::client::conn::http1::handshake(io).await {
     Ok(data) => data,
     Err(err) => {
       if let Some(failed_backends) = failed_backends {
@@ -738,37 +1274,24 @@ async fn http_proxy(
     }
   };
 
+  let mut sender = sender;
   let send_request = sender.send_request(proxy_request);
 
   let mut pinned_conn = Box::pin(conn);
   tokio::pin!(send_request);
 
-  let response;
-
-  loop {
+  let proxy_response = loop {
     tokio::select! {
       biased;
 
-       proxy_response = &mut send_request => {
-        let proxy_response = match proxy_response {
-          Ok(response) => response,
+      proxy_response = &mut send_request => {
+        match proxy_response {
+          Ok(response) => break response,
           Err(err) => {
             error_logger.log(&format!("Bad gateway: {}", err)).await;
             return Ok(ResponseData::builder_without_request().status(StatusCode::BAD_GATEWAY).build());
           }
-        };
-
-        response = ResponseData::builder_without_request()
-                  .response(proxy_response.map(|b| {
-                    b.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-                      .boxed()
-                  }))
-                  .parallel_fn(async move {
-                    pinned_conn.await.unwrap_or_default();
-                  })
-                  .build();
-
-        break;
+        }
       },
       state = &mut pinned_conn => {
         if state.is_err() {
@@ -777,24 +1300,159 @@ async fn http_proxy(
         }
       },
     };
+  };
+
+  // The connection is driven for as long as it's open, and it's returned to the pool after the response is received
+  let response = ResponseData::builder_without_request()
+    .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
+    .parallel_fn(async move {
+      tokio::join!(
+        async move {
+          pinned_conn.await.unwrap_or_default();
+        },
+        return_to_pool(connection_pool, connection_key, sender, 1)
+      );
+    })
+    .build();
+
+  Ok(response)
+}
+
+// Return the connection to the pool once the backend server can accept the next request over it,
+// which is after the response body is received in full. The connection closed meanwhile isn't returned.
+async fn return_to_pool(
+  connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  connection_key: String,
+  mut sender: SendRequest<BoxBody<Bytes, hyper::Error>>,
+  requests: u64,
+) {
+  if sender.ready().await.is_ok() {
+    connection_pool.put(connection_key, sender, requests);
+  }
+}
+
+// Check if the request is an HTTP/1.1 request for a protocol upgrade
+// Check if the request can be safely sent again. Only the requests with idempotent methods
+// and without a body are retried, since the request body is streamed and can't be replayed.
+fn is_retryable_request(request: &HyperRequest) -> bool {
+  matches!(
+    *request.method(),
+    Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+  ) && request.body().size_hint().exact() == Some(0)
+}
+
+// Determine the condition ("error", "timeout", "502", "503" or "504"), on which the proxied request can be retried.
+// The "error" and "timeout" conditions apply to the responses generated by Ferron, when the backend server couldn't be reached,
+// while the status code conditions apply to the responses sent by the backend server.
+fn with_retry_condition(response_data: ResponseData) -> (ResponseData, Option<&'static str>) {
+  let (_, _, response, status, headers, _, parallel_fn) = response_data.into_parts();
+  let retry_condition = match (&response, status) {
+    (Some(response), _) => match response.status() {
+      StatusCode::BAD_GATEWAY => Some("502"),
+      StatusCode::SERVICE_UNAVAILABLE => Some("503"),
+      StatusCode::GATEWAY_TIMEOUT => Some("504"),
+      _ => None,
+    },
+    (None, Some(StatusCode::GATEWAY_TIMEOUT)) => Some("timeout"),
+    (None, Some(StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE)) => Some("error"),
+    _ => None,
+  };
+
+  let mut response_data_builder = ResponseData::builder_without_request();
+  if let Some(response) = response {
+    response_data_builder = response_data_builder.response(response);
+  }
+  if let Some(status) = status {
+    response_data_builder = response_data_builder.status(status);
+  }
+  if let Some(headers) = headers {
+    response_data_builder = response_data_builder.headers(headers);
+  }
+  if let Some(parallel_fn) = parallel_fn {
+    response_data_builder = response_data_builder.parallel_fn(parallel_fn);
   }
+  (response_data_builder.build(), retry_condition)
+}
 
-  if !sender.is_closed() {
-    let mut rwlock_write = connections.write().await;
-    rwlock_write.insert(connect_addr, sender);
-    drop(rwlock_write);
+// Verify the integrity of the response body sent by the backend server. The truncated or corrupted response
+// is aborted instead of being passed to the client (and to the cache) as a complete response.
+fn with_integrity_verification(
+  response_data: ResponseData,
+  is_head_request: bool,
+  proxy_to: &str,
+  error_logger: &ErrorLogger,
+) -> ResponseData {
+  let (_, _, response, status, headers, _, parallel_fn) = response_data.into_parts();
+  let mut response_data_builder = ResponseData::builder_without_request();
+  if let Some(response) = response {
+    let response = match has_verifiable_body(is_head_request, response.status()) {
+      true => {
+        let (response_parts, response_body) = response.into_parts();
+        let proxy_to = proxy_to.to_string();
+        let error_logger = error_logger.clone();
+        let integrity_body = IntegrityBody::new(
+          response_body,
+          &response_parts.headers,
+          response_parts.status == StatusCode::PARTIAL_CONTENT,
+          move |reason| {
+            METRICS.increment_counter(
+              "ferron_proxy_integrity_failures_total",
+              &[("backend", &proxy_to)],
+            );
+            if let Ok(handle) = Handle::try_current() {
+              handle.spawn(async move {
+                error_logger
+                  .log(&format!(
+                    "The response from the backend server \"{}\" was aborted: {}",
+                    proxy_to, reason
+                  ))
+                  .await;
+              });
+            }
+          },
+        );
+        hyper::Response::from_parts(response_parts, integrity_body.boxed())
+      }
+      false => response,
+    };
+    response_data_builder = response_data_builder.response(response);
   }
+  if let Some(status) = status {
+    response_data_builder = response_data_builder.status(status);
+  }
+  if let Some(headers) = headers {
+    response_data_builder = response_data_builder.headers(headers);
+  }
+  if let Some(parallel_fn) = parallel_fn {
+    response_data_builder = response_data_builder.parallel_fn(parallel_fn);
+  }
+  response_data_builder.build()
+}
 
-  Ok(response)
+fn is_http_upgrade_request<B>(request: &Request<B>) -> bool {
+  request.version() == Version::HTTP_11
+    && request.headers().contains_key(header::UPGRADE)
+    && request
+      .headers()
+      .get_all(header::CONNECTION)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .any(|value| value.trim().eq_ignore_ascii_case("upgrade"))
 }
 
-async fn http_proxy_kept_alive(
-  sender: &mut SendRequest<BoxBody<Bytes, hyper::Error>>,
+// Proxy a request for an HTTP/1.1 protocol upgrade. If the backend server switches protocols,
+// the data is passed through between the client and the backend server in both directions.
+async fn http_proxy_upgrade(
+  stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
   proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
+  client_upgrade: OnUpgrade,
   error_logger: &ErrorLogger,
 ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
-  let proxy_response = match sender.send_request(proxy_request).await {
-    Ok(response) => response,
+  let io = TokioIo::new(stream);
+
+  let (mut sender, conn) = match hyper::client::conn::http1::handshake(io).await {
+    Ok(data) => data,
     Err(err) => {
       error_logger.log(&format!("Bad gateway: {}", err)).await;
       return Ok(
@@ -805,12 +1463,226 @@ async fn http_proxy_kept_alive(
     }
   };
 
+  let send_request = sender.send_request(proxy_request);
+
+  let mut pinned_conn = Box::pin(conn.with_upgrades());
+  tokio::pin!(send_request);
+
+  let mut conn_finished = false;
+  let mut proxy_response = loop {
+    tokio::select! {
+      biased;
+
+      proxy_response = &mut send_request => {
+        match proxy_response {
+          Ok(proxy_response) => break proxy_response,
+          Err(err) => {
+            error_logger.log(&format!("Bad gateway: {}", err)).await;
+            return Ok(ResponseData::builder_without_request().status(StatusCode::BAD_GATEWAY).build());
+          }
+        }
+      },
+      // The connection finishes after handing over the stream to the upgraded response, so it's polled only until then
+      state = &mut pinned_conn, if !conn_finished => {
+        if state.is_err() {
+          error_logger.log("Bad gateway: incomplete response").await;
+          return Ok(ResponseData::builder_without_request().status(StatusCode::BAD_GATEWAY).build());
+        }
+        conn_finished = true;
+      },
+    };
+  };
+
+  let parallel_fn: Pin<Box<dyn Future<Output = ()> + Send>> =
+    if proxy_response.status() == StatusCode::SWITCHING_PROTOCOLS {
+      let backend_upgrade = hyper::upgrade::on(&mut proxy_response);
+      let error_logger = error_logger.clone();
+      Box::pin(async move {
+        let conn = async move {
+          if !conn_finished {
+            pinned_conn.await.unwrap_or_default();
+          }
+        };
+        let (_, client_upgraded, backend_upgraded) =
+          tokio::join!(conn, client_upgrade, backend_upgrade);
+        match (client_upgraded, backend_upgraded) {
+          (Ok(client_upgraded), Ok(backend_upgraded)) => {
+            tokio::io::copy_bidirectional(
+              &mut TokioIo::new(client_upgraded),
+              &mut TokioIo::new(backend_upgraded),
+            )
+            .await
+            .unwrap_or_default();
+          }
+          (Err(err), _) | (_, Err(err)) => {
+            error_logger
+              .log(&format!("Cannot upgrade the proxied connection: {}", err))
+              .await;
+          }
+        }
+      })
+    } else {
+      Box::pin(async move {
+        if !conn_finished {
+          pinned_conn.await.unwrap_or_default();
+        }
+      })
+    };
+
+  Ok(
+    ResponseData::builder_without_request()
+      .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
+      .parallel_fn(parallel_fn)
+      .build(),
+  )
+}
+
+// Send a request over a pooled keep-alive connection, over which the specified number of the requests were sent.
+// If the request couldn't be sent, because the connection was closed, the request is returned,
+// so that it can be sent over another connection.
+async fn http_proxy_kept_alive(
+  connection_pool: Arc<ConnectionPool<SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  connection_key: String,
+  mut sender: SendRequest<BoxBody<Bytes, hyper::Error>>,
+  requests: u64,
+  proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
+  error_logger: &ErrorLogger,
+) -> Result<ResponseData, Request<BoxBody<Bytes, hyper::Error>>> {
+  let proxy_response = match sender.try_send_request(proxy_request).await {
+    Ok(response) => response,
+    Err(mut err) => {
+      if let Some(unsent_proxy_request) = err.take_message() {
+        return Err(unsent_proxy_request);
+      }
+      error_logger
+        .log(&format!("Bad gateway: {}", err.into_error()))
+        .await;
+      return Ok(
+        ResponseData::builder_without_request()
+          .status(StatusCode::BAD_GATEWAY)
+          .build(),
+      );
+    }
+  };
+
   let response = ResponseData::builder_without_request()
-    .response(proxy_response.map(|b| {
-      b.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
-        .boxed()
-    }))
+    .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
+    .parallel_fn(return_to_pool(
+      connection_pool,
+      connection_key,
+      sender,
+      requests + 1,
+    ))
     .build();
 
   Ok(response)
 }
+
+// Describe the error of the HTTP client, including its cause (such as the HTTP/2 stream reset reason)
+fn describe_proxy_error(err: &hyper::Error) -> String {
+  match err.source() {
+    Some(source) => format!("{}: {}", err, source),
+    None => err.to_string(),
+  }
+}
+
+async fn http2_proxy(
+  connections: &RwLock<HashMap<String, http2::SendRequest<BoxBody<Bytes, hyper::Error>>>>,
+  connection_key: String,
+  stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+  proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
+  error_logger: &ErrorLogger,
+  proxy_to: String,
+  failed_backends: Option<&tokio::sync::RwLock<TtlCache<std::string::String, u64>>>,
+) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+  let io = TokioIo::new(stream);
+
+  let (mut sender, conn) = match http2::handshake(TokioExecutor::new(), io).await {
+    Ok(data) => data,
+    Err(err) => {
+      if let Some(failed_backends) = failed_backends {
+        let mut failed_backends_write = failed_backends.write().await;
+        let failed_attempts = failed_backends_write.get(&proxy_to);
+        failed_backends_write.insert(proxy_to, failed_attempts.map_or(1, |x| x + 1));
+      }
+      error_logger
+        .log(&format!("Bad gateway: {}", describe_proxy_error(&err)))
+        .await;
+      return Ok(
+        ResponseData::builder_without_request()
+          .status(StatusCode::BAD_GATEWAY)
+          .build(),
+      );
+    }
+  };
+
+  // The connection is shared by the requests, so it's driven in the background
+  // until the backend server closes it (for example, after sending GOAWAY)
+  tokio::spawn(async move {
+    conn.await.unwrap_or_default();
+  });
+
+  let mut rwlock_write = connections.write().await;
+  rwlock_write.insert(connection_key, sender.clone());
+  drop(rwlock_write);
+
+  let proxy_response = match sender.send_request(proxy_request).await {
+    Ok(response) => response,
+    Err(err) => {
+      error_logger
+        .log(&format!("Bad gateway: {}", describe_proxy_error(&err)))
+        .await;
+      return Ok(
+        ResponseData::builder_without_request()
+          .status(StatusCode::BAD_GATEWAY)
+          .build(),
+      );
+    }
+  };
+
+  // The response body is passed through frame by frame, so the trailers (such as the gRPC status) are preserved.
+  // If the backend server resets the stream, the error is passed to the client, which resets the client's stream.
+  Ok(
+    ResponseData::builder_without_request()
+      .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
+      .build(),
+  )
+}
+
+// Send a request over a pooled HTTP/2 connection. If the request couldn't be sent,
+// because the connection is closing, the request is returned, so that it can be sent over a new connection.
+async fn http2_proxy_kept_alive(
+  sender: &mut http2::SendRequest<BoxBody<Bytes, hyper::Error>>,
+  proxy_request: Request<BoxBody<Bytes, hyper::Error>>,
+  error_logger: &ErrorLogger,
+) -> Result<ResponseData, Request<BoxBody<Bytes, hyper::Error>>> {
+  if sender.is_closed() || sender.ready().await.is_err() {
+    return Err(proxy_request);
+  }
+
+  let proxy_response = match sender.try_send_request(proxy_request).await {
+    Ok(response) => response,
+    Err(mut err) => {
+      if let Some(unsent_proxy_request) = err.take_message() {
+        return Err(unsent_proxy_request);
+      }
+      error_logger
+        .log(&format!(
+          "Bad gateway: {}",
+          describe_proxy_error(&err.into_error())
+        ))
+        .await;
+      return Ok(
+        ResponseData::builder_without_request()
+          .status(StatusCode::BAD_GATEWAY)
+          .build(),
+      );
+    }
+  };
+
+  Ok(
+    ResponseData::builder_without_request()
+      .response(proxy_response.map(|b| b.map_err(|e| std::io::Error::other(e.to_string())).boxed()))
+      .build(),
+  )
+}
@@ -3,11 +3,12 @@
 use std::env;
 use std::error::Error;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 
 use async_trait::async_trait;
 use ferron_common::{
-  ErrorLogger, HyperRequest, HyperResponse, RequestData, ResponseData, ServerConfig,
-  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData,
+  ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData,
 };
 use ferron_common::{HyperUpgraded, WithRuntime};
 use futures_util::TryStreamExt;
@@ -19,13 +20,14 @@ use hyper::{header, Response, StatusCode};
 use hyper_tungstenite::HyperWebsocket;
 use tokio::fs;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tokio::net::TcpStream;
 use tokio::runtime::Handle;
 use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::ferron_res::server_software::SERVER_SOFTWARE;
 use crate::ferron_util::cgi_response::CgiResponse;
 use crate::ferron_util::copy_move::Copier;
+use crate::ferron_util::dns_resolver;
+use crate::ferron_util::temp_files::{buffer_body, DEFAULT_BODY_MEMORY_LIMIT};
 
 pub fn server_module_init(
   _config: &ServerConfig,
@@ -370,23 +372,34 @@ async fn execute_scgi_with_environment_variables(
     );
   }
 
-  if !content_length_set {
-    environment_variables.insert("CONTENT_LENGTH".to_string(), "0".to_string());
-  }
-
   let (hyper_request, _) = request.into_parts();
+  let (_, body) = hyper_request.into_parts();
+
+  // The SCGI server needs the length of the request body before the body is sent, so the request body
+  // without the "Content-Length" header (for example, a chunked one) is buffered
+  let body_reader: Pin<Box<dyn AsyncRead + Send>> = match content_length_set {
+    true => Box::pin(StreamReader::new(
+      body.into_data_stream().map_err(std::io::Error::other),
+    )),
+    false => {
+      let buffered_body = buffer_body(body, DEFAULT_BODY_MEMORY_LIMIT, "scgi").await?;
+      environment_variables.insert(
+        "CONTENT_LENGTH".to_string(),
+        buffered_body.size().to_string(),
+      );
+      buffered_body.into_reader()
+    }
+  };
 
-  execute_scgi(hyper_request, error_logger, scgi_to, environment_variables).await
+  execute_scgi(body_reader, error_logger, scgi_to, environment_variables).await
 }
 
 async fn execute_scgi(
-  hyper_request: HyperRequest,
+  cgi_stdin_reader: Pin<Box<dyn AsyncRead + Send>>,
   error_logger: &ErrorLogger,
   scgi_to: &str,
   mut environment_variables: LinkedHashMap<String, String>,
 ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
-  let (_, body) = hyper_request.into_parts();
-
   // Insert other environment variables
   for (key, value) in env::vars_os() {
     let key_string = key.to_string_lossy().to_string();
@@ -494,12 +507,6 @@ async fn execute_scgi(
     .write_all(&environment_variables_netstring)
     .await?;
 
-  let cgi_stdin_reader = StreamReader::new(
-    body
-      .into_data_stream()
-      .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
-  );
-
   // Emulated standard input and standard output
   // SCGI doesn't support standard error
   let stdin = socket_writer;
@@ -553,10 +560,8 @@ async fn execute_scgi(
       break;
     }
     match &header.name.to_lowercase() as &str {
-      "location" => {
-        if !(300..=399).contains(&status_code) {
-          status_code = 302;
-        }
+      "location" if !(300..=399).contains(&status_code) => {
+        status_code = 302;
       }
       "status" => {
         let header_value_cow = String::from_utf8_lossy(header.value);
@@ -609,7 +614,7 @@ async fn connect_tcp(
   ),
   tokio::io::Error,
 > {
-  let socket = TcpStream::connect(addr).await?;
+  let socket = dns_resolver::connect_tcp(addr).await?;
   socket.set_nodelay(true)?;
 
   let (socket_reader_set, socket_writer_set) = tokio::io::split(socket);
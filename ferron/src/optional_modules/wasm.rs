@@ -0,0 +1,434 @@
+// The "wasm" module runs the WebAssembly plugins implementing the proxy-wasm ABI, which can inspect and modify
+// the request and response headers, and send the local responses.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogLevel, ErrorLogger, HyperResponse, RequestData, ResponseData, ServerConfig,
+  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{header, Response};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::blocking_budget::spawn_blocking_budgeted;
+use crate::ferron_util::proxy_wasm::{
+  apply_request_header_pairs, apply_response_header_pairs, request_header_pairs,
+  response_header_pairs, LocalResponse, StreamProperties, WasmPlugin,
+};
+
+// The plugins are identified by the path of the WebAssembly module and the plugin configuration
+type PluginKey = (String, String);
+
+// Get the path and the plugin configuration of the plugin from the entry of the "wasmModules" property
+fn parse_plugin_key(plugin_yaml: &Yaml) -> Option<PluginKey> {
+  let path = plugin_yaml["path"].as_str()?;
+  let configuration = match &plugin_yaml["configuration"] {
+    Yaml::BadValue => "",
+    configuration => configuration.as_str()?,
+  };
+  Some((path.to_string(), configuration.to_string()))
+}
+
+// The instances of a plugin. The requests are spread between the instances,
+// so the requests processed by the plugin at the same time don't wait for a single instance.
+struct PluginPool {
+  instances: Vec<Arc<Mutex<WasmPlugin>>>,
+  next_instance: AtomicUsize,
+}
+
+impl PluginPool {
+  // Get the instance processing a new request. An idle instance is preferred, and otherwise the instances are used in turns.
+  fn instance(&self) -> Arc<Mutex<WasmPlugin>> {
+    let first_instance = self.next_instance.fetch_add(1, Ordering::Relaxed);
+    let instance_count = self.instances.len();
+    let mut instances =
+      (0..instance_count).map(|offset| &self.instances[(first_instance + offset) % instance_count]);
+    instances
+      .find(|instance| instance.try_lock().is_ok())
+      .unwrap_or(&self.instances[first_instance % instance_count])
+      .clone()
+  }
+}
+
+// Load the plugins configured in the "wasmModules" property. Each plugin has an instance for each worker thread.
+fn load_plugins(
+  config: &Yaml,
+  engine: &wasmi::Engine,
+  plugins: &mut HashMap<PluginKey, Arc<PluginPool>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  if let Some(plugins_yaml) = config["wasmModules"].as_vec() {
+    for plugin_yaml in plugins_yaml.iter() {
+      let plugin_key = parse_plugin_key(plugin_yaml)
+        .ok_or(anyhow::anyhow!("Invalid WebAssembly module configuration"))?;
+      if plugins.contains_key(&plugin_key) {
+        continue;
+      }
+      let (path, configuration) = &plugin_key;
+      let wasm = std::fs::read(path).map_err(|err| {
+        anyhow::anyhow!("Cannot read the WebAssembly module \"{}\": {}", path, err)
+      })?;
+      let plugin_name = Path::new(path)
+        .file_stem()
+        .map_or(path.clone(), |file_stem| {
+          file_stem.to_string_lossy().to_string()
+        });
+      let load_error =
+        |err| anyhow::anyhow!("Cannot load the WebAssembly module \"{}\": {}", path, err);
+      let module = WasmPlugin::compile(engine, &wasm).map_err(load_error)?;
+      let instance_count = std::thread::available_parallelism().map_or(1, |count| count.get());
+      let mut instances = Vec::new();
+      for _ in 0..instance_count {
+        let plugin = WasmPlugin::instantiate(engine, &module, &plugin_name, configuration)
+          .map_err(load_error)?;
+        instances.push(Arc::new(Mutex::new(plugin)));
+      }
+      plugins.insert(
+        plugin_key,
+        Arc::new(PluginPool {
+          instances,
+          next_instance: AtomicUsize::new(0),
+        }),
+      );
+    }
+  }
+  Ok(())
+}
+
+pub fn server_module_init(
+  config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  let engine = WasmPlugin::engine();
+  let mut plugins = HashMap::new();
+  load_plugins(&config["global"], &engine, &mut plugins)?;
+  if let Some(hosts) = config["hosts"].as_vec() {
+    for host_yaml in hosts.iter() {
+      load_plugins(host_yaml, &engine, &mut plugins)?;
+      if let Some(locations) = host_yaml["locations"].as_vec() {
+        for location_yaml in locations.iter() {
+          load_plugins(location_yaml, &engine, &mut plugins)?;
+        }
+      }
+    }
+  }
+
+  Ok(Box::new(WasmModule::new(Arc::new(plugins))))
+}
+
+struct WasmModule {
+  plugins: Arc<HashMap<PluginKey, Arc<PluginPool>>>,
+}
+
+impl WasmModule {
+  fn new(plugins: Arc<HashMap<PluginKey, Arc<PluginPool>>>) -> Self {
+    WasmModule { plugins }
+  }
+}
+
+impl ServerModule for WasmModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(WasmModuleHandlers {
+      handle,
+      plugins: self.plugins.clone(),
+      streams: Vec::new(),
+      properties: StreamProperties::default(),
+      error_logger: None,
+    })
+  }
+}
+
+// Run the plugin's callback, and log the messages logged by the plugin. The callback runs in the blocking thread pool,
+// within the module's blocking thread budget, so the long-running callbacks don't block the worker threads.
+async fn run_plugin<R: Send + 'static>(
+  plugin: Arc<Mutex<WasmPlugin>>,
+  error_logger: Option<&ErrorLogger>,
+  callback: impl FnOnce(&mut WasmPlugin) -> R + Send + 'static,
+) -> Result<R, Box<dyn Error + Send + Sync>> {
+  let (result, log_messages) = spawn_blocking_budgeted("wasm", move || {
+    let mut plugin = plugin.lock().map_err(|_| {
+      anyhow::anyhow!("The WebAssembly plugin is unusable after a previous failure")
+    })?;
+    let result = callback(&mut plugin);
+    Ok::<_, Box<dyn Error + Send + Sync>>((result, plugin.take_log_messages()))
+  })
+  .await??;
+  if let Some(error_logger) = error_logger {
+    for (level, message) in log_messages {
+      error_logger.log_with_level(level, &message).await;
+    }
+  }
+  Ok(result)
+}
+
+// Build the response from the local response sent by the plugin
+fn local_response_to_hyper_response(
+  local_response: LocalResponse,
+) -> Result<Response<Bytes>, Box<dyn Error + Send + Sync>> {
+  let mut response = Response::builder().status(local_response.status);
+  if let Some(headers) = response.headers_mut() {
+    *headers = local_response.headers;
+    if !local_response.body.is_empty() && !headers.contains_key(header::CONTENT_TYPE) {
+      headers.insert(header::CONTENT_TYPE, "text/plain".parse()?);
+    }
+  }
+  Ok(response.body(Bytes::from(local_response.body))?)
+}
+
+struct WasmModuleHandlers {
+  handle: Handle,
+  plugins: Arc<HashMap<PluginKey, Arc<PluginPool>>>,
+  // The plugin instances processing the request, with the IDs of the request's contexts
+  streams: Vec<(Arc<Mutex<WasmPlugin>>, i32)>,
+  properties: StreamProperties,
+  error_logger: Option<ErrorLogger>,
+}
+
+impl WasmModuleHandlers {
+  // Notify the plugins that the request is complete
+  async fn finish_streams(&mut self) {
+    for (plugin, context_id) in std::mem::take(&mut self.streams) {
+      match run_plugin(plugin, self.error_logger.as_ref(), move |plugin| {
+        plugin.finish_stream(context_id)
+      })
+      .await
+      {
+        Ok(Ok(())) => (),
+        Ok(Err(err)) | Err(err) => {
+          if let Some(error_logger) = &self.error_logger {
+            error_logger
+              .log_with_level(ErrorLogLevel::Warn, &err.to_string())
+              .await;
+          }
+        }
+      }
+    }
+  }
+}
+
+impl Drop for WasmModuleHandlers {
+  fn drop(&mut self) {
+    // The contexts of the requests whose responses weren't processed by the plugins are deleted here
+    let streams = std::mem::take(&mut self.streams);
+    if streams.is_empty() {
+      return;
+    }
+    self.handle.spawn(spawn_blocking_budgeted("wasm", move || {
+      for (plugin, context_id) in streams {
+        if let Ok(mut plugin) = plugin.lock() {
+          let _ = plugin.finish_stream(context_id);
+          plugin.take_log_messages();
+        }
+      }
+    }));
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for WasmModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    mut request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let plugins = match config.get("wasmModules").as_vec() {
+        Some(plugins_yaml) => plugins_yaml
+          .iter()
+          .filter_map(parse_plugin_key)
+          .filter_map(|plugin_key| self.plugins.get(&plugin_key))
+          .map(|plugin_pool| plugin_pool.instance())
+          .collect::<Vec<_>>(),
+        None => Vec::new(),
+      };
+      if plugins.is_empty() {
+        return Ok(ResponseData::builder(request).build());
+      }
+      self.error_logger = Some(error_logger.clone());
+
+      let scheme = match socket_data.encrypted {
+        true => "https",
+        false => "http",
+      };
+      let hyper_request = request.get_hyper_request();
+      self.properties = StreamProperties {
+        method: hyper_request.method().to_string(),
+        path: hyper_request
+          .uri()
+          .path_and_query()
+          .map_or(hyper_request.uri().path(), |path_and_query| {
+            path_and_query.as_str()
+          })
+          .to_string(),
+        host: hyper_request
+          .headers()
+          .get(header::HOST)
+          .and_then(|host| host.to_str().ok())
+          .unwrap_or_default()
+          .to_string(),
+        scheme: scheme.to_string(),
+        protocol: format!("{:?}", hyper_request.version()),
+        source_address: Some(socket_data.remote_addr),
+        destination_address: Some(socket_data.local_addr),
+        response_code: None,
+      };
+      let mut header_pairs = request_header_pairs(
+        hyper_request.method(),
+        hyper_request.uri(),
+        hyper_request.headers(),
+        scheme,
+      );
+
+      // The plugins process the request headers in the configured order. A local response stops the processing.
+      let mut local_response = None;
+      for plugin in plugins {
+        let properties = self.properties.clone();
+        let mut plugin_header_pairs = std::mem::take(&mut header_pairs);
+        let result = run_plugin(plugin.clone(), Some(error_logger), move |wasm_plugin| {
+          let context_id = wasm_plugin.create_stream(properties.clone())?;
+          let local_response =
+            wasm_plugin.on_request_headers(context_id, properties, &mut plugin_header_pairs, true);
+          Ok::<_, Box<dyn Error + Send + Sync>>((context_id, local_response, plugin_header_pairs))
+        })
+        .await??;
+        let (context_id, plugin_local_response, plugin_header_pairs) = result;
+        header_pairs = plugin_header_pairs;
+        self.streams.push((plugin, context_id));
+        local_response = plugin_local_response?;
+        if local_response.is_some() {
+          break;
+        }
+      }
+
+      let hyper_request = request.get_mut_hyper_request();
+      let mut method = hyper_request.method().clone();
+      let mut uri = hyper_request.uri().clone();
+      let mut headers = hyper_request.headers().clone();
+      apply_request_header_pairs(&header_pairs, &mut method, &mut uri, &mut headers)?;
+      *hyper_request.method_mut() = method;
+      *hyper_request.uri_mut() = uri;
+      *hyper_request.headers_mut() = headers;
+
+      match local_response {
+        Some(local_response) => Ok(
+          ResponseData::builder(request)
+            .validated_response(local_response_to_hyper_response(local_response)?)
+            .build(),
+        ),
+        None => Ok(ResponseData::builder(request).build()),
+      }
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    if self.streams.is_empty() {
+      return Ok(response);
+    }
+    let (mut response_parts, response_body) = response.into_parts();
+    let mut header_pairs = response_header_pairs(response_parts.status, &response_parts.headers);
+    let mut properties = self.properties.clone();
+    properties.response_code = Some(response_parts.status.as_u16());
+
+    // The plugins process the response headers in the reverse order, like the filters in Envoy
+    let mut result = Ok(None);
+    for (plugin, context_id) in self.streams.iter().rev() {
+      let context_id = *context_id;
+      let properties = properties.clone();
+      let mut plugin_header_pairs = std::mem::take(&mut header_pairs);
+      result = run_plugin(plugin.clone(), self.error_logger.as_ref(), move |plugin| {
+        let local_response =
+          plugin.on_response_headers(context_id, properties, &mut plugin_header_pairs, false);
+        (local_response, plugin_header_pairs)
+      })
+      .await
+      .and_then(|(local_response, plugin_header_pairs)| {
+        header_pairs = plugin_header_pairs;
+        local_response
+      });
+      if !matches!(result, Ok(None)) {
+        break;
+      }
+    }
+    self.finish_streams().await;
+
+    match result? {
+      Some(local_response) => Ok(
+        local_response_to_hyper_response(local_response)?
+          .map(|body| Full::new(body).map_err(|e| match e {}).boxed()),
+      ),
+      None => {
+        apply_response_header_pairs(
+          &header_pairs,
+          &mut response_parts.status,
+          &mut response_parts.headers,
+        )?;
+        Ok(Response::from_parts(response_parts, response_body))
+      }
+    }
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
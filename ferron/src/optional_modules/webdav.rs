@@ -0,0 +1,1169 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ferron_common::{
+  ErrorLogger, HyperRequest, HyperResponse, RequestData, ResponseData, ServerConfig,
+  ServerConfigRoot, ServerModule, ServerModuleHandlers, SocketData,
+};
+use ferron_common::{HyperUpgraded, WithRuntime};
+use http_body_util::{BodyExt, Empty, Full, LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper::{header, HeaderMap, Response, StatusCode};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Handle;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::ferron_util::url_sanitizer::sanitize_url;
+use crate::ferron_util::webdav_copy::copy_resource;
+use crate::ferron_util::webdav_locks::{is_same_or_descendant, WebDavLock, WebDavLocks};
+use crate::ferron_util::xml::{escape_xml, parse_xml, XmlElement};
+
+// The maximum size of the XML request bodies (for PROPFIND, PROPPATCH and LOCK requests)
+const MAX_XML_BODY_SIZE: usize = 1048576;
+
+// The lock timeout used when the client doesn't request one, and the maximum lock timeout
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(3600);
+const MAX_LOCK_TIMEOUT: Duration = Duration::from_secs(86400);
+
+const ALLOWED_METHODS: &str =
+  "OPTIONS, GET, HEAD, POST, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+// The dead properties set with PROPPATCH, keyed by the resource key. The properties are kept in memory.
+type DeadProperties = HashMap<String, Vec<XmlElement>>;
+
+pub fn server_module_init(
+  _config: &ServerConfig,
+) -> Result<Box<dyn ServerModule + Send + Sync>, Box<dyn Error + Send + Sync>> {
+  Ok(Box::new(WebDavModule::new()))
+}
+
+struct WebDavModule {
+  locks: Arc<Mutex<WebDavLocks>>,
+  properties: Arc<RwLock<DeadProperties>>,
+}
+
+impl WebDavModule {
+  fn new() -> Self {
+    WebDavModule {
+      locks: Arc::new(Mutex::new(WebDavLocks::new())),
+      properties: Arc::new(RwLock::new(HashMap::new())),
+    }
+  }
+}
+
+impl ServerModule for WebDavModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(WebDavModuleHandlers {
+      locks: self.locks.clone(),
+      properties: self.properties.clone(),
+      handle,
+    })
+  }
+}
+
+struct WebDavModuleHandlers {
+  locks: Arc<Mutex<WebDavLocks>>,
+  properties: Arc<RwLock<DeadProperties>>,
+  handle: Handle,
+}
+
+// A resource in a WebDAV share
+struct WebDavResource {
+  // The decoded URL path without the trailing slash ("" for the root)
+  url_path: String,
+  file_path: PathBuf,
+  // The key identifying the resource in the lock table and the property storage
+  key: String,
+}
+
+// The WebDAV share configuration used for a request
+struct WebDavShare {
+  wwwroot: PathBuf,
+  wwwroot_key: String,
+  webdav_paths: Vec<String>,
+}
+
+impl WebDavShare {
+  fn resource(&self, url_path: &str) -> WebDavResource {
+    WebDavResource {
+      url_path: url_path.to_string(),
+      file_path: self.wwwroot.join(url_path.trim_start_matches('/')),
+      key: format!("{}{}", self.wwwroot_key, url_path),
+    }
+  }
+
+  fn is_webdav_path(&self, url_path: &str) -> bool {
+    self
+      .webdav_paths
+      .iter()
+      .any(|webdav_path| is_same_or_descendant(url_path, webdav_path))
+  }
+
+  // Resolve the URL path of a lock, so that it can be used in the "lockroot" element
+  fn lock_url_path<'a>(&self, lock: &'a WebDavLock) -> &'a str {
+    lock
+      .path
+      .strip_prefix(&self.wwwroot_key)
+      .unwrap_or(&lock.path)
+  }
+}
+
+// Normalize the decoded URL path by removing the trailing slashes. Returns None if the path is invalid.
+fn normalize_url_path(url_path: &str) -> Option<String> {
+  let url_path = url_path.trim_end_matches('/');
+  if url_path.split('/').any(|component| component == "..") {
+    None
+  } else {
+    Some(url_path.to_string())
+  }
+}
+
+fn empty_response(status: StatusCode, headers: HeaderMap) -> ResponseData {
+  let mut response = Response::builder()
+    .status(status)
+    .body(Empty::new().map_err(|e| match e {}).boxed())
+    .unwrap_or_default();
+  response.headers_mut().extend(headers);
+  ResponseData::builder_without_request()
+    .response(response)
+    .build()
+}
+
+fn error_response(status: StatusCode) -> ResponseData {
+  ResponseData::builder_without_request()
+    .status(status)
+    .build()
+}
+
+fn xml_response(status: StatusCode, headers: HeaderMap, xml: String) -> ResponseData {
+  let mut response = Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+    .body(
+      Full::new(Bytes::from(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{}",
+        xml
+      )))
+      .map_err(|e| match e {})
+      .boxed(),
+    )
+    .unwrap_or_default();
+  response.headers_mut().extend(headers);
+  ResponseData::builder_without_request()
+    .response(response)
+    .build()
+}
+
+fn multistatus_response(responses: Vec<String>) -> ResponseData {
+  xml_response(
+    StatusCode::MULTI_STATUS,
+    HeaderMap::new(),
+    format!(
+      "<D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+      responses.join("")
+    ),
+  )
+}
+
+// Read the XML request body. Returns None for an empty body.
+async fn read_xml_body(request: HyperRequest) -> Result<Option<XmlElement>, ResponseData> {
+  let body = match Limited::new(request.into_body(), MAX_XML_BODY_SIZE)
+    .collect()
+    .await
+  {
+    Ok(body) => body.to_bytes(),
+    Err(err) => match err.downcast_ref::<LengthLimitError>() {
+      Some(_) => return Err(error_response(StatusCode::PAYLOAD_TOO_LARGE)),
+      None => return Err(error_response(StatusCode::BAD_REQUEST)),
+    },
+  };
+  if body.is_empty() {
+    return Ok(None);
+  }
+  match std::str::from_utf8(&body)
+    .ok()
+    .and_then(|body| parse_xml(body).ok())
+  {
+    Some(root) => Ok(Some(root)),
+    None => Err(error_response(StatusCode::BAD_REQUEST)),
+  }
+}
+
+// Obtain the lock tokens submitted in the "If" header
+fn get_submitted_lock_tokens(headers: &HeaderMap) -> Vec<String> {
+  let mut tokens = Vec::new();
+  if let Some(if_header) = headers.get("If").and_then(|value| value.to_str().ok()) {
+    let mut remaining = if_header;
+    while let Some(token_start) = remaining.find("<opaquelocktoken:") {
+      remaining = &remaining[token_start + 1..];
+      match remaining.find('>') {
+        Some(token_end) => {
+          tokens.push(remaining[..token_end].to_string());
+          remaining = &remaining[token_end..];
+        }
+        None => break,
+      }
+    }
+  }
+  tokens
+}
+
+// Parse the "Timeout" header, using the first timeout the client asked for
+fn get_lock_timeout(headers: &HeaderMap) -> Duration {
+  let timeout = headers
+    .get("Timeout")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.split(',').next())
+    .map(|value| value.trim());
+  match timeout {
+    Some("Infinite") => MAX_LOCK_TIMEOUT,
+    Some(timeout) => match timeout
+      .strip_prefix("Second-")
+      .and_then(|seconds| seconds.parse::<u64>().ok())
+    {
+      Some(seconds) => Duration::from_secs(seconds).min(MAX_LOCK_TIMEOUT),
+      None => DEFAULT_LOCK_TIMEOUT,
+    },
+    None => DEFAULT_LOCK_TIMEOUT,
+  }
+}
+
+// Encode the URL path for use in the "href" elements
+fn encode_href(url_path: &str, is_collection: bool) -> String {
+  let mut href = url_path
+    .split('/')
+    .map(|component| urlencoding::encode(component).to_string())
+    .collect::<Vec<_>>()
+    .join("/");
+  if is_collection || href.is_empty() {
+    href.push('/');
+  }
+  escape_xml(&href)
+}
+
+fn format_http_date(time: SystemTime) -> String {
+  let datetime: DateTime<Utc> = time.into();
+  datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn format_lock_discovery(share: &WebDavShare, locks: &[WebDavLock]) -> String {
+  let active_locks = locks
+    .iter()
+    .map(|lock| {
+      format!(
+        "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope><D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+        match lock.exclusive {
+          true => "<D:exclusive/>",
+          false => "<D:shared/>",
+        },
+        match lock.depth_infinity {
+          true => "infinity",
+          false => "0",
+        },
+        match &lock.owner {
+          Some(owner) => format!("<D:owner>{}</D:owner>", owner),
+          None => String::new(),
+        },
+        lock.timeout.as_secs(),
+        escape_xml(&lock.token),
+        encode_href(share.lock_url_path(lock), false)
+      )
+    })
+    .collect::<String>();
+  format!("<D:lockdiscovery>{}</D:lockdiscovery>", active_locks)
+}
+
+async fn remove_resource(path: &Path) -> Result<(), std::io::Error> {
+  if fs::symlink_metadata(path).await?.is_dir() {
+    fs::remove_dir_all(path).await
+  } else {
+    fs::remove_file(path).await
+  }
+}
+
+async fn parent_is_directory(path: &Path) -> bool {
+  match path.parent() {
+    Some(parent) => fs::metadata(parent)
+      .await
+      .is_ok_and(|metadata| metadata.is_dir()),
+    None => false,
+  }
+}
+
+impl WebDavModuleHandlers {
+  // Check if the resource can be modified with the lock tokens submitted by the client
+  async fn can_modify(
+    &self,
+    resource: &WebDavResource,
+    headers: &HeaderMap,
+    include_descendants: bool,
+  ) -> bool {
+    self.locks.lock().await.can_modify(
+      &resource.key,
+      &get_submitted_lock_tokens(headers),
+      include_descendants,
+    )
+  }
+
+  // Generate the live properties of a resource, as pairs of the property name and the property XML
+  async fn live_properties(
+    &self,
+    share: &WebDavShare,
+    resource: &WebDavResource,
+    metadata: &Metadata,
+  ) -> Vec<(&'static str, String)> {
+    let mut properties = Vec::new();
+    let is_collection = metadata.is_dir();
+
+    if let Ok(created) = metadata.created().or_else(|_| metadata.modified()) {
+      let datetime: DateTime<Utc> = created.into();
+      properties.push((
+        "creationdate",
+        format!(
+          "<D:creationdate>{}</D:creationdate>",
+          datetime.format("%Y-%m-%dT%H:%M:%SZ")
+        ),
+      ));
+    }
+    properties.push((
+      "displayname",
+      format!(
+        "<D:displayname>{}</D:displayname>",
+        escape_xml(resource.url_path.rsplit('/').next().unwrap_or_default())
+      ),
+    ));
+    if !is_collection {
+      properties.push((
+        "getcontentlength",
+        format!(
+          "<D:getcontentlength>{}</D:getcontentlength>",
+          metadata.len()
+        ),
+      ));
+      properties.push((
+        "getcontenttype",
+        format!(
+          "<D:getcontenttype>{}</D:getcontenttype>",
+          escape_xml(
+            new_mime_guess::from_path(&resource.file_path)
+              .first_raw()
+              .unwrap_or("application/octet-stream")
+          )
+        ),
+      ));
+    }
+    if let Ok(modified) = metadata.modified() {
+      if !is_collection {
+        let modified_seconds = modified
+          .duration_since(SystemTime::UNIX_EPOCH)
+          .map(|duration| duration.as_secs())
+          .unwrap_or_default();
+        properties.push((
+          "getetag",
+          format!(
+            "<D:getetag>\"{:x}-{:x}\"</D:getetag>",
+            metadata.len(),
+            modified_seconds
+          ),
+        ));
+      }
+      properties.push((
+        "getlastmodified",
+        format!(
+          "<D:getlastmodified>{}</D:getlastmodified>",
+          format_http_date(modified)
+        ),
+      ));
+    }
+    properties.push((
+      "resourcetype",
+      match is_collection {
+        true => "<D:resourcetype><D:collection/></D:resourcetype>".to_string(),
+        false => "<D:resourcetype/>".to_string(),
+      },
+    ));
+    properties.push((
+      "supportedlock",
+      "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry><D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>".to_string(),
+    ));
+    let locks = self.locks.lock().await.get_locks(&resource.key);
+    properties.push(("lockdiscovery", format_lock_discovery(share, &locks)));
+
+    properties
+  }
+
+  // Generate a "response" element of the PROPFIND multistatus response
+  async fn propfind_response(
+    &self,
+    share: &WebDavShare,
+    resource: &WebDavResource,
+    metadata: &Metadata,
+    request: Option<&XmlElement>,
+  ) -> String {
+    let live_properties = self.live_properties(share, resource, metadata).await;
+    let dead_properties = self
+      .properties
+      .read()
+      .await
+      .get(&resource.key)
+      .cloned()
+      .unwrap_or_default();
+
+    let mut found = Vec::new();
+    let mut not_found = Vec::new();
+    match request.and_then(|request| {
+      request
+        .children
+        .iter()
+        .find(|child| child.namespace == "DAV:")
+    }) {
+      Some(mode) if mode.name == "propname" => {
+        for (name, _) in live_properties {
+          found.push(format!("<D:{}/>", name));
+        }
+        for property in dead_properties {
+          found.push(
+            XmlElement {
+              text: String::new(),
+              children: Vec::new(),
+              ..property
+            }
+            .to_xml_string(),
+          );
+        }
+      }
+      Some(mode) if mode.name == "prop" => {
+        for requested_property in mode.children.iter() {
+          let live_property = match requested_property.namespace == "DAV:" {
+            true => live_properties
+              .iter()
+              .find(|(name, _)| *name == requested_property.name),
+            false => None,
+          };
+          if let Some((_, property)) = live_property {
+            found.push(property.clone());
+          } else if let Some(property) = dead_properties
+            .iter()
+            .find(|property| property.is(&requested_property.namespace, &requested_property.name))
+          {
+            found.push(property.to_xml_string());
+          } else {
+            not_found.push(
+              XmlElement {
+                text: String::new(),
+                children: Vec::new(),
+                ..requested_property.clone()
+              }
+              .to_xml_string(),
+            );
+          }
+        }
+      }
+      _ => {
+        // The "allprop" request, which is also used when the request body is empty
+        for (_, property) in live_properties {
+          found.push(property);
+        }
+        for property in dead_properties {
+          found.push(property.to_xml_string());
+        }
+      }
+    }
+
+    let mut propstats = Vec::new();
+    for (properties, status) in [(found, StatusCode::OK), (not_found, StatusCode::NOT_FOUND)] {
+      if !properties.is_empty() {
+        propstats.push(format!(
+          "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+          properties.join(""),
+          status
+        ));
+      }
+    }
+    format!(
+      "<D:response><D:href>{}</D:href>{}</D:response>",
+      encode_href(&resource.url_path, metadata.is_dir()),
+      propstats.join("")
+    )
+  }
+
+  async fn handle_propfind(
+    &self,
+    share: &WebDavShare,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let depth = request
+      .headers()
+      .get("Depth")
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or("infinity")
+      .to_string();
+    let include_children = match depth.as_str() {
+      "0" => false,
+      "1" => true,
+      _ => {
+        // Depth-infinity PROPFIND requests aren't supported, since they can be very expensive
+        return Ok(xml_response(
+          StatusCode::FORBIDDEN,
+          HeaderMap::new(),
+          "<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>".to_string(),
+        ));
+      }
+    };
+
+    let body = match read_xml_body(request).await {
+      Ok(body) => body,
+      Err(response) => return Ok(response),
+    };
+    if body
+      .as_ref()
+      .is_some_and(|body| !body.is("DAV:", "propfind"))
+    {
+      return Ok(error_response(StatusCode::BAD_REQUEST));
+    }
+
+    let metadata = match fs::metadata(&resource.file_path).await {
+      Ok(metadata) => metadata,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        return Ok(error_response(StatusCode::NOT_FOUND))
+      }
+      Err(err) => Err(err)?,
+    };
+
+    let mut responses = vec![
+      self
+        .propfind_response(share, &resource, &metadata, body.as_ref())
+        .await,
+    ];
+    if include_children && metadata.is_dir() {
+      let mut entries = fs::read_dir(&resource.file_path).await?;
+      while let Some(entry) = entries.next_entry().await? {
+        let child_url_path = format!(
+          "{}/{}",
+          resource.url_path,
+          entry.file_name().to_string_lossy()
+        );
+        if let Ok(child_metadata) = fs::metadata(entry.path()).await {
+          responses.push(
+            self
+              .propfind_response(
+                share,
+                &share.resource(&child_url_path),
+                &child_metadata,
+                body.as_ref(),
+              )
+              .await,
+          );
+        }
+      }
+    }
+
+    Ok(multistatus_response(responses))
+  }
+
+  async fn handle_proppatch(
+    &self,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    if !self.can_modify(&resource, request.headers(), false).await {
+      return Ok(error_response(StatusCode::LOCKED));
+    }
+    let body = match read_xml_body(request).await {
+      Ok(Some(body)) if body.is("DAV:", "propertyupdate") => body,
+      Ok(_) => return Ok(error_response(StatusCode::BAD_REQUEST)),
+      Err(response) => return Ok(response),
+    };
+    let metadata = match fs::metadata(&resource.file_path).await {
+      Ok(metadata) => metadata,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        return Ok(error_response(StatusCode::NOT_FOUND))
+      }
+      Err(err) => Err(err)?,
+    };
+
+    // The instructions are processed in the document order
+    let mut instructions = Vec::new();
+    for instruction in body.children.iter() {
+      let is_set = match (instruction.namespace.as_str(), instruction.name.as_str()) {
+        ("DAV:", "set") => true,
+        ("DAV:", "remove") => false,
+        _ => continue,
+      };
+      if let Some(prop) = instruction.child("DAV:", "prop") {
+        for property in prop.children.iter() {
+          instructions.push((is_set, property));
+        }
+      }
+    }
+
+    // The DAV: properties are protected, and if any of the instructions fails, none of them are applied
+    let failed = instructions
+      .iter()
+      .any(|(_, property)| property.namespace == "DAV:");
+    if !failed {
+      let mut properties = self.properties.write().await;
+      let resource_properties = properties.entry(resource.key.clone()).or_default();
+      for (is_set, property) in instructions.iter() {
+        resource_properties
+          .retain(|existing_property| !existing_property.is(&property.namespace, &property.name));
+        if *is_set {
+          resource_properties.push((*property).clone());
+        }
+      }
+      if resource_properties.is_empty() {
+        properties.remove(&resource.key);
+      }
+    }
+
+    let propstats = instructions
+      .iter()
+      .map(|(_, property)| {
+        let status = match (failed, property.namespace == "DAV:") {
+          (false, _) => StatusCode::OK,
+          (true, true) => StatusCode::FORBIDDEN,
+          (true, false) => StatusCode::FAILED_DEPENDENCY,
+        };
+        format!(
+          "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+          XmlElement {
+            text: String::new(),
+            children: Vec::new(),
+            ..(*property).clone()
+          }
+          .to_xml_string(),
+          status
+        )
+      })
+      .collect::<String>();
+
+    Ok(multistatus_response(vec![format!(
+      "<D:response><D:href>{}</D:href>{}</D:response>",
+      encode_href(&resource.url_path, metadata.is_dir()),
+      propstats
+    )]))
+  }
+
+  async fn handle_mkcol(
+    &self,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    if !self.can_modify(&resource, request.headers(), false).await {
+      return Ok(error_response(StatusCode::LOCKED));
+    }
+    // Request bodies for MKCOL aren't supported
+    match read_xml_body(request).await {
+      Ok(None) => (),
+      Ok(Some(_)) | Err(_) => return Ok(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE)),
+    }
+    if fs::symlink_metadata(&resource.file_path).await.is_ok() {
+      return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    if !parent_is_directory(&resource.file_path).await {
+      return Ok(error_response(StatusCode::CONFLICT));
+    }
+    fs::create_dir(&resource.file_path).await?;
+    Ok(empty_response(StatusCode::CREATED, HeaderMap::new()))
+  }
+
+  async fn handle_put(
+    &self,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    if !self.can_modify(&resource, request.headers(), false).await {
+      return Ok(error_response(StatusCode::LOCKED));
+    }
+    let existed = match fs::metadata(&resource.file_path).await {
+      Ok(metadata) if metadata.is_dir() => {
+        return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED))
+      }
+      Ok(_) => true,
+      Err(_) => false,
+    };
+    if !parent_is_directory(&resource.file_path).await {
+      return Ok(error_response(StatusCode::CONFLICT));
+    }
+
+    // Stream the request body into the file, so that large uploads aren't buffered in memory
+    let mut file = fs::File::create(&resource.file_path).await?;
+    let mut body = request.into_body();
+    while let Some(frame) = body.frame().await {
+      if let Ok(data) = frame?.into_data() {
+        file.write_all(&data).await?;
+      }
+    }
+    file.flush().await?;
+
+    Ok(empty_response(
+      match existed {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+      },
+      HeaderMap::new(),
+    ))
+  }
+
+  async fn handle_delete(
+    &self,
+    share: &WebDavShare,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    if share.webdav_paths.contains(&resource.url_path) {
+      // The root of the WebDAV share can't be deleted
+      return Ok(error_response(StatusCode::FORBIDDEN));
+    }
+    if !self.can_modify(&resource, request.headers(), true).await {
+      return Ok(error_response(StatusCode::LOCKED));
+    }
+    match remove_resource(&resource.file_path).await {
+      Ok(_) => (),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        return Ok(error_response(StatusCode::NOT_FOUND))
+      }
+      Err(err) => Err(err)?,
+    }
+    self.remove_properties(&resource.key).await;
+    self.locks.lock().await.remove_locks(&resource.key);
+    Ok(empty_response(StatusCode::NO_CONTENT, HeaderMap::new()))
+  }
+
+  async fn remove_properties(&self, key: &str) {
+    self
+      .properties
+      .write()
+      .await
+      .retain(|property_key, _| !is_same_or_descendant(property_key, key));
+  }
+
+  // Copy the dead properties of a resource (and the resources inside of it, if "recursive" is true)
+  async fn copy_properties(&self, source_key: &str, destination_key: &str, recursive: bool) {
+    let mut properties = self.properties.write().await;
+    let copied_properties = properties
+      .iter()
+      .filter(|(property_key, _)| match recursive {
+        true => is_same_or_descendant(property_key, source_key),
+        false => property_key.as_str() == source_key,
+      })
+      .map(|(property_key, resource_properties)| {
+        (
+          format!("{}{}", destination_key, &property_key[source_key.len()..]),
+          resource_properties.clone(),
+        )
+      })
+      .collect::<Vec<_>>();
+    properties.extend(copied_properties);
+  }
+
+  async fn handle_copy_move(
+    &self,
+    share: &WebDavShare,
+    resource: WebDavResource,
+    request: HyperRequest,
+    is_move: bool,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let headers = request.headers();
+
+    // The destination can be either an absolute URI or an absolute path
+    let destination_uri = match headers
+      .get("Destination")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.parse::<hyper::Uri>().ok())
+    {
+      Some(destination_uri) => destination_uri,
+      None => return Ok(error_response(StatusCode::BAD_REQUEST)),
+    };
+    if let Some(destination_authority) = destination_uri.authority() {
+      let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+          request
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str())
+        });
+      if host.is_some_and(|host| !host.eq_ignore_ascii_case(destination_authority.as_str())) {
+        // Copying and moving resources to other servers isn't supported
+        return Ok(error_response(StatusCode::BAD_GATEWAY));
+      }
+    }
+    let destination_url_path =
+      match sanitize_url(destination_uri.path(), false)
+        .ok()
+        .and_then(|path| {
+          urlencoding::decode(&path)
+            .ok()
+            .and_then(|path| normalize_url_path(&path))
+        }) {
+        Some(path) => path,
+        None => return Ok(error_response(StatusCode::BAD_REQUEST)),
+      };
+    if !share.is_webdav_path(&destination_url_path) {
+      return Ok(error_response(StatusCode::FORBIDDEN));
+    }
+    let destination = share.resource(&destination_url_path);
+
+    let overwrite = headers
+      .get("Overwrite")
+      .and_then(|value| value.to_str().ok())
+      .is_none_or(|value| !value.eq_ignore_ascii_case("F"));
+    let recursive = headers
+      .get("Depth")
+      .and_then(|value| value.to_str().ok())
+      .is_none_or(|value| value != "0");
+
+    let source_metadata = match fs::symlink_metadata(&resource.file_path).await {
+      Ok(metadata) => metadata,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        return Ok(error_response(StatusCode::NOT_FOUND))
+      }
+      Err(err) => Err(err)?,
+    };
+    if !is_move && !source_metadata.is_file() && !source_metadata.is_dir() {
+      // The symbolic links and the special files aren't copied
+      return Ok(error_response(StatusCode::FORBIDDEN));
+    }
+    if is_same_or_descendant(&destination.key, &resource.key)
+      || (is_move && share.webdav_paths.contains(&resource.url_path))
+    {
+      // A resource can't be copied nor moved into itself, and the root of the WebDAV share can't be moved
+      return Ok(error_response(StatusCode::FORBIDDEN));
+    }
+    if !parent_is_directory(&destination.file_path).await {
+      return Ok(error_response(StatusCode::CONFLICT));
+    }
+    let destination_exists = fs::symlink_metadata(&destination.file_path).await.is_ok();
+    if destination_exists && !overwrite {
+      return Ok(error_response(StatusCode::PRECONDITION_FAILED));
+    }
+    if (is_move && !self.can_modify(&resource, headers, true).await)
+      || !self.can_modify(&destination, headers, true).await
+    {
+      return Ok(error_response(StatusCode::LOCKED));
+    }
+
+    if destination_exists {
+      remove_resource(&destination.file_path).await?;
+      self.remove_properties(&destination.key).await;
+      self.locks.lock().await.remove_locks(&destination.key);
+    }
+
+    if is_move {
+      if fs::rename(&resource.file_path, &destination.file_path)
+        .await
+        .is_err()
+      {
+        // Renaming fails across file systems, so the resource is copied and removed instead
+        copy_resource(&resource.file_path, &destination.file_path, true).await?;
+        remove_resource(&resource.file_path).await?;
+      }
+      self
+        .copy_properties(&resource.key, &destination.key, true)
+        .await;
+      self.remove_properties(&resource.key).await;
+      self.locks.lock().await.remove_locks(&resource.key);
+    } else {
+      let recursive = recursive || !source_metadata.is_dir();
+      copy_resource(&resource.file_path, &destination.file_path, recursive).await?;
+      self
+        .copy_properties(&resource.key, &destination.key, recursive)
+        .await;
+    }
+
+    Ok(empty_response(
+      match destination_exists {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+      },
+      HeaderMap::new(),
+    ))
+  }
+
+  async fn handle_lock(
+    &self,
+    share: &WebDavShare,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let timeout = get_lock_timeout(request.headers());
+    let tokens = get_submitted_lock_tokens(request.headers());
+    let depth_infinity = match request
+      .headers()
+      .get("Depth")
+      .and_then(|value| value.to_str().ok())
+    {
+      None | Some("infinity") => true,
+      Some("0") => false,
+      Some(_) => return Ok(error_response(StatusCode::BAD_REQUEST)),
+    };
+
+    let body = match read_xml_body(request).await {
+      Ok(body) => body,
+      Err(response) => return Ok(response),
+    };
+
+    let (lock, status) = match body {
+      None => {
+        // A LOCK request without a body refreshes an existing lock
+        match self
+          .locks
+          .lock()
+          .await
+          .refresh(&resource.key, &tokens, timeout)
+        {
+          Some(lock) => (lock, StatusCode::OK),
+          None => return Ok(error_response(StatusCode::PRECONDITION_FAILED)),
+        }
+      }
+      Some(body) => {
+        if !body.is("DAV:", "lockinfo")
+          || body
+            .child("DAV:", "locktype")
+            .and_then(|locktype| locktype.child("DAV:", "write"))
+            .is_none()
+        {
+          return Ok(error_response(StatusCode::BAD_REQUEST));
+        }
+        let exclusive = match body.child("DAV:", "lockscope") {
+          Some(lockscope) if lockscope.child("DAV:", "exclusive").is_some() => true,
+          Some(lockscope) if lockscope.child("DAV:", "shared").is_some() => false,
+          _ => return Ok(error_response(StatusCode::BAD_REQUEST)),
+        };
+        let owner = body
+          .child("DAV:", "owner")
+          .map(|owner| match owner.children.is_empty() {
+            true => escape_xml(&owner.text),
+            false => owner
+              .children
+              .iter()
+              .map(|child| child.to_xml_string())
+              .collect::<String>(),
+          });
+
+        let exists = fs::symlink_metadata(&resource.file_path).await.is_ok();
+        if !exists && !parent_is_directory(&resource.file_path).await {
+          return Ok(error_response(StatusCode::CONFLICT));
+        }
+        let lock = match self.locks.lock().await.lock(
+          &resource.key,
+          exclusive,
+          depth_infinity,
+          owner,
+          timeout,
+        ) {
+          Some(lock) => lock,
+          None => return Ok(error_response(StatusCode::LOCKED)),
+        };
+
+        // Locking an unmapped URL creates an empty resource
+        if !exists {
+          if let Err(err) = fs::File::create(&resource.file_path).await {
+            self.locks.lock().await.unlock(&resource.key, &lock.token);
+            Err(err)?
+          }
+        }
+
+        (
+          lock,
+          match exists {
+            true => StatusCode::OK,
+            false => StatusCode::CREATED,
+          },
+        )
+      }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Lock-Token", format!("<{}>", lock.token).parse()?);
+    Ok(xml_response(
+      status,
+      headers,
+      format!(
+        "<D:prop xmlns:D=\"DAV:\">{}</D:prop>",
+        format_lock_discovery(share, &[lock])
+      ),
+    ))
+  }
+
+  async fn handle_unlock(
+    &self,
+    resource: WebDavResource,
+    request: HyperRequest,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    let token = match request
+      .headers()
+      .get("Lock-Token")
+      .and_then(|value| value.to_str().ok())
+    {
+      Some(token) => token.trim().trim_start_matches('<').trim_end_matches('>'),
+      None => return Ok(error_response(StatusCode::BAD_REQUEST)),
+    };
+    match self.locks.lock().await.unlock(&resource.key, token) {
+      true => Ok(empty_response(StatusCode::NO_CONTENT, HeaderMap::new())),
+      false => Ok(error_response(StatusCode::CONFLICT)),
+    }
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for WebDavModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    WithRuntime::new(self.handle.clone(), async move {
+      let mut webdav_paths = Vec::new();
+      if let Some(webdav_paths_yaml) = config.get("webdavPaths").as_vec() {
+        for webdav_path_yaml in webdav_paths_yaml.iter() {
+          if let Some(webdav_path) = webdav_path_yaml.as_str() {
+            webdav_paths.push(webdav_path.trim_end_matches('/').to_string());
+          }
+        }
+      }
+      let wwwroot = match config.get("wwwroot").as_str() {
+        Some(wwwroot) => wwwroot.to_string(),
+        None => return Ok(ResponseData::builder(request).build()),
+      };
+
+      let share = WebDavShare {
+        wwwroot: PathBuf::from(&wwwroot),
+        wwwroot_key: wwwroot.trim_end_matches('/').to_string(),
+        webdav_paths,
+      };
+
+      let url_path = match urlencoding::decode(request.get_hyper_request().uri().path()) {
+        Ok(url_path) => url_path.to_string(),
+        Err(_) => {
+          return Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::BAD_REQUEST)
+              .build(),
+          )
+        }
+      };
+      let url_path = match normalize_url_path(&url_path) {
+        Some(url_path) => url_path,
+        None => {
+          return Ok(
+            ResponseData::builder(request)
+              .status(StatusCode::FORBIDDEN)
+              .build(),
+          )
+        }
+      };
+      if !share.is_webdav_path(&url_path) {
+        return Ok(ResponseData::builder(request).build());
+      }
+      let resource = share.resource(&url_path);
+
+      let method = request.get_hyper_request().method().clone();
+      let (hyper_request, _) = match method.as_str() {
+        "OPTIONS" | "PROPFIND" | "PROPPATCH" | "MKCOL" | "PUT" | "DELETE" | "COPY" | "MOVE"
+        | "LOCK" | "UNLOCK" => request.into_parts(),
+        // The GET, HEAD and POST requests are handled by the other modules, like the static file serving module
+        _ => return Ok(ResponseData::builder(request).build()),
+      };
+
+      match method.as_str() {
+        "OPTIONS" => {
+          let mut headers = HeaderMap::new();
+          headers.insert("DAV", "1, 2".parse()?);
+          headers.insert("MS-Author-Via", "DAV".parse()?);
+          headers.insert(header::ALLOW, ALLOWED_METHODS.parse()?);
+          Ok(empty_response(StatusCode::OK, headers))
+        }
+        "PROPFIND" => self.handle_propfind(&share, resource, hyper_request).await,
+        "PROPPATCH" => self.handle_proppatch(resource, hyper_request).await,
+        "MKCOL" => self.handle_mkcol(resource, hyper_request).await,
+        "PUT" => self.handle_put(resource, hyper_request).await,
+        "DELETE" => self.handle_delete(&share, resource, hyper_request).await,
+        "COPY" => {
+          self
+            .handle_copy_move(&share, resource, hyper_request, false)
+            .await
+        }
+        "MOVE" => {
+          self
+            .handle_copy_move(&share, resource, hyper_request, true)
+            .await
+        }
+        "LOCK" => self.handle_lock(&share, resource, hyper_request).await,
+        _ => self.handle_unlock(resource, hyper_request).await,
+      }
+    })
+    .await
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    Ok(ResponseData::builder(request).build())
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    Ok(response)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    _upgraded_request: HyperUpgraded,
+    _connect_address: &str,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    false
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    _websocket: HyperWebsocket,
+    _uri: &hyper::Uri,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+    _error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    _config: &ServerConfigRoot,
+    _socket_data: &SocketData,
+  ) -> bool {
+    false
+  }
+}
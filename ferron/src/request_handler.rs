@@ -1,48 +1,90 @@
 use std::convert::Infallible;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::ferron_res::server_software::SERVER_SOFTWARE;
+use crate::ferron_util::access_policy::{AccessDecisionLog, AccessPolicy, AccessSubject};
+use crate::ferron_util::admin_api::log_level_override;
+use crate::ferron_util::bandwidth_throttle::{BandwidthThrottle, ThrottledDirection};
+use crate::ferron_util::client_hints::{
+  detect_device_class, ClientHintsHeaders, DeviceClassCondition,
+};
 use crate::ferron_util::combine_config::combine_config;
-use crate::ferron_util::error_pages::generate_default_error_page;
+use crate::ferron_util::conditional_requests::{last_modified, weak_file_etag};
+use crate::ferron_util::counting_body::{CountedDirection, CountingBody};
+use crate::ferron_util::error_diagnostics::{
+  is_verbose_error_client, ErrorDiagnostics, DEBUG_TOKEN_HEADER,
+};
+use crate::ferron_util::error_pages::{
+  generate_default_error_page, generate_error_page_with_diagnostics,
+};
+use crate::ferron_util::experiments::parse_experiments;
+use crate::ferron_util::expression::ExpressionContext;
+use crate::ferron_util::fair_queue::{FairQueue, FairShare};
+use crate::ferron_util::file_body::{file_body, file_buffer_size};
+use crate::ferron_util::forward_proxy_auth::{
+  acquire_proxy_connection, authorize_proxy_request, proxy_user_key, split_destination,
+  ProxyAuthenticationChallenge,
+};
+use crate::ferron_util::geoip::{GeoIpDatabase, GeoIpInfo};
+use crate::ferron_util::hop_by_hop::strip_hop_by_hop_headers;
+use crate::ferron_util::log_privacy::LogPrivacy;
+use crate::ferron_util::log_sinks::{has_error_log_sinks, parse_access_log_sinks, AccessLogSink};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::min_transfer_rate::{
+  MinRateBody, MinTransferRate, DEFAULT_MIN_RATE_GRACE_PERIOD,
+};
+use crate::ferron_util::module_chain::select_modules;
+use crate::ferron_util::noise_requests::noise_request_response;
+use crate::ferron_util::ssrf_guard::SsrfGuard;
+use crate::ferron_util::timeout_body::TimeoutBody;
+use crate::ferron_util::tls_policy::TlsSession;
 use crate::ferron_util::url_sanitizer::sanitize_url;
+use crate::ferron_util::variable_substitution::substitute_variables;
 
 use async_channel::Sender;
 use chrono::prelude::*;
 use ferron_common::{
-  ErrorLogger, LogMessage, RequestData, ServerConfigRoot, ServerModuleHandlers, SocketData,
+  ClientIdentity, ErrorLogger, LogMessage, RequestByteCounters, RequestData, RequestVariables,
+  ResponseBodyFilters, ServerConfigRoot, ServerModuleHandlers, SocketData,
 };
-use futures_util::TryStreamExt;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Empty, Full, StreamBody};
-use hyper::body::{Body, Bytes, Frame};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Body, Bytes};
 use hyper::header::{self, HeaderName, HeaderValue};
 use hyper::{HeaderMap, Method, Request, Response, StatusCode};
 use hyper_tungstenite::is_upgrade_request;
 use tokio::fs;
-use tokio::io::BufReader;
 use tokio::time::timeout;
-use tokio_util::io::ReaderStream;
 use yaml_rust2::Yaml;
 
 async fn generate_error_response(
   status_code: StatusCode,
   config: &ServerConfigRoot,
   headers: &Option<HeaderMap>,
+  diagnostics: Option<&ErrorDiagnostics>,
 ) -> Response<BoxBody<Bytes, std::io::Error>> {
-  let bare_body =
-    generate_default_error_page(status_code, config.get("serverAdministratorEmail").as_str());
-  let mut content_length: Option<u64> = match bare_body.len().try_into() {
-    Ok(content_length) => Some(content_length),
-    Err(_) => None,
+  let bare_body = match diagnostics {
+    Some(diagnostics) => generate_error_page_with_diagnostics(
+      status_code,
+      config.get("serverAdministratorEmail").as_str(),
+      &diagnostics.to_html(config),
+    ),
+    None => {
+      generate_default_error_page(status_code, config.get("serverAdministratorEmail").as_str())
+    }
   };
+  let mut content_length: Option<u64> = bare_body.len().try_into().ok();
+  let mut validators: Option<(String, Option<String>)> = None;
   let mut response_body = Full::new(Bytes::from(bare_body))
     .map_err(|e| match e {})
     .boxed();
 
-  if let Some(error_pages) = config.get("errorPages").as_vec() {
+  // The custom error pages don't contain the extended diagnostics, so they aren't used for the trusted administrators
+  if let (None, Some(error_pages)) = (diagnostics, config.get("errorPages").as_vec()) {
     for error_page_yaml in error_pages {
       if let Some(page_status_code) = error_page_yaml["scode"].as_i64() {
         let page_status_code = match StatusCode::from_u16(match page_status_code.try_into() {
@@ -64,17 +106,14 @@ async fn generate_error_response(
           };
 
           content_length = match file.metadata().await {
-            Ok(metadata) => Some(metadata.len()),
+            Ok(metadata) => {
+              validators = Some((weak_file_etag(&metadata), last_modified(&metadata)));
+              Some(metadata.len())
+            }
             Err(_) => None,
           };
 
-          // Use BufReader for better performance.
-          let reader_stream = ReaderStream::new(BufReader::with_capacity(12800, file));
-
-          let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
-          let boxed_body = stream_body.boxed();
-
-          response_body = boxed_body;
+          response_body = file_body(file, file_buffer_size(config));
 
           break;
         }
@@ -97,13 +136,68 @@ async fn generate_error_response(
     response_builder = response_builder.header(header::CONTENT_LENGTH, content_length);
   }
   response_builder = response_builder.header(header::CONTENT_TYPE, "text/html");
+  if diagnostics.is_some() {
+    // The diagnostics mustn't be served to other clients from the caches
+    response_builder = response_builder.header(header::CACHE_CONTROL, "no-store");
+  }
+
+  // The validators allow the clients and caches to tell if the custom error page has changed.
+  // The conditional headers aren't evaluated for the error responses (RFC 9110, section 13.2.1), so no 304 is sent.
+  if let Some((etag, last_modified)) = validators {
+    response_builder = response_builder.header(header::ETAG, etag);
+    if let Some(last_modified) = last_modified {
+      response_builder = response_builder.header(header::LAST_MODIFIED, last_modified);
+    }
+  }
 
   response_builder.body(response_body).unwrap_or_default()
 }
 
+// The request properties used in the access log, besides the ones passed to the "log_combined" function
+struct AccessLogContext {
+  // The client's location, appended to the combined log format
+  client_geo: Option<GeoIpInfo>,
+  // Whether the access log file ("logFilePath" property) is enabled
+  log_file_enabled: bool,
+  // The custom log format of the access log file (global, or overridden for the host or the location)
+  log_format: Option<String>,
+  // The access log sinks configured in the "logging" section
+  sinks: Vec<AccessLogSink>,
+  // The request head and the socket data used to resolve the variables in the custom log formats
+  variables: Option<(Request<()>, SocketData)>,
+  // The time the request handling started, logged as the "request_time" variable
+  request_start: Instant,
+  // The experiments and the buckets the request was assigned to, logged as the structured fields
+  experiments: Vec<(String, String)>,
+  // The access decisions made about the request by the access policy and by the modules
+  access_decisions: AccessDecisionLog,
+  // Whether the access decision record is written into the error log ("logAccessDecisions" property)
+  log_access_decisions: bool,
+}
+
+// Copy the request head (including the request variables), so that the variables can be resolved
+// in the access log after the request is handled
+fn request_head<B>(request: &Request<B>) -> Request<()> {
+  let mut request_head = Request::new(());
+  *request_head.method_mut() = request.method().clone();
+  *request_head.uri_mut() = request.uri().clone();
+  *request_head.version_mut() = request.version();
+  *request_head.headers_mut() = request.headers().clone();
+  if let Some(variables) = request.extensions().get::<RequestVariables>() {
+    request_head.extensions_mut().insert(variables.clone());
+  }
+  request_head
+}
+
+// Escape the quotes and backslashes in the value logged in the quotes
+fn escape_log_value(value: &str) -> String {
+  value.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn log_combined(
   logger: &Sender<LogMessage>,
+  log_privacy: &LogPrivacy,
   client_ip: IpAddr,
   auth_user: Option<String>,
   method: String,
@@ -113,46 +207,177 @@ async fn log_combined(
   content_length: Option<u64>,
   referrer: Option<String>,
   user_agent: Option<String>,
+  log_context: &AccessLogContext,
 ) {
+  let request_path = log_privacy.request_path(request_path);
+  let user_agent = log_privacy.user_agent(user_agent);
   let now: DateTime<Local> = Local::now();
   let formatted_time = now.format("%d/%b/%Y:%H:%M:%S %z").to_string();
-  logger
-    .send(LogMessage::new(
-      format!(
-        "{} - {} [{}] \"{} {} {}\" {} {} {} {}",
-        client_ip,
-        match auth_user {
-          Some(auth_user) => auth_user,
-          None => String::from("-"),
-        },
-        formatted_time,
-        method,
-        request_path,
-        protocol,
-        status_code,
-        match content_length {
-          Some(content_length) => format!("{}", content_length),
-          None => String::from("-"),
-        },
-        match referrer {
-          Some(referrer) => format!(
-            "\"{}\"",
-            referrer.replace("\\", "\\\\").replace("\"", "\\\"")
-          ),
-          None => String::from("-"),
-        },
-        match user_agent {
-          Some(user_agent) => format!(
-            "\"{}\"",
-            user_agent.replace("\\", "\\\\").replace("\"", "\\\"")
-          ),
+  let auth_user = auth_user.unwrap_or_else(|| String::from("-"));
+  let content_length = match content_length {
+    Some(content_length) => format!("{}", content_length),
+    None => String::from("-"),
+  };
+
+  let format_entry = |log_format: Option<&str>| {
+    // The custom log format can contain the variables (for example, "$remote_addr" or "$upstream_response_time")
+    if let (Some(log_format), Some((request_head, socket_data))) =
+      (log_format, &log_context.variables)
+    {
+      let expression_context = ExpressionContext::new(request_head, socket_data);
+      return substitute_variables(log_format, |name| {
+        let value = match name {
+          "remote_addr" => log_privacy.client_ip(client_ip),
+          "remote_user" => auth_user.clone(),
+          "time_local" => formatted_time.clone(),
+          "request" => format!("{} {} {}", method, request_path, protocol),
+          "request_method" => method.clone(),
+          "request_uri" => request_path.clone(),
+          "server_protocol" => protocol.clone(),
+          "status" => status_code.to_string(),
+          "body_bytes_sent" => content_length.clone(),
+          "http_referer" => escape_log_value(referrer.as_deref().unwrap_or_default()),
+          "http_user_agent" => escape_log_value(user_agent.as_deref().unwrap_or_default()),
+          // The time until the response head was generated, in seconds with a millisecond resolution
+          "request_time" => format!("{:.3}", log_context.request_start.elapsed().as_secs_f64()),
+          _ => expression_context.variable(name).unwrap_or_default(),
+        };
+        // The empty values and the variables not set for the request are logged as "-"
+        Some(match value.is_empty() {
+          true => String::from("-"),
+          false => value,
+        })
+      });
+    }
+
+    // The country code and the autonomous system number are appended to the combined log format, if enabled
+    let geo_fields = match &log_context.client_geo {
+      Some(client_geo) => format!(
+        " {} {}",
+        client_geo.country.as_deref().unwrap_or("-"),
+        match client_geo.asn {
+          Some(asn) => format!("AS{}", asn),
           None => String::from("-"),
-        },
+        }
       ),
-      false,
-    ))
-    .await
-    .unwrap_or_default();
+      None => String::new(),
+    };
+    format!(
+      "{} - {} [{}] \"{} {} {}\" {} {} {} {}{}",
+      log_privacy.client_ip(client_ip),
+      auth_user,
+      formatted_time,
+      method,
+      request_path,
+      protocol,
+      status_code,
+      content_length,
+      match &referrer {
+        Some(referrer) => format!("\"{}\"", escape_log_value(referrer)),
+        None => String::from("-"),
+      },
+      match &user_agent {
+        Some(user_agent) => format!("\"{}\"", escape_log_value(user_agent)),
+        None => String::from("-"),
+      },
+      geo_fields,
+    )
+  };
+
+  if log_context.log_file_enabled {
+    logger
+      .send(LogMessage::new(
+        format_entry(log_context.log_format.as_deref()),
+        false,
+      ))
+      .await
+      .unwrap_or_default();
+  }
+
+  // A single record of the access decision is logged for the request, so that it can be audited why the request was admitted
+  let access_decision = log_context.access_decisions.final_decision();
+  if let (Some(access_decision), true) = (&access_decision, log_context.log_access_decisions) {
+    logger
+      .send(LogMessage::new(
+        format!(
+          "Access decision: {} by the \"{}\" rule for the client {} (user \"{}\"): \"{} {} {}\" {}",
+          access_decision.action.as_str(),
+          escape_log_value(&access_decision.rule),
+          log_privacy.client_ip(client_ip),
+          escape_log_value(&auth_user),
+          method,
+          request_path,
+          protocol,
+          status_code
+        ),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+  }
+
+  // Each access log sink has its own format and sampling
+  for (sink_index, sink) in log_context.sinks.iter().enumerate() {
+    if sink.should_log(status_code) {
+      let mut message = LogMessage::new(format_entry(sink.format.as_deref()), false)
+        .with_access_log_sink(sink_index);
+      // The request properties are also logged as the structured fields, if the sink supports them
+      if sink.has_structured_fields() {
+        message = message
+          .with_field("REMOTE_ADDR", log_privacy.client_ip(client_ip))
+          .with_field("REMOTE_USER", auth_user.clone())
+          .with_field("HTTP_METHOD", method.clone())
+          .with_field("HTTP_REQUEST_URI", request_path.clone())
+          .with_field("HTTP_PROTOCOL", protocol.clone())
+          .with_field("HTTP_STATUS", status_code.to_string())
+          .with_field("HTTP_BODY_BYTES_SENT", content_length.clone());
+        if let Some(referrer) = &referrer {
+          message = message.with_field("HTTP_REFERER", referrer.clone());
+        }
+        if let Some(user_agent) = &user_agent {
+          message = message.with_field("HTTP_USER_AGENT", user_agent.clone());
+        }
+        if let Some(access_decision) = &access_decision {
+          message = message
+            .with_field(
+              "ACCESS_DECISION",
+              access_decision.action.as_str().to_string(),
+            )
+            .with_field("ACCESS_RULE", access_decision.rule.clone());
+        }
+        for (experiment_name, bucket) in log_context.experiments.iter() {
+          message = message.with_field(
+            &format!("EXPERIMENT_{}", experiment_name.to_uppercase()),
+            bucket.clone(),
+          );
+        }
+      }
+      logger.send(message).await.unwrap_or_default();
+    }
+  }
+}
+
+// Add the headers naming the module that produced the response and the modules that declined the request,
+// if routing debugging is enabled
+fn add_routing_debug_headers(
+  headers: &mut HeaderMap,
+  debug_routing: bool,
+  responding_module: Option<&str>,
+  declined_modules: &[Arc<str>],
+) {
+  if !debug_routing {
+    return;
+  }
+  if let Some(responding_module) = responding_module {
+    if let Ok(header_value) = HeaderValue::from_str(responding_module) {
+      headers.insert("x-ferron-responding-module", header_value);
+    }
+  }
+  if !declined_modules.is_empty() {
+    if let Ok(header_value) = HeaderValue::from_str(&declined_modules.join(", ")) {
+      headers.insert("x-ferron-declined-modules", header_value);
+    }
+  }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -163,8 +388,13 @@ async fn request_handler_wrapped(
   encrypted: bool,
   global_config_root: Arc<ServerConfigRoot>,
   host_config: Arc<Yaml>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
   logger: Sender<LogMessage>,
-  handlers_vec: Vec<Box<dyn ServerModuleHandlers + Send>>,
+  handlers_vec: Vec<(Arc<str>, Box<dyn ServerModuleHandlers + Send>)>,
+  fair_queue: Option<Arc<FairQueue>>,
+  fair_share: &mut Option<FairShare>,
+  response_throttle: &mut Option<BandwidthThrottle>,
+  server_timing: &mut bool,
 ) -> Result<Response<BoxBody<Bytes, std::io::Error>>, Infallible> {
   let is_proxy_request = match request.version() {
     hyper::Version::HTTP_2 | hyper::Version::HTTP_3 => {
@@ -175,6 +405,7 @@ async fn request_handler_wrapped(
   let is_connect_proxy_request = request.method() == hyper::Method::CONNECT;
 
   // Collect request data for logging
+  let request_start = Instant::now();
   let log_method = String::from(request.method().as_str());
   let log_request_path = match is_proxy_request {
     true => request.uri().to_string(),
@@ -209,11 +440,105 @@ async fn request_handler_wrapped(
     },
     None => None,
   };
-  let log_enabled = global_config_root.get("logFilePath").as_str().is_some();
+  let log_file_enabled = global_config_root.get("logFilePath").as_str().is_some();
+  let log_sinks = parse_access_log_sinks(&global_config_root.get("logging")).unwrap_or_default();
+  let mut log_enabled = log_file_enabled || !log_sinks.is_empty();
+  let log_privacy = LogPrivacy::from_config(&global_config_root);
+
+  // Determine the client's location for country-based routing, access control, logging and metrics
+  let client_geo = geoip_database
+    .as_ref()
+    .map(|geoip_database| geoip_database.lookup(remote_address.ip()));
+  if let Some(country) = client_geo
+    .as_ref()
+    .and_then(|client_geo| client_geo.country.as_deref())
+  {
+    METRICS.increment_counter("ferron_geoip_requests_total", &[("country", country)]);
+  }
+
+  // The client's location is available as the "geoip_country" and "geoip_asn" variables
+  if let (Some(client_geo), Some(variables)) = (
+    client_geo.as_ref(),
+    request.extensions().get::<RequestVariables>(),
+  ) {
+    if let Some(country) = &client_geo.country {
+      variables.set("geoip_country", country.clone());
+    }
+    if let Some(asn) = client_geo.asn {
+      variables.set("geoip_asn", asn.to_string());
+    }
+  }
+
+  // The identity of the client authenticated with TLS is available as the "ssl_client_*" variables,
+  // so that it can be used for the authorization decisions and in the access log
+  if let Some(variables) = request.extensions().get::<RequestVariables>() {
+    match request.extensions().get::<ClientIdentity>() {
+      Some(client_identity) => {
+        variables.set("ssl_client_verify", "SUCCESS".to_string());
+        variables.set(
+          "ssl_client_public_key_pin",
+          client_identity.public_key_pin().to_string(),
+        );
+        if let Some(subject) = client_identity.subject() {
+          variables.set("ssl_client_s_dn", subject.to_string());
+        }
+        if !client_identity.subject_alt_names().is_empty() {
+          variables.set(
+            "ssl_client_san",
+            client_identity.subject_alt_names().join(","),
+          );
+        }
+      }
+      None => variables.set("ssl_client_verify", "NONE".to_string()),
+    }
+    // The parameters of the TLS session are available as the "ssl_protocol" and "ssl_cipher" variables
+    if let Some(tls_session) = request.extensions().get::<TlsSession>() {
+      variables.set("ssl_protocol", tls_session.protocol.to_string());
+      variables.set("ssl_cipher", tls_session.cipher.clone());
+    }
+  }
+
+  let log_format = global_config_root
+    .get("logFormat")
+    .as_str()
+    .map(String::from);
+  // The request head is copied only if the variables are used in the custom log formats
+  let log_variables =
+    match log_format.is_some() || log_sinks.iter().any(|sink| sink.format.is_some()) {
+      true => Some((
+        request_head(&request),
+        SocketData::new(remote_address, local_address, encrypted),
+      )),
+      false => None,
+    };
+  let mut log_context = AccessLogContext {
+    client_geo: match global_config_root.get("logGeoIpFields").as_bool() {
+      Some(true) => client_geo.clone(),
+      _ => None,
+    },
+    log_file_enabled,
+    log_format,
+    sinks: log_sinks,
+    variables: log_variables,
+    request_start,
+    experiments: Vec::new(),
+    access_decisions: AccessDecisionLog::new(),
+    log_access_decisions: false,
+  };
+  // The modules record their access decisions, so that they're logged with the request
+  request
+    .extensions_mut()
+    .insert(log_context.access_decisions.clone());
+  // The modules add the filters transforming the response body
+  let response_body_filters = ResponseBodyFilters::new();
+  request
+    .extensions_mut()
+    .insert(response_body_filters.clone());
   let error_log_enabled = global_config_root
     .get("errorLogFilePath")
     .as_str()
-    .is_some();
+    .is_some()
+    || has_error_log_sinks(&global_config_root.get("logging"));
 
   // Construct SocketData
   let mut socket_data = SocketData::new(remote_address, local_address, encrypted);
@@ -252,6 +577,7 @@ async fn request_handler_wrapped(
               if log_enabled {
                 log_combined(
                   &logger,
+                  &log_privacy,
                   socket_data.remote_addr.ip(),
                   None,
                   log_method,
@@ -270,6 +596,7 @@ async fn request_handler_wrapped(
                   },
                   log_referrer,
                   log_user_agent,
+                  &log_context,
                 )
                 .await;
               }
@@ -311,6 +638,7 @@ async fn request_handler_wrapped(
         if log_enabled {
           log_combined(
             &logger,
+            &log_privacy,
             socket_data.remote_addr.ip(),
             None,
             log_method,
@@ -329,6 +657,7 @@ async fn request_handler_wrapped(
             },
             log_referrer,
             log_user_agent,
+            &log_context,
           )
           .await;
         }
@@ -341,21 +670,31 @@ async fn request_handler_wrapped(
     }
   };
 
+  let client_country = client_geo
+    .as_ref()
+    .and_then(|client_geo| client_geo.country.as_deref());
+
+  // Detect the client's device class for the device-based routing. It's available as the "device_class" variable.
+  let device_class = detect_device_class(request.headers());
+  if let Some(variables) = request.extensions().get::<RequestVariables>() {
+    variables.set("device_class", device_class.as_str().to_string());
+  }
+  let device_class_condition = DeviceClassCondition::new(device_class);
+
   // Combine the server configuration
   let combined_config = match combine_config(
     global_config_root,
     host_config,
     match is_proxy_request || is_connect_proxy_request {
       false => match request.headers().get(header::HOST) {
-        Some(value) => match value.to_str() {
-          Ok(value) => Some(value),
-          Err(_) => None,
-        },
+        Some(value) => value.to_str().ok(),
         None => None,
       },
       true => None,
     },
     local_address.ip(),
+    client_country,
+    &device_class_condition,
     request.uri().path(),
   ) {
     Some(config) => config,
@@ -384,6 +723,7 @@ async fn request_handler_wrapped(
       if log_enabled {
         log_combined(
           &logger,
+          &log_privacy,
           socket_data.remote_addr.ip(),
           None,
           log_method,
@@ -402,6 +742,7 @@ async fn request_handler_wrapped(
           },
           log_referrer,
           log_user_agent,
+          &log_context,
         )
         .await;
       }
@@ -413,6 +754,264 @@ async fn request_handler_wrapped(
     }
   };
 
+  // The hosts and the locations can choose the modules that run for them, and their order
+  let handlers_vec = select_modules(handlers_vec, &combined_config);
+
+  // The client hints are requested from the browsers, and the responses routed by the device class vary on it
+  let client_hints_headers = ClientHintsHeaders::new(&combined_config, &device_class_condition);
+
+  // The access log format can be overridden for the host or the location
+  if let Some(log_format) = combined_config.get("logFormat").as_str() {
+    if log_context.variables.is_none() {
+      log_context.variables = Some((
+        request_head(&request),
+        SocketData::new(remote_address, local_address, encrypted),
+      ));
+    }
+    log_context.log_format = Some(log_format.to_string());
+  }
+  *server_timing = combined_config.get("serverTiming").as_bool() == Some(true);
+  log_context.log_access_decisions =
+    combined_config.get("logAccessDecisions").as_bool() == Some(true);
+  // The access decision record is logged even if the access log isn't enabled
+  log_enabled |= log_context.log_access_decisions;
+
+  // The trusted administrators see the extended diagnostics on the error pages
+  let mut error_diagnostics =
+    is_verbose_error_client(&combined_config, remote_address.ip(), request.headers())
+      .then(ErrorDiagnostics::new);
+  // The debug token isn't passed to the modules, so that it isn't forwarded to the backend servers
+  request.headers_mut().remove(DEBUG_TOKEN_HEADER);
+
+  // Evaluate the access policy of the host or the location, including the GeoIP country lists.
+  // The configuration is validated before the server starts, so the invalid access policy isn't expected here.
+  let access_decision = match AccessPolicy::from_config(&combined_config, geoip_database.is_some())
+  {
+    Ok(access_policy) if !access_policy.is_empty() => Some(
+      access_policy
+        .evaluate(&AccessSubject {
+          client_ip: socket_data.remote_addr.ip(),
+          country: client_country,
+          variables: request.extensions().get::<RequestVariables>(),
+          time: Local::now(),
+        })
+        .await,
+    ),
+    _ => None,
+  };
+  if let Some(access_decision) = access_decision.as_ref() {
+    log_context.access_decisions.record(access_decision.clone());
+    METRICS.increment_counter(
+      "ferron_access_policy_decisions_total",
+      &[
+        ("decision", access_decision.action.as_str()),
+        ("rule", &access_decision.rule),
+      ],
+    );
+  }
+  if let Some(access_decision) = access_decision.filter(|decision| !decision.is_allowed()) {
+    if access_decision.rule.starts_with("geoip") {
+      METRICS.increment_counter(
+        "ferron_geoip_denied_requests_total",
+        &[("country", client_country.unwrap_or("unknown"))],
+      );
+    }
+    let response = generate_error_response(
+      StatusCode::FORBIDDEN,
+      &combined_config,
+      &None,
+      error_diagnostics.as_ref(),
+    )
+    .await;
+    if log_enabled {
+      log_combined(
+        &logger,
+        &log_privacy,
+        socket_data.remote_addr.ip(),
+        None,
+        log_method,
+        log_request_path,
+        log_protocol,
+        response.status().as_u16(),
+        match response.headers().get(header::CONTENT_LENGTH) {
+          Some(header_value) => match header_value.to_str() {
+            Ok(header_value) => match header_value.parse::<u64>() {
+              Ok(content_length) => Some(content_length),
+              Err(_) => response.body().size_hint().exact(),
+            },
+            Err(_) => response.body().size_hint().exact(),
+          },
+          None => response.body().size_hint().exact(),
+        },
+        log_referrer,
+        log_user_agent,
+        &log_context,
+      )
+      .await;
+    }
+    let (mut response_parts, response_body) = response.into_parts();
+    if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
+      response_parts.headers.insert(header::SERVER, server_string);
+    };
+    return Ok(Response::from_parts(response_parts, response_body));
+  }
+
+  // Answer the common noise requests (for example, "/favicon.ico") without passing them to the modules
+  if !is_proxy_request && !is_connect_proxy_request {
+    if let Some((noise_request_kind, response)) =
+      noise_request_response(request.method(), request.uri().path(), &combined_config)
+    {
+      METRICS.increment_counter(
+        "ferron_noise_requests_total",
+        &[("kind", noise_request_kind.as_str())],
+      );
+      if log_enabled
+        && combined_config
+          .get("logNoiseRequests")
+          .as_bool()
+          .unwrap_or(true)
+      {
+        log_combined(
+          &logger,
+          &log_privacy,
+          socket_data.remote_addr.ip(),
+          None,
+          log_method,
+          log_request_path,
+          log_protocol,
+          response.status().as_u16(),
+          response.body().size_hint().exact(),
+          log_referrer,
+          log_user_agent,
+          &log_context,
+        )
+        .await;
+      }
+      let (mut response_parts, response_body) = response.into_parts();
+      if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
+        response_parts.headers.insert(header::SERVER, server_string);
+      };
+      return Ok(Response::from_parts(response_parts, response_body));
+    }
+  }
+
+  // Keep the request and response bodies within the configured bandwidth limits
+  let bandwidth_host = combined_config.get("domain");
+  let bandwidth_host = bandwidth_host.as_str().unwrap_or("*");
+  if let Some(upload_throttle) = BandwidthThrottle::from_config(
+    &combined_config,
+    ThrottledDirection::Upload,
+    socket_data.remote_addr,
+    bandwidth_host,
+  ) {
+    let (request_parts, request_body) = request.into_parts();
+    request = Request::from_parts(
+      request_parts,
+      upload_throttle.wrap_body(request_body).boxed(),
+    );
+  }
+  *response_throttle = BandwidthThrottle::from_config(
+    &combined_config,
+    ThrottledDirection::Download,
+    socket_data.remote_addr,
+    bandwidth_host,
+  );
+
+  // Assign the request to the buckets of the experiments. The buckets are available as the "experiment_<name>" variables,
+  // and are passed to the modules and the backend servers in the request headers, if configured.
+  if let Ok(experiments) = parse_experiments(&combined_config.get("experiments")) {
+    for experiment in experiments.iter() {
+      let bucket = experiment
+        .assign(request.headers(), socket_data.remote_addr.ip())
+        .to_string();
+      if let Some(variables) = request.extensions().get::<RequestVariables>() {
+        variables.set(&format!("experiment_{}", experiment.name), bucket.clone());
+      }
+      if let Some(header_name) = &experiment.header {
+        if let Ok(header_value) = HeaderValue::from_str(&bucket) {
+          request
+            .headers_mut()
+            .insert(header_name.clone(), header_value);
+        }
+      }
+      log_context
+        .experiments
+        .push((experiment.name.clone(), bucket));
+    }
+  }
+
+  // Set the custom request variables. The values can refer to the other variables, including the ones set before.
+  if let (Some(set_variables), Some(variables)) = (
+    combined_config.get("setVariables").as_hash(),
+    request.extensions().get::<RequestVariables>(),
+  ) {
+    for (name, value) in set_variables {
+      if let (Some(name), Some(value)) = (name.as_str(), value.as_str()) {
+        let expression_context = ExpressionContext::new(&request, &socket_data);
+        let value = substitute_variables(value, |name| expression_context.variable(name));
+        variables.set(name, value);
+      }
+    }
+  }
+
+  // Admit the request only if the host hasn't used up its share of the listener's request capacity
+  if let Some(fair_queue) = fair_queue {
+    let host = combined_config.get("domain");
+    let host = host.as_str().unwrap_or("*");
+    let weight = combined_config
+      .get("fairShareWeight")
+      .as_i64()
+      .filter(|weight| *weight > 0)
+      .unwrap_or(1) as u64;
+    match fair_queue.try_acquire(host, weight) {
+      Some(share) => *fair_share = Some(share),
+      None => {
+        METRICS.increment_counter(
+          "ferron_fair_queue_rejected_requests_total",
+          &[("host", host)],
+        );
+        let response = generate_error_response(
+          StatusCode::SERVICE_UNAVAILABLE,
+          &combined_config,
+          &None,
+          error_diagnostics.as_ref(),
+        )
+        .await;
+        if log_enabled {
+          log_combined(
+            &logger,
+            &log_privacy,
+            socket_data.remote_addr.ip(),
+            None,
+            log_method,
+            log_request_path,
+            log_protocol,
+            response.status().as_u16(),
+            match response.headers().get(header::CONTENT_LENGTH) {
+              Some(header_value) => match header_value.to_str() {
+                Ok(header_value) => match header_value.parse::<u64>() {
+                  Ok(content_length) => Some(content_length),
+                  Err(_) => response.body().size_hint().exact(),
+                },
+                Err(_) => response.body().size_hint().exact(),
+              },
+              None => response.body().size_hint().exact(),
+            },
+            log_referrer,
+            log_user_agent,
+            &log_context,
+          )
+          .await;
+        }
+        let (mut response_parts, response_body) = response.into_parts();
+        if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
+          response_parts.headers.insert(header::SERVER, server_string);
+        };
+        return Ok(Response::from_parts(response_parts, response_body));
+      }
+    }
+  }
+
   let url_pathname = request.uri().path();
   let sanitized_url_pathname = match sanitize_url(
     url_pathname,
@@ -423,6 +1022,9 @@ async fn request_handler_wrapped(
   ) {
     Ok(sanitized_url) => sanitized_url,
     Err(err) => {
+      if let Some(error_diagnostics) = &error_diagnostics {
+        error_diagnostics.record_error(format!("URL sanitation error: {}", err));
+      }
       if error_log_enabled {
         logger
           .send(LogMessage::new(
@@ -432,11 +1034,17 @@ async fn request_handler_wrapped(
           .await
           .unwrap_or_default();
       }
-      let response =
-        generate_error_response(StatusCode::BAD_REQUEST, &combined_config, &None).await;
+      let response = generate_error_response(
+        StatusCode::BAD_REQUEST,
+        &combined_config,
+        &None,
+        error_diagnostics.as_ref(),
+      )
+      .await;
       if log_enabled {
         log_combined(
           &logger,
+          &log_privacy,
           socket_data.remote_addr.ip(),
           None,
           log_method,
@@ -455,6 +1063,7 @@ async fn request_handler_wrapped(
           },
           log_referrer,
           log_user_agent,
+          &log_context,
         )
         .await;
       }
@@ -503,6 +1112,9 @@ async fn request_handler_wrapped(
       {
         Ok(path_and_query) => path_and_query,
         Err(err) => {
+          if let Some(error_diagnostics) = &error_diagnostics {
+            error_diagnostics.record_error(format!("URL sanitation error: {}", err));
+          }
           if error_log_enabled {
             logger
               .send(LogMessage::new(
@@ -512,11 +1124,17 @@ async fn request_handler_wrapped(
               .await
               .unwrap_or_default();
           }
-          let response =
-            generate_error_response(StatusCode::BAD_REQUEST, &combined_config, &None).await;
+          let response = generate_error_response(
+            StatusCode::BAD_REQUEST,
+            &combined_config,
+            &None,
+            error_diagnostics.as_ref(),
+          )
+          .await;
           if log_enabled {
             log_combined(
               &logger,
+              &log_privacy,
               socket_data.remote_addr.ip(),
               None,
               log_method,
@@ -535,6 +1153,7 @@ async fn request_handler_wrapped(
               },
               log_referrer,
               log_user_agent,
+              &log_context,
             )
             .await;
           }
@@ -565,6 +1184,9 @@ async fn request_handler_wrapped(
     parts.uri = match hyper::Uri::from_parts(url_parts) {
       Ok(uri) => uri,
       Err(err) => {
+        if let Some(error_diagnostics) = &error_diagnostics {
+          error_diagnostics.record_error(format!("URL sanitation error: {}", err));
+        }
         if error_log_enabled {
           logger
             .send(LogMessage::new(
@@ -574,11 +1196,17 @@ async fn request_handler_wrapped(
             .await
             .unwrap_or_default();
         }
-        let response =
-          generate_error_response(StatusCode::BAD_REQUEST, &combined_config, &None).await;
+        let response = generate_error_response(
+          StatusCode::BAD_REQUEST,
+          &combined_config,
+          &None,
+          error_diagnostics.as_ref(),
+        )
+        .await;
         if log_enabled {
           log_combined(
             &logger,
+            &log_privacy,
             socket_data.remote_addr.ip(),
             None,
             log_method,
@@ -597,6 +1225,7 @@ async fn request_handler_wrapped(
             },
             log_referrer,
             log_user_agent,
+            &log_context,
           )
           .await;
         }
@@ -638,12 +1267,19 @@ async fn request_handler_wrapped(
         if let Ok(header_value) = HeaderValue::from_str("GET, POST, HEAD, OPTIONS") {
           header_map.insert(header::ALLOW, header_value);
         };
-        generate_error_response(StatusCode::BAD_REQUEST, &combined_config, &Some(header_map)).await
+        generate_error_response(
+          StatusCode::BAD_REQUEST,
+          &combined_config,
+          &Some(header_map),
+          error_diagnostics.as_ref(),
+        )
+        .await
       }
     };
     if log_enabled {
       log_combined(
         &logger,
+        &log_privacy,
         socket_data.remote_addr.ip(),
         None,
         log_method,
@@ -662,6 +1298,7 @@ async fn request_handler_wrapped(
         },
         log_referrer,
         log_user_agent,
+        &log_context,
       )
       .await;
     }
@@ -693,10 +1330,28 @@ async fn request_handler_wrapped(
     true => ErrorLogger::new(cloned_logger),
     false => ErrorLogger::without_logger(),
   };
+  // The errors logged by the modules (for example, the backend server errors) are shown in the extended diagnostics
+  let error_logger = match &error_diagnostics {
+    Some(error_diagnostics) => {
+      error_logger.with_captured_messages(error_diagnostics.captured_errors())
+    }
+    None => error_logger,
+  };
+  // The error log level might be raised temporarily for the host or the client through the admin API
+  let error_logger = match log_level_override(
+    request
+      .headers()
+      .get(header::HOST)
+      .and_then(|host| host.to_str().ok()),
+    remote_address.ip(),
+  ) {
+    Some(level_override) => error_logger.with_level_override(level_override),
+    None => error_logger,
+  };
 
   if is_connect_proxy_request {
     let mut connect_proxy_handlers = None;
-    for mut handlers in handlers_vec {
+    for (_, mut handlers) in handlers_vec {
       if handlers.does_connect_proxy_requests() {
         connect_proxy_handlers = Some(handlers);
         break;
@@ -709,47 +1364,157 @@ async fn request_handler_wrapped(
         let client_ip = socket_data.remote_addr.ip();
         let custom_headers_yaml = combined_config.get("customHeaders");
 
-        tokio::spawn(async move {
-          match hyper::upgrade::on(request).await {
-            Ok(upgraded_request) => {
-              let result = connect_proxy_handlers
-                .connect_proxy_request_handler(
-                  upgraded_request,
-                  &connect_address,
-                  &combined_config,
-                  &socket_data,
-                  &error_logger,
-                )
-                .await;
-              match result {
-                Ok(_) => (),
-                Err(err) => {
-                  error_logger
-                    .log(&format!("Unexpected error for CONNECT request: {}", err))
-                    .await;
-                }
+        let (destination_host, destination_port) =
+          split_destination(&connect_address, 443).unwrap_or((&connect_address, 443));
+        let authorization = authorize_proxy_request(
+          request.headers(),
+          "CONNECT",
+          &connect_address,
+          (destination_host, destination_port),
+          &combined_config,
+          client_ip,
+          &error_logger,
+        )
+        .await;
+        let mut log_auth_user = None;
+
+        // The destination of the authorized tunnel is resolved and validated before the tunnel is established,
+        // and the tunnel is opened to the validated address
+        let (connect_address, destination_error) =
+          match (&authorization, SsrfGuard::from_config(&combined_config)) {
+            (Ok(Ok(_)), Some(ssrf_guard)) => {
+              match ssrf_guard.resolve(destination_host, destination_port).await {
+                Ok(destination_address) => (destination_address.to_string(), None),
+                Err(err) => (connect_address, Some(err)),
               }
             }
-            Err(err) => {
-              error_logger
-                .log(&format!(
-                  "Error while upgrading HTTP CONNECT request: {}",
-                  err
-                ))
-                .await
-            }
+            _ => (connect_address, None),
+          };
+
+        let response = match (authorization, destination_error) {
+          (Ok(Ok(_)), Some(err)) => {
+            let status = match err.kind() {
+              std::io::ErrorKind::PermissionDenied => {
+                METRICS.increment_counter(
+                  "ferron_forward_proxy_denials_total",
+                  &[("reason", "blocked_destination")],
+                );
+                StatusCode::FORBIDDEN
+              }
+              _ => StatusCode::BAD_GATEWAY,
+            };
+            error_logger
+              .log(&format!("Cannot connect to the remote server: {}", err))
+              .await;
+            Response::builder()
+              .status(status)
+              .body(Empty::new().map_err(|e| match e {}).boxed())
+              .unwrap_or_default()
           }
-        });
+          (Ok(Ok(auth_user)), None) => {
+            let max_connections = combined_config
+              .get("forwardProxyMaxConnectionsPerUser")
+              .as_i64()
+              .map(|max_connections| max_connections as usize);
+            log_auth_user = auth_user.clone();
+            match acquire_proxy_connection(
+              &proxy_user_key(auth_user.as_deref(), client_ip),
+              max_connections,
+            ) {
+              Some(connection_guard) => {
+                // The forward proxy user is passed to the module in the socket data
+                if let Some(auth_user) = auth_user {
+                  socket_data.set_proxy_auth_user(auth_user);
+                }
+                tokio::spawn(async move {
+                  // The connection slot of the forward proxy user is held until the tunnel is closed
+                  let _connection_guard = connection_guard;
+                  match hyper::upgrade::on(request).await {
+                    Ok(upgraded_request) => {
+                      let result = connect_proxy_handlers
+                        .connect_proxy_request_handler(
+                          upgraded_request,
+                          &connect_address,
+                          &combined_config,
+                          &socket_data,
+                          &error_logger,
+                        )
+                        .await;
+                      match result {
+                        Ok(_) => (),
+                        Err(err) => {
+                          error_logger
+                            .log(&format!("Unexpected error for CONNECT request: {}", err))
+                            .await;
+                        }
+                      }
+                    }
+                    Err(err) => {
+                      error_logger
+                        .log(&format!(
+                          "Error while upgrading HTTP CONNECT request: {}",
+                          err
+                        ))
+                        .await
+                    }
+                  }
+                });
 
-        let response = Response::builder()
-          .body(Empty::new().map_err(|e| match e {}).boxed())
-          .unwrap_or_default();
+                Response::builder()
+                  .body(Empty::new().map_err(|e| match e {}).boxed())
+                  .unwrap_or_default()
+              }
+              None => {
+                METRICS.increment_counter(
+                  "ferron_forward_proxy_denials_total",
+                  &[("reason", "connection_limit")],
+                );
+                error_logger
+                  .log(&format!(
+                    "Too many forward proxy connections for client \"{}\"{}",
+                    client_ip,
+                    match &log_auth_user {
+                      Some(auth_user) => format!(" and user \"{}\"", auth_user),
+                      None => String::new(),
+                    }
+                  ))
+                  .await;
+                Response::builder()
+                  .status(StatusCode::TOO_MANY_REQUESTS)
+                  .body(Empty::new().map_err(|e| match e {}).boxed())
+                  .unwrap_or_default()
+              }
+            }
+          }
+          (Ok(Err(denial)), _) => {
+            let mut response = Response::builder()
+              .status(denial.status)
+              .extension(ProxyAuthenticationChallenge)
+              .body(Empty::new().map_err(|e| match e {}).boxed())
+              .unwrap_or_default();
+            *response.headers_mut() = denial.headers;
+            response
+          }
+          (Err(err), _) => {
+            error_logger
+              .log(&format!(
+                "Unexpected error while authorizing CONNECT request: {}",
+                err
+              ))
+              .await;
+            Response::builder()
+              .status(StatusCode::INTERNAL_SERVER_ERROR)
+              .body(Empty::new().map_err(|e| match e {}).boxed())
+              .unwrap_or_default()
+          }
+        };
 
         if log_enabled {
           log_combined(
             &logger,
+            &log_privacy,
             client_ip,
-            None,
+            log_auth_user,
             log_method,
             log_request_path,
             log_protocol,
@@ -766,6 +1531,7 @@ async fn request_handler_wrapped(
             },
             log_referrer,
             log_user_agent,
+            &log_context,
           )
           .await;
         }
@@ -800,6 +1566,7 @@ async fn request_handler_wrapped(
         if log_enabled {
           log_combined(
             &logger,
+            &log_privacy,
             socket_data.remote_addr.ip(),
             None,
             log_method,
@@ -818,6 +1585,7 @@ async fn request_handler_wrapped(
             },
             log_referrer,
             log_user_agent,
+            &log_context,
           )
           .await;
         }
@@ -852,6 +1620,7 @@ async fn request_handler_wrapped(
       if log_enabled {
         log_combined(
           &logger,
+          &log_privacy,
           socket_data.remote_addr.ip(),
           None,
           log_method,
@@ -870,6 +1639,7 @@ async fn request_handler_wrapped(
           },
           log_referrer,
           log_user_agent,
+          &log_context,
         )
         .await;
       }
@@ -900,7 +1670,9 @@ async fn request_handler_wrapped(
     let mut request_data = RequestData::new(request, None);
     let mut latest_auth_data = None;
     let mut executed_handlers = Vec::new();
-    for mut handlers in handlers_vec {
+    let debug_routing = combined_config.get("debugRouting").as_bool() == Some(true);
+    let mut declined_modules = Vec::new();
+    for (module_name, mut handlers) in handlers_vec {
       if is_websocket_request && handlers.does_websocket_requests(&combined_config, &socket_data) {
         let (request, _) = request_data.into_parts();
 
@@ -930,6 +1702,7 @@ async fn request_handler_wrapped(
             if log_enabled {
               log_combined(
                 &logger,
+                &log_privacy,
                 socket_data.remote_addr.ip(),
                 None,
                 log_method,
@@ -948,6 +1721,7 @@ async fn request_handler_wrapped(
                 },
                 log_referrer,
                 log_user_agent,
+                &log_context,
               )
               .await;
             }
@@ -1000,6 +1774,7 @@ async fn request_handler_wrapped(
         if log_enabled {
           log_combined(
             &logger,
+            &log_privacy,
             client_ip,
             None,
             log_method,
@@ -1018,6 +1793,7 @@ async fn request_handler_wrapped(
             },
             log_referrer,
             log_user_agent,
+            &log_context,
           )
           .await;
         }
@@ -1042,6 +1818,13 @@ async fn request_handler_wrapped(
         if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
           response_parts.headers.insert(header::SERVER, server_string);
         };
+        add_routing_debug_headers(
+          &mut response_parts.headers,
+          debug_routing,
+          Some(&module_name),
+          &declined_modules,
+        );
+        client_hints_headers.apply(&mut response_parts.headers);
 
         return Ok(Response::from_parts(response_parts, response_body));
       }
@@ -1060,6 +1843,9 @@ async fn request_handler_wrapped(
       };
 
       executed_handlers.push(handlers);
+      if let Some(error_diagnostics) = &mut error_diagnostics {
+        error_diagnostics.record_module(module_name.clone());
+      }
       match response_result {
         Ok(response) => {
           let (
@@ -1073,7 +1859,7 @@ async fn request_handler_wrapped(
           ) = response.into_parts();
           latest_auth_data = auth_data.clone();
           if let Some(new_remote_address) = new_remote_address {
-            socket_data.remote_addr = new_remote_address;
+            socket_data.set_forwarded_remote_addr(new_remote_address);
           };
           if let Some(parallel_fn) = parallel_fn {
             // Spawn the function in the web server's Tokio runtime.
@@ -1104,6 +1890,13 @@ async fn request_handler_wrapped(
               if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
                 response_parts.headers.insert(header::SERVER, server_string);
               };
+              add_routing_debug_headers(
+                &mut response_parts.headers,
+                debug_routing,
+                Some(&module_name),
+                &declined_modules,
+              );
+              client_hints_headers.apply(&mut response_parts.headers);
               let mut response = Response::from_parts(response_parts, response_body);
 
               while let Some(mut executed_handler) = executed_handlers.pop() {
@@ -1118,6 +1911,10 @@ async fn request_handler_wrapped(
                 response = match response_status {
                   Ok(response) => response,
                   Err(err) => {
+                    if let Some(error_diagnostics) = &error_diagnostics {
+                      error_diagnostics
+                        .record_error(format!("Unexpected error while serving a request: {}", err));
+                    }
                     if error_log_enabled {
                       logger
                         .send(LogMessage::new(
@@ -1132,11 +1929,13 @@ async fn request_handler_wrapped(
                       StatusCode::INTERNAL_SERVER_ERROR,
                       &combined_config,
                       &headers,
+                      error_diagnostics.as_ref(),
                     )
                     .await;
                     if log_enabled {
                       log_combined(
                         &logger,
+                        &log_privacy,
                         socket_data.remote_addr.ip(),
                         auth_data,
                         log_method,
@@ -1155,6 +1954,7 @@ async fn request_handler_wrapped(
                         },
                         log_referrer,
                         log_user_agent,
+                        &log_context,
                       )
                       .await;
                     }
@@ -1185,9 +1985,13 @@ async fn request_handler_wrapped(
                 };
               }
 
+              // The response body is transformed by the filters added by the modules
+              let response = response_body_filters.apply(response);
+
               if log_enabled {
                 log_combined(
                   &logger,
+                  &log_privacy,
                   socket_data.remote_addr.ip(),
                   auth_data,
                   log_method,
@@ -1206,6 +2010,7 @@ async fn request_handler_wrapped(
                   },
                   log_referrer,
                   log_user_agent,
+                  &log_context,
                 )
                 .await;
               }
@@ -1214,7 +2019,13 @@ async fn request_handler_wrapped(
             }
             None => match status {
               Some(status) => {
-                let response = generate_error_response(status, &combined_config, &headers).await;
+                let response = generate_error_response(
+                  status,
+                  &combined_config,
+                  &headers,
+                  error_diagnostics.as_ref(),
+                )
+                .await;
                 let (mut response_parts, response_body) = response.into_parts();
                 if let Some(custom_headers_hash) = combined_config.get("customHeaders").as_hash() {
                   let custom_headers_hash_iter = custom_headers_hash.iter();
@@ -1235,6 +2046,13 @@ async fn request_handler_wrapped(
                 if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
                   response_parts.headers.insert(header::SERVER, server_string);
                 };
+                add_routing_debug_headers(
+                  &mut response_parts.headers,
+                  debug_routing,
+                  Some(&module_name),
+                  &declined_modules,
+                );
+                client_hints_headers.apply(&mut response_parts.headers);
                 let mut response = Response::from_parts(response_parts, response_body);
 
                 while let Some(mut executed_handler) = executed_handlers.pop() {
@@ -1249,6 +2067,12 @@ async fn request_handler_wrapped(
                   response = match response_status {
                     Ok(response) => response,
                     Err(err) => {
+                      if let Some(error_diagnostics) = &error_diagnostics {
+                        error_diagnostics.record_error(format!(
+                          "Unexpected error while serving a request: {}",
+                          err
+                        ));
+                      }
                       if error_log_enabled {
                         logger
                           .send(LogMessage::new(
@@ -1263,11 +2087,13 @@ async fn request_handler_wrapped(
                         StatusCode::INTERNAL_SERVER_ERROR,
                         &combined_config,
                         &headers,
+                        error_diagnostics.as_ref(),
                       )
                       .await;
                       if log_enabled {
                         log_combined(
                           &logger,
+                          &log_privacy,
                           socket_data.remote_addr.ip(),
                           auth_data,
                           log_method,
@@ -1286,6 +2112,7 @@ async fn request_handler_wrapped(
                           },
                           log_referrer,
                           log_user_agent,
+                          &log_context,
                         )
                         .await;
                       }
@@ -1316,9 +2143,13 @@ async fn request_handler_wrapped(
                   };
                 }
 
+                // The response body is transformed by the filters added by the modules
+                let response = response_body_filters.apply(response);
+
                 if log_enabled {
                   log_combined(
                     &logger,
+                    &log_privacy,
                     socket_data.remote_addr.ip(),
                     auth_data,
                     log_method,
@@ -1337,27 +2168,39 @@ async fn request_handler_wrapped(
                     },
                     log_referrer,
                     log_user_agent,
+                    &log_context,
                   )
                   .await;
                 }
                 return Ok(response);
               }
-              None => match request_option {
-                Some(request) => {
-                  request_data = RequestData::new(request, auth_data);
-                  continue;
-                }
-                None => {
-                  break;
+              None => {
+                declined_modules.push(module_name);
+                match request_option {
+                  Some(request) => {
+                    request_data = RequestData::new(request, auth_data);
+                    continue;
+                  }
+                  None => {
+                    break;
+                  }
                 }
-              },
+              }
             },
           }
         }
         Err(err) => {
-          let response =
-            generate_error_response(StatusCode::INTERNAL_SERVER_ERROR, &combined_config, &None)
-              .await;
+          if let Some(error_diagnostics) = &error_diagnostics {
+            error_diagnostics
+              .record_error(format!("Unexpected error while serving a request: {}", err));
+          }
+          let response = generate_error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &combined_config,
+            &None,
+            error_diagnostics.as_ref(),
+          )
+          .await;
 
           let (mut response_parts, response_body) = response.into_parts();
           if let Some(custom_headers_hash) = combined_config.get("customHeaders").as_hash() {
@@ -1379,6 +2222,13 @@ async fn request_handler_wrapped(
           if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
             response_parts.headers.insert(header::SERVER, server_string);
           };
+          add_routing_debug_headers(
+            &mut response_parts.headers,
+            debug_routing,
+            Some(&module_name),
+            &declined_modules,
+          );
+          client_hints_headers.apply(&mut response_parts.headers);
 
           let mut response = Response::from_parts(response_parts, response_body);
 
@@ -1394,6 +2244,10 @@ async fn request_handler_wrapped(
             response = match response_status {
               Ok(response) => response,
               Err(err) => {
+                if let Some(error_diagnostics) = &error_diagnostics {
+                  error_diagnostics
+                    .record_error(format!("Unexpected error while serving a request: {}", err));
+                }
                 if error_log_enabled {
                   logger
                     .send(LogMessage::new(
@@ -1408,11 +2262,13 @@ async fn request_handler_wrapped(
                   StatusCode::INTERNAL_SERVER_ERROR,
                   &combined_config,
                   &None,
+                  error_diagnostics.as_ref(),
                 )
                 .await;
                 if log_enabled {
                   log_combined(
                     &logger,
+                    &log_privacy,
                     socket_data.remote_addr.ip(),
                     latest_auth_data,
                     log_method,
@@ -1431,6 +2287,7 @@ async fn request_handler_wrapped(
                     },
                     log_referrer,
                     log_user_agent,
+                    &log_context,
                   )
                   .await;
                 }
@@ -1459,6 +2316,14 @@ async fn request_handler_wrapped(
             };
           }
 
+          // The response body is transformed by the filters added by the modules
+          let response = response_body_filters.apply(response);
+
+          if let Some(error_diagnostics) = &error_diagnostics {
+            error_diagnostics
+              .record_error(format!("Unexpected error while serving a request: {}", err));
+          }
+
           if error_log_enabled {
             logger
               .send(LogMessage::new(
@@ -1472,6 +2337,7 @@ async fn request_handler_wrapped(
           if log_enabled {
             log_combined(
               &logger,
+              &log_privacy,
               socket_data.remote_addr.ip(),
               latest_auth_data,
               log_method,
@@ -1490,6 +2356,7 @@ async fn request_handler_wrapped(
               },
               log_referrer,
               log_user_agent,
+              &log_context,
             )
             .await;
           }
@@ -1498,7 +2365,13 @@ async fn request_handler_wrapped(
       }
     }
 
-    let response = generate_error_response(StatusCode::NOT_FOUND, &combined_config, &None).await;
+    let response = generate_error_response(
+      StatusCode::NOT_FOUND,
+      &combined_config,
+      &None,
+      error_diagnostics.as_ref(),
+    )
+    .await;
 
     let (mut response_parts, response_body) = response.into_parts();
     if let Some(custom_headers_hash) = combined_config.get("customHeaders").as_hash() {
@@ -1520,6 +2393,13 @@ async fn request_handler_wrapped(
     if let Ok(server_string) = HeaderValue::from_str(SERVER_SOFTWARE) {
       response_parts.headers.insert(header::SERVER, server_string);
     };
+    add_routing_debug_headers(
+      &mut response_parts.headers,
+      debug_routing,
+      None,
+      &declined_modules,
+    );
+    client_hints_headers.apply(&mut response_parts.headers);
     let mut response = Response::from_parts(response_parts, response_body);
 
     while let Some(mut executed_handler) = executed_handlers.pop() {
@@ -1544,12 +2424,17 @@ async fn request_handler_wrapped(
               .unwrap_or_default();
           }
 
-          let response =
-            generate_error_response(StatusCode::INTERNAL_SERVER_ERROR, &combined_config, &None)
-              .await;
+          let response = generate_error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &combined_config,
+            &None,
+            error_diagnostics.as_ref(),
+          )
+          .await;
           if log_enabled {
             log_combined(
               &logger,
+              &log_privacy,
               socket_data.remote_addr.ip(),
               latest_auth_data,
               log_method,
@@ -1568,6 +2453,7 @@ async fn request_handler_wrapped(
               },
               log_referrer,
               log_user_agent,
+              &log_context,
             )
             .await;
           }
@@ -1596,9 +2482,13 @@ async fn request_handler_wrapped(
       };
     }
 
+    // The response body is transformed by the filters added by the modules
+    let response = response_body_filters.apply(response);
+
     if log_enabled {
       log_combined(
         &logger,
+        &log_privacy,
         socket_data.remote_addr.ip(),
         latest_auth_data,
         log_method,
@@ -1617,6 +2507,7 @@ async fn request_handler_wrapped(
         },
         log_referrer,
         log_user_agent,
+        &log_context,
       )
       .await;
     }
@@ -1632,11 +2523,82 @@ pub async fn request_handler(
   encrypted: bool,
   global_config_root: Arc<ServerConfigRoot>,
   host_config: Arc<Yaml>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
   logger: Sender<LogMessage>,
-  handlers_vec: Vec<Box<dyn ServerModuleHandlers + Send>>,
+  handlers_vec: Vec<(Arc<str>, Box<dyn ServerModuleHandlers + Send>)>,
+  fair_queue: Option<Arc<FairQueue>>,
 ) -> Result<Response<BoxBody<Bytes, std::io::Error>>, anyhow::Error> {
-  let timeout_yaml = global_config_root.get("timeout");
-  if timeout_yaml.is_null() {
+  let request_start = Instant::now();
+  // Count the request and response body bytes, so that the modules can read the live counters from the request
+  let byte_counters = RequestByteCounters::new();
+  let (mut request_parts, request_body) = request.into_parts();
+  request_parts.extensions.insert(byte_counters.clone());
+  // The request variables are shared by the configuration features (for example, the URL rewrite rules and the access log)
+  let request_variables = RequestVariables::new();
+  request_parts.extensions.insert(request_variables.clone());
+  let request = Request::from_parts(
+    request_parts,
+    CountingBody::new(
+      request_body,
+      byte_counters.clone(),
+      CountedDirection::Received,
+    )
+    .boxed(),
+  );
+
+  // Limit the time the request body can be received for
+  let body_timed_out = Arc::new(AtomicBool::new(false));
+  let request = match global_config_root.get("requestBodyTimeout").as_i64() {
+    Some(body_timeout_millis) => {
+      let (request_parts, request_body) = request.into_parts();
+      Request::from_parts(
+        request_parts,
+        TimeoutBody::new(
+          request_body,
+          Duration::from_millis(body_timeout_millis as u64),
+          body_timed_out.clone(),
+        )
+        .boxed(),
+      )
+    }
+    None => request,
+  };
+
+  // Stop receiving the request body, if the client trickles it slower than the minimum transfer rate
+  let body_too_slow = Arc::new(AtomicBool::new(false));
+  let request = match global_config_root.get("requestBodyMinRate").as_i64() {
+    Some(body_min_rate) => {
+      let grace_period = Duration::from_millis(
+        global_config_root
+          .get("minRateGracePeriod")
+          .as_i64()
+          .map(|grace_period| grace_period as u64)
+          .unwrap_or(DEFAULT_MIN_RATE_GRACE_PERIOD),
+      );
+      let (request_parts, request_body) = request.into_parts();
+      Request::from_parts(
+        request_parts,
+        MinRateBody::new(
+          request_body,
+          MinTransferRate::new(body_min_rate as u64, grace_period),
+          body_too_slow.clone(),
+        )
+        .boxed(),
+      )
+    }
+    None => request,
+  };
+  let is_http1 = request.version() < hyper::Version::HTTP_2;
+
+  // The "handlerTimeout" property takes precedence over the older "timeout" property
+  let timeout_yaml = match global_config_root.get("handlerTimeout") {
+    handler_timeout_yaml if !handler_timeout_yaml.is_badvalue() => handler_timeout_yaml,
+    _ => global_config_root.get("timeout"),
+  };
+  let mut fair_share = None;
+  let mut response_throttle = None;
+  let mut server_timing = false;
+  let response = if timeout_yaml.is_null() {
     request_handler_wrapped(
       request,
       remote_address,
@@ -1644,8 +2606,13 @@ pub async fn request_handler(
       encrypted,
       global_config_root,
       host_config,
+      geoip_database,
       logger,
       handlers_vec,
+      fair_queue,
+      &mut fair_share,
+      &mut response_throttle,
+      &mut server_timing,
     )
     .await
     .map_err(|e| anyhow::anyhow!(e))
@@ -1660,8 +2627,13 @@ pub async fn request_handler(
         encrypted,
         global_config_root,
         host_config,
+        geoip_database,
         logger,
         handlers_vec,
+        fair_queue,
+        &mut fair_share,
+        &mut response_throttle,
+        &mut server_timing,
       ),
     )
     .await
@@ -1669,5 +2641,97 @@ pub async fn request_handler(
       Ok(response) => response.map_err(|e| anyhow::anyhow!(e)),
       Err(_) => Err(anyhow::anyhow!("The client or server has timed out")),
     }
+  }?;
+
+  let body_too_slow = body_too_slow.load(Ordering::Relaxed);
+  if body_too_slow {
+    METRICS.increment_counter(
+      "ferron_rejected_requests_total",
+      &[("reason", "slow_body_read")],
+    );
+  }
+  if body_timed_out.load(Ordering::Relaxed) || body_too_slow {
+    // The request body wasn't received in time, so the response is based on an incomplete body
+    let mut response_builder = Response::builder()
+      .status(StatusCode::REQUEST_TIMEOUT)
+      .header(header::CONTENT_TYPE, "text/html");
+    if body_too_slow && is_http1 {
+      // The connection, over which the client trickles the data, isn't kept alive
+      response_builder = response_builder.header(header::CONNECTION, "close");
+    }
+    return Ok(
+      response_builder
+        .body(
+          Full::new(Bytes::from(generate_default_error_page(
+            StatusCode::REQUEST_TIMEOUT,
+            None,
+          )))
+          .map_err(|e| match e {})
+          .boxed(),
+        )
+        .unwrap_or_default(),
+    );
+  }
+
+  let (mut response_parts, response_body) = response.into_parts();
+
+  // The handling time is measured until the response head is generated
+  let request_duration = request_start.elapsed().as_secs_f64();
+  METRICS.observe_histogram("ferron_request_duration_seconds", &[], request_duration);
+  if server_timing {
+    let mut server_timing_value = format!("total;dur={:.3}", request_duration * 1000.0);
+    // The time spent waiting for the backend servers (including the retried requests) is reported separately
+    if let Some(upstream_response_time) = request_variables.get("upstream_response_time") {
+      let upstream_duration: f64 = upstream_response_time
+        .split(',')
+        .filter_map(|upstream_time| upstream_time.trim().parse::<f64>().ok())
+        .sum();
+      server_timing_value.push_str(&format!(", upstream;dur={:.3}", upstream_duration * 1000.0));
+    }
+    if let Ok(server_timing_value) = HeaderValue::from_str(&server_timing_value) {
+      response_parts
+        .headers
+        .append("server-timing", server_timing_value);
+    }
+  }
+
+  // The hop-by-hop headers set by the modules (for example, the ones received from a backend server) aren't sent to the client,
+  // except the ones for the protocol upgrade and the forward proxy authentication challenge
+  let proxy_authentication_challenge = match response_parts
+    .extensions
+    .remove::<ProxyAuthenticationChallenge>()
+  {
+    Some(_) => response_parts
+      .headers
+      .get_all(header::PROXY_AUTHENTICATE)
+      .iter()
+      .cloned()
+      .collect(),
+    None => Vec::new(),
+  };
+  strip_hop_by_hop_headers(
+    &mut response_parts.headers,
+    response_parts.status == StatusCode::SWITCHING_PROTOCOLS,
+  );
+  for challenge in proxy_authentication_challenge {
+    response_parts
+      .headers
+      .append(header::PROXY_AUTHENTICATE, challenge);
+  }
+
+  let response_body =
+    CountingBody::new(response_body, byte_counters, CountedDirection::Sent).boxed();
+  let response_body = match response_throttle {
+    Some(response_throttle) => response_throttle.wrap_body(response_body).boxed(),
+    None => response_body,
+  };
+
+  // The host's fair share is held until the response body is sent
+  match fair_share {
+    Some(fair_share) => Ok(Response::from_parts(
+      response_parts,
+      fair_share.wrap_body(response_body).boxed(),
+    )),
+    None => Ok(Response::from_parts(response_parts, response_body)),
   }
 }
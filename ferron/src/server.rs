@@ -1,54 +1,713 @@
 use std::error::Error;
-use std::net::{IpAddr, Ipv6Addr, SocketAddr};
-use std::sync::Arc;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime};
 use std::{env, thread};
 
+use crate::ferron_master::WORKER_PROCESS_ENV;
 use crate::ferron_request_handler::request_handler;
-use crate::ferron_util::load_tls::{load_certs, load_private_key};
+use crate::ferron_util::admin_api::{
+  error_log_level_allows, log_level, parse_error_log_levels, serve_admin_api, set_error_log_levels,
+  AdminListener, SERVER_STATS,
+};
+use crate::ferron_util::auto_ban::{AutoBan, TEMPORARY_BANS, TEMPORARY_BANS_REFRESH_INTERVAL};
+use crate::ferron_util::blocking_budget::{BLOCKING_BUDGETS, DEFAULT_MAX_BLOCKING_THREADS};
+use crate::ferron_util::cache_prewarm::{prewarm_jobs, run_prewarm_job};
+use crate::ferron_util::certificate_checks::check_certificate;
+use crate::ferron_util::client_auth::{
+  client_identity, create_client_cert_verifier, ClientAuthConfig,
+};
+use crate::ferron_util::concurrency_limiter::{ConcurrencyLimiter, ConcurrencyPermit};
+use crate::ferron_util::config_export::set_active_config;
+use crate::ferron_util::connection_drain::{drain_timeout, ConnectionDrain};
+use crate::ferron_util::deployment::parse_deployment_targets;
+use crate::ferron_util::dns_resolver::{set_dns_resolver, DnsResolver, ReqwestDnsResolver};
+use crate::ferron_util::drop_privileges::{drop_privileges, privileges_dropped, reload_refusal};
+use crate::ferron_util::error_pages::generate_default_error_page;
+use crate::ferron_util::fair_queue::FairQueue;
+use crate::ferron_util::geoip::GeoIpDatabase;
+use crate::ferron_util::hsts::check_hsts_configuration;
+use crate::ferron_util::listener_stats::monitor_listeners;
+use crate::ferron_util::load_config::ConfigOrigins;
+use crate::ferron_util::load_listeners::{
+  bind_listener, find_listener_tls_configs, get_systemd_listeners, load_listeners,
+  match_listener_config, ListenerConfig,
+};
+use crate::ferron_util::load_tls::{certificate_key_paths, load_certs, load_private_key};
+use crate::ferron_util::log_file::LogFile;
+use crate::ferron_util::log_sinks::{parse_access_log_sinks, parse_error_log_sinks, LogOutput};
+use crate::ferron_util::log_throttle::{LogThrottle, SERVER_LOG_SOURCE};
+use crate::ferron_util::match_hostname::{match_hostname, strip_host_port};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::min_transfer_rate::DEFAULT_MIN_RATE_GRACE_PERIOD;
+use crate::ferron_util::module_chain::validate_module_lists;
+use crate::ferron_util::module_lifecycle::{run_module_hook, ModuleLifecycleHook};
+use crate::ferron_util::monitored_module::MonitoredModule;
+use crate::ferron_util::ocsp_stapling::{OcspStapler, DEFAULT_OCSP_REFRESH_INTERVAL};
+use crate::ferron_util::response_fairness::ResponseFairness;
 use crate::ferron_util::sni::CustomSniResolver;
+use crate::ferron_util::storage::{create_storage_backend, StorageBackend, STORAGE};
+use crate::ferron_util::temp_files::{request_body_buffer, TEMP_FILES};
+use crate::ferron_util::timeout_stream::{
+  ConnectionActivity, HeaderReadTimeoutError, StreamTimeouts, TimeoutStream,
+};
+use crate::ferron_util::tls_policy::{
+  cipher_suite_by_name, protocol_versions, TlsPolicy, TlsPreset, TlsSession, DEFAULT_ALPN_PROTOCOLS,
+};
+use crate::ferron_util::tracked_body::TrackedBody;
 use crate::ferron_util::validate_config::{prepare_config_for_validation, validate_config};
 
-use async_channel::Sender;
+use async_channel::{Receiver, Sender};
 use chrono::prelude::*;
-use ferron_common::{LogMessage, ServerConfigRoot, ServerModule, ServerModuleHandlers};
+use ferron_common::{
+  ErrorLogLevel, ErrorLogger, LogMessage, ServerConfigRoot, ServerModule, ServerModuleHandlers,
+  SubrequestHandler, TaskScheduler,
+};
+use futures_util::future::join_all;
 use futures_util::StreamExt;
-use http_body_util::BodyExt;
-use hyper::body::Incoming;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
 use hyper::service::service_fn;
-use hyper::Request;
+use hyper::{header, Request, Response, StatusCode};
 use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
-use libloading::Symbol;
-use ocsp_stapler::Stapler;
-use rustls::crypto::ring::cipher_suite::*;
+use hyper_util::server::conn::auto::Http2Builder;
+use libloading::Library;
 use rustls::crypto::ring::default_provider;
 use rustls::crypto::ring::kx_group::*;
-use rustls::server::WebPkiClientVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::server::{Acceptor, ResolvesServerCert};
 use rustls::sign::CertifiedKey;
-use rustls::version::{TLS12, TLS13};
-use rustls::{RootCertStore, ServerConfig};
-use rustls_native_certs::load_native_certs;
-use tokio::io::{AsyncWriteExt, BufWriter};
+use rustls::{ConfigBuilder, ServerConfig, WantsVerifier};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Handle;
+use tokio::signal;
 use tokio::sync::Mutex;
 use tokio::time;
-use tokio::{fs, signal};
-use tokio_rustls::TlsAcceptor;
+use tokio_rustls::LazyConfigAcceptor;
 use tokio_rustls_acme::caches::DirCache;
 use tokio_rustls_acme::{AcmeAcceptor, AcmeConfig};
 use yaml_rust2::Yaml;
 
+// Classify a connection error caused by a rejected request (oversized headers, slow header reads or bad framing)
+fn classify_rejected_request(err: &(dyn Error + Send + Sync + 'static)) -> Option<&'static str> {
+  let hyper_error = err.downcast_ref::<hyper::Error>()?;
+  if hyper_error.is_parse_too_large() {
+    Some("oversized_headers")
+  } else if hyper_error.is_timeout() || is_header_read_timeout(hyper_error) {
+    Some("slow_header_read")
+  } else if hyper_error.is_parse() {
+    Some("bad_framing")
+  } else {
+    None
+  }
+}
+
+// Check if the connection error is caused by the client not sending the request header in time
+fn is_header_read_timeout(hyper_error: &hyper::Error) -> bool {
+  hyper_error
+    .source()
+    .and_then(|err| err.downcast_ref::<std::io::Error>())
+    .and_then(|err| err.get_ref())
+    .is_some_and(|err| err.is::<HeaderReadTimeoutError>())
+}
+
+// Log the connection closed for exceeding an idle timeout
+async fn log_reaped_connection(
+  connection_activity: &ConnectionActivity,
+  remote_address: SocketAddr,
+  logger: &Sender<LogMessage>,
+) {
+  if let Some(reap_reason) = connection_activity.reap_reason() {
+    logger
+      .send(
+        LogMessage::new(
+          format!(
+            "Closed the idle connection from {} (idle timeout: {})",
+            remote_address,
+            reap_reason.as_str()
+          ),
+          true,
+        )
+        .with_level(ErrorLogLevel::Debug),
+      )
+      .await
+      .unwrap_or_default();
+  }
+}
+
+// Obtain a timeout (in milliseconds) from the global configuration. The timeout is disabled if the property is set to null.
+fn get_timeout(
+  global_config_root: &ServerConfigRoot,
+  property: &str,
+  default_millis: Option<u64>,
+) -> Option<time::Duration> {
+  let timeout_yaml = global_config_root.get(property);
+  if timeout_yaml.is_null() {
+    None
+  } else {
+    timeout_yaml
+      .as_i64()
+      .map(|timeout_millis| timeout_millis as u64)
+      .or(default_millis)
+      .map(time::Duration::from_millis)
+  }
+}
+
+// Create the TLS configurations for an SNI host name with the overridden TLS policy. The parameters,
+// which aren't overridden, are inherited from the global and the listener configuration.
+#[allow(clippy::too_many_arguments)]
+fn create_sni_tls_configs(
+  sni_hostname: &str,
+  tls_policy: &TlsPolicy,
+  crypto_provider: &CryptoProvider,
+  min_tls_version: Option<&str>,
+  max_tls_version: Option<&str>,
+  listener_client_auth: &ClientAuthConfig,
+  cert_resolver: Arc<dyn ResolvesServerCert>,
+  logger: Sender<LogMessage>,
+) -> Result<SniTlsConfigs, Box<dyn Error + Send + Sync>> {
+  let mut crypto_provider = crypto_provider.clone();
+  if let Some(cipher_suites) = &tls_policy.cipher_suites {
+    crypto_provider.cipher_suites = cipher_suites
+      .iter()
+      .filter_map(|cipher_suite| cipher_suite_by_name(cipher_suite))
+      .collect();
+  }
+  let versions = protocol_versions(
+    tls_policy.min_version.as_deref().or(min_tls_version),
+    tls_policy.max_version.as_deref().or(max_tls_version),
+  )?;
+  let signature_verification_algorithms = crypto_provider.signature_verification_algorithms;
+  let tls_config_builder = ServerConfig::builder_with_provider(Arc::new(crypto_provider))
+    .with_protocol_versions(versions)?;
+  let client_cert_verifier = create_client_cert_verifier(
+    tls_policy
+      .client_auth
+      .as_ref()
+      .unwrap_or(listener_client_auth),
+    signature_verification_algorithms,
+    logger,
+  )?;
+  let tls_config = match client_cert_verifier {
+    Some(client_cert_verifier) => {
+      tls_config_builder.with_client_cert_verifier(client_cert_verifier)
+    }
+    None => tls_config_builder.with_no_client_auth(),
+  }
+  .with_cert_resolver(cert_resolver);
+
+  // The overridden ALPN protocols are offered regardless of the "enableHTTP2" properties
+  match &tls_policy.alpn_protocols {
+    Some(alpn_protocols) => {
+      let mut tls_config = tls_config;
+      tls_config.alpn_protocols = alpn_protocols
+        .iter()
+        .map(|alpn_protocol| alpn_protocol.as_bytes().to_vec())
+        .collect();
+      let tls_config = Arc::new(tls_config);
+      Ok(SniTlsConfigs {
+        hostname: sni_hostname.to_string(),
+        http1: tls_config.clone(),
+        http2: tls_config,
+        enable_http2: Some(
+          alpn_protocols
+            .iter()
+            .any(|alpn_protocol| alpn_protocol == "h2"),
+        ),
+      })
+    }
+    None => {
+      let mut tls_config = tls_config;
+      tls_config.alpn_protocols = DEFAULT_ALPN_PROTOCOLS[1..]
+        .iter()
+        .map(|alpn_protocol| alpn_protocol.as_bytes().to_vec())
+        .collect();
+      let mut tls_config_http2 = tls_config.clone();
+      tls_config_http2.alpn_protocols = DEFAULT_ALPN_PROTOCOLS
+        .iter()
+        .map(|alpn_protocol| alpn_protocol.as_bytes().to_vec())
+        .collect();
+      Ok(SniTlsConfigs {
+        hostname: sni_hostname.to_string(),
+        http1: Arc::new(tls_config),
+        http2: Arc::new(tls_config_http2),
+        enable_http2: None,
+      })
+    }
+  }
+}
+
+// Run a step of the TLS handshake, failing if the handshake deadline has passed
+async fn tls_handshake_step<T>(
+  deadline: Option<time::Instant>,
+  future: impl Future<Output = Result<T, std::io::Error>>,
+) -> Result<T, std::io::Error> {
+  match deadline {
+    Some(deadline) => match time::timeout_at(deadline, future).await {
+      Ok(result) => result,
+      Err(_) => Err(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "TLS handshake timed out",
+      )),
+    },
+    None => future.await,
+  }
+}
+
+// The TLS configurations of an SNI host name with the overridden TLS policy
+struct SniTlsConfigs {
+  hostname: String,
+  http1: Arc<ServerConfig>,
+  http2: Arc<ServerConfig>,
+  // Whether HTTP/2 is offered, if the ALPN protocols are overridden
+  enable_http2: Option<bool>,
+}
+
+// The TLS configurations of a secure listener, with and without HTTP/2 offered with ALPN
+#[derive(Clone)]
+struct ListenerTlsConfigs {
+  http1: Arc<ServerConfig>,
+  http2: Arc<ServerConfig>,
+  enable_http2: bool,
+  sni_overrides: Arc<Vec<SniTlsConfigs>>,
+}
+
+impl ListenerTlsConfigs {
+  // Get the index of the overridden TLS policy used for the server name. The exact host names
+  // take precedence over the wildcard ones, which are matched in the configuration order.
+  fn policy_index(&self, server_name: Option<&str>) -> Option<usize> {
+    if self.sni_overrides.is_empty() {
+      return None;
+    }
+    let server_name = server_name?.to_lowercase();
+    self
+      .sni_overrides
+      .iter()
+      .position(|sni_override| sni_override.hostname.eq_ignore_ascii_case(&server_name))
+      .or_else(|| {
+        self.sni_overrides.iter().position(|sni_override| {
+          match_hostname(
+            Some(&sni_override.hostname.to_lowercase()),
+            Some(&server_name),
+          )
+        })
+      })
+  }
+
+  // Select the TLS configuration for the server name requested by the client. The "enableHTTP2" property
+  // of the first host with a matching domain overrides the listener's one, unless the ALPN protocols
+  // are overridden for the SNI host name. Returns whether HTTP/2 is enabled.
+  fn select(&self, server_name: Option<&str>, host_config: &Yaml) -> (Arc<ServerConfig>, bool) {
+    let sni_override = self
+      .policy_index(server_name)
+      .and_then(|policy_index| self.sni_overrides.get(policy_index));
+    if let Some(SniTlsConfigs {
+      http1,
+      enable_http2: Some(enable_http2),
+      ..
+    }) = sni_override
+    {
+      return (http1.clone(), *enable_http2);
+    }
+    let (http1, http2) = match sni_override {
+      Some(sni_override) => (&sni_override.http1, &sni_override.http2),
+      None => (&self.http1, &self.http2),
+    };
+    let host_enable_http2 = server_name.and_then(|server_name| {
+      host_config.as_vec()?.iter().find_map(|host| {
+        let domain = host["domain"].as_str()?;
+        match match_hostname(Some(domain), Some(server_name)) {
+          true => host["enableHTTP2"].as_bool(),
+          false => None,
+        }
+      })
+    });
+    match host_enable_http2.unwrap_or(self.enable_http2) {
+      true => (http2.clone(), true),
+      false => (http1.clone(), false),
+    }
+  }
+}
+
+// Apply the HTTP/2 settings to the connection builder, and create the fairness controls of the responses on the connection
+fn configure_http2<E>(
+  mut http2_builder: &mut Http2Builder<'_, E>,
+  http2_settings: &Yaml,
+) -> Option<Arc<ResponseFairness>> {
+  if let Some(initial_window_size) = http2_settings["initialWindowSize"].as_i64() {
+    http2_builder = http2_builder.initial_stream_window_size(initial_window_size as u32);
+  }
+  if let Some(max_frame_size) = http2_settings["maxFrameSize"].as_i64() {
+    http2_builder = http2_builder.max_frame_size(max_frame_size as u32);
+  }
+  if let Some(max_concurrent_streams) = http2_settings["maxConcurrentStreams"].as_i64() {
+    http2_builder = http2_builder.max_concurrent_streams(max_concurrent_streams as u32);
+  }
+  if let Some(max_header_list_size) = http2_settings["maxHeaderListSize"].as_i64() {
+    http2_builder = http2_builder.max_header_list_size(max_header_list_size as u32);
+  }
+  if let Some(enable_connect_protocol) = http2_settings["enableConnectProtocol"].as_bool() {
+    if enable_connect_protocol {
+      http2_builder = http2_builder.enable_connect_protocol();
+    }
+  }
+  let response_fairness = ResponseFairness::from_http2_settings(http2_settings);
+  // The response bodies send at most the per-stream buffered bytes limit at once, so h2 doesn't need to buffer more
+  if let Some(max_stream_buffered_bytes) = response_fairness
+    .as_ref()
+    .and_then(|response_fairness| response_fairness.max_stream_buffered_bytes())
+  {
+    http2_builder.max_send_buf_size(max_stream_buffered_bytes);
+  }
+  response_fairness
+}
+
+// Handle a request, tracking it in the connection activity until the response is sent
+#[allow(clippy::too_many_arguments)]
+async fn request_handler_tracked(
+  connection_activity: Arc<ConnectionActivity>,
+  request_limiter: Option<Arc<ConcurrencyLimiter>>,
+  response_fairness: Option<Arc<ResponseFairness>>,
+  fair_queue: Option<Arc<FairQueue>>,
+  mut request: Request<BoxBody<Bytes, hyper::Error>>,
+  remote_address: SocketAddr,
+  local_address: SocketAddr,
+  encrypted: bool,
+  configuration: Arc<ActiveConfiguration>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
+  logger: Sender<LogMessage>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>, anyhow::Error> {
+  // Limit the number of requests in flight
+  let request_permit = match request_limiter {
+    Some(request_limiter) => match request_limiter.try_acquire(remote_address.ip()) {
+      Ok(request_permit) => Some(request_permit),
+      Err(limit) if request_limiter.is_observing() => {
+        // In the observe mode, the request over the limit is reported, but processed normally
+        METRICS.increment_counter(
+          "ferron_throttled_requests_total",
+          &[("limit", limit.as_str()), ("mode", "observe")],
+        );
+        logger
+          .send(LogMessage::new(
+            format!(
+              "The request from {} exceeds the {} request limit (not enforced, the limit is in the observe mode)",
+              remote_address.ip().to_canonical(),
+              limit.as_str()
+            ),
+            true,
+          ))
+          .await
+          .unwrap_or_default();
+        Some(request_limiter.acquire_over_limit(remote_address.ip()))
+      }
+      Err(limit) => {
+        METRICS.increment_counter(
+          "ferron_throttled_requests_total",
+          &[("limit", limit.as_str()), ("mode", "enforce")],
+        );
+        return Ok(
+          Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::CONTENT_TYPE, "text/html")
+            .body(
+              Full::new(Bytes::from(generate_default_error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                None,
+              )))
+              .map_err(|e| match e {})
+              .boxed(),
+            )
+            .unwrap_or_default(),
+        );
+      }
+    },
+    None => None,
+  };
+
+  // Limit the number of responses in flight on the connection. The requests above the limit wait for
+  // the earlier responses to be sent in the arrival order, so that a single HTTP/2 connection with many
+  // heavy streams can't monopolize the worker.
+  let response_permit = match &response_fairness {
+    Some(response_fairness) => match response_fairness.acquire_response_slot().await {
+      Ok(response_permit) => response_permit,
+      Err(_) => {
+        return Ok(
+          Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::CONTENT_TYPE, "text/html")
+            .body(
+              Full::new(Bytes::from(generate_default_error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                None,
+              )))
+              .map_err(|e| match e {})
+              .boxed(),
+            )
+            .unwrap_or_default(),
+        );
+      }
+    },
+    None => None,
+  };
+
+  let request_guard = connection_activity.start_request();
+  let request_stats_guard = SERVER_STATS.start_request();
+  let is_connect_request = request.method() == hyper::Method::CONNECT;
+
+  // The modules can send subrequests (for example, for the fragments included in the response),
+  // which are handled like the requests received on the same connection
+  let subrequest_configuration = configuration.clone();
+  let subrequest_geoip_database = geoip_database.clone();
+  let subrequest_logger = logger.clone();
+  request
+    .extensions_mut()
+    .insert(SubrequestHandler::new(move |subrequest| {
+      let internal_request = internal_request_handler(
+        subrequest,
+        remote_address,
+        local_address,
+        encrypted,
+        subrequest_configuration.clone(),
+        subrequest_geoip_database.clone(),
+        subrequest_logger.clone(),
+      );
+      async move { internal_request.await.map_err(|err| err.into()) }
+    }));
+
+  // The modules can buffer the request body for the inspection, spilling the large bodies to the temporary files
+  request.extensions_mut().insert(request_body_buffer());
+
+  let handlers_vec = configuration
+    .modules
+    .iter()
+    .map(|module| (module.get_name(), module.get_handlers(Handle::current())))
+    .collect::<Vec<(Arc<str>, Box<dyn ServerModuleHandlers + Send>)>>();
+  let response = request_handler(
+    request,
+    remote_address,
+    local_address,
+    encrypted,
+    configuration.global_config_root.clone(),
+    configuration.host_config.clone(),
+    geoip_database,
+    logger,
+    handlers_vec,
+    fair_queue,
+  )
+  .await?;
+
+  if response.status() == StatusCode::SWITCHING_PROTOCOLS
+    || (is_connect_request && response.status().is_success())
+  {
+    connection_activity.mark_upgraded();
+  }
+
+  // The request guards, the request permit and the configuration used by the request
+  // are dropped together with the response body, after the response is sent
+  let (response_parts, response_body) = response.into_parts();
+  let response_body = TrackedBody::new(
+    response_body,
+    (
+      request_guard,
+      request_stats_guard,
+      request_permit,
+      response_permit,
+      configuration,
+    ),
+  )
+  .boxed();
+  let response_body = match &response_fairness {
+    Some(response_fairness) => response_fairness.fair_body(response_body),
+    None => response_body,
+  };
+  Ok(Response::from_parts(response_parts, response_body))
+}
+
+// Handle a request originating from the server itself (for example, a subrequest or a cache prewarming request)
+// through the modules, without the connection and request limits
+async fn internal_request_handler(
+  mut request: Request<BoxBody<Bytes, hyper::Error>>,
+  remote_address: SocketAddr,
+  local_address: SocketAddr,
+  encrypted: bool,
+  configuration: Arc<ActiveConfiguration>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
+  logger: Sender<LogMessage>,
+) -> Result<Response<BoxBody<Bytes, std::io::Error>>, anyhow::Error> {
+  request.extensions_mut().insert(request_body_buffer());
+  let handlers_vec = configuration
+    .modules
+    .iter()
+    .map(|module| (module.get_name(), module.get_handlers(Handle::current())))
+    .collect::<Vec<(Arc<str>, Box<dyn ServerModuleHandlers + Send>)>>();
+  request_handler(
+    request,
+    remote_address,
+    local_address,
+    encrypted,
+    configuration.global_config_root.clone(),
+    configuration.host_config.clone(),
+    geoip_database,
+    logger,
+    handlers_vec,
+    None,
+  )
+  .await
+}
+
+// Reload the GeoIP databases from the files at the specified interval
+async fn reload_geoip_database(
+  geoip_database: Arc<GeoIpDatabase>,
+  reload_interval: time::Duration,
+  logger: Sender<LogMessage>,
+) {
+  let mut interval = time::interval_at(time::Instant::now() + reload_interval, reload_interval);
+  loop {
+    interval.tick().await;
+    let reload_database = geoip_database.clone();
+    let result = tokio::task::spawn_blocking(move || reload_database.reload()).await;
+    if let Ok(Err(err)) = result {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot reload the GeoIP database: {}", err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+    }
+  }
+}
+
+// Read the temporary bans from the shared storage periodically
+async fn refresh_temporary_bans(logger: Sender<LogMessage>) {
+  let mut interval = time::interval(TEMPORARY_BANS_REFRESH_INTERVAL);
+  loop {
+    interval.tick().await;
+    if let Err(err) = TEMPORARY_BANS.refresh().await {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot read the temporary bans from the storage: {}", err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+    }
+  }
+}
+
+// Run the cache prewarming jobs at their scheduled times. The jobs are read from the current configuration,
+// so the configuration reloads are taken into account. The requests are sent from the loopback address.
+async fn schedule_cache_prewarming(
+  live_configuration: Arc<LiveConfiguration>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
+  logger: Sender<LogMessage>,
+) {
+  loop {
+    // Wake up at the start of each minute
+    let now = Local::now();
+    time::sleep(time::Duration::from_millis(
+      60000 - (now.second() as u64 * 1000 + now.timestamp_subsec_millis() as u64 % 1000),
+    ))
+    .await;
+    let now = Local::now().time();
+
+    let configuration = live_configuration.get();
+    for job in prewarm_jobs(&configuration.yaml_config["global"]["cachePrewarmJobs"]) {
+      if !job.is_scheduled_at(now) {
+        continue;
+      }
+      let configuration = configuration.clone();
+      let geoip_database = geoip_database.clone();
+      let logger = logger.clone();
+      tokio::spawn(run_prewarm_job(job, move |request, encrypted| {
+        internal_request_handler(
+          request,
+          SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+          SocketAddr::from((Ipv4Addr::LOCALHOST, if encrypted { 443 } else { 80 })),
+          encrypted,
+          configuration.clone(),
+          geoip_database.clone(),
+          logger.clone(),
+        )
+      }));
+    }
+  }
+}
+
+// Log a rejected request, count it in the metrics and feed it into the auto-ban subsystem.
+// Returns false if the connection error isn't caused by a rejected request.
+async fn report_rejected_request(
+  err: &(dyn Error + Send + Sync + 'static),
+  client_ip: IpAddr,
+  logger: &Sender<LogMessage>,
+  auto_ban: &Option<Arc<AutoBan>>,
+) -> bool {
+  let reason = match classify_rejected_request(err) {
+    Some(reason) => reason,
+    None => return false,
+  };
+
+  METRICS.increment_counter("ferron_rejected_requests_total", &[("reason", reason)]);
+  logger
+    .send(LogMessage::new(
+      format!(
+        "Rejected a request (client: {}, reason: {}): {}",
+        client_ip, reason, err
+      ),
+      true,
+    ))
+    .await
+    .unwrap_or_default();
+
+  if let Some(auto_ban) = auto_ban {
+    if auto_ban.record_offense(client_ip).await {
+      let (mode, message) = if auto_ban.is_observing() {
+        (
+          "observe",
+          format!(
+            "The client {} would have been temporarily banned after repeated rejected requests (not enforced, the auto-ban is in the observe mode)",
+            client_ip
+          ),
+        )
+      } else {
+        (
+          "enforce",
+          format!(
+            "The client {} has been temporarily banned after repeated rejected requests",
+            client_ip
+          ),
+        )
+      };
+      METRICS.increment_counter("ferron_auto_bans_total", &[("mode", mode)]);
+      logger
+        .send(LogMessage::new(message, true))
+        .await
+        .unwrap_or_default();
+    }
+  }
+
+  true
+}
+
 // Function to accept and handle incoming connections
 #[allow(clippy::too_many_arguments)]
 async fn accept_connection(
   stream: TcpStream,
   remote_address: SocketAddr,
-  tls_acceptor_option: Option<TlsAcceptor>,
-  acme_acceptor_config_option: Option<(AcmeAcceptor, Arc<ServerConfig>)>,
-  global_config_root: Arc<ServerConfigRoot>,
-  host_config: Arc<Yaml>,
+  tls_configs_option: Option<ListenerTlsConfigs>,
+  acme_acceptor_option: Option<AcmeAcceptor>,
+  enable_http2: bool,
+  live_configuration: Arc<LiveConfiguration>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
+  auto_ban: Option<Arc<AutoBan>>,
+  connection_permit: Option<ConcurrencyPermit>,
+  request_limiter: Option<Arc<ConcurrencyLimiter>>,
+  fair_queue: Option<Arc<FairQueue>>,
+  connection_drain: ConnectionDrain,
   logger: Sender<LogMessage>,
-  modules: Arc<Vec<Box<dyn ServerModule + std::marker::Send + Sync>>>,
 ) {
   // Disable Nagle algorithm to improve performance
   if let Err(err) = stream.set_nodelay(true) {
@@ -62,8 +721,10 @@ async fn accept_connection(
     return;
   };
 
-  let global_config_root = global_config_root.clone();
-  let host_config = host_config.clone();
+  // The connection settings are read from the configuration active when the connection is accepted,
+  // while each request uses the configuration active when the request is received
+  let global_config_root = live_configuration.get().global_config_root.clone();
+  let geoip_database = geoip_database.clone();
 
   let local_address = match stream.local_addr() {
     Ok(local_address) => local_address,
@@ -81,10 +742,58 @@ async fn accept_connection(
 
   let logger_clone = logger.clone();
 
-  if let Some((acme_acceptor, tls_config)) = acme_acceptor_config_option {
-    tokio::task::spawn(async move {
-      let start_handshake = match acme_acceptor.accept(stream).await {
+  let keep_alive_timeout = get_timeout(&global_config_root, "keepAliveTimeout", Some(30000));
+  let stream_timeouts = StreamTimeouts {
+    header_read: get_timeout(&global_config_root, "headerReadTimeout", Some(30000)),
+    // The idle timeout before the first request defaults to the keep-alive timeout
+    pre_request_idle: if global_config_root
+      .get("preRequestIdleTimeout")
+      .is_badvalue()
+    {
+      keep_alive_timeout
+    } else {
+      get_timeout(&global_config_root, "preRequestIdleTimeout", None)
+    },
+    keep_alive: keep_alive_timeout,
+    web_socket_idle: get_timeout(&global_config_root, "webSocketIdleTimeout", None),
+    write: get_timeout(&global_config_root, "responseWriteTimeout", None),
+    header_min_rate: global_config_root
+      .get("requestHeaderMinRate")
+      .as_i64()
+      .map(|header_min_rate| header_min_rate as u64),
+    min_rate_grace_period: time::Duration::from_millis(
+      global_config_root
+        .get("minRateGracePeriod")
+        .as_i64()
+        .map(|grace_period| grace_period as u64)
+        .unwrap_or(DEFAULT_MIN_RATE_GRACE_PERIOD),
+    ),
+  };
+  let tls_handshake_deadline = get_timeout(&global_config_root, "tlsHandshakeTimeout", None)
+    .map(|tls_handshake_timeout| time::Instant::now() + tls_handshake_timeout);
+  let connection_activity = ConnectionActivity::new();
+  let reaped_connection_activity = connection_activity.clone();
+  let connection_stats_guard = SERVER_STATS.start_connection();
+
+  if let Some(tls_configs) = tls_configs_option {
+    connection_drain.clone().spawn(async move {
+      let _connection_permit = connection_permit;
+      let _connection_stats_guard = connection_stats_guard;
+      // The ClientHello is read first, so that the TLS configuration can be selected for the requested host
+      let start_handshake_result = match acme_acceptor_option {
+        Some(acme_acceptor) => {
+          tls_handshake_step(tls_handshake_deadline, acme_acceptor.accept(stream)).await
+        }
+        None => tls_handshake_step(
+          tls_handshake_deadline,
+          LazyConfigAcceptor::new(Acceptor::default(), stream),
+        )
+        .await
+        .map(Some),
+      };
+      let start_handshake = match start_handshake_result {
         Ok(Some(start_handshake)) => start_handshake,
+        // The connection was used for the ACME TLS-ALPN-01 challenge
         Ok(None) => return,
         Err(err) => {
           logger
@@ -98,7 +807,22 @@ async fn accept_connection(
         }
       };
 
-      let tls_stream = match start_handshake.into_stream(tls_config).await {
+      let server_name = start_handshake
+        .client_hello()
+        .server_name()
+        .map(String::from);
+      let (tls_config, enable_http2) = tls_configs.select(
+        server_name.as_deref(),
+        &live_configuration.get().host_config,
+      );
+      // The TLS policy negotiated for the server name, which the requests on the connection must be covered by
+      let connection_tls_policy = tls_configs.policy_index(server_name.as_deref());
+      let tls_stream = match tls_handshake_step(
+        tls_handshake_deadline,
+        start_handshake.into_stream(tls_config),
+      )
+      .await
+      {
         Ok(tls_stream) => tls_stream,
         Err(err) => {
           logger
@@ -112,349 +836,753 @@ async fn accept_connection(
         }
       };
 
-      let io = TokioIo::new(tls_stream);
+      // The identity of the client authenticated with a TLS client certificate or a raw public key
+      let tls_client_identity = tls_stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .and_then(|end_entity| client_identity(end_entity));
+      let tls_session = TlsSession::from_connection(tls_stream.get_ref().1);
+
+      let io = TokioIo::new(TimeoutStream::new(
+        tls_stream,
+        connection_activity.clone(),
+        stream_timeouts,
+      ));
       let mut builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
 
-      if let Some(enable_http2) = global_config_root.get("enableHTTP2").as_bool() {
-        if !enable_http2 {
-          builder = builder.http1_only();
-        }
-      } else {
+      if !enable_http2 {
         builder = builder.http1_only();
       }
 
       let mut http1_builder = &mut builder.http1();
-      http1_builder = http1_builder.timer(TokioTimer::new());
+      // The request header read timeout and the keep-alive timeout are enforced by the connection stream wrapper
+      http1_builder = http1_builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(None::<time::Duration>);
       let mut http2_builder = &mut http1_builder.http2();
       http2_builder = http2_builder.timer(TokioTimer::new());
-      let http2_settings = global_config_root.get("http2Settings");
-      if let Some(initial_window_size) = http2_settings["initialWindowSize"].as_i64() {
-        http2_builder = http2_builder.initial_stream_window_size(initial_window_size as u32);
-      }
-      if let Some(max_frame_size) = http2_settings["maxFrameSize"].as_i64() {
-        http2_builder = http2_builder.max_frame_size(max_frame_size as u32);
-      }
-      if let Some(max_concurrent_streams) = http2_settings["maxConcurrentStreams"].as_i64() {
-        http2_builder = http2_builder.max_concurrent_streams(max_concurrent_streams as u32);
-      }
-      if let Some(max_header_list_size) = http2_settings["maxHeaderListSize"].as_i64() {
-        http2_builder = http2_builder.max_header_list_size(max_header_list_size as u32);
-      }
-      if let Some(enable_connect_protocol) = http2_settings["enableConnectProtocol"].as_bool() {
-        if enable_connect_protocol {
-          http2_builder = http2_builder.enable_connect_protocol();
-        }
-      }
-
-      let handlers_vec = modules
-        .iter()
-        .map(|module| module.get_handlers(Handle::current()));
-
-      if let Err(err) = http2_builder
-        .serve_connection_with_upgrades(
-          io,
-          service_fn(move |request: Request<Incoming>| {
-            let global_config_root = global_config_root.clone();
-            let host_config = host_config.clone();
-            let logger = logger_clone.clone();
-            let handlers_vec_clone = handlers_vec
-              .clone()
-              .collect::<Vec<Box<dyn ServerModuleHandlers + Send>>>();
-            let (request_parts, request_body) = request.into_parts();
-            let request = Request::from_parts(request_parts, request_body.boxed());
-            request_handler(
-              request,
-              remote_address,
-              local_address,
-              true,
-              global_config_root,
-              host_config,
-              logger,
-              handlers_vec_clone,
-            )
-          }),
-        )
-        .await
-      {
-        logger
-          .send(LogMessage::new(
-            format!("Error serving HTTPS connection: {:?}", err),
+      let response_fairness =
+        configure_http2(http2_builder, &global_config_root.get("http2Settings"));
+
+      let connection = http2_builder.serve_connection_with_upgrades(
+        io,
+        service_fn(move |request: Request<Incoming>| {
+          let configuration = live_configuration.get();
+          let geoip_database = geoip_database.clone();
+          let connection_activity = connection_activity.clone();
+          let request_limiter = request_limiter.clone();
+          let response_fairness = response_fairness.clone();
+          let fair_queue = fair_queue.clone();
+          let logger = logger_clone.clone();
+          let (mut request_parts, request_body) = request.into_parts();
+          if let Some(tls_client_identity) = tls_client_identity.clone() {
+            request_parts.extensions.insert(tls_client_identity);
+          }
+          if let Some(tls_session) = tls_session.clone() {
+            request_parts.extensions.insert(tls_session);
+          }
+          // The requests for the hosts with a different TLS policy than the one negotiated in the handshake
+          // are rejected, so that the TLS policy (for example, the client authentication) can't be bypassed
+          // by sending a different server name than the "Host" header
+          let request_host = request_parts.uri.host().map(String::from).or_else(|| {
+            request_parts
+              .headers
+              .get(header::HOST)
+              .and_then(|host| host.to_str().ok())
+              .map(|host| strip_host_port(host).to_string())
+          });
+          let is_misdirected =
+            tls_configs.policy_index(request_host.as_deref()) != connection_tls_policy;
+          let request = Request::from_parts(request_parts, request_body.boxed());
+          let response_future = request_handler_tracked(
+            connection_activity,
+            request_limiter,
+            response_fairness,
+            fair_queue,
+            request,
+            remote_address,
+            local_address,
             true,
-          ))
-          .await
-          .unwrap_or_default();
-      }
-    });
-  } else if let Some(tls_acceptor) = tls_acceptor_option {
-    tokio::task::spawn(async move {
-      let tls_stream = match tls_acceptor.accept(stream).await {
-        Ok(tls_stream) => tls_stream,
-        Err(err) => {
+            configuration,
+            geoip_database,
+            logger,
+          );
+          async move {
+            if is_misdirected {
+              return Ok(
+                Response::builder()
+                  .status(StatusCode::MISDIRECTED_REQUEST)
+                  .header(header::CONTENT_TYPE, "text/html")
+                  .body(
+                    Full::new(Bytes::from(generate_default_error_page(
+                      StatusCode::MISDIRECTED_REQUEST,
+                      None,
+                    )))
+                    .map_err(|e| match e {})
+                    .boxed(),
+                  )
+                  .unwrap_or_default(),
+              );
+            }
+            response_future.await
+          }
+        }),
+      );
+      tokio::pin!(connection);
+      // When the server shuts down, the connection is closed gracefully: "Connection: close" is sent
+      // in the next HTTP/1.x response, and a GOAWAY frame is sent over HTTP/2
+      let connection_result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = connection_drain.cancelled() => {
+          connection.as_mut().graceful_shutdown();
+          connection.await
+        }
+      };
+      if let Err(err) = connection_result {
+        if !report_rejected_request(err.as_ref(), remote_address.ip(), &logger, &auto_ban).await {
           logger
             .send(LogMessage::new(
-              format!("Error during TLS handshake: {:?}", err),
+              format!("Error serving HTTPS connection: {:?}", err),
               true,
             ))
             .await
             .unwrap_or_default();
-          return;
         }
-      };
-
-      let io = TokioIo::new(tls_stream);
-      let mut builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
-
-      if let Some(enable_http2) = global_config_root.get("enableHTTP2").as_bool() {
-        if !enable_http2 {
-          builder = builder.http1_only();
-        }
-      } else {
-        builder = builder.http1_only();
-      }
-
-      let mut http1_builder = &mut builder.http1();
-      http1_builder = http1_builder.timer(TokioTimer::new());
-      let mut http2_builder = &mut http1_builder.http2();
-      http2_builder = http2_builder.timer(TokioTimer::new());
-      let http2_settings = global_config_root.get("http2Settings");
-      if let Some(initial_window_size) = http2_settings["initialWindowSize"].as_i64() {
-        http2_builder = http2_builder.initial_stream_window_size(initial_window_size as u32);
-      }
-      if let Some(max_frame_size) = http2_settings["maxFrameSize"].as_i64() {
-        http2_builder = http2_builder.max_frame_size(max_frame_size as u32);
-      }
-      if let Some(max_concurrent_streams) = http2_settings["maxConcurrentStreams"].as_i64() {
-        http2_builder = http2_builder.max_concurrent_streams(max_concurrent_streams as u32);
-      }
-      if let Some(max_header_list_size) = http2_settings["maxHeaderListSize"].as_i64() {
-        http2_builder = http2_builder.max_header_list_size(max_header_list_size as u32);
-      }
-      if let Some(enable_connect_protocol) = http2_settings["enableConnectProtocol"].as_bool() {
-        if enable_connect_protocol {
-          http2_builder = http2_builder.enable_connect_protocol();
-        }
-      }
-
-      let handlers_vec = modules
-        .iter()
-        .map(|module| module.get_handlers(Handle::current()));
-
-      if let Err(err) = http2_builder
-        .serve_connection_with_upgrades(
-          io,
-          service_fn(move |request: Request<Incoming>| {
-            let global_config_root = global_config_root.clone();
-            let host_config = host_config.clone();
-            let logger = logger_clone.clone();
-            let handlers_vec_clone = handlers_vec
-              .clone()
-              .collect::<Vec<Box<dyn ServerModuleHandlers + Send>>>();
-            let (request_parts, request_body) = request.into_parts();
-            let request = Request::from_parts(request_parts, request_body.boxed());
-            request_handler(
-              request,
-              remote_address,
-              local_address,
-              true,
-              global_config_root,
-              host_config,
-              logger,
-              handlers_vec_clone,
-            )
-          }),
-        )
-        .await
-      {
-        logger
-          .send(LogMessage::new(
-            format!("Error serving HTTPS connection: {:?}", err),
-            true,
-          ))
-          .await
-          .unwrap_or_default();
       }
+      log_reaped_connection(&reaped_connection_activity, remote_address, &logger).await;
     });
   } else {
-    let io = TokioIo::new(stream);
-    tokio::task::spawn(async move {
+    let io = TokioIo::new(TimeoutStream::new(
+      stream,
+      connection_activity.clone(),
+      stream_timeouts,
+    ));
+    connection_drain.clone().spawn(async move {
+      let _connection_permit = connection_permit;
+      let _connection_stats_guard = connection_stats_guard;
       let mut builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
-      if let Some(enable_http2) = global_config_root.get("enableHTTP2").as_bool() {
-        if !enable_http2 {
-          builder = builder.http1_only();
-        }
-      } else {
+      if !enable_http2 {
         builder = builder.http1_only();
       }
 
       let mut http1_builder = &mut builder.http1();
-      http1_builder = http1_builder.timer(TokioTimer::new());
+      // The request header read timeout and the keep-alive timeout are enforced by the connection stream wrapper
+      http1_builder = http1_builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(None::<time::Duration>);
       let mut http2_builder = &mut http1_builder.http2();
       http2_builder = http2_builder.timer(TokioTimer::new());
-      let http2_settings = global_config_root.get("http2Settings");
-      if let Some(initial_window_size) = http2_settings["initialWindowSize"].as_i64() {
-        http2_builder = http2_builder.initial_stream_window_size(initial_window_size as u32);
-      }
-      if let Some(max_frame_size) = http2_settings["maxFrameSize"].as_i64() {
-        http2_builder = http2_builder.max_frame_size(max_frame_size as u32);
-      }
-      if let Some(max_concurrent_streams) = http2_settings["maxConcurrentStreams"].as_i64() {
-        http2_builder = http2_builder.max_concurrent_streams(max_concurrent_streams as u32);
-      }
-      if let Some(max_header_list_size) = http2_settings["maxHeaderListSize"].as_i64() {
-        http2_builder = http2_builder.max_header_list_size(max_header_list_size as u32);
-      }
-      if let Some(enable_connect_protocol) = http2_settings["enableConnectProtocol"].as_bool() {
-        if enable_connect_protocol {
-          http2_builder = http2_builder.enable_connect_protocol();
+      let response_fairness =
+        configure_http2(http2_builder, &global_config_root.get("http2Settings"));
+
+      let connection = http2_builder.serve_connection_with_upgrades(
+        io,
+        service_fn(move |request: Request<Incoming>| {
+          let configuration = live_configuration.get();
+          let geoip_database = geoip_database.clone();
+          let connection_activity = connection_activity.clone();
+          let request_limiter = request_limiter.clone();
+          let response_fairness = response_fairness.clone();
+          let fair_queue = fair_queue.clone();
+          let logger = logger_clone.clone();
+          let (request_parts, request_body) = request.into_parts();
+          let request = Request::from_parts(request_parts, request_body.boxed());
+          request_handler_tracked(
+            connection_activity,
+            request_limiter,
+            response_fairness,
+            fair_queue,
+            request,
+            remote_address,
+            local_address,
+            false,
+            configuration,
+            geoip_database,
+            logger,
+          )
+        }),
+      );
+      tokio::pin!(connection);
+      // When the server shuts down, the connection is closed gracefully: "Connection: close" is sent
+      // in the next HTTP/1.x response, and a GOAWAY frame is sent over HTTP/2
+      let connection_result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = connection_drain.cancelled() => {
+          connection.as_mut().graceful_shutdown();
+          connection.await
+        }
+      };
+      if let Err(err) = connection_result {
+        if !report_rejected_request(err.as_ref(), remote_address.ip(), &logger, &auto_ban).await {
+          logger
+            .send(LogMessage::new(
+              format!("Error serving HTTP connection: {:?}", err),
+              true,
+            ))
+            .await
+            .unwrap_or_default();
         }
       }
-
-      let handlers_vec = modules
-        .iter()
-        .map(|module| module.get_handlers(Handle::current()));
-
-      if let Err(err) = http2_builder
-        .serve_connection_with_upgrades(
-          io,
-          service_fn(move |request: Request<Incoming>| {
-            let global_config_root = global_config_root.clone();
-            let host_config = host_config.clone();
-            let logger = logger_clone.clone();
-            let handlers_vec_clone = handlers_vec
-              .clone()
-              .collect::<Vec<Box<dyn ServerModuleHandlers + Send>>>();
-            let (request_parts, request_body) = request.into_parts();
-            let request = Request::from_parts(request_parts, request_body.boxed());
-            request_handler(
-              request,
-              remote_address,
-              local_address,
-              false,
-              global_config_root,
-              host_config,
-              logger,
-              handlers_vec_clone,
-            )
-          }),
-        )
-        .await
-      {
-        logger
-          .send(LogMessage::new(
-            format!("Error serving HTTP connection: {:?}", err),
-            true,
-          ))
-          .await
-          .unwrap_or_default();
-      }
+      log_reaped_connection(&reaped_connection_activity, remote_address, &logger).await;
     });
   }
 }
 
-// Main server event loop
-#[allow(clippy::type_complexity)]
-async fn server_event_loop(
-  yaml_config: Arc<Yaml>,
+// Create an OCSP stapler, which resolves the OCSP responders' host names using the configured DNS resolver
+fn create_ocsp_stapler(
+  inner: Arc<dyn ResolvesServerCert>,
+  global_config: &Yaml,
   logger: Sender<LogMessage>,
-  modules: Vec<Box<dyn ServerModule + Send + Sync>>,
-  module_config_validation_functions: Vec<
-    Symbol<'_, fn(&ServerConfigRoot, bool, bool) -> Result<(), Box<dyn Error + Send + Sync>>>,
-  >,
-  module_error: Option<anyhow::Error>,
-  modules_optional_builtin: Vec<String>,
-  first_startup: bool,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-  if let Some(module_error) = module_error {
+) -> OcspStapler {
+  let client = match reqwest::Client::builder()
+    .connect_timeout(time::Duration::from_millis(3000))
+    .timeout(time::Duration::from_millis(6000))
+    .dns_resolver(Arc::new(ReqwestDnsResolver))
+    .build()
+  {
+    Ok(http_client) => ocsp_stapler::Client::new_with_client(http_client),
+    Err(_) => ocsp_stapler::Client::new(),
+  };
+  OcspStapler::new(
+    inner,
+    client,
+    global_config["ocspCacheDirectory"]
+      .as_str()
+      .map(PathBuf::from),
+    global_config["ocspRefreshInterval"]
+      .as_i64()
+      .map(|refresh_interval| time::Duration::from_secs(refresh_interval as u64))
+      .unwrap_or(DEFAULT_OCSP_REFRESH_INTERVAL),
+    logger,
+  )
+}
+
+// The function validating the configuration for a module
+pub type ModuleConfigValidationFunction =
+  fn(&ServerConfigRoot, bool, bool) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+// The function loading the server configuration and initializing the modules for it
+pub type ServerConfigurationLoader =
+  Arc<dyn Fn() -> Result<ServerConfiguration, Box<dyn Error + Send + Sync>> + Send + Sync>;
+
+// The server configuration together with the modules initialized for it
+pub struct ServerConfiguration {
+  pub yaml_config: Arc<Yaml>,
+  pub config_origins: ConfigOrigins,
+  pub modules: Vec<MonitoredModule>,
+  pub module_config_validation_functions: Vec<ModuleConfigValidationFunction>,
+  pub module_error: Option<anyhow::Error>,
+  pub modules_optional_builtin: Vec<String>,
+  // The libraries of the external modules. They're declared last, so they're unloaded after the modules are dropped.
+  pub module_libraries: Vec<Library>,
+}
+
+// The global configuration properties, which are applied only when the server is started.
+// If any of them is changed, the server is restarted to apply the reloaded configuration.
+const RESTART_REQUIRED_GLOBAL_PROPERTIES: [&str; 65] = [
+  "adminApi",
+  "autoBanDuration",
+  "autoBanMode",
+  "autoBanThreshold",
+  "autoBanWindow",
+  "automaticTLSContactCacheDirectory",
+  "automaticTLSContactEmail",
+  "automaticTLSLetsEncryptProduction",
+  "cert",
+  "chroot",
+  "cipherSuite",
+  "connectionLimitMode",
+  "disableNonEncryptedServer",
+  "drainTimeout",
+  "ecdhCurve",
+  "enableAutomaticTLS",
+  "enableHTTP2",
+  "enableOCSPStapling",
+  "ocspCacheDirectory",
+  "ocspRefreshInterval",
+  "environmentVariables",
+  "errorLogDeduplicationWindow",
+  "errorLogFilePath",
+  "errorLogRateLimit",
+  "fairQueueingMaxRequests",
+  "fairQueueingRate",
+  "geoipAsnDatabase",
+  "geoipDatabase",
+  "geoipReloadInterval",
+  "group",
+  "key",
+  "listeners",
+  "loadModules",
+  "logFilePath",
+  "logging",
+  "moduleBlockingThreads",
+  "maxBlockingThreads",
+  "maxConnections",
+  "maxConnectionsPerIP",
+  "maxRequests",
+  "maxRequestsPerIP",
+  "port",
+  "requestLimitMode",
+  "resolver",
+  "secure",
+  "sni",
+  "sport",
+  "storageBackend",
+  "storageDirectory",
+  "storageRedisAddress",
+  "storageRedisDatabase",
+  "storageRedisPassword",
+  "tlsMaxVersion",
+  "tlsMinVersion",
+  "tlsPreset",
+  "errorLogLevel",
+  "moduleLogLevels",
+  "useClientCertificate",
+  "clientCertificateCA",
+  "clientCertificateCRL",
+  "clientCertificateMode",
+  "clientPublicKeyPins",
+  "clientRawPublicKeys",
+  "user",
+  "workerProcesses",
+];
+
+// The configuration used by the requests, together with the modules initialized for it
+struct ActiveConfiguration {
+  yaml_config: Arc<Yaml>,
+  global_config_root: Arc<ServerConfigRoot>,
+  host_config: Arc<Yaml>,
+  modules: Vec<MonitoredModule>,
+  // The scheduler of the modules' background jobs, which are cancelled when the modules are shut down
+  task_scheduler: TaskScheduler,
+  _module_libraries: Vec<Library>,
+}
+
+impl ActiveConfiguration {
+  fn new(configuration: ServerConfiguration, logger: &Sender<LogMessage>) -> Self {
+    Self {
+      global_config_root: Arc::new(ServerConfigRoot::new(&configuration.yaml_config["global"])),
+      host_config: Arc::new(configuration.yaml_config["hosts"].clone()),
+      yaml_config: configuration.yaml_config,
+      modules: configuration.modules,
+      task_scheduler: TaskScheduler::new(Handle::current(), ErrorLogger::new(logger.clone())),
+      _module_libraries: configuration.module_libraries,
+    }
+  }
+
+  // Run the lifecycle hook of the modules initialized for the configuration
+  async fn run_module_hook(
+    &self,
+    hook: ModuleLifecycleHook,
+    logger: &Sender<LogMessage>,
+  ) -> Result<(), anyhow::Error> {
+    run_module_hook(
+      &self.modules,
+      hook,
+      &self.global_config_root,
+      &self.task_scheduler,
+      logger,
+    )
+    .await
+  }
+}
+
+// The configuration used by the new requests, which is swapped atomically when the configuration is reloaded.
+// The requests already being processed keep using the configuration they were received with.
+struct LiveConfiguration {
+  active: RwLock<Arc<ActiveConfiguration>>,
+}
+
+impl LiveConfiguration {
+  fn new(active: ActiveConfiguration) -> Self {
+    Self {
+      active: RwLock::new(Arc::new(active)),
+    }
+  }
+
+  fn get(&self) -> Arc<ActiveConfiguration> {
+    match self.active.read() {
+      Ok(active) => active.clone(),
+      Err(poisoned) => poisoned.into_inner().clone(),
+    }
+  }
+
+  // Swap the configuration, and return the previous configuration
+  fn swap(&self, active: ActiveConfiguration) -> Arc<ActiveConfiguration> {
+    let mut active_locked = match self.active.write() {
+      Ok(active_locked) => active_locked,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    std::mem::replace(&mut *active_locked, Arc::new(active))
+  }
+}
+
+// Validate the server configuration with both the built-in and the module validation functions
+pub fn validate_server_configuration(
+  configuration: &ServerConfiguration,
+) -> Result<(), anyhow::Error> {
+  if let Some(module_error) = &configuration.module_error {
+    Err(anyhow::anyhow!(module_error.to_string()))?
+  }
+
+  let prepared_config =
+    prepare_config_for_validation(&configuration.yaml_config, &configuration.config_origins)
+      .map_err(|err| anyhow::anyhow!("Server configuration validation failed: {}", err))?;
+  let loaded_module_names = configuration
+    .modules
+    .iter()
+    .map(|module| module.get_name())
+    .collect::<Vec<_>>();
+  for (config_to_validate, is_global, is_location, origin) in prepared_config {
+    // The error message includes the place, where the invalid configuration is defined
+    let validation_error = |err: Box<dyn Error + Send + Sync>| match &origin {
+      Some(origin) => anyhow::anyhow!(
+        "Server configuration validation failed: {} (in {})",
+        err,
+        origin
+      ),
+      None => anyhow::anyhow!("Server configuration validation failed: {}", err),
+    };
+    let config_root_to_validate = ServerConfigRoot::new(&config_to_validate);
+    validate_config(
+      &config_root_to_validate,
+      is_global,
+      is_location,
+      &configuration.modules_optional_builtin,
+    )
+    .map_err(validation_error)?;
+    validate_module_lists(&config_root_to_validate, &loaded_module_names)
+      .map_err(validation_error)?;
+    for module_config_validation_function in configuration.module_config_validation_functions.iter()
+    {
+      module_config_validation_function(&config_root_to_validate, is_global, is_location)
+        .map_err(validation_error)?;
+    }
+  }
+
+  Ok(())
+}
+
+// Get the host domains, for which the certificates are obtained with automatic TLS
+fn automatic_tls_domains(yaml_config: &Yaml) -> Vec<&str> {
+  match yaml_config["hosts"].as_vec() {
+    Some(hosts) => hosts
+      .iter()
+      .filter_map(|host| host["domain"].as_str())
+      .filter(|domain| !domain.contains('*'))
+      .collect(),
+    None => Vec::new(),
+  }
+}
+
+// Check if the server has to be restarted to apply the new configuration
+fn requires_restart(old_yaml_config: &Yaml, new_yaml_config: &Yaml) -> bool {
+  RESTART_REQUIRED_GLOBAL_PROPERTIES
+    .iter()
+    .any(|property| old_yaml_config["global"][*property] != new_yaml_config["global"][*property])
+    || (new_yaml_config["global"]["enableAutomaticTLS"].as_bool() == Some(true)
+      && automatic_tls_domains(old_yaml_config) != automatic_tls_domains(new_yaml_config))
+}
+
+// Reload the configuration. The configuration is swapped without restarting the server, if it's possible.
+// The configuration that fails validation is rejected, and the previous configuration is kept.
+// Returns true if the server has to be restarted to apply the new configuration.
+async fn reload_configuration(
+  configuration_loader: &ServerConfigurationLoader,
+  live_configuration: &LiveConfiguration,
+  logger: &Sender<LogMessage>,
+) -> bool {
+  if let Some(reload_refusal) = reload_refusal(false) {
+    let message = format!(
+      "The server configuration reload was refused, keeping the previous configuration: {}",
+      reload_refusal
+    );
+    eprintln!("{}", message);
+    logger
+      .send(LogMessage::new(message, true))
+      .await
+      .unwrap_or_default();
+    return false;
+  }
+
+  let configuration_loader = configuration_loader.clone();
+  let configuration = match tokio::task::spawn_blocking(move || {
+    configuration_loader().map_err(|err| err.to_string())
+  })
+  .await
+  {
+    Ok(Ok(configuration)) => configuration,
+    Ok(Err(err)) => {
+      let message = format!("Cannot reload the server configuration: {}", err);
+      eprintln!("{}", message);
+      logger
+        .send(LogMessage::new(message, true))
+        .await
+        .unwrap_or_default();
+      return false;
+    }
+    Err(err) => {
+      let message = format!("Cannot reload the server configuration: {}", err);
+      eprintln!("{}", message);
+      logger
+        .send(LogMessage::new(message, true))
+        .await
+        .unwrap_or_default();
+      return false;
+    }
+  };
+
+  if let Err(err) = validate_server_configuration(&configuration) {
+    let message = format!(
+      "The server configuration reload was rejected, keeping the previous configuration: {}",
+      err
+    );
+    eprintln!("{}", message);
+    logger
+      .send(LogMessage::new(message, true))
+      .await
+      .unwrap_or_default();
+    return false;
+  }
+
+  if requires_restart(
+    &live_configuration.get().yaml_config,
+    &configuration.yaml_config,
+  ) {
+    // The privileges dropped after binding to the ports can't be regained by the restarted server
+    if let Some(reload_refusal) = reload_refusal(true) {
+      let message = format!(
+        "The server configuration reload was refused, keeping the previous configuration: {}. Restart the server to apply the configuration.",
+        reload_refusal
+      );
+      eprintln!("{}", message);
+      logger
+        .send(LogMessage::new(message, true))
+        .await
+        .unwrap_or_default();
+      return false;
+    }
+    return true;
+  }
+
+  // The modules of the reloaded configuration are prepared before they handle the requests
+  let active_configuration = ActiveConfiguration::new(configuration, logger);
+  if let Err(err) = active_configuration
+    .run_module_hook(ModuleLifecycleHook::ConfigReload, logger)
+    .await
+  {
+    let message = format!(
+      "The server configuration reload was rejected, keeping the previous configuration: {}",
+      err
+    );
+    eprintln!("{}", message);
     logger
-      .send(LogMessage::new(module_error.to_string(), true))
+      .send(LogMessage::new(message, true))
+      .await
+      .unwrap_or_default();
+    active_configuration
+      .run_module_hook(ModuleLifecycleHook::Shutdown, logger)
       .await
       .unwrap_or_default();
-    Err(module_error)?
+    return false;
   }
 
-  let prepared_config = match prepare_config_for_validation(&yaml_config) {
-    Ok(prepared_config) => prepared_config,
+  set_active_config(active_configuration.yaml_config.clone());
+  let previous_configuration = live_configuration.swap(active_configuration);
+  previous_configuration
+    .run_module_hook(ModuleLifecycleHook::Shutdown, logger)
+    .await
+    .unwrap_or_default();
+  println!("The server configuration has been reloaded");
+  false
+}
+
+// Load the TLS certificate chain and the private key into a certified key
+async fn load_certified_key(
+  cert_path: &str,
+  key_path: &str,
+  crypto_provider: &CryptoProvider,
+  logger: &Sender<LogMessage>,
+) -> Result<CertifiedKey, anyhow::Error> {
+  let certs = match load_certs(cert_path) {
+    Ok(certs) => certs,
     Err(err) => {
       logger
         .send(LogMessage::new(
-          format!("Server configuration validation failed: {}", err),
+          format!("Cannot load the \"{}\" TLS certificate: {}", cert_path, err),
           true,
         ))
         .await
         .unwrap_or_default();
       Err(anyhow::anyhow!(format!(
-        "Server configuration validation failed: {}",
-        err
+        "Cannot load the \"{}\" TLS certificate: {}",
+        cert_path, err
+      )))?
+    }
+  };
+  let key = match load_private_key(key_path) {
+    Ok(key) => key,
+    Err(err) => {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot load the \"{}\" private key: {}", key_path, err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+      Err(anyhow::anyhow!(format!(
+        "Cannot load the \"{}\" private key: {}",
+        key_path, err
+      )))?
+    }
+  };
+  let signing_key = match crypto_provider.key_provider.load_private_key(key) {
+    Ok(key) => key,
+    Err(err) => {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot load the \"{}\" private key: {}", key_path, err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+      Err(anyhow::anyhow!(format!(
+        "Cannot load the \"{}\" private key: {}",
+        key_path, err
       )))?
     }
   };
+  Ok(CertifiedKey::new(certs, signing_key))
+}
 
-  for (config_to_validate, is_global, is_location) in prepared_config {
-    let config_root_to_validate = ServerConfigRoot::new(&config_to_validate);
-    match validate_config(
-      &config_root_to_validate,
-      is_global,
-      is_location,
-      &modules_optional_builtin,
-    ) {
-      Ok(_) => (),
+// Check the loaded TLS certificate for the common misconfigurations, reporting the warnings.
+// Returns an error if the certificate can't be used.
+async fn check_loaded_certificate(
+  cert_path: &str,
+  certified_key: &CertifiedKey,
+  host_names: &[&str],
+  logger: &Sender<LogMessage>,
+) -> Result<(), anyhow::Error> {
+  let problems = check_certificate(certified_key, host_names, SystemTime::now());
+  for warning in problems.warnings {
+    let message = format!("TLS certificate \"{}\": {}", cert_path, warning);
+    eprintln!("WARNING: {}", message);
+    logger
+      .send(LogMessage::new(message, true))
+      .await
+      .unwrap_or_default();
+  }
+  if let Some(error) = problems.errors.first() {
+    logger
+      .send(LogMessage::new(
+        format!(
+          "Cannot load the \"{}\" TLS certificate: {}",
+          cert_path, error
+        ),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!(format!(
+      "Cannot load the \"{}\" TLS certificate: {}",
+      cert_path, error
+    )))?
+  }
+  Ok(())
+}
+
+// The server prepared before the privileges are dropped
+struct PreparedServer {
+  configuration: ServerConfiguration,
+  tcp_listeners: Vec<(TcpListener, ListenerConfig)>,
+  crypto_provider: CryptoProvider,
+  tls_config_builder: ConfigBuilder<ServerConfig, WantsVerifier>,
+  min_tls_version: Option<String>,
+  max_tls_version: Option<String>,
+  sni_tls_policies: Vec<(String, TlsPolicy)>,
+  cert_resolver: Arc<dyn ResolvesServerCert>,
+  acme_tls_acceptor: Option<AcmeAcceptor>,
+  geoip_database: Option<Arc<GeoIpDatabase>>,
+  admin_api_listener: Option<AdminListener>,
+}
+
+// Prepare the server before the privileges are dropped. The TLS certificates, the ports, the GeoIP databases
+// and the admin API address might be accessible only to the privileged user.
+async fn prepare_server(
+  configuration: ServerConfiguration,
+  systemd_listeners: Vec<std::net::TcpListener>,
+  logger: Sender<LogMessage>,
+  first_startup: bool,
+) -> Result<PreparedServer, Box<dyn Error + Send + Sync>> {
+  if let Err(err) = validate_server_configuration(&configuration) {
+    logger
+      .send(LogMessage::new(err.to_string(), true))
+      .await
+      .unwrap_or_default();
+    Err(err)?
+  }
+  let yaml_config = configuration.yaml_config.clone();
+
+  // Configure the DNS resolver used for the server-side lookups (proxying, health checks and OCSP stapling)
+  let dns_resolver = match yaml_config["global"]["resolver"].as_hash() {
+    Some(_) => match DnsResolver::from_config(&yaml_config["global"]["resolver"]) {
+      Ok(dns_resolver) => Some(dns_resolver),
       Err(err) => {
         logger
           .send(LogMessage::new(
-            format!("Server configuration validation failed: {}", err),
+            format!("Cannot configure the DNS resolver: {}", err),
             true,
           ))
           .await
           .unwrap_or_default();
         Err(anyhow::anyhow!(format!(
-          "Server configuration validation failed: {}",
+          "Cannot configure the DNS resolver: {}",
           err
         )))?
       }
-    };
-    let module_config_validation_functions_iter = module_config_validation_functions.iter();
-    for module_config_validation_function in module_config_validation_functions_iter {
-      match module_config_validation_function(&config_root_to_validate, is_global, is_location) {
-        Ok(_) => (),
-        Err(err) => {
-          logger
-            .send(LogMessage::new(
-              format!("Server configuration validation failed: {}", err),
-              true,
-            ))
-            .await
-            .unwrap_or_default();
-          Err(anyhow::anyhow!(format!(
-            "Server configuration validation failed: {}",
-            err
-          )))?
-        }
-      };
-    }
-  }
+    },
+    None => None,
+  };
+  set_dns_resolver(dns_resolver);
 
   let mut crypto_provider = default_provider();
 
+  // The TLS preset selects the cipher suites, the key exchange groups and the minimum TLS version at once.
+  // The explicitly configured cipher suites, ECDH curves and TLS versions override the preset.
+  let tls_preset = match yaml_config["global"]["tlsPreset"].as_str() {
+    Some(tls_preset_name) => match TlsPreset::from_name(tls_preset_name) {
+      Some(tls_preset) => Some(tls_preset),
+      None => {
+        logger
+          .send(LogMessage::new(
+            format!("The \"{}\" TLS preset is not supported", tls_preset_name),
+            true,
+          ))
+          .await
+          .unwrap_or_default();
+        Err(anyhow::anyhow!(format!(
+          "The \"{}\" TLS preset is not supported",
+          tls_preset_name
+        )))?
+      }
+    },
+    None => None,
+  };
+  if let Some(tls_preset) = tls_preset {
+    crypto_provider.cipher_suites = tls_preset.cipher_suites();
+    crypto_provider.kx_groups = tls_preset.kx_groups();
+  }
+
   if let Some(cipher_suite) = yaml_config["global"]["cipherSuite"].as_vec() {
     let mut cipher_suites = Vec::new();
     let cipher_suite_iter = cipher_suite.iter();
     for cipher_suite_yaml in cipher_suite_iter {
       if let Some(cipher_suite) = cipher_suite_yaml.as_str() {
-        let cipher_suite_to_add = match cipher_suite {
-          "TLS_AES_128_GCM_SHA256" => TLS13_AES_128_GCM_SHA256,
-          "TLS_AES_256_GCM_SHA384" => TLS13_AES_256_GCM_SHA384,
-          "TLS_CHACHA20_POLY1305_SHA256" => TLS13_CHACHA20_POLY1305_SHA256,
-          "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256" => TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
-          "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384" => TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
-          "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256" => {
-            TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
-          }
-          "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256" => TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-          "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384" => TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-          "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256" => {
-            TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
-          }
-          _ => {
+        let cipher_suite_to_add = match cipher_suite_by_name(cipher_suite) {
+          Some(cipher_suite_to_add) => cipher_suite_to_add,
+          None => {
             logger
               .send(LogMessage::new(
                 format!("The \"{}\" cipher suite is not supported", cipher_suite),
@@ -528,59 +1656,45 @@ async fn server_event_loop(
   }
 
   if !automatic_tls_enabled {
-    // Load public certificate and private key
-    if let Some(cert_path) = yaml_config["global"]["cert"].as_str() {
-      if let Some(key_path) = yaml_config["global"]["key"].as_str() {
-        let certs = match load_certs(cert_path) {
-          Ok(certs) => certs,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Cannot load the \"{}\" TLS certificate: {}", cert_path, err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Cannot load the \"{}\" TLS certificate: {}",
-              cert_path, err
-            )))?
-          }
-        };
-        let key = match load_private_key(key_path) {
-          Ok(key) => key,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Cannot load the \"{}\" private key: {}", cert_path, err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Cannot load the \"{}\" private key: {}",
-              cert_path, err
-            )))?
-          }
-        };
-        let signing_key = match crypto_provider_cloned.key_provider.load_private_key(key) {
-          Ok(key) => key,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Cannot load the \"{}\" private key: {}", cert_path, err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Cannot load the \"{}\" private key: {}",
-              cert_path, err
-            )))?
+    // The host names, for which the SNI certificates are configured
+    let sni_hostnames = yaml_config["global"]["sni"]
+      .as_hash()
+      .map(|sni| {
+        sni
+          .keys()
+          .filter_map(|key| key.as_str())
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+
+    // Load public certificates and private keys
+    if let Some(cert_key_paths) = certificate_key_paths(
+      &yaml_config["global"]["cert"],
+      &yaml_config["global"]["key"],
+    ) {
+      // The default certificate is used for the hosts not covered by the SNI certificates
+      let mut host_names = Vec::new();
+      if let Some(hosts) = yaml_config["hosts"].as_vec() {
+        for domain in hosts.iter().filter_map(|host| host["domain"].as_str()) {
+          if domain != "*"
+            && !host_names.contains(&domain)
+            && !sni_hostnames
+              .iter()
+              .any(|sni_hostname| match_hostname(Some(sni_hostname), Some(domain)))
+          {
+            host_names.push(domain);
           }
-        };
-        let certified_key = CertifiedKey::new(certs, signing_key);
-        sni_resolver.load_fallback_cert_key(Arc::new(certified_key));
+        }
+      }
+
+      for (cert_path, key_path) in cert_key_paths {
+        let certified_key =
+          load_certified_key(cert_path, key_path, &crypto_provider_cloned, &logger).await?;
+        check_loaded_certificate(cert_path, &certified_key, &host_names, &logger).await?;
+
+        let certified_key_arc = Arc::new(certified_key);
+        sni_resolver.load_fallback_cert_key(certified_key_arc.clone());
+        certified_keys.push(certified_key_arc);
       }
     }
 
@@ -588,57 +1702,20 @@ async fn server_event_loop(
       let sni_hostnames = sni.keys();
       for sni_hostname_unknown in sni_hostnames {
         if let Some(sni_hostname) = sni_hostname_unknown.as_str() {
-          if let Some(cert_path) = sni[sni_hostname_unknown]["cert"].as_str() {
-            if let Some(key_path) = sni[sni_hostname_unknown]["key"].as_str() {
-              let certs = match load_certs(cert_path) {
-                Ok(certs) => certs,
-                Err(err) => {
-                  logger
-                    .send(LogMessage::new(
-                      format!("Cannot load the \"{}\" TLS certificate: {}", cert_path, err),
-                      true,
-                    ))
-                    .await
-                    .unwrap_or_default();
-                  Err(anyhow::anyhow!(format!(
-                    "Cannot load the \"{}\" TLS certificate: {}",
-                    cert_path, err
-                  )))?
-                }
-              };
-              let key = match load_private_key(key_path) {
-                Ok(key) => key,
-                Err(err) => {
-                  logger
-                    .send(LogMessage::new(
-                      format!("Cannot load the \"{}\" private key: {}", cert_path, err),
-                      true,
-                    ))
-                    .await
-                    .unwrap_or_default();
-                  Err(anyhow::anyhow!(format!(
-                    "Cannot load the \"{}\" private key: {}",
-                    cert_path, err
-                  )))?
-                }
-              };
-              let signing_key = match crypto_provider_cloned.key_provider.load_private_key(key) {
-                Ok(key) => key,
-                Err(err) => {
-                  logger
-                    .send(LogMessage::new(
-                      format!("Cannot load the \"{}\" private key: {}", cert_path, err),
-                      true,
-                    ))
-                    .await
-                    .unwrap_or_default();
-                  Err(anyhow::anyhow!(format!(
-                    "Cannot load the \"{}\" private key: {}",
-                    cert_path, err
-                  )))?
-                }
-              };
-              let certified_key_arc = Arc::new(CertifiedKey::new(certs, signing_key));
+          if let Some(cert_key_paths) = certificate_key_paths(
+            &sni[sni_hostname_unknown]["cert"],
+            &sni[sni_hostname_unknown]["key"],
+          ) {
+            let host_names = match sni_hostname {
+              "*" => Vec::new(),
+              _ => vec![sni_hostname],
+            };
+            for (cert_path, key_path) in cert_key_paths {
+              let certified_key =
+                load_certified_key(cert_path, key_path, &crypto_provider_cloned, &logger).await?;
+              check_loaded_certificate(cert_path, &certified_key, &host_names, &logger).await?;
+
+              let certified_key_arc = Arc::new(certified_key);
               sni_resolver.load_host_cert_key(sni_hostname, certified_key_arc.clone());
               certified_keys.push(certified_key_arc);
             }
@@ -650,247 +1727,101 @@ async fn server_event_loop(
 
   // Build TLS configuration
   let tls_config_builder_wants_versions =
-    ServerConfig::builder_with_provider(Arc::new(crypto_provider_cloned));
+    ServerConfig::builder_with_provider(Arc::new(crypto_provider_cloned.clone()));
 
-  // Very simple minimum and maximum TLS version logic for now...
-  let min_tls_version_option = yaml_config["global"]["tlsMinVersion"].as_str();
+  let min_tls_version_option = yaml_config["global"]["tlsMinVersion"]
+    .as_str()
+    .or(tls_preset.map(|tls_preset| tls_preset.min_version()));
   let max_tls_version_option = yaml_config["global"]["tlsMaxVersion"].as_str();
-  let tls_config_builder_wants_verifier = match min_tls_version_option {
-    Some("TLSv1.3") => match max_tls_version_option {
-      Some("TLSv1.2") => {
-        logger
-          .send(LogMessage::new(
-            String::from("The maximum TLS version is older than the minimum TLS version"),
-            true,
-          ))
-          .await
-          .unwrap_or_default();
-        Err(anyhow::anyhow!(String::from(
-          "The maximum TLS version is older than the minimum TLS version"
-        )))?
-      }
-      Some("TLSv1.3") | None => {
-        match tls_config_builder_wants_versions.with_protocol_versions(&[&TLS13]) {
-          Ok(builder) => builder,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Couldn't create the TLS server configuration: {}", err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Couldn't create the TLS server configuration: {}",
-              err
-            )))?
-          }
-        }
-      }
-      _ => {
-        logger
-          .send(LogMessage::new(
-            String::from("Invalid maximum TLS version"),
-            true,
-          ))
-          .await
-          .unwrap_or_default();
-        Err(anyhow::anyhow!(String::from("Invalid maximum TLS version")))?
-      }
-    },
-    Some("TLSv1.2") | None => match max_tls_version_option {
-      Some("TLSv1.2") => {
-        match tls_config_builder_wants_versions.with_protocol_versions(&[&TLS12]) {
-          Ok(builder) => builder,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Couldn't create the TLS server configuration: {}", err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Couldn't create the TLS server configuration: {}",
-              err
-            )))?
-          }
-        }
-      }
-      Some("TLSv1.3") | None => {
-        match tls_config_builder_wants_versions.with_protocol_versions(&[&TLS12, &TLS13]) {
-          Ok(builder) => builder,
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Couldn't create the TLS server configuration: {}", err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!(format!(
-              "Couldn't create the TLS server configuration: {}",
-              err
-            )))?
-          }
-        }
-      }
-      _ => {
-        logger
-          .send(LogMessage::new(
-            String::from("Invalid maximum TLS version"),
-            true,
-          ))
-          .await
-          .unwrap_or_default();
-        Err(anyhow::anyhow!(String::from("Invalid maximum TLS version")))?
-      }
-    },
-    _ => {
-      logger
-        .send(LogMessage::new(
-          String::from("Invalid minimum TLS version"),
-          true,
-        ))
-        .await
-        .unwrap_or_default();
-      Err(anyhow::anyhow!(String::from("Invalid minimum TLS version")))?
-    }
-  };
-
-  let tls_config_builder_wants_server_cert =
-    match yaml_config["global"]["useClientCertificate"].as_bool() {
-      Some(true) => {
-        let mut roots = RootCertStore::empty();
-        let certs_result = load_native_certs();
-        if !certs_result.errors.is_empty() {
+  let tls_config_builder_wants_verifier =
+    match protocol_versions(min_tls_version_option, max_tls_version_option) {
+      Ok(versions) => match tls_config_builder_wants_versions.with_protocol_versions(versions) {
+        Ok(builder) => builder,
+        Err(err) => {
           logger
             .send(LogMessage::new(
-              format!(
-                "Couldn't load the native certificate store: {}",
-                certs_result.errors[0]
-              ),
+              format!("Couldn't create the TLS server configuration: {}", err),
               true,
             ))
             .await
             .unwrap_or_default();
           Err(anyhow::anyhow!(format!(
-            "Couldn't load the native certificate store: {}",
-            certs_result.errors[0]
+            "Couldn't create the TLS server configuration: {}",
+            err
           )))?
         }
-        let certs = certs_result.certs;
+      },
+      Err(err) => {
+        logger
+          .send(LogMessage::new(err.to_string(), true))
+          .await
+          .unwrap_or_default();
+        Err(anyhow::anyhow!(err.to_string()))?
+      }
+    };
 
-        for cert in certs {
-          match roots.add(cert) {
-            Ok(_) => (),
-            Err(err) => {
-              logger
-                .send(LogMessage::new(
-                  format!(
-                    "Couldn't add a certificate to the certificate store: {}",
-                    err
-                  ),
-                  true,
-                ))
-                .await
-                .unwrap_or_default();
-              Err(anyhow::anyhow!(format!(
-                "Couldn't add a certificate to the certificate store: {}",
-                err
-              )))?
+  // The TLS policies overridden for the SNI host names
+  let mut sni_tls_policies = Vec::new();
+  if let Some(sni) = yaml_config["global"]["sni"].as_hash() {
+    for (sni_hostname, sni_config) in sni.iter() {
+      if let Some(sni_hostname) = sni_hostname.as_str() {
+        match TlsPolicy::from_yaml(sni_config) {
+          Ok(tls_policy) => {
+            if tls_policy.is_override() {
+              sni_tls_policies.push((sni_hostname.to_string(), tls_policy));
             }
           }
+          Err(err) => {
+            let message = format!("Invalid TLS policy for \"{}\": {}", sni_hostname, err);
+            logger
+              .send(LogMessage::new(message.clone(), true))
+              .await
+              .unwrap_or_default();
+            Err(anyhow::anyhow!(message))?
+          }
         }
-        tls_config_builder_wants_verifier
-          .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
       }
-      _ => tls_config_builder_wants_verifier.with_no_client_auth(),
-    };
-
-  let mut tls_config;
+    }
+  }
 
-  let mut addr = SocketAddr::from((IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 80));
-  let mut addr_tls = SocketAddr::from((IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 443));
-  let mut tls_enabled = false;
-  let mut non_tls_disabled = false;
+  let cert_resolver: Arc<dyn ResolvesServerCert>;
 
   // Install a process-wide cryptography provider. If it fails, then warn about it.
   if crypto_provider.install_default().is_err() && first_startup {
     logger
       .send(LogMessage::new(
-        "Cannot install a process-wide cryptography provider".to_string(),
-        true,
-      ))
-      .await
-      .unwrap_or_default();
-    Err(anyhow::anyhow!(
-      "Cannot install a process-wide cryptography provider"
-    ))?;
-  }
-
-  // Read port configurations from YAML
-  if let Some(read_port) = yaml_config["global"]["port"].as_i64() {
-    addr = SocketAddr::from((
-      IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
-      match read_port.try_into() {
-        Ok(port) => port,
-        Err(_) => {
-          logger
-            .send(LogMessage::new(String::from("Invalid HTTP port"), true))
-            .await
-            .unwrap_or_default();
-          Err(anyhow::anyhow!("Invalid HTTP port"))?
-        }
-      },
-    ));
-  } else if let Some(read_port) = yaml_config["global"]["port"].as_str() {
-    addr = match read_port.parse() {
-      Ok(addr) => addr,
-      Err(_) => {
-        logger
-          .send(LogMessage::new(String::from("Invalid HTTP port"), true))
-          .await
-          .unwrap_or_default();
-        Err(anyhow::anyhow!("Invalid HTTP port"))?
-      }
-    };
+        "Cannot install a process-wide cryptography provider".to_string(),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!(
+      "Cannot install a process-wide cryptography provider"
+    ))?;
   }
 
-  if let Some(read_tls_enabled) = yaml_config["global"]["secure"].as_bool() {
-    tls_enabled = read_tls_enabled;
-    if let Some(read_non_tls_disabled) =
-      yaml_config["global"]["disableNonEncryptedServer"].as_bool()
-    {
-      non_tls_disabled = read_non_tls_disabled;
+  // Read listener configurations from YAML
+  let listeners = match load_listeners(&yaml_config["global"]) {
+    Ok(listeners) => listeners,
+    Err(err) => {
+      logger
+        .send(LogMessage::new(err.to_string(), true))
+        .await
+        .unwrap_or_default();
+      Err(anyhow::anyhow!(err.to_string()))?
     }
-  }
+  };
+  let tls_enabled = listeners
+    .iter()
+    .any(|listener_config| listener_config.secure);
 
-  if let Some(read_port) = yaml_config["global"]["sport"].as_i64() {
-    addr_tls = SocketAddr::from((
-      IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
-      match read_port.try_into() {
-        Ok(port) => port,
-        Err(_) => {
-          logger
-            .send(LogMessage::new(String::from("Invalid HTTPS port"), true))
-            .await
-            .unwrap_or_default();
-          Err(anyhow::anyhow!("Invalid HTTPS port"))?
-        }
-      },
-    ));
-  } else if let Some(read_port) = yaml_config["global"]["sport"].as_str() {
-    addr_tls = match read_port.parse() {
-      Ok(addr) => addr,
-      Err(_) => {
-        logger
-          .send(LogMessage::new(String::from("Invalid HTTPS port"), true))
-          .await
-          .unwrap_or_default();
-        Err(anyhow::anyhow!("Invalid HTTPS port"))?
-      }
-    };
+  // Report the HSTS policies, which are inconsistent with the way the hosts are served
+  for warning in check_hsts_configuration(&yaml_config, &listeners) {
+    eprintln!("WARNING: {}", warning);
+    logger
+      .send(LogMessage::new(warning, true))
+      .await
+      .unwrap_or_default();
   }
 
   // Get domains for ACME configuration
@@ -922,11 +1853,14 @@ async fn server_event_loop(
     let mut acme_state = acme_config_with_cache.state();
     let acceptor = acme_state.acceptor();
 
-    // Create TLS configuration
-    tls_config = match yaml_config["global"]["enableOCSPStapling"].as_bool() {
-      Some(true) => tls_config_builder_wants_server_cert
-        .with_cert_resolver(Arc::new(Stapler::new(acme_state.resolver()))),
-      _ => tls_config_builder_wants_server_cert.with_cert_resolver(acme_state.resolver()),
+    // Create the TLS certificate resolver
+    cert_resolver = match yaml_config["global"]["enableOCSPStapling"].as_bool() {
+      Some(true) => Arc::new(create_ocsp_stapler(
+        acme_state.resolver(),
+        &yaml_config["global"],
+        logger.clone(),
+      )),
+      _ => acme_state.resolver(),
     };
 
     let acme_logger = logger.clone();
@@ -946,16 +1880,20 @@ async fn server_event_loop(
 
     Some(acceptor)
   } else {
-    // Create TLS configuration
-    tls_config = match yaml_config["global"]["enableOCSPStapling"].as_bool() {
+    // Create the TLS certificate resolver
+    cert_resolver = match yaml_config["global"]["enableOCSPStapling"].as_bool() {
       Some(true) => {
-        let ocsp_stapler_arc = Arc::new(Stapler::new(Arc::new(sni_resolver)));
+        let ocsp_stapler_arc = Arc::new(create_ocsp_stapler(
+          Arc::new(sni_resolver),
+          &yaml_config["global"],
+          logger.clone(),
+        ));
         for certified_key in certified_keys.iter() {
           ocsp_stapler_arc.preload(certified_key.clone());
         }
-        tls_config_builder_wants_server_cert.with_cert_resolver(ocsp_stapler_arc.clone())
+        ocsp_stapler_arc
       }
-      _ => tls_config_builder_wants_server_cert.with_cert_resolver(Arc::new(sni_resolver)),
+      _ => Arc::new(sni_resolver),
     };
 
     // Drop the ACME configuration
@@ -963,177 +1901,572 @@ async fn server_event_loop(
     None
   };
 
-  // Configure ALPN protocols
-  let mut alpn_protocols = vec![b"http/1.1".to_vec(), b"http/1.0".to_vec()];
-  if let Some(enable_http2) = yaml_config["global"]["enableHTTP2"].as_bool() {
-    if enable_http2 {
-      alpn_protocols.insert(0, b"h2".to_vec());
-    }
-  }
-  tls_config.alpn_protocols = alpn_protocols;
-  let tls_config_arc = Arc::new(tls_config);
+  let mut tcp_listeners = Vec::new();
 
-  let acme_tls_acceptor_and_config =
-    acme_tls_acceptor.map(|acceptor| (acceptor, tls_config_arc.clone()));
+  // If the server is socket-activated, only the systemd-passed sockets are used
+  let listeners_to_bind = if systemd_listeners.is_empty() {
+    listeners
+  } else {
+    for systemd_listener in systemd_listeners {
+      let address = systemd_listener.local_addr()?;
+      let listener_config = match_listener_config(address, &listeners, &yaml_config["global"]);
+      println!(
+        "{} server is listening at {} ({})",
+        if listener_config.secure {
+          "HTTPS"
+        } else {
+          "HTTP"
+        },
+        listener_config.address,
+        if env::var_os(WORKER_PROCESS_ENV).is_some() {
+          format!("worker process {}", std::process::id())
+        } else {
+          String::from("socket-activated")
+        }
+      );
+      tcp_listeners.push((TcpListener::from_std(systemd_listener)?, listener_config));
+    }
+    Vec::new()
+  };
 
-  // Create TLS acceptor
-  let tls_acceptor = TlsAcceptor::from(tls_config_arc.clone());
+  // Bind to the specified addresses
+  for listener_config in listeners_to_bind {
+    println!(
+      "{} server is listening at {}",
+      if listener_config.secure {
+        "HTTPS"
+      } else {
+        "HTTP"
+      },
+      listener_config.address
+    );
+    let tcp_listener = match bind_listener(&listener_config) {
+      Ok(tcp_listener) => tcp_listener,
+      Err(err) => {
+        logger
+          .send(LogMessage::new(
+            format!("Cannot listen to {}: {}", listener_config.address, err),
+            true,
+          ))
+          .await
+          .unwrap_or_default();
+        Err(anyhow::anyhow!(format!(
+          "Cannot listen to {}: {}",
+          listener_config.address, err
+        )))?
+      }
+    };
+    tcp_listeners.push((tcp_listener, listener_config));
+  }
 
-  let mut listener = None;
-  let mut listener_tls = None;
+  if tcp_listeners.is_empty() {
+    // No server is listening...
+    logger
+      .send(LogMessage::new(
+        String::from("No server is listening"),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!("No server is listening"))?;
+  }
 
-  // Bind to the specified ports
-  if !non_tls_disabled {
-    println!("HTTP server is listening at {}", addr);
-    listener = Some(match TcpListener::bind(addr).await {
-      Ok(listener) => listener,
+  // Load the GeoIP databases used for country-based routing, access control and logging. They're loaded before
+  // dropping the privileges, since the database files may be outside the chroot directory.
+  let geoip_database_path = yaml_config["global"]["geoipDatabase"].as_str();
+  let geoip_asn_database_path = yaml_config["global"]["geoipAsnDatabase"].as_str();
+  let geoip_database = match geoip_database_path.is_some() || geoip_asn_database_path.is_some() {
+    true => match GeoIpDatabase::open(geoip_database_path, geoip_asn_database_path) {
+      Ok(geoip_database) => Some(Arc::new(geoip_database)),
       Err(err) => {
         logger
           .send(LogMessage::new(
-            format!("Cannot listen to HTTP port: {}", err),
+            format!("Cannot load the GeoIP database: {}", err),
             true,
           ))
           .await
           .unwrap_or_default();
         Err(anyhow::anyhow!(format!(
-          "Cannot listen to HTTP port: {}",
+          "Cannot load the GeoIP database: {}",
           err
         )))?
       }
-    });
-  }
+    },
+    false => None,
+  };
 
-  if tls_enabled {
-    println!("HTTPS server is listening at {}", addr_tls);
-    listener_tls = Some(match TcpListener::bind(addr_tls).await {
-      Ok(listener) => listener,
+  // Bind to the admin API address. The admin API isn't available in the worker processes,
+  // since all of them would have to listen on the same address.
+  let admin_api_listener = if !yaml_config["global"]["adminApi"].is_badvalue()
+    && env::var_os(WORKER_PROCESS_ENV).is_none()
+  {
+    match AdminListener::bind(&yaml_config["global"]["adminApi"]).await {
+      Ok(admin_api_listener) => {
+        println!(
+          "Admin API is listening at {}",
+          yaml_config["global"]["adminApi"]["listen"]
+            .as_str()
+            .unwrap_or_default()
+        );
+        Some(admin_api_listener)
+      }
       Err(err) => {
         logger
           .send(LogMessage::new(
-            format!("Cannot listen to HTTPS port: {}", err),
+            format!("Cannot start the admin API: {}", err),
             true,
           ))
           .await
           .unwrap_or_default();
         Err(anyhow::anyhow!(format!(
-          "Cannot listen to HTTPS port: {}",
+          "Cannot start the admin API: {}",
           err
         )))?
       }
-    });
+    }
+  } else {
+    None
+  };
+
+  Ok(PreparedServer {
+    configuration,
+    tcp_listeners,
+    crypto_provider: crypto_provider_cloned,
+    tls_config_builder: tls_config_builder_wants_verifier,
+    min_tls_version: min_tls_version_option.map(String::from),
+    max_tls_version: max_tls_version_option.map(String::from),
+    sni_tls_policies,
+    cert_resolver,
+    acme_tls_acceptor,
+    geoip_database,
+    admin_api_listener,
+  })
+}
+
+// Drop the privileges after binding to the ports, if the user, the group or the root directory is configured.
+// It's done only once, since the privileges can't be regained.
+async fn drop_server_privileges(
+  global_config: &Yaml,
+  logger: &Sender<LogMessage>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let user = global_config["user"].as_str();
+  let group = global_config["group"].as_str();
+  let chroot = global_config["chroot"].as_str();
+  if privileges_dropped() || (user.is_none() && group.is_none() && chroot.is_none()) {
+    return Ok(());
+  }
+  if let Err(err) = drop_privileges(user, group, chroot) {
+    logger
+      .send(LogMessage::new(
+        format!("Cannot drop privileges: {}", err),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!(format!("Cannot drop privileges: {}", err)))?
   }
+  Ok(())
+}
 
-  // Wrap the modules vector in an Arc
-  let modules_arc = Arc::new(modules);
+// Main server event loop. Returns true if the server has to be restarted to apply the reloaded configuration.
+async fn server_event_loop(
+  prepared_server: PreparedServer,
+  configuration_loader: ServerConfigurationLoader,
+  logger: Sender<LogMessage>,
+  reload_sender: Sender<()>,
+  reload_receiver: Receiver<()>,
+  connection_drain: ConnectionDrain,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+  let PreparedServer {
+    configuration,
+    tcp_listeners,
+    crypto_provider: crypto_provider_cloned,
+    tls_config_builder: tls_config_builder_wants_verifier,
+    min_tls_version,
+    max_tls_version,
+    sni_tls_policies,
+    cert_resolver,
+    acme_tls_acceptor,
+    geoip_database,
+    admin_api_listener,
+  } = prepared_server;
+  let yaml_config = configuration.yaml_config.clone();
+  let signature_verification_algorithms = crypto_provider_cloned.signature_verification_algorithms;
+  let min_tls_version_option = min_tls_version.as_deref();
+  let max_tls_version_option = max_tls_version.as_deref();
+
+  // Create the auto-ban subsystem, which temporarily bans clients that repeatedly send rejected requests
+  let auto_ban = match yaml_config["global"]["autoBanThreshold"].as_i64() {
+    Some(threshold) if threshold > 0 => Some(Arc::new(AutoBan::new(
+      threshold as u64,
+      time::Duration::from_secs(
+        yaml_config["global"]["autoBanWindow"]
+          .as_i64()
+          .unwrap_or(60) as u64,
+      ),
+      time::Duration::from_secs(
+        yaml_config["global"]["autoBanDuration"]
+          .as_i64()
+          .unwrap_or(600) as u64,
+      ),
+      yaml_config["global"]["autoBanMode"].as_str() == Some("observe"),
+      &TEMPORARY_BANS,
+    ))),
+    _ => None,
+  };
 
-  // Create a global configuration root
-  let global_config_root = Arc::new(ServerConfigRoot::new(&yaml_config["global"]));
-  let host_config = Arc::new(yaml_config["hosts"].clone());
+  // Create the limiters of concurrent connections and requests
+  let get_limit = |property: &str| {
+    yaml_config["global"][property]
+      .as_i64()
+      .filter(|limit| *limit > 0)
+      .map(|limit| limit as usize)
+  };
+  let connection_limiter = match (
+    get_limit("maxConnections"),
+    get_limit("maxConnectionsPerIP"),
+  ) {
+    (None, None) => None,
+    (max_total, max_per_ip) => Some(ConcurrencyLimiter::new(
+      max_total,
+      max_per_ip,
+      yaml_config["global"]["connectionLimitMode"].as_str() == Some("observe"),
+    )),
+  };
+  let request_limiter = match (get_limit("maxRequests"), get_limit("maxRequestsPerIP")) {
+    (None, None) => None,
+    (max_total, max_per_ip) => Some(ConcurrencyLimiter::new(
+      max_total,
+      max_per_ip,
+      yaml_config["global"]["requestLimitMode"].as_str() == Some("observe"),
+    )),
+  };
 
-  // Main loop to accept incoming connections
-  loop {
-    match &listener {
-      Some(listener) => match &listener_tls {
-        Some(listener_tls) => {
-          tokio::select! {
-              status = listener.accept() => {
-                match status {
-                  Ok((stream, remote_address)) => {
-                    accept_connection(
-                      stream,
-                      remote_address,
-                      None,
-                      None,
-                      global_config_root.clone(),
-                      host_config.clone(),
-                      logger.clone(),
-                      modules_arc.clone(),
-                    )
-                    .await;
-                  }
-                  Err(err) => {
-                    logger
-                      .send(LogMessage::new(
-                        format!("Cannot accept a connection: {}", err),
-                        true,
-                      ))
-                      .await
-                      .unwrap_or_default();
-                  }
-                }
-              },
-              status = listener_tls.accept() => {
-                match status {
-                  Ok((stream, remote_address)) => {
-                    let tls_acceptor = tls_acceptor.clone();
-                    accept_connection(
-                      stream,
-                      remote_address,
-                      Some(tls_acceptor),
-                      acme_tls_acceptor_and_config.clone(),
-                      global_config_root.clone(),
-                      host_config.clone(),
-                      logger.clone(),
-                      modules_arc.clone(),
-                    )
-                    .await;
-                  }
-                  Err(err) => {
-                    logger
-                      .send(LogMessage::new(
-                        format!("Cannot accept a connection: {}", err),
-                        true,
-                      ))
-                      .await
-                      .unwrap_or_default();
-                  }
-                }
-              }
-          };
+  // The temporary files are created by the server's (possibly unprivileged) user
+  if let Err(err) = TEMP_FILES.configure(
+    yaml_config["global"]["tempDirectory"]
+      .as_str()
+      .map(Path::new),
+    yaml_config["global"]["maxTempFilesSize"]
+      .as_i64()
+      .map(|max_temp_files_size| max_temp_files_size as u64),
+    yaml_config["global"]["tempFilesSync"]
+      .as_bool()
+      .unwrap_or(false),
+  ) {
+    logger
+      .send(LogMessage::new(
+        format!("Cannot prepare the temporary file directory: {}", err),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!(format!(
+      "Cannot prepare the temporary file directory: {}",
+      err
+    )))?
+  }
+
+  // The storage of the cache, the rate limits, and the bans is prepared by the server's (possibly unprivileged) user
+  match create_storage_backend(&yaml_config["global"]) {
+    Ok(storage_backend) => STORAGE.configure(storage_backend),
+    Err(err) => {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot prepare the storage: {}", err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+      Err(anyhow::anyhow!(format!(
+        "Cannot prepare the storage: {}",
+        err
+      )))?
+    }
+  }
+
+  // The bans stored before the restart or by other servers sharing the storage are enforced by this server too
+  if STORAGE.is_shared() {
+    tokio::spawn(refresh_temporary_bans(logger.clone()));
+  }
+
+  // Serve the admin API
+  if let Some(admin_api_listener) = admin_api_listener {
+    let deployments =
+      parse_deployment_targets(&yaml_config["global"]["adminApi"]).unwrap_or_default();
+    tokio::task::spawn(serve_admin_api(
+      admin_api_listener,
+      Arc::from(
+        yaml_config["global"]["adminApi"]["token"]
+          .as_str()
+          .unwrap_or_default(),
+      ),
+      Arc::new(deployments),
+      reload_sender,
+      logger.clone(),
+    ));
+  }
+
+  // Create the TLS configurations. The client authentication can be configured per listener,
+  // while HTTP/2 can be enabled per listener and per host, so the configurations with and without it are created.
+  #[allow(clippy::type_complexity)]
+  let mut tls_configs: Vec<(
+    ClientAuthConfig,
+    Arc<ServerConfig>,
+    Arc<ServerConfig>,
+    Arc<Vec<SniTlsConfigs>>,
+  )> = Vec::new();
+  for (_, listener_config) in tcp_listeners.iter() {
+    if !listener_config.secure
+      || tls_configs
+        .iter()
+        .any(|(client_auth, _, _, _)| *client_auth == listener_config.client_auth)
+    {
+      continue;
+    }
+    let client_cert_verifier = match create_client_cert_verifier(
+      &listener_config.client_auth,
+      signature_verification_algorithms,
+      logger.clone(),
+    ) {
+      Ok(client_cert_verifier) => client_cert_verifier,
+      Err(err) => {
+        logger
+          .send(LogMessage::new(err.to_string(), true))
+          .await
+          .unwrap_or_default();
+        Err(anyhow::anyhow!(err.to_string()))?
+      }
+    };
+    let mut tls_config = match client_cert_verifier {
+      Some(client_cert_verifier) => tls_config_builder_wants_verifier
+        .clone()
+        .with_client_cert_verifier(client_cert_verifier),
+      None => tls_config_builder_wants_verifier
+        .clone()
+        .with_no_client_auth(),
+    }
+    .with_cert_resolver(cert_resolver.clone());
+
+    // Configure ALPN protocols
+    tls_config.alpn_protocols = vec![b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+    let mut tls_config_http2 = tls_config.clone();
+    tls_config_http2.alpn_protocols.insert(0, b"h2".to_vec());
+
+    // Create the TLS configurations for the SNI host names with the overridden TLS policies
+    let mut sni_overrides = Vec::new();
+    for (sni_hostname, tls_policy) in sni_tls_policies.iter() {
+      match create_sni_tls_configs(
+        sni_hostname,
+        tls_policy,
+        &crypto_provider_cloned,
+        min_tls_version_option,
+        max_tls_version_option,
+        &listener_config.client_auth,
+        cert_resolver.clone(),
+        logger.clone(),
+      ) {
+        Ok(sni_tls_configs) => sni_overrides.push(sni_tls_configs),
+        Err(err) => {
+          let message = format!(
+            "Couldn't create the TLS server configuration for \"{}\": {}",
+            sni_hostname, err
+          );
+          logger
+            .send(LogMessage::new(message.clone(), true))
+            .await
+            .unwrap_or_default();
+          Err(anyhow::anyhow!(message))?
         }
-        None => match listener.accept().await {
-          Ok((stream, remote_address)) => {
-            accept_connection(
-              stream,
-              remote_address,
-              None,
-              None,
-              global_config_root.clone(),
-              host_config.clone(),
-              logger.clone(),
-              modules_arc.clone(),
-            )
-            .await;
-          }
-          Err(err) => {
-            logger
-              .send(LogMessage::new(
-                format!("Cannot accept a connection: {}", err),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-          }
-        },
-      },
-      None => {
-        match &listener_tls {
-          Some(listener_tls) => match listener_tls.accept().await {
+      }
+    }
+
+    tls_configs.push((
+      listener_config.client_auth.clone(),
+      Arc::new(tls_config),
+      Arc::new(tls_config_http2),
+      Arc::new(sni_overrides),
+    ));
+  }
+
+  // Create the configuration used by the requests, which is swapped when the configuration is reloaded
+  set_active_config(configuration.yaml_config.clone());
+  let live_configuration = Arc::new(LiveConfiguration::new(ActiveConfiguration::new(
+    configuration,
+    &logger,
+  )));
+
+  // The modules are started before the server accepts the connections
+  if let Err(err) = live_configuration
+    .get()
+    .run_module_hook(ModuleLifecycleHook::Startup, &logger)
+    .await
+  {
+    logger
+      .send(LogMessage::new(
+        format!("Cannot start the modules: {}", err),
+        true,
+      ))
+      .await
+      .unwrap_or_default();
+    Err(anyhow::anyhow!(format!(
+      "Cannot start the modules: {}",
+      err
+    )))?
+  }
+
+  // The GeoIP databases are reloaded periodically, so that the updated database files are used
+  if let (Some(geoip_database), Some(reload_interval)) = (
+    geoip_database.clone(),
+    yaml_config["global"]["geoipReloadInterval"].as_i64(),
+  ) {
+    tokio::spawn(reload_geoip_database(
+      geoip_database,
+      time::Duration::from_secs(reload_interval as u64),
+      logger.clone(),
+    ));
+  }
+
+  // The cache prewarming jobs populate the cache at the scheduled times
+  tokio::spawn(schedule_cache_prewarming(
+    live_configuration.clone(),
+    geoip_database.clone(),
+    logger.clone(),
+  ));
+
+  // The listeners are shared with the listen queue monitor, and labeled with their addresses in the metrics
+  let tcp_listeners = tcp_listeners
+    .into_iter()
+    .map(|(tcp_listener, listener_config)| {
+      let listener_address = tcp_listener
+        .local_addr()
+        .map(|address| address.to_string())
+        .unwrap_or_default();
+      (Arc::new(tcp_listener), listener_address, listener_config)
+    })
+    .collect::<Vec<_>>();
+  let listener_monitor = monitor_listeners(
+    tcp_listeners
+      .iter()
+      .map(|(tcp_listener, listener_address, _)| (listener_address.clone(), tcp_listener.clone()))
+      .collect(),
+    logger.clone(),
+  );
+
+  // Main loops to accept incoming connections, one for each listener
+  let accept_loops = tcp_listeners
+    .into_iter()
+    .map(|(tcp_listener, listener_address, listener_config)| {
+      let listener_tls_configs =
+        find_listener_tls_configs(&tls_configs, &listener_config, |(client_auth, _, _, _)| {
+          client_auth
+        })
+        .map(|(_, http1, http2, sni_overrides)| ListenerTlsConfigs {
+          http1: http1.clone(),
+          http2: http2.clone(),
+          enable_http2: listener_config.enable_http2,
+          sni_overrides: sni_overrides.clone(),
+        });
+      let acme_tls_acceptor = match listener_tls_configs {
+        Some(_) => acme_tls_acceptor.clone(),
+        None => None,
+      };
+      let live_configuration = live_configuration.clone();
+      let geoip_database = geoip_database.clone();
+      let auto_ban = auto_ban.clone();
+      let connection_limiter = connection_limiter.clone();
+      let request_limiter = request_limiter.clone();
+      // The bandwidth and the request capacity are shared fairly between the hosts on each listener
+      let fair_queue = match (
+        yaml_config["global"]["fairQueueingRate"]
+          .as_i64()
+          .filter(|rate| *rate > 0)
+          .map(|rate| rate as u64),
+        get_limit("fairQueueingMaxRequests"),
+      ) {
+        (None, None) => None,
+        (rate, max_requests) => Some(FairQueue::new(rate, max_requests)),
+      };
+      let connection_drain = connection_drain.clone();
+      let logger = logger.clone();
+      async move {
+        loop {
+          // The listener stops accepting the connections when the server shuts down
+          let accept_result = tokio::select! {
+            accept_result = tcp_listener.accept() => accept_result,
+            _ = connection_drain.cancelled() => break,
+          };
+          match accept_result {
             Ok((stream, remote_address)) => {
-              let tls_acceptor = tls_acceptor.clone();
+              METRICS.increment_counter(
+                "ferron_accepted_connections_total",
+                &[("listener", &listener_address)],
+              );
+              // Close the connections from banned clients immediately
+              if TEMPORARY_BANS.is_banned(remote_address.ip()) {
+                METRICS.increment_counter("ferron_banned_connections_total", &[]);
+                continue;
+              }
+              // Reset the connections over the connection limits
+              let connection_permit = match &connection_limiter {
+                Some(connection_limiter) => {
+                  match connection_limiter.try_acquire(remote_address.ip()) {
+                    Ok(connection_permit) => Some(connection_permit),
+                    Err(limit) if connection_limiter.is_observing() => {
+                      // In the observe mode, the connection over the limit is reported, but accepted
+                      METRICS.increment_counter(
+                        "ferron_rejected_connections_total",
+                        &[("limit", limit.as_str()), ("mode", "observe")],
+                      );
+                      logger
+                        .send(LogMessage::new(
+                          format!(
+                            "The connection from {} exceeds the {} connection limit (not enforced, the limit is in the observe mode)",
+                            remote_address.ip().to_canonical(),
+                            limit.as_str()
+                          ),
+                          true,
+                        ))
+                        .await
+                        .unwrap_or_default();
+                      Some(connection_limiter.acquire_over_limit(remote_address.ip()))
+                    }
+                    Err(limit) => {
+                      METRICS.increment_counter(
+                        "ferron_rejected_connections_total",
+                        &[("limit", limit.as_str()), ("mode", "enforce")],
+                      );
+                      stream
+                        .set_linger(Some(time::Duration::ZERO))
+                        .unwrap_or_default();
+                      continue;
+                    }
+                  }
+                }
+                None => None,
+              };
               accept_connection(
                 stream,
                 remote_address,
-                Some(tls_acceptor),
-                acme_tls_acceptor_and_config.clone(),
-                global_config_root.clone(),
-                host_config.clone(),
+                listener_tls_configs.clone(),
+                acme_tls_acceptor.clone(),
+                listener_config.enable_http2,
+                live_configuration.clone(),
+                geoip_database.clone(),
+                auto_ban.clone(),
+                connection_permit,
+                request_limiter.clone(),
+                fair_queue.clone(),
+                connection_drain.clone(),
                 logger.clone(),
-                modules_arc.clone(),
               )
               .await;
             }
             Err(err) => {
+              METRICS.increment_counter(
+                "ferron_accept_errors_total",
+                &[("listener", &listener_address)],
+              );
               logger
                 .send(LogMessage::new(
                   format!("Cannot accept a connection: {}", err),
@@ -1142,36 +2475,43 @@ async fn server_event_loop(
                 .await
                 .unwrap_or_default();
             }
-          },
-          None => {
-            // No server is listening...
-            logger
-              .send(LogMessage::new(
-                String::from("No server is listening"),
-                true,
-              ))
-              .await
-              .unwrap_or_default();
-            Err(anyhow::anyhow!("No server is listening"))?;
           }
         }
       }
+    })
+    .collect::<Vec<_>>();
+
+  // Reload the configuration when requested, until the server has to be restarted
+  let mut accept_loops = join_all(accept_loops);
+  tokio::pin!(listener_monitor);
+  let restart = loop {
+    tokio::select! {
+      _ = &mut accept_loops => break false,
+      _ = &mut listener_monitor => (),
+      Ok(()) = reload_receiver.recv() => {
+        if reload_configuration(&configuration_loader, &live_configuration, &logger).await {
+          break true;
+        }
+      }
     }
-  }
+  };
+
+  // The modules flush their state before the server shuts down or restarts
+  live_configuration
+    .get()
+    .run_module_hook(ModuleLifecycleHook::Shutdown, &logger)
+    .await
+    .unwrap_or_default();
+  Ok(restart)
 }
 
-// Start the server
-#[allow(clippy::type_complexity)]
+// Start the server. Returns true if the server has to be restarted to apply the reloaded configuration.
 pub fn start_server(
-  yaml_config: Arc<Yaml>,
-  modules: Vec<Box<dyn ServerModule + Send + Sync>>,
-  module_config_validation_functions: Vec<
-    Symbol<'_, fn(&ServerConfigRoot, bool, bool) -> Result<(), Box<dyn Error + Send + Sync>>>,
-  >,
-  module_error: Option<anyhow::Error>,
-  modules_optional_builtin: Vec<String>,
+  configuration: ServerConfiguration,
+  configuration_loader: ServerConfigurationLoader,
   first_startup: bool,
 ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+  let yaml_config = configuration.yaml_config.clone();
   if let Some(environment_variables_hash) = yaml_config["global"]["environmentVariables"].as_hash()
   {
     let environment_variables_hash_iter = environment_variables_hash.iter();
@@ -1190,12 +2530,37 @@ pub fn start_server(
     }
   }
 
+  // The sockets passed by systemd socket activation are taken over before the server's threads are started,
+  // since the environment variables of the socket activation are removed then
+  let systemd_listeners = match get_systemd_listeners() {
+    Ok(systemd_listeners) => systemd_listeners,
+    Err(err) => Err(anyhow::anyhow!(format!(
+      "Cannot obtain systemd-passed sockets: {}",
+      err
+    )))?,
+  };
+
   let available_parallelism = thread::available_parallelism()?.get();
 
+  // The blocking thread pool grows with the blocking work up to the limit, and the idle threads are stopped.
+  // Each module can use only a part of the pool, which shrinks to a fair share when several modules run blocking work,
+  // so that the file serving isn't starved by other modules.
+  let max_blocking_threads = yaml_config["global"]["maxBlockingThreads"]
+    .as_i64()
+    .map(|max_blocking_threads| max_blocking_threads as usize)
+    .unwrap_or(DEFAULT_MAX_BLOCKING_THREADS);
+  BLOCKING_BUDGETS.configure(
+    max_blocking_threads,
+    yaml_config["global"]["moduleBlockingThreads"]
+      .as_i64()
+      .map(|module_blocking_threads| module_blocking_threads as usize)
+      .unwrap_or(max_blocking_threads / 2),
+  );
+
   // Create Tokio runtime for the server
   let server_runtime = tokio::runtime::Builder::new_multi_thread()
     .worker_threads(available_parallelism)
-    .max_blocking_threads(1536)
+    .max_blocking_threads(max_blocking_threads)
     .event_interval(25)
     .thread_name("server-pool")
     .enable_all()
@@ -1209,10 +2574,13 @@ pub fn start_server(
     })
     .max_blocking_threads(768)
     .thread_name("log-pool")
+    // The I/O driver is needed by the syslog and systemd journal log sinks
+    .enable_io()
     .enable_time()
     .build()?;
 
   let (logger, receive_log) = async_channel::bounded::<LogMessage>(10000);
+  let (reload_sender, reload_receiver) = async_channel::bounded::<()>(1);
 
   let log_filename = yaml_config["global"]["logFilePath"]
     .as_str()
@@ -1220,32 +2588,37 @@ pub fn start_server(
   let error_log_filename = yaml_config["global"]["errorLogFilePath"]
     .as_str()
     .map(String::from);
+  let access_log_sinks =
+    parse_access_log_sinks(&yaml_config["global"]["logging"]).unwrap_or_default();
+  let error_log_sinks =
+    parse_error_log_sinks(&yaml_config["global"]["logging"]).unwrap_or_default();
+  if let Ok(error_log_levels) = parse_error_log_levels(&yaml_config["global"]) {
+    set_error_log_levels(error_log_levels);
+  }
+  let mut log_throttle = LogThrottle::new(
+    yaml_config["global"]["errorLogDeduplicationWindow"]
+      .as_i64()
+      .filter(|window| *window > 0)
+      .map(|window| time::Duration::from_millis(window as u64)),
+    yaml_config["global"]["errorLogRateLimit"]
+      .as_i64()
+      .map(|rate_limit| rate_limit as u64),
+  );
 
   log_runtime.spawn(async move {
+    // The log file paths can contain strftime specifiers, in which case the logs are split into time-sliced files
     let log_file = match log_filename {
-      Some(log_filename) => Some(
-        fs::OpenOptions::new()
-          .append(true)
-          .create(true)
-          .open(log_filename)
-          .await,
-      ),
+      Some(log_filename) => Some(LogFile::open(&log_filename).await),
       None => None,
     };
 
     let error_log_file = match error_log_filename {
-      Some(error_log_filename) => Some(
-        fs::OpenOptions::new()
-          .append(true)
-          .create(true)
-          .open(error_log_filename)
-          .await,
-      ),
+      Some(error_log_filename) => Some(LogFile::open(&error_log_filename).await),
       None => None,
     };
 
     let log_file_wrapped = match log_file {
-      Some(Ok(file)) => Some(Arc::new(Mutex::new(BufWriter::with_capacity(131072, file)))),
+      Some(Ok(file)) => Some(Arc::new(Mutex::new(file))),
       Some(Err(e)) => {
         eprintln!("Failed to open log file: {}", e);
         None
@@ -1254,7 +2627,7 @@ pub fn start_server(
     };
 
     let error_log_file_wrapped = match error_log_file {
-      Some(Ok(file)) => Some(Arc::new(Mutex::new(BufWriter::with_capacity(131072, file)))),
+      Some(Ok(file)) => Some(Arc::new(Mutex::new(file))),
       Some(Err(e)) => {
         eprintln!("Failed to open error log file: {}", e);
         None
@@ -1262,9 +2635,32 @@ pub fn start_server(
       None => None,
     };
 
+    // The access log sinks configured in the "logging" section, indexed like in the configuration
+    let mut access_log_outputs = Vec::new();
+    for access_log_sink in access_log_sinks.iter() {
+      access_log_outputs.push(match LogOutput::open(&access_log_sink.target).await {
+        Ok(output) => Some(Arc::new(Mutex::new(output))),
+        Err(e) => {
+          eprintln!("Failed to open access log sink: {}", e);
+          None
+        }
+      });
+    }
+
+    // The error log sinks configured in the "logging" section, written to besides the error log file
+    let mut error_log_outputs = Vec::new();
+    for error_log_sink in error_log_sinks.iter() {
+      match LogOutput::open(error_log_sink).await {
+        Ok(output) => error_log_outputs.push(Arc::new(Mutex::new(output))),
+        Err(e) => eprintln!("Failed to open error log sink: {}", e),
+      }
+    }
+
     // The logs are written when the log message is received by the log event loop, and flushed every 100 ms, improving the server performance.
     let log_file_wrapped_cloned_for_sleep = log_file_wrapped.clone();
     let error_log_file_wrapped_cloned_for_sleep = error_log_file_wrapped.clone();
+    let access_log_outputs_cloned_for_sleep = access_log_outputs.clone();
+    let error_log_outputs_cloned_for_sleep = error_log_outputs.clone();
     tokio::task::spawn(async move {
       let mut interval = time::interval(time::Duration::from_millis(100));
       loop {
@@ -1278,12 +2674,50 @@ pub fn start_server(
           let mut locked_file = error_log_file_wrapped_cloned.lock().await;
           locked_file.flush().await.unwrap_or_default();
         }
+        for log_output in access_log_outputs_cloned_for_sleep
+          .iter()
+          .flatten()
+          .chain(error_log_outputs_cloned_for_sleep.iter())
+        {
+          let mut locked_output = log_output.lock().await;
+          locked_output.flush().await.unwrap_or_default();
+        }
       }
     });
 
-    // Logging loop
-    while let Ok(message) = receive_log.recv().await {
-      let (mut message, is_error) = message.get_message();
+    let write_access_log_sink_message =
+      |message: String, fields: Vec<(String, String)>, access_log_sink: usize| {
+        if let Some(Some(access_log_output)) = access_log_outputs.get(access_log_sink).cloned() {
+          tokio::task::spawn(async move {
+            let mut locked_output = access_log_output.lock().await;
+            if let Err(e) = locked_output.write(&message, None, &fields).await {
+              eprintln!("Failed to write to access log sink: {}", e);
+            }
+          });
+        }
+      };
+
+    // The module, which logged the error message, is preserved as a structured field
+    let write_error_log_sink_message =
+      |message: &str, source: Option<&str>, level: ErrorLogLevel| {
+        let fields = match source {
+          Some(source) => vec![(String::from("FERRON_SOURCE"), source.to_string())],
+          None => Vec::new(),
+        };
+        for error_log_output in error_log_outputs.iter() {
+          let error_log_output = error_log_output.clone();
+          let message = message.to_string();
+          let fields = fields.clone();
+          tokio::task::spawn(async move {
+            let mut locked_output = error_log_output.lock().await;
+            if let Err(e) = locked_output.write(&message, Some(level), &fields).await {
+              eprintln!("Failed to write to error log sink: {}", e);
+            }
+          });
+        }
+      };
+
+    let write_log_message = |mut message: String, is_error: bool| {
       let log_file_wrapped_cloned = if !is_error {
         log_file_wrapped.clone()
       } else {
@@ -1304,42 +2738,154 @@ pub fn start_server(
           }
         });
       }
+    };
+
+    // Logging loop. The error log messages are deduplicated and rate-limited,
+    // and the summaries of the suppressed messages are written once their windows end.
+    let mut throttle_interval = time::interval(time::Duration::from_secs(1));
+    loop {
+      tokio::select! {
+        message = receive_log.recv() => {
+          let Ok(mut message) = message else {
+            break;
+          };
+          let source = message.get_source().unwrap_or(SERVER_LOG_SOURCE).to_string();
+          let access_log_sink = message.get_access_log_sink();
+          let fields = message.take_fields();
+          let level = message.get_level();
+          // The error log level might be raised temporarily for the request, in which the message was logged
+          let level_allowed = error_log_level_allows(&source, level)
+            || message.get_level_override().is_some_and(|level_override| level <= level_override);
+          let (mut message, is_error) = message.get_message();
+          if !log_level().allows(is_error) || (is_error && !level_allowed) {
+            continue;
+          }
+          if let (Some(access_log_sink), false) = (access_log_sink, is_error) {
+            write_access_log_sink_message(message, fields, access_log_sink);
+          } else if is_error {
+            // The messages less severe than errors are labeled with their level
+            if level != ErrorLogLevel::Error {
+              message = format!("[{}] {}", level.as_str(), message);
+            }
+            for message in log_throttle.process(&source, message, Instant::now()) {
+              write_error_log_sink_message(&message, Some(&source), level);
+              write_log_message(message, true);
+            }
+          } else {
+            write_log_message(message, false);
+          }
+        }
+        _ = throttle_interval.tick() => {
+          for message in log_throttle.flush_expired(Instant::now()) {
+            write_error_log_sink_message(&message, None, ErrorLogLevel::Error);
+            write_log_message(message, true);
+          }
+        }
+      }
     }
   });
 
+  // The connections are drained when the server shuts down
+  let connection_drain = ConnectionDrain::new();
+  let drain_timeout = drain_timeout(&ServerConfigRoot::new(&yaml_config["global"]));
+
   // Run the server event loop
   let result = server_runtime.block_on(async {
+    #[cfg(unix)]
+    let hangup_reload_sender = reload_sender.clone();
+    #[cfg(unix)]
+    let drain_logger = logger.clone();
+
+    // The privileges are dropped right after binding to the ports and loading the files
+    // accessible only to the privileged user, before the server accepts the connections
+    let prepared_server = match prepare_server(
+      configuration,
+      systemd_listeners,
+      logger.clone(),
+      first_startup,
+    )
+    .await
+    {
+      Ok(prepared_server) => drop_server_privileges(&yaml_config["global"], &logger)
+        .await
+        .map(|_| prepared_server),
+      Err(err) => Err(err),
+    };
+    let prepared_server = match prepared_server {
+      Ok(prepared_server) => prepared_server,
+      Err(err) => {
+        // Sleep the Tokio runtime to ensure error logs are saved
+        time::sleep(tokio::time::Duration::from_millis(100)).await;
+        return Err(err);
+      }
+    };
+
     let event_loop_future = server_event_loop(
-      yaml_config,
+      prepared_server,
+      configuration_loader,
       logger,
-      modules,
-      module_config_validation_functions,
-      module_error,
-      modules_optional_builtin,
-      first_startup,
+      reload_sender,
+      reload_receiver,
+      connection_drain.clone(),
     );
+    tokio::pin!(event_loop_future);
 
     #[cfg(unix)]
     {
-      match signal::unix::signal(signal::unix::SignalKind::hangup()) {
-        Ok(mut signal) => {
+      match (
+        signal::unix::signal(signal::unix::SignalKind::hangup()),
+        signal::unix::signal(signal::unix::SignalKind::terminate()),
+      ) {
+        (Ok(mut hangup_signal), Ok(mut terminate_signal)) => {
+          // The configuration is reloaded by the server event loop
+          tokio::task::spawn(async move {
+            while hangup_signal.recv().await.is_some() {
+              hangup_reload_sender.try_send(()).unwrap_or_default();
+            }
+          });
+
           tokio::select! {
-            result = event_loop_future => {
+            result = &mut event_loop_future => {
               // Sleep the Tokio runtime to ensure error logs are saved
               time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-              result.map(|_| false)
+              result
             },
-            _ = signal.recv() => Ok(true)
+            _ = terminate_signal.recv() => {
+              // The server no longer accepts connections, so the keep-alive connections are asked to close,
+              // and the pending requests are given the drain timeout to complete.
+              // This allows the master process to gracefully replace worker processes.
+              // The server event loop finishes afterwards, running the modules' shutdown hooks.
+              let (drained, result) = connection_drain
+                .drain_and_finish(drain_timeout, &mut event_loop_future)
+                .await;
+              if !drained {
+                drain_logger
+                  .send(LogMessage::new(
+                    format!(
+                      "The drain timeout elapsed, closing {} remaining connections",
+                      connection_drain.connection_count()
+                    ),
+                    true,
+                  ))
+                  .await
+                  .unwrap_or_default();
+              }
+
+              // Sleep the Tokio runtime to ensure error logs are saved
+              time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+              result.map(|_| false)
+            }
           }
         }
-        Err(_) => {
+        _ => {
           let result = event_loop_future.await;
 
           // Sleep the Tokio runtime to ensure error logs are saved
           time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-          result.map(|_| false)
+          result
         }
       }
     }
@@ -1351,7 +2897,7 @@ pub fn start_server(
       // Sleep the Tokio runtime to ensure error logs are saved
       time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-      result.map(|_| false)
+      result
     }
   });
 
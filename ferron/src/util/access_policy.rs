@@ -0,0 +1,559 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use ferron_common::{RequestVariables, ServerConfigRoot};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::geoip::is_country_allowed;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+use crate::ferron_util::storage::{StorageBackend, STORAGE};
+
+// The prefix of the storage keys of the access policy rate counters
+const RATE_KEY_PREFIX: &str = "ferron:access-rate:";
+
+// The action taken for the requests matching an access policy rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessAction {
+  Allow,
+  Deny,
+}
+
+impl AccessAction {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AccessAction::Allow => "allow",
+      AccessAction::Deny => "deny",
+    }
+  }
+
+  pub fn parse(action: &str) -> Option<Self> {
+    match action {
+      "allow" => Some(AccessAction::Allow),
+      "deny" => Some(AccessAction::Deny),
+      _ => None,
+    }
+  }
+}
+
+// The access decision about a request, with the name of the rule, which made it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessDecision {
+  pub action: AccessAction,
+  pub rule: String,
+}
+
+impl AccessDecision {
+  pub fn new(action: AccessAction, rule: &str) -> Self {
+    Self {
+      action,
+      rule: rule.to_string(),
+    }
+  }
+
+  pub fn is_allowed(&self) -> bool {
+    self.action == AccessAction::Allow
+  }
+}
+
+// The access decisions made about a request by the server and by the modules (for example, the block list module).
+// It's shared through the request extensions, and a single record of the decisions is logged with the request.
+#[derive(Clone, Default)]
+pub struct AccessDecisionLog {
+  decisions: Arc<Mutex<Vec<AccessDecision>>>,
+}
+
+impl AccessDecisionLog {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record(&self, decision: AccessDecision) {
+    if let Ok(mut decisions) = self.decisions.lock() {
+      decisions.push(decision);
+    }
+  }
+
+  // Get the decision, which determined if the request was admitted. The first denial ends the request handling,
+  // so it's the final decision. Otherwise, the most recent allowing decision is the final one.
+  pub fn final_decision(&self) -> Option<AccessDecision> {
+    let decisions = match self.decisions.lock() {
+      Ok(decisions) => decisions,
+      Err(err) => err.into_inner(),
+    };
+    decisions
+      .iter()
+      .find(|decision| !decision.is_allowed())
+      .or(decisions.last())
+      .cloned()
+  }
+}
+
+// The properties of the request, against which the access policy rules are evaluated
+pub struct AccessSubject<'a> {
+  pub client_ip: IpAddr,
+  // The client's country (an ISO 3166-1 alpha-2 code), if the GeoIP database is configured
+  pub country: Option<&'a str>,
+  // The request variables, which are the claims about the client (for example, "ssl_client_s_dn")
+  pub variables: Option<&'a RequestVariables>,
+  pub time: DateTime<Local>,
+}
+
+// The days and the local time of the day, during which a rule applies. The windows ending before they start
+// (for example, from 22:00 to 06:00) span midnight, and the days are the days the window starts on.
+struct TimeWindow {
+  days: Vec<Weekday>,
+  from: NaiveTime,
+  to: NaiveTime,
+}
+
+impl TimeWindow {
+  fn contains(&self, time: &DateTime<Local>) -> bool {
+    let time_of_day = time.time();
+    let (in_window, day) = if self.from <= self.to {
+      (
+        time_of_day >= self.from && time_of_day < self.to,
+        time.weekday(),
+      )
+    } else if time_of_day >= self.from {
+      (true, time.weekday())
+    } else {
+      (time_of_day < self.to, time.weekday().pred())
+    };
+    in_window && (self.days.is_empty() || self.days.contains(&day))
+  }
+}
+
+enum AccessCondition {
+  // The client's IP address is in one of the networks
+  Networks(IpPrefixTrie),
+  // The client's country is in the country list
+  Countries(Yaml),
+  // The client's country isn't in the country list, or it's unknown
+  CountriesOutside(Yaml),
+  // The request is made during the time window
+  Time(TimeWindow),
+  // The request variable has one of the values
+  Claim {
+    variable: String,
+    values: Vec<String>,
+  },
+  // The client made more requests matching the rule within the period than allowed
+  Rate {
+    requests: u64,
+    period: Duration,
+  },
+}
+
+impl AccessCondition {
+  // Check the conditions, which don't count the requests
+  fn matches(&self, subject: &AccessSubject<'_>) -> bool {
+    match self {
+      AccessCondition::Networks(networks) => networks.contains(subject.client_ip),
+      AccessCondition::Countries(countries) => {
+        !is_country_allowed(subject.country, &Yaml::BadValue, countries)
+      }
+      AccessCondition::CountriesOutside(countries) => {
+        !is_country_allowed(subject.country, countries, &Yaml::BadValue)
+      }
+      AccessCondition::Time(time_window) => time_window.contains(&subject.time),
+      AccessCondition::Claim { variable, values } => subject
+        .variables
+        .and_then(|variables| variables.get(variable))
+        .is_some_and(|value| values.contains(&value)),
+      AccessCondition::Rate { .. } => true,
+    }
+  }
+}
+
+struct AccessRule {
+  name: String,
+  action: AccessAction,
+  conditions: Vec<AccessCondition>,
+}
+
+// The access policy of a host or a location. The rules are evaluated in order, and the first rule, whose all conditions
+// match, decides about the request. The requests not matching any rule are handled with the default action.
+pub struct AccessPolicy {
+  // The rate counters are separate for each host
+  scope: String,
+  rules: Vec<AccessRule>,
+  default_action: AccessAction,
+}
+
+impl AccessPolicy {
+  // Create the access policy from the "accessPolicy" rules and the default action ("accessPolicyDefault" property).
+  // If the GeoIP database is configured, the GeoIP country lists ("geoipDenyCountries" and "geoipAllowCountries"
+  // properties) are evaluated before the rules.
+  pub fn from_config(
+    config: &ServerConfigRoot,
+    geoip_enabled: bool,
+  ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let mut rules = Vec::new();
+    if geoip_enabled {
+      let deny_countries = config.get("geoipDenyCountries");
+      if !deny_countries.is_badvalue() {
+        rules.push(AccessRule {
+          name: String::from("geoipDenyCountries"),
+          action: AccessAction::Deny,
+          conditions: vec![AccessCondition::Countries(deny_countries)],
+        });
+      }
+      let allow_countries = config.get("geoipAllowCountries");
+      if !allow_countries.is_badvalue() {
+        rules.push(AccessRule {
+          name: String::from("geoipAllowCountries"),
+          action: AccessAction::Deny,
+          conditions: vec![AccessCondition::CountriesOutside(allow_countries)],
+        });
+      }
+    }
+    let access_policy = config.get("accessPolicy");
+    if !access_policy.is_badvalue() {
+      rules.append(&mut parse_access_rules(&access_policy)?);
+    }
+
+    let default_action = match config.get("accessPolicyDefault") {
+      Yaml::BadValue => AccessAction::Allow,
+      default_action => default_action
+        .as_str()
+        .and_then(AccessAction::parse)
+        .ok_or(anyhow::anyhow!("Invalid default access policy action"))?,
+    };
+
+    Ok(Self {
+      scope: config.get("domain").as_str().unwrap_or("*").to_string(),
+      rules,
+      default_action,
+    })
+  }
+
+  // Check if the policy makes no decisions, so that the requests are admitted without recording a decision
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty() && self.default_action == AccessAction::Allow
+  }
+
+  pub async fn evaluate(&self, subject: &AccessSubject<'_>) -> AccessDecision {
+    for rule in self.rules.iter() {
+      if !rule
+        .conditions
+        .iter()
+        .all(|condition| condition.matches(subject))
+      {
+        continue;
+      }
+
+      // The requests are counted only after the other conditions match
+      let mut rate_exceeded = true;
+      for condition in rule.conditions.iter() {
+        if let AccessCondition::Rate { requests, period } = condition {
+          let key = format!(
+            "{}{}:{}:{}",
+            RATE_KEY_PREFIX,
+            self.scope,
+            rule.name,
+            subject.client_ip.to_canonical()
+          );
+          // The rate isn't enforced, if the requests can't be counted
+          rate_exceeded &= STORAGE
+            .increment(&key, *period)
+            .await
+            .is_ok_and(|count| count > *requests);
+        }
+      }
+      if rate_exceeded {
+        return AccessDecision::new(rule.action, &rule.name);
+      }
+    }
+    AccessDecision::new(self.default_action, "default")
+  }
+}
+
+// Parse the list of the values, which is either a single value or an array of values
+fn parse_string_list(yaml: &Yaml) -> Option<Vec<String>> {
+  match yaml {
+    Yaml::Array(values) => values
+      .iter()
+      .map(|value| match value {
+        Yaml::String(value) => Some(value.clone()),
+        Yaml::Integer(value) => Some(value.to_string()),
+        Yaml::Boolean(value) => Some(value.to_string()),
+        _ => None,
+      })
+      .collect(),
+    value => parse_string_list(&Yaml::Array(vec![value.clone()])),
+  }
+}
+
+fn parse_time_window(time_yaml: &Yaml) -> Result<TimeWindow, Box<dyn Error + Send + Sync>> {
+  if time_yaml.as_hash().is_none() {
+    Err(anyhow::anyhow!("Invalid access policy time window"))?
+  }
+  let parse_time = |time: &Yaml| {
+    time
+      .as_str()
+      .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+  };
+  let (from, to) = match (parse_time(&time_yaml["from"]), parse_time(&time_yaml["to"])) {
+    (Some(from), Some(to)) => (from, to),
+    _ => Err(anyhow::anyhow!(
+      "The access policy time window must have the start and end times in the HH:MM format"
+    ))?,
+  };
+  let days = match &time_yaml["days"] {
+    Yaml::BadValue => Vec::new(),
+    days => parse_string_list(days)
+      .and_then(|days| {
+        days
+          .iter()
+          .map(|day| day.parse::<Weekday>().ok())
+          .collect::<Option<Vec<_>>>()
+      })
+      .ok_or(anyhow::anyhow!("Invalid access policy time window days"))?,
+  };
+  Ok(TimeWindow { days, from, to })
+}
+
+fn parse_access_rule(
+  index: usize,
+  rule_yaml: &Yaml,
+) -> Result<AccessRule, Box<dyn Error + Send + Sync>> {
+  if rule_yaml.as_hash().is_none() {
+    Err(anyhow::anyhow!("Invalid access policy rule"))?
+  }
+  let name = match &rule_yaml["name"] {
+    Yaml::BadValue => format!("accessPolicy[{}]", index),
+    name => name
+      .as_str()
+      .filter(|name| !name.is_empty())
+      .ok_or(anyhow::anyhow!("Invalid access policy rule name"))?
+      .to_string(),
+  };
+  let action = rule_yaml["action"]
+    .as_str()
+    .and_then(AccessAction::parse)
+    .ok_or(anyhow::anyhow!(
+      "The action of the \"{}\" access policy rule must be \"allow\" or \"deny\"",
+      name
+    ))?;
+
+  let mut conditions = Vec::new();
+  if !rule_yaml["ip"].is_badvalue() {
+    let mut networks = IpPrefixTrie::new();
+    if !parse_string_list(&rule_yaml["ip"]).is_some_and(|networks_list| {
+      networks_list
+        .iter()
+        .all(|network| networks.insert_cidr(network))
+    }) {
+      Err(anyhow::anyhow!(
+        "Invalid IP address or CIDR range in the \"{}\" access policy rule",
+        name
+      ))?
+    }
+    conditions.push(AccessCondition::Networks(networks));
+  }
+  if !rule_yaml["countries"].is_badvalue() {
+    let countries = parse_string_list(&rule_yaml["countries"])
+      .filter(|countries| {
+        countries
+          .iter()
+          .all(|country| country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()))
+      })
+      .ok_or(anyhow::anyhow!(
+        "Invalid country list in the \"{}\" access policy rule",
+        name
+      ))?;
+    conditions.push(AccessCondition::Countries(Yaml::Array(
+      countries.into_iter().map(Yaml::String).collect(),
+    )));
+  }
+  if !rule_yaml["time"].is_badvalue() {
+    conditions.push(AccessCondition::Time(parse_time_window(
+      &rule_yaml["time"],
+    )?));
+  }
+  if !rule_yaml["claims"].is_badvalue() {
+    let claims = rule_yaml["claims"].as_hash().ok_or(anyhow::anyhow!(
+      "Invalid claims in the \"{}\" access policy rule",
+      name
+    ))?;
+    for (variable, values) in claims.iter() {
+      match (variable.as_str(), parse_string_list(values)) {
+        (Some(variable), Some(values)) => conditions.push(AccessCondition::Claim {
+          variable: variable.to_string(),
+          values,
+        }),
+        _ => Err(anyhow::anyhow!(
+          "Invalid claims in the \"{}\" access policy rule",
+          name
+        ))?,
+      }
+    }
+  }
+  if !rule_yaml["rate"].is_badvalue() {
+    match (
+      rule_yaml["rate"]["requests"].as_i64(),
+      rule_yaml["rate"]["period"].as_i64(),
+    ) {
+      (Some(requests), Some(period)) if requests >= 0 && period > 0 => {
+        conditions.push(AccessCondition::Rate {
+          requests: requests as u64,
+          period: Duration::from_secs(period as u64),
+        })
+      }
+      _ => Err(anyhow::anyhow!(
+        "The rate of the \"{}\" access policy rule must have the number of requests and the period in seconds",
+        name
+      ))?,
+    }
+  }
+
+  Ok(AccessRule {
+    name,
+    action,
+    conditions,
+  })
+}
+
+// Parse the access policy rules ("accessPolicy" property)
+fn parse_access_rules(
+  access_policy: &Yaml,
+) -> Result<Vec<AccessRule>, Box<dyn Error + Send + Sync>> {
+  access_policy
+    .as_vec()
+    .ok_or(anyhow::anyhow!("Invalid access policy"))?
+    .iter()
+    .enumerate()
+    .map(|(index, rule_yaml)| parse_access_rule(index, rule_yaml))
+    .collect()
+}
+
+// Validate the access policy rules ("accessPolicy" property)
+pub fn validate_access_policy(access_policy: &Yaml) -> Result<(), Box<dyn Error + Send + Sync>> {
+  parse_access_rules(access_policy).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn policy(yaml: &str) -> AccessPolicy {
+    AccessPolicy::from_config(
+      &ServerConfigRoot::new(&yaml_rust2::YamlLoader::load_from_str(yaml).unwrap()[0]),
+      true,
+    )
+    .unwrap()
+  }
+
+  fn subject<'a>(
+    client_ip: &str,
+    country: Option<&'a str>,
+    variables: Option<&'a RequestVariables>,
+    time: DateTime<Local>,
+  ) -> AccessSubject<'a> {
+    AccessSubject {
+      client_ip: client_ip.parse().unwrap(),
+      country,
+      variables,
+      time,
+    }
+  }
+
+  #[tokio::test]
+  async fn test_access_policy_rules() {
+    let access_policy = policy(
+      r#"
+      geoipDenyCountries: [RU]
+      accessPolicyDefault: deny
+      accessPolicy:
+        - name: office
+          action: allow
+          ip: 10.0.0.0/8
+          time:
+            days: [Mon, Tue, Wed, Thu, Fri]
+            from: "08:00"
+            to: "18:00"
+        - name: verified clients
+          action: allow
+          claims:
+            ssl_client_verify: SUCCESS
+      "#,
+    );
+    // 2024-01-15 was a Monday
+    let working_hours = Local.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+    let night = Local.with_ymd_and_hms(2024, 1, 15, 22, 0, 0).unwrap();
+
+    let decision = access_policy
+      .evaluate(&subject("10.1.2.3", Some("PL"), None, working_hours))
+      .await;
+    assert_eq!(decision, AccessDecision::new(AccessAction::Allow, "office"));
+    let decision = access_policy
+      .evaluate(&subject("10.1.2.3", Some("PL"), None, night))
+      .await;
+    assert_eq!(decision, AccessDecision::new(AccessAction::Deny, "default"));
+    let decision = access_policy
+      .evaluate(&subject("10.1.2.3", Some("RU"), None, working_hours))
+      .await;
+    assert_eq!(
+      decision,
+      AccessDecision::new(AccessAction::Deny, "geoipDenyCountries")
+    );
+
+    let variables = RequestVariables::new();
+    variables.set("ssl_client_verify", String::from("SUCCESS"));
+    let decision = access_policy
+      .evaluate(&subject("192.0.2.1", None, Some(&variables), night))
+      .await;
+    assert_eq!(
+      decision,
+      AccessDecision::new(AccessAction::Allow, "verified clients")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_access_policy_rate() {
+    let access_policy = policy(
+      "domain: rate.example\naccessPolicy:\n  - action: deny\n    rate:\n      requests: 2\n      period: 60\n",
+    );
+    let now = Local::now();
+    for expected_action in [AccessAction::Allow, AccessAction::Allow, AccessAction::Deny] {
+      let decision = access_policy
+        .evaluate(&subject("192.0.2.2", None, None, now))
+        .await;
+      assert_eq!(decision.action, expected_action);
+    }
+    let decision = access_policy
+      .evaluate(&subject("192.0.2.3", None, None, now))
+      .await;
+    assert_eq!(decision.action, AccessAction::Allow);
+  }
+
+  #[test]
+  fn test_time_window_spanning_midnight() {
+    let time_window = parse_time_window(
+      &yaml_rust2::YamlLoader::load_from_str("days: [Fri]\nfrom: \"22:00\"\nto: \"06:00\"")
+        .unwrap()[0],
+    )
+    .unwrap();
+    // 2024-01-19 was a Friday
+    assert!(time_window.contains(&Local.with_ymd_and_hms(2024, 1, 19, 23, 0, 0).unwrap()));
+    assert!(time_window.contains(&Local.with_ymd_and_hms(2024, 1, 20, 5, 0, 0).unwrap()));
+    assert!(!time_window.contains(&Local.with_ymd_and_hms(2024, 1, 19, 5, 0, 0).unwrap()));
+    assert!(!time_window.contains(&Local.with_ymd_and_hms(2024, 1, 20, 12, 0, 0).unwrap()));
+  }
+
+  #[test]
+  fn test_access_decision_log() {
+    let access_decisions = AccessDecisionLog::new();
+    assert_eq!(access_decisions.final_decision(), None);
+    access_decisions.record(AccessDecision::new(AccessAction::Allow, "office"));
+    access_decisions.record(AccessDecision::new(AccessAction::Deny, "blocklist"));
+    assert_eq!(
+      access_decisions.final_decision(),
+      Some(AccessDecision::new(AccessAction::Deny, "blocklist"))
+    );
+  }
+}
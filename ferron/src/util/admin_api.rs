@@ -0,0 +1,1201 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use async_channel::Sender;
+use ferron_common::{ErrorLogLevel, LogMessage};
+use http_body_util::{BodyExt, Empty, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::blocking_budget::spawn_blocking_budgeted;
+use crate::ferron_util::cache_prewarm::prewarm_job_statuses_json;
+use crate::ferron_util::config_export::active_config_json;
+use crate::ferron_util::deployment::{
+  current_release, deploy, releases, rollback, DeploymentTarget,
+};
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+use crate::ferron_util::match_hostname::{match_hostname, strip_host_port};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::upstream_health::{upstream_health_html, upstream_health_json};
+
+// The maximum size of the admin API request body
+const MAX_REQUEST_BODY_SIZE: usize = 65536;
+
+// The process-wide statistics of the connections and the requests
+pub static SERVER_STATS: ServerStats = ServerStats::new();
+
+// Incremented each time the cache purge is requested through the admin API
+static CACHE_PURGE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// The reverse proxy backends, to which no new requests are sent
+static DRAINED_BACKENDS: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+
+// The temporary error log level overrides for the specific hosts or client IP addresses
+static LOG_LEVEL_OVERRIDES: RwLock<Vec<LogLevelOverride>> = RwLock::new(Vec::new());
+
+// The identifier of the next error log level override
+static NEXT_LOG_LEVEL_OVERRIDE_ID: AtomicU64 = AtomicU64::new(1);
+
+// The default and the maximum duration of the error log level override, in seconds
+const DEFAULT_LOG_LEVEL_OVERRIDE_DURATION: u64 = 600;
+const MAX_LOG_LEVEL_OVERRIDE_DURATION: u64 = 86400;
+
+// The current log level
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+// The minimum error log levels, globally and for the modules
+static ERROR_LOG_LEVELS: RwLock<ErrorLogLevels> = RwLock::new(ErrorLogLevels {
+  default_level: ErrorLogLevel::Info,
+  module_levels: BTreeMap::new(),
+});
+
+// The minimum levels of the error log messages, which are written into the error log.
+// The module levels override the default level for the messages logged by the specific modules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorLogLevels {
+  pub default_level: ErrorLogLevel,
+  pub module_levels: BTreeMap<String, ErrorLogLevel>,
+}
+
+impl ErrorLogLevels {
+  // Check if the error log message logged by the source (a module name, or "server") at the level is written
+  pub fn allows(&self, source: &str, level: ErrorLogLevel) -> bool {
+    level
+      <= self
+        .module_levels
+        .get(source)
+        .copied()
+        .unwrap_or(self.default_level)
+  }
+
+  fn to_json(&self) -> Value {
+    json!({
+      "level": self.default_level.as_str(),
+      "modules": self
+        .module_levels
+        .iter()
+        .map(|(module, level)| (module.clone(), Value::from(level.as_str())))
+        .collect::<serde_json::Map<_, _>>(),
+    })
+  }
+}
+
+// Get the current minimum error log levels
+pub fn error_log_levels() -> ErrorLogLevels {
+  match ERROR_LOG_LEVELS.read() {
+    Ok(error_log_levels) => error_log_levels.clone(),
+    Err(_) => ErrorLogLevels {
+      default_level: ErrorLogLevel::Info,
+      module_levels: BTreeMap::new(),
+    },
+  }
+}
+
+// Check if the error log message logged by the source at the level is written
+pub fn error_log_level_allows(source: &str, level: ErrorLogLevel) -> bool {
+  match ERROR_LOG_LEVELS.read() {
+    Ok(error_log_levels) => error_log_levels.allows(source, level),
+    Err(_) => true,
+  }
+}
+
+// Set the minimum error log levels. They are set from the configuration when the server starts,
+// and can be changed through the admin API afterwards.
+pub fn set_error_log_levels(levels: ErrorLogLevels) {
+  if let Ok(mut error_log_levels) = ERROR_LOG_LEVELS.write() {
+    *error_log_levels = levels;
+  }
+}
+
+// Parse the minimum error log levels from the global configuration ("errorLogLevel" and "moduleLogLevels" properties)
+pub fn parse_error_log_levels(
+  global_config: &Yaml,
+) -> Result<ErrorLogLevels, Box<dyn Error + Send + Sync>> {
+  let default_level = match global_config["errorLogLevel"].as_str() {
+    Some(level) => ErrorLogLevel::parse(level)
+      .ok_or_else(|| anyhow::anyhow!("Invalid error log level \"{}\"", level))?,
+    None => ErrorLogLevel::Info,
+  };
+  let mut module_levels = BTreeMap::new();
+  if let Some(modules) = global_config["moduleLogLevels"].as_hash() {
+    for (module, level) in modules {
+      let module = module
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid module name in the module log levels"))?;
+      let level = level
+        .as_str()
+        .and_then(ErrorLogLevel::parse)
+        .ok_or_else(|| anyhow::anyhow!("Invalid error log level for the \"{}\" module", module))?;
+      module_levels.insert(module.to_string(), level);
+    }
+  }
+  Ok(ErrorLogLevels {
+    default_level,
+    module_levels,
+  })
+}
+
+// The log level, which determines which log messages are written into the log files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+  // Both the access log and the error log entries are written
+  Info = 0,
+  // Only the error log entries are written
+  Error = 1,
+  // No log entries are written
+  Off = 2,
+}
+
+impl LogLevel {
+  pub fn parse(level: &str) -> Option<Self> {
+    match level {
+      "info" => Some(Self::Info),
+      "error" => Some(Self::Error),
+      "off" => Some(Self::Off),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Info => "info",
+      Self::Error => "error",
+      Self::Off => "off",
+    }
+  }
+
+  // Check if the log message is written at this log level
+  pub fn allows(&self, is_error: bool) -> bool {
+    match self {
+      Self::Info => true,
+      Self::Error => is_error,
+      Self::Off => false,
+    }
+  }
+}
+
+// Get the current log level
+pub fn log_level() -> LogLevel {
+  match LOG_LEVEL.load(Ordering::Relaxed) {
+    0 => LogLevel::Info,
+    1 => LogLevel::Error,
+    _ => LogLevel::Off,
+  }
+}
+
+// Set the current log level. The log level is kept when the configuration is reloaded.
+pub fn set_log_level(level: LogLevel) {
+  LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Get the current cache purge generation. The caches are purged when the generation changes.
+pub fn cache_purge_generation() -> u64 {
+  CACHE_PURGE_GENERATION.load(Ordering::Relaxed)
+}
+
+// Request the caches to be purged
+pub fn purge_caches() {
+  CACHE_PURGE_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+// Check if the reverse proxy backend is drained
+pub fn is_backend_drained(backend: &str) -> bool {
+  match DRAINED_BACKENDS.read() {
+    Ok(drained_backends) => drained_backends.contains(backend),
+    Err(poisoned) => poisoned.into_inner().contains(backend),
+  }
+}
+
+// Get the list of the drained reverse proxy backends
+pub fn drained_backends() -> Vec<String> {
+  match DRAINED_BACKENDS.read() {
+    Ok(drained_backends) => drained_backends.iter().cloned().collect(),
+    Err(poisoned) => poisoned.into_inner().iter().cloned().collect(),
+  }
+}
+
+// Mark the reverse proxy backend as drained or not drained
+fn set_backend_drained(backend: &str, drained: bool) {
+  let mut drained_backends = match DRAINED_BACKENDS.write() {
+    Ok(drained_backends) => drained_backends,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if drained {
+    drained_backends.insert(backend.to_string());
+  } else {
+    drained_backends.remove(backend);
+  }
+}
+
+// The error log level raised temporarily for the requests to a host, or from a client IP address (or both).
+// The override expires automatically, so that the verbose logging isn't left enabled by accident.
+#[derive(Clone)]
+pub struct LogLevelOverride {
+  id: u64,
+  host: Option<String>,
+  client_network: Option<String>,
+  client_network_trie: IpPrefixTrie,
+  level: ErrorLogLevel,
+  expires: Instant,
+}
+
+impl LogLevelOverride {
+  pub fn new(
+    host: Option<&str>,
+    client_network: Option<&str>,
+    level: ErrorLogLevel,
+    duration: Duration,
+  ) -> Result<Self, String> {
+    if host.is_none() && client_network.is_none() {
+      return Err(String::from(
+        "Either the host or the client IP address must be specified",
+      ));
+    }
+    let mut client_network_trie = IpPrefixTrie::new();
+    if let Some(client_network) = client_network {
+      if !client_network_trie.insert_cidr(client_network) {
+        return Err(String::from("Invalid client IP address"));
+      }
+    }
+    Ok(Self {
+      id: NEXT_LOG_LEVEL_OVERRIDE_ID.fetch_add(1, Ordering::Relaxed),
+      host: host.map(|host| host.to_lowercase()),
+      client_network: client_network.map(String::from),
+      client_network_trie,
+      level,
+      expires: Instant::now() + duration,
+    })
+  }
+
+  // Check if the override applies to the request to the host (the "Host" header value) from the client IP address
+  fn matches(&self, host: Option<&str>, client_ip: IpAddr, now: Instant) -> bool {
+    now < self.expires
+      && (self.host.is_none() || match_hostname(self.host.as_deref(), host.map(strip_host_port)))
+      && (self.client_network.is_none() || self.client_network_trie.contains(client_ip))
+  }
+
+  fn to_json(&self, now: Instant) -> Value {
+    json!({
+      "id": self.id,
+      "host": self.host,
+      "clientIp": self.client_network,
+      "level": self.level.as_str(),
+      "expiresIn": self.expires.saturating_duration_since(now).as_secs(),
+    })
+  }
+}
+
+// Get the most verbose error log level override applying to the request to the host from the client IP address
+pub fn log_level_override(host: Option<&str>, client_ip: IpAddr) -> Option<ErrorLogLevel> {
+  let log_level_overrides = match LOG_LEVEL_OVERRIDES.read() {
+    Ok(log_level_overrides) => log_level_overrides,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if log_level_overrides.is_empty() {
+    return None;
+  }
+  let now = Instant::now();
+  log_level_overrides
+    .iter()
+    .filter(|log_level_override| log_level_override.matches(host, client_ip, now))
+    .map(|log_level_override| log_level_override.level)
+    .max()
+}
+
+// Modify the error log level overrides, removing the expired ones, and get the remaining overrides in the JSON format
+fn modify_log_level_overrides<T>(
+  modify: impl FnOnce(&mut Vec<LogLevelOverride>) -> T,
+) -> (T, Value) {
+  let mut log_level_overrides = match LOG_LEVEL_OVERRIDES.write() {
+    Ok(log_level_overrides) => log_level_overrides,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let now = Instant::now();
+  log_level_overrides.retain(|log_level_override| now < log_level_override.expires);
+  let result = modify(&mut log_level_overrides);
+  (
+    result,
+    Value::from(
+      log_level_overrides
+        .iter()
+        .map(|log_level_override| log_level_override.to_json(now))
+        .collect::<Vec<_>>(),
+    ),
+  )
+}
+
+// The counters of the active and total connections and requests
+pub struct ServerStats {
+  active_connections: AtomicU64,
+  idle_connections: AtomicU64,
+  upgraded_connections: AtomicU64,
+  reaped_connections: AtomicU64,
+  total_connections: AtomicU64,
+  active_requests: AtomicU64,
+  total_requests: AtomicU64,
+}
+
+impl ServerStats {
+  const fn new() -> Self {
+    Self {
+      active_connections: AtomicU64::new(0),
+      idle_connections: AtomicU64::new(0),
+      upgraded_connections: AtomicU64::new(0),
+      reaped_connections: AtomicU64::new(0),
+      total_connections: AtomicU64::new(0),
+      active_requests: AtomicU64::new(0),
+      total_requests: AtomicU64::new(0),
+    }
+  }
+
+  // Count a new connection. The connection is active until the returned guard is dropped.
+  pub fn start_connection(&'static self) -> ActiveGuard {
+    self.total_connections.fetch_add(1, Ordering::Relaxed);
+    self.active_connections.fetch_add(1, Ordering::Relaxed);
+    ActiveGuard(&self.active_connections)
+  }
+
+  // Count a connection waiting for a request. The connection is idle until the returned guard is dropped.
+  pub fn start_idle_connection(&'static self) -> ActiveGuard {
+    self.idle_connections.fetch_add(1, Ordering::Relaxed);
+    ActiveGuard(&self.idle_connections)
+  }
+
+  // Count an upgraded (for example WebSocket) connection, until the returned guard is dropped.
+  pub fn start_upgraded_connection(&'static self) -> ActiveGuard {
+    self.upgraded_connections.fetch_add(1, Ordering::Relaxed);
+    ActiveGuard(&self.upgraded_connections)
+  }
+
+  // Count a connection closed for exceeding an idle timeout
+  pub fn record_reaped_connection(&self) {
+    self.reaped_connections.fetch_add(1, Ordering::Relaxed);
+  }
+
+  // Count a new request. The request is active until the returned guard is dropped.
+  pub fn start_request(&'static self) -> ActiveGuard {
+    self.total_requests.fetch_add(1, Ordering::Relaxed);
+    self.active_requests.fetch_add(1, Ordering::Relaxed);
+    ActiveGuard(&self.active_requests)
+  }
+
+  fn to_json(&self) -> Value {
+    json!({
+      "connections": {
+        "active": self.active_connections.load(Ordering::Relaxed),
+        "idle": self.idle_connections.load(Ordering::Relaxed),
+        "upgraded": self.upgraded_connections.load(Ordering::Relaxed),
+        "reaped": self.reaped_connections.load(Ordering::Relaxed),
+        "total": self.total_connections.load(Ordering::Relaxed),
+      },
+      "requests": {
+        "active": self.active_requests.load(Ordering::Relaxed),
+        "total": self.total_requests.load(Ordering::Relaxed),
+      },
+    })
+  }
+}
+
+// A guard, which decrements the counter of the active connections or requests when dropped
+pub struct ActiveGuard(&'static AtomicU64);
+
+impl Drop for ActiveGuard {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+// The address the admin API listens on
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdminAddress {
+  Tcp(SocketAddr),
+  Unix(String),
+}
+
+// Parse the admin API listen address, which is either a loopback address with a port, or "unix:" followed by the socket path
+pub fn parse_admin_address(address: &str) -> Result<AdminAddress, Box<dyn Error + Send + Sync>> {
+  if let Some(path) = address.strip_prefix("unix:") {
+    if path.is_empty() {
+      Err(anyhow::anyhow!("The admin API socket path is empty"))?
+    }
+    if cfg!(not(unix)) {
+      Err(anyhow::anyhow!(
+        "Unix sockets for the admin API are not supported on this platform"
+      ))?
+    }
+    return Ok(AdminAddress::Unix(path.to_string()));
+  }
+  let socket_address = address
+    .parse::<SocketAddr>()
+    .map_err(|_| anyhow::anyhow!("Invalid admin API listen address: {}", address))?;
+  if !socket_address.ip().is_loopback() {
+    Err(anyhow::anyhow!(
+      "The admin API can listen only on a loopback address"
+    ))?
+  }
+  Ok(AdminAddress::Tcp(socket_address))
+}
+
+// Check if the "Authorization" header contains the admin API token. The token is compared in constant time.
+fn is_authorized(authorization: Option<&str>, token: &str) -> bool {
+  let provided_token = match authorization.and_then(|authorization| {
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    match scheme.eq_ignore_ascii_case("Bearer") {
+      true => Some(credentials.trim()),
+      false => None,
+    }
+  }) {
+    Some(provided_token) => provided_token,
+    None => return false,
+  };
+  provided_token.len() == token.len()
+    && provided_token
+      .bytes()
+      .zip(token.bytes())
+      .fold(0, |difference, (a, b)| difference | (a ^ b))
+      == 0
+}
+
+// A listener of the admin API connections
+pub enum AdminListener {
+  Tcp(TcpListener),
+  #[cfg(unix)]
+  Unix(UnixListener),
+}
+
+impl AdminListener {
+  // Bind to the admin API address specified in the "adminApi" configuration section
+  pub async fn bind(admin_api_yaml: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let address = admin_api_yaml["listen"].as_str().ok_or(anyhow::anyhow!(
+      "The admin API listen address isn't specified"
+    ))?;
+    match parse_admin_address(address)? {
+      AdminAddress::Tcp(socket_address) => Ok(Self::Tcp(TcpListener::bind(socket_address).await?)),
+      #[cfg(unix)]
+      AdminAddress::Unix(path) => {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Remove the socket left over by the previous server run or configuration
+        if let Err(err) = std::fs::remove_file(&path) {
+          if err.kind() != std::io::ErrorKind::NotFound {
+            Err(err)?
+          }
+        }
+        let listener = UnixListener::bind(&path)?;
+        // Only the user running the server can connect to the socket
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Self::Unix(listener))
+      }
+      #[cfg(not(unix))]
+      AdminAddress::Unix(_) => Err(anyhow::anyhow!(
+        "Unix sockets for the admin API are not supported on this platform"
+      ))?,
+    }
+  }
+}
+
+// Send a GET request to the admin API of the running server, returning the response body
+pub async fn admin_api_get(
+  admin_api_yaml: &Yaml,
+  path: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+  let address = admin_api_yaml["listen"].as_str().ok_or(anyhow::anyhow!(
+    "The admin API listen address isn't specified"
+  ))?;
+  let token = admin_api_yaml["token"].as_str().unwrap_or_default();
+  match parse_admin_address(address)? {
+    AdminAddress::Tcp(socket_address) => {
+      admin_api_get_over_stream(TcpStream::connect(socket_address).await?, path, token).await
+    }
+    #[cfg(unix)]
+    AdminAddress::Unix(path_to_socket) => {
+      admin_api_get_over_stream(UnixStream::connect(path_to_socket).await?, path, token).await
+    }
+    #[cfg(not(unix))]
+    AdminAddress::Unix(_) => Err(anyhow::anyhow!(
+      "Unix sockets for the admin API are not supported on this platform"
+    ))?,
+  }
+}
+
+async fn admin_api_get_over_stream(
+  stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+  path: &str,
+  token: &str,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+  let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+  tokio::spawn(async move {
+    conn.await.unwrap_or_default();
+  });
+
+  let request = Request::builder()
+    .uri(path)
+    .header(header::HOST, "localhost")
+    .header(header::AUTHORIZATION, format!("Bearer {}", token))
+    .body(Empty::<Bytes>::new())?;
+
+  let response = sender.send_request(request).await?;
+  let status = response.status();
+  let body = response.into_body().collect().await?.to_bytes();
+  if !status.is_success() {
+    Err(anyhow::anyhow!(
+      "Unexpected response status code: {} ({})",
+      status,
+      String::from_utf8_lossy(&body)
+    ))?
+  }
+
+  Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+// Serve the admin API. The reload sender is used to request the configuration reload.
+pub async fn serve_admin_api(
+  listener: AdminListener,
+  token: Arc<str>,
+  deployments: Arc<Vec<DeploymentTarget>>,
+  reload_sender: Sender<()>,
+  logger: Sender<LogMessage>,
+) {
+  loop {
+    let accepted = match &listener {
+      AdminListener::Tcp(listener) => listener.accept().await.map(|(stream, _)| {
+        spawn_admin_connection(
+          stream,
+          token.clone(),
+          deployments.clone(),
+          reload_sender.clone(),
+          logger.clone(),
+        )
+      }),
+      #[cfg(unix)]
+      AdminListener::Unix(listener) => listener.accept().await.map(|(stream, _)| {
+        spawn_admin_connection(
+          stream,
+          token.clone(),
+          deployments.clone(),
+          reload_sender.clone(),
+          logger.clone(),
+        )
+      }),
+    };
+    if let Err(err) = accepted {
+      logger
+        .send(LogMessage::new(
+          format!("Cannot accept an admin API connection: {:?}", err),
+          true,
+        ))
+        .await
+        .unwrap_or_default();
+    }
+  }
+}
+
+fn spawn_admin_connection<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+  stream: S,
+  token: Arc<str>,
+  deployments: Arc<Vec<DeploymentTarget>>,
+  reload_sender: Sender<()>,
+  logger: Sender<LogMessage>,
+) {
+  tokio::task::spawn(async move {
+    let service = service_fn(move |request| {
+      let token = token.clone();
+      let deployments = deployments.clone();
+      let reload_sender = reload_sender.clone();
+      let logger = logger.clone();
+      async move {
+        Ok::<_, Infallible>(
+          admin_request_handler(request, &token, &deployments, reload_sender, logger).await,
+        )
+      }
+    });
+    hyper::server::conn::http1::Builder::new()
+      .serve_connection(TokioIo::new(stream), service)
+      .await
+      .unwrap_or_default();
+  });
+}
+
+// Create a JSON response of the admin API
+fn json_response(status_code: StatusCode, body: Value) -> Response<Full<Bytes>> {
+  Response::builder()
+    .status(status_code)
+    .header(header::CONTENT_TYPE, "application/json")
+    .header(header::CACHE_CONTROL, "no-store")
+    .body(Full::new(Bytes::from(body.to_string())))
+    .unwrap_or_default()
+}
+
+fn error_response(status_code: StatusCode, message: &str) -> Response<Full<Bytes>> {
+  json_response(status_code, json!({ "error": message }))
+}
+
+// Read the JSON request body, returning the error response if the body is invalid
+async fn read_json_body(request: Request<Incoming>) -> Result<Value, Response<Full<Bytes>>> {
+  let body = match Limited::new(request.into_body(), MAX_REQUEST_BODY_SIZE)
+    .collect()
+    .await
+  {
+    Ok(body) => body.to_bytes(),
+    Err(_) => {
+      return Err(error_response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "The request body is too large",
+      ))
+    }
+  };
+  serde_json::from_slice(&body)
+    .map_err(|_| error_response(StatusCode::BAD_REQUEST, "The request body isn't valid JSON"))
+}
+
+// Apply the error log level changes from the admin API request body ("level" and "modules" properties).
+// The module levels set to null are removed, so the modules use the default level again.
+fn apply_error_log_levels(levels: &mut ErrorLogLevels, changes: &Value) -> Result<(), String> {
+  let invalid_level = || {
+    String::from(
+      "The error log level must be \"error\", \"warn\", \"info\", \"debug\" or \"trace\"",
+    )
+  };
+  match changes {
+    Value::Null => return Ok(()),
+    Value::Object(_) => (),
+    _ => return Err(String::from("The error log levels must be an object")),
+  }
+  if !changes["level"].is_null() {
+    levels.default_level = changes["level"]
+      .as_str()
+      .and_then(ErrorLogLevel::parse)
+      .ok_or_else(invalid_level)?;
+  }
+  match &changes["modules"] {
+    Value::Null => (),
+    Value::Object(modules) => {
+      for (module, level) in modules {
+        match level {
+          Value::Null => {
+            levels.module_levels.remove(module);
+          }
+          level => {
+            let level = level
+              .as_str()
+              .and_then(ErrorLogLevel::parse)
+              .ok_or_else(invalid_level)?;
+            levels.module_levels.insert(module.clone(), level);
+          }
+        }
+      }
+    }
+    _ => {
+      return Err(String::from(
+        "The module error log levels must be an object",
+      ))
+    }
+  }
+  Ok(())
+}
+
+// Generate the status page, showing the connections, the requests and the health of the backend servers
+fn status_page_html() -> String {
+  let stats = SERVER_STATS.to_json();
+  format!(
+    "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"UTF-8\">
+    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
+    <title>Ferron status</title>
+    <style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #999; padding: 0.25em 0.5em; text-align: left; }} tr.failing {{ background: #fff3cd; }} tr.ejected {{ background: #f8d7da; }} tr.drained {{ background: #e2e3e5; }}</style>
+</head>
+<body>
+    <h1>Ferron status</h1>
+    <p>Connections: {} active, {} idle, {} upgraded, {} total. Requests: {} active, {} total.</p>
+    <h2>Backend servers</h2>
+    {}
+</body>
+</html>",
+    stats["connections"]["active"],
+    stats["connections"]["idle"],
+    stats["connections"]["upgraded"],
+    stats["connections"]["total"],
+    stats["requests"]["active"],
+    stats["requests"]["total"],
+    upstream_health_html()
+  )
+}
+
+// Get the deployment targets with their releases in the JSON format
+async fn deployments_json(deployments: &[DeploymentTarget]) -> Value {
+  let deployments = deployments.to_vec();
+  spawn_blocking_budgeted("admin_api", move || {
+    deployments
+      .iter()
+      .map(|target| {
+        json!({
+          "name": target.name,
+          "path": target.path.to_string_lossy(),
+          "currentRelease": current_release(target),
+          "releases": releases(target).unwrap_or_default(),
+        })
+      })
+      .collect::<Vec<_>>()
+  })
+  .await
+  .map(Value::from)
+  .unwrap_or_default()
+}
+
+async fn admin_request_handler(
+  request: Request<Incoming>,
+  token: &str,
+  deployments: &[DeploymentTarget],
+  reload_sender: Sender<()>,
+  logger: Sender<LogMessage>,
+) -> Response<Full<Bytes>> {
+  let authorization = request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|authorization| authorization.to_str().ok());
+  if !is_authorized(authorization, token) {
+    let mut response = error_response(StatusCode::UNAUTHORIZED, "Invalid admin API token");
+    response.headers_mut().insert(
+      header::WWW_AUTHENTICATE,
+      header::HeaderValue::from_static("Bearer"),
+    );
+    return response;
+  }
+
+  let log_action = |message: String| {
+    let logger = logger.clone();
+    async move {
+      logger
+        .send(LogMessage::new(format!("Admin API: {}", message), true))
+        .await
+        .unwrap_or_default();
+    }
+  };
+
+  let path = request.uri().path().to_string();
+  match (request.method(), path.as_str()) {
+    (&Method::GET, "/health") => json_response(StatusCode::OK, json!({ "status": "ok" })),
+    (&Method::GET, "/stats") => {
+      let mut stats = SERVER_STATS.to_json();
+      stats["drainedUpstreams"] = json!(drained_backends());
+      stats["upstreams"] = upstream_health_json();
+      json_response(StatusCode::OK, stats)
+    }
+    (&Method::GET, "/status") => Response::builder()
+      .status(StatusCode::OK)
+      .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+      .header(header::CACHE_CONTROL, "no-store")
+      .body(Full::new(Bytes::from(status_page_html())))
+      .unwrap_or_default(),
+    (&Method::GET, "/config") => json_response(StatusCode::OK, active_config_json()),
+    (&Method::GET, "/metrics") => Response::builder()
+      .status(StatusCode::OK)
+      .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+      .header(header::CACHE_CONTROL, "no-store")
+      .body(Full::new(Bytes::from(METRICS.render())))
+      .unwrap_or_default(),
+    (&Method::POST, "/reload") => {
+      log_action(String::from("configuration reload requested")).await;
+      // Reload the configuration after the response is sent
+      tokio::task::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        reload_sender.try_send(()).unwrap_or_default();
+      });
+      json_response(StatusCode::ACCEPTED, json!({ "status": "reloading" }))
+    }
+    (&Method::POST, "/cache/purge") => {
+      purge_caches();
+      log_action(String::from("cache purge requested")).await;
+      json_response(StatusCode::OK, json!({ "status": "purged" }))
+    }
+    (&Method::GET, "/cache/prewarm") => json_response(
+      StatusCode::OK,
+      json!({ "jobs": prewarm_job_statuses_json() }),
+    ),
+    (&Method::GET, "/upstreams") => json_response(
+      StatusCode::OK,
+      json!({ "drained": drained_backends(), "backends": upstream_health_json() }),
+    ),
+    (&Method::POST, "/upstreams/drain") | (&Method::POST, "/upstreams/undrain") => {
+      let drained = path == "/upstreams/drain";
+      let body = match read_json_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+      };
+      let backend = match body["backend"].as_str() {
+        Some(backend) => backend,
+        None => {
+          return error_response(
+            StatusCode::BAD_REQUEST,
+            "The upstream backend isn't specified",
+          )
+        }
+      };
+      set_backend_drained(backend, drained);
+      log_action(format!(
+        "upstream backend {} {}",
+        backend,
+        match drained {
+          true => "drained",
+          false => "undrained",
+        }
+      ))
+      .await;
+      json_response(StatusCode::OK, json!({ "drained": drained_backends() }))
+    }
+    (&Method::GET, "/deployments") => json_response(
+      StatusCode::OK,
+      json!({ "deployments": deployments_json(deployments).await }),
+    ),
+    (&Method::POST, deployment_path) if deployment_path.starts_with("/deployments/") => {
+      let (name, is_rollback) =
+        match deployment_path["/deployments/".len()..].strip_suffix("/rollback") {
+          Some(name) => (name, true),
+          None => (&deployment_path["/deployments/".len()..], false),
+        };
+      let target = match deployments.iter().find(|target| target.name == name) {
+        Some(target) => target,
+        None => return error_response(StatusCode::NOT_FOUND, "The deployment doesn't exist"),
+      };
+
+      if is_rollback {
+        // The release to roll back to is optional, and the previous release is used by default
+        let release = match request.headers().contains_key(header::CONTENT_LENGTH)
+          || request.headers().contains_key(header::TRANSFER_ENCODING)
+        {
+          true => match read_json_body(request).await {
+            Ok(body) => body["release"].as_str().map(String::from),
+            Err(response) => return response,
+          },
+          false => None,
+        };
+        return match rollback(target, release.as_deref()).await {
+          Ok(release) => {
+            purge_caches();
+            log_action(format!(
+              "deployment {} rolled back to release {}",
+              target.name, release
+            ))
+            .await;
+            json_response(
+              StatusCode::OK,
+              json!({ "deployment": target.name, "release": release }),
+            )
+          }
+          Err(err) => error_response(StatusCode::CONFLICT, &err.to_string()),
+        };
+      }
+
+      let archive = match Limited::new(request.into_body(), target.max_upload_size as usize)
+        .collect()
+        .await
+      {
+        Ok(archive) => archive.to_bytes(),
+        Err(_) => {
+          return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The uploaded archive is too large",
+          )
+        }
+      };
+      match deploy(target, &archive).await {
+        Ok((release, files)) => {
+          // The cached responses might contain the content of the previous release
+          purge_caches();
+          log_action(format!(
+            "release {} deployed to {} ({} files)",
+            release, target.name, files
+          ))
+          .await;
+          json_response(
+            StatusCode::CREATED,
+            json!({ "deployment": target.name, "release": release, "files": files }),
+          )
+        }
+        Err(err) => error_response(StatusCode::UNPROCESSABLE_ENTITY, &err.to_string()),
+      }
+    }
+    (&Method::GET, "/log-level") => json_response(
+      StatusCode::OK,
+      json!({ "level": log_level().as_str(), "errorLog": error_log_levels().to_json() }),
+    ),
+    (&Method::PUT, "/log-level") => {
+      let body = match read_json_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+      };
+      let level = match &body["level"] {
+        Value::Null => None,
+        level => match level.as_str().and_then(LogLevel::parse) {
+          Some(level) => Some(level),
+          None => {
+            return error_response(
+              StatusCode::BAD_REQUEST,
+              "The log level must be \"info\", \"error\" or \"off\"",
+            )
+          }
+        },
+      };
+      let mut new_error_log_levels = error_log_levels();
+      if let Err(err) = apply_error_log_levels(&mut new_error_log_levels, &body["errorLog"]) {
+        return error_response(StatusCode::BAD_REQUEST, &err);
+      }
+      if level.is_none() && body["errorLog"].is_null() {
+        return error_response(
+          StatusCode::BAD_REQUEST,
+          "Either the log level or the error log levels must be specified",
+        );
+      }
+      // The changes are logged before the error log entries might be disabled
+      if let Some(level) = level {
+        log_action(format!("log level changed to {}", level.as_str())).await;
+        set_log_level(level);
+      }
+      if !body["errorLog"].is_null() {
+        log_action(format!(
+          "error log levels changed to {}",
+          new_error_log_levels.to_json()
+        ))
+        .await;
+        set_error_log_levels(new_error_log_levels.clone());
+      }
+      json_response(
+        StatusCode::OK,
+        json!({ "level": log_level().as_str(), "errorLog": new_error_log_levels.to_json() }),
+      )
+    }
+    (&Method::GET, "/log-level/overrides") => {
+      let (_, overrides) = modify_log_level_overrides(|_| ());
+      json_response(StatusCode::OK, json!({ "overrides": overrides }))
+    }
+    (&Method::POST, "/log-level/overrides") => {
+      let body = match read_json_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+      };
+      let level = match body["level"].as_str().and_then(ErrorLogLevel::parse) {
+        Some(level) => level,
+        None => {
+          return error_response(
+            StatusCode::BAD_REQUEST,
+            "The error log level must be \"error\", \"warn\", \"info\", \"debug\" or \"trace\"",
+          )
+        }
+      };
+      let duration = match &body["duration"] {
+        Value::Null => DEFAULT_LOG_LEVEL_OVERRIDE_DURATION,
+        duration => match duration.as_u64() {
+          Some(duration) if duration > 0 && duration <= MAX_LOG_LEVEL_OVERRIDE_DURATION => duration,
+          _ => {
+            return error_response(
+              StatusCode::BAD_REQUEST,
+              "The duration must be between 1 and 86400 seconds",
+            )
+          }
+        },
+      };
+      let log_level_override = match LogLevelOverride::new(
+        body["host"].as_str(),
+        body["clientIp"].as_str(),
+        level,
+        Duration::from_secs(duration),
+      ) {
+        Ok(log_level_override) => log_level_override,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+      };
+      let override_json = log_level_override.to_json(Instant::now());
+      log_action(format!(
+        "error log level override {} added for {} seconds",
+        override_json, duration
+      ))
+      .await;
+      modify_log_level_overrides(|log_level_overrides| {
+        log_level_overrides.push(log_level_override)
+      });
+      json_response(StatusCode::CREATED, override_json)
+    }
+    (&Method::DELETE, "/log-level/overrides") => {
+      let body = match read_json_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+      };
+      let id = match body["id"].as_u64() {
+        Some(id) => id,
+        None => {
+          return error_response(
+            StatusCode::BAD_REQUEST,
+            "The error log level override identifier isn't specified",
+          )
+        }
+      };
+      let (removed, overrides) = modify_log_level_overrides(|log_level_overrides| {
+        let override_count = log_level_overrides.len();
+        log_level_overrides.retain(|log_level_override| log_level_override.id != id);
+        log_level_overrides.len() != override_count
+      });
+      if !removed {
+        return error_response(
+          StatusCode::NOT_FOUND,
+          "The error log level override doesn't exist or has expired",
+        );
+      }
+      log_action(format!("error log level override {} removed", id)).await;
+      json_response(StatusCode::OK, json!({ "overrides": overrides }))
+    }
+    (
+      _,
+      "/health"
+      | "/stats"
+      | "/status"
+      | "/config"
+      | "/metrics"
+      | "/reload"
+      | "/cache/purge"
+      | "/cache/prewarm"
+      | "/upstreams"
+      | "/upstreams/drain"
+      | "/upstreams/undrain"
+      | "/log-level"
+      | "/log-level/overrides"
+      | "/deployments",
+    ) => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+    _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_admin_address() {
+    assert_eq!(
+      parse_admin_address("127.0.0.1:8081").unwrap(),
+      AdminAddress::Tcp("127.0.0.1:8081".parse().unwrap())
+    );
+    assert_eq!(
+      parse_admin_address("[::1]:8081").unwrap(),
+      AdminAddress::Tcp("[::1]:8081".parse().unwrap())
+    );
+    assert!(parse_admin_address("0.0.0.0:8081").is_err());
+    assert!(parse_admin_address("localhost").is_err());
+    assert!(parse_admin_address("unix:").is_err());
+    #[cfg(unix)]
+    assert_eq!(
+      parse_admin_address("unix:/run/ferron-admin.sock").unwrap(),
+      AdminAddress::Unix(String::from("/run/ferron-admin.sock"))
+    );
+  }
+
+  #[test]
+  fn test_is_authorized() {
+    assert!(is_authorized(Some("Bearer s3cret"), "s3cret"));
+    assert!(is_authorized(Some("bearer  s3cret"), "s3cret"));
+    assert!(!is_authorized(Some("Bearer s3cre"), "s3cret"));
+    assert!(!is_authorized(Some("Bearer s3creT"), "s3cret"));
+    assert!(!is_authorized(Some("Basic s3cret"), "s3cret"));
+    assert!(!is_authorized(None, "s3cret"));
+  }
+
+  #[test]
+  fn test_error_log_levels() {
+    let mut levels = parse_error_log_levels(
+      &yaml_rust2::YamlLoader::load_from_str(
+        "errorLogLevel: warn\nmoduleLogLevels:\n  rproxy: debug",
+      )
+      .unwrap()[0],
+    )
+    .unwrap();
+    assert!(levels.allows("server", ErrorLogLevel::Warn));
+    assert!(!levels.allows("server", ErrorLogLevel::Info));
+    assert!(levels.allows("rproxy", ErrorLogLevel::Debug));
+    assert!(!levels.allows("rproxy", ErrorLogLevel::Trace));
+    apply_error_log_levels(
+      &mut levels,
+      &json!({ "level": "error", "modules": { "rproxy": null, "waf": "trace" } }),
+    )
+    .unwrap();
+    assert!(!levels.allows("rproxy", ErrorLogLevel::Warn));
+    assert!(levels.allows("waf", ErrorLogLevel::Trace));
+    assert!(apply_error_log_levels(&mut levels, &json!({ "level": "verbose" })).is_err());
+    assert!(parse_error_log_levels(
+      &yaml_rust2::YamlLoader::load_from_str("errorLogLevel: verbose").unwrap()[0]
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_log_level_override() {
+    let now = Instant::now();
+    let host_override = LogLevelOverride::new(
+      Some("Example.com"),
+      None,
+      ErrorLogLevel::Debug,
+      Duration::from_secs(60),
+    )
+    .unwrap();
+    let client_ip = "192.0.2.10".parse().unwrap();
+    assert!(host_override.matches(Some("example.com:8443"), client_ip, now));
+    assert!(!host_override.matches(Some("example.org"), client_ip, now));
+    assert!(!host_override.matches(
+      Some("example.com"),
+      client_ip,
+      now + Duration::from_secs(61)
+    ));
+    let network_override = LogLevelOverride::new(
+      Some("*.example.com"),
+      Some("192.0.2.0/24"),
+      ErrorLogLevel::Trace,
+      Duration::from_secs(60),
+    )
+    .unwrap();
+    assert!(network_override.matches(Some("www.example.com"), client_ip, now));
+    assert!(!network_override.matches(
+      Some("www.example.com"),
+      "198.51.100.1".parse().unwrap(),
+      now
+    ));
+    assert!(
+      LogLevelOverride::new(None, None, ErrorLogLevel::Debug, Duration::from_secs(60)).is_err()
+    );
+    assert!(LogLevelOverride::new(
+      None,
+      Some("192.0.2.0/33"),
+      ErrorLogLevel::Debug,
+      Duration::from_secs(60)
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_log_levels() {
+    assert_eq!(LogLevel::parse("error"), Some(LogLevel::Error));
+    assert_eq!(LogLevel::parse("debug"), None);
+    assert!(LogLevel::Info.allows(false));
+    assert!(LogLevel::Error.allows(true));
+    assert!(!LogLevel::Error.allows(false));
+    assert!(!LogLevel::Off.allows(true));
+  }
+
+  #[test]
+  fn test_status_page() {
+    let status_page = status_page_html();
+    assert!(status_page.starts_with("<!DOCTYPE html>"));
+    assert!(status_page.contains("<h2>Backend servers</h2>"));
+  }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ferron_util::storage::{unix_time_millis, Storage, StorageBackend, STORAGE};
+
+// The prefix of the storage keys of the temporary bans. The values are the ban expiration times.
+const BAN_KEY_PREFIX: &str = "ferron:ban:";
+
+// The prefix of the storage keys of the offense counters
+const OFFENSE_KEY_PREFIX: &str = "ferron:auto-ban-offenses:";
+
+// How often the bans are read from a shared storage, so that the bans made by other servers are enforced
+pub const TEMPORARY_BANS_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+// The process-wide list of the temporarily banned clients, whose connections are closed immediately.
+// The clients are banned by the auto-ban subsystem and by the modules (for example, the bot mitigation module).
+pub static TEMPORARY_BANS: LazyLock<TemporaryBans> = LazyLock::new(|| TemporaryBans::new(&STORAGE));
+
+// A list of the temporarily banned clients. The bans are kept in memory, so that the accepted connections are checked
+// without waiting for the storage, and are written to the storage, so that they outlive the process or are shared with other servers.
+pub struct TemporaryBans {
+  bans: Mutex<HashMap<IpAddr, Instant>>,
+  storage: &'static Storage,
+}
+
+impl TemporaryBans {
+  pub fn new(storage: &'static Storage) -> Self {
+    Self {
+      bans: Mutex::new(HashMap::new()),
+      storage,
+    }
+  }
+
+  // Ban a client for the specified duration
+  pub async fn ban(&self, ip: IpAddr, ban_duration: Duration) {
+    let ip = ip.to_canonical();
+    let now = Instant::now();
+    if let Ok(mut bans) = self.bans.lock() {
+      bans.retain(|_, banned_until| *banned_until > now);
+      bans.insert(ip, now + ban_duration);
+    }
+
+    // The ban is enforced by this process even if it can't be stored
+    let banned_until = unix_time_millis() + ban_duration.as_millis() as u64;
+    self
+      .storage
+      .set(
+        &format!("{}{}", BAN_KEY_PREFIX, ip),
+        banned_until.to_string().as_bytes(),
+        Some(ban_duration),
+      )
+      .await
+      .unwrap_or_default();
+  }
+
+  // Check if a client is banned
+  pub fn is_banned(&self, ip: IpAddr) -> bool {
+    match self.bans.lock() {
+      Ok(bans) => bans
+        .get(&ip.to_canonical())
+        .is_some_and(|banned_until| *banned_until > Instant::now()),
+      Err(_) => false,
+    }
+  }
+
+  // Read the bans from the storage, including the bans made before the restart or by other servers sharing the storage
+  pub async fn refresh(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stored_bans = self.storage.scan(BAN_KEY_PREFIX).await?;
+    let now = Instant::now();
+    let now_unix_millis = unix_time_millis();
+    if let Ok(mut bans) = self.bans.lock() {
+      bans.retain(|_, banned_until| *banned_until > now);
+      for (key, value) in stored_bans {
+        let ip = match key[BAN_KEY_PREFIX.len()..].parse::<IpAddr>() {
+          Ok(ip) => ip.to_canonical(),
+          Err(_) => continue,
+        };
+        let banned_until = match std::str::from_utf8(&value)
+          .ok()
+          .and_then(|value| value.parse::<u64>().ok())
+        {
+          Some(banned_until) if banned_until > now_unix_millis => {
+            now + Duration::from_millis(banned_until - now_unix_millis)
+          }
+          _ => continue,
+        };
+        let ban = bans.entry(ip).or_insert(banned_until);
+        *ban = (*ban).max(banned_until);
+      }
+    }
+    Ok(())
+  }
+}
+
+// Temporarily bans clients that repeatedly send malicious or malformed requests
+pub struct AutoBan {
+  threshold: u64,
+  window: Duration,
+  ban_duration: Duration,
+  observe: bool,
+  bans: &'static TemporaryBans,
+}
+
+impl AutoBan {
+  // Create a new auto-ban tracker. A client is banned for "ban_duration" after "threshold" offenses within "window".
+  // The bans are stored in the specified ban list, and the offenses are counted in the storage of the ban list.
+  // In the observe mode, the clients reaching the threshold aren't banned.
+  pub fn new(
+    threshold: u64,
+    window: Duration,
+    ban_duration: Duration,
+    observe: bool,
+    bans: &'static TemporaryBans,
+  ) -> Self {
+    Self {
+      threshold,
+      window,
+      ban_duration,
+      observe,
+      bans,
+    }
+  }
+
+  // Record an offense of a client. Returns true if the client has just been banned (or would have been banned in the observe mode).
+  pub async fn record_offense(&self, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    let offense_key = format!("{}{}", OFFENSE_KEY_PREFIX, ip);
+    let offense_count = match self.bans.storage.increment(&offense_key, self.window).await {
+      Ok(offense_count) => offense_count,
+      Err(_) => return false,
+    };
+    if offense_count < self.threshold {
+      return false;
+    }
+    self
+      .bans
+      .storage
+      .delete(&offense_key)
+      .await
+      .unwrap_or_default();
+
+    if !self.observe {
+      self.bans.ban(ip, self.ban_duration).await;
+    }
+    true
+  }
+
+  // Check if the auto-ban subsystem is in the observe mode
+  pub fn is_observing(&self) -> bool {
+    self.observe
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn leaked_storage() -> &'static Storage {
+    Box::leak(Box::new(Storage::new()))
+  }
+
+  fn leaked_bans() -> &'static TemporaryBans {
+    Box::leak(Box::new(TemporaryBans::new(leaked_storage())))
+  }
+
+  #[tokio::test]
+  async fn test_ban_after_threshold() {
+    let bans = leaked_bans();
+    let auto_ban = AutoBan::new(
+      3,
+      Duration::from_secs(60),
+      Duration::from_secs(60),
+      false,
+      bans,
+    );
+    let ip: IpAddr = "203.0.113.5".parse().unwrap();
+
+    assert!(!auto_ban.record_offense(ip).await);
+    assert!(!auto_ban.record_offense(ip).await);
+    assert!(!bans.is_banned(ip));
+    assert!(auto_ban.record_offense(ip).await);
+    assert!(bans.is_banned(ip));
+    assert!(bans.is_banned("::ffff:203.0.113.5".parse().unwrap()));
+    assert!(!bans.is_banned("203.0.113.6".parse().unwrap()));
+  }
+
+  #[tokio::test]
+  async fn test_ban_expiration() {
+    let bans = leaked_bans();
+    let auto_ban = AutoBan::new(
+      1,
+      Duration::from_secs(60),
+      Duration::from_millis(10),
+      false,
+      bans,
+    );
+    let ip: IpAddr = "2001:db8::1".parse().unwrap();
+
+    assert!(auto_ban.record_offense(ip).await);
+    assert!(bans.is_banned(ip));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!bans.is_banned(ip));
+  }
+
+  #[tokio::test]
+  async fn test_observe_mode() {
+    let bans = leaked_bans();
+    let auto_ban = AutoBan::new(
+      1,
+      Duration::from_secs(60),
+      Duration::from_secs(60),
+      true,
+      bans,
+    );
+    let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+    assert!(auto_ban.record_offense(ip).await);
+    assert!(!bans.is_banned(ip));
+  }
+
+  #[tokio::test]
+  async fn test_refresh_from_storage() {
+    let storage = leaked_storage();
+    let bans = TemporaryBans::new(storage);
+    let shared_bans = TemporaryBans::new(storage);
+    let ip: IpAddr = "192.0.2.10".parse().unwrap();
+
+    bans.ban(ip, Duration::from_secs(60)).await;
+    assert!(!shared_bans.is_banned(ip));
+    shared_bans.refresh().await.unwrap();
+    assert!(shared_bans.is_banned(ip));
+    assert!(!shared_bans.is_banned("192.0.2.11".parse().unwrap()));
+  }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use ferron_common::ServerConfigRoot;
+use hyper::body::{Body, Buf, Frame, SizeHint};
+use tokio::time::{sleep, Sleep};
+
+// The number of the shared token buckets, above which the buckets no longer in use are removed
+const BUCKET_CLEANUP_THRESHOLD: usize = 1024;
+
+// The token buckets shared by the bodies sent to (or received from) the same connection, client IP address or host
+static SHARED_BUCKETS: Mutex<SharedBuckets> = Mutex::new(SharedBuckets {
+  buckets: None,
+  cleanup_at: BUCKET_CLEANUP_THRESHOLD,
+});
+
+struct SharedBuckets {
+  buckets: Option<HashMap<String, Weak<TokenBucket>>>,
+  cleanup_at: usize,
+}
+
+struct TokenBucketState {
+  tokens: f64,
+  bytes_per_second: u64,
+  last_refill: Instant,
+}
+
+// A token bucket limiting the bandwidth to the specified number of bytes per second, with bursts of up to one second of data.
+// The bytes are taken from the bucket after they are sent, so the sender waits for the bucket to refill before sending more.
+pub struct TokenBucket {
+  state: Mutex<TokenBucketState>,
+}
+
+impl TokenBucket {
+  pub fn new(bytes_per_second: u64) -> Self {
+    Self {
+      state: Mutex::new(TokenBucketState {
+        tokens: bytes_per_second as f64,
+        bytes_per_second,
+        last_refill: Instant::now(),
+      }),
+    }
+  }
+
+  // Change the bandwidth limit (for example, after the configuration has been reloaded)
+  pub fn set_rate(&self, bytes_per_second: u64) {
+    let mut state = match self.state.lock() {
+      Ok(state) => state,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    if state.bytes_per_second != bytes_per_second {
+      state.bytes_per_second = bytes_per_second;
+      state.tokens = state.tokens.min(bytes_per_second as f64);
+    }
+  }
+
+  // Take the bytes from the bucket. Returns how long the sender should wait before sending more data.
+  pub fn consume(&self, bytes: usize) -> Duration {
+    let mut state = match self.state.lock() {
+      Ok(state) => state,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    if state.bytes_per_second == 0 {
+      return Duration::ZERO;
+    }
+    let rate = state.bytes_per_second as f64;
+    let now = Instant::now();
+    state.tokens =
+      (state.tokens + now.duration_since(state.last_refill).as_secs_f64() * rate).min(rate);
+    state.last_refill = now;
+    state.tokens -= bytes as f64;
+    match state.tokens < 0.0 {
+      true => Duration::from_secs_f64(-state.tokens / rate),
+      false => Duration::ZERO,
+    }
+  }
+}
+
+// Get the token bucket shared under the key, creating it if no body uses it at the moment
+fn shared_token_bucket(key: String, bytes_per_second: u64) -> Arc<TokenBucket> {
+  let mut shared_buckets = match SHARED_BUCKETS.lock() {
+    Ok(shared_buckets) => shared_buckets,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let shared_buckets = &mut *shared_buckets;
+  let buckets = shared_buckets.buckets.get_or_insert_with(HashMap::new);
+  if let Some(bucket) = buckets.get(&key).and_then(Weak::upgrade) {
+    bucket.set_rate(bytes_per_second);
+    return bucket;
+  }
+  if buckets.len() >= shared_buckets.cleanup_at {
+    buckets.retain(|_, bucket| bucket.strong_count() > 0);
+    shared_buckets.cleanup_at = (buckets.len() * 2).max(BUCKET_CLEANUP_THRESHOLD);
+  }
+  let bucket = Arc::new(TokenBucket::new(bytes_per_second));
+  buckets.insert(key, Arc::downgrade(&bucket));
+  bucket
+}
+
+// The direction of the throttled body
+#[derive(Debug, Clone, Copy)]
+pub enum ThrottledDirection {
+  Download,
+  Upload,
+}
+
+impl ThrottledDirection {
+  fn as_str(&self) -> &'static str {
+    match self {
+      ThrottledDirection::Download => "download",
+      ThrottledDirection::Upload => "upload",
+    }
+  }
+}
+
+// The per-connection, per-IP and per-host bandwidth limits applying to a request or a response body
+#[derive(Clone)]
+pub struct BandwidthThrottle {
+  buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl BandwidthThrottle {
+  // Get the bandwidth limits configured with the "downloadLimitPer*" or "uploadLimitPer*" properties (in bytes per second)
+  pub fn from_config(
+    config: &ServerConfigRoot,
+    direction: ThrottledDirection,
+    remote_address: SocketAddr,
+    host: &str,
+  ) -> Option<Self> {
+    let direction_str = direction.as_str();
+    let buckets = [
+      (
+        "Connection",
+        format!("{}:connection:{}", direction_str, remote_address),
+      ),
+      (
+        "Ip",
+        format!(
+          "{}:ip:{}",
+          direction_str,
+          remote_address.ip().to_canonical()
+        ),
+      ),
+      ("Host", format!("{}:host:{}", direction_str, host)),
+    ]
+    .into_iter()
+    .filter_map(|(scope, key)| {
+      config
+        .get(&format!("{}LimitPer{}", direction_str, scope))
+        .as_i64()
+        .filter(|bytes_per_second| *bytes_per_second > 0)
+        .map(|bytes_per_second| shared_token_bucket(key, bytes_per_second as u64))
+    })
+    .collect::<Vec<_>>();
+    match buckets.is_empty() {
+      true => None,
+      false => Some(Self { buckets }),
+    }
+  }
+
+  pub fn wrap_body<B>(self, inner: B) -> ThrottledBody<B> {
+    ThrottledBody {
+      inner,
+      throttle: self,
+      delay: None,
+    }
+  }
+}
+
+// A request or response body wrapper, which keeps the body within the bandwidth limits
+pub struct ThrottledBody<B> {
+  inner: B,
+  throttle: BandwidthThrottle,
+  delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B: Body + Unpin> Body for ThrottledBody<B> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if let Some(delay) = this.delay.as_mut() {
+      match delay.as_mut().poll(cx) {
+        Poll::Ready(_) => this.delay = None,
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    let frame = Pin::new(&mut this.inner).poll_frame(cx);
+    if let Poll::Ready(Some(Ok(frame))) = &frame {
+      if let Some(data) = frame.data_ref() {
+        // The longest wait of all the limits applies
+        let wait = this
+          .throttle
+          .buckets
+          .iter()
+          .map(|bucket| bucket.consume(data.remaining()))
+          .max()
+          .unwrap_or_default();
+        if !wait.is_zero() {
+          this.delay = Some(Box::pin(sleep(wait)));
+        }
+      }
+    }
+    frame
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, StreamBody};
+  use hyper::body::Bytes;
+
+  #[test]
+  fn test_token_bucket() {
+    let bucket = TokenBucket::new(1000);
+    assert_eq!(bucket.consume(1000), Duration::ZERO);
+    let wait = bucket.consume(500);
+    assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+
+    let unlimited_bucket = TokenBucket::new(0);
+    assert_eq!(unlimited_bucket.consume(1_000_000), Duration::ZERO);
+  }
+
+  #[test]
+  fn test_shared_token_bucket() {
+    let bucket = shared_token_bucket("test:shared".to_string(), 1000);
+    let same_bucket = shared_token_bucket("test:shared".to_string(), 2000);
+    assert!(Arc::ptr_eq(&bucket, &same_bucket));
+    drop(bucket);
+    drop(same_bucket);
+    let new_bucket = shared_token_bucket("test:shared".to_string(), 1000);
+    assert_eq!(new_bucket.consume(1000), Duration::ZERO);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_throttled_body() {
+    let frames = (0..4).map(|_| Ok::<_, std::io::Error>(Frame::data(Bytes::from(vec![0u8; 500]))));
+    let throttle = BandwidthThrottle {
+      buckets: vec![Arc::new(TokenBucket::new(1000))],
+    };
+    let start = tokio::time::Instant::now();
+    let body = throttle.wrap_body(StreamBody::new(futures_util::stream::iter(frames)));
+    assert_eq!(body.collect().await.unwrap().to_bytes().len(), 2000);
+    // The first 1000 bytes are sent in a burst, and the remaining ones at 1000 bytes per second
+    assert!(start.elapsed() >= Duration::from_millis(900));
+  }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::Notify;
+use tokio::task::JoinError;
+
+use crate::ferron_util::metrics::METRICS;
+
+// The default maximum number of the threads in the blocking thread pool
+pub const DEFAULT_MAX_BLOCKING_THREADS: usize = 1536;
+
+// The process-wide blocking thread budgets of the modules
+pub static BLOCKING_BUDGETS: LazyLock<Arc<BlockingBudgets>> =
+  LazyLock::new(|| Arc::new(BlockingBudgets::new()));
+
+// The blocking tasks run and queued by the modules
+struct BudgetState {
+  pool_size: usize,
+  module_limit: usize,
+  running: HashMap<&'static str, usize>,
+  queued: HashMap<&'static str, usize>,
+}
+
+impl BudgetState {
+  // The number of the blocking tasks a module can run concurrently. The budget adapts to the load:
+  // a module can use the whole module limit while the other modules don't run blocking work,
+  // and the pool is shared fairly between the modules running or waiting for blocking work.
+  fn module_budget(&self, module: &'static str) -> usize {
+    let mut busy_modules = self
+      .running
+      .keys()
+      .chain(self.queued.keys())
+      .collect::<Vec<_>>();
+    busy_modules.push(&module);
+    busy_modules.sort_unstable();
+    busy_modules.dedup();
+    (self.pool_size / busy_modules.len())
+      .min(self.module_limit)
+      .max(1)
+  }
+
+  fn try_start(&mut self, module: &'static str) -> bool {
+    let running = self.running.get(module).copied().unwrap_or(0);
+    if running >= self.module_budget(module) {
+      return false;
+    }
+    self.running.insert(module, running + 1);
+    true
+  }
+
+  fn change_count(counts: &mut HashMap<&'static str, usize>, module: &'static str, add: bool) {
+    let count = counts.entry(module).or_insert(0);
+    match add {
+      true => *count += 1,
+      false => *count = count.saturating_sub(1),
+    }
+    if *count == 0 {
+      counts.remove(module);
+    }
+  }
+}
+
+// The budgets of the blocking tasks run concurrently by each module,
+// so that one module's blocking work can't take all the threads of the blocking thread pool
+pub struct BlockingBudgets {
+  state: Mutex<BudgetState>,
+  released: Notify,
+}
+
+impl BlockingBudgets {
+  fn new() -> Self {
+    Self {
+      state: Mutex::new(BudgetState {
+        pool_size: DEFAULT_MAX_BLOCKING_THREADS,
+        module_limit: DEFAULT_MAX_BLOCKING_THREADS / 2,
+        running: HashMap::new(),
+        queued: HashMap::new(),
+      }),
+      released: Notify::new(),
+    }
+  }
+
+  fn lock_state(&self) -> MutexGuard<'_, BudgetState> {
+    match self.state.lock() {
+      Ok(state) => state,
+      Err(poisoned) => poisoned.into_inner(),
+    }
+  }
+
+  // Set the size of the blocking thread pool, shared between the modules,
+  // and the maximum number of the blocking tasks run concurrently by a module
+  pub fn configure(&self, pool_size: usize, module_limit: usize) {
+    let mut state = self.lock_state();
+    state.pool_size = pool_size.max(1);
+    state.module_limit = module_limit.max(1);
+    drop(state);
+    self.released.notify_waiters();
+  }
+
+  // Wait until the module can run a blocking task within its budget.
+  // The blocking task counts against the budget until the returned permit is dropped.
+  pub async fn acquire(self: &Arc<Self>, module: &'static str) -> BlockingPermit {
+    let mut queued_guard = None;
+    loop {
+      // The notification is enabled before checking the budget, so that the release between them isn't missed
+      let released = self.released.notified();
+      tokio::pin!(released);
+      released.as_mut().enable();
+      if self.lock_state().try_start(module) {
+        drop(queued_guard);
+        return BlockingPermit {
+          budgets: self.clone(),
+          module,
+          _running_guard: GaugeGuard::new("ferron_blocking_tasks", module),
+        };
+      }
+      if queued_guard.is_none() {
+        BudgetState::change_count(&mut self.lock_state().queued, module, true);
+        queued_guard = Some(QueuedGuard {
+          budgets: self.clone(),
+          module,
+          _queued_guard: GaugeGuard::new("ferron_blocking_tasks_queued", module),
+        });
+      }
+      released.await;
+    }
+  }
+
+  // Run a blocking function in the blocking thread pool, within the module's blocking thread budget
+  pub async fn spawn_blocking<F, R>(
+    self: &Arc<Self>,
+    module: &'static str,
+    function: F,
+  ) -> Result<R, JoinError>
+  where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    let permit = self.acquire(module).await;
+    tokio::task::spawn_blocking(move || {
+      // The permit is dropped after the function returns, even if the caller no longer waits for it
+      let _permit = permit;
+      function()
+    })
+    .await
+  }
+}
+
+// A blocking task counted against the module's budget
+pub struct BlockingPermit {
+  budgets: Arc<BlockingBudgets>,
+  module: &'static str,
+  _running_guard: GaugeGuard,
+}
+
+impl Drop for BlockingPermit {
+  fn drop(&mut self) {
+    BudgetState::change_count(&mut self.budgets.lock_state().running, self.module, false);
+    self.budgets.released.notify_waiters();
+  }
+}
+
+// A module waiting for its blocking thread budget, removed from the queue when dropped (also when the wait is cancelled)
+struct QueuedGuard {
+  budgets: Arc<BlockingBudgets>,
+  module: &'static str,
+  _queued_guard: GaugeGuard,
+}
+
+impl Drop for QueuedGuard {
+  fn drop(&mut self) {
+    BudgetState::change_count(&mut self.budgets.lock_state().queued, self.module, false);
+    // The budgets of the other modules grow when this module stops waiting
+    self.budgets.released.notify_waiters();
+  }
+}
+
+// A guard decrementing the gauge for a module when dropped
+struct GaugeGuard {
+  name: &'static str,
+  module: &'static str,
+}
+
+impl GaugeGuard {
+  fn new(name: &'static str, module: &'static str) -> Self {
+    METRICS.add_to_gauge(name, &[("module", module)], 1);
+    Self { name, module }
+  }
+}
+
+impl Drop for GaugeGuard {
+  fn drop(&mut self) {
+    METRICS.add_to_gauge(self.name, &[("module", self.module)], -1);
+  }
+}
+
+// Run a blocking function in the blocking thread pool, within the module's blocking thread budget.
+// If the module has used up its budget, the function waits for the blocking tasks to finish.
+pub async fn spawn_blocking_budgeted<F, R>(
+  module: &'static str,
+  function: F,
+) -> Result<R, JoinError>
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static,
+{
+  BLOCKING_BUDGETS.spawn_blocking(module, function).await
+}
+
+// Run a future doing blocking work in the blocking thread pool (for example, a Tokio file system operation)
+// within the module's blocking thread budget
+pub async fn run_budgeted<F: Future>(module: &'static str, future: F) -> F::Output {
+  let _permit = BLOCKING_BUDGETS.acquire(module).await;
+  future.await
+}
+
+type AcquireFuture = Pin<Box<dyn Future<Output = BlockingPermit> + Send>>;
+
+// A reader reading a file (through the blocking thread pool) within the module's blocking thread budget.
+// The budget is used only while a read is in progress, so the responses sending large files don't hold it.
+pub struct BudgetedReader<R> {
+  module: &'static str,
+  inner: R,
+  permit: Option<BlockingPermit>,
+  // The future is wrapped in a mutex, because the boxed response bodies have to be shareable between the threads
+  acquire: Mutex<Option<AcquireFuture>>,
+}
+
+impl<R> BudgetedReader<R> {
+  pub fn new(module: &'static str, inner: R) -> Self {
+    Self {
+      module,
+      inner,
+      permit: None,
+      acquire: Mutex::new(None),
+    }
+  }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BudgetedReader<R> {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<std::io::Result<()>> {
+    let this = self.get_mut();
+    if this.permit.is_none() {
+      let acquire = this
+        .acquire
+        .get_mut()
+        .unwrap_or_else(|err| err.into_inner());
+      let module = this.module;
+      let acquire_future = acquire.get_or_insert_with(|| {
+        let budgets = BLOCKING_BUDGETS.clone();
+        Box::pin(async move { budgets.acquire(module).await })
+      });
+      match acquire_future.as_mut().poll(cx) {
+        Poll::Ready(permit) => {
+          *acquire = None;
+          this.permit = Some(permit);
+        }
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+    let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+    if result.is_ready() {
+      this.permit = None;
+    }
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::time::Duration;
+
+  #[tokio::test]
+  async fn test_module_blocking_budget() {
+    let budgets = Arc::new(BlockingBudgets::new());
+    budgets.configure(8, 2);
+
+    // The blocking tasks of a module don't run concurrently beyond the budget
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+    let tasks = (0..6).map(|_| {
+      let running = running.clone();
+      let max_running = max_running.clone();
+      budgets.spawn_blocking("budget_test_module", move || {
+        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+        max_running.fetch_max(now_running, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        running.fetch_sub(1, Ordering::SeqCst);
+      })
+    });
+    for result in futures_util::future::join_all(tasks).await {
+      result.unwrap();
+    }
+    assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    assert!(budgets.lock_state().running.is_empty());
+    assert!(budgets.lock_state().queued.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_adaptive_module_budgets() {
+    let budgets = Arc::new(BlockingBudgets::new());
+    budgets.configure(4, 4);
+
+    // A module can use the whole pool while the other modules don't run blocking work
+    let mut first_permits = Vec::new();
+    for _ in 0..4 {
+      first_permits.push(budgets.acquire("first").await);
+    }
+
+    // Another module still gets its fair share of the pool
+    let second_permit = tokio::time::timeout(Duration::from_secs(1), budgets.acquire("second"))
+      .await
+      .unwrap();
+
+    // The busy module has to wait, until it uses less than its fair share
+    let first_waiting = tokio::spawn({
+      let budgets = budgets.clone();
+      async move { budgets.acquire("first").await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!first_waiting.is_finished());
+    assert_eq!(budgets.lock_state().queued.get("first"), Some(&1));
+    first_permits.truncate(2);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!first_waiting.is_finished());
+    first_permits.truncate(1);
+    let _first_permit = tokio::time::timeout(Duration::from_secs(1), first_waiting)
+      .await
+      .unwrap()
+      .unwrap();
+
+    // The budget grows again after the other module finishes its blocking work
+    drop(second_permit);
+    for _ in 0..2 {
+      first_permits.push(
+        tokio::time::timeout(Duration::from_secs(1), budgets.acquire("first"))
+          .await
+          .unwrap(),
+      );
+    }
+    assert_eq!(budgets.lock_state().running.get("first"), Some(&4));
+  }
+
+  #[tokio::test]
+  async fn test_budgeted_reader() {
+    use tokio::io::AsyncReadExt;
+
+    let mut reader = BudgetedReader::new("budgeted_reader_test", &b"file contents"[..]);
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).await.unwrap();
+    assert_eq!(contents, "file contents");
+    // The budget isn't held between the reads
+    assert!(reader.permit.is_none());
+  }
+}
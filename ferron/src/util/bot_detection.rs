@@ -0,0 +1,274 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use fancy_regex::Regex;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::ferron_util::storage::{Storage, StorageBackend};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The name of the cookie, which is set after the client passes the challenge
+pub const CHALLENGE_COOKIE_NAME: &str = "ferron_bot_check";
+
+// The user agents of the well-known vulnerability scanners and reconnaissance tools
+const SCANNER_USER_AGENT_PATTERN: &str = r"(?i)\b(sqlmap|nikto|nmap|masscan|zgrab|nuclei|wpscan|dirbuster|gobuster|ffuf|feroxbuster|acunetix|netsparker|w3af|havij|nessus|openvas|whatweb|jorgee)\b";
+
+// The paths probed by the scanners looking for leaked secrets and vulnerable software
+const SCANNER_PROBE_PATHS: [&str; 11] = [
+  "/.env",
+  "/.git/",
+  "/.aws/credentials",
+  "/phpinfo.php",
+  "/phpmyadmin",
+  "/wp-config.php",
+  "/.DS_Store",
+  "/actuator/env",
+  "/vendor/phpunit/",
+  "/boaform/",
+  "/HNAP1",
+];
+
+// The reason, for which the client was recognized as a bot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotDetectionReason {
+  UserAgent,
+  ScannerProbe,
+  NotFound,
+}
+
+impl BotDetectionReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      BotDetectionReason::UserAgent => "user_agent",
+      BotDetectionReason::ScannerProbe => "scanner_probe",
+      BotDetectionReason::NotFound => "not_found",
+    }
+  }
+}
+
+// Detects the abusive clients by their user agents and the paths they request
+pub struct BotDetector {
+  user_agent_regexes: Vec<Regex>,
+}
+
+impl BotDetector {
+  // Create a new bot detector. The additional user agent patterns are matched along with the built-in ones.
+  pub fn new(user_agent_patterns: &[&str]) -> Result<Self, Box<fancy_regex::Error>> {
+    let mut user_agent_regexes = vec![Regex::new(SCANNER_USER_AGENT_PATTERN)?];
+    for user_agent_pattern in user_agent_patterns {
+      user_agent_regexes.push(Regex::new(user_agent_pattern)?);
+    }
+    Ok(Self { user_agent_regexes })
+  }
+
+  // Check the request. Returns the reason, for which the client is recognized as a bot.
+  pub fn detect(&self, user_agent: Option<&str>, path: &str) -> Option<BotDetectionReason> {
+    if let Some(user_agent) = user_agent {
+      if self
+        .user_agent_regexes
+        .iter()
+        .any(|regex| regex.is_match(user_agent).unwrap_or(false))
+      {
+        return Some(BotDetectionReason::UserAgent);
+      }
+    }
+    if SCANNER_PROBE_PATHS.iter().any(|probe_path| {
+      if probe_path.ends_with('/') {
+        path.starts_with(probe_path) || path == &probe_path[..probe_path.len() - 1]
+      } else {
+        path == *probe_path || path.starts_with(&format!("{}/", probe_path))
+      }
+    }) {
+      return Some(BotDetectionReason::ScannerProbe);
+    }
+    None
+  }
+}
+
+// The prefixes of the storage keys of the "404 Not Found" response counters and of the flagged clients
+const NOT_FOUND_KEY_PREFIX: &str = "ferron:bot-not-found:";
+const FLAGGED_KEY_PREFIX: &str = "ferron:bot-flagged:";
+
+// Tracks the "404 Not Found" responses sent to the clients, and flags the clients causing too many of them
+pub struct BotTracker {
+  storage: &'static Storage,
+}
+
+impl BotTracker {
+  pub fn new(storage: &'static Storage) -> Self {
+    Self { storage }
+  }
+
+  // Record a "404 Not Found" response sent to a client. The client is flagged for "penalty_duration" after
+  // "threshold" responses within "window". Returns true if the client has just been flagged.
+  pub async fn record_not_found(
+    &self,
+    ip: IpAddr,
+    threshold: u64,
+    window: Duration,
+    penalty_duration: Duration,
+  ) -> bool {
+    let ip = ip.to_canonical();
+    let not_found_key = format!("{}{}", NOT_FOUND_KEY_PREFIX, ip);
+    let count = match self.storage.increment(&not_found_key, window).await {
+      Ok(count) => count,
+      Err(_) => return false,
+    };
+    if count < threshold {
+      return false;
+    }
+    self
+      .storage
+      .delete(&not_found_key)
+      .await
+      .unwrap_or_default();
+
+    self
+      .storage
+      .set(
+        &format!("{}{}", FLAGGED_KEY_PREFIX, ip),
+        b"",
+        Some(penalty_duration),
+      )
+      .await
+      .is_ok()
+  }
+
+  // Check if a client is flagged as a bot
+  pub async fn is_flagged(&self, ip: IpAddr) -> bool {
+    self
+      .storage
+      .get(&format!("{}{}", FLAGGED_KEY_PREFIX, ip.to_canonical()))
+      .await
+      .is_ok_and(|flagged| flagged.is_some())
+  }
+}
+
+// Create the HMAC-SHA256 instance for a client IP address with an expiration timestamp
+fn challenge_mac(secret: &[u8], ip: IpAddr, expires: u64) -> HmacSha256 {
+  let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any size");
+  mac.update(format!("{}\n{}", ip.to_canonical(), expires).as_bytes());
+  mac
+}
+
+// Create the challenge token for a client IP address, which is valid until the expiration timestamp.
+// The token has the "<expiration timestamp>.<hexadecimal signature>" format.
+pub fn create_challenge_token(secret: &[u8], ip: IpAddr, expires: u64) -> String {
+  let signature: String = challenge_mac(secret, ip, expires)
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect();
+  format!("{}.{}", expires, signature)
+}
+
+// Verify the challenge token for a client IP address in constant time
+pub fn verify_challenge_token(secret: &[u8], ip: IpAddr, token: &str, now: u64) -> bool {
+  let (expires, signature) = match token.split_once('.') {
+    Some((expires, signature)) => (expires, signature),
+    None => return false,
+  };
+  let expires = match expires.parse::<u64>() {
+    Ok(expires) => expires,
+    Err(_) => return false,
+  };
+  if expires < now || !signature.len().is_multiple_of(2) {
+    return false;
+  }
+  let signature_bytes: Option<Vec<u8>> = (0..signature.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+    .collect();
+  match signature_bytes {
+    Some(signature_bytes) => challenge_mac(secret, ip, expires)
+      .verify_slice(&signature_bytes)
+      .is_ok(),
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_bot_detector() {
+    let detector = BotDetector::new(&["(?i)badbot"]).unwrap();
+    assert_eq!(
+      detector.detect(Some("sqlmap/1.7.2#stable (https://sqlmap.org)"), "/"),
+      Some(BotDetectionReason::UserAgent)
+    );
+    assert_eq!(
+      detector.detect(Some("Mozilla/5.0 (compatible; BadBot/2.1)"), "/"),
+      Some(BotDetectionReason::UserAgent)
+    );
+    assert_eq!(
+      detector.detect(Some("Mozilla/5.0"), "/.git/config"),
+      Some(BotDetectionReason::ScannerProbe)
+    );
+    assert_eq!(
+      detector.detect(None, "/phpmyadmin/index.php"),
+      Some(BotDetectionReason::ScannerProbe)
+    );
+    assert_eq!(detector.detect(Some("Mozilla/5.0"), "/.environment"), None);
+    assert_eq!(detector.detect(Some("curl/8.5.0"), "/index.html"), None);
+  }
+
+  #[tokio::test]
+  async fn test_bot_tracker() {
+    let tracker = BotTracker::new(Box::leak(Box::new(Storage::new())));
+    let ip: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+    let window = Duration::from_secs(60);
+    let penalty_duration = Duration::from_secs(600);
+    assert!(
+      !tracker
+        .record_not_found(ip, 3, window, penalty_duration)
+        .await
+    );
+    assert!(
+      !tracker
+        .record_not_found(ip, 3, window, penalty_duration)
+        .await
+    );
+    assert!(!tracker.is_flagged(ip).await);
+    assert!(
+      tracker
+        .record_not_found(ip, 3, window, penalty_duration)
+        .await
+    );
+    assert!(tracker.is_flagged("192.0.2.1".parse().unwrap()).await);
+    assert!(!tracker.is_flagged("192.0.2.2".parse().unwrap()).await);
+  }
+
+  #[test]
+  fn test_challenge_token() {
+    let secret = b"secret";
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    let token = create_challenge_token(secret, ip, 2000);
+    assert!(verify_challenge_token(secret, ip, &token, 1000));
+    assert!(verify_challenge_token(
+      secret,
+      "::ffff:192.0.2.1".parse().unwrap(),
+      &token,
+      1000
+    ));
+    assert!(!verify_challenge_token(secret, ip, &token, 3000));
+    assert!(!verify_challenge_token(
+      secret,
+      "192.0.2.2".parse().unwrap(),
+      &token,
+      1000
+    ));
+    assert!(!verify_challenge_token(b"other", ip, &token, 1000));
+    assert!(!verify_challenge_token(
+      secret,
+      ip,
+      &token.replacen("2000", "9000", 1),
+      1000
+    ));
+    assert!(!verify_challenge_token(secret, ip, "garbage", 1000));
+  }
+}
@@ -0,0 +1,295 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use futures_util::future;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// The maximum number of ranges in a single request, so that the clients can't request a huge number of tiny ranges
+const MAX_RANGES: usize = 100;
+
+// The byte ranges requested with the "Range" header
+#[derive(Debug, PartialEq)]
+pub enum ByteRanges {
+  // The header is invalid or uses an unknown range unit, so the whole file is sent
+  Ignored,
+  // None of the ranges overlap the file
+  Unsatisfiable,
+  // The inclusive byte ranges, sorted and with the overlapping and adjacent ranges merged
+  Satisfiable(Vec<(u64, u64)>),
+}
+
+// Parse the "Range" header, as specified in RFC 7233
+pub fn parse_byte_ranges(range_header: &str, file_length: u64) -> ByteRanges {
+  let range_set = match range_header.trim().strip_prefix("bytes=") {
+    Some(range_set) => range_set,
+    None => return ByteRanges::Ignored,
+  };
+
+  let mut ranges = Vec::new();
+  for range_spec in range_set.split(',').map(|range_spec| range_spec.trim()) {
+    if range_spec.is_empty() {
+      continue;
+    }
+    let (first, last) = match range_spec.split_once('-') {
+      Some(range) => range,
+      None => return ByteRanges::Ignored,
+    };
+    if first.is_empty() {
+      // A suffix range, which selects the last bytes of the file
+      let suffix_length = match last.parse::<u64>() {
+        Ok(suffix_length) => suffix_length,
+        Err(_) => return ByteRanges::Ignored,
+      };
+      if suffix_length > 0 && file_length > 0 {
+        ranges.push((file_length.saturating_sub(suffix_length), file_length - 1));
+      }
+    } else {
+      let first = match first.parse::<u64>() {
+        Ok(first) => first,
+        Err(_) => return ByteRanges::Ignored,
+      };
+      let last = match last {
+        "" => u64::MAX,
+        last => match last.parse::<u64>() {
+          Ok(last) if last >= first => last,
+          _ => return ByteRanges::Ignored,
+        },
+      };
+      if first < file_length {
+        ranges.push((first, last.min(file_length - 1)));
+      }
+    }
+  }
+
+  if ranges.is_empty() {
+    return ByteRanges::Unsatisfiable;
+  }
+  if ranges.len() > MAX_RANGES {
+    return ByteRanges::Ignored;
+  }
+
+  ranges.sort_unstable();
+  let mut merged_ranges: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+  for (first, last) in ranges {
+    match merged_ranges.last_mut() {
+      Some(previous) if first <= previous.1.saturating_add(1) => {
+        previous.1 = previous.1.max(last);
+      }
+      _ => merged_ranges.push((first, last)),
+    }
+  }
+  ByteRanges::Satisfiable(merged_ranges)
+}
+
+// Check if the "If-Range" validator (either an entity tag or a date) matches the file, so that the ranges can be sent
+pub fn if_range_matches(if_range: &str, etag: Option<&str>, modified: Option<SystemTime>) -> bool {
+  let if_range = if_range.trim();
+  if let Ok(date) = DateTime::parse_from_rfc2822(if_range) {
+    modified
+      .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+      .is_some_and(|modified| modified.as_secs() as i64 == date.timestamp())
+  } else {
+    // Weak entity tags can't be used for the range requests
+    !if_range.starts_with("W/") && etag.is_some_and(|etag| etag == if_range)
+  }
+}
+
+// Parse the "Content-Range" header of a single part response. Returns the first byte position, the last byte position and the
+// complete length (if known).
+pub fn parse_content_range(content_range_header: &str) -> Option<(u64, u64, Option<u64>)> {
+  let (range, complete_length) = content_range_header
+    .trim()
+    .strip_prefix("bytes ")?
+    .split_once('/')?;
+  let (first, last) = range.split_once('-')?;
+  let first = first.trim().parse::<u64>().ok()?;
+  let last = last.trim().parse::<u64>().ok()?;
+  let complete_length = match complete_length.trim() {
+    "*" => None,
+    complete_length => Some(complete_length.parse::<u64>().ok()?),
+  };
+  if last < first || complete_length.is_some_and(|complete_length| last >= complete_length) {
+    return None;
+  }
+  Some((first, last, complete_length))
+}
+
+// Create a "multipart/byteranges" response body. Returns the body length and the body.
+pub fn multipart_byteranges_body(
+  path: PathBuf,
+  ranges: Vec<(u64, u64)>,
+  content_type: Option<&str>,
+  file_length: u64,
+  boundary: &str,
+  buffer_size: usize,
+) -> (u64, BoxBody<Bytes, std::io::Error>) {
+  let part_headers = ranges
+    .iter()
+    .map(|&(first, last)| {
+      Bytes::from(format!(
+        "--{}\r\n{}Content-Range: bytes {}-{}/{}\r\n\r\n",
+        boundary,
+        match content_type {
+          Some(content_type) => format!("Content-Type: {}\r\n", content_type),
+          None => String::new(),
+        },
+        first,
+        last,
+        file_length
+      ))
+    })
+    .collect::<Vec<_>>();
+  let closing_delimiter = Bytes::from(format!("--{}--\r\n", boundary));
+  let content_length = ranges
+    .iter()
+    .zip(part_headers.iter())
+    .map(|(&(first, last), part_header)| part_header.len() as u64 + (last - first + 1) + 2)
+    .sum::<u64>()
+    + closing_delimiter.len() as u64;
+
+  let parts = ranges
+    .into_iter()
+    .zip(part_headers)
+    .map(move |((first, last), part_header)| {
+      let path = path.clone();
+      // The file is opened separately for each part, when the part is about to be sent
+      let part_data = stream::once(async move {
+        let mut file = fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(first)).await?;
+        Ok::<_, std::io::Error>(ReaderStream::with_capacity(
+          file.take(last - first + 1),
+          buffer_size,
+        ))
+      })
+      .try_flatten();
+      stream::once(future::ready(Ok(part_header)))
+        .chain(part_data)
+        .chain(stream::once(future::ready(Ok(Bytes::from_static(b"\r\n")))))
+    });
+
+  let body_stream = stream::iter(parts)
+    .flatten()
+    .chain(stream::once(future::ready(Ok(closing_delimiter))));
+  (
+    content_length,
+    BodyExt::boxed(StreamBody::new(body_stream.map_ok(Frame::data))),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_byte_ranges() {
+    assert_eq!(
+      parse_byte_ranges("bytes=0-99", 1000),
+      ByteRanges::Satisfiable(vec![(0, 99)])
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=900-", 1000),
+      ByteRanges::Satisfiable(vec![(900, 999)])
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=-100", 1000),
+      ByteRanges::Satisfiable(vec![(900, 999)])
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=-2000", 1000),
+      ByteRanges::Satisfiable(vec![(0, 999)])
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=500-5000", 1000),
+      ByteRanges::Satisfiable(vec![(500, 999)])
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=500-599, 0-9, 10-19, 550-700", 1000),
+      ByteRanges::Satisfiable(vec![(0, 19), (500, 700)])
+    );
+  }
+
+  #[test]
+  fn test_invalid_and_unsatisfiable_ranges() {
+    assert_eq!(parse_byte_ranges("items=0-1", 1000), ByteRanges::Ignored);
+    assert_eq!(parse_byte_ranges("bytes=abc", 1000), ByteRanges::Ignored);
+    assert_eq!(parse_byte_ranges("bytes=5-1", 1000), ByteRanges::Ignored);
+    assert_eq!(
+      parse_byte_ranges("bytes=1000-", 1000),
+      ByteRanges::Unsatisfiable
+    );
+    assert_eq!(
+      parse_byte_ranges("bytes=-0", 1000),
+      ByteRanges::Unsatisfiable
+    );
+    assert_eq!(parse_byte_ranges("bytes=0-", 0), ByteRanges::Unsatisfiable);
+    let many_ranges = (0..200)
+      .map(|index| format!("{}-{}", index * 2, index * 2))
+      .collect::<Vec<_>>()
+      .join(",");
+    assert_eq!(
+      parse_byte_ranges(&format!("bytes={}", many_ranges), 1000),
+      ByteRanges::Ignored
+    );
+  }
+
+  #[test]
+  fn test_parse_content_range() {
+    assert_eq!(
+      parse_content_range("bytes 100-999/1000"),
+      Some((100, 999, Some(1000)))
+    );
+    assert_eq!(parse_content_range("bytes 0-9/*"), Some((0, 9, None)));
+    assert_eq!(parse_content_range("bytes */1000"), None);
+    assert_eq!(parse_content_range("bytes 10-5/1000"), None);
+    assert_eq!(parse_content_range("bytes 0-1000/1000"), None);
+    assert_eq!(parse_content_range("items 0-9/10"), None);
+  }
+
+  #[test]
+  fn test_if_range_matches() {
+    let modified = UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+    assert!(if_range_matches("abc123", Some("abc123"), None));
+    assert!(!if_range_matches("abc124", Some("abc123"), None));
+    assert!(!if_range_matches("W/abc123", Some("W/abc123"), None));
+    assert!(if_range_matches(
+      "Sun, 06 Nov 1994 08:49:37 GMT",
+      None,
+      Some(modified)
+    ));
+    assert!(!if_range_matches(
+      "Sun, 06 Nov 1994 08:49:38 GMT",
+      None,
+      Some(modified)
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_multipart_byteranges_body() {
+    let path = std::env::temp_dir().join(format!("ferron-byte-ranges-test-{}", std::process::id()));
+    fs::write(&path, "0123456789").await.unwrap();
+
+    let (content_length, body) = multipart_byteranges_body(
+      path.clone(),
+      vec![(0, 1), (5, 9)],
+      Some("text/plain"),
+      10,
+      "BOUNDARY",
+      4096,
+    );
+    let body = body.collect().await.unwrap().to_bytes();
+    fs::remove_file(&path).await.unwrap();
+
+    assert_eq!(
+      body,
+      "--BOUNDARY\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n--BOUNDARY\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-9/10\r\n\r\n56789\r\n--BOUNDARY--\r\n"
+    );
+    assert_eq!(content_length, body.len() as u64);
+  }
+}
@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Local, NaiveTime, Timelike};
+use ferron_common::{HyperRequest, HyperResponse};
+use futures_util::StreamExt;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{header, Method, Request, Uri};
+use serde_json::{json, Value};
+use yaml_rust2::Yaml;
+
+const DEFAULT_PREWARM_CONCURRENCY: usize = 4;
+const MAX_REPORTED_FAILURES: usize = 10;
+const PREWARM_USER_AGENT: &str = "Ferron cache prewarmer";
+
+// The statuses of the cache prewarming jobs, reported in the admin API
+static PREWARM_JOB_STATUSES: RwLock<BTreeMap<String, PrewarmJobStatus>> =
+  RwLock::new(BTreeMap::new());
+
+// A cache prewarming job, which requests the URLs at the scheduled times to populate the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrewarmJob {
+  pub name: String,
+  urls: Vec<Uri>,
+  sitemap: Option<Uri>,
+  schedule: Vec<NaiveTime>,
+  concurrency: usize,
+}
+
+// Parse the absolute URL of a page to prewarm
+fn parse_prewarm_url(url: &str) -> Option<Uri> {
+  let url = Uri::from_str(url).ok()?;
+  match (url.scheme_str(), url.authority()) {
+    (Some("http" | "https"), Some(_)) => Some(url),
+    _ => None,
+  }
+}
+
+impl PrewarmJob {
+  // Parse the prewarming job from the configuration
+  pub fn from_yaml(yaml: &Yaml) -> Result<Self, anyhow::Error> {
+    let name = match yaml["name"].as_str() {
+      Some(name) => name.to_string(),
+      None => Err(anyhow::anyhow!(
+        "The cache prewarming job name isn't specified"
+      ))?,
+    };
+
+    let mut urls = Vec::new();
+    if !yaml["urls"].is_badvalue() {
+      match yaml["urls"].as_vec() {
+        Some(urls_yaml) => {
+          for url in urls_yaml {
+            match url.as_str().and_then(parse_prewarm_url) {
+              Some(url) => urls.push(url),
+              None => Err(anyhow::anyhow!(
+                "Invalid URL in the \"{}\" cache prewarming job",
+                name
+              ))?,
+            }
+          }
+        }
+        None => Err(anyhow::anyhow!(
+          "Invalid URLs in the \"{}\" cache prewarming job",
+          name
+        ))?,
+      }
+    }
+
+    let sitemap = match &yaml["sitemap"] {
+      Yaml::BadValue => None,
+      sitemap => match sitemap.as_str().and_then(parse_prewarm_url) {
+        Some(sitemap) => Some(sitemap),
+        None => Err(anyhow::anyhow!(
+          "Invalid sitemap URL in the \"{}\" cache prewarming job",
+          name
+        ))?,
+      },
+    };
+
+    if urls.is_empty() && sitemap.is_none() {
+      Err(anyhow::anyhow!(
+        "The \"{}\" cache prewarming job has neither URLs nor a sitemap URL",
+        name
+      ))?
+    }
+
+    // The schedule is either a single time or a list of times, in the "HH:MM" format in the local time zone
+    let schedule_yaml = match &yaml["schedule"] {
+      Yaml::Array(schedule_yaml) => schedule_yaml.clone(),
+      Yaml::BadValue => Vec::new(),
+      schedule_yaml => vec![schedule_yaml.clone()],
+    };
+    let mut schedule = Vec::new();
+    for time in schedule_yaml {
+      match time
+        .as_str()
+        .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+      {
+        Some(time) => schedule.push(time),
+        None => Err(anyhow::anyhow!(
+          "Invalid schedule of the \"{}\" cache prewarming job",
+          name
+        ))?,
+      }
+    }
+    if schedule.is_empty() {
+      Err(anyhow::anyhow!(
+        "The \"{}\" cache prewarming job isn't scheduled",
+        name
+      ))?
+    }
+
+    let concurrency = match &yaml["concurrency"] {
+      Yaml::BadValue => DEFAULT_PREWARM_CONCURRENCY,
+      concurrency => match concurrency.as_i64() {
+        Some(concurrency) if concurrency > 0 => concurrency as usize,
+        _ => Err(anyhow::anyhow!(
+          "Invalid concurrency limit of the \"{}\" cache prewarming job",
+          name
+        ))?,
+      },
+    };
+
+    Ok(Self {
+      name,
+      urls,
+      sitemap,
+      schedule,
+      concurrency,
+    })
+  }
+
+  // Check if the job is scheduled to run in the minute of the specified time
+  pub fn is_scheduled_at(&self, time: NaiveTime) -> bool {
+    self
+      .schedule
+      .iter()
+      .any(|scheduled| scheduled.hour() == time.hour() && scheduled.minute() == time.minute())
+  }
+}
+
+// Parse the cache prewarming jobs from the configuration. The invalid jobs are rejected by the configuration validation.
+pub fn prewarm_jobs(jobs_yaml: &Yaml) -> Vec<PrewarmJob> {
+  jobs_yaml
+    .as_vec()
+    .map(|jobs_yaml| {
+      jobs_yaml
+        .iter()
+        .filter_map(|job_yaml| PrewarmJob::from_yaml(job_yaml).ok())
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+// Parse the URLs from the XML sitemap. The sitemap index lists the URLs of the nested sitemaps instead of the pages.
+// Returns the URLs and whether the sitemap is a sitemap index.
+pub fn parse_sitemap(xml: &str) -> (Vec<String>, bool) {
+  let mut urls = Vec::new();
+  let mut rest = xml;
+  while let Some(loc_start) = rest.find("<loc>") {
+    rest = &rest[(loc_start + 5)..];
+    let loc_end = match rest.find("</loc>") {
+      Some(loc_end) => loc_end,
+      None => break,
+    };
+    urls.push(
+      rest[..loc_end]
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&"),
+    );
+    rest = &rest[(loc_end + 6)..];
+  }
+  (urls, xml.contains("<sitemapindex"))
+}
+
+// The status of a cache prewarming job
+#[derive(Default)]
+struct PrewarmJobStatus {
+  running: bool,
+  last_started: Option<DateTime<Local>>,
+  last_finished: Option<DateTime<Local>>,
+  requested: usize,
+  succeeded: usize,
+  failed: usize,
+  failures: Vec<String>,
+}
+
+// Mark the job as running. Returns false, if the job is already running.
+fn try_start_job(name: &str) -> bool {
+  let mut statuses = match PREWARM_JOB_STATUSES.write() {
+    Ok(statuses) => statuses,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let status = statuses.entry(name.to_string()).or_default();
+  if status.running {
+    return false;
+  }
+  status.running = true;
+  status.last_started = Some(Local::now());
+  true
+}
+
+// Record the results of the finished job
+fn finish_job(name: &str, requested: usize, succeeded: usize, failures: Vec<String>) {
+  let mut statuses = match PREWARM_JOB_STATUSES.write() {
+    Ok(statuses) => statuses,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let status = statuses.entry(name.to_string()).or_default();
+  status.running = false;
+  status.last_finished = Some(Local::now());
+  status.requested = requested;
+  status.succeeded = succeeded;
+  status.failed = failures.len();
+  status.failures = failures.into_iter().take(MAX_REPORTED_FAILURES).collect();
+}
+
+// Get the statuses of the cache prewarming jobs, reported in the admin API
+pub fn prewarm_job_statuses_json() -> Value {
+  let statuses = match PREWARM_JOB_STATUSES.read() {
+    Ok(statuses) => statuses,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  Value::Array(
+    statuses
+      .iter()
+      .map(|(name, status)| {
+        json!({
+          "name": name,
+          "running": status.running,
+          "lastStarted": status.last_started.map(|time| time.to_rfc3339()),
+          "lastFinished": status.last_finished.map(|time| time.to_rfc3339()),
+          "requested": status.requested,
+          "succeeded": status.succeeded,
+          "failed": status.failed,
+          "failures": status.failures,
+        })
+      })
+      .collect(),
+  )
+}
+
+// Request the URL through the request handler, which receives the request and whether it's an HTTPS request.
+// The whole response body is received, so that the response is stored in the cache.
+async fn fetch_url<F, Fut>(url: &Uri, handle_request: &F) -> Result<Bytes, anyhow::Error>
+where
+  F: Fn(HyperRequest, bool) -> Fut,
+  Fut: Future<Output = Result<HyperResponse, anyhow::Error>>,
+{
+  let request = Request::builder()
+    .method(Method::GET)
+    .uri(
+      url
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str()),
+    )
+    .header(
+      header::HOST,
+      url.authority().map_or("", |authority| authority.as_str()),
+    )
+    .header(header::USER_AGENT, PREWARM_USER_AGENT)
+    .body(Empty::new().map_err(|e| match e {}).boxed())?;
+  let response = handle_request(request, url.scheme_str() == Some("https")).await?;
+  if !response.status().is_success() {
+    Err(anyhow::anyhow!("status code {}", response.status()))?
+  }
+  Ok(response.into_body().collect().await?.to_bytes())
+}
+
+// Get the page URLs from the sitemap, including the ones from the sitemaps nested in the sitemap index
+async fn fetch_sitemap_urls<F, Fut>(
+  sitemap: &Uri,
+  handle_request: &F,
+  failures: &mut Vec<String>,
+) -> Vec<Uri>
+where
+  F: Fn(HyperRequest, bool) -> Fut,
+  Fut: Future<Output = Result<HyperResponse, anyhow::Error>>,
+{
+  let mut page_urls = Vec::new();
+  let mut sitemaps = vec![sitemap.clone()];
+  let mut is_top_level = true;
+  while let Some(sitemap) = sitemaps.pop() {
+    let (urls, is_index) = match fetch_url(&sitemap, handle_request).await {
+      Ok(xml) => parse_sitemap(&String::from_utf8_lossy(&xml)),
+      Err(err) => {
+        failures.push(format!("{}: {}", sitemap, err));
+        continue;
+      }
+    };
+    let urls = urls.iter().filter_map(|url| parse_prewarm_url(url));
+    // Only one level of the sitemap index is followed
+    match is_index && is_top_level {
+      true => sitemaps.extend(urls),
+      false => page_urls.extend(urls),
+    }
+    is_top_level = false;
+  }
+  page_urls
+}
+
+// Run the prewarming job, sending the requests through the request handler, which receives the request
+// and whether it's an HTTPS request. The job isn't run, if the previous run hasn't finished yet.
+pub async fn run_prewarm_job<F, Fut>(job: PrewarmJob, handle_request: F)
+where
+  F: Fn(HyperRequest, bool) -> Fut,
+  Fut: Future<Output = Result<HyperResponse, anyhow::Error>>,
+{
+  if !try_start_job(&job.name) {
+    return;
+  }
+
+  let mut failures = Vec::new();
+  let mut urls = job.urls.clone();
+  if let Some(sitemap) = &job.sitemap {
+    urls.append(&mut fetch_sitemap_urls(sitemap, &handle_request, &mut failures).await);
+  }
+  let mut seen_urls = HashSet::new();
+  urls.retain(|url| seen_urls.insert(url.to_string()));
+
+  let handle_request = &handle_request;
+  let results = futures_util::stream::iter(urls.clone())
+    .map(|url| async move {
+      fetch_url(&url, handle_request)
+        .await
+        .map_err(|err| format!("{}: {}", url, err))
+    })
+    .buffer_unordered(job.concurrency)
+    .collect::<Vec<_>>()
+    .await;
+  let succeeded = results.iter().filter(|result| result.is_ok()).count();
+  failures.extend(results.into_iter().filter_map(|result| result.err()));
+
+  finish_job(&job.name, urls.len(), succeeded, failures);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn job_yaml(source: &str) -> Yaml {
+    YamlLoader::load_from_str(source).unwrap().remove(0)
+  }
+
+  #[test]
+  fn test_parse_prewarm_job() {
+    let job = PrewarmJob::from_yaml(&job_yaml(
+      "name: main\nurls: [\"https://example.com/\"]\nsitemap: http://example.com/sitemap.xml\nschedule: [\"03:00\", \"15:30\"]",
+    ))
+    .unwrap();
+    assert_eq!(job.name, "main");
+    assert_eq!(job.urls, vec![Uri::from_static("https://example.com/")]);
+    assert_eq!(job.concurrency, DEFAULT_PREWARM_CONCURRENCY);
+    assert!(job.is_scheduled_at(NaiveTime::from_hms_opt(15, 30, 42).unwrap()));
+    assert!(!job.is_scheduled_at(NaiveTime::from_hms_opt(15, 31, 0).unwrap()));
+
+    let job = PrewarmJob::from_yaml(&job_yaml(
+      "name: main\nurls: [\"http://example.com/\"]\nschedule: \"04:15\"\nconcurrency: 8",
+    ))
+    .unwrap();
+    assert_eq!(job.concurrency, 8);
+    assert!(job.is_scheduled_at(NaiveTime::from_hms_opt(4, 15, 0).unwrap()));
+  }
+
+  #[test]
+  fn test_invalid_prewarm_jobs() {
+    assert!(
+      PrewarmJob::from_yaml(&job_yaml("urls: [\"http://a/\"]\nschedule: \"03:00\"")).is_err()
+    );
+    assert!(
+      PrewarmJob::from_yaml(&job_yaml("name: a\nurls: [\"/\"]\nschedule: \"03:00\"")).is_err()
+    );
+    assert!(PrewarmJob::from_yaml(&job_yaml("name: a\nschedule: \"03:00\"")).is_err());
+    assert!(PrewarmJob::from_yaml(&job_yaml(
+      "name: a\nurls: [\"http://a/\"]\nschedule: \"3 AM\""
+    ))
+    .is_err());
+    assert!(PrewarmJob::from_yaml(&job_yaml("name: a\nurls: [\"http://a/\"]")).is_err());
+    assert!(PrewarmJob::from_yaml(&job_yaml(
+      "name: a\nurls: [\"http://a/\"]\nschedule: \"03:00\"\nconcurrency: 0"
+    ))
+    .is_err());
+  }
+
+  #[test]
+  fn test_parse_sitemap() {
+    let (urls, is_index) = parse_sitemap(
+      "<?xml version=\"1.0\"?><urlset><url><loc> https://example.com/?a=1&amp;b=2 </loc></url>\
+       <url><loc>https://example.com/about</loc><lastmod>2024-01-01</lastmod></url></urlset>",
+    );
+    assert_eq!(
+      urls,
+      vec!["https://example.com/?a=1&b=2", "https://example.com/about"]
+    );
+    assert!(!is_index);
+
+    let (urls, is_index) = parse_sitemap(
+      "<sitemapindex><sitemap><loc>https://example.com/sitemap-1.xml</loc></sitemap></sitemapindex>",
+    );
+    assert_eq!(urls, vec!["https://example.com/sitemap-1.xml"]);
+    assert!(is_index);
+  }
+}
@@ -0,0 +1,262 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use rustls::sign::CertifiedKey;
+use rustls::{Error as RustlsError, InconsistentKeys};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+
+// The certificates expiring in fewer days than this are reported
+const EXPIRY_WARNING_DAYS: i64 = 30;
+
+// The problems found in a TLS certificate chain. The errors make the certificate unusable,
+// while the warnings describe a misconfiguration, which can make some clients fail to connect.
+#[derive(Debug, Default)]
+pub struct CertificateProblems {
+  pub errors: Vec<String>,
+  pub warnings: Vec<String>,
+}
+
+// Check the TLS certificate chain and the private key for the common misconfigurations.
+// The host names are the names the certificate is expected to be valid for.
+pub fn check_certificate(
+  certified_key: &CertifiedKey,
+  host_names: &[&str],
+  now: SystemTime,
+) -> CertificateProblems {
+  let mut problems = CertificateProblems::default();
+
+  if certified_key.cert.is_empty() {
+    problems
+      .errors
+      .push(String::from("The file doesn't contain any certificates"));
+    return problems;
+  }
+
+  match certified_key.keys_match() {
+    Ok(()) | Err(RustlsError::InconsistentKeys(InconsistentKeys::Unknown)) => (),
+    Err(RustlsError::InconsistentKeys(InconsistentKeys::KeyMismatch)) => problems.errors.push(
+      String::from("The private key doesn't match the certificate"),
+    ),
+    Err(err) => problems.errors.push(format!(
+      "Cannot check if the private key matches the certificate: {}",
+      err
+    )),
+  }
+
+  let mut certificates = Vec::new();
+  for (index, certificate_der) in certified_key.cert.iter().enumerate() {
+    match X509Certificate::from_der(certificate_der) {
+      Ok((_, certificate)) => certificates.push(certificate),
+      Err(err) => {
+        problems.errors.push(format!(
+          "Cannot parse the certificate #{} in the chain: {}",
+          index + 1,
+          err
+        ));
+        return problems;
+      }
+    }
+  }
+
+  // Each certificate in the chain should be issued by the next one, starting with the end-entity certificate
+  let end_entity = &certificates[0];
+  if end_entity.is_ca() && certificates.len() > 1 {
+    problems.warnings.push(String::from(
+      "The first certificate in the chain is a CA certificate. The end-entity certificate should be the first one",
+    ));
+  } else {
+    for (index, pair) in certificates.windows(2).enumerate() {
+      if pair[0].issuer().as_raw() != pair[1].subject().as_raw() {
+        problems.warnings.push(format!(
+          "The certificate #{} in the chain isn't issued by the next certificate (\"{}\"). The chain may be out of order or contain unrelated certificates",
+          index + 1,
+          pair[1].subject()
+        ));
+        break;
+      }
+    }
+  }
+
+  let now = now
+    .duration_since(UNIX_EPOCH)
+    .map(|now| now.as_secs() as i64)
+    .unwrap_or(0);
+  for (index, certificate) in certificates.iter().enumerate() {
+    let name = match index {
+      0 => String::from("The certificate"),
+      _ => format!("The certificate #{} in the chain", index + 1),
+    };
+    let validity = certificate.validity();
+    let not_after = validity.not_after.timestamp();
+    if not_after < now {
+      problems.warnings.push(format!(
+        "{} has expired on {}",
+        name,
+        format_timestamp(not_after)
+      ));
+    } else if validity.not_before.timestamp() > now {
+      problems.warnings.push(format!(
+        "{} isn't valid until {}",
+        name,
+        format_timestamp(validity.not_before.timestamp())
+      ));
+    } else if not_after - now < EXPIRY_WARNING_DAYS * 86400 {
+      problems.warnings.push(format!(
+        "{} expires on {}, in {} days",
+        name,
+        format_timestamp(not_after),
+        (not_after - now) / 86400
+      ));
+    }
+  }
+
+  // Modern clients ignore the common name, so the host names must be in the Subject Alternative Name extension
+  let dns_names = match end_entity.subject_alternative_name() {
+    Ok(Some(subject_alternative_name)) => subject_alternative_name
+      .value
+      .general_names
+      .iter()
+      .filter_map(|general_name| match general_name {
+        GeneralName::DNSName(dns_name) => Some(*dns_name),
+        _ => None,
+      })
+      .collect::<Vec<_>>(),
+    _ => Vec::new(),
+  };
+  if dns_names.is_empty() {
+    if !host_names.is_empty() {
+      problems.warnings.push(String::from(
+        "The certificate doesn't list any DNS names in the Subject Alternative Name extension, so browsers will reject it",
+      ));
+    }
+  } else {
+    for host_name in host_names {
+      if !dns_names
+        .iter()
+        .any(|dns_name| dns_name_covers(dns_name, host_name))
+      {
+        problems.warnings.push(format!(
+          "The certificate isn't valid for the \"{}\" host name (it's valid for {})",
+          host_name,
+          dns_names.join(", ")
+        ));
+      }
+    }
+  }
+
+  problems
+}
+
+// Check if the DNS name from the certificate covers the configured host name.
+// A wildcard matches exactly one label, and a wildcard host name is covered only by the same wildcard.
+fn dns_name_covers(dns_name: &str, host_name: &str) -> bool {
+  let dns_name = dns_name.trim_end_matches('.');
+  let host_name = host_name.trim_end_matches('.');
+  if dns_name.eq_ignore_ascii_case(host_name) {
+    return true;
+  }
+  match (dns_name.strip_prefix("*."), host_name.split_once('.')) {
+    (Some(dns_name_parent), Some((label, host_name_parent))) => {
+      !label.is_empty() && label != "*" && dns_name_parent.eq_ignore_ascii_case(host_name_parent)
+    }
+    _ => false,
+  }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+  match DateTime::from_timestamp(timestamp, 0) {
+    Some(date_time) => date_time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+    None => timestamp.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{date_time_ymd, CertificateParams, IsCa, KeyPair};
+  use rustls::crypto::ring::sign::any_supported_type;
+  use rustls_pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+  use std::time::Duration;
+
+  fn now() -> SystemTime {
+    // 2025-06-01
+    UNIX_EPOCH + Duration::from_secs(1748736000)
+  }
+
+  fn certificate_params(names: &[&str], valid_until_year: i32) -> CertificateParams {
+    let mut params = CertificateParams::new(
+      names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>(),
+    )
+    .unwrap();
+    params.not_before = date_time_ymd(2025, 1, 1);
+    params.not_after = date_time_ymd(valid_until_year, 1, 1);
+    params
+  }
+
+  fn certified_key(
+    chain: Vec<rustls_pki_types::CertificateDer<'static>>,
+    key: &KeyPair,
+  ) -> CertifiedKey {
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    CertifiedKey::new(chain, any_supported_type(&key).unwrap())
+  }
+
+  #[test]
+  fn test_valid_certificate_chain() {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = certificate_params(&[], 2030);
+    ca_params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca_certificate = ca_params.self_signed(&ca_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let certificate = certificate_params(&["example.com", "*.example.com"], 2026)
+      .signed_by(&key, &ca_certificate, &ca_key)
+      .unwrap();
+
+    let chain = vec![certificate.der().clone(), ca_certificate.der().clone()];
+    let problems = check_certificate(
+      &certified_key(chain, &key),
+      &["example.com", "www.example.com", "*.example.com"],
+      now(),
+    );
+    assert!(problems.errors.is_empty());
+    assert!(problems.warnings.is_empty(), "{:?}", problems.warnings);
+
+    // The chain is out of order
+    let chain = vec![ca_certificate.der().clone(), certificate.der().clone()];
+    let problems = check_certificate(&certified_key(chain, &key), &[], now());
+    assert_eq!(problems.warnings.len(), 1);
+  }
+
+  #[test]
+  fn test_certificate_problems() {
+    let key = KeyPair::generate().unwrap();
+    let certificate = certificate_params(&["example.com"], 2025)
+      .self_signed(&key)
+      .unwrap();
+    let other_key = KeyPair::generate().unwrap();
+
+    let problems = check_certificate(
+      &certified_key(vec![certificate.der().clone()], &other_key),
+      &["example.com", "example.org", "a.b.example.com"],
+      now(),
+    );
+    assert_eq!(problems.errors.len(), 1);
+    // The certificate has expired, and it isn't valid for two of the host names
+    assert_eq!(problems.warnings.len(), 3, "{:?}", problems.warnings);
+  }
+
+  #[test]
+  fn test_dns_name_covers() {
+    assert!(dns_name_covers("example.com", "EXAMPLE.com"));
+    assert!(dns_name_covers("*.example.com", "www.example.com"));
+    assert!(dns_name_covers("*.example.com", "*.example.com"));
+    assert!(!dns_name_covers("*.example.com", "example.com"));
+    assert!(!dns_name_covers("*.example.com", "a.b.example.com"));
+    assert!(!dns_name_covers("www.example.com", "*.example.com"));
+  }
+}
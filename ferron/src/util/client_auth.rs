@@ -0,0 +1,564 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use async_channel::Sender;
+use base64::{engine::general_purpose, Engine};
+use ferron_common::{ClientIdentity, LogMessage};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{
+  verify_tls12_signature, verify_tls13_signature, verify_tls13_signature_with_raw_key,
+  WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::{CertificateDer, SubjectPublicKeyInfoDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{
+  CertificateError, DigitallySignedStruct, DistinguishedName, Error as RustlsError,
+  PeerIncompatible, RootCertStore, SignatureScheme,
+};
+use rustls_native_certs::load_native_certs;
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+use x509_parser::x509::SubjectPublicKeyInfo;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::load_tls::{load_certs, load_crls};
+
+// The configuration options of the TLS client authentication
+const CLIENT_AUTH_OPTIONS: [&str; 6] = [
+  "useClientCertificate",
+  "clientCertificateCA",
+  "clientCertificateCRL",
+  "clientCertificateMode",
+  "clientPublicKeyPins",
+  "clientRawPublicKeys",
+];
+
+// The TLS client authentication configuration of a listener
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientAuthConfig {
+  // Whether the client certificates are verified against the system certificate store
+  pub use_system_store: bool,
+  // The paths to the private CA certificates, which replace the system certificate store
+  pub ca_paths: Vec<String>,
+  // The paths to the certificate revocation lists, against which the client certificates are checked
+  pub crl_paths: Vec<String>,
+  // Whether the clients are allowed to connect without a client certificate
+  pub optional: bool,
+  // The SHA-256 hashes of the pinned client public keys (Subject Public Key Info)
+  pub public_key_pins: Vec<[u8; 32]>,
+  // Whether the clients authenticate with raw public keys (RFC 7250) instead of certificates
+  pub raw_public_keys: bool,
+}
+
+impl ClientAuthConfig {
+  // Parse the client authentication options from the global or the listener configuration
+  pub fn from_yaml(config: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    Self::parse(
+      &config["useClientCertificate"],
+      &config["clientCertificateCA"],
+      &config["clientCertificateCRL"],
+      &config["clientCertificateMode"],
+      &config["clientPublicKeyPins"],
+      &config["clientRawPublicKeys"],
+    )
+  }
+
+  // Parse the client authentication options
+  pub fn parse(
+    use_client_certificate: &Yaml,
+    ca_paths: &Yaml,
+    crl_paths: &Yaml,
+    mode: &Yaml,
+    public_key_pins: &Yaml,
+    raw_public_keys: &Yaml,
+  ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let use_system_store = match use_client_certificate {
+      Yaml::BadValue => false,
+      _ => use_client_certificate.as_bool().ok_or(anyhow::anyhow!(
+        "Invalid client certificate verification enabling option value"
+      ))?,
+    };
+    let ca_paths = match ca_paths {
+      Yaml::BadValue => Vec::new(),
+      Yaml::String(ca_path) => vec![ca_path.clone()],
+      Yaml::Array(ca_paths) if !ca_paths.is_empty() => ca_paths
+        .iter()
+        .map(|ca_path| ca_path.as_str().map(String::from))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(anyhow::anyhow!("Invalid client certificate authority path"))?,
+      _ => Err(anyhow::anyhow!("Invalid client certificate authority path"))?,
+    };
+    let crl_paths = match crl_paths {
+      Yaml::BadValue => Vec::new(),
+      Yaml::String(crl_path) => vec![crl_path.clone()],
+      Yaml::Array(crl_paths) if !crl_paths.is_empty() => crl_paths
+        .iter()
+        .map(|crl_path| crl_path.as_str().map(String::from))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(anyhow::anyhow!(
+          "Invalid client certificate revocation list path"
+        ))?,
+      _ => Err(anyhow::anyhow!(
+        "Invalid client certificate revocation list path"
+      ))?,
+    };
+    let optional = match mode {
+      Yaml::BadValue => false,
+      _ => match mode.as_str() {
+        Some("required") => false,
+        Some("optional") => true,
+        _ => Err(anyhow::anyhow!(
+          "Invalid client certificate mode. The mode must be \"required\" or \"optional\""
+        ))?,
+      },
+    };
+    let public_key_pins = match public_key_pins {
+      Yaml::BadValue => Vec::new(),
+      Yaml::Array(public_key_pins) if !public_key_pins.is_empty() => {
+        let mut parsed_public_key_pins = Vec::new();
+        for public_key_pin in public_key_pins {
+          match public_key_pin.as_str().and_then(parse_public_key_pin) {
+            Some(parsed_public_key_pin) => parsed_public_key_pins.push(parsed_public_key_pin),
+            None => Err(anyhow::anyhow!(
+              "Invalid client public key pin. The pins must be in the \"sha256/<base64>\" format"
+            ))?,
+          }
+        }
+        parsed_public_key_pins
+      }
+      _ => Err(anyhow::anyhow!("Invalid client public key pin list"))?,
+    };
+    let raw_public_keys = match raw_public_keys {
+      Yaml::BadValue => false,
+      _ => raw_public_keys.as_bool().ok_or(anyhow::anyhow!(
+        "Invalid client raw public key enabling option value"
+      ))?,
+    };
+    if raw_public_keys && (public_key_pins.is_empty() || use_system_store || !ca_paths.is_empty()) {
+      Err(anyhow::anyhow!(
+        "The client raw public keys can be verified only against the pinned public keys"
+      ))?
+    }
+    if !crl_paths.is_empty() && !use_system_store && ca_paths.is_empty() {
+      Err(anyhow::anyhow!(
+        "The client certificate revocation lists require the client certificates to be verified against a certificate authority"
+      ))?
+    }
+
+    let client_auth_config = Self {
+      use_system_store,
+      ca_paths,
+      crl_paths,
+      optional,
+      public_key_pins,
+      raw_public_keys,
+    };
+    if client_auth_config.optional && !client_auth_config.is_enabled() {
+      Err(anyhow::anyhow!(
+        "The optional client certificate mode requires the client authentication to be enabled"
+      ))?
+    }
+    Ok(client_auth_config)
+  }
+
+  // Check if the clients are asked to authenticate with TLS
+  pub fn is_enabled(&self) -> bool {
+    self.use_system_store || !self.ca_paths.is_empty() || !self.public_key_pins.is_empty()
+  }
+}
+
+// Check if the listener configuration contains its own client authentication options
+pub fn has_client_auth_options(listener_config: &Yaml) -> bool {
+  CLIENT_AUTH_OPTIONS
+    .iter()
+    .any(|option| !listener_config[*option].is_badvalue())
+}
+
+// Parse the public key pin in the "sha256/<base64>" format
+pub fn parse_public_key_pin(public_key_pin: &str) -> Option<[u8; 32]> {
+  let public_key_hash = general_purpose::STANDARD
+    .decode(public_key_pin.strip_prefix("sha256/")?)
+    .ok()?;
+  public_key_hash.try_into().ok()
+}
+
+// Get the public key pin of the Subject Public Key Info, in the "sha256/<base64>" format
+pub fn public_key_pin(spki_der: &[u8]) -> String {
+  format!(
+    "sha256/{}",
+    general_purpose::STANDARD.encode(Sha256::digest(spki_der))
+  )
+}
+
+// Format the subject alternative name in the "<type>:<value>" format (for example, "DNS:client.example.com")
+fn format_subject_alt_name(name: &GeneralName<'_>) -> Option<String> {
+  match name {
+    GeneralName::DNSName(dns_name) => Some(format!("DNS:{}", dns_name)),
+    GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+    GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+    GeneralName::IPAddress(ip_address) => {
+      let ip_address = match ip_address.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(*ip_address).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(*ip_address).ok()?),
+        _ => return None,
+      };
+      Some(format!("IP:{}", ip_address))
+    }
+    GeneralName::DirectoryName(directory_name) => Some(format!("DirName:{}", directory_name)),
+    _ => None,
+  }
+}
+
+// Get the client identity from the end-entity client certificate, or from the raw public key
+pub fn client_identity(end_entity: &[u8]) -> Option<ClientIdentity> {
+  match X509Certificate::from_der(end_entity) {
+    Ok((_, certificate)) => {
+      let subject_alt_names = match certificate.subject_alternative_name() {
+        Ok(Some(subject_alt_names)) => subject_alt_names
+          .value
+          .general_names
+          .iter()
+          .filter_map(format_subject_alt_name)
+          .collect(),
+        _ => Vec::new(),
+      };
+      Some(ClientIdentity::new(
+        public_key_pin(certificate.public_key().raw),
+        Some(certificate.subject().to_string()),
+        subject_alt_names,
+      ))
+    }
+    Err(_) => {
+      SubjectPublicKeyInfo::from_der(end_entity).ok()?;
+      Some(ClientIdentity::new(
+        public_key_pin(end_entity),
+        None,
+        Vec::new(),
+      ))
+    }
+  }
+}
+
+// Get the Subject Public Key Info of the end-entity certificate, or of the raw public key
+fn subject_public_key_info(
+  end_entity: &CertificateDer<'_>,
+  raw_public_keys: bool,
+) -> Result<Vec<u8>, RustlsError> {
+  if raw_public_keys {
+    return Ok(end_entity.to_vec());
+  }
+  match X509Certificate::from_der(end_entity) {
+    Ok((_, certificate)) => Ok(certificate.public_key().raw.to_vec()),
+    Err(_) => Err(RustlsError::InvalidCertificate(
+      CertificateError::BadEncoding,
+    )),
+  }
+}
+
+// The client certificate verifier, which verifies the certificates against the private trust anchors
+// or the system certificate store, and checks the client public keys against the pins
+#[derive(Debug)]
+pub struct PinnedClientCertVerifier {
+  trust_anchor_verifier: Option<Arc<dyn ClientCertVerifier>>,
+  public_key_pins: Vec<[u8; 32]>,
+  raw_public_keys: bool,
+  optional: bool,
+  supported_algorithms: WebPkiSupportedAlgorithms,
+  logger: Sender<LogMessage>,
+}
+
+impl PinnedClientCertVerifier {
+  fn check_public_key_pin(&self, spki_der: &[u8]) -> Result<(), RustlsError> {
+    if self.public_key_pins.is_empty() {
+      return Ok(());
+    }
+    let public_key_hash: [u8; 32] = Sha256::digest(spki_der).into();
+    if self.public_key_pins.contains(&public_key_hash) {
+      Ok(())
+    } else {
+      self
+        .logger
+        .try_send(LogMessage::new(
+          format!(
+            "Rejected a TLS client with an unpinned public key ({})",
+            public_key_pin(spki_der)
+          ),
+          true,
+        ))
+        .unwrap_or_default();
+      Err(RustlsError::InvalidCertificate(
+        CertificateError::ApplicationVerificationFailure,
+      ))
+    }
+  }
+}
+
+impl ClientCertVerifier for PinnedClientCertVerifier {
+  fn client_auth_mandatory(&self) -> bool {
+    !self.optional
+  }
+
+  fn root_hint_subjects(&self) -> &[DistinguishedName] {
+    match &self.trust_anchor_verifier {
+      Some(trust_anchor_verifier) => trust_anchor_verifier.root_hint_subjects(),
+      None => &[],
+    }
+  }
+
+  fn verify_client_cert(
+    &self,
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    now: UnixTime,
+  ) -> Result<ClientCertVerified, RustlsError> {
+    if let Some(trust_anchor_verifier) = &self.trust_anchor_verifier {
+      trust_anchor_verifier.verify_client_cert(end_entity, intermediates, now)?;
+    }
+    self.check_public_key_pin(&subject_public_key_info(end_entity, self.raw_public_keys)?)?;
+    Ok(ClientCertVerified::assertion())
+  }
+
+  fn verify_tls12_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, RustlsError> {
+    if self.raw_public_keys {
+      return Err(RustlsError::PeerIncompatible(
+        PeerIncompatible::Tls12NotOffered,
+      ));
+    }
+    verify_tls12_signature(message, cert, dss, &self.supported_algorithms)
+  }
+
+  fn verify_tls13_signature(
+    &self,
+    message: &[u8],
+    cert: &CertificateDer<'_>,
+    dss: &DigitallySignedStruct,
+  ) -> Result<HandshakeSignatureValid, RustlsError> {
+    if self.raw_public_keys {
+      verify_tls13_signature_with_raw_key(
+        message,
+        &SubjectPublicKeyInfoDer::from(cert.as_ref()),
+        dss,
+        &self.supported_algorithms,
+      )
+    } else {
+      verify_tls13_signature(message, cert, dss, &self.supported_algorithms)
+    }
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.supported_algorithms.supported_schemes()
+  }
+
+  fn requires_raw_public_keys(&self) -> bool {
+    self.raw_public_keys
+  }
+}
+
+// Create the client certificate verifier. Returns None if the client authentication is disabled.
+pub fn create_client_cert_verifier(
+  client_auth_config: &ClientAuthConfig,
+  supported_algorithms: WebPkiSupportedAlgorithms,
+  logger: Sender<LogMessage>,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>, Box<dyn Error + Send + Sync>> {
+  if !client_auth_config.is_enabled() {
+    return Ok(None);
+  }
+
+  // The private CA certificates replace the system certificate store
+  let trust_anchors = if !client_auth_config.ca_paths.is_empty() {
+    let mut roots = RootCertStore::empty();
+    for ca_path in client_auth_config.ca_paths.iter() {
+      let certs = load_certs(ca_path).map_err(|err| {
+        anyhow::anyhow!(
+          "Cannot load the \"{}\" client certificate authority: {}",
+          ca_path,
+          err
+        )
+      })?;
+      if certs.is_empty() {
+        Err(anyhow::anyhow!(
+          "Cannot load the \"{}\" client certificate authority: The file doesn't contain any certificates",
+          ca_path
+        ))?
+      }
+      for cert in certs {
+        roots.add(cert).map_err(|err| {
+          anyhow::anyhow!(
+            "Cannot load the \"{}\" client certificate authority: {}",
+            ca_path,
+            err
+          )
+        })?;
+      }
+    }
+    Some(roots)
+  } else if client_auth_config.use_system_store {
+    let mut roots = RootCertStore::empty();
+    let certs_result = load_native_certs();
+    if !certs_result.errors.is_empty() {
+      Err(anyhow::anyhow!(
+        "Couldn't load the native certificate store: {}",
+        certs_result.errors[0]
+      ))?
+    }
+    for cert in certs_result.certs {
+      roots.add(cert).map_err(|err| {
+        anyhow::anyhow!(
+          "Couldn't add a certificate to the certificate store: {}",
+          err
+        )
+      })?;
+    }
+    Some(roots)
+  } else {
+    None
+  };
+
+  let trust_anchor_verifier = match trust_anchors {
+    Some(roots) => {
+      let mut crls = Vec::new();
+      for crl_path in client_auth_config.crl_paths.iter() {
+        let file_crls = load_crls(crl_path).map_err(|err| {
+          anyhow::anyhow!(
+            "Cannot load the \"{}\" client certificate revocation list: {}",
+            crl_path,
+            err
+          )
+        })?;
+        if file_crls.is_empty() {
+          Err(anyhow::anyhow!(
+            "Cannot load the \"{}\" client certificate revocation list: The file doesn't contain any revocation lists",
+            crl_path
+          ))?
+        }
+        crls.extend(file_crls);
+      }
+      let mut verifier_builder = WebPkiClientVerifier::builder(Arc::new(roots)).with_crls(crls);
+      if client_auth_config.optional {
+        verifier_builder = verifier_builder.allow_unauthenticated();
+      }
+      Some(verifier_builder.build()?)
+    }
+    None => None,
+  };
+
+  // Without the pins, the trust anchors are enough to verify the client certificates
+  if client_auth_config.public_key_pins.is_empty() {
+    return Ok(trust_anchor_verifier);
+  }
+
+  Ok(Some(Arc::new(PinnedClientCertVerifier {
+    trust_anchor_verifier,
+    public_key_pins: client_auth_config.public_key_pins.clone(),
+    raw_public_keys: client_auth_config.raw_public_keys,
+    optional: client_auth_config.optional,
+    supported_algorithms,
+    logger,
+  })))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{CertificateParams, KeyPair};
+  use yaml_rust2::YamlLoader;
+
+  #[test]
+  fn test_client_auth_config() {
+    let config = YamlLoader::load_from_str(
+      "clientPublicKeyPins:\n  - sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=\nclientRawPublicKeys: true\n",
+    )
+    .unwrap()
+    .remove(0);
+    assert!(has_client_auth_options(&config));
+    let client_auth_config = ClientAuthConfig::from_yaml(&config).unwrap();
+    assert!(client_auth_config.is_enabled());
+    let empty_hash: [u8; 32] = Sha256::digest(b"").into();
+    assert_eq!(client_auth_config.public_key_pins, vec![empty_hash]);
+
+    let config = YamlLoader::load_from_str("clientPublicKeyPins:\n  - sha1/AAAA\n")
+      .unwrap()
+      .remove(0);
+    assert!(ClientAuthConfig::from_yaml(&config).is_err());
+    let config = YamlLoader::load_from_str("clientRawPublicKeys: true\n")
+      .unwrap()
+      .remove(0);
+    assert!(ClientAuthConfig::from_yaml(&config).is_err());
+    assert!(!ClientAuthConfig::from_yaml(&Yaml::BadValue)
+      .unwrap()
+      .is_enabled());
+
+    let config = YamlLoader::load_from_str(
+      "clientCertificateCA: ca.crt\nclientCertificateCRL: [ca.crl]\nclientCertificateMode: optional\n",
+    )
+    .unwrap()
+    .remove(0);
+    let client_auth_config = ClientAuthConfig::from_yaml(&config).unwrap();
+    assert!(client_auth_config.optional);
+    assert_eq!(client_auth_config.crl_paths, vec!["ca.crl".to_string()]);
+    let config = YamlLoader::load_from_str("clientCertificateMode: optional\n")
+      .unwrap()
+      .remove(0);
+    assert!(ClientAuthConfig::from_yaml(&config).is_err());
+    let config =
+      YamlLoader::load_from_str("useClientCertificate: true\nclientCertificateMode: maybe\n")
+        .unwrap()
+        .remove(0);
+    assert!(ClientAuthConfig::from_yaml(&config).is_err());
+    let config = YamlLoader::load_from_str(
+      "clientPublicKeyPins:\n  - sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=\nclientCertificateCRL: ca.crl\n",
+    )
+    .unwrap()
+    .remove(0);
+    assert!(ClientAuthConfig::from_yaml(&config).is_err());
+  }
+
+  #[test]
+  fn test_pinned_client_certificate() {
+    let key = KeyPair::generate().unwrap();
+    let certificate = CertificateParams::new(vec!["client.example.com".to_string()])
+      .unwrap()
+      .self_signed(&key)
+      .unwrap();
+    let pin = public_key_pin(&key.public_key_der());
+    let identity = client_identity(certificate.der()).unwrap();
+    assert_eq!(identity.public_key_pin(), pin);
+    assert!(identity.subject().is_some());
+    assert_eq!(identity.subject_alt_names(), ["DNS:client.example.com"]);
+    let identity = client_identity(&key.public_key_der()).unwrap();
+    assert_eq!(identity.public_key_pin(), pin);
+    assert_eq!(identity.subject(), None);
+    assert!(identity.subject_alt_names().is_empty());
+
+    let (logger, _log_receiver) = async_channel::unbounded();
+    let verifier = |pin: &str| {
+      create_client_cert_verifier(
+        &ClientAuthConfig {
+          public_key_pins: vec![parse_public_key_pin(pin).unwrap()],
+          ..Default::default()
+        },
+        rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        logger.clone(),
+      )
+      .unwrap()
+      .unwrap()
+    };
+    assert!(verifier(&pin)
+      .verify_client_cert(certificate.der(), &[], UnixTime::now())
+      .is_ok());
+    assert!(
+      verifier("sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=")
+        .verify_client_cert(certificate.der(), &[], UnixTime::now())
+        .is_err()
+    );
+  }
+}
@@ -0,0 +1,278 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ferron_common::ServerConfigRoot;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use yaml_rust2::Yaml;
+
+// The request headers the device class is detected from. The responses routed by the device class vary on them.
+const DEVICE_CLASS_HEADERS: [&str; 2] = ["Sec-CH-UA-Mobile", "User-Agent"];
+
+// The device class of the client, used for routing the requests to the device-specific hosts and locations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceClass {
+  Mobile,
+  Tablet,
+  Desktop,
+}
+
+impl DeviceClass {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DeviceClass::Mobile => "mobile",
+      DeviceClass::Tablet => "tablet",
+      DeviceClass::Desktop => "desktop",
+    }
+  }
+
+  fn parse(device_class: &str) -> Option<Self> {
+    match device_class.to_lowercase().as_str() {
+      "mobile" => Some(DeviceClass::Mobile),
+      "tablet" => Some(DeviceClass::Tablet),
+      "desktop" => Some(DeviceClass::Desktop),
+      _ => None,
+    }
+  }
+}
+
+// Detect the device class of the client. The "Sec-CH-UA-Mobile" client hint takes precedence,
+// while the "User-Agent" header is used for the browsers not sending the User-Agent client hints.
+// The tablets don't identify themselves as mobile devices in the client hint, so they're detected from the "User-Agent" header.
+pub fn detect_device_class(headers: &HeaderMap) -> DeviceClass {
+  let user_agent = headers
+    .get(header::USER_AGENT)
+    .and_then(|user_agent| user_agent.to_str().ok())
+    .unwrap_or_default();
+  let is_tablet = user_agent.contains("iPad")
+    || user_agent.contains("Tablet")
+    || (user_agent.contains("Android") && !user_agent.contains("Mobile"));
+
+  match headers
+    .get("sec-ch-ua-mobile")
+    .and_then(|mobile| mobile.to_str().ok())
+    .map(|mobile| mobile.trim())
+  {
+    Some("?1") => DeviceClass::Mobile,
+    Some("?0") if is_tablet => DeviceClass::Tablet,
+    Some("?0") => DeviceClass::Desktop,
+    _ if is_tablet => DeviceClass::Tablet,
+    _ if user_agent.contains("Mobi")
+      || user_agent.contains("iPhone")
+      || user_agent.contains("iPod")
+      || user_agent.contains("Android") =>
+    {
+      DeviceClass::Mobile
+    }
+    _ => DeviceClass::Desktop,
+  }
+}
+
+// The device class condition of the hosts and the locations. It records if the device class was checked,
+// so that the responses routed by the device class vary on the request headers it's detected from.
+pub struct DeviceClassCondition {
+  device_class: DeviceClass,
+  checked: AtomicBool,
+}
+
+impl DeviceClassCondition {
+  pub fn new(device_class: DeviceClass) -> Self {
+    Self {
+      device_class,
+      checked: AtomicBool::new(false),
+    }
+  }
+
+  // Check if the device class is in the device class list (either a single device class or an array of device classes)
+  pub fn matches(&self, device_classes: &Yaml) -> bool {
+    self.checked.store(true, Ordering::Relaxed);
+    let matches = |device_class: &Yaml| {
+      device_class
+        .as_str()
+        .and_then(DeviceClass::parse)
+        .is_some_and(|device_class| device_class == self.device_class)
+    };
+    match device_classes {
+      Yaml::Array(device_classes) => device_classes.iter().any(matches),
+      device_class => matches(device_class),
+    }
+  }
+
+  pub fn was_checked(&self) -> bool {
+    self.checked.load(Ordering::Relaxed)
+  }
+}
+
+// Check if the value is a valid device class list
+pub fn is_device_class_list(device_classes: &Yaml) -> bool {
+  let is_device_class =
+    |device_class: &Yaml| device_class.as_str().and_then(DeviceClass::parse).is_some();
+  match device_classes {
+    Yaml::Array(device_classes) => device_classes.iter().all(is_device_class),
+    device_class => is_device_class(device_class),
+  }
+}
+
+// The client hints headers added to the responses, before the modules' response handlers (such as the cache) see them
+pub struct ClientHintsHeaders {
+  accept_ch: Option<HeaderValue>,
+  vary_on_device_class: bool,
+}
+
+impl ClientHintsHeaders {
+  pub fn new(config: &ServerConfigRoot, device_class_condition: &DeviceClassCondition) -> Self {
+    // The client hints requested from the browsers with the "Accept-CH" header
+    let accept_ch = config.get("clientHints").as_vec().and_then(|client_hints| {
+      let client_hints = client_hints
+        .iter()
+        .filter_map(|client_hint| client_hint.as_str())
+        .collect::<Vec<_>>();
+      match client_hints.is_empty() {
+        true => None,
+        false => HeaderValue::from_str(&client_hints.join(", ")).ok(),
+      }
+    });
+    Self {
+      accept_ch,
+      vary_on_device_class: device_class_condition.was_checked(),
+    }
+  }
+
+  pub fn apply(&self, headers: &mut HeaderMap) {
+    if let Some(accept_ch) = &self.accept_ch {
+      if !headers.contains_key("accept-ch") {
+        headers.insert(HeaderName::from_static("accept-ch"), accept_ch.clone());
+      }
+    }
+    if self.vary_on_device_class {
+      append_vary(headers, &DEVICE_CLASS_HEADERS);
+    }
+  }
+}
+
+// Add the header names to the "Vary" header, unless they're already listed
+fn append_vary(headers: &mut HeaderMap, header_names: &[&str]) {
+  let existing_vary = headers
+    .get_all(header::VARY)
+    .iter()
+    .filter_map(|vary| vary.to_str().ok())
+    .flat_map(|vary| vary.split(','))
+    .map(|header_name| header_name.trim().to_string())
+    .filter(|header_name| !header_name.is_empty())
+    .collect::<Vec<_>>();
+  if existing_vary.iter().any(|header_name| header_name == "*") {
+    return;
+  }
+
+  let mut vary = existing_vary.clone();
+  for header_name in header_names {
+    if !vary
+      .iter()
+      .any(|existing_header_name| existing_header_name.eq_ignore_ascii_case(header_name))
+    {
+      vary.push(header_name.to_string());
+    }
+  }
+  if vary.len() != existing_vary.len() {
+    if let Ok(vary) = HeaderValue::from_str(&vary.join(", ")) {
+      headers.insert(header::VARY, vary);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn headers(header_pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (header_name, header_value) in header_pairs {
+      headers.insert(*header_name, HeaderValue::from_static(header_value));
+    }
+    headers
+  }
+
+  #[test]
+  fn test_detect_device_class() {
+    assert_eq!(
+      detect_device_class(&headers(&[("sec-ch-ua-mobile", "?1")])),
+      DeviceClass::Mobile
+    );
+    assert_eq!(
+      detect_device_class(&headers(&[
+        ("sec-ch-ua-mobile", "?0"),
+        (
+          "user-agent",
+          "Mozilla/5.0 (Linux; Android 10; K) AppleWebKit/537.36 Chrome/120.0 Mobile Safari/537.36"
+        )
+      ])),
+      DeviceClass::Desktop
+    );
+    assert_eq!(
+      detect_device_class(&headers(&[(
+        "user-agent",
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148"
+      )])),
+      DeviceClass::Mobile
+    );
+    assert_eq!(
+      detect_device_class(&headers(&[(
+        "user-agent",
+        "Mozilla/5.0 (iPad; CPU OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148"
+      )])),
+      DeviceClass::Tablet
+    );
+    assert_eq!(
+      detect_device_class(&headers(&[(
+        "user-agent",
+        "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0"
+      )])),
+      DeviceClass::Desktop
+    );
+  }
+
+  #[test]
+  fn test_device_class_condition() {
+    let device_classes = yaml_rust2::YamlLoader::load_from_str("[mobile, Tablet]")
+      .unwrap()
+      .remove(0);
+    let condition = DeviceClassCondition::new(DeviceClass::Tablet);
+    assert!(!condition.was_checked());
+    assert!(condition.matches(&device_classes));
+    assert!(condition.was_checked());
+    assert!(!DeviceClassCondition::new(DeviceClass::Desktop).matches(&device_classes));
+    assert!(DeviceClassCondition::new(DeviceClass::Desktop)
+      .matches(&Yaml::String(String::from("desktop"))));
+
+    assert!(is_device_class_list(&device_classes));
+    assert!(!is_device_class_list(&Yaml::String(String::from("watch"))));
+  }
+
+  #[test]
+  fn test_client_hints_headers() {
+    let config = ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(
+        "clientHints:\n  - Sec-CH-UA-Mobile\n  - Sec-CH-UA-Model",
+      )
+      .unwrap()
+      .remove(0),
+    );
+    let condition = DeviceClassCondition::new(DeviceClass::Mobile);
+    condition.matches(&Yaml::String(String::from("mobile")));
+    let client_hints_headers = ClientHintsHeaders::new(&config, &condition);
+
+    let mut response_headers = headers(&[("vary", "Accept-Encoding, user-agent")]);
+    client_hints_headers.apply(&mut response_headers);
+    assert_eq!(
+      response_headers.get("accept-ch").unwrap(),
+      "Sec-CH-UA-Mobile, Sec-CH-UA-Model"
+    );
+    assert_eq!(
+      response_headers.get(header::VARY).unwrap(),
+      "Accept-Encoding, user-agent, Sec-CH-UA-Mobile"
+    );
+
+    // The responses varying on all the request headers aren't changed
+    let mut response_headers = headers(&[("vary", "*")]);
+    client_hints_headers.apply(&mut response_headers);
+    assert_eq!(response_headers.get(header::VARY).unwrap(), "*");
+  }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{broadcast, Notify};
+
+use crate::ferron_util::metrics::METRICS;
+
+// The process-wide registry of the file reads shared by the concurrent responses sending the same file
+pub static COALESCED_FILE_READS: LazyLock<CoalescedFileReads> =
+  LazyLock::new(CoalescedFileReads::new);
+
+// The minimum size of the files, for which the reads are coalesced
+pub const COALESCED_READ_MIN_SIZE: u64 = 1048576;
+
+// The number of the chunks, which can be queued for the responses sending the shared file
+const COALESCED_READ_CHANNEL_CAPACITY: usize = 64;
+
+// The number of the queued chunks, after which the shared read waits for the responses to catch up
+const COALESCED_READ_PACING_LENGTH: usize = COALESCED_READ_CHANNEL_CAPACITY / 2;
+
+// The maximum time the shared read waits for the slowest response. The responses left behind read the file themselves.
+const COALESCED_READ_PACING_TIMEOUT: Duration = Duration::from_millis(500);
+
+// The file identity, so that the reads of a modified file aren't shared with the reads of the previous version
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct FileKey {
+  path: PathBuf,
+  length: u64,
+  modified: Option<SystemTime>,
+}
+
+// A chunk of the shared file, with its offset in the file
+type SharedChunk = (u64, Bytes);
+
+// The state of a shared file read
+struct SharedReadState {
+  // The offset of the next chunk to be sent
+  offset: u64,
+  sender: broadcast::Sender<SharedChunk>,
+}
+
+// A file read shared by the concurrent responses
+struct SharedRead {
+  state: Mutex<SharedReadState>,
+  // Notified by the responses when they receive a chunk, so that the shared read can continue
+  received: Notify,
+}
+
+// The registry of the file reads shared by the concurrent responses
+pub struct CoalescedFileReads {
+  reads: Mutex<HashMap<FileKey, Arc<SharedRead>>>,
+}
+
+impl CoalescedFileReads {
+  pub fn new() -> Self {
+    Self {
+      reads: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // Create a response body sending the whole file. The file is read once for the concurrent responses sending
+  // the same file. The response joining the read in progress reads the part of the file already read by itself,
+  // using the file opened for the response, and so do the responses falling too far behind the shared read.
+  pub fn file_body(
+    &'static self,
+    path: &Path,
+    file: File,
+    length: u64,
+    modified: Option<SystemTime>,
+    buffer_size: usize,
+  ) -> BoxBody<Bytes, std::io::Error> {
+    let key = FileKey {
+      path: path.to_path_buf(),
+      length,
+      modified,
+    };
+    let (shared_read, join_offset, receiver) = self.join(key, buffer_size);
+    METRICS.increment_counter(
+      "ferron_coalesced_file_reads_total",
+      &[(
+        "result",
+        match join_offset {
+          0 => "shared",
+          _ => "joined",
+        },
+      )],
+    );
+
+    let state = CoalescedBodyState {
+      file,
+      position: 0,
+      length,
+      join_offset,
+      receiver: Some(receiver),
+      shared_read,
+      buffer_size,
+    };
+    let stream = futures_util::stream::unfold(state, |mut state| async move {
+      match state.next_chunk().await {
+        Ok(Some(chunk)) => Some((Ok(Frame::data(chunk)), state)),
+        Ok(None) => None,
+        Err(err) => {
+          // The body ends after the error
+          state.position = state.length;
+          state.receiver = None;
+          Some((Err(err), state))
+        }
+      }
+    });
+    BodyExt::boxed(StreamBody::new(stream))
+  }
+
+  // Join the shared read of the file, starting it if there is no read in progress.
+  // Returns the shared read, the offset of the first chunk received, and the receiver of the chunks.
+  fn join(
+    &'static self,
+    key: FileKey,
+    buffer_size: usize,
+  ) -> (Arc<SharedRead>, u64, broadcast::Receiver<SharedChunk>) {
+    let mut reads = match self.reads.lock() {
+      Ok(reads) => reads,
+      Err(err) => err.into_inner(),
+    };
+    if let Some(shared_read) = reads.get(&key) {
+      let state = match shared_read.state.lock() {
+        Ok(state) => state,
+        Err(err) => err.into_inner(),
+      };
+      let join_offset = state.offset;
+      let receiver = state.sender.subscribe();
+      drop(state);
+      return (shared_read.clone(), join_offset, receiver);
+    }
+
+    let (sender, receiver) = broadcast::channel(COALESCED_READ_CHANNEL_CAPACITY);
+    let shared_read = Arc::new(SharedRead {
+      state: Mutex::new(SharedReadState { offset: 0, sender }),
+      received: Notify::new(),
+    });
+    reads.insert(key.clone(), shared_read.clone());
+    drop(reads);
+
+    tokio::spawn(self.read_shared(key, shared_read.clone(), buffer_size));
+    (shared_read, 0, receiver)
+  }
+
+  // Read the file, and send its chunks to the responses sharing the read.
+  // The read is paced by the responses, unless they fall too far behind.
+  async fn read_shared(&self, key: FileKey, shared_read: Arc<SharedRead>, buffer_size: usize) {
+    if let Ok(mut file) = File::open(&key.path).await {
+      loop {
+        let sender = match shared_read.state.lock() {
+          Ok(state) => state.sender.clone(),
+          Err(err) => err.into_inner().sender.clone(),
+        };
+        // All the responses sharing the read are gone
+        if sender.receiver_count() == 0 {
+          break;
+        }
+        while sender.len() >= COALESCED_READ_PACING_LENGTH {
+          if tokio::time::timeout(
+            COALESCED_READ_PACING_TIMEOUT,
+            shared_read.received.notified(),
+          )
+          .await
+          .is_err()
+          {
+            break;
+          }
+        }
+        drop(sender);
+
+        let mut buffer = vec![0u8; buffer_size];
+        let read_length = match file.read(&mut buffer).await {
+          Ok(0) | Err(_) => break,
+          Ok(read_length) => read_length,
+        };
+        buffer.truncate(read_length);
+
+        let mut state = match shared_read.state.lock() {
+          Ok(state) => state,
+          Err(err) => err.into_inner(),
+        };
+        let offset = state.offset;
+        state
+          .sender
+          .send((offset, Bytes::from(buffer)))
+          .unwrap_or_default();
+        state.offset += read_length as u64;
+      }
+    }
+
+    // The responses read the rest of the file themselves, if the shared read has failed
+    let mut reads = match self.reads.lock() {
+      Ok(reads) => reads,
+      Err(err) => err.into_inner(),
+    };
+    if reads
+      .get(&key)
+      .is_some_and(|registered_read| Arc::ptr_eq(registered_read, &shared_read))
+    {
+      reads.remove(&key);
+    }
+  }
+}
+
+// The state of the response body sending the shared file
+struct CoalescedBodyState {
+  file: File,
+  position: u64,
+  length: u64,
+  join_offset: u64,
+  // The receiver of the shared chunks, or None if the response reads the file itself
+  receiver: Option<broadcast::Receiver<SharedChunk>>,
+  shared_read: Arc<SharedRead>,
+  buffer_size: usize,
+}
+
+impl CoalescedBodyState {
+  async fn next_chunk(&mut self) -> Result<Option<Bytes>, std::io::Error> {
+    if self.position >= self.length {
+      return Ok(None);
+    }
+
+    // The part of the file read before the response joined the shared read is read by the response itself
+    if self.position < self.join_offset {
+      let maximum_length = (self.join_offset - self.position).min(self.buffer_size as u64);
+      return self.read_file(maximum_length as usize).await;
+    }
+
+    while let Some(receiver) = self.receiver.as_mut() {
+      match receiver.recv().await {
+        Ok((offset, chunk)) => {
+          self.shared_read.received.notify_one();
+          let chunk_end = offset + chunk.len() as u64;
+          if chunk_end <= self.position {
+            continue;
+          } else if offset > self.position {
+            // A part of the file was missed, so the response reads the rest of the file itself
+            self.stop_sharing().await?;
+          } else {
+            let chunk = chunk.slice((self.position - offset) as usize..);
+            self.position = chunk_end;
+            return Ok(Some(chunk));
+          }
+        }
+        Err(broadcast::error::RecvError::Lagged(_)) | Err(broadcast::error::RecvError::Closed) => {
+          // The response fell too far behind the shared read, or the shared read has ended early
+          self.stop_sharing().await?;
+        }
+      }
+    }
+
+    let maximum_length = (self.length - self.position).min(self.buffer_size as u64);
+    self.read_file(maximum_length as usize).await
+  }
+
+  // Stop receiving the shared chunks, and continue reading the file from the current position
+  async fn stop_sharing(&mut self) -> Result<(), std::io::Error> {
+    self.receiver = None;
+    self.file.seek(SeekFrom::Start(self.position)).await?;
+    Ok(())
+  }
+
+  async fn read_file(&mut self, maximum_length: usize) -> Result<Option<Bytes>, std::io::Error> {
+    let mut buffer = vec![0u8; maximum_length];
+    let read_length = self.file.read(&mut buffer).await?;
+    if read_length == 0 {
+      // The file was truncated while it was sent
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "The file ended before its expected length",
+      ));
+    }
+    buffer.truncate(read_length);
+    self.position += read_length as u64;
+    Ok(Some(Bytes::from(buffer)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn collect_body(mut body: BoxBody<Bytes, std::io::Error>) -> Vec<u8> {
+    let mut received = Vec::new();
+    while let Some(frame) = body.frame().await {
+      received.extend_from_slice(&frame.unwrap().into_data().unwrap());
+    }
+    received
+  }
+
+  #[tokio::test]
+  async fn test_coalesced_file_reads() {
+    let reads: &'static CoalescedFileReads = Box::leak(Box::new(CoalescedFileReads::new()));
+    let path = std::env::temp_dir().join(format!("ferron-coalesced-read-{}", std::process::id()));
+    let data = (0..300000)
+      .map(|index| (index % 251) as u8)
+      .collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+
+    let mut bodies = Vec::new();
+    for _ in 0..8 {
+      let file = File::open(&path).await.unwrap();
+      bodies.push(reads.file_body(&path, file, data.len() as u64, None, 4096));
+    }
+    assert_eq!(reads.reads.lock().unwrap().len(), 1);
+
+    // The bodies are consumed concurrently, some of them after the shared read has progressed
+    let mut received = Vec::new();
+    let mut bodies = bodies.into_iter();
+    let first_body = bodies.next().unwrap();
+    received.push(collect_body(first_body).await);
+    let late_file = File::open(&path).await.unwrap();
+    let late_body = reads.file_body(&path, late_file, data.len() as u64, None, 4096);
+    for received_body in
+      futures_util::future::join_all(bodies.chain(std::iter::once(late_body)).map(collect_body))
+        .await
+    {
+      received.push(received_body);
+    }
+
+    for received_body in received {
+      assert_eq!(received_body, data);
+    }
+    std::fs::remove_file(path).unwrap();
+  }
+}
@@ -4,7 +4,10 @@ use ferron_common::ServerConfigRoot;
 use yaml_rust2::{yaml::Hash, Yaml};
 
 use crate::ferron_util::{
-  ip_match::ip_match, match_hostname::match_hostname, match_location::match_location,
+  client_hints::DeviceClassCondition,
+  ip_match::ip_match,
+  match_hostname::match_hostname,
+  match_location::{select_location, LocationMatcher},
 };
 
 pub fn combine_config(
@@ -12,6 +15,8 @@ pub fn combine_config(
   host_config: Arc<Yaml>,
   hostname: Option<&str>,
   client_ip: IpAddr,
+  client_country: Option<&str>,
+  device_class: &DeviceClassCondition,
   path: &str,
 ) -> Option<ServerConfigRoot> {
   let global_config = global_config_root.as_hash();
@@ -32,8 +37,27 @@ pub fn combine_config(
           .map(|ip| ip_match(ip, client_ip))
           .unwrap_or(true);
 
-        if domain_matched && ip_matched {
-          return Some(merge_host_configs(combined_config, host_hashtable, path));
+        let country_matched = host_hashtable
+          .get(&Yaml::String("country".to_string()))
+          .map(|country| country_match(country, client_country))
+          .unwrap_or(true);
+
+        let device_class_matched = domain_matched
+          && ip_matched
+          && country_matched
+          && host_hashtable
+            .get(&Yaml::String("deviceClass".to_string()))
+            .map(|device_classes| device_class.matches(device_classes))
+            .unwrap_or(true);
+
+        if device_class_matched {
+          return Some(merge_host_configs(
+            combined_config,
+            host_hashtable,
+            client_country,
+            Some(device_class),
+            path,
+          ));
         }
       }
     }
@@ -42,9 +66,50 @@ pub fn combine_config(
   combined_config.map(ServerConfigRoot::from_hash)
 }
 
+// Combine the global configuration with the host configuration and, optionally, with the location configuration,
+// the same way as for the requests matched to the host and the location
+pub fn combine_scope_config(
+  global_config_root: &ServerConfigRoot,
+  host: &Hash,
+  location: Option<&Hash>,
+) -> ServerConfigRoot {
+  let mut host = host.clone();
+  host.remove(&Yaml::String("locations".to_string()));
+  let host_config = merge_host_configs(
+    Some(global_config_root.as_hash().clone()),
+    &host,
+    None,
+    None,
+    "/",
+  );
+  match location {
+    Some(location) => merge_location_configs(Some(host_config.as_hash().clone()), location),
+    None => host_config,
+  }
+}
+
+// Check if the client's country (an ISO 3166-1 alpha-2 code) is in the country list (either a single code or an array of codes)
+fn country_match(country: &Yaml, client_country: Option<&str>) -> bool {
+  let client_country = match client_country {
+    Some(client_country) => client_country,
+    None => return false,
+  };
+  match country {
+    Yaml::String(country) => country.eq_ignore_ascii_case(client_country),
+    Yaml::Array(countries) => countries.iter().any(|country| {
+      country
+        .as_str()
+        .is_some_and(|country| country.eq_ignore_ascii_case(client_country))
+    }),
+    _ => false,
+  }
+}
+
 fn merge_host_configs(
   global: Option<HashMap<String, Yaml>>,
   host: &Hash,
+  client_country: Option<&str>,
+  device_class: Option<&DeviceClassCondition>,
   path: &str,
 ) -> ServerConfigRoot {
   let mut merged = global.unwrap_or_default();
@@ -94,18 +159,31 @@ fn merge_host_configs(
 
   if let Some(locations) = locations {
     if let Ok(decoded_path) = urlencoding::decode(path) {
-      for location in locations {
-        if let Some(location_hashtable) = location.as_hash() {
-          let path_matched = location_hashtable
-            .get(&Yaml::String("path".to_string()))
-            .and_then(Yaml::as_str)
-            .map(|path_match| match_location(path_match, &decoded_path))
-            .unwrap_or(true);
+      let matching_locations = locations.iter().filter_map(|location| {
+        let location_hashtable = location.as_hash()?;
+        let location_match = LocationMatcher::from_yaml(location)
+          .ok()?
+          .matches(&decoded_path)?;
+
+        let country_matched = location_hashtable
+          .get(&Yaml::String("country".to_string()))
+          .map(|country| country_match(country, client_country))
+          .unwrap_or(true);
 
-          if path_matched {
-            return merge_location_configs(Some(merged), location_hashtable);
-          }
-        }
+        let device_class_matched = country_matched
+          && match (
+            location_hashtable.get(&Yaml::String("deviceClass".to_string())),
+            device_class,
+          ) {
+            (Some(device_classes), Some(device_class)) => device_class.matches(device_classes),
+            _ => true,
+          };
+
+        device_class_matched.then_some((location_hashtable, location_match))
+      });
+
+      if let Some(location_hashtable) = select_location(matching_locations) {
+        return merge_location_configs(Some(merged), location_hashtable);
       }
     }
   }
@@ -161,6 +239,7 @@ fn merge_location_configs(
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::ferron_util::client_hints::DeviceClass;
   use std::net::{IpAddr, Ipv4Addr};
   use yaml_rust2::{Yaml, YamlLoader};
 
@@ -198,7 +277,15 @@ mod tests {
     let hostname = Some("example.com");
     let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
 
-    let result = combine_config(global_config_root, host_config, hostname, client_ip, "/");
+    let result = combine_config(
+      global_config_root,
+      host_config,
+      hostname,
+      client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
+      "/",
+    );
     assert!(result.is_some());
 
     let result_yaml = result.unwrap();
@@ -214,7 +301,15 @@ mod tests {
     let hostname = Some("nonexistent.com");
     let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
 
-    let result = combine_config(global_config_root, host_config, hostname, client_ip, "/");
+    let result = combine_config(
+      global_config_root,
+      host_config,
+      hostname,
+      client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
+      "/",
+    );
     assert!(result.is_some());
     assert!(result.unwrap().as_hash().get("key3").is_none());
   }
@@ -225,7 +320,15 @@ mod tests {
     let hostname = Some("example.com");
     let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
 
-    let result = combine_config(global_config_root, host_config, hostname, client_ip, "/");
+    let result = combine_config(
+      global_config_root,
+      host_config,
+      hostname,
+      client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
+      "/",
+    );
     assert!(result.is_some());
     assert!(result.unwrap().as_hash().get("key3").is_none());
   }
@@ -248,7 +351,15 @@ mod tests {
     let hostname = None;
     let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
 
-    let result = combine_config(global_config_root, host_config, hostname, client_ip, "/");
+    let result = combine_config(
+      global_config_root,
+      host_config,
+      hostname,
+      client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
+      "/",
+    );
     assert!(result.is_some());
 
     let result_yaml = result.unwrap();
@@ -276,7 +387,15 @@ mod tests {
     let hostname = Some("example.com");
     let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
 
-    let result = combine_config(global_config_root, host_config, hostname, client_ip, "/");
+    let result = combine_config(
+      global_config_root,
+      host_config,
+      hostname,
+      client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
+      "/",
+    );
     assert!(result.is_some());
 
     let result_yaml = result.unwrap();
@@ -316,6 +435,8 @@ mod tests {
       host_config,
       hostname,
       client_ip,
+      None,
+      &DeviceClassCondition::new(DeviceClass::Desktop),
       "/test",
     );
     assert!(result.is_some());
@@ -325,4 +446,164 @@ mod tests {
 
     assert_eq!(result_hash.get("key3").unwrap().as_vec().unwrap().len(), 1);
   }
+
+  #[test]
+  fn test_combine_config_with_location_precedence() {
+    let yaml_str = r#"
+        global:
+          root: /var/www/html
+        hosts:
+          - domain: example.com
+            locations:
+              - path: /
+                root: /var/www/root
+              - path: /static
+                root: /var/www/static
+              - path: /static/images
+                root: /var/www/images
+              - pathRegex: \.php$
+                root: /var/www/php
+        "#;
+
+    let docs = YamlLoader::load_from_str(yaml_str).unwrap();
+    let config_yaml = docs[0].clone();
+    let global_config_root = Arc::new(ServerConfigRoot::new(&config_yaml["global"]));
+    let host_config = Arc::new(config_yaml["hosts"].clone());
+    let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+
+    let get_root = |path: &str| {
+      combine_config(
+        global_config_root.clone(),
+        host_config.clone(),
+        Some("example.com"),
+        client_ip,
+        None,
+        &DeviceClassCondition::new(DeviceClass::Desktop),
+        path,
+      )
+      .unwrap()
+      .get("root")
+      .as_str()
+      .unwrap()
+      .to_string()
+    };
+
+    assert_eq!(get_root("/index.html"), "/var/www/root");
+    assert_eq!(get_root("/static/style.css"), "/var/www/static");
+    assert_eq!(get_root("/static/images/logo.png"), "/var/www/images");
+    assert_eq!(get_root("/static/index.php"), "/var/www/php");
+  }
+
+  #[test]
+  fn test_combine_config_with_country_match() {
+    let yaml_str = r#"
+        global:
+          root: /var/www/html
+        hosts:
+          - domain: example.com
+            country: [DE, FR]
+            root: /var/www/eu
+          - domain: example.com
+            locations:
+              - path: /downloads
+                country: XX
+                root: /var/www/embargoed
+        "#;
+
+    let docs = YamlLoader::load_from_str(yaml_str).unwrap();
+    let config_yaml = docs[0].clone();
+    let global_config_root = Arc::new(ServerConfigRoot::new(&config_yaml["global"]));
+    let host_config = Arc::new(config_yaml["hosts"].clone());
+
+    let hostname = Some("example.com");
+    let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+
+    let get_root = |country: Option<&str>, path: &str| {
+      combine_config(
+        global_config_root.clone(),
+        host_config.clone(),
+        hostname,
+        client_ip,
+        country,
+        &DeviceClassCondition::new(DeviceClass::Desktop),
+        path,
+      )
+      .unwrap()
+      .get("root")
+      .as_str()
+      .unwrap()
+      .to_string()
+    };
+
+    assert_eq!(get_root(Some("de"), "/"), "/var/www/eu");
+    assert_eq!(get_root(Some("US"), "/"), "/var/www/html");
+    assert_eq!(get_root(None, "/"), "/var/www/html");
+    assert_eq!(
+      get_root(Some("XX"), "/downloads/file"),
+      "/var/www/embargoed"
+    );
+    assert_eq!(get_root(Some("US"), "/downloads/file"), "/var/www/html");
+  }
+
+  #[test]
+  fn test_combine_config_with_device_class_match() {
+    let yaml_str = r#"
+        global:
+          root: /var/www/html
+        hosts:
+          - domain: example.com
+            deviceClass: [mobile, tablet]
+            root: /var/www/mobile
+          - domain: example.com
+            locations:
+              - path: /app
+                deviceClass: desktop
+                root: /var/www/app
+          - domain: other.example
+            root: /var/www/other
+        "#;
+
+    let docs = YamlLoader::load_from_str(yaml_str).unwrap();
+    let config_yaml = docs[0].clone();
+    let global_config_root = Arc::new(ServerConfigRoot::new(&config_yaml["global"]));
+    let host_config = Arc::new(config_yaml["hosts"].clone());
+    let client_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+
+    let get_root = |hostname: &str, device_class: DeviceClass, path: &str| {
+      let device_class = DeviceClassCondition::new(device_class);
+      let root = combine_config(
+        global_config_root.clone(),
+        host_config.clone(),
+        Some(hostname),
+        client_ip,
+        None,
+        &device_class,
+        path,
+      )
+      .unwrap()
+      .get("root")
+      .as_str()
+      .unwrap()
+      .to_string();
+      (root, device_class.was_checked())
+    };
+
+    assert_eq!(
+      get_root("example.com", DeviceClass::Tablet, "/app"),
+      ("/var/www/mobile".to_string(), true)
+    );
+    assert_eq!(
+      get_root("example.com", DeviceClass::Desktop, "/app/index.html"),
+      ("/var/www/app".to_string(), true)
+    );
+    assert_eq!(
+      get_root("example.com", DeviceClass::Desktop, "/"),
+      ("/var/www/html".to_string(), true)
+    );
+    // The device class isn't checked for the hosts without the device class routing
+    assert_eq!(
+      get_root("other.example", DeviceClass::Mobile, "/"),
+      ("/var/www/other".to_string(), false)
+    );
+  }
 }
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// The limit that was exceeded when trying to acquire a permit
+#[derive(Debug, PartialEq)]
+pub enum LimitExceeded {
+  Global,
+  PerIp,
+}
+
+impl LimitExceeded {
+  // Get the name of the limit, used as a metric label
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LimitExceeded::Global => "global",
+      LimitExceeded::PerIp => "per_ip",
+    }
+  }
+}
+
+// Limits the number of concurrent connections or requests, both globally and per client IP address
+pub struct ConcurrencyLimiter {
+  max_total: Option<usize>,
+  max_per_ip: Option<usize>,
+  observe: bool,
+  total: AtomicUsize,
+  per_ip: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConcurrencyLimiter {
+  // Create a new limiter. In the observe mode, the exceeded limits are reported, but not enforced.
+  pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>, observe: bool) -> Arc<Self> {
+    Arc::new(Self {
+      max_total,
+      max_per_ip,
+      observe,
+      total: AtomicUsize::new(0),
+      per_ip: Mutex::new(HashMap::new()),
+    })
+  }
+
+  // Try to acquire a permit for a client. The permit is released when the returned guard is dropped.
+  pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConcurrencyPermit, LimitExceeded> {
+    let ip = ip.to_canonical();
+    let previous_total = self.total.fetch_add(1, Ordering::AcqRel);
+    if self
+      .max_total
+      .is_some_and(|max_total| previous_total >= max_total)
+    {
+      self.total.fetch_sub(1, Ordering::AcqRel);
+      return Err(LimitExceeded::Global);
+    }
+
+    if let Some(max_per_ip) = self.max_per_ip {
+      if let Ok(mut per_ip) = self.per_ip.lock() {
+        let count = per_ip.entry(ip).or_insert(0);
+        if *count >= max_per_ip {
+          drop(per_ip);
+          self.total.fetch_sub(1, Ordering::AcqRel);
+          return Err(LimitExceeded::PerIp);
+        }
+        *count += 1;
+      }
+    }
+
+    Ok(ConcurrencyPermit {
+      limiter: self.clone(),
+      ip,
+    })
+  }
+
+  // Acquire a permit for a client regardless of the limits. It's used in the observe mode,
+  // so that the connections or requests over the limits are still counted.
+  pub fn acquire_over_limit(self: &Arc<Self>, ip: IpAddr) -> ConcurrencyPermit {
+    let ip = ip.to_canonical();
+    self.total.fetch_add(1, Ordering::AcqRel);
+    if self.max_per_ip.is_some() {
+      if let Ok(mut per_ip) = self.per_ip.lock() {
+        *per_ip.entry(ip).or_insert(0) += 1;
+      }
+    }
+    ConcurrencyPermit {
+      limiter: self.clone(),
+      ip,
+    }
+  }
+
+  // Check if the limiter is in the observe mode
+  pub fn is_observing(&self) -> bool {
+    self.observe
+  }
+}
+
+pub struct ConcurrencyPermit {
+  limiter: Arc<ConcurrencyLimiter>,
+  ip: IpAddr,
+}
+
+impl Drop for ConcurrencyPermit {
+  fn drop(&mut self) {
+    self.limiter.total.fetch_sub(1, Ordering::AcqRel);
+    if self.limiter.max_per_ip.is_some() {
+      if let Ok(mut per_ip) = self.limiter.per_ip.lock() {
+        // Remove the entries of clients without permits, so that the map doesn't grow indefinitely
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+          *count = count.saturating_sub(1);
+          if *count == 0 {
+            per_ip.remove(&self.ip);
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_global_limit() {
+    let limiter = ConcurrencyLimiter::new(Some(2), None, false);
+    let first_permit = limiter.try_acquire("192.0.2.1".parse().unwrap()).unwrap();
+    let _second_permit = limiter.try_acquire("192.0.2.2".parse().unwrap()).unwrap();
+    assert_eq!(
+      limiter.try_acquire("192.0.2.3".parse().unwrap()).err(),
+      Some(LimitExceeded::Global)
+    );
+    drop(first_permit);
+    assert!(limiter.try_acquire("192.0.2.3".parse().unwrap()).is_ok());
+  }
+
+  #[test]
+  fn test_per_ip_limit() {
+    let limiter = ConcurrencyLimiter::new(Some(10), Some(1), false);
+    let ip: IpAddr = "2001:db8::1".parse().unwrap();
+    let permit = limiter.try_acquire(ip).unwrap();
+    assert_eq!(limiter.try_acquire(ip).err(), Some(LimitExceeded::PerIp));
+    assert!(limiter.try_acquire("2001:db8::2".parse().unwrap()).is_ok());
+    drop(permit);
+    let _permit = limiter.try_acquire(ip).unwrap();
+    assert_eq!(limiter.total.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn test_acquire_over_limit() {
+    let limiter = ConcurrencyLimiter::new(None, Some(1), true);
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    let _permit = limiter.try_acquire(ip).unwrap();
+    assert_eq!(limiter.try_acquire(ip).err(), Some(LimitExceeded::PerIp));
+    let over_limit_permit = limiter.acquire_over_limit(ip);
+    assert_eq!(limiter.total.load(Ordering::Relaxed), 2);
+    drop(over_limit_permit);
+    assert_eq!(limiter.per_ip.lock().unwrap().get(&ip), Some(&1));
+  }
+}
@@ -0,0 +1,116 @@
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+
+// Format the time as an HTTP date (IMF-fixdate), as used in the "Last-Modified" header
+pub fn http_date(time: SystemTime) -> String {
+  let datetime: DateTime<Utc> = time.into();
+  datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// Get the value of the "Last-Modified" header for a file
+pub fn last_modified(metadata: &Metadata) -> Option<String> {
+  metadata.modified().ok().map(http_date)
+}
+
+// Generate a weak entity tag from the file length and the modification time, which is cheap to compute for every request
+pub fn weak_file_etag(metadata: &Metadata) -> String {
+  let modified_secs = metadata
+    .modified()
+    .ok()
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .map(|modified| modified.as_secs())
+    .unwrap_or(0);
+  format!("W/\"{:x}-{:x}\"", metadata.len(), modified_secs)
+}
+
+// Check if the entity tag matches any of the entity tags in the "If-Match" or "If-None-Match" header.
+// The weak comparison ignores the "W/" prefixes, while the strong comparison never matches the weak entity tags.
+// The quotes are optional, since the entity tags generated for static files are sent without the quotes.
+pub fn etag_list_matches(header_value: &str, etag: &str, weak_comparison: bool) -> bool {
+  let header_value = header_value.trim();
+  if header_value == "*" {
+    return true;
+  }
+
+  let normalize = |etag: &str| -> Option<String> {
+    let etag = etag.trim();
+    let (is_weak, opaque_tag) = match etag.strip_prefix("W/") {
+      Some(opaque_tag) => (true, opaque_tag),
+      None => (false, etag),
+    };
+    if is_weak && !weak_comparison {
+      return None;
+    }
+    let opaque_tag = opaque_tag
+      .strip_prefix('"')
+      .and_then(|opaque_tag| opaque_tag.strip_suffix('"'))
+      .unwrap_or(opaque_tag);
+    Some(opaque_tag.to_string())
+  };
+
+  let etag = match normalize(etag) {
+    Some(etag) => etag,
+    None => return false,
+  };
+  header_value
+    .split(',')
+    .filter_map(normalize)
+    .any(|header_etag| header_etag == etag)
+}
+
+// Check if the file wasn't modified since the date in the "If-Modified-Since" header. Invalid dates are ignored.
+pub fn not_modified_since(if_modified_since: &str, modified: Option<SystemTime>) -> bool {
+  let date = match DateTime::parse_from_rfc2822(if_modified_since.trim()) {
+    Ok(date) => date,
+    Err(_) => return false,
+  };
+  modified
+    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+    .is_some_and(|modified| modified.as_secs() as i64 <= date.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn test_http_date() {
+    let time = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+  }
+
+  #[test]
+  fn test_etag_list_matches() {
+    assert!(etag_list_matches("abc123", "abc123", true));
+    assert!(etag_list_matches("\"abc123\"", "abc123", false));
+    assert!(etag_list_matches("\"xyz\", \"abc123\"", "abc123", true));
+    assert!(etag_list_matches("*", "abc123", false));
+    assert!(etag_list_matches("W/\"abc123\"", "abc123", true));
+    assert!(!etag_list_matches("W/\"abc123\"", "abc123", false));
+    assert!(!etag_list_matches("\"xyz\"", "abc123", true));
+    assert!(etag_list_matches("W/\"1-2\"", "W/\"1-2\"", true));
+    assert!(!etag_list_matches("W/\"1-2\"", "W/\"1-2\"", false));
+  }
+
+  #[test]
+  fn test_not_modified_since() {
+    let modified = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+    assert!(not_modified_since(
+      "Sun, 06 Nov 1994 08:49:37 GMT",
+      modified
+    ));
+    assert!(not_modified_since(
+      "Mon, 07 Nov 1994 08:49:37 GMT",
+      modified
+    ));
+    assert!(!not_modified_since(
+      "Sun, 06 Nov 1994 08:49:36 GMT",
+      modified
+    ));
+    assert!(!not_modified_since("invalid date", modified));
+    assert!(!not_modified_since("Sun, 06 Nov 1994 08:49:37 GMT", None));
+  }
+}
@@ -0,0 +1,304 @@
+use std::sync::{Arc, RwLock};
+
+use ferron_common::ServerConfigRoot;
+use serde_json::{json, Map, Value};
+use yaml_rust2::yaml::Hash;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::combine_config::combine_scope_config;
+use crate::ferron_util::diagnostics::redact_config;
+
+// The configuration used by the requests, exported through the admin API
+static ACTIVE_CONFIG: RwLock<Option<Arc<Yaml>>> = RwLock::new(None);
+
+// The default values of the configuration properties, applied by the server when they aren't set.
+// The hosts and the locations inherit the global properties, so the defaults apply to them too.
+const CONFIG_DEFAULTS: [(&str, ConfigDefault); 16] = [
+  ("port", ConfigDefault::Integer(80)),
+  ("sport", ConfigDefault::Integer(443)),
+  ("enableHTTP2", ConfigDefault::Boolean(false)),
+  ("enableIPSpoofing", ConfigDefault::Boolean(false)),
+  ("enableAutomaticTLS", ConfigDefault::Boolean(false)),
+  ("disableNonEncryptedServer", ConfigDefault::Boolean(false)),
+  ("logNoiseRequests", ConfigDefault::Boolean(true)),
+  ("tempFilesSync", ConfigDefault::Boolean(false)),
+  ("autoBanWindow", ConfigDefault::Integer(60)),
+  ("autoBanDuration", ConfigDefault::Integer(600)),
+  ("enableCompression", ConfigDefault::Boolean(true)),
+  ("enableDirectoryListing", ConfigDefault::Boolean(false)),
+  ("enableETag", ConfigDefault::Boolean(true)),
+  ("enableFileReadCoalescing", ConfigDefault::Boolean(false)),
+  ("disableToHTTPSRedirect", ConfigDefault::Boolean(false)),
+  ("logAccessDecisions", ConfigDefault::Boolean(false)),
+];
+
+#[derive(Clone, Copy)]
+enum ConfigDefault {
+  Boolean(bool),
+  Integer(i64),
+}
+
+impl ConfigDefault {
+  fn to_json(self) -> Value {
+    match self {
+      ConfigDefault::Boolean(value) => Value::from(value),
+      ConfigDefault::Integer(value) => Value::from(value),
+    }
+  }
+}
+
+// The configuration scopes, from the least specific one
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConfigScope {
+  Global,
+  Host,
+  Location,
+}
+
+impl ConfigScope {
+  fn as_str(&self) -> &'static str {
+    match self {
+      ConfigScope::Global => "global",
+      ConfigScope::Host => "host",
+      ConfigScope::Location => "location",
+    }
+  }
+}
+
+// Set the configuration used by the requests. It's set when the server starts and after the configuration reload.
+pub fn set_active_config(yaml_config: Arc<Yaml>) {
+  if let Ok(mut active_config) = ACTIVE_CONFIG.write() {
+    *active_config = Some(yaml_config);
+  }
+}
+
+// Export the configuration used by the requests in the JSON format, or null if the server hasn't started yet
+pub fn active_config_json() -> Value {
+  let active_config = match ACTIVE_CONFIG.read() {
+    Ok(active_config) => active_config.clone(),
+    Err(_) => None,
+  };
+  match active_config {
+    Some(yaml_config) => export_config(&yaml_config),
+    None => Value::Null,
+  }
+}
+
+// Convert the YAML value into the JSON value. The YAML mapping keys, which aren't strings, are converted into strings.
+fn yaml_to_json(yaml: &Yaml) -> Value {
+  match yaml {
+    Yaml::String(value) => Value::from(value.as_str()),
+    Yaml::Integer(value) => Value::from(*value),
+    Yaml::Real(value) => value
+      .parse::<f64>()
+      .ok()
+      .and_then(serde_json::Number::from_f64)
+      .map_or(Value::Null, Value::Number),
+    Yaml::Boolean(value) => Value::from(*value),
+    Yaml::Array(array) => Value::Array(array.iter().map(yaml_to_json).collect()),
+    Yaml::Hash(hash) => Value::Object(
+      hash
+        .iter()
+        .map(|(key, value)| {
+          let key = match key {
+            Yaml::String(key) | Yaml::Real(key) => key.clone(),
+            Yaml::Integer(key) => key.to_string(),
+            Yaml::Boolean(key) => key.to_string(),
+            _ => String::new(),
+          };
+          (key, yaml_to_json(value))
+        })
+        .collect(),
+    ),
+    Yaml::Null | Yaml::BadValue | Yaml::Alias(_) => Value::Null,
+  }
+}
+
+// Export the effective configuration of the scope, with the provenance of each property. The scopes are listed
+// from the least specific one, along with their own properties. The arrays and the mappings set in more than one scope
+// are merged, so all the scopes they were merged from are listed.
+fn export_scope_config(config: &ServerConfigRoot, scopes: &[(ConfigScope, &Hash)]) -> Value {
+  let mut exported_config = Map::new();
+  for (key, value) in config.as_hash().iter() {
+    // The locations are exported separately
+    if key == "locations" {
+      continue;
+    }
+    let yaml_key = Yaml::String(key.clone());
+    let defining_scopes = scopes
+      .iter()
+      .filter(|(_, hash)| hash.contains_key(&yaml_key))
+      .map(|(scope, hash)| (*scope, &hash[&yaml_key]))
+      .collect::<Vec<_>>();
+    let mut exported_property = Map::new();
+    exported_property.insert(String::from("value"), yaml_to_json(value));
+    if let Some((scope, _)) = defining_scopes.last() {
+      exported_property.insert(String::from("source"), Value::from(scope.as_str()));
+    }
+
+    // The values merged from more than one scope have the same type as the most specific value
+    let merged_scopes = defining_scopes
+      .iter()
+      .rev()
+      .take_while(|(_, scope_value)| match value {
+        Yaml::Array(_) => scope_value.as_vec().is_some(),
+        Yaml::Hash(_) => scope_value.as_hash().is_some(),
+        _ => false,
+      })
+      .map(|(scope, _)| scope.as_str())
+      .collect::<Vec<_>>();
+    if merged_scopes.len() > 1 {
+      exported_property.insert(
+        String::from("mergedFrom"),
+        Value::from(merged_scopes.into_iter().rev().collect::<Vec<_>>()),
+      );
+    }
+    exported_config.insert(key.clone(), Value::Object(exported_property));
+  }
+
+  for (key, default_value) in CONFIG_DEFAULTS.iter() {
+    if !exported_config.contains_key(*key) {
+      exported_config.insert(
+        key.to_string(),
+        json!({ "value": default_value.to_json(), "source": "default" }),
+      );
+    }
+  }
+  Value::Object(exported_config)
+}
+
+// Export the validated configuration in the normalized JSON format for the external tooling. The effective configuration
+// of the global scope, each host and each location is exported with the default values applied, and each property
+// is annotated with the scope it comes from ("default", "global", "host", or "location"). The secrets are redacted.
+pub fn export_config(yaml_config: &Yaml) -> Value {
+  let yaml_config = redact_config(yaml_config);
+  let empty_hash = Hash::new();
+  let global_hash = yaml_config["global"].as_hash().unwrap_or(&empty_hash);
+  let global_config_root = ServerConfigRoot::new(&yaml_config["global"]);
+
+  let mut hosts = Vec::new();
+  if let Some(host_yamls) = yaml_config["hosts"].as_vec() {
+    for host in host_yamls.iter() {
+      let host_hash = match host.as_hash() {
+        Some(host_hash) => host_hash,
+        None => continue,
+      };
+
+      let mut locations = Vec::new();
+      if let Some(location_yamls) = host["locations"].as_vec() {
+        for location in location_yamls.iter() {
+          if let Some(location_hash) = location.as_hash() {
+            locations.push(json!({
+              "path": yaml_to_json(&location["path"]),
+              "pathRegex": yaml_to_json(&location["pathRegex"]),
+              "config": export_scope_config(
+                &combine_scope_config(&global_config_root, host_hash, Some(location_hash)),
+                &[
+                  (ConfigScope::Global, global_hash),
+                  (ConfigScope::Host, host_hash),
+                  (ConfigScope::Location, location_hash),
+                ],
+              ),
+            }));
+          }
+        }
+      }
+
+      hosts.push(json!({
+        "domain": yaml_to_json(&host["domain"]),
+        "ip": yaml_to_json(&host["ip"]),
+        "config": export_scope_config(
+          &combine_scope_config(&global_config_root, host_hash, None),
+          &[(ConfigScope::Global, global_hash), (ConfigScope::Host, host_hash)],
+        ),
+        "locations": locations,
+      }));
+    }
+  }
+
+  json!({
+    "global": {
+      "config": export_scope_config(
+        &global_config_root,
+        &[(ConfigScope::Global, global_hash)],
+      ),
+    },
+    "hosts": hosts,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_export_config() {
+    let yaml_config = yaml_rust2::YamlLoader::load_from_str(
+      r#"
+      global:
+        wwwroot: /var/www
+        enableCompression: false
+        cacheVaryHeaders:
+          - Accept-Encoding
+        adminApi:
+          listen: 127.0.0.1:8081
+          token: secret
+      hosts:
+        - domain: example.com
+          wwwroot: /var/www/example
+          cacheVaryHeaders:
+            - Accept-Language
+          locations:
+            - path: /api
+              proxyTo: http://localhost:3000
+      "#,
+    )
+    .unwrap()
+    .remove(0);
+    let exported_config = export_config(&yaml_config);
+
+    let global_config = &exported_config["global"]["config"];
+    assert_eq!(
+      global_config["wwwroot"],
+      json!({ "value": "/var/www", "source": "global" })
+    );
+    assert_eq!(
+      global_config["enableCompression"],
+      json!({ "value": false, "source": "global" })
+    );
+    assert_eq!(
+      global_config["enableETag"],
+      json!({ "value": true, "source": "default" })
+    );
+    assert_eq!(
+      global_config["port"],
+      json!({ "value": 80, "source": "default" })
+    );
+    assert_eq!(global_config["adminApi"]["value"]["token"], "[REDACTED]");
+
+    let host = &exported_config["hosts"][0];
+    assert_eq!(host["domain"], "example.com");
+    assert_eq!(
+      host["config"]["wwwroot"],
+      json!({ "value": "/var/www/example", "source": "host" })
+    );
+    assert_eq!(
+      host["config"]["cacheVaryHeaders"],
+      json!({
+        "value": ["Accept-Encoding", "Accept-Language"],
+        "source": "host",
+        "mergedFrom": ["global", "host"],
+      })
+    );
+    assert!(host["config"]["locations"].is_null());
+
+    let location = &host["locations"][0];
+    assert_eq!(location["path"], "/api");
+    assert_eq!(
+      location["config"]["proxyTo"],
+      json!({ "value": "http://localhost:3000", "source": "location" })
+    );
+    assert_eq!(location["config"]["wwwroot"]["source"], "host");
+    assert_eq!(location["config"]["enableCompression"]["source"], "global");
+  }
+}
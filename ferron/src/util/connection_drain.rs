@@ -0,0 +1,165 @@
+use std::future::Future;
+use std::time::Duration;
+
+use ferron_common::ServerConfigRoot;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+// The default time (in milliseconds) given to the connections to close when the server shuts down
+const DEFAULT_DRAIN_TIMEOUT: u64 = 10000;
+
+// The connections served by the server, which are drained when the server shuts down.
+// Only the connections are tracked, so the server's background tasks (for example, the admin API,
+// the cache prewarming, or the ban refreshing) don't delay the shutdown.
+#[derive(Clone, Default)]
+pub struct ConnectionDrain {
+  shutdown_token: CancellationToken,
+  connections: TaskTracker,
+}
+
+impl ConnectionDrain {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Spawn a task serving a connection
+  pub fn spawn<F>(&self, connection_future: F)
+  where
+    F: Future<Output = ()> + Send + 'static,
+  {
+    self.connections.spawn(connection_future);
+  }
+
+  // Wait until the server starts shutting down
+  pub async fn cancelled(&self) {
+    self.shutdown_token.cancelled().await
+  }
+
+  // Ask the connections to close, and wait until they're closed or the drain timeout elapses.
+  // Returns false if the drain timeout elapsed before all the connections were closed.
+  pub async fn drain(&self, drain_timeout: Option<Duration>) -> bool {
+    self.shutdown_token.cancel();
+    self.connections.close();
+    match drain_timeout {
+      Some(drain_timeout) => time::timeout(drain_timeout, self.connections.wait())
+        .await
+        .is_ok(),
+      None => {
+        self.connections.wait().await;
+        true
+      }
+    }
+  }
+
+  // Drain the connections, and then wait until the server event loop finishes. The event loop stops accepting
+  // the connections when the server starts shutting down, and runs the modules' shutdown hooks when it finishes,
+  // so the hooks run after the last requests are served. Returns false as the first value if the drain timeout elapsed.
+  pub async fn drain_and_finish<F: Future>(
+    &self,
+    drain_timeout: Option<Duration>,
+    event_loop: F,
+  ) -> (bool, F::Output) {
+    let drained = self.drain(drain_timeout).await;
+    (drained, event_loop.await)
+  }
+
+  // The number of the connections still being served
+  pub fn connection_count(&self) -> usize {
+    self.connections.len()
+  }
+}
+
+// Obtain the drain timeout from the global configuration. The timeout is 10 seconds if it isn't set,
+// and the server waits for all the connections to close if it's set to null.
+pub fn drain_timeout(global_config_root: &ServerConfigRoot) -> Option<Duration> {
+  let drain_timeout_yaml = global_config_root.get("drainTimeout");
+  if drain_timeout_yaml.is_null() {
+    None
+  } else {
+    Some(Duration::from_millis(
+      drain_timeout_yaml
+        .as_i64()
+        .map(|drain_timeout| drain_timeout as u64)
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  #[test]
+  fn test_drain_timeout() {
+    let load_config =
+      |config: &str| ServerConfigRoot::new(&YamlLoader::load_from_str(config).unwrap().remove(0));
+    assert_eq!(
+      drain_timeout(&load_config("port: 80")),
+      Some(Duration::from_secs(10))
+    );
+    assert_eq!(
+      drain_timeout(&load_config("drainTimeout: 500")),
+      Some(Duration::from_millis(500))
+    );
+    assert_eq!(drain_timeout(&load_config("drainTimeout: null")), None);
+  }
+
+  #[tokio::test]
+  async fn test_idle_server_drains_immediately() {
+    // The background tasks, which never end, aren't connections
+    tokio::spawn(std::future::pending::<()>());
+    let connection_drain = ConnectionDrain::new();
+    let drain_start = time::Instant::now();
+    assert!(connection_drain.drain(Some(Duration::from_secs(10))).await);
+    assert!(drain_start.elapsed() < Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn test_drain_waits_for_connections() {
+    let connection_drain = ConnectionDrain::new();
+    let connection_drain_clone = connection_drain.clone();
+    connection_drain.spawn(async move {
+      // The connection finishes its last response after the server starts shutting down
+      connection_drain_clone.cancelled().await;
+      time::sleep(Duration::from_millis(50)).await;
+    });
+    assert_eq!(connection_drain.connection_count(), 1);
+    assert!(connection_drain.drain(Some(Duration::from_secs(10))).await);
+    assert_eq!(connection_drain.connection_count(), 0);
+  }
+
+  #[tokio::test]
+  async fn test_event_loop_finishes_after_drain() {
+    let connection_drain = ConnectionDrain::new();
+    let connection_drain_clone = connection_drain.clone();
+    connection_drain.spawn(async move {
+      connection_drain_clone.cancelled().await;
+      time::sleep(Duration::from_millis(50)).await;
+    });
+    let event_loop_drain = connection_drain.clone();
+    let event_loop = async move {
+      // The accept loops end when the server starts shutting down, and the shutdown hooks run afterwards
+      event_loop_drain.cancelled().await;
+      event_loop_drain.connection_count()
+    };
+    let (drained, connections_at_shutdown_hooks) = connection_drain
+      .drain_and_finish(Some(Duration::from_secs(10)), event_loop)
+      .await;
+    assert!(drained);
+    assert_eq!(connections_at_shutdown_hooks, 0);
+  }
+
+  #[tokio::test]
+  async fn test_drain_timeout_elapses() {
+    let connection_drain = ConnectionDrain::new();
+    connection_drain.spawn(std::future::pending::<()>());
+    assert!(
+      !connection_drain
+        .drain(Some(Duration::from_millis(50)))
+        .await
+    );
+    assert_eq!(connection_drain.connection_count(), 1);
+  }
+}
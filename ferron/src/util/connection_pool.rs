@@ -0,0 +1,246 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The minimum interval, at which the expired idle connections are closed
+const MIN_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// The limits of the keep-alive connections kept in the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionPoolConfig {
+  // The maximum number of the idle connections kept for each upstream
+  pub max_idle_connections: usize,
+  // The time after which an idle connection is closed. None keeps the idle connections open indefinitely.
+  pub idle_timeout: Option<Duration>,
+  // The maximum number of the requests sent over a connection. None doesn't limit the requests.
+  pub max_requests: Option<u64>,
+}
+
+struct IdleConnection<T> {
+  connection: T,
+  requests: u64,
+  idle_since: Instant,
+}
+
+// A pool of the idle keep-alive connections to the upstreams, keyed by the upstream
+pub struct ConnectionPool<T> {
+  config: ConnectionPoolConfig,
+  idle: Mutex<HashMap<String, VecDeque<IdleConnection<T>>>>,
+}
+
+impl<T> ConnectionPool<T> {
+  pub fn new(config: ConnectionPoolConfig) -> Self {
+    Self {
+      config,
+      idle: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // Take an idle connection to the upstream, along with the number of the requests already sent over it.
+  // The most recently used connection is taken first, so that the rarely used connections time out.
+  // The connections, for which the "is_usable" function returns false, are closed.
+  pub fn take(&self, key: &str, is_usable: impl Fn(&T) -> bool) -> Option<(T, u64)> {
+    let mut idle = self.idle.lock().ok()?;
+    self.remove_expired(&mut idle);
+    let idle_connections = idle.get_mut(key)?;
+    let mut taken_connection = None;
+    while let Some(idle_connection) = idle_connections.pop_back() {
+      if is_usable(&idle_connection.connection) {
+        taken_connection = Some((idle_connection.connection, idle_connection.requests));
+        break;
+      }
+    }
+    if idle_connections.is_empty() {
+      idle.remove(key);
+    }
+    taken_connection
+  }
+
+  // Return the connection, over which the specified number of the requests were sent, to the pool.
+  // The connection is closed instead, if it has reached the maximum number of the requests.
+  // If there are too many idle connections to the upstream, the least recently used one is closed.
+  pub fn put(&self, key: String, connection: T, requests: u64) {
+    if self.config.max_idle_connections == 0
+      || self
+        .config
+        .max_requests
+        .is_some_and(|max_requests| requests >= max_requests)
+    {
+      return;
+    }
+    if let Ok(mut idle) = self.idle.lock() {
+      self.remove_expired(&mut idle);
+      let idle_connections = idle.entry(key).or_default();
+      while idle_connections.len() >= self.config.max_idle_connections {
+        idle_connections.pop_front();
+      }
+      idle_connections.push_back(IdleConnection {
+        connection,
+        requests,
+        idle_since: Instant::now(),
+      });
+    }
+  }
+
+  // The interval, at which the expired idle connections have to be closed with "close_expired".
+  // Returns None if the idle connections don't expire.
+  pub fn expiry_check_interval(&self) -> Option<Duration> {
+    self
+      .config
+      .idle_timeout
+      .map(|idle_timeout| (idle_timeout / 2).max(MIN_EXPIRY_CHECK_INTERVAL))
+  }
+
+  // Close the connections, which have been idle for longer than the idle timeout. Called periodically,
+  // so that the idle connections are closed even if no more requests are sent to their upstreams.
+  pub fn close_expired(&self) {
+    if let Ok(mut idle) = self.idle.lock() {
+      self.remove_expired(&mut idle);
+    }
+  }
+
+  // Close the connections, which have been idle for longer than the idle timeout
+  fn remove_expired(&self, idle: &mut HashMap<String, VecDeque<IdleConnection<T>>>) {
+    if let Some(idle_timeout) = self.config.idle_timeout {
+      idle.retain(|_, idle_connections| {
+        // The connections are ordered from the least recently used, so the expired ones are at the front
+        while idle_connections
+          .front()
+          .is_some_and(|idle_connection| idle_connection.idle_since.elapsed() >= idle_timeout)
+        {
+          idle_connections.pop_front();
+        }
+        !idle_connections.is_empty()
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  impl<T> ConnectionPool<T> {
+    fn idle_count(&self, key: &str) -> usize {
+      self
+        .idle
+        .lock()
+        .unwrap()
+        .get(key)
+        .map_or(0, |idle_connections| idle_connections.len())
+    }
+  }
+
+  fn create_pool(
+    max_idle_connections: usize,
+    idle_timeout: Option<Duration>,
+    max_requests: Option<u64>,
+  ) -> ConnectionPool<u32> {
+    ConnectionPool::new(ConnectionPoolConfig {
+      max_idle_connections,
+      idle_timeout,
+      max_requests,
+    })
+  }
+
+  #[test]
+  fn test_take_most_recently_used() {
+    let pool = create_pool(8, None, None);
+    assert_eq!(pool.take("backend", |_| true), None);
+    pool.put(String::from("backend"), 1, 1);
+    pool.put(String::from("backend"), 2, 5);
+    pool.put(String::from("other-backend"), 3, 1);
+    assert_eq!(pool.idle_count("backend"), 2);
+    assert_eq!(pool.take("backend", |_| true), Some((2, 5)));
+    assert_eq!(pool.take("backend", |_| true), Some((1, 1)));
+    assert_eq!(pool.take("backend", |_| true), None);
+    assert_eq!(pool.idle_count("other-backend"), 1);
+  }
+
+  #[test]
+  fn test_unusable_connections_closed() {
+    let pool = create_pool(8, None, None);
+    pool.put(String::from("backend"), 1, 1);
+    pool.put(String::from("backend"), 2, 1);
+    pool.put(String::from("backend"), 3, 1);
+    assert_eq!(
+      pool.take("backend", |connection| *connection == 1),
+      Some((1, 1))
+    );
+    assert_eq!(pool.idle_count("backend"), 0);
+  }
+
+  #[test]
+  fn test_max_idle_connections() {
+    let pool = create_pool(2, None, None);
+    pool.put(String::from("backend"), 1, 1);
+    pool.put(String::from("backend"), 2, 1);
+    pool.put(String::from("backend"), 3, 1);
+    assert_eq!(pool.idle_count("backend"), 2);
+    assert_eq!(pool.take("backend", |_| true), Some((3, 1)));
+    assert_eq!(pool.take("backend", |_| true), Some((2, 1)));
+
+    let pool = create_pool(0, None, None);
+    pool.put(String::from("backend"), 1, 1);
+    assert_eq!(pool.idle_count("backend"), 0);
+  }
+
+  #[test]
+  fn test_max_requests() {
+    let pool = create_pool(8, None, Some(3));
+    pool.put(String::from("backend"), 1, 2);
+    pool.put(String::from("backend"), 2, 3);
+    assert_eq!(pool.take("backend", |_| true), Some((1, 2)));
+    assert_eq!(pool.take("backend", |_| true), None);
+  }
+
+  #[test]
+  fn test_idle_timeout() {
+    let pool = create_pool(8, Some(Duration::from_millis(50)), None);
+    pool.put(String::from("backend"), 1, 1);
+    std::thread::sleep(Duration::from_millis(100));
+    pool.put(String::from("backend"), 2, 1);
+    assert_eq!(pool.idle_count("backend"), 1);
+    assert_eq!(pool.take("backend", |_| true), Some((2, 1)));
+  }
+
+  #[tokio::test]
+  async fn test_expired_connections_closed() {
+    use http_body_util::Empty;
+    use hyper::body::Bytes;
+    use hyper_util::rt::TokioIo;
+
+    let pool = ConnectionPool::new(ConnectionPoolConfig {
+      max_idle_connections: 8,
+      idle_timeout: Some(Duration::from_millis(50)),
+      max_requests: None,
+    });
+    assert_eq!(
+      pool.expiry_check_interval(),
+      Some(MIN_EXPIRY_CHECK_INTERVAL)
+    );
+    let (client_io, _server_io) = tokio::io::duplex(1024);
+    let (sender, connection) =
+      hyper::client::conn::http1::handshake::<_, Empty<Bytes>>(TokioIo::new(client_io))
+        .await
+        .unwrap();
+    let connection_task = tokio::spawn(connection);
+    pool.put(String::from("backend"), sender, 1);
+
+    // The connection isn't closed until it expires
+    pool.close_expired();
+    assert_eq!(pool.idle_count("backend"), 1);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The expired connection is closed without the pool being used for the upstream, and its task finishes
+    pool.close_expired();
+    assert_eq!(pool.idle_count("backend"), 0);
+    tokio::time::timeout(Duration::from_secs(5), connection_task)
+      .await
+      .unwrap()
+      .unwrap()
+      .unwrap();
+
+    assert_eq!(create_pool(8, None, None).expiry_check_interval(), None);
+  }
+}
@@ -0,0 +1,89 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ferron_common::RequestByteCounters;
+use hyper::body::{Body, Buf, Frame, SizeHint};
+
+// The direction of the body counted by the wrapper
+#[derive(Clone, Copy)]
+pub enum CountedDirection {
+  Received,
+  Sent,
+}
+
+// A body wrapper, which adds the length of the data frames to the request's byte counters
+pub struct CountingBody<B> {
+  inner: B,
+  counters: RequestByteCounters,
+  direction: CountedDirection,
+}
+
+impl<B> CountingBody<B> {
+  pub fn new(inner: B, counters: RequestByteCounters, direction: CountedDirection) -> Self {
+    Self {
+      inner,
+      counters,
+      direction,
+    }
+  }
+}
+
+impl<B: Body + Unpin> Body for CountingBody<B> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    let poll = Pin::new(&mut this.inner).poll_frame(cx);
+    if let Poll::Ready(Some(Ok(frame))) = &poll {
+      if let Some(data) = frame.data_ref() {
+        let length = data.remaining() as u64;
+        match this.direction {
+          CountedDirection::Received => this.counters.add_bytes_received(length),
+          CountedDirection::Sent => this.counters.add_bytes_sent(length),
+        }
+      }
+    }
+    poll
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, Full};
+  use hyper::body::Bytes;
+
+  #[tokio::test]
+  async fn test_counting_body() {
+    let counters = RequestByteCounters::new();
+    let request_body = CountingBody::new(
+      Full::new(Bytes::from("request")),
+      counters.clone(),
+      CountedDirection::Received,
+    );
+    let response_body = CountingBody::new(
+      Full::new(Bytes::from("response body")),
+      counters.clone(),
+      CountedDirection::Sent,
+    );
+    assert_eq!(response_body.size_hint().exact(), Some(13));
+
+    request_body.collect().await.unwrap();
+    assert_eq!(counters.bytes_received(), 7);
+    assert_eq!(counters.bytes_sent(), 0);
+    response_body.collect().await.unwrap();
+    assert_eq!(counters.bytes_sent(), 13);
+  }
+}
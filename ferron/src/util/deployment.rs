@@ -0,0 +1,549 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use async_compression::tokio::bufread::GzipDecoder;
+use chrono::Utc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Mutex;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::blocking_budget::spawn_blocking_budgeted;
+
+// The default maximum size of the uploaded archive (100 MiB)
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 104_857_600;
+
+// The default number of the releases kept for the rollbacks
+const DEFAULT_KEEP_RELEASES: usize = 5;
+
+// The maximum ratio of the unpacked content size to the uploaded archive size, which protects against the archive bombs
+const MAX_UNPACKED_SIZE_RATIO: u64 = 20;
+
+// The size of a tar block
+const TAR_BLOCK_SIZE: usize = 512;
+
+// The prefix of the directories, into which the archives are unpacked before they become releases
+const STAGING_PREFIX: &str = ".staging-";
+
+// The deployments are serialized, so that the concurrent uploads don't race for the symlink
+static DEPLOYMENT_LOCK: Mutex<()> = Mutex::const_new(());
+
+// A deployment target, whose content is replaced by uploading an archive through the admin API.
+// The target path is a symbolic link (used as the webroot) to the current release in the releases directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeploymentTarget {
+  pub name: String,
+  pub path: PathBuf,
+  pub releases_directory: PathBuf,
+  pub keep_releases: usize,
+  pub max_upload_size: u64,
+}
+
+impl DeploymentTarget {
+  // Parse the deployment target from the admin API configuration
+  pub fn from_yaml(target_yaml: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let name = match target_yaml["name"].as_str() {
+      Some(name)
+        if !name.is_empty()
+          && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') =>
+      {
+        name.to_string()
+      }
+      _ => Err(anyhow::anyhow!(
+        "Invalid deployment name. The name must consist of letters, digits, hyphens and underscores"
+      ))?,
+    };
+    let path = match target_yaml["path"].as_str() {
+      Some(path) if !path.is_empty() => PathBuf::from(path.trim_end_matches('/')),
+      _ => Err(anyhow::anyhow!(
+        "The path of the \"{}\" deployment isn't specified",
+        name
+      ))?,
+    };
+    let releases_directory = match &target_yaml["releasesDirectory"] {
+      Yaml::BadValue => {
+        let mut releases_directory = path.clone().into_os_string();
+        releases_directory.push(".releases");
+        PathBuf::from(releases_directory)
+      }
+      Yaml::String(releases_directory) if !releases_directory.is_empty() => {
+        PathBuf::from(releases_directory)
+      }
+      _ => Err(anyhow::anyhow!(
+        "Invalid releases directory of the \"{}\" deployment",
+        name
+      ))?,
+    };
+    let keep_releases = match &target_yaml["keepReleases"] {
+      Yaml::BadValue => DEFAULT_KEEP_RELEASES,
+      Yaml::Integer(keep_releases) if *keep_releases >= 1 => *keep_releases as usize,
+      _ => Err(anyhow::anyhow!(
+        "Invalid number of the kept releases of the \"{}\" deployment",
+        name
+      ))?,
+    };
+    let max_upload_size = match &target_yaml["maxUploadSize"] {
+      Yaml::BadValue => DEFAULT_MAX_UPLOAD_SIZE,
+      Yaml::Integer(max_upload_size) if *max_upload_size > 0 => *max_upload_size as u64,
+      _ => Err(anyhow::anyhow!(
+        "Invalid maximum upload size of the \"{}\" deployment",
+        name
+      ))?,
+    };
+
+    Ok(Self {
+      name,
+      path,
+      releases_directory,
+      keep_releases,
+      max_upload_size,
+    })
+  }
+}
+
+// Parse the deployment targets from the admin API configuration
+pub fn parse_deployment_targets(
+  admin_api_yaml: &Yaml,
+) -> Result<Vec<DeploymentTarget>, Box<dyn Error + Send + Sync>> {
+  let targets_yaml = match &admin_api_yaml["deployments"] {
+    Yaml::BadValue => return Ok(Vec::new()),
+    Yaml::Array(targets_yaml) => targets_yaml,
+    _ => Err(anyhow::anyhow!("Invalid deployments configuration"))?,
+  };
+  if cfg!(not(unix)) && !targets_yaml.is_empty() {
+    Err(anyhow::anyhow!(
+      "The deployments are supported only on Unix-like systems"
+    ))?
+  }
+  let mut names = HashSet::new();
+  let mut targets = Vec::new();
+  for target_yaml in targets_yaml {
+    let target = DeploymentTarget::from_yaml(target_yaml)?;
+    if !names.insert(target.name.clone()) {
+      Err(anyhow::anyhow!(
+        "Duplicate deployment name: {}",
+        target.name
+      ))?
+    }
+    targets.push(target);
+  }
+  Ok(targets)
+}
+
+// Parse the octal number from a tar header field
+fn parse_tar_octal(field: &[u8]) -> Option<u64> {
+  let field = field
+    .iter()
+    .take_while(|b| **b != 0)
+    .map(|b| *b as char)
+    .collect::<String>();
+  let field = field.trim();
+  if field.is_empty() {
+    return Some(0);
+  }
+  u64::from_str_radix(field, 8).ok()
+}
+
+// Parse the string from a NUL-terminated tar header field
+fn parse_tar_string(field: &[u8]) -> String {
+  let length = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+  String::from_utf8_lossy(&field[..length]).into_owned()
+}
+
+// Get the path from the "path" record of a PAX extended header
+fn parse_pax_path(data: &[u8]) -> Option<String> {
+  let mut data = data;
+  let mut path = None;
+  while !data.is_empty() {
+    let space_index = data.iter().position(|b| *b == b' ')?;
+    let record_length: usize = std::str::from_utf8(&data[..space_index])
+      .ok()?
+      .parse()
+      .ok()?;
+    if record_length <= space_index || record_length > data.len() {
+      return None;
+    }
+    let record = &data[space_index + 1..record_length];
+    let record = record.strip_suffix(b"\n").unwrap_or(record);
+    if let Some(value) = record.strip_prefix(b"path=") {
+      path = Some(String::from_utf8_lossy(value).into_owned());
+    }
+    data = &data[record_length..];
+  }
+  path
+}
+
+// Get the relative path of an archive entry. The absolute paths and the paths escaping the destination are rejected.
+fn sanitize_entry_path(entry_path: &str) -> Result<Option<PathBuf>, Box<dyn Error + Send + Sync>> {
+  let mut sanitized_path = PathBuf::new();
+  for component in Path::new(entry_path).components() {
+    match component {
+      Component::Normal(component) => sanitized_path.push(component),
+      Component::CurDir => (),
+      _ => Err(anyhow::anyhow!(
+        "The archive entry \"{}\" has an unsafe path",
+        entry_path
+      ))?,
+    }
+  }
+  match sanitized_path.as_os_str().is_empty() {
+    true => Ok(None),
+    false => Ok(Some(sanitized_path)),
+  }
+}
+
+// Unpack the tar archive into the destination directory. Only the regular files and the directories are allowed,
+// so the archive can't create links pointing outside the release. Returns the number of the unpacked files.
+pub fn unpack_tar(archive: &[u8], destination: &Path) -> Result<u64, Box<dyn Error + Send + Sync>> {
+  let mut offset = 0;
+  let mut files = 0;
+  let mut long_path: Option<String> = None;
+  while offset + TAR_BLOCK_SIZE <= archive.len() {
+    let header = &archive[offset..offset + TAR_BLOCK_SIZE];
+    offset += TAR_BLOCK_SIZE;
+    // The archive ends with the zero blocks
+    if header.iter().all(|b| *b == 0) {
+      break;
+    }
+
+    let checksum = parse_tar_octal(&header[148..156]).ok_or(anyhow::anyhow!(
+      "The archive has an invalid header checksum"
+    ))?;
+    let computed_checksum: u64 = header
+      .iter()
+      .enumerate()
+      .map(|(index, b)| match index {
+        148..156 => b' ' as u64,
+        _ => *b as u64,
+      })
+      .sum();
+    if checksum != computed_checksum {
+      Err(anyhow::anyhow!(
+        "The archive is corrupted or isn't a tar archive"
+      ))?
+    }
+
+    let size = parse_tar_octal(&header[124..136])
+      .ok_or(anyhow::anyhow!("The archive has an invalid entry size"))? as usize;
+    let data_end = offset
+      .checked_add(size)
+      .filter(|data_end| *data_end <= archive.len())
+      .ok_or(anyhow::anyhow!("The archive is truncated"))?;
+    let data = &archive[offset..data_end];
+    offset += size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+
+    let entry_type = header[156];
+    match entry_type {
+      // The GNU long path and the PAX extended header apply to the next entry
+      b'L' => {
+        long_path = Some(parse_tar_string(data));
+        continue;
+      }
+      b'x' => {
+        long_path = parse_pax_path(data).or(long_path);
+        continue;
+      }
+      b'g' => continue,
+      _ => (),
+    }
+
+    let entry_path = match long_path.take() {
+      Some(long_path) => long_path,
+      None => {
+        let name = parse_tar_string(&header[0..100]);
+        let prefix = match &header[257..262] == b"ustar" {
+          true => parse_tar_string(&header[345..500]),
+          false => String::new(),
+        };
+        match prefix.is_empty() {
+          true => name,
+          false => format!("{}/{}", prefix, name),
+        }
+      }
+    };
+    let relative_path = match sanitize_entry_path(&entry_path)? {
+      Some(relative_path) => relative_path,
+      None => continue,
+    };
+    let path = destination.join(relative_path);
+
+    match entry_type {
+      b'0' | 0 | b'7' => {
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, data)?;
+        files += 1;
+      }
+      b'5' => fs::create_dir_all(&path)?,
+      _ => Err(anyhow::anyhow!(
+        "The archive entry \"{}\" isn't a regular file or a directory",
+        entry_path
+      ))?,
+    }
+  }
+  Ok(files)
+}
+
+// Decompress the archive, if it's compressed with gzip
+async fn decompress_archive(
+  archive: &[u8],
+  max_size: u64,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+  if !archive.starts_with(&[0x1f, 0x8b]) {
+    return Ok(archive.to_vec());
+  }
+  let mut decompressed_archive = Vec::new();
+  GzipDecoder::new(archive)
+    .take(max_size + 1)
+    .read_to_end(&mut decompressed_archive)
+    .await?;
+  if decompressed_archive.len() as u64 > max_size {
+    Err(anyhow::anyhow!("The unpacked archive is too large"))?
+  }
+  Ok(decompressed_archive)
+}
+
+// Get the releases of the deployment target, from the oldest to the newest
+pub fn releases(target: &DeploymentTarget) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+  let mut releases = Vec::new();
+  match fs::read_dir(&target.releases_directory) {
+    Ok(entries) => {
+      for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+          if let Some(release) = entry.file_name().to_str() {
+            if !release.starts_with('.') {
+              releases.push(release.to_string());
+            }
+          }
+        }
+      }
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+    Err(err) => Err(err)?,
+  }
+  releases.sort();
+  Ok(releases)
+}
+
+// Get the release the deployment target currently points to
+pub fn current_release(target: &DeploymentTarget) -> Option<String> {
+  fs::read_link(&target.path)
+    .ok()?
+    .file_name()?
+    .to_str()
+    .map(String::from)
+}
+
+// Atomically point the deployment target to the release, by renaming a new symbolic link over the old one
+#[cfg(unix)]
+fn switch_release(
+  target: &DeploymentTarget,
+  release: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  if fs::symlink_metadata(&target.path).is_ok_and(|metadata| !metadata.file_type().is_symlink()) {
+    Err(anyhow::anyhow!(
+      "The deployment path \"{}\" exists and isn't a symbolic link",
+      target.path.display()
+    ))?
+  }
+  let release_path = fs::canonicalize(target.releases_directory.join(release))?;
+  let mut temporary_link_path = target.path.clone().into_os_string();
+  temporary_link_path.push(format!(".{}.tmp", release));
+  let temporary_link_path = PathBuf::from(temporary_link_path);
+  fs::remove_file(&temporary_link_path).unwrap_or_default();
+  std::os::unix::fs::symlink(release_path, &temporary_link_path)?;
+  if let Err(err) = fs::rename(&temporary_link_path, &target.path) {
+    fs::remove_file(&temporary_link_path).unwrap_or_default();
+    Err(err)?
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn switch_release(
+  _target: &DeploymentTarget,
+  _release: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  Err(anyhow::anyhow!(
+    "The deployments are supported only on Unix-like systems"
+  ))?
+}
+
+// Remove the oldest releases, keeping the configured number of releases and the current release
+fn prune_releases(target: &DeploymentTarget) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let current_release = current_release(target);
+  let releases = releases(target)?;
+  let prunable_count = releases.len().saturating_sub(target.keep_releases);
+  for release in releases.iter().take(prunable_count) {
+    if Some(release) != current_release.as_ref() {
+      fs::remove_dir_all(target.releases_directory.join(release))?;
+    }
+  }
+  Ok(())
+}
+
+// Unpack the uploaded archive (a tar archive, optionally compressed with gzip) into a new release,
+// and switch the deployment target to it. Returns the name of the new release and the number of the unpacked files.
+pub async fn deploy(
+  target: &DeploymentTarget,
+  archive: &[u8],
+) -> Result<(String, u64), Box<dyn Error + Send + Sync>> {
+  let _deployment_guard = DEPLOYMENT_LOCK.lock().await;
+  let archive = decompress_archive(
+    archive,
+    target
+      .max_upload_size
+      .saturating_mul(MAX_UNPACKED_SIZE_RATIO),
+  )
+  .await?;
+
+  let release = Utc::now().format("%Y%m%d%H%M%S%3f").to_string();
+  let target = target.clone();
+  let deploy_release = release.clone();
+  let files = spawn_blocking_budgeted("deployment", move || {
+    fs::create_dir_all(&target.releases_directory)?;
+    let release_path = target.releases_directory.join(&deploy_release);
+    if release_path.exists() {
+      Err(anyhow::anyhow!(
+        "The release {} already exists",
+        deploy_release
+      ))?
+    }
+
+    // The archive is unpacked into a staging directory, so a failed upload never becomes a release
+    let staging_path = target
+      .releases_directory
+      .join(format!("{}{}", STAGING_PREFIX, deploy_release));
+    let files = match unpack_tar(&archive, &staging_path) {
+      Ok(files) => files,
+      Err(err) => {
+        fs::remove_dir_all(&staging_path).unwrap_or_default();
+        Err(err)?
+      }
+    };
+    if files == 0 {
+      fs::remove_dir_all(&staging_path).unwrap_or_default();
+      Err(anyhow::anyhow!("The archive doesn't contain any files"))?
+    }
+    fs::rename(&staging_path, &release_path)?;
+    switch_release(&target, &deploy_release)?;
+    prune_releases(&target)?;
+    Ok::<_, Box<dyn Error + Send + Sync>>(files)
+  })
+  .await??;
+  Ok((release, files))
+}
+
+// Switch the deployment target back to the specified release, or to the release preceding the current one.
+// Returns the name of the release the deployment target was switched to.
+pub async fn rollback(
+  target: &DeploymentTarget,
+  release: Option<&str>,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+  let _deployment_guard = DEPLOYMENT_LOCK.lock().await;
+  let target = target.clone();
+  let release = release.map(String::from);
+  spawn_blocking_budgeted("deployment", move || {
+    let releases = releases(&target)?;
+    let release = match release {
+      Some(release) if releases.contains(&release) => release,
+      Some(release) => Err(anyhow::anyhow!("The release {} doesn't exist", release))?,
+      None => {
+        let current_index = current_release(&target)
+          .and_then(|current_release| releases.iter().position(|r| *r == current_release))
+          .ok_or(anyhow::anyhow!(
+            "The deployment doesn't point to any known release"
+          ))?;
+        match current_index.checked_sub(1) {
+          Some(previous_index) => releases[previous_index].clone(),
+          None => Err(anyhow::anyhow!(
+            "There is no previous release to roll back to"
+          ))?,
+        }
+      }
+    };
+    switch_release(&target, &release)?;
+    Ok(release)
+  })
+  .await?
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  // Build a tar entry with the ustar header
+  fn tar_entry(path: &str, entry_type: u8, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    header[..path.len()].copy_from_slice(path.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = entry_type;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|b| *b as u64).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    entry.resize(entry.len().div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE, 0);
+    entry
+  }
+
+  #[test]
+  fn test_deployment_targets() {
+    let config = YamlLoader::load_from_str(
+      "deployments:\n  - name: site\n    path: /var/www/site/\n    keepReleases: 3\n",
+    )
+    .unwrap()
+    .remove(0);
+    if cfg!(unix) {
+      let targets = parse_deployment_targets(&config).unwrap();
+      assert_eq!(targets[0].path, PathBuf::from("/var/www/site"));
+      assert_eq!(
+        targets[0].releases_directory,
+        PathBuf::from("/var/www/site.releases")
+      );
+      assert_eq!(targets[0].keep_releases, 3);
+      assert_eq!(targets[0].max_upload_size, DEFAULT_MAX_UPLOAD_SIZE);
+    }
+
+    let config =
+      YamlLoader::load_from_str("deployments:\n  - name: ../site\n    path: /var/www/site\n")
+        .unwrap()
+        .remove(0);
+    assert!(parse_deployment_targets(&config).is_err());
+  }
+
+  #[test]
+  fn test_unpack_tar() {
+    let destination =
+      std::env::temp_dir().join(format!("ferron-test-unpack-tar-{}", std::process::id()));
+    let mut archive = tar_entry("./", b'5', b"");
+    archive.extend(tar_entry("./index.html", b'0', b"<h1>Hello</h1>"));
+    archive.extend(tar_entry("assets/style.css", b'0', b"body {}"));
+    archive.extend([0u8; TAR_BLOCK_SIZE * 2]);
+    assert_eq!(unpack_tar(&archive, &destination).unwrap(), 2);
+    assert_eq!(
+      fs::read_to_string(destination.join("index.html")).unwrap(),
+      "<h1>Hello</h1>"
+    );
+    assert_eq!(
+      fs::read_to_string(destination.join("assets/style.css")).unwrap(),
+      "body {}"
+    );
+
+    assert!(unpack_tar(&tar_entry("../escape.html", b'0', b""), &destination).is_err());
+    assert!(unpack_tar(&tar_entry("/etc/passwd", b'0', b""), &destination).is_err());
+    assert!(unpack_tar(&tar_entry("link", b'2', b""), &destination).is_err());
+    let mut corrupted_archive = tar_entry("index.html", b'0', b"");
+    corrupted_archive[0] = b'x';
+    assert!(unpack_tar(&corrupted_archive, &destination).is_err());
+    fs::remove_dir_all(&destination).unwrap();
+  }
+}
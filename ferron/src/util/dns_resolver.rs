@@ -0,0 +1,695 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{header, Method, Request, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use rustls::RootCertStore;
+use rustls_native_certs::load_native_certs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::TlsConnector;
+use yaml_rust2::Yaml;
+
+// The default time to wait for a response from a DNS server
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The default time the non-existent names are cached for, in seconds
+const DEFAULT_NEGATIVE_CACHE_TTL: u64 = 30;
+
+// The maximum time the DNS records are cached for, regardless of their TTL, in seconds
+const MAX_CACHE_TTL: u32 = 86400;
+
+// The maximum number of cached DNS answers
+const MAX_CACHE_ENTRIES: usize = 10000;
+
+// The DNS resolver configured with the "resolver" property. If it's not configured, the OS resolver is used.
+static DNS_RESOLVER: RwLock<Option<Arc<DnsResolver>>> = RwLock::new(None);
+
+// The DNS record types queried by the resolver
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum RecordType {
+  A,
+  Aaaa,
+}
+
+impl RecordType {
+  fn code(self) -> u16 {
+    match self {
+      RecordType::A => 1,
+      RecordType::Aaaa => 28,
+    }
+  }
+}
+
+// An upstream DNS server
+pub enum DnsUpstream {
+  // A DNS server queried over UDP, falling back to TCP for truncated responses
+  Udp(SocketAddr),
+  // A DNS-over-TLS server (RFC 7858)
+  Tls(SocketAddr, ServerName<'static>),
+  // A DNS-over-HTTPS server (RFC 8484)
+  Https(Uri),
+}
+
+// Parse an IP address with an optional port, e.g. "192.0.2.1", "192.0.2.1:53", "2001:db8::1" or "[2001:db8::1]:53"
+fn parse_server_address(address: &str, default_port: u16) -> Option<SocketAddr> {
+  if let Ok(socket_addr) = address.parse::<SocketAddr>() {
+    return Some(socket_addr);
+  }
+  if let Ok(ip) = address.parse::<IpAddr>() {
+    return Some(SocketAddr::new(ip, default_port));
+  }
+  address
+    .strip_prefix('[')
+    .and_then(|address| address.strip_suffix(']'))
+    .and_then(|ip| ip.parse::<IpAddr>().ok())
+    .map(|ip| SocketAddr::new(ip, default_port))
+}
+
+// Parse an upstream DNS server. The supported forms are "192.0.2.1[:53]" (or "udp://192.0.2.1[:53]"),
+// "tls://192.0.2.1[:853][#dns.example.com]" and "https://dns.example.com/dns-query".
+pub fn parse_dns_upstream(server: &str) -> Result<DnsUpstream, anyhow::Error> {
+  if let Some(tls_server) = server.strip_prefix("tls://") {
+    let (address, name) = match tls_server.split_once('#') {
+      Some((address, name)) => (address, Some(name)),
+      None => (tls_server, None),
+    };
+    let socket_addr = parse_server_address(address, 853)
+      .ok_or_else(|| anyhow::anyhow!("Invalid DNS-over-TLS server address \"{}\"", address))?;
+    let server_name = match name {
+      Some(name) => ServerName::try_from(name.to_string())
+        .map_err(|_| anyhow::anyhow!("Invalid DNS-over-TLS server name \"{}\"", name))?,
+      None => ServerName::IpAddress(socket_addr.ip().into()),
+    };
+    Ok(DnsUpstream::Tls(socket_addr, server_name))
+  } else if server.starts_with("https://") {
+    let uri = server
+      .parse::<Uri>()
+      .map_err(|_| anyhow::anyhow!("Invalid DNS-over-HTTPS server URL \"{}\"", server))?;
+    if uri.host().is_none() {
+      Err(anyhow::anyhow!(
+        "The DNS-over-HTTPS server URL \"{}\" doesn't have a host",
+        server
+      ))?
+    }
+    Ok(DnsUpstream::Https(uri))
+  } else {
+    let address = server.strip_prefix("udp://").unwrap_or(server);
+    parse_server_address(address, 53)
+      .map(DnsUpstream::Udp)
+      .ok_or_else(|| anyhow::anyhow!("Invalid DNS server address \"{}\"", address))
+  }
+}
+
+// Build a DNS query message for a single question
+fn build_query(id: u16, name: &str, record_type: RecordType) -> io::Result<Vec<u8>> {
+  let mut query = Vec::with_capacity(name.len() + 18);
+  query.extend_from_slice(&id.to_be_bytes());
+  // Only the "recursion desired" flag is set
+  query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+  let name = name.strip_suffix('.').unwrap_or(name);
+  if name.is_empty() || name.len() > 253 {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "Invalid host name",
+    ));
+  }
+  for label in name.split('.') {
+    if label.is_empty() || label.len() > 63 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Invalid host name",
+      ));
+    }
+    query.push(label.len() as u8);
+    query.extend_from_slice(label.as_bytes());
+  }
+  query.push(0);
+  query.extend_from_slice(&record_type.code().to_be_bytes());
+  // The "IN" class
+  query.extend_from_slice(&[0x00, 0x01]);
+  Ok(query)
+}
+
+// A parsed DNS response
+#[derive(Debug, PartialEq)]
+struct DnsResponse {
+  // The response was truncated, so the query has to be retried over TCP
+  truncated: bool,
+  addresses: Vec<IpAddr>,
+  // The lowest TTL of the address records
+  ttl: Option<u32>,
+}
+
+fn read_u16(message: &[u8], position: usize) -> io::Result<u16> {
+  match message.get(position..position + 2) {
+    Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+    None => Err(malformed_response()),
+  }
+}
+
+fn malformed_response() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, "Malformed DNS response")
+}
+
+// Skip a (possibly compressed) domain name in a DNS message, returning the position after the name
+fn skip_name(message: &[u8], mut position: usize) -> io::Result<usize> {
+  loop {
+    let length = *message.get(position).ok_or_else(malformed_response)?;
+    match length {
+      0 => return Ok(position + 1),
+      length if length & 0xc0 == 0xc0 => return Ok(position + 2),
+      length if length & 0xc0 == 0 => position += length as usize + 1,
+      _ => return Err(malformed_response()),
+    }
+  }
+}
+
+// Parse a DNS response, extracting the addresses of the queried record type
+fn parse_response(message: &[u8], id: u16, record_type: RecordType) -> io::Result<DnsResponse> {
+  if message.len() < 12 || read_u16(message, 0)? != id {
+    return Err(malformed_response());
+  }
+  let flags = read_u16(message, 2)?;
+  if flags & 0x8000 == 0 {
+    return Err(malformed_response());
+  }
+  let truncated = flags & 0x0200 != 0;
+  let response_code = flags & 0x000f;
+  match response_code {
+    // No error and the non-existent domain
+    0 | 3 => (),
+    _ => {
+      return Err(io::Error::other(format!(
+        "The DNS server returned the error code {}",
+        response_code
+      )))
+    }
+  }
+
+  if truncated {
+    // The truncated answers can be incomplete, so they aren't parsed
+    return Ok(DnsResponse {
+      truncated,
+      addresses: Vec::new(),
+      ttl: None,
+    });
+  }
+
+  let question_count = read_u16(message, 4)?;
+  let answer_count = read_u16(message, 6)?;
+  let mut position = 12;
+  for _ in 0..question_count {
+    position = skip_name(message, position)? + 4;
+  }
+
+  let mut addresses = Vec::new();
+  let mut ttl: Option<u32> = None;
+  for _ in 0..answer_count {
+    position = skip_name(message, position)?;
+    let answer_type = read_u16(message, position)?;
+    let answer_class = read_u16(message, position + 2)?;
+    let answer_ttl =
+      (read_u16(message, position + 4)? as u32) << 16 | read_u16(message, position + 6)? as u32;
+    let data_length = read_u16(message, position + 8)? as usize;
+    position += 10;
+    let data = message
+      .get(position..position + data_length)
+      .ok_or_else(malformed_response)?;
+    position += data_length;
+
+    // The other records (for example CNAME records) are skipped, since the addresses of the aliased name follow them
+    if answer_class != 1 || answer_type != record_type.code() {
+      continue;
+    }
+    let address = match (record_type, data.len()) {
+      (RecordType::A, 4) => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+      (RecordType::Aaaa, 16) => {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(data);
+        IpAddr::V6(Ipv6Addr::from(octets))
+      }
+      _ => return Err(malformed_response()),
+    };
+    addresses.push(address);
+    ttl = Some(ttl.map_or(answer_ttl, |ttl| ttl.min(answer_ttl)));
+  }
+
+  Ok(DnsResponse {
+    truncated,
+    addresses,
+    ttl,
+  })
+}
+
+// Send a DNS query over a stream (TCP or TLS), where the messages are prefixed with their length
+async fn query_over_stream(
+  mut stream: impl AsyncRead + AsyncWrite + Unpin,
+  query: &[u8],
+) -> io::Result<Vec<u8>> {
+  let mut message = Vec::with_capacity(query.len() + 2);
+  message.extend_from_slice(&(query.len() as u16).to_be_bytes());
+  message.extend_from_slice(query);
+  stream.write_all(&message).await?;
+  stream.flush().await?;
+
+  let response_length = stream.read_u16().await?;
+  let mut response = vec![0u8; response_length as usize];
+  stream.read_exact(&mut response).await?;
+  Ok(response)
+}
+
+struct CacheEntry {
+  addresses: Vec<IpAddr>,
+  expires: Instant,
+}
+
+// A stub DNS resolver, which forwards the queries to the configured DNS servers and caches the answers
+pub struct DnsResolver {
+  upstreams: Vec<DnsUpstream>,
+  timeout: Duration,
+  negative_cache_ttl: Duration,
+  roots: Arc<RootCertStore>,
+  tls_connector: OnceLock<TlsConnector>,
+  cache: Mutex<HashMap<(String, RecordType), CacheEntry>>,
+}
+
+impl DnsResolver {
+  // Create a DNS resolver from the "resolver" configuration section
+  pub fn from_config(resolver_yaml: &Yaml) -> Result<Self, anyhow::Error> {
+    let mut upstreams = Vec::new();
+    if let Some(servers) = resolver_yaml["servers"].as_vec() {
+      for server in servers {
+        if let Some(server) = server.as_str() {
+          upstreams.push(parse_dns_upstream(server)?);
+        }
+      }
+    }
+    if upstreams.is_empty() {
+      Err(anyhow::anyhow!("No DNS servers are configured"))?
+    }
+
+    let mut roots = RootCertStore::empty();
+    if upstreams
+      .iter()
+      .any(|upstream| !matches!(upstream, DnsUpstream::Udp(_)))
+    {
+      for cert in load_native_certs().certs {
+        roots.add(cert)?;
+      }
+    }
+    Ok(Self {
+      upstreams,
+      timeout: resolver_yaml["timeout"]
+        .as_i64()
+        .map(|timeout| Duration::from_millis(timeout as u64))
+        .unwrap_or(DEFAULT_TIMEOUT),
+      negative_cache_ttl: Duration::from_secs(
+        resolver_yaml["negativeCacheTTL"]
+          .as_i64()
+          .map(|ttl| ttl as u64)
+          .unwrap_or(DEFAULT_NEGATIVE_CACHE_TTL),
+      ),
+      roots: Arc::new(roots),
+      tls_connector: OnceLock::new(),
+      cache: Mutex::new(HashMap::new()),
+    })
+  }
+
+  // The TLS connector is created when it's first used, since the process-wide cryptography provider
+  // isn't installed yet when the resolver is configured
+  fn tls_connector(&self) -> &TlsConnector {
+    self.tls_connector.get_or_init(|| {
+      TlsConnector::from(Arc::new(
+        rustls::ClientConfig::builder()
+          .with_root_certificates(self.roots.clone())
+          .with_no_client_auth(),
+      ))
+    })
+  }
+
+  // Resolve the host name into the IPv4 and IPv6 addresses
+  pub async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+      return Ok(vec![ip]);
+    }
+    if host.eq_ignore_ascii_case("localhost") {
+      return Ok(vec![
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+      ]);
+    }
+
+    let (ipv4_addresses, ipv6_addresses) = tokio::join!(
+      self.lookup_record(host, RecordType::A),
+      self.lookup_record(host, RecordType::Aaaa)
+    );
+    let addresses = match (ipv4_addresses, ipv6_addresses) {
+      (Err(err), Err(_)) => return Err(err),
+      (ipv4_addresses, ipv6_addresses) => ipv4_addresses
+        .unwrap_or_default()
+        .into_iter()
+        .chain(ipv6_addresses.unwrap_or_default())
+        .collect::<Vec<_>>(),
+    };
+    if addresses.is_empty() {
+      return Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No addresses found for \"{}\"", host),
+      ));
+    }
+    Ok(addresses)
+  }
+
+  async fn lookup_record(&self, host: &str, record_type: RecordType) -> io::Result<Vec<IpAddr>> {
+    let cache_key = (host.to_lowercase(), record_type);
+    if let Ok(cache) = self.cache.lock() {
+      if let Some(entry) = cache.get(&cache_key) {
+        if entry.expires > Instant::now() {
+          return Ok(entry.addresses.clone());
+        }
+      }
+    }
+
+    let mut last_error = None;
+    for upstream in &self.upstreams {
+      match tokio::time::timeout(self.timeout, self.query(upstream, host, record_type)).await {
+        Ok(Ok(response)) => {
+          // The names without the addresses are cached for the negative caching TTL
+          let ttl = match response.ttl {
+            Some(ttl) => Duration::from_secs(ttl.min(MAX_CACHE_TTL) as u64),
+            None => self.negative_cache_ttl,
+          };
+          if let Ok(mut cache) = self.cache.lock() {
+            if cache.len() >= MAX_CACHE_ENTRIES {
+              let now = Instant::now();
+              cache.retain(|_, entry| entry.expires > now);
+              if cache.len() >= MAX_CACHE_ENTRIES {
+                cache.clear();
+              }
+            }
+            cache.insert(
+              cache_key,
+              CacheEntry {
+                addresses: response.addresses.clone(),
+                expires: Instant::now() + ttl,
+              },
+            );
+          }
+          return Ok(response.addresses);
+        }
+        Ok(Err(err)) => last_error = Some(err),
+        Err(_) => {
+          last_error = Some(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "The DNS query has timed out",
+          ))
+        }
+      }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("No DNS servers are configured")))
+  }
+
+  async fn query(
+    &self,
+    upstream: &DnsUpstream,
+    host: &str,
+    record_type: RecordType,
+  ) -> io::Result<DnsResponse> {
+    match upstream {
+      DnsUpstream::Udp(server_address) => {
+        let id = rand::random::<u16>();
+        let query = build_query(id, host, record_type)?;
+        let socket = UdpSocket::bind(match server_address {
+          SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+          SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        })
+        .await?;
+        socket.connect(server_address).await?;
+        socket.send(&query).await?;
+
+        let mut buffer = vec![0u8; 4096];
+        let response = loop {
+          let length = socket.recv(&mut buffer).await?;
+          // The responses with other IDs (for example spoofed responses) are ignored
+          if buffer[..length].starts_with(&id.to_be_bytes()) {
+            break parse_response(&buffer[..length], id, record_type)?;
+          }
+        };
+        if !response.truncated {
+          return Ok(response);
+        }
+
+        let stream = TcpStream::connect(server_address).await?;
+        let response = query_over_stream(stream, &query).await?;
+        parse_response(&response, id, record_type)
+      }
+      DnsUpstream::Tls(server_address, server_name) => {
+        let id = rand::random::<u16>();
+        let query = build_query(id, host, record_type)?;
+        let stream = TcpStream::connect(server_address).await?;
+        stream.set_nodelay(true)?;
+        let tls_stream = self
+          .tls_connector()
+          .connect(server_name.clone(), stream)
+          .await?;
+        let response = query_over_stream(tls_stream, &query).await?;
+        parse_response(&response, id, record_type)
+      }
+      DnsUpstream::Https(uri) => {
+        // The ID is zero for DNS-over-HTTPS, so that the responses can be cached by HTTP caches
+        let query = build_query(0, host, record_type)?;
+        let response = self
+          .query_over_https(uri, query)
+          .await
+          .map_err(io::Error::other)?;
+        parse_response(&response, 0, record_type)
+      }
+    }
+  }
+
+  async fn query_over_https(
+    &self,
+    uri: &Uri,
+    query: Vec<u8>,
+  ) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    let host = uri.host().unwrap_or_default();
+    let host = host
+      .strip_prefix('[')
+      .and_then(|host| host.strip_suffix(']'))
+      .unwrap_or(host);
+    let port = uri.port_u16().unwrap_or(443);
+
+    // The DNS-over-HTTPS server's host name is resolved using the OS resolver
+    let stream = TcpStream::connect((host, port)).await?;
+    stream.set_nodelay(true)?;
+    let server_name = ServerName::try_from(host.to_string())?;
+    let tls_stream = self.tls_connector().connect(server_name, stream).await?;
+
+    let (mut sender, conn) =
+      hyper::client::conn::http1::handshake(TokioIo::new(tls_stream)).await?;
+    tokio::spawn(async move {
+      conn.await.unwrap_or_default();
+    });
+
+    let request = Request::builder()
+      .method(Method::POST)
+      .uri(match uri.path_and_query() {
+        Some(path_and_query) => path_and_query.as_str(),
+        None => "/",
+      })
+      .header(header::HOST, uri.authority().map_or("", |a| a.as_str()))
+      .header(header::USER_AGENT, "Ferron")
+      .header(header::CONTENT_TYPE, "application/dns-message")
+      .header(header::ACCEPT, "application/dns-message")
+      .body(Full::new(Bytes::from(query)))?;
+
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+      Err(anyhow::anyhow!(
+        "Unexpected DNS-over-HTTPS response status code: {}",
+        response.status()
+      ))?
+    }
+    Ok(response.into_body().collect().await?.to_bytes())
+  }
+}
+
+// Set the DNS resolver used for the server-side lookups. If it's None, the OS resolver is used.
+pub fn set_dns_resolver(resolver: Option<DnsResolver>) {
+  if let Ok(mut dns_resolver) = DNS_RESOLVER.write() {
+    *dns_resolver = resolver.map(Arc::new);
+  }
+}
+
+fn dns_resolver() -> Option<Arc<DnsResolver>> {
+  DNS_RESOLVER
+    .read()
+    .ok()
+    .and_then(|dns_resolver| dns_resolver.clone())
+}
+
+// Resolve the host name using the configured DNS resolver, or the OS resolver if it's not configured
+pub async fn lookup_host(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+  match dns_resolver() {
+    Some(resolver) => Ok(
+      resolver
+        .lookup(host)
+        .await?
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect(),
+    ),
+    None => Ok(tokio::net::lookup_host((host, port)).await?.collect()),
+  }
+}
+
+// Open a TCP connection to the "host:port" address, resolving the host name using the configured DNS resolver
+pub async fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+  let resolver = match dns_resolver() {
+    Some(resolver) => resolver,
+    None => return TcpStream::connect(address).await,
+  };
+  if let Ok(socket_addr) = address.parse::<SocketAddr>() {
+    return TcpStream::connect(socket_addr).await;
+  }
+
+  let (host, port) = match address.rsplit_once(':') {
+    Some((host, port)) => match port.parse::<u16>() {
+      Ok(port) => (host, port),
+      Err(_) => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidInput,
+          "Invalid port number",
+        ))
+      }
+    },
+    None => {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The address doesn't have a port number",
+      ))
+    }
+  };
+
+  let mut last_error = None;
+  for ip in resolver.lookup(host).await? {
+    match TcpStream::connect(SocketAddr::new(ip, port)).await {
+      Ok(stream) => return Ok(stream),
+      Err(err) => last_error = Some(err),
+    }
+  }
+  Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotFound)))
+}
+
+// A DNS resolver for the HTTP clients from the "reqwest" crate (used for OCSP stapling)
+pub struct ReqwestDnsResolver;
+
+impl reqwest::dns::Resolve for ReqwestDnsResolver {
+  fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+    let host = name.as_str().to_string();
+    Box::pin(async move {
+      let addresses = lookup_host(&host, 0).await?;
+      Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A response to the "example.com" A query, with a CNAME record and two A records
+  fn example_response(id: u16) -> Vec<u8> {
+    let mut response = build_query(id, "example.com", RecordType::A).unwrap();
+    response[2] = 0x81;
+    response[3] = 0x80;
+    response[7] = 3;
+    // CNAME record pointing to the question name
+    response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00]);
+    response.extend_from_slice(&[0x00, 0x02, 0xc0, 0x0c]);
+    response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c]);
+    response.extend_from_slice(&[0x00, 0x04, 192, 0, 2, 1]);
+    response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x1e]);
+    response.extend_from_slice(&[0x00, 0x04, 192, 0, 2, 2]);
+    response
+  }
+
+  #[test]
+  fn test_build_query() {
+    let query = build_query(0x1234, "example.com.", RecordType::Aaaa).unwrap();
+    assert_eq!(
+      query,
+      [
+        &[0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..],
+        b"\x07example\x03com\x00",
+        &[0x00, 0x1c, 0x00, 0x01],
+      ]
+      .concat()
+    );
+    assert!(build_query(1, "invalid..name", RecordType::A).is_err());
+    assert!(build_query(1, &"a".repeat(64), RecordType::A).is_err());
+  }
+
+  #[test]
+  fn test_parse_response() {
+    assert_eq!(
+      parse_response(&example_response(0x1234), 0x1234, RecordType::A).unwrap(),
+      DnsResponse {
+        truncated: false,
+        addresses: vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()],
+        ttl: Some(30),
+      }
+    );
+    // The responses with a different ID are rejected
+    assert!(parse_response(&example_response(0x1234), 0x4321, RecordType::A).is_err());
+    // The truncated responses are rejected
+    let response = example_response(0x1234);
+    assert!(parse_response(&response[..response.len() - 3], 0x1234, RecordType::A).is_err());
+
+    // The non-existent domain
+    let mut response = build_query(0x1234, "example.com", RecordType::A).unwrap();
+    response[2] = 0x81;
+    response[3] = 0x83;
+    assert_eq!(
+      parse_response(&response, 0x1234, RecordType::A).unwrap(),
+      DnsResponse {
+        truncated: false,
+        addresses: vec![],
+        ttl: None,
+      }
+    );
+    // Server failure
+    response[3] = 0x82;
+    assert!(parse_response(&response, 0x1234, RecordType::A).is_err());
+  }
+
+  #[test]
+  fn test_parse_dns_upstream() {
+    assert!(matches!(
+      parse_dns_upstream("192.0.2.53"),
+      Ok(DnsUpstream::Udp(address)) if address == "192.0.2.53:53".parse().unwrap()
+    ));
+    assert!(matches!(
+      parse_dns_upstream("udp://[2001:db8::53]:5353"),
+      Ok(DnsUpstream::Udp(address)) if address == "[2001:db8::53]:5353".parse().unwrap()
+    ));
+    assert!(matches!(
+      parse_dns_upstream("tls://192.0.2.53#dns.example.com"),
+      Ok(DnsUpstream::Tls(address, ServerName::DnsName(_))) if address.port() == 853
+    ));
+    assert!(matches!(
+      parse_dns_upstream("https://dns.example.com/dns-query"),
+      Ok(DnsUpstream::Https(_))
+    ));
+    assert!(parse_dns_upstream("dns.example.com").is_err());
+    assert!(parse_dns_upstream("tls://192.0.2.53#invalid name").is_err());
+  }
+}
@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use std::ffi::CString;
+
+// Whether the server has dropped its privileges or changed its root directory. The privileges can't be regained.
+static PRIVILEGES_DROPPED: AtomicBool = AtomicBool::new(false);
+static ROOT_CHANGED: AtomicBool = AtomicBool::new(false);
+
+// Look up the user ID and the primary group ID of a user (either an user name or a numeric ID)
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(libc::uid_t, libc::gid_t), Box<dyn Error + Send + Sync>> {
+  let user_cstring = CString::new(user)?;
+  let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+  let mut passwd_result: *mut libc::passwd = std::ptr::null_mut();
+  let mut buffer = vec![0 as libc::c_char; 16384];
+
+  // Safety: the buffers are valid for the duration of the call
+  let result = unsafe {
+    libc::getpwnam_r(
+      user_cstring.as_ptr(),
+      &mut passwd,
+      buffer.as_mut_ptr(),
+      buffer.len(),
+      &mut passwd_result,
+    )
+  };
+  if result == 0 && !passwd_result.is_null() {
+    return Ok((passwd.pw_uid, passwd.pw_gid));
+  }
+
+  if let Ok(uid) = user.parse::<libc::uid_t>() {
+    // Safety: the buffers are valid for the duration of the call
+    let result = unsafe {
+      libc::getpwuid_r(
+        uid,
+        &mut passwd,
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        &mut passwd_result,
+      )
+    };
+    if result == 0 && !passwd_result.is_null() {
+      return Ok((passwd.pw_uid, passwd.pw_gid));
+    }
+  }
+
+  Err(anyhow::anyhow!("The \"{}\" user doesn't exist", user))?
+}
+
+// Look up the group ID of a group (either a group name or a numeric ID)
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t, Box<dyn Error + Send + Sync>> {
+  let group_cstring = CString::new(group)?;
+  let mut group_entry: libc::group = unsafe { std::mem::zeroed() };
+  let mut group_result: *mut libc::group = std::ptr::null_mut();
+  let mut buffer = vec![0 as libc::c_char; 16384];
+
+  // Safety: the buffers are valid for the duration of the call
+  let result = unsafe {
+    libc::getgrnam_r(
+      group_cstring.as_ptr(),
+      &mut group_entry,
+      buffer.as_mut_ptr(),
+      buffer.len(),
+      &mut group_result,
+    )
+  };
+  if result == 0 && !group_result.is_null() {
+    return Ok(group_entry.gr_gid);
+  }
+
+  if let Ok(gid) = group.parse::<libc::gid_t>() {
+    return Ok(gid);
+  }
+
+  Err(anyhow::anyhow!("The \"{}\" group doesn't exist", group))?
+}
+
+// Change the root directory, and switch to an unprivileged user and group
+#[cfg(unix)]
+pub fn drop_privileges(
+  user: Option<&str>,
+  group: Option<&str>,
+  chroot: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  // Resolve the user and the group before changing the root directory, because "/etc/passwd" may not exist in the chroot
+  let user_ids = match user {
+    Some(user) => Some((lookup_user(user)?, CString::new(user)?)),
+    None => None,
+  };
+  let gid = match group {
+    Some(group) => Some(lookup_group(group)?),
+    None => user_ids.as_ref().map(|((_, gid), _)| *gid),
+  };
+
+  PRIVILEGES_DROPPED.store(true, Ordering::Relaxed);
+  if let Some(chroot) = chroot {
+    std::os::unix::fs::chroot(chroot)?;
+    ROOT_CHANGED.store(true, Ordering::Relaxed);
+    std::env::set_current_dir("/")?;
+  }
+
+  if let Some(gid) = gid {
+    // Safety: the calls don't involve any pointers, other than to a local variable
+    unsafe {
+      match &user_ids {
+        Some((_, user_cstring)) => {
+          if libc::initgroups(user_cstring.as_ptr(), gid as _) != 0 {
+            Err(std::io::Error::last_os_error())?
+          }
+        }
+        None => {
+          if libc::setgroups(1, &gid) != 0 {
+            Err(std::io::Error::last_os_error())?
+          }
+        }
+      }
+      if libc::setgid(gid) != 0 {
+        Err(std::io::Error::last_os_error())?
+      }
+    }
+  }
+
+  if let Some(((uid, _), _)) = user_ids {
+    // Safety: the call doesn't involve any pointers
+    if unsafe { libc::setuid(uid) } != 0 {
+      Err(std::io::Error::last_os_error())?
+    }
+
+    // Make sure that the root privileges can't be regained
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+      Err(anyhow::anyhow!("The root privileges could be regained"))?
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(
+  _user: Option<&str>,
+  _group: Option<&str>,
+  _chroot: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  Err(anyhow::anyhow!(
+    "Privilege dropping is not supported on this platform"
+  ))?
+}
+
+// Check if the privileges have been dropped
+pub fn privileges_dropped() -> bool {
+  PRIVILEGES_DROPPED.load(Ordering::Relaxed)
+}
+
+// Check if the configuration can be reloaded after the privileges have been dropped.
+// Returns the reason, why the reload is refused.
+pub fn reload_refusal(restart_required: bool) -> Option<&'static str> {
+  reload_refusal_for(
+    restart_required,
+    PRIVILEGES_DROPPED.load(Ordering::Relaxed),
+    ROOT_CHANGED.load(Ordering::Relaxed),
+  )
+}
+
+// The configuration file would be read relative to the new root directory, and the restarted server
+// couldn't bind to the privileged ports nor read the files accessible only to the privileged user
+// (for example, the TLS private keys).
+fn reload_refusal_for(
+  restart_required: bool,
+  privileges_dropped: bool,
+  root_changed: bool,
+) -> Option<&'static str> {
+  if root_changed {
+    Some("the configuration can't be reloaded after changing the root directory")
+  } else if privileges_dropped && restart_required {
+    Some("the server can't be restarted to apply the configuration after dropping the privileges")
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(unix)]
+  #[test]
+  fn test_lookup_root_user() {
+    assert_eq!(lookup_user("root").unwrap().0, 0);
+    assert_eq!(lookup_user("0").unwrap().0, 0);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_lookup_nonexistent_user() {
+    assert!(lookup_user("ferron-nonexistent-user").is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_lookup_group() {
+    assert_eq!(lookup_group("0").unwrap(), 0);
+    assert!(lookup_group("ferron-nonexistent-group").is_err());
+  }
+
+  #[test]
+  fn test_restart_after_dropping_privileges() {
+    // Before the privileges are dropped, the server can be restarted
+    assert_eq!(reload_refusal_for(true, false, false), None);
+    // After the privileges are dropped, the configuration is swapped only if it doesn't require a restart
+    assert_eq!(reload_refusal_for(false, true, false), None);
+    assert!(reload_refusal_for(true, true, false).is_some());
+    // After the root directory is changed, the configuration file can't be read again
+    assert!(reload_refusal_for(false, true, true).is_some());
+  }
+}
@@ -0,0 +1,205 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use ferron_common::ServerConfigRoot;
+use hyper::HeaderMap;
+
+use crate::ferron_util::anti_xss::anti_xss;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+
+// The request header carrying the debug token ("verboseErrorToken" property)
+pub const DEBUG_TOKEN_HEADER: &str = "x-ferron-debug-token";
+
+// The maximum number of the error messages shown on the error page (the most recent ones are shown)
+const MAX_SHOWN_ERRORS: usize = 10;
+
+// Check if the client is a trusted administrator, who sees the extended diagnostics on the error pages.
+// The client is trusted, if it connects from one of the admin networks ("verboseErrorNetworks" property),
+// or if the request carries the debug token ("verboseErrorToken" property). The token is compared in constant time.
+pub fn is_verbose_error_client(
+  config: &ServerConfigRoot,
+  client_ip: IpAddr,
+  headers: &HeaderMap,
+) -> bool {
+  if let Some(networks) = config.get("verboseErrorNetworks").as_vec() {
+    let mut networks_trie = IpPrefixTrie::new();
+    for network in networks {
+      if let Some(network) = network.as_str() {
+        networks_trie.insert_cidr(network);
+      }
+    }
+    if networks_trie.contains(client_ip) {
+      return true;
+    }
+  }
+  match (
+    config.get("verboseErrorToken").as_str(),
+    headers
+      .get(DEBUG_TOKEN_HEADER)
+      .and_then(|header_value| header_value.to_str().ok()),
+  ) {
+    (Some(token), Some(provided_token)) if !token.is_empty() => {
+      provided_token.len() == token.len()
+        && provided_token
+          .bytes()
+          .zip(token.bytes())
+          .fold(0, |difference, (a, b)| difference | (a ^ b))
+          == 0
+    }
+    _ => false,
+  }
+}
+
+// Describe the part of the configuration that applied to the request (the host and the location blocks)
+fn config_source(config: &ServerConfigRoot) -> String {
+  let mut sources = Vec::new();
+  let host_conditions = [
+    ("domain", "domain"),
+    ("ip", "IP address"),
+    ("country", "country"),
+    ("deviceClass", "device class"),
+  ]
+  .into_iter()
+  .filter_map(|(property, description)| {
+    config
+      .get(property)
+      .as_str()
+      .map(|value| format!("{} {}", description, value))
+  })
+  .collect::<Vec<_>>();
+  if !host_conditions.is_empty() {
+    sources.push(format!("host ({})", host_conditions.join(", ")));
+  }
+  if let Some(path_regex) = config.get("pathRegex").as_str() {
+    sources.push(format!("location (path regex {})", path_regex));
+  } else if let Some(path) = config.get("path").as_str() {
+    sources.push(format!("location (path {})", path));
+  }
+  match sources.is_empty() {
+    true => String::from("global configuration"),
+    false => sources.join(", "),
+  }
+}
+
+// The extended diagnostics of the request, shown on the error pages to the trusted administrators
+pub struct ErrorDiagnostics {
+  // The modules that processed the request, in order
+  module_chain: Vec<Arc<str>>,
+  // The error messages logged while processing the request (including the backend server errors)
+  errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl ErrorDiagnostics {
+  pub fn new() -> Self {
+    Self {
+      module_chain: Vec::new(),
+      errors: Arc::new(Mutex::new(Vec::new())),
+    }
+  }
+
+  // Get the list, to which the error logger appends the logged error messages
+  pub fn captured_errors(&self) -> Arc<Mutex<Vec<String>>> {
+    self.errors.clone()
+  }
+
+  pub fn record_module(&mut self, module_name: Arc<str>) {
+    self.module_chain.push(module_name);
+  }
+
+  pub fn record_error(&self, error: String) {
+    if let Ok(mut errors) = self.errors.lock() {
+      errors.push(error);
+    }
+  }
+
+  // Render the diagnostics as an HTML fragment of the error page
+  pub fn to_html(&self, config: &ServerConfigRoot) -> String {
+    let module_chain = match self.module_chain.is_empty() {
+      true => String::from("none"),
+      false => self
+        .module_chain
+        .iter()
+        .map(|module_name| anti_xss(module_name))
+        .collect::<Vec<_>>()
+        .join(" &rarr; "),
+    };
+    let errors = match self.errors.lock() {
+      Ok(errors) => errors
+        .iter()
+        .skip(errors.len().saturating_sub(MAX_SHOWN_ERRORS))
+        .map(|error| format!("\n        <li>{}</li>", anti_xss(error)))
+        .collect::<String>(),
+      Err(_) => String::new(),
+    };
+    format!(
+      "<h2>Diagnostics</h2>
+    <ul>
+      <li>Configuration source: {}</li>
+      <li>Module chain: {}</li>
+      <li>Errors: {}</li>
+    </ul>
+",
+      anti_xss(&config_source(config)),
+      module_chain,
+      match errors.is_empty() {
+        true => String::from("none"),
+        false => format!("<ul>{}\n      </ul>", errors),
+      }
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn config(yaml: &str) -> ServerConfigRoot {
+    ServerConfigRoot::new(&YamlLoader::load_from_str(yaml).unwrap()[0])
+  }
+
+  #[test]
+  fn test_is_verbose_error_client() {
+    let config = config("verboseErrorNetworks: [\"10.0.0.0/8\"]\nverboseErrorToken: secret");
+    let mut headers = HeaderMap::new();
+    assert!(is_verbose_error_client(
+      &config,
+      "10.1.2.3".parse().unwrap(),
+      &headers
+    ));
+    assert!(!is_verbose_error_client(
+      &config,
+      "192.0.2.1".parse().unwrap(),
+      &headers
+    ));
+    headers.insert(DEBUG_TOKEN_HEADER, "secret".parse().unwrap());
+    assert!(is_verbose_error_client(
+      &config,
+      "192.0.2.1".parse().unwrap(),
+      &headers
+    ));
+    headers.insert(DEBUG_TOKEN_HEADER, "secreT".parse().unwrap());
+    assert!(!is_verbose_error_client(
+      &config,
+      "192.0.2.1".parse().unwrap(),
+      &headers
+    ));
+  }
+
+  #[test]
+  fn test_error_diagnostics_html() {
+    let mut diagnostics = ErrorDiagnostics::new();
+    diagnostics.record_module(Arc::from("static"));
+    diagnostics.record_module(Arc::from("rproxy"));
+    diagnostics.record_error(String::from("Bad gateway: <connection refused>"));
+    let html = diagnostics.to_html(&config(
+      "domain: example.com\npath: /api\nproxyTo: http://backend",
+    ));
+    assert!(html.contains("host (domain example.com), location (path /api)"));
+    assert!(html.contains("static &rarr; rproxy"));
+    assert!(!html.contains("<connection refused>"));
+    assert!(ErrorDiagnostics::new()
+      .to_html(&config("{}"))
+      .contains("Configuration source: global configuration"));
+  }
+}
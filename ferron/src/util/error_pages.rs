@@ -84,3 +84,16 @@ pub fn generate_default_error_page(
     anti_xss(&status_code_description)
   )
 }
+
+// Generate the default error page with the extended diagnostics (an HTML fragment) for the trusted administrators
+pub fn generate_error_page_with_diagnostics(
+  status_code: hyper::StatusCode,
+  server_administrator_email: Option<&str>,
+  diagnostics_html: &str,
+) -> String {
+  generate_default_error_page(status_code, server_administrator_email).replacen(
+    "\n</body>",
+    &format!("\n    {}</body>", diagnostics_html),
+    1,
+  )
+}
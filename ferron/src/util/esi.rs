@@ -0,0 +1,246 @@
+use std::str::FromStr;
+
+use hyper::body::Bytes;
+use hyper::Uri;
+
+// A part of a document with ESI (Edge Side Includes) markup
+#[derive(Debug, PartialEq)]
+pub enum EsiSegment {
+  // The text copied to the assembled document as-is
+  Text(Bytes),
+  // The fragment included from the URL, with the alternative URL tried if the fragment can't be fetched
+  Include {
+    src: String,
+    alt: Option<String>,
+    continue_on_error: bool,
+  },
+}
+
+// Find the position of the byte sequence in the document, starting from the specified position
+fn find(document: &[u8], needle: &[u8], start: usize) -> Option<usize> {
+  document
+    .get(start..)?
+    .windows(needle.len())
+    .position(|window| window == needle)
+    .map(|position| position + start)
+}
+
+// Find the end of the tag (the position after the ">" character), skipping the ">" characters in the quoted attribute values
+fn find_tag_end(document: &[u8], start: usize) -> Option<usize> {
+  let mut quote = None;
+  for (position, byte) in document.iter().enumerate().skip(start) {
+    match (quote, byte) {
+      (None, b'"' | b'\'') => quote = Some(*byte),
+      (Some(quote_byte), _) if quote_byte == *byte => quote = None,
+      (None, b'>') => return Some(position + 1),
+      _ => (),
+    }
+  }
+  None
+}
+
+// Parse the attributes of the tag, with the tag name and the angle brackets excluded
+fn parse_attributes(attributes: &str) -> Vec<(String, String)> {
+  let mut parsed_attributes = Vec::new();
+  let mut chars = attributes.chars().peekable();
+  loop {
+    while chars.next_if(|c| c.is_whitespace() || *c == '/').is_some() {}
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=' && *c != '/') {
+      name.push(c);
+    }
+    if name.is_empty() {
+      break;
+    }
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    let mut value = String::new();
+    if chars.next_if_eq(&'=').is_some() {
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+      match chars.next_if(|c| *c == '"' || *c == '\'') {
+        Some(quote) => {
+          for c in chars.by_ref() {
+            if c == quote {
+              break;
+            }
+            value.push(c);
+          }
+        }
+        None => {
+          while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            value.push(c);
+          }
+        }
+      }
+    }
+    parsed_attributes.push((name, value.replace("&amp;", "&")));
+  }
+  parsed_attributes
+}
+
+// Parse the "esi:include" tag into a segment. The tags without the "src" attribute are ignored.
+fn parse_include(tag: &str) -> Option<EsiSegment> {
+  let attributes = parse_attributes(tag);
+  let attribute = |name: &str| {
+    attributes
+      .iter()
+      .find(|(attribute_name, _)| attribute_name == name)
+      .map(|(_, value)| value.clone())
+  };
+  Some(EsiSegment::Include {
+    src: attribute("src")?,
+    alt: attribute("alt"),
+    continue_on_error: attribute("onerror").is_some_and(|onerror| onerror == "continue"),
+  })
+}
+
+// Parse the document with the ESI markup into segments. The "esi:include", "esi:remove" and "esi:comment" tags
+// and the "<!--esi ... -->" comments are supported, while the other markup is copied as text.
+pub fn parse_esi(document: &Bytes) -> Vec<EsiSegment> {
+  let mut segments = Vec::new();
+  let mut text_start = 0;
+  let mut position = 0;
+
+  while let Some(tag_start) = find(document, b"<", position) {
+    let rest = &document[tag_start..];
+    let tag_end = if rest.starts_with(b"<!--esi") {
+      // The content of the "<!--esi ... -->" comment is processed, while the comment markers are removed
+      match find(document, b"-->", tag_start) {
+        Some(comment_end) => {
+          segments.push(EsiSegment::Text(document.slice(text_start..tag_start)));
+          segments.extend(parse_esi(&document.slice((tag_start + 7)..comment_end)));
+          Some(comment_end + 3)
+        }
+        None => None,
+      }
+    } else if rest.starts_with(b"<esi:include") {
+      find_tag_end(document, tag_start).map(|include_end| {
+        segments.push(EsiSegment::Text(document.slice(text_start..tag_start)));
+        let tag = String::from_utf8_lossy(&document[(tag_start + 12)..(include_end - 1)]);
+        let is_self_closing = tag.trim_end().ends_with('/');
+        segments.extend(parse_include(&tag));
+        match is_self_closing {
+          true => include_end,
+          false => {
+            find(document, b"</esi:include>", include_end).map_or(include_end, |end| end + 14)
+          }
+        }
+      })
+    } else if rest.starts_with(b"<esi:remove>") {
+      find(document, b"</esi:remove>", tag_start).map(|remove_end| {
+        segments.push(EsiSegment::Text(document.slice(text_start..tag_start)));
+        remove_end + 13
+      })
+    } else if rest.starts_with(b"<esi:comment") {
+      find_tag_end(document, tag_start).inspect(|_| {
+        segments.push(EsiSegment::Text(document.slice(text_start..tag_start)));
+      })
+    } else {
+      None
+    };
+
+    match tag_end {
+      Some(tag_end) => {
+        text_start = tag_end;
+        position = tag_end;
+      }
+      None => position = tag_start + 1,
+    }
+  }
+
+  segments.push(EsiSegment::Text(document.slice(text_start..)));
+  segments.retain(|segment| !matches!(segment, EsiSegment::Text(text) if text.is_empty()));
+  segments
+}
+
+// Resolve the URL of the fragment included with ESI against the path of the request.
+// The absolute URLs keep their host, which is used for selecting the host configuration of the subrequest.
+pub fn resolve_esi_url(src: &str, request_path: &str) -> Option<Uri> {
+  if src.starts_with("http://") || src.starts_with("https://") || src.starts_with('/') {
+    Uri::from_str(src).ok()
+  } else {
+    let base_path = match request_path.rfind('/') {
+      Some(index) => &request_path[..=index],
+      None => "/",
+    };
+    Uri::from_str(&format!("{}{}", base_path, src)).ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(text: &'static str) -> EsiSegment {
+    EsiSegment::Text(Bytes::from_static(text.as_bytes()))
+  }
+
+  fn include(src: &str, alt: Option<&str>, continue_on_error: bool) -> EsiSegment {
+    EsiSegment::Include {
+      src: src.to_string(),
+      alt: alt.map(|alt| alt.to_string()),
+      continue_on_error,
+    }
+  }
+
+  #[test]
+  fn test_parse_include() {
+    let document = Bytes::from_static(
+      b"<p>Hello, <esi:include src=\"/user?id=1&amp;full=1\" onerror=\"continue\"/>!</p>\
+        <esi:include src='/footer' alt='/footer-fallback'></esi:include>",
+    );
+    assert_eq!(
+      parse_esi(&document),
+      vec![
+        text("<p>Hello, "),
+        include("/user?id=1&full=1", None, true),
+        text("!</p>"),
+        include("/footer", Some("/footer-fallback"), false),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_remove_and_comments() {
+    let document = Bytes::from_static(
+      b"<esi:remove><a href=\"/user\">User</a></esi:remove>\
+        <!--esi <b><esi:include src=\"/user\" /></b> -->\
+        <esi:comment text=\"Not shown\"/><esi:choose></esi:choose>",
+    );
+    assert_eq!(
+      parse_esi(&document),
+      vec![
+        text(" <b>"),
+        include("/user", None, false),
+        text("</b> "),
+        text("<esi:choose></esi:choose>"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_without_esi() {
+    let document = Bytes::from_static(b"<html><esi:include src=\"/unterminated\"");
+    assert_eq!(
+      parse_esi(&document),
+      vec![text("<html><esi:include src=\"/unterminated\"")]
+    );
+    assert_eq!(parse_esi(&Bytes::new()), vec![]);
+  }
+
+  #[test]
+  fn test_resolve_esi_url() {
+    assert_eq!(
+      resolve_esi_url("/fragment?a=1", "/blog/post"),
+      Some(Uri::from_static("/fragment?a=1"))
+    );
+    assert_eq!(
+      resolve_esi_url("fragment", "/blog/post"),
+      Some(Uri::from_static("/blog/fragment"))
+    );
+    assert_eq!(
+      resolve_esi_url("https://example.com/fragment", "/"),
+      Some(Uri::from_static("https://example.com/fragment"))
+    );
+    assert_eq!(resolve_esi_url("frag ment", "/"), None);
+  }
+}
@@ -0,0 +1,263 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use hyper::header::{self, HeaderMap, HeaderName};
+use sha2::{Digest, Sha256};
+use yaml_rust2::Yaml;
+
+// An experiment, which assigns the requests to the named buckets in the configured ratios.
+// The assignment is derived from the hash of the client's cookie (if configured and present) or the client's IP address,
+// so that the client stays in the same bucket across requests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Experiment {
+  pub name: String,
+  buckets: Vec<(String, u64)>,
+  cookie: Option<String>,
+  pub header: Option<HeaderName>,
+}
+
+// Check if the experiment name can be used in the variable name ("experiment_<name>")
+fn is_valid_experiment_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+// Check if the bucket name can be used in the header value and in the access log
+fn is_valid_bucket_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
+// Get the value of the cookie from the request headers
+fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+  for cookie_header in headers.get_all(header::COOKIE) {
+    if let Ok(cookie_header) = cookie_header.to_str() {
+      for cookie in cookie_header.split(';') {
+        if let Some((cookie_name, cookie_value)) = cookie.trim().split_once('=') {
+          if cookie_name == name {
+            return Some(cookie_value);
+          }
+        }
+      }
+    }
+  }
+  None
+}
+
+impl Experiment {
+  // Parse the experiment from the entry of the "experiments" property
+  pub fn from_yaml(experiment_yaml: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    if experiment_yaml.as_hash().is_none() {
+      Err(anyhow::anyhow!("Invalid experiment configuration"))?
+    }
+    let name = match experiment_yaml["name"].as_str() {
+      Some(name) if is_valid_experiment_name(name) => name.to_string(),
+      _ => Err(anyhow::anyhow!(
+        "Invalid experiment name. The names can contain only letters, digits and underscores"
+      ))?,
+    };
+
+    let mut buckets = Vec::new();
+    match experiment_yaml["buckets"].as_hash() {
+      Some(buckets_yaml) if !buckets_yaml.is_empty() => {
+        for (bucket_name, weight) in buckets_yaml.iter() {
+          let bucket_name = match bucket_name.as_str() {
+            Some(bucket_name) if is_valid_bucket_name(bucket_name) => bucket_name,
+            _ => Err(anyhow::anyhow!(
+              "Invalid bucket name of the \"{}\" experiment",
+              name
+            ))?,
+          };
+          let weight = match weight.as_i64() {
+            Some(weight) if weight >= 0 => weight as u64,
+            _ => Err(anyhow::anyhow!(
+              "Invalid weight of the \"{}\" bucket of the \"{}\" experiment",
+              bucket_name,
+              name
+            ))?,
+          };
+          buckets.push((bucket_name.to_string(), weight));
+        }
+      }
+      _ => Err(anyhow::anyhow!(
+        "Invalid buckets configuration of the \"{}\" experiment",
+        name
+      ))?,
+    }
+    if buckets.iter().all(|(_, weight)| *weight == 0) {
+      Err(anyhow::anyhow!(
+        "At least one bucket of the \"{}\" experiment must have a non-zero weight",
+        name
+      ))?
+    }
+
+    let cookie = match &experiment_yaml["cookie"] {
+      Yaml::BadValue => None,
+      cookie => match cookie.as_str() {
+        Some(cookie) if !cookie.is_empty() => Some(cookie.to_string()),
+        _ => Err(anyhow::anyhow!(
+          "Invalid cookie name of the \"{}\" experiment",
+          name
+        ))?,
+      },
+    };
+
+    let header = match &experiment_yaml["header"] {
+      Yaml::BadValue => None,
+      header => match header.as_str().map(HeaderName::from_str) {
+        Some(Ok(header)) => Some(header),
+        _ => Err(anyhow::anyhow!(
+          "Invalid header name of the \"{}\" experiment",
+          name
+        ))?,
+      },
+    };
+
+    Ok(Self {
+      name,
+      buckets,
+      cookie,
+      header,
+    })
+  }
+
+  // Assign the request to one of the buckets, and return the bucket name
+  pub fn assign(&self, headers: &HeaderMap, client_ip: IpAddr) -> &str {
+    let client_ip = client_ip.to_canonical().to_string();
+    let key = self
+      .cookie
+      .as_deref()
+      .and_then(|cookie| get_cookie(headers, cookie))
+      .unwrap_or(&client_ip);
+
+    // The experiment name is hashed along with the key, so that the assignments of different experiments are independent
+    let mut hasher = Sha256::new();
+    hasher.update(self.name.as_bytes());
+    hasher.update([0]);
+    hasher.update(key.as_bytes());
+    let hash = hasher.finalize();
+    let mut hash_prefix = [0u8; 8];
+    hash_prefix.copy_from_slice(&hash[..8]);
+
+    let total_weight: u64 = self.buckets.iter().map(|(_, weight)| weight).sum();
+    let mut position = u64::from_be_bytes(hash_prefix) % total_weight;
+    for (bucket_name, weight) in self.buckets.iter() {
+      if position < *weight {
+        return bucket_name;
+      }
+      position -= weight;
+    }
+    // Unreachable, since the position is less than the sum of the weights
+    &self.buckets[self.buckets.len() - 1].0
+  }
+}
+
+// Parse the experiments from the "experiments" property
+pub fn parse_experiments(
+  experiments_yaml: &Yaml,
+) -> Result<Vec<Experiment>, Box<dyn Error + Send + Sync>> {
+  match experiments_yaml {
+    Yaml::BadValue => Ok(Vec::new()),
+    Yaml::Array(experiments_yaml) => {
+      let mut experiments: Vec<Experiment> = Vec::new();
+      for experiment_yaml in experiments_yaml.iter() {
+        let experiment = Experiment::from_yaml(experiment_yaml)?;
+        if experiments
+          .iter()
+          .any(|other_experiment| other_experiment.name == experiment.name)
+        {
+          Err(anyhow::anyhow!(
+            "Duplicate experiment name \"{}\"",
+            experiment.name
+          ))?
+        }
+        experiments.push(experiment);
+      }
+      Ok(experiments)
+    }
+    _ => Err(anyhow::anyhow!("Invalid experiments configuration"))?,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn experiments_from_str(yaml: &str) -> Result<Vec<Experiment>, Box<dyn Error + Send + Sync>> {
+    parse_experiments(&YamlLoader::load_from_str(yaml).unwrap()[0])
+  }
+
+  #[test]
+  fn test_parse_experiments() {
+    let experiments = experiments_from_str(
+      "- name: checkout\n  buckets:\n    control: 90\n    variant: 10\n  cookie: uid\n  header: X-Experiment-Checkout\n",
+    )
+    .unwrap();
+    assert_eq!(experiments.len(), 1);
+    assert_eq!(experiments[0].name, "checkout");
+    assert_eq!(
+      experiments[0].buckets,
+      vec![(String::from("control"), 90), (String::from("variant"), 10)]
+    );
+    assert_eq!(experiments[0].cookie.as_deref(), Some("uid"));
+    assert_eq!(
+      experiments[0].header,
+      Some(HeaderName::from_static("x-experiment-checkout"))
+    );
+
+    assert!(experiments_from_str("- name: checkout\n  buckets:\n    control: -1\n").is_err());
+    assert!(experiments_from_str("- name: checkout\n  buckets:\n    control: 0\n").is_err());
+    assert!(experiments_from_str("- name: check-out\n  buckets:\n    control: 1\n").is_err());
+    assert!(experiments_from_str(
+      "- name: checkout\n  buckets:\n    a: 1\n- name: checkout\n  buckets:\n    b: 1\n"
+    )
+    .is_err());
+    assert!(experiments_from_str("checkout").is_err());
+  }
+
+  #[test]
+  fn test_experiment_assignment_is_stable() {
+    let experiments =
+      experiments_from_str("- name: checkout\n  buckets:\n    a: 1\n    b: 1\n  cookie: uid\n")
+        .unwrap();
+    let experiment = &experiments[0];
+    let mut headers = HeaderMap::new();
+    headers.insert(header::COOKIE, "theme=dark; uid=12345".parse().unwrap());
+    let bucket = experiment.assign(&headers, "192.0.2.1".parse().unwrap());
+    // The cookie takes precedence over the client's IP address
+    assert_eq!(
+      experiment.assign(&headers, "198.51.100.1".parse().unwrap()),
+      bucket
+    );
+    assert_eq!(
+      experiment.assign(&HeaderMap::new(), "192.0.2.1".parse().unwrap()),
+      experiment.assign(&HeaderMap::new(), "192.0.2.1".parse().unwrap())
+    );
+  }
+
+  #[test]
+  fn test_experiment_assignment_ratios() {
+    let experiments = experiments_from_str(
+      "- name: checkout\n  buckets:\n    control: 3\n    variant: 1\n    disabled: 0\n",
+    )
+    .unwrap();
+    let experiment = &experiments[0];
+    let mut control = 0;
+    let mut variant = 0;
+    for i in 0..4000u32 {
+      match experiment.assign(&HeaderMap::new(), IpAddr::from(i.to_be_bytes())) {
+        "control" => control += 1,
+        "variant" => variant += 1,
+        bucket => panic!("unexpected bucket \"{}\"", bucket),
+      }
+    }
+    assert!((2800..3200).contains(&control));
+    assert!((800..1200).contains(&variant));
+  }
+}
@@ -0,0 +1,820 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use fancy_regex::{Regex, RegexBuilder};
+use ferron_common::{RequestVariables, SocketData};
+use hyper::header::{self, HeaderMap};
+use hyper::{Method, Request, Uri};
+
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+
+// The limits of the expressions, so that the configuration can't make the server use excessive resources
+const MAX_EXPRESSION_LENGTH: usize = 4096;
+const MAX_NESTING_DEPTH: usize = 32;
+const REGEX_BACKTRACK_LIMIT: usize = 100000;
+
+// A value of an evaluated (sub)expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+  Null,
+  Bool(bool),
+  Integer(i64),
+  String(String),
+}
+
+impl Value {
+  fn is_truthy(&self) -> bool {
+    match self {
+      Value::Null => false,
+      Value::Bool(value) => *value,
+      Value::Integer(value) => *value != 0,
+      Value::String(value) => !value.is_empty(),
+    }
+  }
+
+  fn into_string(self) -> Option<String> {
+    match self {
+      Value::String(value) => Some(value),
+      Value::Integer(value) => Some(value.to_string()),
+      Value::Bool(value) => Some(value.to_string()),
+      Value::Null => None,
+    }
+  }
+}
+
+// The request properties available to the expressions
+pub struct ExpressionContext<'a> {
+  method: &'a Method,
+  uri: &'a Uri,
+  headers: &'a HeaderMap,
+  socket_data: &'a SocketData,
+  variables: Option<&'a RequestVariables>,
+}
+
+impl<'a> ExpressionContext<'a> {
+  pub fn new<B>(request: &'a Request<B>, socket_data: &'a SocketData) -> Self {
+    Self {
+      method: request.method(),
+      uri: request.uri(),
+      headers: request.headers(),
+      socket_data,
+      variables: request.extensions().get::<RequestVariables>(),
+    }
+  }
+
+  fn host(&self) -> Option<String> {
+    let host = match self.headers.get(header::HOST) {
+      Some(host) => host.to_str().ok()?,
+      None => self.uri.host()?,
+    };
+    // Strip the port number, but not the colons in the IPv6 addresses
+    let host = match host.rsplit_once(':') {
+      Some((host_without_port, port))
+        if !port.contains(']') && !host_without_port.ends_with(':') =>
+      {
+        host_without_port
+      }
+      _ => host,
+    };
+    Some(host.to_lowercase())
+  }
+
+  // Get the value of the variable substituted in the configuration (for example, "remote_addr" or "host").
+  // Besides the built-in variables, the request headers are available as "http_<header name>" variables
+  // (for example, "http_user_agent"), and the variables set for the request (for example, with "setVariables").
+  pub fn variable(&self, name: &str) -> Option<String> {
+    match name {
+      "remote_addr" => Some(self.socket_data.remote_addr.ip().to_canonical().to_string()),
+      "remote_port" => Some(self.socket_data.remote_addr.port().to_string()),
+      "server_addr" => Some(self.socket_data.local_addr.ip().to_canonical().to_string()),
+      "server_port" => Some(self.socket_data.local_addr.port().to_string()),
+      "host" => Some(self.host().unwrap_or_default()),
+      "scheme" => Some(
+        match self.socket_data.encrypted {
+          true => "https",
+          false => "http",
+        }
+        .to_string(),
+      ),
+      "method" => Some(self.method.to_string()),
+      "uri" => Some(self.uri.path().to_string()),
+      "query_string" => Some(self.uri.query().unwrap_or_default().to_string()),
+      "request_uri" => Some(
+        self
+          .uri
+          .path_and_query()
+          .map_or(self.uri.path(), |path_and_query| path_and_query.as_str())
+          .to_string(),
+      ),
+      _ => match name.strip_prefix("http_") {
+        Some(header_name) => Some(
+          self
+            .header(&header_name.replace('_', "-"))
+            .into_string()
+            .unwrap_or_default(),
+        ),
+        None => self.variables.and_then(|variables| variables.get(name)),
+      },
+    }
+  }
+
+  fn header(&self, name: &str) -> Value {
+    match self.headers.get(name).map(|value| value.to_str()) {
+      Some(Ok(value)) => Value::String(value.to_string()),
+      _ => Value::Null,
+    }
+  }
+
+  fn cookie(&self, name: &str) -> Value {
+    for cookie_header in self.headers.get_all(header::COOKIE) {
+      if let Ok(cookie_header) = cookie_header.to_str() {
+        for cookie in cookie_header.split(';') {
+          if let Some((cookie_name, cookie_value)) = cookie.trim().split_once('=') {
+            if cookie_name == name {
+              return Value::String(cookie_value.to_string());
+            }
+          }
+        }
+      }
+    }
+    Value::Null
+  }
+
+  fn query_parameter(&self, name: &str) -> Value {
+    for parameter in self.uri.query().unwrap_or_default().split('&') {
+      let (parameter_name, parameter_value) = parameter.split_once('=').unwrap_or((parameter, ""));
+      let parameter_name = urlencoding::decode(&parameter_name.replace('+', " "))
+        .map(|parameter_name| parameter_name.into_owned());
+      if parameter_name.as_deref() == Ok(name) {
+        return match urlencoding::decode(&parameter_value.replace('+', " ")) {
+          Ok(parameter_value) => Value::String(parameter_value.into_owned()),
+          Err(_) => Value::Null,
+        };
+      }
+    }
+    Value::Null
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+  Method,
+  Path,
+  Query,
+  Host,
+  Scheme,
+  ClientIp,
+  ClientPort,
+  ServerIp,
+  ServerPort,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+  Header,
+  Cookie,
+  Param,
+  Lower,
+  Upper,
+  Len,
+  Int,
+  StartsWith,
+  EndsWith,
+  Contains,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOperator {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+enum Node {
+  Literal(Value),
+  Variable(Variable),
+  Call(Function, Vec<Node>),
+  RegexMatch(Box<Node>, Regex, bool),
+  IpMatch(Box<Node>, IpPrefixTrie),
+  Compare(Box<Node>, CompareOperator, Box<Node>),
+  Not(Box<Node>),
+  And(Box<Node>, Box<Node>),
+  Or(Box<Node>, Box<Node>),
+}
+
+// An expression compiled from the configuration, like `req.path ~ "^/api" && req.header("X-Key") == "secret"`
+pub struct Expression {
+  source: String,
+  root: Node,
+}
+
+impl fmt::Debug for Expression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Expression").field(&self.source).finish()
+  }
+}
+
+impl Expression {
+  // Compile an expression. Returns an error if the expression is invalid.
+  pub fn compile(source: &str) -> Result<Self, anyhow::Error> {
+    if source.len() > MAX_EXPRESSION_LENGTH {
+      Err(anyhow::anyhow!("The expression is too long"))?
+    }
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+      tokens,
+      position: 0,
+      depth: 0,
+    };
+    let root = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+      Err(anyhow::anyhow!("Unexpected {} in the expression", token))?
+    }
+    Ok(Self {
+      source: source.to_string(),
+      root,
+    })
+  }
+
+  // Evaluate the expression for a request
+  pub fn evaluate(&self, context: &ExpressionContext) -> bool {
+    evaluate_node(&self.root, context).is_truthy()
+  }
+}
+
+fn evaluate_node(node: &Node, context: &ExpressionContext) -> Value {
+  match node {
+    Node::Literal(value) => value.clone(),
+    Node::Variable(variable) => match variable {
+      Variable::Method => Value::String(context.method.to_string()),
+      Variable::Path => Value::String(context.uri.path().to_string()),
+      Variable::Query => Value::String(context.uri.query().unwrap_or_default().to_string()),
+      Variable::Host => context.host().map_or(Value::Null, Value::String),
+      Variable::Scheme => Value::String(
+        match context.socket_data.encrypted {
+          true => "https",
+          false => "http",
+        }
+        .to_string(),
+      ),
+      Variable::ClientIp => Value::String(
+        context
+          .socket_data
+          .remote_addr
+          .ip()
+          .to_canonical()
+          .to_string(),
+      ),
+      Variable::ClientPort => Value::Integer(context.socket_data.remote_addr.port() as i64),
+      Variable::ServerIp => Value::String(
+        context
+          .socket_data
+          .local_addr
+          .ip()
+          .to_canonical()
+          .to_string(),
+      ),
+      Variable::ServerPort => Value::Integer(context.socket_data.local_addr.port() as i64),
+    },
+    Node::Call(function, arguments) => {
+      let mut arguments = arguments
+        .iter()
+        .map(|argument| evaluate_node(argument, context).into_string());
+      let mut next_argument = || arguments.next().flatten();
+      match function {
+        Function::Header => next_argument().map_or(Value::Null, |name| context.header(&name)),
+        Function::Cookie => next_argument().map_or(Value::Null, |name| context.cookie(&name)),
+        Function::Param => {
+          next_argument().map_or(Value::Null, |name| context.query_parameter(&name))
+        }
+        Function::Lower => {
+          next_argument().map_or(Value::Null, |value| Value::String(value.to_lowercase()))
+        }
+        Function::Upper => {
+          next_argument().map_or(Value::Null, |value| Value::String(value.to_uppercase()))
+        }
+        Function::Len => next_argument().map_or(Value::Null, |value| {
+          Value::Integer(value.chars().count() as i64)
+        }),
+        Function::Int => next_argument()
+          .and_then(|value| value.trim().parse::<i64>().ok())
+          .map_or(Value::Null, Value::Integer),
+        Function::StartsWith | Function::EndsWith | Function::Contains => {
+          match (next_argument(), next_argument()) {
+            (Some(value), Some(pattern)) => Value::Bool(match function {
+              Function::StartsWith => value.starts_with(&pattern),
+              Function::EndsWith => value.ends_with(&pattern),
+              _ => value.contains(&pattern),
+            }),
+            _ => Value::Bool(false),
+          }
+        }
+      }
+    }
+    Node::RegexMatch(node, regex, negated) => {
+      let matched = match evaluate_node(node, context).into_string() {
+        Some(value) => regex.is_match(&value).unwrap_or(false),
+        None => false,
+      };
+      Value::Bool(matched != *negated)
+    }
+    Node::IpMatch(node, ranges) => Value::Bool(
+      evaluate_node(node, context)
+        .into_string()
+        .and_then(|address| address.parse::<IpAddr>().ok())
+        .is_some_and(|address| ranges.contains(address)),
+    ),
+    Node::Compare(left, operator, right) => {
+      let left = evaluate_node(left, context);
+      let right = evaluate_node(right, context);
+      let ordering = match (&left, &right) {
+        (Value::Integer(left), Value::Integer(right)) => Some(left.cmp(right)),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+      };
+      Value::Bool(match operator {
+        CompareOperator::Equal => left == right,
+        CompareOperator::NotEqual => left != right,
+        CompareOperator::Less => ordering.is_some_and(|ordering| ordering.is_lt()),
+        CompareOperator::LessOrEqual => ordering.is_some_and(|ordering| ordering.is_le()),
+        CompareOperator::Greater => ordering.is_some_and(|ordering| ordering.is_gt()),
+        CompareOperator::GreaterOrEqual => ordering.is_some_and(|ordering| ordering.is_ge()),
+      })
+    }
+    Node::Not(node) => Value::Bool(!evaluate_node(node, context).is_truthy()),
+    Node::And(left, right) => Value::Bool(
+      evaluate_node(left, context).is_truthy() && evaluate_node(right, context).is_truthy(),
+    ),
+    Node::Or(left, right) => Value::Bool(
+      evaluate_node(left, context).is_truthy() || evaluate_node(right, context).is_truthy(),
+    ),
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  String(String),
+  Integer(i64),
+  Identifier(String),
+  Dot,
+  Comma,
+  LeftParenthesis,
+  RightParenthesis,
+  Operator(&'static str),
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Token::String(value) => write!(f, "string \"{}\"", value),
+      Token::Integer(value) => write!(f, "number {}", value),
+      Token::Identifier(value) => write!(f, "identifier \"{}\"", value),
+      Token::Dot => write!(f, "\".\""),
+      Token::Comma => write!(f, "\",\""),
+      Token::LeftParenthesis => write!(f, "\"(\""),
+      Token::RightParenthesis => write!(f, "\")\""),
+      Token::Operator(operator) => write!(f, "\"{}\"", operator),
+    }
+  }
+}
+
+const OPERATORS: [&str; 11] = ["==", "!=", "!~", "<=", ">=", "&&", "||", "~", "<", ">", "!"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, anyhow::Error> {
+  let mut tokens = Vec::new();
+  let mut characters = source.char_indices().peekable();
+  while let Some(&(index, character)) = characters.peek() {
+    if character.is_whitespace() {
+      characters.next();
+    } else if character == '"' || character == '\'' {
+      characters.next();
+      let mut value = String::new();
+      loop {
+        match characters.next() {
+          Some((_, '\\')) => match characters.next() {
+            Some((_, 'n')) => value.push('\n'),
+            Some((_, 't')) => value.push('\t'),
+            Some((_, escaped)) => value.push(escaped),
+            None => Err(anyhow::anyhow!("Unterminated string in the expression"))?,
+          },
+          Some((_, current)) if current == character => break,
+          Some((_, current)) => value.push(current),
+          None => Err(anyhow::anyhow!("Unterminated string in the expression"))?,
+        }
+      }
+      tokens.push(Token::String(value));
+    } else if character.is_ascii_digit() {
+      let mut value = String::new();
+      while let Some(&(_, digit)) = characters.peek() {
+        if !digit.is_ascii_digit() {
+          break;
+        }
+        value.push(digit);
+        characters.next();
+      }
+      tokens.push(Token::Integer(value.parse().map_err(|_| {
+        anyhow::anyhow!("Invalid number \"{}\" in the expression", value)
+      })?));
+    } else if character.is_ascii_alphabetic() || character == '_' {
+      let mut value = String::new();
+      while let Some(&(_, identifier_character)) = characters.peek() {
+        if !identifier_character.is_ascii_alphanumeric() && identifier_character != '_' {
+          break;
+        }
+        value.push(identifier_character);
+        characters.next();
+      }
+      tokens.push(Token::Identifier(value));
+    } else {
+      let token = match character {
+        '.' => Some(Token::Dot),
+        ',' => Some(Token::Comma),
+        '(' => Some(Token::LeftParenthesis),
+        ')' => Some(Token::RightParenthesis),
+        _ => None,
+      };
+      match token {
+        Some(token) => {
+          characters.next();
+          tokens.push(token);
+        }
+        None => {
+          let operator = OPERATORS
+            .iter()
+            .find(|operator| source[index..].starts_with(**operator))
+            .ok_or_else(|| {
+              anyhow::anyhow!("Unexpected character \"{}\" in the expression", character)
+            })?;
+          for _ in 0..operator.len() {
+            characters.next();
+          }
+          tokens.push(Token::Operator(operator));
+        }
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+  depth: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn next_if_operator(&mut self, operators: &[&str]) -> Option<&'static str> {
+    match self.peek() {
+      Some(Token::Operator(operator)) if operators.contains(operator) => {
+        let operator = *operator;
+        self.position += 1;
+        Some(operator)
+      }
+      _ => None,
+    }
+  }
+
+  fn expect(&mut self, expected: Token) -> Result<(), anyhow::Error> {
+    match self.next() {
+      Some(token) if token == expected => Ok(()),
+      Some(token) => Err(anyhow::anyhow!(
+        "Expected {}, found {} in the expression",
+        expected,
+        token
+      )),
+      None => Err(anyhow::anyhow!(
+        "Expected {} at the end of the expression",
+        expected
+      )),
+    }
+  }
+
+  fn enter(&mut self) -> Result<(), anyhow::Error> {
+    self.depth += 1;
+    if self.depth > MAX_NESTING_DEPTH {
+      Err(anyhow::anyhow!("The expression is nested too deeply"))?
+    }
+    Ok(())
+  }
+
+  fn parse_or(&mut self) -> Result<Node, anyhow::Error> {
+    self.enter()?;
+    let mut node = self.parse_and()?;
+    while self.next_if_operator(&["||"]).is_some() {
+      node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+    }
+    self.depth -= 1;
+    Ok(node)
+  }
+
+  fn parse_and(&mut self) -> Result<Node, anyhow::Error> {
+    let mut node = self.parse_not()?;
+    while self.next_if_operator(&["&&"]).is_some() {
+      node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+    }
+    Ok(node)
+  }
+
+  fn parse_not(&mut self) -> Result<Node, anyhow::Error> {
+    if self.next_if_operator(&["!"]).is_some() {
+      self.enter()?;
+      let node = Node::Not(Box::new(self.parse_not()?));
+      self.depth -= 1;
+      Ok(node)
+    } else {
+      self.parse_comparison()
+    }
+  }
+
+  fn parse_comparison(&mut self) -> Result<Node, anyhow::Error> {
+    let left = self.parse_primary()?;
+    let operator = match self.next_if_operator(&["==", "!=", "<", "<=", ">", ">=", "~", "!~"]) {
+      Some(operator) => operator,
+      None => return Ok(left),
+    };
+    if operator == "~" || operator == "!~" {
+      // The regular expressions are compiled together with the expression
+      let pattern = match self.next() {
+        Some(Token::String(pattern)) => pattern,
+        _ => Err(anyhow::anyhow!(
+          "The regular expression after \"{}\" must be a string",
+          operator
+        ))?,
+      };
+      let regex = RegexBuilder::new(&pattern)
+        .backtrack_limit(REGEX_BACKTRACK_LIMIT)
+        .build()
+        .map_err(|err| anyhow::anyhow!("Invalid regular expression \"{}\": {}", pattern, err))?;
+      return Ok(Node::RegexMatch(Box::new(left), regex, operator == "!~"));
+    }
+    let operator = match operator {
+      "==" => CompareOperator::Equal,
+      "!=" => CompareOperator::NotEqual,
+      "<" => CompareOperator::Less,
+      "<=" => CompareOperator::LessOrEqual,
+      ">" => CompareOperator::Greater,
+      _ => CompareOperator::GreaterOrEqual,
+    };
+    Ok(Node::Compare(
+      Box::new(left),
+      operator,
+      Box::new(self.parse_primary()?),
+    ))
+  }
+
+  fn parse_primary(&mut self) -> Result<Node, anyhow::Error> {
+    match self.next() {
+      Some(Token::String(value)) => Ok(Node::Literal(Value::String(value))),
+      Some(Token::Integer(value)) => Ok(Node::Literal(Value::Integer(value))),
+      Some(Token::LeftParenthesis) => {
+        let node = self.parse_or()?;
+        self.expect(Token::RightParenthesis)?;
+        Ok(node)
+      }
+      Some(Token::Identifier(identifier)) => {
+        let mut name = identifier;
+        while self.peek() == Some(&Token::Dot) {
+          self.position += 1;
+          match self.next() {
+            Some(Token::Identifier(identifier)) => {
+              name.push('.');
+              name.push_str(&identifier);
+            }
+            _ => Err(anyhow::anyhow!(
+              "Expected a property name after \"{}.\" in the expression",
+              name
+            ))?,
+          }
+        }
+        if self.peek() == Some(&Token::LeftParenthesis) {
+          self.position += 1;
+          self.parse_call(&name)
+        } else {
+          self.parse_variable(&name)
+        }
+      }
+      Some(token) => Err(anyhow::anyhow!("Unexpected {} in the expression", token)),
+      None => Err(anyhow::anyhow!("Unexpected end of the expression")),
+    }
+  }
+
+  fn parse_variable(&mut self, name: &str) -> Result<Node, anyhow::Error> {
+    Ok(match name {
+      "true" => Node::Literal(Value::Bool(true)),
+      "false" => Node::Literal(Value::Bool(false)),
+      "null" => Node::Literal(Value::Null),
+      "req.method" => Node::Variable(Variable::Method),
+      "req.path" => Node::Variable(Variable::Path),
+      "req.query" => Node::Variable(Variable::Query),
+      "req.host" => Node::Variable(Variable::Host),
+      "req.scheme" => Node::Variable(Variable::Scheme),
+      "client.ip" => Node::Variable(Variable::ClientIp),
+      "client.port" => Node::Variable(Variable::ClientPort),
+      "server.ip" => Node::Variable(Variable::ServerIp),
+      "server.port" => Node::Variable(Variable::ServerPort),
+      _ => Err(anyhow::anyhow!(
+        "Unknown variable \"{}\" in the expression",
+        name
+      ))?,
+    })
+  }
+
+  fn parse_call(&mut self, name: &str) -> Result<Node, anyhow::Error> {
+    self.enter()?;
+    let mut arguments = Vec::new();
+    if self.peek() == Some(&Token::RightParenthesis) {
+      self.position += 1;
+    } else {
+      loop {
+        arguments.push(self.parse_or()?);
+        match self.next() {
+          Some(Token::Comma) => continue,
+          Some(Token::RightParenthesis) => break,
+          Some(token) => Err(anyhow::anyhow!(
+            "Expected \",\" or \")\", found {} in the expression",
+            token
+          ))?,
+          None => Err(anyhow::anyhow!(
+            "Expected \")\" at the end of the expression"
+          ))?,
+        }
+      }
+    }
+    self.depth -= 1;
+
+    let (function, argument_count) = match name {
+      "req.header" => (Function::Header, 1),
+      "req.cookie" => (Function::Cookie, 1),
+      "req.param" => (Function::Param, 1),
+      "lower" => (Function::Lower, 1),
+      "upper" => (Function::Upper, 1),
+      "len" => (Function::Len, 1),
+      "int" => (Function::Int, 1),
+      "startsWith" => (Function::StartsWith, 2),
+      "endsWith" => (Function::EndsWith, 2),
+      "contains" => (Function::Contains, 2),
+      "ipMatch" => {
+        // The address ranges are parsed together with the expression
+        let mut arguments = arguments.into_iter();
+        let (address, ranges) = match (arguments.next(), arguments.next(), arguments.next()) {
+          (Some(address), Some(Node::Literal(Value::String(ranges))), None) => (address, ranges),
+          _ => Err(anyhow::anyhow!(
+            "The \"ipMatch\" function requires an address and a string with the address ranges"
+          ))?,
+        };
+        let mut ranges_trie = IpPrefixTrie::new();
+        for range in ranges.split([',', ' ']).filter(|range| !range.is_empty()) {
+          if !ranges_trie.insert_cidr(range) {
+            Err(anyhow::anyhow!(
+              "Invalid address range \"{}\" in the expression",
+              range
+            ))?
+          }
+        }
+        return Ok(Node::IpMatch(Box::new(address), ranges_trie));
+      }
+      _ => Err(anyhow::anyhow!(
+        "Unknown function \"{}\" in the expression",
+        name
+      ))?,
+    };
+    if arguments.len() != argument_count {
+      Err(anyhow::anyhow!(
+        "The \"{}\" function requires {} argument(s)",
+        name,
+        argument_count
+      ))?
+    }
+    Ok(Node::Call(function, arguments))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn evaluate(source: &str, request: &Request<()>) -> bool {
+    let socket_data = SocketData::new(
+      "192.168.1.10:50000".parse().unwrap(),
+      "[::1]:443".parse().unwrap(),
+      true,
+    );
+    Expression::compile(source)
+      .unwrap()
+      .evaluate(&ExpressionContext::new(request, &socket_data))
+  }
+
+  fn request() -> Request<()> {
+    Request::builder()
+      .method("POST")
+      .uri("/api/users?id=42&name=J%C3%B3zef+K")
+      .header("Host", "Example.com:8080")
+      .header("X-Key", "secret")
+      .header("Cookie", "session=abc; theme=dark")
+      .body(())
+      .unwrap()
+  }
+
+  #[test]
+  fn test_request_properties() {
+    let request = request();
+    assert!(evaluate(
+      r#"req.path ~ "^/api" && req.header("X-Key") == "secret""#,
+      &request
+    ));
+    assert!(evaluate(
+      r#"req.method == "POST" && req.host == "example.com" && req.scheme == "https""#,
+      &request
+    ));
+    assert!(evaluate(
+      r#"req.query == "id=42&name=J%C3%B3zef+K" && req.param("name") == "Józef K""#,
+      &request
+    ));
+    assert!(evaluate(
+      r#"int(req.param("id")) >= 40 && req.cookie("theme") == 'dark'"#,
+      &request
+    ));
+    assert!(evaluate(
+      r#"client.ip == "192.168.1.10" && server.port == 443 && client.port > 1024"#,
+      &request
+    ));
+    assert!(!evaluate(
+      r#"req.header("X-Missing") == "secret""#,
+      &request
+    ));
+    assert!(evaluate(r#"req.header("X-Missing") == null"#, &request));
+  }
+
+  #[test]
+  fn test_variables() {
+    let request = request();
+    let socket_data = SocketData::new(
+      "[::ffff:192.168.1.10]:50000".parse().unwrap(),
+      "[::1]:443".parse().unwrap(),
+      true,
+    );
+    let context = ExpressionContext::new(&request, &socket_data);
+    assert_eq!(context.variable("remote_addr").unwrap(), "192.168.1.10");
+    assert_eq!(context.variable("server_port").unwrap(), "443");
+    assert_eq!(context.variable("host").unwrap(), "example.com");
+    assert_eq!(context.variable("scheme").unwrap(), "https");
+    assert_eq!(
+      context.variable("request_uri").unwrap(),
+      "/api/users?id=42&name=J%C3%B3zef+K"
+    );
+    assert_eq!(context.variable("http_x_key").unwrap(), "secret");
+    assert_eq!(context.variable("http_x_missing").unwrap(), "");
+    assert_eq!(context.variable("unknown"), None);
+
+    let variables = RequestVariables::new();
+    variables.set("backend", String::from("api"));
+    let mut request = request;
+    request.extensions_mut().insert(variables);
+    let context = ExpressionContext::new(&request, &socket_data);
+    assert_eq!(context.variable("backend").unwrap(), "api");
+  }
+
+  #[test]
+  fn test_operators_and_functions() {
+    let request = request();
+    assert!(evaluate(r#"!(req.path !~ "^/api/") || false"#, &request));
+    assert!(evaluate(
+      r#"startsWith(req.path, "/api") && endsWith(req.path, "users") && contains(lower(req.header("X-Key")), "SEC") == false"#,
+      &request
+    ));
+    assert!(evaluate(r#"len(upper("abc")) == 3 && "b" > "a""#, &request));
+    assert!(evaluate(
+      r#"ipMatch(client.ip, "10.0.0.0/8, 192.168.0.0/16") && !ipMatch(server.ip, "127.0.0.1")"#,
+      &request
+    ));
+    assert!(!evaluate(r#"1 == "1""#, &request));
+  }
+
+  #[test]
+  fn test_invalid_expressions() {
+    assert!(Expression::compile(r#"req.path ~ "(""#).is_err());
+    assert!(Expression::compile(r#"req.path ~ req.query"#).is_err());
+    assert!(Expression::compile(r#"req.unknown == "a""#).is_err());
+    assert!(Expression::compile(r#"unknown("a")"#).is_err());
+    assert!(Expression::compile(r#"req.header("a", "b")"#).is_err());
+    assert!(Expression::compile(r#"req.path == "/"#).is_err());
+    assert!(Expression::compile(r#"(req.path == "/""#).is_err());
+    assert!(Expression::compile(r#"req.path == "/" req.query"#).is_err());
+    assert!(Expression::compile(r#"ipMatch(client.ip, "10.0.0.0/33")"#).is_err());
+    assert!(Expression::compile(r#"req.path = "/""#).is_err());
+    assert!(Expression::compile(&format!("{}true{}", "(".repeat(100), ")".repeat(100))).is_err());
+  }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper::body::{Body, Buf, Frame, SizeHint};
+use tokio::time::Sleep;
+
+use crate::ferron_util::metrics::METRICS;
+
+// How often the byte budget is distributed between the hosts
+const TICK: Duration = Duration::from_millis(10);
+
+// The maximum number of ticks the budget is distributed for at once, so that the hosts don't get large bursts after idle periods
+const MAX_TICKS: u32 = 10;
+
+struct HostState {
+  weight: u64,
+  requests: usize,
+  // The bytes the host can send. It can be negative, since whole frames are sent.
+  credit: i64,
+  // The number of response bodies waiting for the credit
+  waiting: usize,
+}
+
+struct FairQueueState {
+  hosts: HashMap<Arc<str>, HostState>,
+  last_refill: Instant,
+}
+
+// Shares the listener's bandwidth and request capacity between the hosts in proportion to their weights
+pub struct FairQueue {
+  // The bandwidth shared by the hosts, in bytes per second
+  rate: Option<u64>,
+  // The number of concurrent requests shared by the hosts
+  max_requests: Option<usize>,
+  state: Mutex<FairQueueState>,
+}
+
+impl FairQueue {
+  pub fn new(rate: Option<u64>, max_requests: Option<usize>) -> Arc<Self> {
+    Arc::new(Self {
+      rate,
+      max_requests,
+      state: Mutex::new(FairQueueState {
+        hosts: HashMap::new(),
+        last_refill: Instant::now(),
+      }),
+    })
+  }
+
+  // Try to admit a request for a host. Returns None if the host has used up its share of the request capacity.
+  //
+  // Each host with requests in flight is guaranteed its weighted share of the capacity. A host can use more
+  // than its share only if there are free slots left after reserving the unused shares of the other hosts
+  // and a slot for the hosts without requests in flight, so that a single host can't take the whole capacity.
+  pub fn try_acquire(self: &Arc<Self>, host: &str, weight: u64) -> Option<FairShare> {
+    let weight = weight.max(1);
+    let mut state = self.state.lock().ok()?;
+    let host: Arc<str> = match state.hosts.get_key_value(host) {
+      Some((host, _)) => host.clone(),
+      None => Arc::from(host),
+    };
+
+    if let Some(max_requests) = self.max_requests {
+      let total_requests = state
+        .hosts
+        .values()
+        .map(|host_state| host_state.requests)
+        .sum::<usize>();
+      if total_requests >= max_requests {
+        return None;
+      }
+
+      let host_requests = state
+        .hosts
+        .get(&host)
+        .map(|host_state| host_state.requests)
+        .unwrap_or(0);
+      let total_weight = weight
+        + state
+          .hosts
+          .iter()
+          .filter(|(other_host, host_state)| **other_host != host && host_state.requests > 0)
+          .map(|(_, host_state)| host_state.weight)
+          .sum::<u64>();
+      let guaranteed_requests = |weight: u64| {
+        ((max_requests as u64 * weight / total_weight) as usize)
+          .min(max_requests - 1)
+          .max(1)
+      };
+
+      if host_requests >= guaranteed_requests(weight) {
+        let reserved_requests = state
+          .hosts
+          .iter()
+          .filter(|(other_host, host_state)| **other_host != host && host_state.requests > 0)
+          .map(|(_, host_state)| {
+            guaranteed_requests(host_state.weight).saturating_sub(host_state.requests)
+          })
+          .sum::<usize>()
+          + 1;
+        if max_requests - total_requests <= reserved_requests {
+          return None;
+        }
+      }
+    }
+
+    let host_state = state.hosts.entry(host.clone()).or_insert(HostState {
+      weight,
+      requests: 0,
+      credit: 0,
+      waiting: 0,
+    });
+    host_state.weight = weight;
+    host_state.requests += 1;
+
+    Some(FairShare {
+      queue: self.clone(),
+      host,
+    })
+  }
+
+  // Distribute the byte budget accumulated since the last refill between the hosts waiting for the credit
+  fn refill(&self, state: &mut FairQueueState, now: Instant) {
+    let rate = match self.rate {
+      Some(rate) => rate,
+      None => return,
+    };
+    let elapsed = now.saturating_duration_since(state.last_refill);
+    let ticks = (elapsed.as_nanos() / TICK.as_nanos()) as u32;
+    if ticks == 0 {
+      return;
+    }
+    state.last_refill = match ticks > MAX_TICKS {
+      true => now,
+      false => state.last_refill + TICK * ticks,
+    };
+
+    let tick_budget = (rate as u128 * TICK.as_nanos() / 1_000_000_000) as i64;
+    let total_weight = state
+      .hosts
+      .values()
+      .filter(|host_state| host_state.waiting > 0)
+      .map(|host_state| host_state.weight)
+      .sum::<u64>();
+    if total_weight == 0 {
+      return;
+    }
+    for host_state in state.hosts.values_mut() {
+      if host_state.waiting > 0 {
+        let host_budget = tick_budget * host_state.weight as i64 / total_weight as i64;
+        host_state.credit =
+          (host_state.credit + host_budget * ticks.min(MAX_TICKS) as i64).min(host_budget * 2);
+      }
+    }
+  }
+
+  // Try to spend the host's credit for sending a frame
+  fn try_consume(&self, host: &str, length: usize, was_waiting: bool) -> bool {
+    if self.rate.is_none() {
+      return true;
+    }
+    let mut state = match self.state.lock() {
+      Ok(state) => state,
+      Err(_) => return true,
+    };
+    self.refill(&mut state, Instant::now());
+    let host_state = match state.hosts.get_mut(host) {
+      Some(host_state) => host_state,
+      None => return true,
+    };
+    if host_state.credit > 0 {
+      host_state.credit -= length as i64;
+      if was_waiting {
+        host_state.waiting -= 1;
+      }
+      true
+    } else {
+      if !was_waiting {
+        host_state.waiting += 1;
+      }
+      false
+    }
+  }
+
+  fn stop_waiting(&self, host: &str) {
+    if let Ok(mut state) = self.state.lock() {
+      if let Some(host_state) = state.hosts.get_mut(host) {
+        host_state.waiting = host_state.waiting.saturating_sub(1);
+      }
+    }
+  }
+}
+
+// A host's share of the fair queue, held until the response is sent
+pub struct FairShare {
+  queue: Arc<FairQueue>,
+  host: Arc<str>,
+}
+
+impl FairShare {
+  // Wrap the response body, so that it's sent at the host's share of the bandwidth
+  pub fn wrap_body<B: Body>(self, body: B) -> FairBody<B> {
+    FairBody {
+      inner: body,
+      share: self,
+      pending_frame: None,
+      waiting: false,
+      sleep: None,
+    }
+  }
+}
+
+impl Drop for FairShare {
+  fn drop(&mut self) {
+    if let Ok(mut state) = self.queue.state.lock() {
+      if let Some(host_state) = state.hosts.get_mut(&self.host) {
+        host_state.requests = host_state.requests.saturating_sub(1);
+        if host_state.requests == 0 && host_state.waiting == 0 {
+          state.hosts.remove(&self.host);
+        }
+      }
+    }
+  }
+}
+
+// A response body, which sends the data frames only when the host has the credit for them
+pub struct FairBody<B: Body> {
+  inner: B,
+  share: FairShare,
+  pending_frame: Option<Frame<B::Data>>,
+  waiting: bool,
+  sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<B: Body> Drop for FairBody<B> {
+  fn drop(&mut self) {
+    if self.waiting {
+      self.share.queue.stop_waiting(&self.share.host);
+    }
+  }
+}
+
+impl<B: Body + Unpin> Body for FairBody<B>
+where
+  B::Data: Unpin,
+{
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    loop {
+      let frame = match this.pending_frame.take() {
+        Some(frame) => frame,
+        None => match Pin::new(&mut this.inner).poll_frame(cx) {
+          Poll::Ready(Some(Ok(frame))) => frame,
+          other => return other,
+        },
+      };
+      let length = match frame.data_ref() {
+        Some(data) => data.remaining(),
+        None => return Poll::Ready(Some(Ok(frame))),
+      };
+
+      if this
+        .share
+        .queue
+        .try_consume(&this.share.host, length, this.waiting)
+      {
+        this.waiting = false;
+        METRICS.add_to_counter(
+          "ferron_fair_queue_bytes_total",
+          &[("host", &this.share.host)],
+          length as u64,
+        );
+        return Poll::Ready(Some(Ok(frame)));
+      }
+
+      // Wait for the next distribution of the byte budget
+      this.waiting = true;
+      this.pending_frame = Some(frame);
+      let sleep = this
+        .sleep
+        .get_or_insert_with(|| Box::pin(tokio::time::sleep(TICK)));
+      match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => this.sleep = None,
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.pending_frame.is_none() && self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    match &self.pending_frame {
+      Some(_) => SizeHint::default(),
+      None => self.inner.size_hint(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, StreamBody};
+  use hyper::body::Bytes;
+
+  #[test]
+  fn test_weighted_request_shares() {
+    let queue = FairQueue::new(None, Some(4));
+    // A single host can use the whole capacity except for a slot for the other hosts
+    let mut heavy_shares = (0..3)
+      .map(|_| queue.try_acquire("heavy.example", 1).unwrap())
+      .collect::<Vec<_>>();
+    assert!(queue.try_acquire("heavy.example", 1).is_none());
+    // Another host is guaranteed its share, even though the heavy host is using most of the capacity
+    let _light_share = queue.try_acquire("light.example", 1).unwrap();
+    assert!(queue.try_acquire("light.example", 1).is_none());
+    assert!(queue.try_acquire("heavy.example", 1).is_none());
+
+    heavy_shares.pop();
+    // The freed slot is reserved for the host below its share
+    assert!(queue.try_acquire("heavy.example", 1).is_none());
+    assert!(queue.try_acquire("light.example", 1).is_some());
+  }
+
+  #[test]
+  fn test_weights_of_request_shares() {
+    let queue = FairQueue::new(None, Some(8));
+    let _light_share = queue.try_acquire("light.example", 1).unwrap();
+    // The host with the weight of 3 is guaranteed 6 of 8 requests
+    let _heavy_shares = (0..6)
+      .map(|_| queue.try_acquire("heavy.example", 3).unwrap())
+      .collect::<Vec<_>>();
+    // The remaining slot is reserved for the other host
+    assert!(queue.try_acquire("heavy.example", 3).is_none());
+    let _light_share_2 = queue.try_acquire("light.example", 1).unwrap();
+    assert!(queue.try_acquire("light.example", 1).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_bandwidth_shares() {
+    // 1 MB/s, so that 10 kB can be sent per tick
+    let queue = FairQueue::new(Some(1_000_000), None);
+    let make_body = |host: &str, weight: u64| {
+      let frames =
+        (0..100).map(|_| Ok::<_, std::io::Error>(Frame::data(Bytes::from(vec![0u8; 1000]))));
+      queue
+        .try_acquire(host, weight)
+        .unwrap()
+        .wrap_body(StreamBody::new(futures_util::stream::iter(frames)))
+    };
+    let heavy_body = make_body("heavy.example", 3);
+    let light_body = make_body("light.example", 1);
+
+    let started = Instant::now();
+    let (heavy_result, light_result) = tokio::join!(
+      async {
+        heavy_body.collect().await.unwrap();
+        started.elapsed()
+      },
+      async {
+        light_body.collect().await.unwrap();
+        started.elapsed()
+      }
+    );
+
+    // The host with the higher weight gets the larger share, so it finishes first
+    assert!(heavy_result < light_result);
+    // 200 kB at 1 MB/s takes at least about 200 ms
+    assert!(light_result >= Duration::from_millis(150));
+  }
+}
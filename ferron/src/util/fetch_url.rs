@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::body::Bytes;
+use hyper::{header, Request, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use rustls::RootCertStore;
+use rustls_native_certs::load_native_certs;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsConnector;
+
+use crate::ferron_util::dns_resolver::connect_tcp;
+
+// The maximum time the download of a resource can take, including connecting to the server
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+// The maximum size of a downloaded resource
+const MAX_FETCH_SIZE: usize = 67108864;
+
+// Fetch a resource over HTTP or HTTPS (used for downloading lists referenced in the configuration)
+pub async fn fetch_url(url: &str) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+  fetch_url_with_limits(url, FETCH_TIMEOUT, MAX_FETCH_SIZE).await
+}
+
+// Fetch a resource, failing if the download takes too long or the resource is too large,
+// so that an unresponsive or malicious server can't stall the job or exhaust the memory
+async fn fetch_url_with_limits(
+  url: &str,
+  timeout: Duration,
+  max_size: usize,
+) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+  match tokio::time::timeout(timeout, fetch_url_inner(url, max_size)).await {
+    Ok(result) => result,
+    Err(_) => Err(anyhow::anyhow!("The download of \"{}\" timed out", url))?,
+  }
+}
+
+async fn fetch_url_inner(
+  url: &str,
+  max_size: usize,
+) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+  let uri = url.parse::<Uri>()?;
+  let encrypted = match uri.scheme_str() {
+    Some("http") => false,
+    Some("https") => true,
+    _ => Err(anyhow::anyhow!("Only HTTP and HTTPS URLs are supported"))?,
+  };
+  let host = match uri.host() {
+    Some(host) => host,
+    None => Err(anyhow::anyhow!("The URL doesn't have a host"))?,
+  };
+  let port = uri.port_u16().unwrap_or(if encrypted { 443 } else { 80 });
+
+  let stream = connect_tcp(&format!("{}:{}", host, port)).await?;
+  stream.set_nodelay(true)?;
+
+  if !encrypted {
+    fetch_over_stream(stream, &uri, max_size).await
+  } else {
+    let mut roots = RootCertStore::empty();
+    for cert in load_native_certs().certs {
+      roots.add(cert)?;
+    }
+    let tls_client_config = rustls::ClientConfig::builder()
+      .with_root_certificates(roots)
+      .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(tls_client_config));
+    let domain = ServerName::try_from(host)?.to_owned();
+    let tls_stream = connector.connect(domain, stream).await?;
+    fetch_over_stream(tls_stream, &uri, max_size).await
+  }
+}
+
+async fn fetch_over_stream(
+  stream: impl AsyncRead + AsyncWrite + Send + Unpin + 'static,
+  uri: &Uri,
+  max_size: usize,
+) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+  let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+  tokio::spawn(async move {
+    conn.await.unwrap_or_default();
+  });
+
+  let request = Request::builder()
+    .uri(match uri.path_and_query() {
+      Some(path_and_query) => path_and_query.as_str(),
+      None => "/",
+    })
+    .header(header::HOST, uri.authority().map_or("", |a| a.as_str()))
+    .header(header::USER_AGENT, "Ferron")
+    .body(Empty::<Bytes>::new())?;
+
+  let response = sender.send_request(request).await?;
+  if response.status() != StatusCode::OK {
+    Err(anyhow::anyhow!(
+      "Unexpected response status code: {}",
+      response.status()
+    ))?
+  }
+
+  Ok(
+    Limited::new(response.into_body(), max_size)
+      .collect()
+      .await?
+      .to_bytes(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio::net::TcpListener;
+
+  // Start a server answering a single request with the response
+  async fn serve_once(response: Option<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      // Read the request headers
+      let mut request = Vec::new();
+      while !request.ends_with(b"\r\n\r\n") {
+        request.push(stream.read_u8().await.unwrap());
+      }
+      match response {
+        Some(response) => stream.write_all(&response).await.unwrap_or_default(),
+        // The server never responds
+        None => std::future::pending().await,
+      }
+    });
+    format!("http://{}/list.txt", address)
+  }
+
+  #[tokio::test]
+  async fn test_fetch_url() {
+    let url = serve_once(Some(
+      b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n192.0.2.1\r\n".to_vec(),
+    ))
+    .await;
+    assert_eq!(
+      fetch_url_with_limits(&url, Duration::from_secs(10), 1024)
+        .await
+        .unwrap(),
+      Bytes::from_static(b"192.0.2.1\r\n")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_fetch_url_timeout() {
+    let url = serve_once(None).await;
+    let err = fetch_url_with_limits(&url, Duration::from_millis(100), 1024)
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("timed out"));
+  }
+
+  #[tokio::test]
+  async fn test_fetch_url_size_limit() {
+    let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 4096\r\n\r\n".to_vec();
+    response.extend_from_slice(&[b'a'; 4096]);
+    let url = serve_once(Some(response)).await;
+    assert!(fetch_url_with_limits(&url, Duration::from_secs(10), 1024)
+      .await
+      .is_err());
+  }
+}
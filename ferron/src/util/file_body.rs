@@ -0,0 +1,102 @@
+use ferron_common::ServerConfigRoot;
+use futures_util::TryStreamExt;
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+// The default size of the buffer used to read the files sent in the responses
+pub const DEFAULT_FILE_BUFFER_SIZE: usize = 65536;
+
+// The maximum size of the buffer. A single read of a Tokio file returns at most 2 MiB,
+// so the larger buffers would only allocate more memory without reducing the number of reads.
+pub const MAX_FILE_BUFFER_SIZE: usize = 2097152;
+
+// Get the size of the buffer used to read the files, configured with the "fileBufferSize" property
+pub fn file_buffer_size(config: &ServerConfigRoot) -> usize {
+  config
+    .get("fileBufferSize")
+    .as_i64()
+    .filter(|buffer_size| *buffer_size > 0)
+    .map(|buffer_size| (buffer_size as usize).min(MAX_FILE_BUFFER_SIZE))
+    .unwrap_or(DEFAULT_FILE_BUFFER_SIZE)
+}
+
+// Create a response body reading the file directly into the chunks sent to the client.
+// The chunks aren't copied through an intermediate buffer, unlike when the file is wrapped in a BufReader.
+// This is also used for the unencrypted connections: hyper writes the response to the socket itself,
+// so the file can't be passed to sendfile() or splice(), and the larger chunks reduce the number of reads and writes instead.
+pub fn file_body<R>(reader: R, buffer_size: usize) -> BoxBody<Bytes, std::io::Error>
+where
+  R: AsyncRead + Send + Sync + 'static,
+{
+  let reader_stream = ReaderStream::with_capacity(reader, buffer_size);
+  BodyExt::boxed(StreamBody::new(reader_stream.map_ok(Frame::data)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(yaml: &str) -> ServerConfigRoot {
+    ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(yaml)
+        .unwrap()
+        .remove(0),
+    )
+  }
+
+  #[test]
+  fn test_file_buffer_size() {
+    assert_eq!(file_buffer_size(&config("fileBufferSize: 262144")), 262144);
+    assert_eq!(
+      file_buffer_size(&config("fileBufferSize: 1073741824")),
+      MAX_FILE_BUFFER_SIZE
+    );
+    assert_eq!(
+      file_buffer_size(&config("fileBufferSize: 0")),
+      DEFAULT_FILE_BUFFER_SIZE
+    );
+    assert_eq!(
+      file_buffer_size(&ServerConfigRoot::from_hash(Default::default())),
+      DEFAULT_FILE_BUFFER_SIZE
+    );
+  }
+
+  #[tokio::test]
+  async fn test_file_body_reads_full_chunks() {
+    let path = std::env::temp_dir().join(format!("ferron-file-body-test-{}", std::process::id()));
+    let data = (0..600000)
+      .map(|index| (index % 251) as u8)
+      .collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+    let file = tokio::fs::File::open(&path).await.unwrap();
+    let mut body = file_body(file, 262144);
+
+    let mut chunk_sizes = Vec::new();
+    let mut received = Vec::new();
+    while let Some(frame) = body.frame().await {
+      let chunk = frame.unwrap().into_data().unwrap();
+      chunk_sizes.push(chunk.len());
+      received.extend_from_slice(&chunk);
+    }
+    std::fs::remove_file(&path).unwrap();
+    // Each read fills the whole buffer, so the file is sent in three chunks
+    assert_eq!(chunk_sizes, vec![262144, 262144, 75712]);
+    assert_eq!(received, data);
+  }
+
+  #[tokio::test]
+  async fn test_file_body_chunks() {
+    let data = (0..10000).map(|index| index as u8).collect::<Vec<_>>();
+    let mut body = file_body(std::io::Cursor::new(data.clone()), 4096);
+
+    let mut received = Vec::new();
+    while let Some(frame) = body.frame().await {
+      let chunk = frame.unwrap().into_data().unwrap();
+      assert!(chunk.len() <= 4096);
+      received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, data);
+  }
+}
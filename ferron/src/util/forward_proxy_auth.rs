@@ -0,0 +1,731 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose, Engine};
+use ferron_common::{ErrorLogger, ServerConfigRoot};
+use hmac::{Hmac, Mac};
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, StatusCode};
+use password_auth::verify_password;
+use sha2::{Digest, Sha256};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::bandwidth_throttle::TokenBucket;
+use crate::ferron_util::blocking_budget::spawn_blocking_budgeted;
+use crate::ferron_util::match_hostname::match_hostname;
+use crate::ferron_util::metrics::METRICS;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The default realm sent in the "Proxy-Authenticate" header
+const DEFAULT_REALM: &str = "Ferron Forward Proxy";
+
+// How long a Digest authentication nonce is accepted after it has been issued
+const DIGEST_NONCE_LIFETIME: Duration = Duration::from_secs(300);
+
+// The active connections and the bandwidth budget of the forward proxy users, keyed by the user name (or the client IP address)
+static PROXY_USERS: Mutex<BTreeMap<String, Arc<ProxyUserUsage>>> = Mutex::new(BTreeMap::new());
+
+// The key used to sign the Digest authentication nonces, generated once per process
+static DIGEST_NONCE_SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+
+// The issue timestamps of the Digest authentication nonces, and the nonce counts with the client nonces used with them,
+// so that the replayed credentials are refused
+type DigestNonceUses = BTreeMap<String, (u64, BTreeSet<(u64, String)>)>;
+static DIGEST_NONCE_USES: Mutex<DigestNonceUses> = Mutex::new(BTreeMap::new());
+
+// A destination of the forward proxy, matched against the host and the port of the request target
+#[derive(Debug, PartialEq)]
+pub struct ProxyDestinationRule {
+  host: String,
+  ports: Option<(u16, u16)>,
+}
+
+impl ProxyDestinationRule {
+  fn matches(&self, host: &str, port: u16) -> bool {
+    match_hostname(Some(&self.host), Some(&host.to_lowercase()))
+      && self
+        .ports
+        .is_none_or(|(first_port, last_port)| (first_port..=last_port).contains(&port))
+  }
+}
+
+// Parse a destination rule, for example "example.com", "*.example.com:443", "*:8000-8080" or "[::1]:443"
+fn parse_destination_rule(rule: &str) -> Option<ProxyDestinationRule> {
+  let (host, ports) = if let Some(bracketed) = rule.strip_prefix('[') {
+    let (host, rest) = bracketed.split_once(']')?;
+    match rest {
+      "" => (host, None),
+      _ => (host, Some(rest.strip_prefix(':')?)),
+    }
+  } else {
+    match rule.rsplit_once(':') {
+      Some((host, ports)) if !host.contains(':') => (host, Some(ports)),
+      _ => (rule, None),
+    }
+  };
+  if host.is_empty() {
+    return None;
+  }
+  let ports = match ports {
+    None | Some("*") => None,
+    Some(ports) => {
+      let (first_port, last_port) = ports.split_once('-').unwrap_or((ports, ports));
+      let first_port = first_port.parse::<u16>().ok()?;
+      let last_port = last_port.parse::<u16>().ok()?;
+      if first_port > last_port {
+        return None;
+      }
+      Some((first_port, last_port))
+    }
+  };
+  Some(ProxyDestinationRule {
+    host: host.to_lowercase(),
+    ports,
+  })
+}
+
+// Parse the list of the destination rules from the server configuration
+pub fn parse_destination_rules(
+  yaml: &Yaml,
+) -> Result<Vec<ProxyDestinationRule>, Box<dyn Error + Send + Sync>> {
+  if yaml.is_badvalue() {
+    return Ok(Vec::new());
+  }
+  let rules_yaml = match yaml.as_vec() {
+    Some(rules_yaml) => rules_yaml,
+    None => Err(anyhow::anyhow!(
+      "Invalid forward proxy destination list configuration"
+    ))?,
+  };
+  rules_yaml
+    .iter()
+    .map(|rule_yaml| {
+      rule_yaml
+        .as_str()
+        .and_then(parse_destination_rule)
+        .ok_or_else(|| {
+          anyhow::anyhow!(
+            "Invalid forward proxy destination: {}",
+            rule_yaml.as_str().unwrap_or("(not a string)")
+          )
+          .into()
+        })
+    })
+    .collect()
+}
+
+// Check the destination against the allowlist and the denylist. The denylist takes precedence over the allowlist.
+pub fn is_destination_allowed(
+  host: &str,
+  port: u16,
+  allowed: &[ProxyDestinationRule],
+  denied: &[ProxyDestinationRule],
+) -> bool {
+  let host = host.trim_start_matches('[').trim_end_matches(']');
+  !denied.iter().any(|rule| rule.matches(host, port))
+    && (allowed.is_empty() || allowed.iter().any(|rule| rule.matches(host, port)))
+}
+
+// Split the CONNECT request target (or the host of the forward proxy request) into the host and the port
+pub fn split_destination(authority: &str, default_port: u16) -> Option<(&str, u16)> {
+  if authority.starts_with('[') {
+    let bracket_index = authority.find(']')?;
+    let port = match &authority[bracket_index + 1..] {
+      "" => default_port,
+      rest => rest.strip_prefix(':')?.parse::<u16>().ok()?,
+    };
+    return Some((&authority[..=bracket_index], port));
+  }
+  match authority.rsplit_once(':') {
+    Some((host, port)) => Some((host, port.parse::<u16>().ok()?)),
+    None => Some((authority, default_port)),
+  }
+}
+
+// The reason why a forward proxy request has been refused, with the headers to send to the client
+pub struct ProxyDenial {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+}
+
+impl ProxyDenial {
+  fn new(status: StatusCode) -> Self {
+    Self {
+      status,
+      headers: HeaderMap::new(),
+    }
+  }
+}
+
+// The response extension marking the "Proxy-Authenticate" headers as sent by this proxy,
+// so that they aren't removed along with the hop-by-hop headers received from the backend servers
+#[derive(Clone)]
+pub struct ProxyAuthenticationChallenge;
+
+fn quote_realm(realm: &str) -> String {
+  realm.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+fn digest_nonce_mac(timestamp: u64) -> HmacSha256 {
+  let secret = DIGEST_NONCE_SECRET.get_or_init(rand::random::<[u8; 32]>);
+  let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any size");
+  mac.update(timestamp.to_string().as_bytes());
+  mac
+}
+
+// Issue a Digest authentication nonce, which is the issue timestamp signed with the per-process secret
+fn issue_digest_nonce() -> String {
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  format!(
+    "{}.{}",
+    timestamp,
+    general_purpose::URL_SAFE_NO_PAD.encode(digest_nonce_mac(timestamp).finalize().into_bytes())
+  )
+}
+
+// Check if the nonce has been issued by this process and hasn't expired yet. Returns the issue timestamp of the valid nonce.
+fn digest_nonce_timestamp(nonce: &str) -> Option<u64> {
+  let (timestamp, signature) = nonce.split_once('.')?;
+  let timestamp = timestamp.parse::<u64>().ok()?;
+  let signature = general_purpose::URL_SAFE_NO_PAD.decode(signature).ok()?;
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  (now.saturating_sub(timestamp) <= DIGEST_NONCE_LIFETIME.as_secs()
+    && digest_nonce_mac(timestamp).verify_slice(&signature).is_ok())
+  .then_some(timestamp)
+}
+
+// Record the use of the nonce with the nonce count and the client nonce, forgetting the expired nonces.
+// Returns false if the nonce was already used with the same nonce count and client nonce.
+fn record_digest_nonce_use(nonce: &str, timestamp: u64, nonce_count: u64, cnonce: &str) -> bool {
+  let mut digest_nonce_uses = match DIGEST_NONCE_USES.lock() {
+    Ok(digest_nonce_uses) => digest_nonce_uses,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  digest_nonce_uses.retain(|_, (issue_timestamp, _)| {
+    now.saturating_sub(*issue_timestamp) <= DIGEST_NONCE_LIFETIME.as_secs()
+  });
+  digest_nonce_uses
+    .entry(nonce.to_string())
+    .or_insert_with(|| (timestamp, BTreeSet::new()))
+    .1
+    .insert((nonce_count, cnonce.to_string()))
+}
+
+fn sha256_hex(data: &str) -> String {
+  Sha256::digest(data.as_bytes())
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+// Parse the parameters of the Digest credentials, for example 'username="user", nc=00000001'
+fn parse_digest_params(params: &str) -> HashMap<String, String> {
+  let mut parsed_params = HashMap::new();
+  let mut chars = params.chars().peekable();
+  loop {
+    while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| *c != '=' && *c != ',') {
+      name.push(c);
+    }
+    if name.is_empty() {
+      break;
+    }
+    let mut value = String::new();
+    if chars.next_if_eq(&'=').is_some() {
+      if chars.next_if_eq(&'"').is_some() {
+        while let Some(c) = chars.next() {
+          match c {
+            '\\' => value.extend(chars.next()),
+            '"' => break,
+            _ => value.push(c),
+          }
+        }
+      } else {
+        while let Some(c) = chars.next_if(|c| *c != ',') {
+          value.push(c);
+        }
+      }
+    }
+    parsed_params.insert(name.trim().to_lowercase(), value.trim().to_string());
+  }
+  parsed_params
+}
+
+// Verify the Digest credentials (RFC 7616, with the SHA-256 algorithm) against the configured "digestHash" values.
+// The "digestHash" value is the hexadecimal SHA-256 hash of "username:realm:password".
+// The credentials must be for the request target, and can't be replayed with the same nonce count and client nonce.
+fn verify_digest_credentials(
+  params: &HashMap<String, String>,
+  method: &str,
+  request_target: &str,
+  realm: &str,
+  users: &[Yaml],
+) -> Option<String> {
+  let username = params.get("username")?;
+  let nonce = params.get("nonce")?;
+  let uri = params.get("uri")?;
+  let response = params.get("response")?;
+  if params.get("realm").map(|r| r.as_str()) != Some(realm)
+    || uri != request_target
+    || params
+      .get("algorithm")
+      .is_some_and(|algorithm| !algorithm.eq_ignore_ascii_case("SHA-256"))
+  {
+    return None;
+  }
+  let nonce_timestamp = digest_nonce_timestamp(nonce)?;
+  let digest_hash = users
+    .iter()
+    .find(|user_yaml| user_yaml["name"].as_str() == Some(username))
+    .and_then(|user_yaml| user_yaml["digestHash"].as_str())?
+    .to_lowercase();
+  let ha2 = sha256_hex(&format!("{}:{}", method, uri));
+  // Without the "qop" parameter, there's no nonce count, so the nonce can be used only once
+  let (expected_response, nonce_count, cnonce) = match params.get("qop").map(|qop| qop.as_str()) {
+    Some("auth") => {
+      let nc = params.get("nc")?;
+      let cnonce = params.get("cnonce")?;
+      (
+        sha256_hex(&format!(
+          "{}:{}:{}:{}:auth:{}",
+          digest_hash, nonce, nc, cnonce, ha2
+        )),
+        u64::from_str_radix(nc, 16).ok()?,
+        cnonce.as_str(),
+      )
+    }
+    Some(_) => return None,
+    None => (
+      sha256_hex(&format!("{}:{}:{}", digest_hash, nonce, ha2)),
+      0,
+      "",
+    ),
+  };
+  // Compare the responses in constant time
+  let response = response.to_lowercase();
+  let is_response_valid = response.len() == expected_response.len()
+    && response
+      .bytes()
+      .zip(expected_response.bytes())
+      .fold(0, |difference, (a, b)| difference | (a ^ b))
+      == 0;
+  (is_response_valid && record_digest_nonce_use(nonce, nonce_timestamp, nonce_count, cnonce))
+    .then(|| username.to_string())
+}
+
+// Parse the Basic credentials from the "Proxy-Authorization" header value
+fn parse_basic_credentials(credentials: &str) -> Option<(String, String)> {
+  let decoded = general_purpose::STANDARD.decode(credentials).ok()?;
+  let decoded_str = String::from_utf8(decoded).ok()?;
+  let (username, password) = decoded_str.split_once(':')?;
+  Some((username.to_string(), password.to_string()))
+}
+
+// Verify the Basic credentials against the password hashes of the configured users
+async fn verify_basic_credentials(
+  credentials: &str,
+  users: &[Yaml],
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+  let (username, password) = match parse_basic_credentials(credentials) {
+    Some(credentials) => credentials,
+    None => return Ok(None),
+  };
+  let password_hash = match users
+    .iter()
+    .find(|user_yaml| user_yaml["name"].as_str() == Some(&username))
+    .and_then(|user_yaml| user_yaml["pass"].as_str())
+  {
+    Some(password_hash) => password_hash.to_string(),
+    None => return Ok(None),
+  };
+  // Offload verifying the hash into a separate blocking thread.
+  let password_valid = spawn_blocking_budgeted("fproxy", move || {
+    verify_password(password, &password_hash).is_ok()
+  })
+  .await?;
+  Ok(password_valid.then_some(username))
+}
+
+// Authenticate the forward proxy client with the "Proxy-Authorization" header (if enabled),
+// and check the destination against the allowlist and the denylist.
+// Returns the authenticated user name, or the denial to send to the client.
+pub async fn authorize_proxy_request(
+  headers: &HeaderMap,
+  method: &str,
+  request_target: &str,
+  destination: (&str, u16),
+  config: &ServerConfigRoot,
+  client_ip: IpAddr,
+  error_logger: &ErrorLogger,
+) -> Result<Result<Option<String>, ProxyDenial>, Box<dyn Error + Send + Sync>> {
+  let mut auth_user = None;
+  if config
+    .get("forwardProxyAuthentication")
+    .as_bool()
+    .unwrap_or(false)
+  {
+    let realm = config
+      .get("forwardProxyRealm")
+      .as_str()
+      .unwrap_or(DEFAULT_REALM)
+      .to_string();
+    let users = config.get("users").as_vec().cloned().unwrap_or_default();
+    let is_digest_enabled = users
+      .iter()
+      .any(|user_yaml| user_yaml["digestHash"].as_str().is_some());
+
+    if let Some(authorization) = headers
+      .get(header::PROXY_AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+    {
+      let (scheme, credentials) = authorization
+        .trim()
+        .split_once(' ')
+        .unwrap_or((authorization, ""));
+      auth_user = if scheme.eq_ignore_ascii_case("Basic") {
+        verify_basic_credentials(credentials.trim(), &users).await?
+      } else if scheme.eq_ignore_ascii_case("Digest") && is_digest_enabled {
+        verify_digest_credentials(
+          &parse_digest_params(credentials),
+          method,
+          request_target,
+          &realm,
+          &users,
+        )
+      } else {
+        None
+      };
+      if auth_user.is_none() {
+        METRICS.increment_counter(
+          "ferron_forward_proxy_denials_total",
+          &[("reason", "authentication")],
+        );
+        error_logger
+          .log(&format!(
+            "Forward proxy authorization failed for client \"{}\"",
+            client_ip
+          ))
+          .await;
+      }
+    }
+
+    if auth_user.is_none() {
+      let mut denial = ProxyDenial::new(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+      denial.headers.append(
+        header::PROXY_AUTHENTICATE,
+        HeaderValue::from_str(&format!(
+          "Basic realm=\"{}\", charset=\"UTF-8\"",
+          quote_realm(&realm)
+        ))?,
+      );
+      if is_digest_enabled {
+        denial.headers.append(
+          header::PROXY_AUTHENTICATE,
+          HeaderValue::from_str(&format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm=SHA-256, nonce=\"{}\"",
+            quote_realm(&realm),
+            issue_digest_nonce()
+          ))?,
+        );
+      }
+      return Ok(Err(denial));
+    }
+  }
+
+  let allowed = parse_destination_rules(&config.get("forwardProxyAllowedDestinations"))?;
+  let denied = parse_destination_rules(&config.get("forwardProxyDeniedDestinations"))?;
+  let (host, port) = destination;
+  if !is_destination_allowed(host, port, &allowed, &denied) {
+    METRICS.increment_counter(
+      "ferron_forward_proxy_denials_total",
+      &[("reason", "destination")],
+    );
+    error_logger
+      .log(&format!(
+        "Forward proxy destination \"{}\" is not allowed for client \"{}\"{}",
+        request_target,
+        client_ip,
+        match &auth_user {
+          Some(auth_user) => format!(" and user \"{}\"", auth_user),
+          None => String::new(),
+        }
+      ))
+      .await;
+    return Ok(Err(ProxyDenial::new(StatusCode::FORBIDDEN)));
+  }
+
+  Ok(Ok(auth_user))
+}
+
+// The key, under which the limits of the forward proxy user are tracked. Unauthenticated clients are tracked by their IP address.
+pub fn proxy_user_key(auth_user: Option<&str>, client_ip: IpAddr) -> String {
+  match auth_user {
+    Some(auth_user) => format!("user:{}", auth_user),
+    None => format!("ip:{}", client_ip.to_canonical()),
+  }
+}
+
+// The active connections and the bandwidth budget of a forward proxy user
+pub struct ProxyUserUsage {
+  connections: AtomicUsize,
+  bandwidth: TokenBucket,
+}
+
+impl ProxyUserUsage {
+  // Wait until the user's bandwidth budget allows transferring the bytes. The budget is shared between all the user's connections.
+  pub async fn throttle(&self, bytes: usize, bytes_per_second: u64) {
+    self.bandwidth.set_rate(bytes_per_second);
+    let delay = self.bandwidth.consume(bytes);
+    if !delay.is_zero() {
+      tokio::time::sleep(delay).await;
+    }
+  }
+}
+
+// A connection slot of a forward proxy user, released when dropped
+pub struct ProxyConnectionGuard {
+  key: String,
+  usage: Arc<ProxyUserUsage>,
+}
+
+impl Drop for ProxyConnectionGuard {
+  fn drop(&mut self) {
+    let mut proxy_users = match PROXY_USERS.lock() {
+      Ok(proxy_users) => proxy_users,
+      Err(poisoned) => poisoned.into_inner(),
+    };
+    if self.usage.connections.fetch_sub(1, Ordering::Relaxed) == 1 {
+      proxy_users.remove(&self.key);
+    }
+  }
+}
+
+// Take a connection slot of the forward proxy user. Returns `None`, if the user already has the maximum number of connections.
+pub fn acquire_proxy_connection(
+  key: &str,
+  max_connections: Option<usize>,
+) -> Option<ProxyConnectionGuard> {
+  let mut proxy_users = match PROXY_USERS.lock() {
+    Ok(proxy_users) => proxy_users,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  let usage = proxy_users
+    .entry(key.to_string())
+    .or_insert_with(|| {
+      Arc::new(ProxyUserUsage {
+        connections: AtomicUsize::new(0),
+        bandwidth: TokenBucket::new(0),
+      })
+    })
+    .clone();
+  let connections = usage.connections.load(Ordering::Relaxed);
+  if max_connections.is_some_and(|max_connections| connections >= max_connections) {
+    if connections == 0 {
+      proxy_users.remove(key);
+    }
+    return None;
+  }
+  usage.connections.fetch_add(1, Ordering::Relaxed);
+  Some(ProxyConnectionGuard {
+    key: key.to_string(),
+    usage,
+  })
+}
+
+// Get the usage of the forward proxy user with at least one active connection
+pub fn proxy_user_usage(key: &str) -> Option<Arc<ProxyUserUsage>> {
+  let proxy_users = match PROXY_USERS.lock() {
+    Ok(proxy_users) => proxy_users,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  proxy_users.get(key).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_destination_rules() {
+    let allowed = parse_destination_rules(&Yaml::Array(vec![
+      Yaml::String("*.example.com:443".to_string()),
+      Yaml::String("example.org".to_string()),
+      Yaml::String("*:8000-8080".to_string()),
+      Yaml::String("[::1]:22".to_string()),
+    ]))
+    .unwrap();
+    let denied = parse_destination_rules(&Yaml::Array(vec![Yaml::String(
+      "internal.example.com".to_string(),
+    )]))
+    .unwrap();
+
+    assert!(is_destination_allowed(
+      "www.example.com",
+      443,
+      &allowed,
+      &denied
+    ));
+    assert!(is_destination_allowed(
+      "Example.COM",
+      443,
+      &allowed,
+      &denied
+    ));
+    assert!(!is_destination_allowed(
+      "www.example.com",
+      80,
+      &allowed,
+      &denied
+    ));
+    assert!(is_destination_allowed("example.org", 25, &allowed, &denied));
+    assert!(is_destination_allowed(
+      "anything.test",
+      8080,
+      &allowed,
+      &denied
+    ));
+    assert!(!is_destination_allowed(
+      "anything.test",
+      8081,
+      &allowed,
+      &denied
+    ));
+    assert!(is_destination_allowed("[::1]", 22, &allowed, &denied));
+    assert!(!is_destination_allowed(
+      "internal.example.com",
+      443,
+      &allowed,
+      &denied
+    ));
+    assert!(is_destination_allowed("anything.test", 1, &[], &[]));
+
+    for invalid_rule in ["", ":443", "example.com:80-70", "example.com:http", "[::1"] {
+      assert!(
+        parse_destination_rules(&Yaml::Array(vec![Yaml::String(invalid_rule.to_string())]))
+          .is_err()
+      );
+    }
+  }
+
+  #[test]
+  fn test_split_destination() {
+    assert_eq!(
+      split_destination("example.com:443", 80),
+      Some(("example.com", 443))
+    );
+    assert_eq!(
+      split_destination("example.com", 80),
+      Some(("example.com", 80))
+    );
+    assert_eq!(split_destination("[::1]:8443", 80), Some(("[::1]", 8443)));
+    assert_eq!(split_destination("example.com:port", 80), None);
+  }
+
+  #[test]
+  fn test_digest_credentials() {
+    let users = vec![{
+      let mut user_yaml = yaml_rust2::yaml::Hash::new();
+      user_yaml.insert(
+        Yaml::String("name".to_string()),
+        Yaml::String("test".to_string()),
+      );
+      user_yaml.insert(
+        Yaml::String("digestHash".to_string()),
+        Yaml::String(sha256_hex("test:Proxy:secret")),
+      );
+      Yaml::Hash(user_yaml)
+    }];
+    let nonce = issue_digest_nonce();
+    let ha1 = sha256_hex("test:Proxy:secret");
+    let ha2 = sha256_hex("CONNECT:example.com:443");
+    let response = sha256_hex(&format!("{}:{}:00000001:abc:auth:{}", ha1, nonce, ha2));
+    let credentials = format!(
+      "username=\"test\", realm=\"Proxy\", nonce=\"{}\", uri=\"example.com:443\", algorithm=SHA-256, qop=auth, nc=00000001, cnonce=\"abc\", response=\"{}\"",
+      nonce, response
+    );
+
+    let params = parse_digest_params(&credentials);
+    let target = "example.com:443";
+    assert_eq!(
+      verify_digest_credentials(&params, "CONNECT", target, "Other realm", &users),
+      None
+    );
+    assert_eq!(
+      verify_digest_credentials(&params, "GET", target, "Proxy", &users),
+      None
+    );
+    assert_eq!(
+      verify_digest_credentials(&params, "CONNECT", "other.example.com:443", "Proxy", &users),
+      None
+    );
+    assert_eq!(
+      verify_digest_credentials(&params, "CONNECT", target, "Proxy", &users),
+      Some("test".to_string())
+    );
+
+    // The credentials can't be replayed, but the nonce can be used with the next nonce count
+    assert_eq!(
+      verify_digest_credentials(&params, "CONNECT", target, "Proxy", &users),
+      None
+    );
+    let next_response = sha256_hex(&format!("{}:{}:00000002:abc:auth:{}", ha1, nonce, ha2));
+    let next_params = parse_digest_params(
+      &credentials
+        .replace("00000001", "00000002")
+        .replace(&response, &next_response),
+    );
+    assert_eq!(
+      verify_digest_credentials(&next_params, "CONNECT", target, "Proxy", &users),
+      Some("test".to_string())
+    );
+
+    let forged_params = parse_digest_params(&credentials.replace(&nonce, "0.forged"));
+    assert_eq!(
+      verify_digest_credentials(&forged_params, "CONNECT", target, "Proxy", &users),
+      None
+    );
+  }
+
+  #[test]
+  fn test_parse_basic_credentials() {
+    assert_eq!(
+      parse_basic_credentials("dGVzdDpwYXNzOndvcmQ="),
+      Some(("test".to_string(), "pass:word".to_string()))
+    );
+    assert_eq!(parse_basic_credentials("not base64"), None);
+  }
+
+  #[test]
+  fn test_proxy_connection_limit() {
+    let key = "user:connection-limit-test";
+    let first_guard = acquire_proxy_connection(key, Some(2)).unwrap();
+    let second_guard = acquire_proxy_connection(key, Some(2)).unwrap();
+    assert!(acquire_proxy_connection(key, Some(2)).is_none());
+    assert!(Arc::ptr_eq(
+      &first_guard.usage,
+      &proxy_user_usage(key).unwrap()
+    ));
+    drop(first_guard);
+    assert!(acquire_proxy_connection(key, Some(2)).is_some());
+    drop(second_guard);
+    assert!(proxy_user_usage(key).is_none());
+    assert!(acquire_proxy_connection(key, Some(0)).is_none());
+    assert!(proxy_user_usage(key).is_none());
+  }
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use ferron_common::ServerConfig;
+use hyper::header::{self, HeaderMap};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+
+// Parse a node identifier from the "X-Forwarded-For", "X-Real-IP" or "Forwarded" header.
+// The identifier can be an IP address with an optional port, and IPv6 addresses can be enclosed in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+  let node = node.trim();
+  if let Ok(ip) = node.parse::<IpAddr>() {
+    return Some(ip);
+  }
+  if let Ok(socket_addr) = node.parse::<SocketAddr>() {
+    return Some(socket_addr.ip());
+  }
+  node
+    .strip_prefix('[')
+    .and_then(|node| node.strip_suffix(']'))
+    .and_then(|ip| ip.parse::<IpAddr>().ok())
+}
+
+// Parse the "X-Forwarded-For" header into the chain of client addresses, from the client to the last proxy.
+// Returns None if any of the addresses is invalid.
+pub fn parse_x_forwarded_for(header_value: &str) -> Option<Vec<Option<IpAddr>>> {
+  header_value
+    .split(',')
+    .map(|node| parse_node(node).map(Some))
+    .collect()
+}
+
+// Parse the "Forwarded" header (RFC 7239) into the chain of client addresses, from the client to the last proxy.
+// The "unknown" and obfuscated identifiers are represented as None. Returns None if the header is malformed.
+pub fn parse_forwarded(header_value: &str) -> Option<Vec<Option<IpAddr>>> {
+  let mut chain = Vec::new();
+  let mut element = String::new();
+  let mut in_quotes = false;
+  let mut elements = Vec::new();
+  for character in header_value.chars() {
+    match character {
+      '"' => {
+        in_quotes = !in_quotes;
+        element.push(character);
+      }
+      ',' if !in_quotes => elements.push(std::mem::take(&mut element)),
+      _ => element.push(character),
+    }
+  }
+  if in_quotes {
+    return None;
+  }
+  elements.push(element);
+
+  for element in elements {
+    let mut node = None;
+    for pair in element.split(';') {
+      let pair = pair.trim();
+      if pair.is_empty() {
+        continue;
+      }
+      let (name, value) = pair.split_once('=')?;
+      if name.trim().eq_ignore_ascii_case("for") {
+        let value = value.trim();
+        let value = match value.strip_prefix('"') {
+          Some(value) => value.strip_suffix('"')?,
+          None => value,
+        };
+        node = parse_node(value);
+      }
+    }
+    chain.push(node);
+  }
+  Some(chain)
+}
+
+// The header the client address is read from. Only one header is trusted, because the proxy
+// sets only one of them, and the client could send the other ones to spoof its address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientIpHeader {
+  Forwarded,
+  XForwardedFor,
+  XRealIp,
+}
+
+impl ClientIpHeader {
+  // Parse the header name from the configuration. The header is "X-Forwarded-For" if it isn't set.
+  // Returns None if the header isn't supported.
+  pub fn from_config(header_name: Option<&str>) -> Option<Self> {
+    match header_name
+      .map(|header_name| header_name.to_lowercase())
+      .as_deref()
+    {
+      None | Some("x-forwarded-for") => Some(ClientIpHeader::XForwardedFor),
+      Some("forwarded") => Some(ClientIpHeader::Forwarded),
+      Some("x-real-ip") => Some(ClientIpHeader::XRealIp),
+      _ => None,
+    }
+  }
+}
+
+// Parse the chain of client addresses from the trusted header. All the header lines are joined,
+// since a proxy can append its own line after the lines sent by the client.
+// Returns an empty chain if the header isn't present, and None if the header is malformed.
+pub fn parse_client_ip_chain(
+  headers: &HeaderMap,
+  client_ip_header: ClientIpHeader,
+) -> Option<Vec<Option<IpAddr>>> {
+  let header_name = match client_ip_header {
+    ClientIpHeader::Forwarded => header::FORWARDED.as_str(),
+    ClientIpHeader::XForwardedFor => "x-forwarded-for",
+    ClientIpHeader::XRealIp => "x-real-ip",
+  };
+  let mut header_values = Vec::new();
+  for header_value in headers.get_all(header_name) {
+    header_values.push(header_value.to_str().ok()?);
+  }
+  if header_values.is_empty() {
+    return Some(Vec::new());
+  }
+  let header_value = header_values.join(",");
+  match client_ip_header {
+    ClientIpHeader::Forwarded => parse_forwarded(&header_value),
+    ClientIpHeader::XForwardedFor | ClientIpHeader::XRealIp => parse_x_forwarded_for(&header_value),
+  }
+}
+
+// Select the client address from the chain of addresses forwarded by the trusted proxies.
+// The chain is walked from the last proxy towards the client, and the first address that isn't
+// a trusted proxy is the client address. The addresses before it could have been spoofed by the client.
+// Returns None if the client address is unknown.
+pub fn select_client_ip(
+  chain: &[Option<IpAddr>],
+  trusted_proxies: &IpPrefixTrie,
+) -> Option<IpAddr> {
+  for node in chain.iter().rev() {
+    match node {
+      Some(ip) if trusted_proxies.contains(*ip) => continue,
+      _ => return *node,
+    }
+  }
+  // All the addresses in the chain are trusted proxies
+  chain.first().copied().flatten()
+}
+
+// The tries of the trusted proxies configured with the "trustedProxies" properties, parsed once per configuration,
+// so that they don't have to be built for each request
+#[derive(Default)]
+pub struct TrustedProxies {
+  tries: HashMap<Yaml, Arc<IpPrefixTrie>>,
+}
+
+impl TrustedProxies {
+  // Parse the "trustedProxies" properties of the global configuration, the hosts and the locations
+  pub fn from_config(config: &ServerConfig) -> Self {
+    let mut trusted_proxies = Self::default();
+    trusted_proxies.insert(&config["global"]["trustedProxies"]);
+    if let Some(hosts) = config["hosts"].as_vec() {
+      for host_yaml in hosts.iter() {
+        trusted_proxies.insert(&host_yaml["trustedProxies"]);
+        if let Some(locations) = host_yaml["locations"].as_vec() {
+          for location_yaml in locations.iter() {
+            trusted_proxies.insert(&location_yaml["trustedProxies"]);
+          }
+        }
+      }
+    }
+    trusted_proxies
+  }
+
+  fn insert(&mut self, trusted_proxies_yaml: &Yaml) {
+    if let Some(trusted_proxy_list) = trusted_proxies_yaml.as_vec() {
+      self
+        .tries
+        .entry(trusted_proxies_yaml.clone())
+        .or_insert_with(|| Arc::new(trusted_proxies_trie(trusted_proxy_list)));
+    }
+  }
+
+  // Get the trie of the trusted proxies configured with the "trustedProxies" property.
+  // Returns None if the trusted proxies aren't configured.
+  pub fn get(&self, trusted_proxies_yaml: &Yaml) -> Option<Arc<IpPrefixTrie>> {
+    let trusted_proxy_list = trusted_proxies_yaml.as_vec()?;
+    match self.tries.get(trusted_proxies_yaml) {
+      Some(trie) => Some(trie.clone()),
+      None => Some(Arc::new(trusted_proxies_trie(trusted_proxy_list))),
+    }
+  }
+}
+
+// Build the trie of the trusted proxy addresses and CIDR ranges
+fn trusted_proxies_trie(trusted_proxy_list: &[Yaml]) -> IpPrefixTrie {
+  let mut trie = IpPrefixTrie::new();
+  for trusted_proxy in trusted_proxy_list {
+    if let Some(trusted_proxy) = trusted_proxy.as_str() {
+      trie.insert_cidr(trusted_proxy);
+    }
+  }
+  trie
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ip(ip: &str) -> Option<IpAddr> {
+    Some(ip.parse().unwrap())
+  }
+
+  #[test]
+  fn test_parse_x_forwarded_for() {
+    assert_eq!(
+      parse_x_forwarded_for("203.0.113.7, 10.0.0.1"),
+      Some(vec![ip("203.0.113.7"), ip("10.0.0.1")])
+    );
+    assert_eq!(
+      parse_x_forwarded_for("203.0.113.7:4711, [2001:db8::1]:443"),
+      Some(vec![ip("203.0.113.7"), ip("2001:db8::1")])
+    );
+    assert_eq!(parse_x_forwarded_for("203.0.113.7, garbage"), None);
+  }
+
+  #[test]
+  fn test_parse_forwarded() {
+    assert_eq!(
+      parse_forwarded("for=192.0.2.43, for=\"[2001:db8:cafe::17]:4711\";proto=https"),
+      Some(vec![ip("192.0.2.43"), ip("2001:db8:cafe::17")])
+    );
+    assert_eq!(
+      parse_forwarded("For=unknown;by=10.0.0.1, for=_hidden, proto=http"),
+      Some(vec![None, None, None])
+    );
+    assert_eq!(parse_forwarded("for=\"192.0.2.43"), None);
+    assert_eq!(parse_forwarded("for"), None);
+  }
+
+  #[test]
+  fn test_client_ip_header_from_config() {
+    assert_eq!(
+      ClientIpHeader::from_config(None),
+      Some(ClientIpHeader::XForwardedFor)
+    );
+    assert_eq!(
+      ClientIpHeader::from_config(Some("Forwarded")),
+      Some(ClientIpHeader::Forwarded)
+    );
+    assert_eq!(
+      ClientIpHeader::from_config(Some("X-Real-IP")),
+      Some(ClientIpHeader::XRealIp)
+    );
+    assert_eq!(ClientIpHeader::from_config(Some("Via")), None);
+  }
+
+  #[test]
+  fn test_other_headers_are_ignored() {
+    let mut trusted_proxies = IpPrefixTrie::new();
+    trusted_proxies.insert_cidr("10.0.0.0/8");
+
+    // The proxy sets "X-Forwarded-For", and the client sends the "Forwarded" header to spoof its address
+    let mut headers = HeaderMap::new();
+    headers.insert(header::FORWARDED, "for=198.51.100.1".parse().unwrap());
+    headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+    let chain = parse_client_ip_chain(&headers, ClientIpHeader::XForwardedFor).unwrap();
+    assert_eq!(
+      select_client_ip(&chain, &trusted_proxies),
+      ip("203.0.113.7")
+    );
+
+    headers.remove("x-forwarded-for");
+    assert_eq!(
+      parse_client_ip_chain(&headers, ClientIpHeader::XForwardedFor),
+      Some(Vec::new())
+    );
+  }
+
+  #[test]
+  fn test_all_header_lines_are_used() {
+    let mut trusted_proxies = IpPrefixTrie::new();
+    trusted_proxies.insert_cidr("10.0.0.0/8");
+
+    // The client sends its own header line, and the proxy appends another line instead of joining them
+    let mut headers = HeaderMap::new();
+    headers.append("x-forwarded-for", "198.51.100.1".parse().unwrap());
+    headers.append("x-forwarded-for", "203.0.113.7, 10.0.0.2".parse().unwrap());
+    let chain = parse_client_ip_chain(&headers, ClientIpHeader::XForwardedFor).unwrap();
+    assert_eq!(
+      chain,
+      vec![ip("198.51.100.1"), ip("203.0.113.7"), ip("10.0.0.2")]
+    );
+    assert_eq!(
+      select_client_ip(&chain, &trusted_proxies),
+      ip("203.0.113.7")
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.append(header::FORWARDED, "for=198.51.100.1".parse().unwrap());
+    headers.append(header::FORWARDED, "for=203.0.113.7".parse().unwrap());
+    let chain = parse_client_ip_chain(&headers, ClientIpHeader::Forwarded).unwrap();
+    assert_eq!(
+      select_client_ip(&chain, &trusted_proxies),
+      ip("203.0.113.7")
+    );
+  }
+
+  #[test]
+  fn test_select_client_ip() {
+    let mut trusted_proxies = IpPrefixTrie::new();
+    trusted_proxies.insert_cidr("10.0.0.0/8");
+
+    let chain = [ip("198.51.100.1"), ip("203.0.113.7"), ip("10.0.0.2")];
+    // The client can prepend the spoofed addresses, so the address added by the first trusted proxy is used
+    assert_eq!(
+      select_client_ip(&chain, &trusted_proxies),
+      ip("203.0.113.7")
+    );
+    assert_eq!(
+      select_client_ip(&[ip("10.0.0.3"), ip("10.0.0.2")], &trusted_proxies),
+      ip("10.0.0.3")
+    );
+    assert_eq!(
+      select_client_ip(&[ip("203.0.113.7"), None, ip("10.0.0.2")], &trusted_proxies),
+      None
+    );
+  }
+
+  #[test]
+  fn test_trusted_proxies_parsed_once() {
+    let config = yaml_rust2::YamlLoader::load_from_str(
+      "global:\n  trustedProxies: [\"10.0.0.0/8\"]\nhosts:\n  - domain: example.com\n    trustedProxies: [\"192.0.2.1\"]\n    locations:\n      - path: /api\n        trustedProxies: [\"10.0.0.0/8\"]",
+    )
+    .unwrap()
+    .remove(0);
+    let trusted_proxies = TrustedProxies::from_config(&config);
+    assert_eq!(trusted_proxies.tries.len(), 2);
+
+    let global_trie = trusted_proxies
+      .get(&config["global"]["trustedProxies"])
+      .unwrap();
+    assert!(global_trie.contains("10.1.2.3".parse().unwrap()));
+    assert!(!global_trie.contains("192.0.2.1".parse().unwrap()));
+    assert!(Arc::ptr_eq(
+      &global_trie,
+      &trusted_proxies
+        .get(&config["hosts"][0]["locations"][0]["trustedProxies"])
+        .unwrap()
+    ));
+    let host_trie = trusted_proxies
+      .get(&config["hosts"][0]["trustedProxies"])
+      .unwrap();
+    assert!(host_trie.contains("192.0.2.1".parse().unwrap()));
+
+    assert!(trusted_proxies.get(&Yaml::BadValue).is_none());
+  }
+}
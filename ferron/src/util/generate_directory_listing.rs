@@ -1,4 +1,6 @@
+use std::cmp::Ordering;
 use std::error::Error;
+use std::time::SystemTime;
 
 use chrono::{DateTime, Local};
 use tokio::fs::ReadDir;
@@ -6,10 +8,94 @@ use tokio::fs::ReadDir;
 use crate::ferron_util::anti_xss::anti_xss;
 use crate::ferron_util::sizify::sizify;
 
+// The default directory listing template. The "{path}", "{rows}" and "{description}" placeholders are replaced with the listing contents.
+const DEFAULT_DIRECTORY_LISTING_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"UTF-8\">
+    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
+    <title>Directory: {path}</title>
+</head>
+<body>
+    <h1>Directory: {path}</h1>
+    <table>
+      {rows}
+      {description}
+    </table>
+</body>
+</html>";
+
+// The column the directory listing is sorted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DirectoryListingSortKey {
+  Name,
+  Size,
+  Date,
+}
+
+// The sorting order of the directory listing, obtained from the "sort" and "order" query parameters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectoryListingSort {
+  pub key: DirectoryListingSortKey,
+  pub descending: bool,
+}
+
+impl DirectoryListingSort {
+  pub fn from_query(query: Option<&str>) -> Self {
+    let mut sort = Self {
+      key: DirectoryListingSortKey::Name,
+      descending: false,
+    };
+    if let Some(query) = query {
+      for (name, value) in query
+        .split('&')
+        .filter_map(|query_parameter| query_parameter.split_once('='))
+      {
+        match (name, value) {
+          ("sort", "name") => sort.key = DirectoryListingSortKey::Name,
+          ("sort", "size") => sort.key = DirectoryListingSortKey::Size,
+          ("sort", "date") => sort.key = DirectoryListingSortKey::Date,
+          ("order", "asc") => sort.descending = false,
+          ("order", "desc") => sort.descending = true,
+          _ => (),
+        }
+      }
+    }
+    sort
+  }
+
+  // Generate a table header cell with a link, which sorts the listing by a specified column
+  fn header_link(&self, key: DirectoryListingSortKey, label: &str) -> String {
+    let key_name = match key {
+      DirectoryListingSortKey::Name => "name",
+      DirectoryListingSortKey::Size => "size",
+      DirectoryListingSortKey::Date => "date",
+    };
+    // Clicking the header of the column the listing is sorted by reverses the order
+    let order = match self.key == key && !self.descending {
+      true => "desc",
+      false => "asc",
+    };
+    format!(
+      "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+      key_name, order, label
+    )
+  }
+}
+
+struct DirectoryListingEntry {
+  filename: String,
+  is_dir: bool,
+  size: Option<u64>,
+  modified: Option<SystemTime>,
+}
+
 pub async fn generate_directory_listing(
   mut directory: ReadDir,
   request_path: &str,
   description: Option<String>,
+  sort: DirectoryListingSort,
+  template: Option<String>,
 ) -> Result<String, Box<dyn Error + Send + Sync>> {
   let mut request_path_without_trailing_slashes = request_path;
   while request_path_without_trailing_slashes.ends_with("/") {
@@ -23,7 +109,12 @@ pub async fn generate_directory_listing(
   return_path_vec.push("");
   let return_path = &return_path_vec.join("/") as &str;
 
-  let mut table_rows = Vec::new();
+  let mut table_rows = vec![format!(
+    "<tr>{}{}{}</tr>",
+    sort.header_link(DirectoryListingSortKey::Name, "Filename"),
+    sort.header_link(DirectoryListingSortKey::Size, "Size"),
+    sort.header_link(DirectoryListingSortKey::Date, "Date")
+  )];
   if !request_path_without_trailing_slashes.is_empty() {
     table_rows.push(format!(
       "<tr><td><a href=\"{}\">Return</a></td><td></td><td></td></tr>",
@@ -32,98 +123,184 @@ pub async fn generate_directory_listing(
   }
   let min_table_rows_length = table_rows.len();
 
-  // Create a vector containing entries, then sort them by file name.
+  // Create a vector containing entries, then sort them.
   let mut entries = Vec::new();
   while let Some(entry) = directory.next_entry().await? {
-    entries.push(entry);
-  }
-  entries.sort_by_cached_key(|entry| entry.file_name().to_string_lossy().to_string());
-
-  for entry in entries.iter() {
     let filename = entry.file_name().to_string_lossy().to_string();
     if filename.starts_with('.') {
       // Don't add files nor directories with "." at the beginning of their names
       continue;
     }
-    match entry.metadata().await {
-      Ok(metadata) => {
-        let filename_link = format!(
-          "<a href=\"{}/{}{}\">{}</a>",
-          request_path_without_trailing_slashes,
-          anti_xss(urlencoding::encode(&filename).as_ref()),
-          match metadata.is_dir() {
-            true => "/",
-            false => "",
-          },
-          anti_xss(&filename)
-        );
-
-        let row = format!(
-          "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-          filename_link,
-          match metadata.is_file() {
-            true => anti_xss(&sizify(metadata.len(), false)),
-            false => "-".to_string(),
-          },
-          anti_xss(
-            &(match metadata.modified() {
-              Ok(mtime) => {
-                let datetime: DateTime<Local> = mtime.into();
-                datetime.format("%a %b %d %Y").to_string()
-              }
-              Err(_) => "-".to_string(),
-            })
-          )
-        );
-        table_rows.push(row);
-      }
-      Err(_) => {
-        let filename_link = format!(
-          "<a href=\"{}{}{}\">{}</a>",
-          "{}{}",
-          request_path_without_trailing_slashes,
-          anti_xss(urlencoding::encode(&filename).as_ref()),
-          anti_xss(&filename)
-        );
-        let row = format!("<tr><td>{}</td><td>-</td><td>-</td></tr>", filename_link);
-        table_rows.push(row);
-      }
-    };
+    let metadata = entry.metadata().await.ok();
+    entries.push(DirectoryListingEntry {
+      filename,
+      is_dir: metadata.as_ref().is_some_and(|metadata| metadata.is_dir()),
+      size: metadata
+        .as_ref()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len()),
+      modified: metadata.and_then(|metadata| metadata.modified().ok()),
+    });
   }
+  entries.sort_by(|a, b| {
+    let ordering = match sort.key {
+      DirectoryListingSortKey::Name => Ordering::Equal,
+      DirectoryListingSortKey::Size => a.size.cmp(&b.size),
+      DirectoryListingSortKey::Date => a.modified.cmp(&b.modified),
+    }
+    .then_with(|| a.filename.cmp(&b.filename));
+    match sort.descending {
+      true => ordering.reverse(),
+      false => ordering,
+    }
+  });
 
-  if table_rows.len() < min_table_rows_length {
+  for entry in entries.iter() {
+    let filename_link = format!(
+      "<a href=\"{}/{}{}\">{}</a>",
+      request_path_without_trailing_slashes,
+      anti_xss(urlencoding::encode(&entry.filename).as_ref()),
+      match entry.is_dir {
+        true => "/",
+        false => "",
+      },
+      anti_xss(&entry.filename)
+    );
+
+    let row = format!(
+      "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+      filename_link,
+      match entry.size {
+        Some(size) => anti_xss(&sizify(size, false)),
+        None => "-".to_string(),
+      },
+      anti_xss(
+        &(match entry.modified {
+          Some(mtime) => {
+            let datetime: DateTime<Local> = mtime.into();
+            datetime.format("%a %b %d %Y %H:%M:%S").to_string()
+          }
+          None => "-".to_string(),
+        })
+      )
+    );
+    table_rows.push(row);
+  }
+
+  if table_rows.len() == min_table_rows_length {
     table_rows.push("<tr><td>No files found</td><td></td><td></td></tr>".to_string());
   }
 
-  Ok(format!(
-    "<!DOCTYPE html>
-<html lang=\"en\">
-<head>
-    <meta charset=\"UTF-8\">
-    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-    <title>Directory: {}</title>
-</head>
-<body>
-    <h1>Directory: {}</h1>
-    <table>
-      <tr><th>Filename</th><th>Size</th><th>Date</th></tr>
-      {}
-      {}
-    </table>
-</body>
-</html>",
-    anti_xss(request_path),
-    anti_xss(request_path),
-    table_rows.join(""),
-    match description {
-      Some(description) => format!(
-        "<hr>{}",
-        anti_xss(&description)
-          .replace("\r\n", "\n")
-          .replace("\r", "\n")
-          .replace("\n", "<br>")
-      ),
-      None => "".to_string(),
-    }
+  let description = match description {
+    Some(description) => format!(
+      "<hr>{}",
+      anti_xss(&description)
+        .replace("\r\n", "\n")
+        .replace("\r", "\n")
+        .replace("\n", "<br>")
+    ),
+    None => "".to_string(),
+  };
+
+  Ok(render_template(
+    template
+      .as_deref()
+      .unwrap_or(DEFAULT_DIRECTORY_LISTING_TEMPLATE),
+    &[
+      ("{path}", &anti_xss(request_path)),
+      ("{rows}", &table_rows.join("")),
+      ("{description}", &description),
+    ],
   ))
 }
+
+// Replace the placeholders in the template in a single pass, so that the substituted values aren't processed again
+fn render_template(template: &str, placeholders: &[(&str, &str)]) -> String {
+  let mut rendered = String::with_capacity(template.len());
+  let mut remaining = template;
+  while let Some(brace_index) = remaining.find('{') {
+    rendered.push_str(&remaining[..brace_index]);
+    remaining = &remaining[brace_index..];
+    match placeholders
+      .iter()
+      .find(|(placeholder, _)| remaining.starts_with(placeholder))
+    {
+      Some((placeholder, value)) => {
+        rendered.push_str(value);
+        remaining = &remaining[placeholder.len()..];
+      }
+      None => {
+        rendered.push('{');
+        remaining = &remaining[1..];
+      }
+    }
+  }
+  rendered.push_str(remaining);
+  rendered
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_sort_from_query() {
+    assert_eq!(
+      DirectoryListingSort::from_query(None),
+      DirectoryListingSort {
+        key: DirectoryListingSortKey::Name,
+        descending: false
+      }
+    );
+    assert_eq!(
+      DirectoryListingSort::from_query(Some("sort=size&order=desc")),
+      DirectoryListingSort {
+        key: DirectoryListingSortKey::Size,
+        descending: true
+      }
+    );
+    assert_eq!(
+      DirectoryListingSort::from_query(Some("sort=invalid&foo=bar")),
+      DirectoryListingSort {
+        key: DirectoryListingSortKey::Name,
+        descending: false
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn test_directory_listing_with_template() {
+    let directory_path = std::env::temp_dir().join(format!(
+      "ferron-directory-listing-test-{}",
+      std::process::id()
+    ));
+    tokio::fs::create_dir_all(&directory_path).await.unwrap();
+    tokio::fs::write(directory_path.join("small.txt"), "a")
+      .await
+      .unwrap();
+    tokio::fs::write(directory_path.join("large.txt"), "abcdef")
+      .await
+      .unwrap();
+    tokio::fs::write(directory_path.join(".hidden"), "")
+      .await
+      .unwrap();
+
+    let listing = generate_directory_listing(
+      tokio::fs::read_dir(&directory_path).await.unwrap(),
+      "/files/",
+      None,
+      DirectoryListingSort::from_query(Some("sort=size&order=desc")),
+      Some("<title>{path}</title><table>{rows}</table>".to_string()),
+    )
+    .await
+    .unwrap();
+    tokio::fs::remove_dir_all(&directory_path).await.unwrap();
+
+    assert!(listing.starts_with("<title>/files/</title><table>"));
+    assert!(listing.contains("<a href=\"?sort=size&amp;order=asc\">Size</a>"));
+    assert!(!listing.contains(".hidden"));
+    let large_position = listing.find("large.txt").unwrap();
+    let small_position = listing.find("small.txt").unwrap();
+    assert!(large_position < small_position);
+  }
+}
@@ -0,0 +1,153 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use maxminddb::{geoip2, Reader};
+use yaml_rust2::Yaml;
+
+// The location of a client, as determined by the GeoIP databases
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GeoIpInfo {
+  // The ISO 3166-1 alpha-2 code of the country
+  pub country: Option<String>,
+  // The autonomous system number
+  pub asn: Option<u32>,
+}
+
+// The readers of the loaded database files
+struct GeoIpReaders {
+  country: Option<Reader<Vec<u8>>>,
+  asn: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIpReaders {
+  fn open(
+    country_path: Option<&str>,
+    asn_path: Option<&str>,
+  ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    Ok(Self {
+      country: country_path.map(Reader::open_readfile).transpose()?,
+      asn: asn_path.map(Reader::open_readfile).transpose()?,
+    })
+  }
+}
+
+// A wrapper around MaxMind DB databases used for country lookups (for example GeoLite2 Country or GeoIP2 Country)
+// and autonomous system lookups (for example GeoLite2 ASN). The databases can be reloaded while the server is running.
+pub struct GeoIpDatabase {
+  country_path: Option<String>,
+  asn_path: Option<String>,
+  readers: RwLock<Arc<GeoIpReaders>>,
+}
+
+impl GeoIpDatabase {
+  // Load the databases from files
+  pub fn open(
+    country_path: Option<&str>,
+    asn_path: Option<&str>,
+  ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    Ok(Self {
+      readers: RwLock::new(Arc::new(GeoIpReaders::open(country_path, asn_path)?)),
+      country_path: country_path.map(String::from),
+      asn_path: asn_path.map(String::from),
+    })
+  }
+
+  // Load the databases from the files again (for example, after they have been updated).
+  // If the files can't be loaded, the previously loaded databases are kept.
+  pub fn reload(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let readers = GeoIpReaders::open(self.country_path.as_deref(), self.asn_path.as_deref())?;
+    if let Ok(mut current_readers) = self.readers.write() {
+      *current_readers = Arc::new(readers);
+    }
+    Ok(())
+  }
+
+  fn readers(&self) -> Option<Arc<GeoIpReaders>> {
+    self.readers.read().ok().map(|readers| readers.clone())
+  }
+
+  // Look up the ISO 3166-1 alpha-2 code of the country the IP address is located in
+  pub fn lookup_country(&self, ip: IpAddr) -> Option<String> {
+    let readers = self.readers()?;
+    let country = readers
+      .country
+      .as_ref()?
+      .lookup::<geoip2::Country>(ip.to_canonical())
+      .ok()?;
+    country
+      .country
+      .and_then(|country| country.iso_code)
+      .map(|iso_code| iso_code.to_uppercase())
+  }
+
+  // Look up the number of the autonomous system the IP address belongs to
+  pub fn lookup_asn(&self, ip: IpAddr) -> Option<u32> {
+    let readers = self.readers()?;
+    let asn = readers
+      .asn
+      .as_ref()?
+      .lookup::<geoip2::Asn>(ip.to_canonical())
+      .ok()?;
+    asn.autonomous_system_number
+  }
+
+  // Look up the location of the IP address
+  pub fn lookup(&self, ip: IpAddr) -> GeoIpInfo {
+    GeoIpInfo {
+      country: self.lookup_country(ip),
+      asn: self.lookup_asn(ip),
+    }
+  }
+}
+
+// Check if the list of country codes in the configuration contains the country
+fn country_list_contains(country_list: &Yaml, country: &str) -> bool {
+  country_list.as_vec().is_some_and(|country_list| {
+    country_list.iter().any(|list_country| {
+      list_country
+        .as_str()
+        .is_some_and(|list_country| list_country.eq_ignore_ascii_case(country))
+    })
+  })
+}
+
+// Check if the client from the country is allowed by the country allow and deny lists.
+// The clients from unknown countries are allowed only if there is no allow list.
+pub fn is_country_allowed(country: Option<&str>, allow_list: &Yaml, deny_list: &Yaml) -> bool {
+  match country {
+    Some(country) => {
+      (allow_list.is_badvalue() || country_list_contains(allow_list, country))
+        && !country_list_contains(deny_list, country)
+    }
+    None => allow_list.is_badvalue(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  #[test]
+  fn test_is_country_allowed() {
+    let country_lists = YamlLoader::load_from_str("allow: [PL, de]\ndeny: [RU]")
+      .unwrap()
+      .remove(0);
+    let allow_list = &country_lists["allow"];
+    let deny_list = &country_lists["deny"];
+    let no_list = &Yaml::BadValue;
+
+    assert!(is_country_allowed(Some("PL"), allow_list, no_list));
+    assert!(is_country_allowed(Some("DE"), allow_list, no_list));
+    assert!(!is_country_allowed(Some("US"), allow_list, no_list));
+    assert!(!is_country_allowed(None, allow_list, no_list));
+
+    assert!(!is_country_allowed(Some("RU"), no_list, deny_list));
+    assert!(is_country_allowed(Some("US"), no_list, deny_list));
+    assert!(is_country_allowed(None, no_list, deny_list));
+
+    assert!(!is_country_allowed(Some("RU"), allow_list, deny_list));
+    assert!(is_country_allowed(None, no_list, no_list));
+  }
+}
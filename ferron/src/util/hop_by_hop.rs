@@ -0,0 +1,122 @@
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+// The hop-by-hop headers (RFC 9110, section 7.6.1), which apply only to a single connection,
+// along with the "Proxy-*" headers meant for the proxy itself
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+  header::CONNECTION,
+  HeaderName::from_static("keep-alive"),
+  HeaderName::from_static("proxy-connection"),
+  header::PROXY_AUTHENTICATE,
+  header::PROXY_AUTHORIZATION,
+  header::TE,
+  header::TRANSFER_ENCODING,
+  header::UPGRADE,
+];
+
+// The headers needed for routing and framing the message, which can't be removed by listing them in the "Connection" header
+const PROTECTED_HEADERS: [HeaderName; 2] = [header::HOST, header::CONTENT_LENGTH];
+
+// Check if the comma-separated header values contain the specified token
+fn contains_token(headers: &HeaderMap, name: &HeaderName, token: &str) -> bool {
+  headers
+    .get_all(name)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+// Remove the hop-by-hop headers, including the ones listed in the "Connection" header, from the message
+// forwarded to the next hop. If the protocol upgrade is handled, the "Upgrade" header is kept along with "Connection: upgrade".
+// The "TE: trailers" header is kept, as it's required by the gRPC services.
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap, keep_upgrade: bool) {
+  let connection_listed_headers = headers
+    .get_all(header::CONNECTION)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+    .collect::<Vec<_>>();
+  let accepts_trailers = contains_token(headers, &header::TE, "trailers");
+  let upgrade = match keep_upgrade {
+    true => headers.get(header::UPGRADE).cloned(),
+    false => None,
+  };
+
+  for name in connection_listed_headers {
+    if !PROTECTED_HEADERS.contains(&name) {
+      headers.remove(name);
+    }
+  }
+  for name in HOP_BY_HOP_HEADERS {
+    headers.remove(name);
+  }
+
+  if accepts_trailers {
+    headers.insert(header::TE, HeaderValue::from_static("trailers"));
+  }
+  if let Some(upgrade) = upgrade {
+    headers.insert(header::UPGRADE, upgrade);
+    headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn create_headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+      header_map.append(*name, HeaderValue::from_static(value));
+    }
+    header_map
+  }
+
+  #[test]
+  fn test_strip_hop_by_hop_headers() {
+    let mut headers = create_headers(&[
+      ("host", "example.com"),
+      ("connection", "keep-alive, x-internal"),
+      ("connection", "host, content-length"),
+      ("keep-alive", "timeout=5"),
+      ("transfer-encoding", "chunked"),
+      ("content-length", "10"),
+      ("proxy-authorization", "Basic dGVzdDp0ZXN0"),
+      ("proxy-connection", "keep-alive"),
+      ("x-internal", "secret"),
+      ("upgrade", "websocket"),
+      ("accept", "*/*"),
+    ]);
+    strip_hop_by_hop_headers(&mut headers, false);
+
+    let mut remaining_headers = headers.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+    remaining_headers.sort();
+    assert_eq!(remaining_headers, vec!["accept", "content-length", "host"]);
+  }
+
+  #[test]
+  fn test_keep_upgrade() {
+    let mut headers = create_headers(&[
+      ("connection", "Upgrade, keep-alive"),
+      ("upgrade", "websocket"),
+      ("keep-alive", "timeout=5"),
+    ]);
+    strip_hop_by_hop_headers(&mut headers, true);
+    assert_eq!(headers.get(header::UPGRADE).unwrap(), "websocket");
+    assert_eq!(headers.get(header::CONNECTION).unwrap(), "upgrade");
+    assert!(!headers.contains_key("keep-alive"));
+  }
+
+  #[test]
+  fn test_keep_te_trailers() {
+    let mut headers = create_headers(&[("connection", "te"), ("te", "trailers, deflate")]);
+    strip_hop_by_hop_headers(&mut headers, false);
+    assert_eq!(headers.get(header::TE).unwrap(), "trailers");
+    assert!(!headers.contains_key(header::CONNECTION));
+
+    let mut headers = create_headers(&[("te", "gzip")]);
+    strip_hop_by_hop_headers(&mut headers, false);
+    assert!(!headers.contains_key(header::TE));
+  }
+}
@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use ferron_common::ServerConfigRoot;
+use hyper::{header, HeaderMap, Uri};
+
+use crate::ferron_util::match_hostname::match_hostname;
+use crate::ferron_util::url_signature::{parse_signature_query, verify_url_signature};
+
+// Check if the request is allowed by the hotlink protection configured with the "hotlinkAllowedOrigins" property.
+// The request is allowed if the protection isn't enabled, the requested file isn't protected, the request has a valid
+// signed token, or the Referer header points to the requested host or one of the allowed origins.
+// The current time is the number of seconds since the Unix epoch.
+pub fn is_hotlink_allowed(
+  config: &ServerConfigRoot,
+  uri: &Uri,
+  headers: &HeaderMap,
+  now: u64,
+) -> bool {
+  let allowed_origins = config.get("hotlinkAllowedOrigins");
+  let allowed_origins = match allowed_origins.as_vec() {
+    Some(allowed_origins) => allowed_origins,
+    None => return true,
+  };
+  let request_path = uri.path();
+
+  // Only protect files with specified extensions, if they are configured
+  if let Some(extensions) = config.get("hotlinkProtectionExtensions").as_vec() {
+    let request_extension = Path::new(request_path)
+      .extension()
+      .map(|extension| extension.to_string_lossy().to_lowercase());
+    let is_protected = match request_extension {
+      Some(request_extension) => extensions.iter().any(|extension| {
+        extension
+          .as_str()
+          .map(|extension| extension.trim_start_matches('.').to_lowercase())
+          == Some(request_extension.clone())
+      }),
+      None => false,
+    };
+    if !is_protected {
+      return true;
+    }
+  }
+
+  // Don't block the placeholder itself, to avoid redirect loops
+  if config.get("hotlinkRedirect").as_str() == Some(request_path) {
+    return true;
+  }
+
+  // Requests with a valid signed token bypass the Referer check
+  if let Some(secret) = config.get("hotlinkTokenSecret").as_str() {
+    if let (Some(signature), Some(expires)) = parse_signature_query(uri.query().unwrap_or("")) {
+      if expires >= now && verify_url_signature(secret.as_bytes(), request_path, expires, signature)
+      {
+        return true;
+      }
+    }
+  }
+
+  match headers.get(header::REFERER) {
+    Some(referer) => {
+      let referer_host = referer
+        .to_str()
+        .ok()
+        .and_then(|referer| referer.parse::<Uri>().ok())
+        .and_then(|referer| referer.host().map(|host| host.to_lowercase()));
+      match referer_host {
+        Some(referer_host) => {
+          let request_host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(|host| match host.rsplit_once(':') {
+              Some((hostname, port)) if port.bytes().all(|b| b.is_ascii_digit()) => hostname,
+              _ => host,
+            });
+          request_host == Some(referer_host.as_str())
+            || allowed_origins.iter().any(|allowed_origin| {
+              allowed_origin.as_str().is_some()
+                && match_hostname(allowed_origin.as_str(), Some(&referer_host))
+            })
+        }
+        None => false,
+      }
+    }
+    None => config.get("hotlinkAllowEmptyReferer").as_bool() != Some(false),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use hmac::{Hmac, Mac};
+  use hyper::header::HeaderValue;
+  use sha2::Sha256;
+
+  const NOW: u64 = 1700000000;
+
+  fn config(yaml: &str) -> ServerConfigRoot {
+    ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(yaml)
+        .unwrap()
+        .remove(0),
+    )
+  }
+
+  fn is_allowed(config: &ServerConfigRoot, uri: &str, referer: Option<&str>) -> bool {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::HOST, HeaderValue::from_static("example.com:8443"));
+    if let Some(referer) = referer {
+      headers.insert(header::REFERER, HeaderValue::from_str(referer).unwrap());
+    }
+    is_hotlink_allowed(config, &uri.parse().unwrap(), &headers, NOW)
+  }
+
+  fn sign(secret: &[u8], path: &str, expires: u64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(format!("{}\n{}", path, expires).as_bytes());
+    mac
+      .finalize()
+      .into_bytes()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect()
+  }
+
+  #[test]
+  fn test_hotlink_protection_disabled() {
+    let config = config("{}");
+    assert!(is_allowed(&config, "/image.jpg", Some("https://evil.com/")));
+  }
+
+  #[test]
+  fn test_missing_referer() {
+    let allowing_config = config("hotlinkAllowedOrigins: []");
+    assert!(is_allowed(&allowing_config, "/image.jpg", None));
+    let denying_config = config("hotlinkAllowedOrigins: []\nhotlinkAllowEmptyReferer: false");
+    assert!(!is_allowed(&denying_config, "/image.jpg", None));
+  }
+
+  #[test]
+  fn test_referer_with_port() {
+    let config = config("hotlinkAllowedOrigins: []");
+    assert!(is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://example.com:8080/page.html")
+    ));
+    assert!(!is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://evil.com:8443/page.html")
+    ));
+    assert!(!is_allowed(&config, "/image.jpg", Some("not a URL")));
+  }
+
+  #[test]
+  fn test_allowed_origin_wildcards() {
+    let config = config("hotlinkAllowedOrigins:\n  - \"*.partner.com\"\n  - friend.org");
+    assert!(is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://cdn.partner.com/page.html")
+    ));
+    assert!(is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://FRIEND.org/page.html")
+    ));
+    assert!(!is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://www.friend.org/page.html")
+    ));
+    assert!(!is_allowed(
+      &config,
+      "/image.jpg",
+      Some("https://partner.com.evil.com/")
+    ));
+  }
+
+  #[test]
+  fn test_protected_extensions() {
+    let config = config(
+      "hotlinkAllowedOrigins: []\nhotlinkProtectionExtensions: [\".jpg\", png]\nhotlinkRedirect: /placeholder.png",
+    );
+    assert!(!is_allowed(
+      &config,
+      "/image.JPG",
+      Some("https://evil.com/")
+    ));
+    assert!(!is_allowed(
+      &config,
+      "/image.png",
+      Some("https://evil.com/")
+    ));
+    assert!(is_allowed(&config, "/page.html", Some("https://evil.com/")));
+    assert!(is_allowed(&config, "/README", Some("https://evil.com/")));
+    assert!(is_allowed(
+      &config,
+      "/placeholder.png",
+      Some("https://evil.com/")
+    ));
+  }
+
+  #[test]
+  fn test_signed_tokens() {
+    let config = config("hotlinkAllowedOrigins: []\nhotlinkTokenSecret: secret");
+    let valid_uri = format!(
+      "/image.jpg?exp={}&sig={}",
+      NOW + 60,
+      sign(b"secret", "/image.jpg", NOW + 60)
+    );
+    assert!(is_allowed(&config, &valid_uri, Some("https://evil.com/")));
+
+    let expired_uri = format!(
+      "/image.jpg?exp={}&sig={}",
+      NOW - 1,
+      sign(b"secret", "/image.jpg", NOW - 1)
+    );
+    assert!(!is_allowed(
+      &config,
+      &expired_uri,
+      Some("https://evil.com/")
+    ));
+
+    let other_path_uri = format!(
+      "/other.jpg?exp={}&sig={}",
+      NOW + 60,
+      sign(b"secret", "/image.jpg", NOW + 60)
+    );
+    assert!(!is_allowed(
+      &config,
+      &other_path_uri,
+      Some("https://evil.com/")
+    ));
+  }
+}
@@ -0,0 +1,285 @@
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::load_listeners::ListenerConfig;
+
+// The minimum "max-age" value accepted by the HSTS preload list (one year)
+const PRELOAD_MIN_MAX_AGE: u64 = 31536000;
+
+// The HSTS policy parsed from the "Strict-Transport-Security" header value
+#[derive(Debug, PartialEq)]
+pub struct HstsPolicy {
+  pub max_age: u64,
+  pub include_subdomains: bool,
+  pub preload: bool,
+}
+
+// The way the server handles the requests for a host, which affects the HSTS policy
+pub struct HstsHostContext {
+  pub serves_https: bool,
+  pub serves_http: bool,
+  pub redirects_to_https: bool,
+}
+
+// Parse the "Strict-Transport-Security" header value (RFC 6797, section 6.1)
+pub fn parse_hsts_header(value: &str) -> Result<HstsPolicy, String> {
+  let mut max_age = None;
+  let mut include_subdomains = false;
+  let mut preload = false;
+  let mut seen_directives = Vec::new();
+
+  for directive in value.split(';') {
+    let directive = directive.trim();
+    if directive.is_empty() {
+      continue;
+    }
+    let (name, directive_value) = match directive.split_once('=') {
+      Some((name, directive_value)) => (
+        name.trim().to_lowercase(),
+        Some(directive_value.trim().trim_matches('"')),
+      ),
+      None => (directive.to_lowercase(), None),
+    };
+    if seen_directives.contains(&name) {
+      Err(format!("the \"{}\" directive is repeated", name))?
+    }
+    match name.as_str() {
+      "max-age" => {
+        max_age = Some(
+          directive_value
+            .and_then(|max_age| max_age.parse::<u64>().ok())
+            .ok_or_else(|| String::from("the \"max-age\" directive value is invalid"))?,
+        )
+      }
+      "includesubdomains" => include_subdomains = true,
+      "preload" => preload = true,
+      _ => (),
+    }
+    seen_directives.push(name);
+  }
+
+  Ok(HstsPolicy {
+    max_age: max_age.ok_or_else(|| String::from("the \"max-age\" directive is missing"))?,
+    include_subdomains,
+    preload,
+  })
+}
+
+// Check if the HSTS policy is consistent with the way the host is served, and if the host is eligible for the HSTS
+// preload list, when the policy requests preloading. Returns the descriptions of the problems found.
+pub fn check_hsts_policy(header_value: &str, context: &HstsHostContext) -> Vec<String> {
+  let mut problems = Vec::new();
+  let policy = match parse_hsts_header(header_value) {
+    Ok(policy) => policy,
+    Err(err) => {
+      problems.push(format!(
+        "The Strict-Transport-Security header is invalid ({}), so the browsers ignore it",
+        err
+      ));
+      return problems;
+    }
+  };
+
+  if !context.serves_https {
+    problems.push(String::from(
+      "The Strict-Transport-Security header is sent, but HTTPS isn't enabled. The browsers ignore the header received over HTTP",
+    ));
+    return problems;
+  }
+
+  if policy.preload {
+    if policy.max_age < PRELOAD_MIN_MAX_AGE {
+      problems.push(format!(
+        "The HSTS policy requests preloading, but the \"max-age\" directive value is less than {} seconds",
+        PRELOAD_MIN_MAX_AGE
+      ));
+    }
+    if !policy.include_subdomains {
+      problems.push(String::from(
+        "The HSTS policy requests preloading, but the \"includeSubDomains\" directive is missing",
+      ));
+    }
+    if context.serves_http && !context.redirects_to_https {
+      problems.push(String::from(
+        "The HSTS policy requests preloading, but the HTTP requests aren't redirected to HTTPS",
+      ));
+    }
+  } else if policy.max_age > 0 && context.serves_http && !context.redirects_to_https {
+    problems.push(String::from(
+      "The Strict-Transport-Security header is sent, but the HTTP requests aren't redirected to HTTPS, so the first visit isn't protected",
+    ));
+  }
+
+  problems
+}
+
+// Get the "Strict-Transport-Security" header value, which is sent for every request to the host.
+// The headers added with the conditional header rules are not considered.
+fn sent_hsts_header(global_config: &Yaml, host_config: &Yaml) -> Option<String> {
+  let mut header_value = None;
+  for config in [global_config, host_config] {
+    if let Some(custom_headers) = config["customHeaders"].as_hash() {
+      for (name, value) in custom_headers {
+        if let (Some(name), Some(value)) = (name.as_str(), value.as_str()) {
+          if name.eq_ignore_ascii_case("strict-transport-security") {
+            header_value = Some(value.to_string());
+          }
+        }
+      }
+    }
+  }
+  // The header rules are applied after the custom headers, so their values take precedence
+  for config in [global_config, host_config] {
+    if let Some(header_rules) = config["headerRules"].as_vec() {
+      for header_rule in header_rules {
+        if !header_rule["condition"].is_badvalue() {
+          continue;
+        }
+        for key in ["setHeaders", "addHeaders"] {
+          if let Some(headers) = header_rule[key].as_hash() {
+            for (name, value) in headers {
+              if let (Some(name), Some(value)) = (name.as_str(), value.as_str()) {
+                if name.eq_ignore_ascii_case("strict-transport-security") {
+                  header_value = Some(value.to_string());
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+  header_value
+}
+
+// Check the HSTS policies sent for the hosts in the server configuration. Returns the warnings to report.
+pub fn check_hsts_configuration(yaml_config: &Yaml, listeners: &[ListenerConfig]) -> Vec<String> {
+  let global_config = &yaml_config["global"];
+  let serves_https = listeners.iter().any(|listener| listener.secure);
+  let serves_http = listeners.iter().any(|listener| !listener.secure);
+  // The redirect to HTTPS is performed only, if HTTPS is enabled with the "secure" property
+  let https_redirect_enabled = global_config["secure"].as_bool() == Some(true)
+    && global_config["disableNonEncryptedServer"].as_bool() != Some(true);
+
+  let mut hosts = vec![(String::from("the default host"), &Yaml::BadValue)];
+  if let Some(hosts_config) = yaml_config["hosts"].as_vec() {
+    for host_config in hosts_config {
+      let host_label = match (host_config["domain"].as_str(), host_config["ip"].as_str()) {
+        (Some(domain), _) => format!("the \"{}\" host", domain),
+        (None, Some(ip)) => format!("the \"{}\" IP address host", ip),
+        (None, None) => String::from("the host without a domain"),
+      };
+      hosts.push((host_label, host_config));
+    }
+  }
+
+  let mut warnings = Vec::new();
+  for (host_label, host_config) in hosts {
+    if let Some(header_value) = sent_hsts_header(global_config, host_config) {
+      let redirect_disabled = match host_config["disableToHTTPSRedirect"].as_bool() {
+        Some(redirect_disabled) => redirect_disabled,
+        None => global_config["disableToHTTPSRedirect"].as_bool() == Some(true),
+      };
+      let context = HstsHostContext {
+        serves_https,
+        serves_http,
+        redirects_to_https: https_redirect_enabled && !redirect_disabled,
+      };
+      for problem in check_hsts_policy(&header_value, &context) {
+        warnings.push(format!("HSTS policy for {}: {}", host_label, problem));
+      }
+    }
+  }
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  const HTTPS_WITH_REDIRECT: HstsHostContext = HstsHostContext {
+    serves_https: true,
+    serves_http: true,
+    redirects_to_https: true,
+  };
+
+  #[test]
+  fn test_parse_hsts_header() {
+    assert_eq!(
+      parse_hsts_header("max-age=\"31536000\"; includeSubDomains;preload"),
+      Ok(HstsPolicy {
+        max_age: 31536000,
+        include_subdomains: true,
+        preload: true,
+      })
+    );
+    assert_eq!(
+      parse_hsts_header("MAX-AGE=0"),
+      Ok(HstsPolicy {
+        max_age: 0,
+        include_subdomains: false,
+        preload: false,
+      })
+    );
+    assert!(parse_hsts_header("includeSubDomains").is_err());
+    assert!(parse_hsts_header("max-age=abc").is_err());
+    assert!(parse_hsts_header("max-age=1; max-age=2").is_err());
+  }
+
+  #[test]
+  fn test_check_hsts_preload() {
+    assert!(check_hsts_policy(
+      "max-age=63072000; includeSubDomains; preload",
+      &HTTPS_WITH_REDIRECT
+    )
+    .is_empty());
+    assert_eq!(
+      check_hsts_policy("max-age=86400; preload", &HTTPS_WITH_REDIRECT).len(),
+      2
+    );
+    let without_redirect = HstsHostContext {
+      serves_https: true,
+      serves_http: true,
+      redirects_to_https: false,
+    };
+    assert_eq!(
+      check_hsts_policy(
+        "max-age=63072000; includeSubDomains; preload",
+        &without_redirect
+      )
+      .len(),
+      1
+    );
+    let https_only = HstsHostContext {
+      serves_https: true,
+      serves_http: false,
+      redirects_to_https: false,
+    };
+    assert!(
+      check_hsts_policy("max-age=63072000; includeSubDomains; preload", &https_only).is_empty()
+    );
+  }
+
+  #[test]
+  fn test_check_hsts_configuration() {
+    let yaml_config = YamlLoader::load_from_str(
+      "global:\n  secure: true\n  customHeaders:\n    Strict-Transport-Security: max-age=63072000; includeSubDomains; preload\n\
+       hosts:\n  - domain: example.com\n    disableToHTTPSRedirect: true\n\
+       \x20 - domain: example.org\n    headerRules:\n      - setHeaders:\n          strict-transport-security: max-age=3600; preload\n",
+    )
+    .unwrap()
+    .remove(0);
+    let listeners = [false, true].map(|secure| ListenerConfig {
+      address: "[::]:80".parse().unwrap(),
+      secure,
+      enable_http2: false,
+      bind_device: None,
+      ipv6_only: None,
+      client_auth: Default::default(),
+    });
+    let warnings = check_hsts_configuration(&yaml_config, &listeners);
+    assert_eq!(warnings.len(), 3);
+    assert!(warnings[0].starts_with("HSTS policy for the \"example.com\" host: "));
+    assert!(warnings[1].starts_with("HSTS policy for the \"example.org\" host: "));
+  }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use fancy_regex::Regex;
+use ferron_common::ServerConfigRoot;
+
+// The default pattern of the content-addressed (hashed) asset file names, for example "app.3f9a1c2b.js"
+pub const DEFAULT_IMMUTABLE_ASSET_PATTERN: &str = r"\.[0-9a-f]{8,}\.";
+
+// The default "max-age" of the immutable assets (one year)
+const DEFAULT_IMMUTABLE_ASSET_MAX_AGE: u64 = 31536000;
+
+// The patterns of the hashed asset file names, compiled once, as the file names are matched for every static file request
+static IMMUTABLE_ASSET_REGEX_CACHE: LazyLock<RwLock<HashMap<String, Arc<Regex>>>> =
+  LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// Compile the pattern of the hashed asset file names, or obtain it from the cache
+fn compile_immutable_asset_regex(
+  pattern: &str,
+) -> Result<Arc<Regex>, Box<dyn Error + Send + Sync>> {
+  if let Ok(cache) = IMMUTABLE_ASSET_REGEX_CACHE.read() {
+    if let Some(regex) = cache.get(pattern) {
+      return Ok(regex.clone());
+    }
+  }
+  let regex = Arc::new(Regex::new(pattern)?);
+  if let Ok(mut cache) = IMMUTABLE_ASSET_REGEX_CACHE.write() {
+    cache.insert(pattern.to_string(), regex.clone());
+  }
+  Ok(regex)
+}
+
+// Check if the file name matches the pattern of the hashed asset file names
+pub fn is_immutable_asset(
+  file_name: &str,
+  pattern: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+  Ok(compile_immutable_asset_regex(pattern)?.is_match(file_name)?)
+}
+
+// Obtain the "Cache-Control" header value for the file, if the immutable asset serving ("immutableAssets") is enabled
+// and the file name matches the pattern of the hashed asset file names
+pub fn immutable_asset_cache_control(
+  path: &Path,
+  config: &ServerConfigRoot,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+  if config.get("immutableAssets").as_bool() != Some(true) {
+    return Ok(None);
+  }
+  let file_name = match path.file_name() {
+    Some(file_name) => file_name.to_string_lossy(),
+    None => return Ok(None),
+  };
+  let pattern = config.get("immutableAssetPattern");
+  let pattern = pattern.as_str().unwrap_or(DEFAULT_IMMUTABLE_ASSET_PATTERN);
+  if !is_immutable_asset(&file_name, pattern)? {
+    return Ok(None);
+  }
+  let max_age = config
+    .get("immutableAssetMaxAge")
+    .as_i64()
+    .map(|max_age| max_age as u64)
+    .unwrap_or(DEFAULT_IMMUTABLE_ASSET_MAX_AGE);
+  Ok(Some(format!("public, max-age={}, immutable", max_age)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_is_immutable_asset() {
+    for file_name in [
+      "app.3f9a1c2b.js",
+      "style.0123456789abcdef.css",
+      "logo.deadbeef.min.svg",
+    ] {
+      assert!(is_immutable_asset(file_name, DEFAULT_IMMUTABLE_ASSET_PATTERN).unwrap());
+    }
+    for file_name in [
+      "app.js",
+      "index.html",
+      "app.3f9a1c.js",
+      "app.3F9A1C2B.js",
+      "data.12345678",
+    ] {
+      assert!(!is_immutable_asset(file_name, DEFAULT_IMMUTABLE_ASSET_PATTERN).unwrap());
+    }
+    assert!(is_immutable_asset("app-3f9a1c2b.js", r"-[0-9a-f]{8}\.").unwrap());
+  }
+}
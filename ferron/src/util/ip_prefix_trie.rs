@@ -0,0 +1,173 @@
+use std::net::IpAddr;
+
+#[derive(Clone, Default)]
+struct IpPrefixTrieNode {
+  children: [Option<usize>; 2],
+  terminal: bool,
+}
+
+// A binary trie for matching IP addresses against a list of CIDR prefixes
+#[derive(Clone)]
+pub struct IpPrefixTrie {
+  nodes_v4: Vec<IpPrefixTrieNode>,
+  nodes_v6: Vec<IpPrefixTrieNode>,
+}
+
+impl IpPrefixTrie {
+  // Create a new empty trie
+  pub fn new() -> Self {
+    Self {
+      nodes_v4: vec![IpPrefixTrieNode::default()],
+      nodes_v6: vec![IpPrefixTrieNode::default()],
+    }
+  }
+
+  // Insert an IP address prefix into the trie
+  pub fn insert(&mut self, ip: IpAddr, prefix_length: u8) {
+    let (nodes, bits, bit_length) = match ip.to_canonical() {
+      IpAddr::V4(ip) => (&mut self.nodes_v4, (u32::from(ip) as u128) << 96, 32),
+      IpAddr::V6(ip) => (&mut self.nodes_v6, u128::from(ip), 128),
+    };
+    let prefix_length = prefix_length.min(bit_length);
+
+    let mut node_index = 0;
+    for bit_index in 0..prefix_length {
+      if nodes[node_index].terminal {
+        // A shorter prefix already covers this one
+        return;
+      }
+      let bit = ((bits >> (127 - bit_index)) & 1) as usize;
+      node_index = match nodes[node_index].children[bit] {
+        Some(child_index) => child_index,
+        None => {
+          nodes.push(IpPrefixTrieNode::default());
+          let child_index = nodes.len() - 1;
+          nodes[node_index].children[bit] = Some(child_index);
+          child_index
+        }
+      };
+    }
+
+    nodes[node_index].terminal = true;
+    nodes[node_index].children = [None, None];
+  }
+
+  // Insert a prefix in CIDR notation (or a single IP address) into the trie
+  pub fn insert_cidr(&mut self, cidr: &str) -> bool {
+    let (ip_str, prefix_length_str) = match cidr.split_once('/') {
+      Some((ip_str, prefix_length_str)) => (ip_str, Some(prefix_length_str)),
+      None => (cidr, None),
+    };
+    let ip = match ip_str.parse::<IpAddr>() {
+      Ok(ip) => ip,
+      Err(_) => return false,
+    };
+    let max_prefix_length = match ip.to_canonical() {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+    let prefix_length = match prefix_length_str {
+      Some(prefix_length_str) => match prefix_length_str.parse::<u8>() {
+        Ok(prefix_length) if prefix_length <= max_prefix_length => prefix_length,
+        _ => return false,
+      },
+      None => max_prefix_length,
+    };
+    self.insert(ip, prefix_length);
+    true
+  }
+
+  // Load prefixes from a text list (one prefix per line, "#" and ";" start comments)
+  pub fn load_from_text(&mut self, text: &str) -> usize {
+    let mut loaded = 0;
+    for line in text.lines() {
+      let line = line.split(['#', ';']).next().unwrap_or("");
+      if let Some(cidr) = line.split_whitespace().next() {
+        if self.insert_cidr(cidr) {
+          loaded += 1;
+        }
+      }
+    }
+    loaded
+  }
+
+  // Check if an IP address is covered by any of the prefixes
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    let (nodes, bits, bit_length) = match ip.to_canonical() {
+      IpAddr::V4(ip) => (&self.nodes_v4, (u32::from(ip) as u128) << 96, 32),
+      IpAddr::V6(ip) => (&self.nodes_v6, u128::from(ip), 128),
+    };
+
+    let mut node_index = 0;
+    for bit_index in 0..bit_length {
+      if nodes[node_index].terminal {
+        return true;
+      }
+      let bit = ((bits >> (127 - bit_index)) & 1) as usize;
+      node_index = match nodes[node_index].children[bit] {
+        Some(child_index) => child_index,
+        None => return false,
+      };
+    }
+    nodes[node_index].terminal
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_ipv4_prefixes() {
+    let mut trie = IpPrefixTrie::new();
+    assert!(trie.insert_cidr("192.168.0.0/16"));
+    assert!(trie.insert_cidr("10.0.0.1"));
+
+    assert!(trie.contains("192.168.1.1".parse().unwrap()));
+    assert!(trie.contains("10.0.0.1".parse().unwrap()));
+    assert!(!trie.contains("10.0.0.2".parse().unwrap()));
+    assert!(!trie.contains("8.8.8.8".parse().unwrap()));
+    assert!(trie.contains("::ffff:192.168.5.5".parse().unwrap()));
+  }
+
+  #[test]
+  fn test_ipv6_prefixes() {
+    let mut trie = IpPrefixTrie::new();
+    assert!(trie.insert_cidr("2001:db8::/32"));
+
+    assert!(trie.contains("2001:db8::1".parse().unwrap()));
+    assert!(!trie.contains("2001:db9::1".parse().unwrap()));
+    assert!(!trie.contains("32.1.13.184".parse().unwrap()));
+  }
+
+  #[test]
+  fn test_overlapping_prefixes() {
+    let mut trie = IpPrefixTrie::new();
+    trie.insert_cidr("10.1.0.0/16");
+    trie.insert_cidr("10.0.0.0/8");
+    trie.insert_cidr("10.2.0.0/16");
+
+    assert!(trie.contains("10.1.2.3".parse().unwrap()));
+    assert!(trie.contains("10.200.0.1".parse().unwrap()));
+    assert!(!trie.contains("11.0.0.1".parse().unwrap()));
+  }
+
+  #[test]
+  fn test_invalid_prefixes() {
+    let mut trie = IpPrefixTrie::new();
+    assert!(!trie.insert_cidr("10.0.0.0/33"));
+    assert!(!trie.insert_cidr("not-an-ip"));
+    assert!(!trie.contains("10.0.0.1".parse().unwrap()));
+  }
+
+  #[test]
+  fn test_load_from_text() {
+    let mut trie = IpPrefixTrie::new();
+    let loaded = trie.load_from_text(
+      "; Datacenter list\n1.10.16.0/20 ; SBL256894\n# comment\n\n2001:db8::/32 AS64496\n",
+    );
+    assert_eq!(loaded, 2);
+    assert!(trie.contains("1.10.16.5".parse().unwrap()));
+    assert!(trie.contains("2001:db8::5".parse().unwrap()));
+  }
+}
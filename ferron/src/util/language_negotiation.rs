@@ -0,0 +1,200 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use hyper::HeaderMap;
+
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+
+// Parse the Accept-Language header value into language ranges, ordered by their quality values
+pub fn parse_accept_language(accept_language: &str) -> Vec<String> {
+  let mut language_ranges = Vec::new();
+  for (index, part) in accept_language.split(',').enumerate() {
+    let mut part_split = part.split(';');
+    let language_range = part_split.next().unwrap_or("").trim().to_lowercase();
+    if language_range.is_empty() {
+      continue;
+    }
+    let mut quality = 1.0;
+    for parameter in part_split {
+      if let Some(quality_str) = parameter.trim().strip_prefix("q=") {
+        quality = quality_str.trim().parse::<f64>().unwrap_or(0.0);
+      }
+    }
+    if quality > 0.0 {
+      language_ranges.push((language_range, quality, index));
+    }
+  }
+
+  // Sort by quality in descending order, keeping the original order for equal qualities
+  language_ranges.sort_by(|a, b| {
+    b.1
+      .partial_cmp(&a.1)
+      .unwrap_or(std::cmp::Ordering::Equal)
+      .then(a.2.cmp(&b.2))
+  });
+
+  language_ranges
+    .into_iter()
+    .map(|(language_range, _, _)| language_range)
+    .collect()
+}
+
+// Select the best available language for the accepted language ranges
+pub fn select_language(
+  accepted_languages: &[String],
+  available_languages: &[String],
+) -> Option<String> {
+  for accepted_language in accepted_languages {
+    if accepted_language == "*" {
+      return available_languages.first().cloned();
+    }
+    for available_language in available_languages {
+      let available_language_lowercase = available_language.to_lowercase();
+      if &available_language_lowercase == accepted_language
+        || available_language_lowercase.starts_with(&format!("{}-", accepted_language))
+        || accepted_language.starts_with(&format!("{}-", available_language_lowercase))
+      {
+        return Some(available_language.clone());
+      }
+    }
+  }
+  None
+}
+
+// Obtain the path of a language variant of a file (for example "index.de.html" for "index.html")
+pub fn get_language_variant_path(path: &Path, language: &str) -> Option<PathBuf> {
+  let file_stem = path.file_stem()?.to_string_lossy();
+  let file_name = match path.extension() {
+    Some(extension) => format!("{}.{}.{}", file_stem, language, extension.to_string_lossy()),
+    None => format!("{}.{}", file_stem, language),
+  };
+  Some(path.with_file_name(file_name))
+}
+
+// Determine the client's country, to which a language variant can be mapped. The country found in the GeoIP database
+// takes precedence. The country header (for example, set by a CDN) is accepted only from the trusted proxies,
+// because the clients could set it themselves.
+pub fn client_country(
+  geoip_country: Option<String>,
+  headers: &HeaderMap,
+  country_header_name: Option<&str>,
+  peer_ip: IpAddr,
+  trusted_proxies: &IpPrefixTrie,
+) -> Option<String> {
+  if geoip_country.is_some() {
+    return geoip_country;
+  }
+  if !trusted_proxies.contains(peer_ip) {
+    return None;
+  }
+  headers
+    .get(country_header_name?)
+    .and_then(|value| value.to_str().ok())
+    .map(|country| country.trim().to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_accept_language() {
+    assert_eq!(
+      parse_accept_language("de-DE,de;q=0.9,en;q=0.8"),
+      vec!["de-de", "de", "en"]
+    );
+    assert_eq!(
+      parse_accept_language("en;q=0.5, fr, pl;q=0"),
+      vec!["fr", "en"]
+    );
+    assert!(parse_accept_language("").is_empty());
+  }
+
+  #[test]
+  fn test_select_language() {
+    let available_languages = vec!["en".to_string(), "de".to_string()];
+    assert_eq!(
+      select_language(
+        &parse_accept_language("de-DE,en;q=0.5"),
+        &available_languages
+      ),
+      Some("de".to_string())
+    );
+    assert_eq!(
+      select_language(&parse_accept_language("fr,en;q=0.5"), &available_languages),
+      Some("en".to_string())
+    );
+    assert_eq!(
+      select_language(&parse_accept_language("fr"), &available_languages),
+      None
+    );
+    assert_eq!(
+      select_language(&parse_accept_language("*"), &available_languages),
+      Some("en".to_string())
+    );
+  }
+
+  #[test]
+  fn test_get_language_variant_path() {
+    assert_eq!(
+      get_language_variant_path(Path::new("/var/www/index.html"), "de"),
+      Some(PathBuf::from("/var/www/index.de.html"))
+    );
+    assert_eq!(
+      get_language_variant_path(Path::new("/var/www/README"), "pl"),
+      Some(PathBuf::from("/var/www/README.pl"))
+    );
+  }
+
+  #[test]
+  fn test_client_country() {
+    let mut trusted_proxies = IpPrefixTrie::new();
+    trusted_proxies.insert_cidr("10.0.0.0/8");
+    let mut headers = HeaderMap::new();
+    headers.insert("cf-ipcountry", "de".parse().unwrap());
+    let proxy_ip = "10.0.0.2".parse().unwrap();
+    let client_ip = "203.0.113.7".parse().unwrap();
+
+    assert_eq!(
+      client_country(
+        None,
+        &headers,
+        Some("CF-IPCountry"),
+        proxy_ip,
+        &trusted_proxies
+      ),
+      Some("DE".to_string())
+    );
+    // The clients connecting directly can't choose their country
+    assert_eq!(
+      client_country(
+        None,
+        &headers,
+        Some("CF-IPCountry"),
+        client_ip,
+        &trusted_proxies
+      ),
+      None
+    );
+    assert_eq!(
+      client_country(
+        None,
+        &headers,
+        Some("CF-IPCountry"),
+        proxy_ip,
+        &IpPrefixTrie::new()
+      ),
+      None
+    );
+    assert_eq!(
+      client_country(
+        Some("FR".to_string()),
+        &headers,
+        Some("CF-IPCountry"),
+        proxy_ip,
+        &trusted_proxies
+      ),
+      Some("FR".to_string())
+    );
+  }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use async_channel::Sender;
+use ferron_common::LogMessage;
+use tokio::net::TcpListener;
+use tokio::time;
+
+use crate::ferron_util::metrics::METRICS;
+
+// The interval between the listen queue checks
+const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// The listen queue is under pressure, if it's filled in at least this percentage of the backlog
+const PRESSURE_THRESHOLD_PERCENT: u64 = 80;
+
+// The TCP state of a listening socket, as reported by Linux
+#[cfg(target_os = "linux")]
+const TCP_LISTEN: u8 = 10;
+
+// The minimum interval between the listen queue overflow warnings
+const OVERFLOW_WARNING_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+// The state of the queue of connections waiting to be accepted on a listening socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenQueueInfo {
+  pub length: u32,
+  pub backlog: u32,
+}
+
+impl ListenQueueInfo {
+  pub fn is_under_pressure(&self) -> bool {
+    self.backlog > 0 && self.length as u64 * 100 >= self.backlog as u64 * PRESSURE_THRESHOLD_PERCENT
+  }
+}
+
+// Obtain the listen queue state of a listening socket. For listening sockets, Linux reports
+// the queue length as the unacknowledged segment count, and the backlog as the selective acknowledgement count.
+#[cfg(target_os = "linux")]
+pub fn listen_queue_info(tcp_listener: &TcpListener) -> Option<ListenQueueInfo> {
+  use std::os::fd::AsRawFd;
+
+  let mut tcp_info: libc::tcp_info = unsafe { std::mem::zeroed() };
+  let mut tcp_info_length = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+  let result = unsafe {
+    libc::getsockopt(
+      tcp_listener.as_raw_fd(),
+      libc::IPPROTO_TCP,
+      libc::TCP_INFO,
+      &mut tcp_info as *mut libc::tcp_info as *mut libc::c_void,
+      &mut tcp_info_length,
+    )
+  };
+  if result != 0 || tcp_info.tcpi_state != TCP_LISTEN {
+    return None;
+  }
+  Some(ListenQueueInfo {
+    length: tcp_info.tcpi_unacked,
+    backlog: tcp_info.tcpi_sacked,
+  })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn listen_queue_info(_tcp_listener: &TcpListener) -> Option<ListenQueueInfo> {
+  None
+}
+
+// Obtain the system-wide count of the connections dropped because of a full listen queue
+#[cfg(target_os = "linux")]
+pub fn read_listen_overflows() -> Option<u64> {
+  parse_listen_overflows(&std::fs::read_to_string("/proc/net/netstat").ok()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_listen_overflows() -> Option<u64> {
+  None
+}
+
+// Parse the "ListenOverflows" counter from the "/proc/net/netstat" file contents
+fn parse_listen_overflows(netstat: &str) -> Option<u64> {
+  let mut lines = netstat.lines();
+  while let Some(header_line) = lines.next() {
+    let values_line = lines.next()?;
+    if let (Some(names), Some(values)) = (
+      header_line.strip_prefix("TcpExt:"),
+      values_line.strip_prefix("TcpExt:"),
+    ) {
+      return names
+        .split_whitespace()
+        .zip(values.split_whitespace())
+        .find(|(name, _)| *name == "ListenOverflows")
+        .and_then(|(_, value)| value.parse().ok());
+    }
+  }
+  None
+}
+
+// Periodically update the listen queue metrics, and warn about the listen queues under pressure and overflows.
+// The listeners are labeled with their addresses. This function never returns.
+pub async fn monitor_listeners(
+  listeners: Vec<(String, Arc<TcpListener>)>,
+  logger: Sender<LogMessage>,
+) {
+  let mut under_pressure = vec![false; listeners.len()];
+  let mut previous_overflows = read_listen_overflows();
+  let mut last_overflow_warning: Option<time::Instant> = None;
+  let mut interval = time::interval(CHECK_INTERVAL);
+  loop {
+    interval.tick().await;
+
+    for ((listener_address, tcp_listener), under_pressure) in
+      listeners.iter().zip(under_pressure.iter_mut())
+    {
+      let queue_info = match listen_queue_info(tcp_listener) {
+        Some(queue_info) => queue_info,
+        None => continue,
+      };
+      let labels = [("listener", listener_address.as_str())];
+      METRICS.set_gauge(
+        "ferron_listen_queue_length",
+        &labels,
+        queue_info.length as i64,
+      );
+      METRICS.set_gauge("ferron_listen_backlog", &labels, queue_info.backlog as i64);
+
+      // The warning is logged only when the listen queue comes under pressure
+      if queue_info.is_under_pressure() && !*under_pressure {
+        logger
+          .send(LogMessage::new(
+            format!(
+              "The listen queue of the {} listener is under pressure ({} of {} connections waiting to be accepted)",
+              listener_address, queue_info.length, queue_info.backlog
+            ),
+            true,
+          ))
+          .await
+          .unwrap_or_default();
+      }
+      *under_pressure = queue_info.is_under_pressure();
+    }
+
+    if let Some(overflows) = read_listen_overflows() {
+      let new_overflows = overflows.saturating_sub(previous_overflows.unwrap_or(overflows));
+      previous_overflows = Some(overflows);
+      if new_overflows > 0 {
+        METRICS.add_to_counter("ferron_listen_overflows_total", &[], new_overflows);
+        if last_overflow_warning.is_none_or(|last_overflow_warning| {
+          last_overflow_warning.elapsed() >= OVERFLOW_WARNING_INTERVAL
+        }) {
+          last_overflow_warning = Some(time::Instant::now());
+          logger
+            .send(LogMessage::new(
+              format!(
+                "{} connections were dropped, because a listen queue was full",
+                new_overflows
+              ),
+              true,
+            ))
+            .await
+            .unwrap_or_default();
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_listen_overflows() {
+    let netstat = "TcpExt: SyncookiesSent SyncookiesRecv ListenOverflows ListenDrops\n\
+                   TcpExt: 0 0 42 43\n\
+                   IpExt: InNoRoutes InTruncatedPkts\n\
+                   IpExt: 0 0\n";
+    assert_eq!(parse_listen_overflows(netstat), Some(42));
+    assert_eq!(
+      parse_listen_overflows("IpExt: InNoRoutes\nIpExt: 0\n"),
+      None
+    );
+  }
+
+  #[test]
+  fn test_listen_queue_pressure() {
+    let queue_info = |length, backlog| ListenQueueInfo { length, backlog };
+    assert!(!queue_info(0, 1024).is_under_pressure());
+    assert!(!queue_info(818, 1024).is_under_pressure());
+    assert!(queue_info(820, 1024).is_under_pressure());
+    assert!(!queue_info(0, 0).is_under_pressure());
+  }
+}
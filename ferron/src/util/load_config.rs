@@ -1,19 +1,350 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{collections::HashSet, error::Error};
 
 use glob::glob;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+use yaml_rust2::yaml::Hash;
 use yaml_rust2::{Yaml, YamlLoader};
 
+// The place in the server configuration file, where a part of the configuration is defined
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigOrigin {
+  pub path: String,
+  pub line: usize,
+}
+
+impl fmt::Display for ConfigOrigin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.path, self.line)
+  }
+}
+
+// The origin of a virtual host, and of its locations
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostConfigOrigin {
+  pub origin: ConfigOrigin,
+  pub locations: Vec<ConfigOrigin>,
+}
+
+// The places, where the global configuration sections, the virtual hosts and the locations are defined.
+// The hosts are in the same order as in the merged configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigOrigins {
+  pub global: Vec<ConfigOrigin>,
+  pub hosts: Vec<HostConfigOrigin>,
+}
+
+impl ConfigOrigins {
+  fn extend(&mut self, other: ConfigOrigins) {
+    self.global.extend(other.global);
+    self.hosts.extend(other.hosts);
+  }
+}
+
+// The state of the YAML collection, in which the parsed node is
+enum CollectionState {
+  Mapping {
+    key: Option<String>,
+    expecting_key: bool,
+  },
+  Sequence,
+}
+
+// The YAML event receiver, which records the lines of the global sections, the hosts and the locations
+struct ConfigOriginsReceiver<'a> {
+  path: &'a str,
+  documents: usize,
+  stack: Vec<CollectionState>,
+  origins: ConfigOrigins,
+}
+
+impl ConfigOriginsReceiver<'_> {
+  // Check if the collections at the depth are a mapping value under the key, and a sequence in that value
+  fn is_in_sequence_of(&self, depth: usize, key_name: &str) -> bool {
+    self.stack.len() >= depth + 2
+      && matches!(
+        &self.stack[depth],
+        CollectionState::Mapping { key: Some(key), expecting_key: false } if key == key_name
+      )
+      && matches!(self.stack[depth + 1], CollectionState::Sequence)
+  }
+
+  fn origin(&self, mark: &Marker) -> ConfigOrigin {
+    ConfigOrigin {
+      path: self.path.to_string(),
+      line: mark.line(),
+    }
+  }
+
+  // Record the origin of the node, which starts at the marker
+  fn start_node(&mut self, scalar_key: Option<&str>, mark: &Marker) {
+    if let Some(CollectionState::Mapping {
+      expecting_key: true,
+      ..
+    }) = self.stack.last()
+    {
+      if self.stack.len() == 1 && scalar_key == Some("global") {
+        let origin = self.origin(mark);
+        self.origins.global.push(origin);
+      }
+    } else if self.stack.len() == 2 && self.is_in_sequence_of(0, "hosts") {
+      let origin = self.origin(mark);
+      self.origins.hosts.push(HostConfigOrigin {
+        origin,
+        locations: Vec::new(),
+      });
+    } else if self.stack.len() == 4
+      && self.is_in_sequence_of(0, "hosts")
+      && self.is_in_sequence_of(2, "locations")
+    {
+      let origin = self.origin(mark);
+      if let Some(host) = self.origins.hosts.last_mut() {
+        host.locations.push(origin);
+      }
+    }
+  }
+
+  // Advance the state of the mapping after its key or value has ended
+  fn end_node(&mut self, scalar_key: Option<String>) {
+    match self.stack.last_mut() {
+      Some(CollectionState::Mapping { key, expecting_key }) => {
+        if *expecting_key {
+          *key = scalar_key;
+          *expecting_key = false;
+        } else {
+          *key = None;
+          *expecting_key = true;
+        }
+      }
+      Some(CollectionState::Sequence) | None => (),
+    }
+  }
+}
+
+impl MarkedEventReceiver for ConfigOriginsReceiver<'_> {
+  fn on_event(&mut self, event: Event, mark: Marker) {
+    if let Event::DocumentStart = event {
+      self.documents += 1;
+    }
+    // Only the first YAML document is used as the server configuration
+    if self.documents != 1 {
+      return;
+    }
+    match event {
+      Event::Scalar(value, ..) => {
+        self.start_node(Some(&value), &mark);
+        self.end_node(Some(value));
+      }
+      Event::Alias(_) => {
+        self.start_node(None, &mark);
+        self.end_node(None);
+      }
+      Event::MappingStart(..) => {
+        self.start_node(None, &mark);
+        self.stack.push(CollectionState::Mapping {
+          key: None,
+          expecting_key: true,
+        });
+      }
+      Event::SequenceStart(..) => {
+        self.start_node(None, &mark);
+        self.stack.push(CollectionState::Sequence);
+      }
+      Event::MappingEnd | Event::SequenceEnd => {
+        self.stack.pop();
+        self.end_node(None);
+      }
+      _ => (),
+    }
+  }
+}
+
+// Find the places, where the global configuration sections, the hosts and the locations are defined in the YAML source
+fn find_config_origins(path: &str, source: &str) -> ConfigOrigins {
+  let mut receiver = ConfigOriginsReceiver {
+    path,
+    documents: 0,
+    stack: Vec::new(),
+    origins: ConfigOrigins::default(),
+  };
+  Parser::new_from_str(source)
+    .load(&mut receiver, true)
+    .unwrap_or_default();
+  receiver.origins
+}
+
+// Get the configuration files in the included directory ("conf.d"-style). The files are loaded in alphabetical order.
+fn config_directory_files(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+  let mut files = Vec::new();
+  for entry in fs::read_dir(directory)? {
+    let path = entry?.path();
+    let is_yaml = path
+      .extension()
+      .is_some_and(|extension| extension == "yaml" || extension == "yml");
+    let is_hidden = path
+      .file_name()
+      .is_some_and(|file_name| file_name.to_string_lossy().starts_with('.'));
+    if is_yaml && !is_hidden && path.is_file() {
+      files.push(path);
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
 pub fn load_config(path: PathBuf) -> Result<Yaml, Box<dyn Error + Send + Sync>> {
-  load_config_inner(path, &mut HashSet::new())
+  load_config_with_origins(path).map(|(yaml_config, _)| yaml_config)
+}
+
+// Load the server configuration, along with the places, where its parts are defined
+pub fn load_config_with_origins(
+  path: PathBuf,
+) -> Result<(Yaml, ConfigOrigins), Box<dyn Error + Send + Sync>> {
+  let (mut yaml_config, config_origins) = load_config_inner(path, &mut HashSet::new())?;
+  apply_config_presets(&mut yaml_config)?;
+  Ok((yaml_config, config_origins))
+}
+
+// Merge the YAML hash into another one. The nested hashes are merged, and the other values are replaced.
+fn merge_yaml_hash(base: &mut Hash, overlay: Hash) {
+  for (key, value) in overlay {
+    match (base.get_mut(&key), value) {
+      (Some(Yaml::Hash(base_hash)), Yaml::Hash(overlay_hash)) => {
+        merge_yaml_hash(base_hash, overlay_hash)
+      }
+      (_, value) => {
+        base.insert(key, value);
+      }
+    }
+  }
+}
+
+// Get the configuration preset with the presets it uses applied
+fn resolve_config_preset(
+  name: &str,
+  presets: &Hash,
+  resolved_presets: &mut BTreeMap<String, Hash>,
+  resolving_presets: &mut Vec<String>,
+) -> Result<Hash, Box<dyn Error + Send + Sync>> {
+  if let Some(resolved_preset) = resolved_presets.get(name) {
+    return Ok(resolved_preset.clone());
+  }
+  if resolving_presets.iter().any(|resolving| resolving == name) {
+    Err(anyhow::anyhow!(
+      "Detected the configuration preset loop while attempting to use the \"{}\" preset",
+      name
+    ))?
+  }
+  let mut preset = match presets.get(&Yaml::String(name.to_string())) {
+    Some(preset @ Yaml::Hash(_)) => preset.clone(),
+    Some(_) => Err(anyhow::anyhow!("Invalid configuration preset \"{}\"", name))?,
+    None => Err(anyhow::anyhow!(
+      "The configuration preset \"{}\" doesn't exist",
+      name
+    ))?,
+  };
+  resolving_presets.push(name.to_string());
+  apply_presets_to_node(&mut preset, presets, resolved_presets, resolving_presets)?;
+  resolving_presets.pop();
+  let preset = preset.into_hash().unwrap_or_default();
+  resolved_presets.insert(name.to_string(), preset.clone());
+  Ok(preset)
+}
+
+// Replace the "use" property of the configuration node with the properties of the referenced presets
+fn apply_presets_to_node(
+  node: &mut Yaml,
+  presets: &Hash,
+  resolved_presets: &mut BTreeMap<String, Hash>,
+  resolving_presets: &mut Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let node_hash = match node.as_mut_hash() {
+    Some(node_hash) => node_hash,
+    None => return Ok(()),
+  };
+  let preset_names = match node_hash.remove(&Yaml::String("use".to_string())) {
+    None => return Ok(()),
+    Some(Yaml::String(preset_name)) => vec![preset_name],
+    Some(Yaml::Array(preset_names_yaml)) => preset_names_yaml
+      .into_iter()
+      .map(|preset_name_yaml| match preset_name_yaml {
+        Yaml::String(preset_name) => Ok(preset_name),
+        _ => Err(anyhow::anyhow!("Invalid configuration preset name")),
+      })
+      .collect::<Result<Vec<_>, _>>()?,
+    Some(_) => Err(anyhow::anyhow!("Invalid configuration preset list"))?,
+  };
+  let mut merged_hash = Hash::new();
+  for preset_name in preset_names {
+    merge_yaml_hash(
+      &mut merged_hash,
+      resolve_config_preset(&preset_name, presets, resolved_presets, resolving_presets)?,
+    );
+  }
+  merge_yaml_hash(&mut merged_hash, std::mem::take(node_hash));
+  *node_hash = merged_hash;
+  Ok(())
+}
+
+// Apply the named configuration presets ("presets") referenced with the "use" property in the global configuration, hosts and locations.
+// The properties set directly take precedence over the ones from the presets, and the later presets take precedence over the earlier ones.
+fn apply_config_presets(yaml_config: &mut Yaml) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let yaml_config_hash = match yaml_config.as_mut_hash() {
+    Some(yaml_config_hash) => yaml_config_hash,
+    None => return Ok(()),
+  };
+  let presets = match yaml_config_hash.remove(&Yaml::String("presets".to_string())) {
+    None | Some(Yaml::Null) => Hash::new(),
+    Some(Yaml::Hash(presets)) => presets,
+    Some(_) => Err(anyhow::anyhow!("Invalid configuration presets"))?,
+  };
+  let mut resolved_presets = BTreeMap::new();
+  let mut resolving_presets = Vec::new();
+
+  if let Some(global_yaml) = yaml_config_hash.get_mut(&Yaml::String("global".to_string())) {
+    apply_presets_to_node(
+      global_yaml,
+      &presets,
+      &mut resolved_presets,
+      &mut resolving_presets,
+    )?;
+  }
+  if let Some(Yaml::Array(hosts)) = yaml_config_hash.get_mut(&Yaml::String("hosts".to_string())) {
+    for host_yaml in hosts {
+      apply_presets_to_node(
+        host_yaml,
+        &presets,
+        &mut resolved_presets,
+        &mut resolving_presets,
+      )?;
+      if let Some(Yaml::Array(locations)) = host_yaml
+        .as_mut_hash()
+        .and_then(|host_hash| host_hash.get_mut(&Yaml::String("locations".to_string())))
+      {
+        for location_yaml in locations {
+          apply_presets_to_node(
+            location_yaml,
+            &presets,
+            &mut resolved_presets,
+            &mut resolving_presets,
+          )?;
+        }
+      }
+    }
+  }
+
+  Ok(())
 }
 
 fn load_config_inner(
   path: PathBuf,
   loaded_paths: &mut HashSet<PathBuf>,
-) -> Result<Yaml, Box<dyn Error + Send + Sync>> {
+) -> Result<(Yaml, ConfigOrigins), Box<dyn Error + Send + Sync>> {
   // Canonicalize the path
   let canonical_pathbuf = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
 
@@ -46,10 +377,15 @@ fn load_config_inner(
   // Load YAML configuration from the file contents
   let yaml_configs = match YamlLoader::load_from_str(&file_contents) {
     Ok(yaml_configs) => yaml_configs,
-    Err(err) => Err(anyhow::anyhow!(
-      "Failed to parse the server configuration file: {}",
-      err
-    ))?,
+    Err(err) => {
+      let canonical_path = canonical_pathbuf.to_string_lossy().into_owned();
+
+      Err(anyhow::anyhow!(
+        "Failed to parse the server configuration file at \"{}\": {}",
+        canonical_path,
+        err
+      ))?
+    }
   };
 
   // Ensure the YAML file is not empty
@@ -59,13 +395,20 @@ fn load_config_inner(
     ))?;
   }
   let mut yaml_config = yaml_configs[0].clone(); // Clone the first YAML document
+  let mut config_origins =
+    find_config_origins(&canonical_pathbuf.to_string_lossy(), &file_contents);
 
   if yaml_config.is_hash() {
-    // Get the list of included files
+    // Get the list of included files. The included paths can be glob patterns or directories.
     let mut include_files = Vec::new();
-    if let Some(include_yaml) = yaml_config["include"].as_vec() {
-      for include_one_yaml in include_yaml.iter() {
-        if let Some(include_glob) = include_one_yaml.as_str() {
+    let include_globs = match &yaml_config["include"] {
+      Yaml::Array(include_yaml) => include_yaml.iter().collect(),
+      Yaml::BadValue => Vec::new(),
+      include_one_yaml => vec![include_one_yaml],
+    };
+    for include_one_yaml in include_globs {
+      match include_one_yaml.as_str() {
+        Some(include_glob) => {
           let include_glob_pathbuf = match PathBuf::from_str(include_glob) {
             Ok(pathbuf) => pathbuf,
             Err(err) => {
@@ -85,6 +428,13 @@ fn load_config_inner(
             canonical_dirname.pop();
             canonical_dirname.join(include_glob_pathbuf)
           };
+          let is_pattern = include_glob.contains(['*', '?', '[']);
+          if !is_pattern && !include_glob_pathbuf_canonicalized.exists() {
+            Err(anyhow::anyhow!(
+              "The included server configuration file or directory at \"{}\" doesn't exist",
+              include_glob_pathbuf_canonicalized.to_string_lossy()
+            ))?
+          }
           let files_globbed = match glob(&include_glob_pathbuf_canonicalized.to_string_lossy()) {
             Ok(files_globbed) => files_globbed,
             Err(err) => {
@@ -111,10 +461,34 @@ fn load_config_inner(
                 ))?
               }
             };
-            include_files
-              .push(fs::canonicalize(&file_globbed).unwrap_or_else(|_| file_globbed.clone()));
+            if file_globbed.is_dir() {
+              let directory_files = match config_directory_files(&file_globbed) {
+                Ok(directory_files) => directory_files,
+                Err(err) => Err(anyhow::anyhow!(
+                  "Failed to read the included server configuration directory at \"{}\": {}",
+                  file_globbed.to_string_lossy(),
+                  err
+                ))?,
+              };
+              for directory_file in directory_files {
+                include_files.push(
+                  fs::canonicalize(&directory_file).unwrap_or_else(|_| directory_file.clone()),
+                );
+              }
+            } else {
+              include_files
+                .push(fs::canonicalize(&file_globbed).unwrap_or_else(|_| file_globbed.clone()));
+            }
           }
         }
+        None => {
+          let canonical_path = canonical_pathbuf.to_string_lossy().into_owned();
+
+          Err(anyhow::anyhow!(
+            "Invalid include path in the server configuration file at \"{}\"",
+            canonical_path
+          ))?
+        }
       }
     }
 
@@ -124,7 +498,9 @@ fn load_config_inner(
 
       // Merge included configuration
       for included_file in include_files {
-        let yaml_to_include = load_config_inner(included_file, loaded_paths)?;
+        let (yaml_to_include, included_config_origins) =
+          load_config_inner(included_file, loaded_paths)?;
+        config_origins.extend(included_config_origins);
         if let Some(yaml_to_include_hashmap) = yaml_to_include.as_hash() {
           for (key, value) in yaml_to_include_hashmap.iter() {
             if let Some(key) = key.as_str() {
@@ -169,5 +545,153 @@ fn load_config_inner(
   }
 
   // Return the server configuration
-  Ok(yaml_config)
+  Ok((yaml_config, config_origins))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_find_config_origins() {
+    let origins = find_config_origins(
+      "ferron.yaml",
+      "global:\n  port: 80\nhosts:\n  - domain: example.com\n    locations:\n      - path: /a\n      - path: /b\n  - domain: example.org\n",
+    );
+    let origin = |line| ConfigOrigin {
+      path: String::from("ferron.yaml"),
+      line,
+    };
+    assert_eq!(origins.global, vec![origin(1)]);
+    assert_eq!(
+      origins.hosts,
+      vec![
+        HostConfigOrigin {
+          origin: origin(4),
+          locations: vec![origin(6), origin(7)],
+        },
+        HostConfigOrigin {
+          origin: origin(8),
+          locations: Vec::new(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_load_config_with_included_directory() {
+    let directory_path =
+      std::env::temp_dir().join(format!("ferron-load-config-test-{}", std::process::id()));
+    let conf_d_path = directory_path.join("conf.d");
+    fs::create_dir_all(&conf_d_path).unwrap();
+    fs::write(
+      directory_path.join("ferron.yaml"),
+      "include: conf.d\nglobal:\n  port: 80\nhosts:\n  - domain: example.com\n",
+    )
+    .unwrap();
+    fs::write(
+      conf_d_path.join("b.yaml"),
+      "hosts:\n  - domain: b.example.com\n",
+    )
+    .unwrap();
+    fs::write(
+      conf_d_path.join("a.yml"),
+      "global:\n  sport: 443\nhosts:\n  - domain: a.example.com\n",
+    )
+    .unwrap();
+    fs::write(conf_d_path.join("README.txt"), "not a configuration file").unwrap();
+
+    let result = load_config_with_origins(directory_path.join("ferron.yaml"));
+    let missing_include_result = {
+      fs::write(directory_path.join("missing.yaml"), "include: missing.d\n").unwrap();
+      load_config(directory_path.join("missing.yaml"))
+    };
+    fs::remove_dir_all(&directory_path).unwrap();
+
+    let (yaml_config, config_origins) = result.unwrap();
+    assert_eq!(yaml_config["global"]["port"].as_i64(), Some(80));
+    assert_eq!(yaml_config["global"]["sport"].as_i64(), Some(443));
+    let domains = yaml_config["hosts"]
+      .as_vec()
+      .unwrap()
+      .iter()
+      .map(|host| host["domain"].as_str().unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      domains,
+      vec!["example.com", "a.example.com", "b.example.com"]
+    );
+    assert_eq!(config_origins.global.len(), 2);
+    assert_eq!(config_origins.hosts.len(), 3);
+    assert!(config_origins.hosts[2].origin.path.ends_with("b.yaml"));
+    assert_eq!(config_origins.hosts[2].origin.line, 2);
+    assert!(missing_include_result.is_err());
+  }
+
+  #[test]
+  fn test_apply_config_presets() {
+    let mut yaml_config = YamlLoader::load_from_str(
+      r#"
+presets:
+  security-strict:
+    use: headers
+    hsts: true
+    customHeaders:
+      X-Frame-Options: DENY
+  static-cache-long:
+    cacheControl: "max-age=31536000"
+    hsts: false
+  headers:
+    customHeaders:
+      X-Content-Type-Options: nosniff
+global:
+  use: security-strict
+hosts:
+  - domain: example.com
+    use: [security-strict, static-cache-long]
+    customHeaders:
+      X-Frame-Options: SAMEORIGIN
+    locations:
+      - path: /assets
+        use: static-cache-long
+"#,
+    )
+    .unwrap()
+    .remove(0);
+    apply_config_presets(&mut yaml_config).unwrap();
+
+    assert!(yaml_config["presets"].is_badvalue());
+    assert_eq!(yaml_config["global"]["hsts"].as_bool(), Some(true));
+    assert!(yaml_config["global"]["use"].is_badvalue());
+    assert_eq!(
+      yaml_config["global"]["customHeaders"]["X-Content-Type-Options"].as_str(),
+      Some("nosniff")
+    );
+    let host = &yaml_config["hosts"][0];
+    assert_eq!(host["hsts"].as_bool(), Some(false));
+    assert_eq!(host["cacheControl"].as_str(), Some("max-age=31536000"));
+    assert_eq!(
+      host["customHeaders"]["X-Frame-Options"].as_str(),
+      Some("SAMEORIGIN")
+    );
+    assert_eq!(
+      host["customHeaders"]["X-Content-Type-Options"].as_str(),
+      Some("nosniff")
+    );
+    assert_eq!(
+      host["locations"][0]["cacheControl"].as_str(),
+      Some("max-age=31536000")
+    );
+    assert_eq!(host["locations"][0]["path"].as_str(), Some("/assets"));
+
+    for invalid_config in [
+      "global:\n  use: missing\n",
+      "presets:\n  a:\n    use: b\n  b:\n    use: a\nglobal:\n  use: a\n",
+      "presets:\n  a: true\nglobal:\n  use: a\n",
+      "presets:\n  a: {}\nglobal:\n  use: [1]\n",
+    ] {
+      let mut yaml_config = YamlLoader::load_from_str(invalid_config).unwrap().remove(0);
+      assert!(apply_config_presets(&mut yaml_config).is_err());
+    }
+  }
 }
@@ -0,0 +1,637 @@
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+#[cfg(unix)]
+use std::sync::Mutex;
+
+use tokio::net::{TcpListener, TcpSocket};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::client_auth::{has_client_auth_options, ClientAuthConfig};
+
+#[cfg(unix)]
+use crate::ferron_master::WORKER_PROCESS_ENV;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerConfig {
+  pub address: SocketAddr,
+  pub secure: bool,
+  pub enable_http2: bool,
+  pub bind_device: Option<String>,
+  // Whether the IPv6 listening socket accepts only IPv6 connections (the "IPV6_V6ONLY" option).
+  // None leaves the operating system's default.
+  pub ipv6_only: Option<bool>,
+  pub client_auth: ClientAuthConfig,
+}
+
+// The address family of a listener, specified with the "family" listener property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerFamily {
+  Ipv4,
+  Ipv6,
+  DualStack,
+}
+
+impl ListenerFamily {
+  pub fn parse(family: &str) -> Option<Self> {
+    match family {
+      "ipv4" => Some(Self::Ipv4),
+      "ipv6" => Some(Self::Ipv6),
+      "dual" => Some(Self::DualStack),
+      _ => None,
+    }
+  }
+
+  // Check if the listener of this address family can listen on the address. Dual-stack listeners listen on IPv6 addresses.
+  pub fn allows(&self, address: &IpAddr) -> bool {
+    match self {
+      Self::Ipv4 => address.is_ipv4(),
+      Self::Ipv6 | Self::DualStack => address.is_ipv6(),
+    }
+  }
+
+  fn unspecified_address(&self) -> IpAddr {
+    match self {
+      Self::Ipv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+      Self::Ipv6 | Self::DualStack => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+  }
+
+  fn ipv6_only(&self) -> Option<bool> {
+    match self {
+      Self::Ipv4 => None,
+      Self::Ipv6 => Some(true),
+      Self::DualStack => Some(false),
+    }
+  }
+}
+
+// Resolve the listener address, which is either an IP address or a host name resolved at startup.
+// Only the addresses of the listener's address family are used.
+fn resolve_listener_address(
+  address: &str,
+  port: u16,
+  family: Option<ListenerFamily>,
+) -> Result<Vec<SocketAddr>, Box<dyn Error + Send + Sync>> {
+  if let Ok(ip) = address.parse::<IpAddr>() {
+    if family.is_some_and(|family| !family.allows(&ip)) {
+      Err(anyhow::anyhow!(
+        "The listener address \"{}\" doesn't match the listener address family",
+        address
+      ))?
+    }
+    return Ok(vec![SocketAddr::new(ip, port)]);
+  }
+
+  let resolved_addresses = match (address, port).to_socket_addrs() {
+    Ok(resolved_addresses) => resolved_addresses,
+    Err(err) => Err(anyhow::anyhow!(
+      "Cannot resolve the listener address \"{}\": {}",
+      address,
+      err
+    ))?,
+  };
+  let mut addresses: Vec<SocketAddr> = Vec::new();
+  for resolved_address in resolved_addresses {
+    if family.is_none_or(|family| family.allows(&resolved_address.ip()))
+      && !addresses.contains(&resolved_address)
+    {
+      addresses.push(resolved_address);
+    }
+  }
+  if addresses.is_empty() {
+    Err(anyhow::anyhow!(
+      "The listener address \"{}\" doesn't resolve to any address of the listener address family",
+      address
+    ))?
+  }
+  Ok(addresses)
+}
+
+// Determine the listeners from the global configuration.
+// If the "listeners" property is present, it replaces the "port", "sport" and "disableNonEncryptedServer" properties.
+// The listeners without their own client authentication options use the global ones.
+pub fn load_listeners(
+  global_config: &Yaml,
+) -> Result<Vec<ListenerConfig>, Box<dyn Error + Send + Sync>> {
+  let global_enable_http2 = global_config["enableHTTP2"].as_bool().unwrap_or(false);
+  let global_client_auth = ClientAuthConfig::from_yaml(global_config)?;
+  let mut listeners = Vec::new();
+
+  if let Some(listeners_yaml) = global_config["listeners"].as_vec() {
+    for listener_yaml in listeners_yaml.iter() {
+      let secure = listener_yaml["secure"].as_bool().unwrap_or(false);
+      let family = match listener_yaml["family"].as_str() {
+        Some(family) => match ListenerFamily::parse(family) {
+          Some(family) => Some(family),
+          None => Err(anyhow::anyhow!(
+            "Invalid listener address family: \"{}\"",
+            family
+          ))?,
+        },
+        None => None,
+      };
+      let port = match listener_yaml["port"].as_i64() {
+        Some(port) => match port.try_into() {
+          Ok(port) => port,
+          Err(_) => Err(anyhow::anyhow!("Invalid listener port"))?,
+        },
+        None => match secure {
+          true => 443,
+          false => 80,
+        },
+      };
+
+      let addresses = match listener_yaml["address"].as_str() {
+        Some(address) => resolve_listener_address(address, port, family)?,
+        None => vec![SocketAddr::new(
+          family.map_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED), |family| {
+            family.unspecified_address()
+          }),
+          port,
+        )],
+      };
+
+      let listener_config = ListenerConfig {
+        address: addresses[0],
+        secure,
+        enable_http2: listener_yaml["enableHTTP2"]
+          .as_bool()
+          .unwrap_or(global_enable_http2),
+        bind_device: listener_yaml["bindDevice"].as_str().map(String::from),
+        ipv6_only: family.and_then(|family| family.ipv6_only()),
+        client_auth: match has_client_auth_options(listener_yaml) {
+          true => ClientAuthConfig::from_yaml(listener_yaml)?,
+          false => global_client_auth.clone(),
+        },
+      };
+
+      // A host name resolving to multiple addresses is listened on at each of them
+      for address in addresses {
+        listeners.push(ListenerConfig {
+          address,
+          ..listener_config.clone()
+        });
+      }
+    }
+
+    return Ok(listeners);
+  }
+
+  let mut addr = SocketAddr::from((IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 80));
+  let mut addr_tls = SocketAddr::from((IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 443));
+  let mut tls_enabled = false;
+  let mut non_tls_disabled = false;
+
+  if let Some(read_port) = global_config["port"].as_i64() {
+    addr = SocketAddr::from((
+      IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
+      match read_port.try_into() {
+        Ok(port) => port,
+        Err(_) => Err(anyhow::anyhow!("Invalid HTTP port"))?,
+      },
+    ));
+  } else if let Some(read_port) = global_config["port"].as_str() {
+    addr = match read_port.parse() {
+      Ok(addr) => addr,
+      Err(_) => Err(anyhow::anyhow!("Invalid HTTP port"))?,
+    };
+  }
+
+  if let Some(read_tls_enabled) = global_config["secure"].as_bool() {
+    tls_enabled = read_tls_enabled;
+    if let Some(read_non_tls_disabled) = global_config["disableNonEncryptedServer"].as_bool() {
+      non_tls_disabled = read_non_tls_disabled;
+    }
+  }
+
+  if let Some(read_port) = global_config["sport"].as_i64() {
+    addr_tls = SocketAddr::from((
+      IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)),
+      match read_port.try_into() {
+        Ok(port) => port,
+        Err(_) => Err(anyhow::anyhow!("Invalid HTTPS port"))?,
+      },
+    ));
+  } else if let Some(read_port) = global_config["sport"].as_str() {
+    addr_tls = match read_port.parse() {
+      Ok(addr) => addr,
+      Err(_) => Err(anyhow::anyhow!("Invalid HTTPS port"))?,
+    };
+  }
+
+  if !non_tls_disabled {
+    listeners.push(ListenerConfig {
+      address: addr,
+      secure: false,
+      enable_http2: global_enable_http2,
+      bind_device: None,
+      ipv6_only: None,
+      client_auth: global_client_auth.clone(),
+    });
+  }
+
+  if tls_enabled {
+    listeners.push(ListenerConfig {
+      address: addr_tls,
+      secure: true,
+      enable_http2: global_enable_http2,
+      bind_device: None,
+      ipv6_only: None,
+      client_auth: global_client_auth,
+    });
+  }
+
+  Ok(listeners)
+}
+
+// Bind a TCP listener, optionally bound to a specific network device, and optionally accepting only IPv6 connections
+pub fn bind_listener(
+  listener_config: &ListenerConfig,
+) -> Result<TcpListener, Box<dyn Error + Send + Sync>> {
+  let socket = match listener_config.address {
+    SocketAddr::V4(_) => TcpSocket::new_v4()?,
+    SocketAddr::V6(_) => TcpSocket::new_v6()?,
+  };
+
+  #[cfg(not(windows))]
+  socket.set_reuseaddr(true)?;
+
+  if let (SocketAddr::V6(_), Some(ipv6_only)) = (listener_config.address, listener_config.ipv6_only)
+  {
+    socket2::SockRef::from(&socket).set_only_v6(ipv6_only)?;
+  }
+
+  if let Some(bind_device) = &listener_config.bind_device {
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    socket.bind_device(Some(bind_device.as_bytes()))?;
+
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    Err(anyhow::anyhow!(
+      "Binding to the \"{}\" network device is not supported on this platform",
+      bind_device
+    ))?;
+  }
+
+  socket.bind(listener_config.address)?;
+  Ok(socket.listen(1024)?)
+}
+
+// The listening sockets passed by systemd socket activation. They're taken over once,
+// and kept open when the server is restarted to apply a reloaded configuration.
+#[cfg(unix)]
+static SYSTEMD_LISTENERS: Mutex<Option<Vec<std::net::TcpListener>>> = Mutex::new(None);
+
+// Obtain the listening sockets passed by systemd socket activation (the sd_listen_fds() protocol).
+// The server uses the duplicates of the sockets, so that the sockets stay open when the server is restarted.
+#[cfg(unix)]
+pub fn get_systemd_listeners() -> Result<Vec<std::net::TcpListener>, Box<dyn Error + Send + Sync>> {
+  let mut systemd_listeners = match SYSTEMD_LISTENERS.lock() {
+    Ok(systemd_listeners) => systemd_listeners,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if systemd_listeners.is_none() {
+    *systemd_listeners = Some(take_systemd_listeners()?);
+  }
+  let mut listeners = Vec::new();
+  for systemd_listener in systemd_listeners.iter().flatten() {
+    listeners.push(systemd_listener.try_clone()?);
+  }
+  Ok(listeners)
+}
+
+// Take over the listening sockets passed by systemd. Like sd_listen_fds(1), the environment variables
+// of the socket activation are removed, so that they aren't inherited by the processes started by the server
+// (for example, the CGI programs).
+#[cfg(unix)]
+fn take_systemd_listeners() -> Result<Vec<std::net::TcpListener>, Box<dyn Error + Send + Sync>> {
+  const SD_LISTEN_FDS_START: RawFd = 3;
+
+  let listen_pid = std::env::var("LISTEN_PID");
+  let listen_fds = std::env::var("LISTEN_FDS");
+  std::env::remove_var("LISTEN_PID");
+  std::env::remove_var("LISTEN_FDS");
+  std::env::remove_var("LISTEN_FDNAMES");
+
+  // Worker processes receive the listening sockets from the master process without the "LISTEN_PID" variable,
+  // since the process ID isn't known before the worker process is started.
+  if std::env::var_os(WORKER_PROCESS_ENV).is_none() {
+    match listen_pid {
+      Ok(listen_pid) => {
+        if listen_pid.parse::<u32>().ok() != Some(std::process::id()) {
+          return Ok(Vec::new());
+        }
+      }
+      Err(_) => return Ok(Vec::new()),
+    }
+  }
+
+  let listen_fds = match listen_fds {
+    Ok(listen_fds) => match listen_fds.parse::<RawFd>() {
+      Ok(listen_fds) => listen_fds,
+      Err(_) => Err(anyhow::anyhow!("Invalid number of systemd-passed sockets"))?,
+    },
+    Err(_) => return Ok(Vec::new()),
+  };
+
+  take_listening_sockets(SD_LISTEN_FDS_START, listen_fds)
+}
+
+// Take the ownership of the inherited listening sockets. The sockets are marked as close-on-exec,
+// so that they aren't inherited by the processes started by the server.
+#[cfg(unix)]
+fn take_listening_sockets(
+  first_fd: RawFd,
+  count: RawFd,
+) -> Result<Vec<std::net::TcpListener>, Box<dyn Error + Send + Sync>> {
+  let mut listeners = Vec::new();
+  for fd in first_fd..(first_fd + count) {
+    // Safety: the file descriptors are passed by systemd (or the master process) to the server,
+    // and they're taken over only once
+    let owned_fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // Safety: the call doesn't involve any pointers, and the file descriptor is owned
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+      Err(std::io::Error::last_os_error())?
+    }
+    let listener = std::net::TcpListener::from(owned_fd);
+    listener.set_nonblocking(true)?;
+    listeners.push(listener);
+  }
+
+  Ok(listeners)
+}
+
+#[cfg(not(unix))]
+pub fn get_systemd_listeners() -> Result<Vec<std::net::TcpListener>, Box<dyn Error + Send + Sync>> {
+  Ok(Vec::new())
+}
+
+// Find the listener configuration for a socket passed by systemd.
+// If there is no configured listener with a matching address, the socket is used for plain HTTP.
+pub fn match_listener_config(
+  address: SocketAddr,
+  listeners: &[ListenerConfig],
+  global_config: &Yaml,
+) -> ListenerConfig {
+  let listener_config_option = listeners
+    .iter()
+    .find(|listener_config| listener_config.address == address)
+    .or_else(|| {
+      listeners.iter().find(|listener_config| {
+        listener_config.address.ip().is_unspecified()
+          && listener_config.address.port() == address.port()
+      })
+    });
+
+  match listener_config_option {
+    Some(listener_config) => ListenerConfig {
+      address,
+      ..listener_config.clone()
+    },
+    None => ListenerConfig {
+      address,
+      secure: false,
+      enable_http2: global_config["enableHTTP2"].as_bool().unwrap_or(false),
+      bind_device: None,
+      ipv6_only: None,
+      client_auth: ClientAuthConfig::default(),
+    },
+  }
+}
+
+// Find the TLS configurations used by a listener, which are created for its client authentication configuration.
+// The non-encrypted listeners don't use the TLS configurations, even if their client authentication configuration
+// (inherited from the global configuration) matches the one of an encrypted listener.
+pub fn find_listener_tls_configs<'a, T>(
+  tls_configs: &'a [T],
+  listener_config: &ListenerConfig,
+  client_auth: impl Fn(&T) -> &ClientAuthConfig,
+) -> Option<&'a T> {
+  if !listener_config.secure {
+    return None;
+  }
+  tls_configs
+    .iter()
+    .find(|tls_config| *client_auth(tls_config) == listener_config.client_auth)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn load_global(yaml_str: &str) -> Yaml {
+    YamlLoader::load_from_str(yaml_str).unwrap()[0]["global"].clone()
+  }
+
+  #[test]
+  fn test_default_listener() {
+    let listeners = load_listeners(&load_global("global: {}")).unwrap();
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].address.port(), 80);
+    assert!(!listeners[0].secure);
+  }
+
+  #[test]
+  fn test_legacy_ports() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          port: 8080
+          sport: 8443
+          secure: true
+          enableHTTP2: true
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[0].address.port(), 8080);
+    assert!(!listeners[0].secure);
+    assert_eq!(listeners[1].address.port(), 8443);
+    assert!(listeners[1].secure);
+    assert!(listeners[1].enable_http2);
+  }
+
+  #[test]
+  fn test_non_encrypted_server_disabled() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          secure: true
+          disableNonEncryptedServer: true
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 1);
+    assert!(listeners[0].secure);
+  }
+
+  #[test]
+  fn test_listeners_array() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          port: 8080
+          enableHTTP2: true
+          listeners:
+            - address: 127.0.0.1
+              port: 8081
+            - address: "::1"
+              secure: true
+              enableHTTP2: false
+              bindDevice: lo
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[0].address, "127.0.0.1:8081".parse().unwrap());
+    assert!(listeners[0].enable_http2);
+    assert_eq!(listeners[1].address, "[::1]:443".parse().unwrap());
+    assert!(listeners[1].secure);
+    assert!(!listeners[1].enable_http2);
+    assert_eq!(listeners[1].bind_device.as_deref(), Some("lo"));
+  }
+
+  #[test]
+  fn test_invalid_listener_address() {
+    assert!(load_listeners(&load_global(
+      r#"
+        global:
+          listeners:
+            - address: "not a host name"
+        "#,
+    ))
+    .is_err());
+  }
+
+  #[test]
+  fn test_listener_address_families() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          listeners:
+            - family: ipv4
+              port: 8080
+            - family: ipv6
+              port: 8081
+            - family: dual
+              port: 8082
+            - port: 8083
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 4);
+    assert_eq!(listeners[0].address, "0.0.0.0:8080".parse().unwrap());
+    assert_eq!(listeners[0].ipv6_only, None);
+    assert_eq!(listeners[1].address, "[::]:8081".parse().unwrap());
+    assert_eq!(listeners[1].ipv6_only, Some(true));
+    assert_eq!(listeners[2].address, "[::]:8082".parse().unwrap());
+    assert_eq!(listeners[2].ipv6_only, Some(false));
+    assert_eq!(listeners[3].address, "[::]:8083".parse().unwrap());
+    assert_eq!(listeners[3].ipv6_only, None);
+
+    assert!(load_listeners(&load_global(
+      r#"
+        global:
+          listeners:
+            - address: 127.0.0.1
+              family: ipv6
+        "#,
+    ))
+    .is_err());
+    assert!(load_listeners(&load_global(
+      r#"
+        global:
+          listeners:
+            - family: ipx
+        "#,
+    ))
+    .is_err());
+  }
+
+  #[test]
+  fn test_listener_host_name() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          listeners:
+            - address: localhost
+              family: ipv4
+              port: 8080
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].address, "127.0.0.1:8080".parse().unwrap());
+  }
+
+  #[test]
+  fn test_match_listener_config() {
+    let global_config = load_global(
+      r#"
+        global:
+          sport: 443
+          secure: true
+        "#,
+    );
+    let listeners = load_listeners(&global_config).unwrap();
+
+    let listener_config =
+      match_listener_config("[::1]:443".parse().unwrap(), &listeners, &global_config);
+    assert!(listener_config.secure);
+    assert_eq!(listener_config.address, "[::1]:443".parse().unwrap());
+
+    let listener_config = match_listener_config(
+      "127.0.0.1:8080".parse().unwrap(),
+      &listeners,
+      &global_config,
+    );
+    assert!(!listener_config.secure);
+  }
+
+  #[test]
+  fn test_plain_listener_with_client_auth_listener() {
+    let listeners = load_listeners(&load_global(
+      r#"
+        global:
+          port: 8080
+          sport: 8443
+          secure: true
+          useClientCertificate: true
+        "#,
+    ))
+    .unwrap();
+    assert_eq!(listeners.len(), 2);
+    // The plain listener inherits the global client authentication configuration of the encrypted listener
+    assert!(!listeners[0].secure);
+    assert_eq!(listeners[0].client_auth, listeners[1].client_auth);
+
+    let tls_configs = vec![(listeners[1].client_auth.clone(), "TLS configuration")];
+    let find_tls_configs = |listener_config| {
+      find_listener_tls_configs(&tls_configs, listener_config, |(client_auth, _)| {
+        client_auth
+      })
+    };
+    assert_eq!(find_tls_configs(&listeners[0]), None);
+    assert_eq!(find_tls_configs(&listeners[1]), Some(&tls_configs[0]));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_take_listening_socket() {
+    use std::os::fd::{AsRawFd, IntoRawFd};
+
+    // The inherited sockets aren't marked as close-on-exec
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let fd = listener.into_raw_fd();
+    unsafe { libc::fcntl(fd, libc::F_SETFD, 0) };
+
+    let listeners = take_listening_sockets(fd, 1).unwrap();
+    assert_eq!(listeners.len(), 1);
+    assert_eq!(listeners[0].local_addr().unwrap(), address);
+    let fd_flags = unsafe { libc::fcntl(listeners[0].as_raw_fd(), libc::F_GETFD) };
+    assert_ne!(fd_flags & libc::FD_CLOEXEC, 0);
+  }
+}
@@ -1,25 +1,26 @@
-use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer};
+use yaml_rust2::Yaml;
 
 // Load public certificate from file
 pub fn load_certs(filename: &str) -> std::io::Result<Vec<CertificateDer<'static>>> {
-  let certfile = std::fs::File::open(filename).map_err(|e| {
-    std::io::Error::new(
-      std::io::ErrorKind::Other,
-      format!("failed to open {}: {}", filename, e),
-    )
-  })?;
+  let certfile = std::fs::File::open(filename)
+    .map_err(|e| std::io::Error::other(format!("failed to open {}: {}", filename, e)))?;
   let mut reader = std::io::BufReader::new(certfile);
   rustls_pemfile::certs(&mut reader).collect()
 }
 
+// Load certificate revocation lists from file
+pub fn load_crls(filename: &str) -> std::io::Result<Vec<CertificateRevocationListDer<'static>>> {
+  let crlfile = std::fs::File::open(filename)
+    .map_err(|e| std::io::Error::other(format!("failed to open {}: {}", filename, e)))?;
+  let mut reader = std::io::BufReader::new(crlfile);
+  rustls_pemfile::crls(&mut reader).collect()
+}
+
 // Load private key from file
 pub fn load_private_key(filename: &str) -> std::io::Result<PrivateKeyDer<'static>> {
-  let keyfile = std::fs::File::open(filename).map_err(|e| {
-    std::io::Error::new(
-      std::io::ErrorKind::Other,
-      format!("failed to open {}: {}", filename, e),
-    )
-  })?;
+  let keyfile = std::fs::File::open(filename)
+    .map_err(|e| std::io::Error::other(format!("failed to open {}: {}", filename, e)))?;
   let mut reader = std::io::BufReader::new(keyfile);
   match rustls_pemfile::private_key(&mut reader) {
     Ok(Some(private_key)) => Ok(private_key),
@@ -30,3 +31,22 @@ pub fn load_private_key(filename: &str) -> std::io::Result<PrivateKeyDer<'static
     Err(err) => Err(err),
   }
 }
+
+// Get the pairs of the TLS certificate and private key paths. The paths are either single strings,
+// or lists of strings paired by their position (for example, an ECDSA and an RSA certificate for the same host).
+// Returns None if the paths are invalid or the lists have different lengths.
+pub fn certificate_key_paths<'a>(cert: &'a Yaml, key: &'a Yaml) -> Option<Vec<(&'a str, &'a str)>> {
+  match (cert, key) {
+    (Yaml::String(cert_path), Yaml::String(key_path)) => Some(vec![(cert_path, key_path)]),
+    (Yaml::Array(cert_paths), Yaml::Array(key_paths))
+      if !cert_paths.is_empty() && cert_paths.len() == key_paths.len() =>
+    {
+      cert_paths
+        .iter()
+        .zip(key_paths)
+        .map(|(cert_path, key_path)| Some((cert_path.as_str()?, key_path.as_str()?)))
+        .collect()
+    }
+    _ => None,
+  }
+}
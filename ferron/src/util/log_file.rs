@@ -0,0 +1,125 @@
+use std::io;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+// The size of the buffer for the log file writes
+const LOG_FILE_BUFFER_CAPACITY: usize = 131072;
+
+// Check if the log file path is a valid strftime pattern (for example, "/var/log/ferron/access-%Y%m%d.log")
+pub fn is_valid_log_file_path(path_pattern: &str) -> bool {
+  !StrftimeItems::new(path_pattern).any(|item| matches!(item, Item::Error))
+}
+
+// Check if the log file path contains strftime specifiers, so the log is split into time-sliced files
+fn is_time_sliced(path_pattern: &str) -> bool {
+  StrftimeItems::new(path_pattern).any(|item| {
+    !matches!(
+      item,
+      Item::Literal(_) | Item::OwnedLiteral(_) | Item::Space(_) | Item::OwnedSpace(_)
+    )
+  })
+}
+
+// Get the path of the log file for the specified time
+fn log_file_path(path_pattern: &str, time: &DateTime<Local>) -> String {
+  time.format(path_pattern).to_string()
+}
+
+// Get the path of the log file currently written to
+pub fn current_log_file_path(path_pattern: &str) -> String {
+  if is_valid_log_file_path(path_pattern) && is_time_sliced(path_pattern) {
+    log_file_path(path_pattern, &Local::now())
+  } else {
+    path_pattern.to_string()
+  }
+}
+
+async fn open_log_file(path: &str) -> Result<BufWriter<File>, io::Error> {
+  let file = fs::OpenOptions::new()
+    .append(true)
+    .create(true)
+    .open(path)
+    .await?;
+  Ok(BufWriter::with_capacity(LOG_FILE_BUFFER_CAPACITY, file))
+}
+
+// A log file, whose path can contain strftime specifiers. When the formatted path changes (for example,
+// at midnight for "access-%Y%m%d.log"), the buffered lines are flushed to the previous file,
+// and the next lines are written to the new file, so no lines are lost at the period boundaries.
+pub struct LogFile {
+  path_pattern: String,
+  time_sliced: bool,
+  current_path: String,
+  writer: BufWriter<File>,
+}
+
+impl LogFile {
+  // Open the log file for the current time
+  pub async fn open(path_pattern: &str) -> Result<Self, io::Error> {
+    let time_sliced = is_valid_log_file_path(path_pattern) && is_time_sliced(path_pattern);
+    let current_path = current_log_file_path(path_pattern);
+    let writer = open_log_file(&current_path).await?;
+    Ok(Self {
+      path_pattern: path_pattern.to_string(),
+      time_sliced,
+      current_path,
+      writer,
+    })
+  }
+
+  // Write the data to the log file for the current time, switching the files if the period has changed
+  pub async fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+    if self.time_sliced {
+      let path = log_file_path(&self.path_pattern, &Local::now());
+      if path != self.current_path {
+        let writer = open_log_file(&path).await?;
+        self.writer.flush().await?;
+        self.writer = writer;
+        self.current_path = path;
+      }
+    }
+    self.writer.write_all(data).await
+  }
+
+  // Flush the buffered data to the log file
+  pub async fn flush(&mut self) -> Result<(), io::Error> {
+    self.writer.flush().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn test_log_file_path() {
+    let time = Local.with_ymd_and_hms(2025, 3, 9, 14, 5, 0).unwrap();
+    assert_eq!(
+      log_file_path("/var/log/ferron/access-%Y%m%d.log", &time),
+      "/var/log/ferron/access-20250309.log"
+    );
+    assert_eq!(
+      log_file_path("/var/log/ferron/%Y/%m/error-%H.log", &time),
+      "/var/log/ferron/2025/03/error-14.log"
+    );
+    assert_eq!(
+      log_file_path("/var/log/ferron/100%%.log", &time),
+      "/var/log/ferron/100%.log"
+    );
+  }
+
+  #[test]
+  fn test_time_sliced_log_file_paths() {
+    assert!(is_time_sliced("/var/log/ferron/access-%Y%m%d.log"));
+    assert!(!is_time_sliced("/var/log/ferron/access.log"));
+    assert!(!is_time_sliced("/var/log/ferron/100%%.log"));
+    assert!(is_valid_log_file_path("/var/log/ferron/access-%Y%m%d.log"));
+    assert!(is_valid_log_file_path("/var/log/ferron/access.log"));
+    assert!(!is_valid_log_file_path("/var/log/ferron/access-%Q.log"));
+    assert!(!is_valid_log_file_path("/var/log/ferron/access-%"));
+  }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ferron_common::ServerConfigRoot;
+use sha2::{Digest, Sha256};
+
+// How often the salt used to hash the client IP addresses is replaced, in seconds.
+// After the salt is replaced, the hashes can't be linked with the hashes from the previous period.
+const IP_HASH_SALT_ROTATION: u64 = 86400;
+
+// The current salt and the rotation period it was generated for
+static IP_HASH_SALT: Mutex<Option<(u64, [u8; 32])>> = Mutex::new(None);
+
+// How the client IP addresses are written into the access logs
+enum IpAnonymization {
+  None,
+  // The last octet of IPv4 addresses and the last 80 bits of IPv6 addresses are zeroed
+  Truncate,
+  // The addresses are replaced with the hashes salted with a rotating salt
+  Hash,
+}
+
+// The access log privacy options, applied before the access log entries are formatted
+pub struct LogPrivacy {
+  ip_anonymization: IpAnonymization,
+  omit_query_string: bool,
+  redacted_query_parameters: Vec<String>,
+  omit_user_agent: bool,
+}
+
+impl LogPrivacy {
+  pub fn from_config(config: &ServerConfigRoot) -> Self {
+    Self {
+      ip_anonymization: match config.get("logIpAnonymization").as_str() {
+        Some("truncate") => IpAnonymization::Truncate,
+        Some("hash") => IpAnonymization::Hash,
+        _ => IpAnonymization::None,
+      },
+      omit_query_string: config.get("logOmitQueryString").as_bool() == Some(true),
+      redacted_query_parameters: config
+        .get("logRedactQueryParameters")
+        .as_vec()
+        .map(|parameters| {
+          parameters
+            .iter()
+            .filter_map(|parameter| parameter.as_str().map(|parameter| parameter.to_string()))
+            .collect()
+        })
+        .unwrap_or_default(),
+      omit_user_agent: config.get("logOmitUserAgent").as_bool() == Some(true),
+    }
+  }
+
+  // Get the client IP address as written into the access log
+  pub fn client_ip(&self, client_ip: IpAddr) -> String {
+    match self.ip_anonymization {
+      IpAnonymization::None => client_ip.to_string(),
+      IpAnonymization::Truncate => truncate_ip(client_ip).to_string(),
+      IpAnonymization::Hash => hash_ip(client_ip, &ip_hash_salt(SystemTime::now())),
+    }
+  }
+
+  // Get the request path as written into the access log, with the query string or the redacted parameters removed
+  pub fn request_path(&self, request_path: String) -> String {
+    let (path, query) = match request_path.split_once('?') {
+      Some((path, query)) => (path, query),
+      None => return request_path,
+    };
+    if self.omit_query_string {
+      return path.to_string();
+    }
+    if self.redacted_query_parameters.is_empty() {
+      return request_path;
+    }
+
+    let query = query
+      .split('&')
+      .filter(|parameter| {
+        let name = parameter.split('=').next().unwrap_or_default();
+        let name = urlencoding::decode(name)
+          .map(|name| name.into_owned())
+          .unwrap_or_else(|_| name.to_string());
+        !self.redacted_query_parameters.contains(&name)
+      })
+      .collect::<Vec<_>>()
+      .join("&");
+    match query.is_empty() {
+      true => path.to_string(),
+      false => format!("{}?{}", path, query),
+    }
+  }
+
+  // Get the user agent as written into the access log
+  pub fn user_agent(&self, user_agent: Option<String>) -> Option<String> {
+    match self.omit_user_agent {
+      true => None,
+      false => user_agent,
+    }
+  }
+}
+
+// Zero the host part of the IP address, so that the address identifies only the network
+fn truncate_ip(ip: IpAddr) -> IpAddr {
+  match ip.to_canonical() {
+    IpAddr::V4(ipv4) => {
+      let octets = ipv4.octets();
+      IpAddr::from([octets[0], octets[1], octets[2], 0])
+    }
+    IpAddr::V6(ipv6) => IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & !((1u128 << 80) - 1))),
+  }
+}
+
+// Get the salt for the current rotation period, generating a new salt if the period has changed
+fn ip_hash_salt(now: SystemTime) -> [u8; 32] {
+  let period = now
+    .duration_since(UNIX_EPOCH)
+    .map(|now| now.as_secs() / IP_HASH_SALT_ROTATION)
+    .unwrap_or(0);
+  let mut salt_locked = match IP_HASH_SALT.lock() {
+    Ok(salt_locked) => salt_locked,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  match *salt_locked {
+    Some((salt_period, salt)) if salt_period == period => salt,
+    _ => {
+      let salt = rand::random::<[u8; 32]>();
+      *salt_locked = Some((period, salt));
+      salt
+    }
+  }
+}
+
+// Hash the IP address with the salt
+fn hash_ip(ip: IpAddr, salt: &[u8; 32]) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(salt);
+  hasher.update(ip.to_canonical().to_string());
+  hasher
+    .finalize()
+    .iter()
+    .take(8)
+    .fold(String::new(), |mut output, b| {
+      let _ = write!(output, "{b:02x}");
+      output
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn log_privacy(yaml: &str) -> LogPrivacy {
+    LogPrivacy::from_config(&ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(yaml)
+        .unwrap()
+        .remove(0),
+    ))
+  }
+
+  #[test]
+  fn test_ip_anonymization() {
+    let truncating = log_privacy("logIpAnonymization: truncate");
+    assert_eq!(
+      truncating.client_ip("192.168.1.123".parse().unwrap()),
+      "192.168.1.0"
+    );
+    assert_eq!(
+      truncating.client_ip("::ffff:10.0.0.5".parse().unwrap()),
+      "10.0.0.0"
+    );
+    assert_eq!(
+      truncating.client_ip("2001:db8:1234:5678::1".parse().unwrap()),
+      "2001:db8:1234::"
+    );
+
+    let hashing = log_privacy("logIpAnonymization: hash");
+    let hash = hashing.client_ip("192.168.1.123".parse().unwrap());
+    assert_eq!(hash.len(), 16);
+    assert_eq!(hash, hashing.client_ip("192.168.1.123".parse().unwrap()));
+    assert_ne!(hash, hashing.client_ip("192.168.1.124".parse().unwrap()));
+
+    let plain = log_privacy("logOmitUserAgent: false");
+    assert_eq!(
+      plain.client_ip("192.168.1.123".parse().unwrap()),
+      "192.168.1.123"
+    );
+  }
+
+  #[test]
+  fn test_ip_hash_salts() {
+    let ip = "192.168.1.123".parse().unwrap();
+    assert_eq!(hash_ip(ip, &[1; 32]), hash_ip(ip, &[1; 32]));
+    // The hashes from different rotation periods can't be linked
+    assert_ne!(hash_ip(ip, &[1; 32]), hash_ip(ip, &[2; 32]));
+  }
+
+  #[test]
+  fn test_request_path_redaction() {
+    let omitting = log_privacy("logOmitQueryString: true");
+    assert_eq!(
+      omitting.request_path(String::from("/login?token=abc&page=2")),
+      "/login"
+    );
+
+    let redacting = log_privacy("logRedactQueryParameters:\n  - token\n  - api_key");
+    assert_eq!(
+      redacting.request_path(String::from("/login?token=abc&page=2&api%5Fkey=def")),
+      "/login?page=2"
+    );
+    assert_eq!(redacting.request_path(String::from("/?token=abc")), "/");
+    assert_eq!(
+      redacting.request_path(String::from("/index.html")),
+      "/index.html"
+    );
+
+    assert_eq!(
+      redacting.user_agent(Some(String::from("curl"))),
+      Some(String::from("curl"))
+    );
+    assert_eq!(
+      log_privacy("logOmitUserAgent: true").user_agent(Some(String::from("curl"))),
+      None
+    );
+  }
+}
@@ -0,0 +1,693 @@
+use std::error::Error;
+use std::io;
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Local};
+use ferron_common::ErrorLogLevel;
+use tokio::io::{AsyncWriteExt, BufWriter, Stderr, Stdout};
+#[cfg(unix)]
+use tokio::net::UnixDatagram;
+use tokio::net::{TcpStream, UdpSocket};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::log_file::{is_valid_log_file_path, LogFile};
+
+// The syslog facilities, with their codes as specified in RFC 5424
+const SYSLOG_FACILITIES: [(&str, u8); 20] = [
+  ("kern", 0),
+  ("user", 1),
+  ("mail", 2),
+  ("daemon", 3),
+  ("auth", 4),
+  ("syslog", 5),
+  ("lpr", 6),
+  ("news", 7),
+  ("uucp", 8),
+  ("cron", 9),
+  ("authpriv", 10),
+  ("ftp", 11),
+  ("local0", 16),
+  ("local1", 17),
+  ("local2", 18),
+  ("local3", 19),
+  ("local4", 20),
+  ("local5", 21),
+  ("local6", 22),
+  ("local7", 23),
+];
+
+// The default syslog facility of the log messages
+const DEFAULT_SYSLOG_FACILITY: u8 = 16;
+
+// The syslog severities of the access log messages ("informational") and the error log messages ("error").
+// The systemd journal uses the same priorities.
+const ACCESS_LOG_SEVERITY: u8 = 6;
+const ERROR_LOG_SEVERITY: u8 = 3;
+
+// Get the syslog severity of the error log message level
+fn error_log_severity(level: ErrorLogLevel) -> u8 {
+  match level {
+    ErrorLogLevel::Error => ERROR_LOG_SEVERITY,
+    ErrorLogLevel::Warn => 4,
+    ErrorLogLevel::Info => 6,
+    ErrorLogLevel::Debug | ErrorLogLevel::Trace => 7,
+  }
+}
+
+// The application name in the syslog messages and the systemd journal entries
+const LOG_IDENTIFIER: &str = "ferron";
+
+// The socket of the systemd journal's native protocol
+#[cfg(unix)]
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+// The host name in the RFC 5424 syslog messages, or "-" if it can't be determined
+static SYSLOG_HOSTNAME: LazyLock<String> = LazyLock::new(|| {
+  hostname()
+    .filter(|hostname| {
+      !hostname.is_empty()
+        && hostname.len() <= 255
+        && hostname.bytes().all(|byte| byte.is_ascii_graphic())
+    })
+    .unwrap_or_else(|| String::from("-"))
+});
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+  let mut buffer = [0u8; 256];
+  let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+  if result != 0 {
+    return None;
+  }
+  let length = buffer.iter().position(|byte| *byte == 0)?;
+  String::from_utf8(buffer[..length].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+  std::env::var("COMPUTERNAME").ok()
+}
+
+// The transport of the syslog messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyslogTransport {
+  Udp,
+  // The messages are framed with the octet counting method, as specified in RFC 6587
+  Tcp,
+  Unix,
+}
+
+// The format of the syslog messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyslogProtocol {
+  Rfc3164,
+  Rfc5424,
+}
+
+// The destination of a log sink
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogTarget {
+  File(String),
+  Stdout,
+  Stderr,
+  // The address is either a Unix socket path (for example, "/dev/log") or a host name (or an IP address) with a port
+  Syslog {
+    address: String,
+    transport: SyslogTransport,
+    facility: u8,
+    protocol: SyslogProtocol,
+  },
+  // The systemd journal, with the structured fields of the log messages preserved
+  Journald,
+}
+
+// Parse the destination of the log sink
+fn parse_log_target(sink_yaml: &Yaml) -> Result<LogTarget, Box<dyn Error + Send + Sync>> {
+  if sink_yaml.as_hash().is_none() {
+    Err(anyhow::anyhow!("Invalid log sink configuration"))?
+  }
+  Ok(match sink_yaml["target"].as_str() {
+    Some("file") => match sink_yaml["path"].as_str() {
+      Some(path) if is_valid_log_file_path(path) => LogTarget::File(path.to_string()),
+      _ => Err(anyhow::anyhow!("Invalid log file path"))?,
+    },
+    Some("stdout") => LogTarget::Stdout,
+    Some("stderr") => LogTarget::Stderr,
+    Some("syslog") => {
+      let address = match &sink_yaml["address"] {
+        Yaml::BadValue => "/dev/log",
+        address => match address.as_str() {
+          Some(address) => address,
+          None => Err(anyhow::anyhow!("Invalid syslog address"))?,
+        },
+      };
+      let transport = match sink_yaml["transport"].as_str() {
+        Some("udp") => SyslogTransport::Udp,
+        Some("tcp") => SyslogTransport::Tcp,
+        Some("unix") => SyslogTransport::Unix,
+        None if sink_yaml["transport"].is_badvalue() => match address.starts_with('/') {
+          true => SyslogTransport::Unix,
+          false => SyslogTransport::Udp,
+        },
+        _ => Err(anyhow::anyhow!("Invalid syslog transport"))?,
+      };
+      let facility = match &sink_yaml["facility"] {
+        Yaml::BadValue => DEFAULT_SYSLOG_FACILITY,
+        facility => match SYSLOG_FACILITIES
+          .iter()
+          .find(|(name, _)| Some(*name) == facility.as_str())
+        {
+          Some((_, code)) => *code,
+          None => Err(anyhow::anyhow!("Invalid syslog facility"))?,
+        },
+      };
+      let protocol = match sink_yaml["protocol"].as_str() {
+        Some("rfc5424") => SyslogProtocol::Rfc5424,
+        Some("rfc3164") => SyslogProtocol::Rfc3164,
+        None if sink_yaml["protocol"].is_badvalue() => SyslogProtocol::Rfc5424,
+        _ => Err(anyhow::anyhow!("Invalid syslog protocol"))?,
+      };
+      LogTarget::Syslog {
+        address: address.to_string(),
+        transport,
+        facility,
+        protocol,
+      }
+    }
+    Some("journald") => LogTarget::Journald,
+    _ => Err(anyhow::anyhow!("Invalid log sink target"))?,
+  })
+}
+
+// Get the list of the log sinks of the specified kind ("access" or "error") from the "logging" section
+fn log_sinks_yaml<'a>(
+  logging: &'a Yaml,
+  kind: &str,
+) -> Result<&'a [Yaml], Box<dyn Error + Send + Sync>> {
+  if logging.is_badvalue() {
+    return Ok(&[]);
+  }
+  if logging.as_hash().is_none() {
+    Err(anyhow::anyhow!("Invalid logging configuration"))?
+  }
+  match &logging[kind] {
+    Yaml::BadValue => Ok(&[]),
+    Yaml::Array(sinks_yaml) => Ok(sinks_yaml),
+    _ => Err(anyhow::anyhow!("Invalid {} log sinks configuration", kind))?,
+  }
+}
+
+// A rule for the access log entries that are always logged, regardless of the sampling
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlwaysLogRule {
+  Status(u16),
+  // The status code class, for example 5 for "5xx"
+  StatusClass(u16),
+}
+
+impl AlwaysLogRule {
+  fn parse(yaml: &Yaml) -> Option<Self> {
+    if let Some(status) = yaml.as_i64() {
+      return u16::try_from(status).ok().map(AlwaysLogRule::Status);
+    }
+    let rule = yaml.as_str()?.trim().to_lowercase();
+    match rule.strip_suffix("xx") {
+      Some(class) => class
+        .parse::<u16>()
+        .ok()
+        .filter(|class| (1..=5).contains(class))
+        .map(AlwaysLogRule::StatusClass),
+      None => rule.parse::<u16>().ok().map(AlwaysLogRule::Status),
+    }
+  }
+
+  fn matches(&self, status: u16) -> bool {
+    match self {
+      AlwaysLogRule::Status(rule_status) => *rule_status == status,
+      AlwaysLogRule::StatusClass(class) => status / 100 == *class,
+    }
+  }
+}
+
+// An access log sink configured in the "logging" section
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogSink {
+  pub target: LogTarget,
+  // The custom log format (with the same variables as the "logFormat" property), or the combined log format if not set
+  pub format: Option<String>,
+  // Only one in this many entries is logged, except the ones matching the always-log rules
+  sample: u64,
+  always_log: Vec<AlwaysLogRule>,
+}
+
+impl AccessLogSink {
+  // Check if the access log entry for the response with the status code is logged to the sink
+  pub fn should_log(&self, status: u16) -> bool {
+    self.sample <= 1
+      || self.always_log.iter().any(|rule| rule.matches(status))
+      || rand::random_range(0..self.sample) == 0
+  }
+
+  // Check if the sink preserves the structured fields of the access log entries
+  pub fn has_structured_fields(&self) -> bool {
+    self.target == LogTarget::Journald
+  }
+}
+
+// Parse the access log sinks from the "logging" section ("access" list)
+pub fn parse_access_log_sinks(
+  logging: &Yaml,
+) -> Result<Vec<AccessLogSink>, Box<dyn Error + Send + Sync>> {
+  let mut sinks = Vec::new();
+  for sink_yaml in log_sinks_yaml(logging, "access")? {
+    let target = parse_log_target(sink_yaml)?;
+    let format = match &sink_yaml["format"] {
+      Yaml::BadValue => None,
+      format => match format.as_str() {
+        Some(format) => Some(format.to_string()),
+        None => Err(anyhow::anyhow!("Invalid access log sink format"))?,
+      },
+    };
+    let sample = match &sink_yaml["sample"] {
+      Yaml::BadValue => 1,
+      sample => match sample.as_i64() {
+        Some(sample) if sample >= 1 => sample as u64,
+        _ => Err(anyhow::anyhow!("Invalid access log sampling rate"))?,
+      },
+    };
+    // The error responses are always logged by default
+    let always_log = match &sink_yaml["alwaysLog"] {
+      Yaml::BadValue => vec![AlwaysLogRule::StatusClass(4), AlwaysLogRule::StatusClass(5)],
+      always_log => match always_log.as_vec() {
+        Some(rules) => rules
+          .iter()
+          .map(|rule| {
+            AlwaysLogRule::parse(rule).ok_or(anyhow::anyhow!("Invalid access log always-log rule"))
+          })
+          .collect::<Result<Vec<_>, _>>()?,
+        None => Err(anyhow::anyhow!("Invalid access log always-log rules"))?,
+      },
+    };
+    sinks.push(AccessLogSink {
+      target,
+      format,
+      sample,
+      always_log,
+    });
+  }
+  Ok(sinks)
+}
+
+// Parse the error log sinks from the "logging" section ("error" list)
+pub fn parse_error_log_sinks(
+  logging: &Yaml,
+) -> Result<Vec<LogTarget>, Box<dyn Error + Send + Sync>> {
+  log_sinks_yaml(logging, "error")?
+    .iter()
+    .map(parse_log_target)
+    .collect()
+}
+
+// Check if any error log sink is configured in the "logging" section
+pub fn has_error_log_sinks(logging: &Yaml) -> bool {
+  logging["error"]
+    .as_vec()
+    .is_some_and(|error_log_sinks| !error_log_sinks.is_empty())
+}
+
+pub enum SyslogSocket {
+  Udp(UdpSocket),
+  // The TCP connection is established when the first message is sent, and re-established after a write error
+  Tcp {
+    address: String,
+    stream: Option<TcpStream>,
+  },
+  #[cfg(unix)]
+  Unix(UnixDatagram),
+}
+
+// An opened log sink, written to by the logging runtime
+pub enum LogOutput {
+  File(LogFile),
+  Stdout(BufWriter<Stdout>),
+  Stderr(BufWriter<Stderr>),
+  Syslog {
+    socket: SyslogSocket,
+    facility: u8,
+    protocol: SyslogProtocol,
+  },
+  #[cfg(unix)]
+  Journald(UnixDatagram),
+}
+
+impl LogOutput {
+  pub async fn open(target: &LogTarget) -> Result<Self, io::Error> {
+    match target {
+      LogTarget::File(path) => Ok(LogOutput::File(LogFile::open(path).await?)),
+      LogTarget::Stdout => Ok(LogOutput::Stdout(BufWriter::new(tokio::io::stdout()))),
+      LogTarget::Stderr => Ok(LogOutput::Stderr(BufWriter::new(tokio::io::stderr()))),
+      LogTarget::Syslog {
+        address,
+        transport,
+        facility,
+        protocol,
+      } => {
+        let socket = match transport {
+          SyslogTransport::Udp => {
+            let socket = UdpSocket::bind(match address.starts_with('[') {
+              true => "[::]:0",
+              false => "0.0.0.0:0",
+            })
+            .await?;
+            socket.connect(address).await?;
+            SyslogSocket::Udp(socket)
+          }
+          SyslogTransport::Tcp => SyslogSocket::Tcp {
+            address: address.clone(),
+            stream: None,
+          },
+          #[cfg(unix)]
+          SyslogTransport::Unix => {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(address)?;
+            SyslogSocket::Unix(socket)
+          }
+          #[cfg(not(unix))]
+          SyslogTransport::Unix => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets are not supported on this platform",
+          ))?,
+        };
+        Ok(LogOutput::Syslog {
+          socket,
+          facility: *facility,
+          protocol: *protocol,
+        })
+      }
+      #[cfg(unix)]
+      LogTarget::Journald => {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET_PATH)?;
+        Ok(LogOutput::Journald(socket))
+      }
+      #[cfg(not(unix))]
+      LogTarget::Journald => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The systemd journal is not supported on this platform",
+      )),
+    }
+  }
+
+  // Write the log message. The file and standard output messages are buffered, while the syslog messages
+  // and the systemd journal entries are sent immediately. The error messages written to the files
+  // and the standard output are prefixed with the time, like in the error log file.
+  // The access log messages have no error log level.
+  pub async fn write(
+    &mut self,
+    message: &str,
+    error_log_level: Option<ErrorLogLevel>,
+    fields: &[(String, String)],
+  ) -> Result<(), io::Error> {
+    let is_error = error_log_level.is_some();
+    let severity = error_log_level.map_or(ACCESS_LOG_SEVERITY, error_log_severity);
+    let line = || match is_error {
+      true => format!(
+        "[{}]: {}\n",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        message
+      ),
+      false => format!("{}\n", message),
+    };
+    match self {
+      LogOutput::File(log_file) => log_file.write(line().as_bytes()).await,
+      LogOutput::Stdout(stdout) => stdout.write_all(line().as_bytes()).await,
+      LogOutput::Stderr(stderr) => stderr.write_all(line().as_bytes()).await,
+      LogOutput::Syslog {
+        socket,
+        facility,
+        protocol,
+      } => {
+        let now = Local::now();
+        let datagram = match protocol {
+          SyslogProtocol::Rfc3164 => syslog_message_rfc3164(*facility, severity, &now, message),
+          SyslogProtocol::Rfc5424 => syslog_message_rfc5424(
+            *facility,
+            severity,
+            &now,
+            match is_error {
+              true => "error",
+              false => "access",
+            },
+            message,
+          ),
+        };
+        match socket {
+          SyslogSocket::Udp(socket) => socket.send(datagram.as_bytes()).await.map(|_| ()),
+          SyslogSocket::Tcp { address, stream } => {
+            let connected_stream = match stream {
+              Some(stream) => stream,
+              None => stream.insert(TcpStream::connect(address.as_str()).await?),
+            };
+            let frame = format!("{} {}", datagram.len(), datagram);
+            let result = connected_stream.write_all(frame.as_bytes()).await;
+            if result.is_err() {
+              *stream = None;
+            }
+            result
+          }
+          #[cfg(unix)]
+          SyslogSocket::Unix(socket) => socket.send(datagram.as_bytes()).await.map(|_| ()),
+        }
+      }
+      #[cfg(unix)]
+      LogOutput::Journald(socket) => socket
+        .send(&journald_message(message, severity, fields))
+        .await
+        .map(|_| ()),
+    }
+  }
+
+  pub async fn flush(&mut self) -> Result<(), io::Error> {
+    match self {
+      LogOutput::File(log_file) => log_file.flush().await,
+      LogOutput::Stdout(stdout) => stdout.flush().await,
+      LogOutput::Stderr(stderr) => stderr.flush().await,
+      LogOutput::Syslog { .. } => Ok(()),
+      #[cfg(unix)]
+      LogOutput::Journald(_) => Ok(()),
+    }
+  }
+}
+
+// Format the syslog message, as specified in RFC 3164
+fn syslog_message_rfc3164(
+  facility: u8,
+  severity: u8,
+  time: &DateTime<Local>,
+  message: &str,
+) -> String {
+  format!(
+    "<{}>{} {}[{}]: {}",
+    facility as u16 * 8 + severity as u16,
+    time.format("%b %e %H:%M:%S"),
+    LOG_IDENTIFIER,
+    std::process::id(),
+    message
+  )
+}
+
+// Format the syslog message, as specified in RFC 5424. The message has no structured data.
+fn syslog_message_rfc5424(
+  facility: u8,
+  severity: u8,
+  time: &DateTime<Local>,
+  message_id: &str,
+  message: &str,
+) -> String {
+  format!(
+    "<{}>1 {} {} {} {} {} - {}",
+    facility as u16 * 8 + severity as u16,
+    time.format("%Y-%m-%dT%H:%M:%S%.6f%:z"),
+    *SYSLOG_HOSTNAME,
+    LOG_IDENTIFIER,
+    std::process::id(),
+    message_id,
+    message
+  )
+}
+
+// Check if the field name is valid in the systemd journal (uppercase letters, digits and underscores,
+// not starting with a digit or an underscore, at most 64 characters)
+#[cfg(unix)]
+fn is_valid_journald_field_name(name: &str) -> bool {
+  !name.is_empty()
+    && name.len() <= 64
+    && !name.starts_with(|character: char| character == '_' || character.is_ascii_digit())
+    && name
+      .bytes()
+      .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit() || byte == b'_')
+}
+
+// Encode the systemd journal entry with the native protocol. The values containing newlines are encoded
+// in the binary form (the field name, a newline, the 64-bit little-endian length and the value).
+#[cfg(unix)]
+fn journald_message(message: &str, priority: u8, fields: &[(String, String)]) -> Vec<u8> {
+  let mut datagram = Vec::new();
+  let mut add_field = |name: &str, value: &str| {
+    datagram.extend_from_slice(name.as_bytes());
+    match value.contains('\n') {
+      true => {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+      }
+      false => datagram.push(b'='),
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+  };
+  add_field("MESSAGE", message);
+  add_field("PRIORITY", &priority.to_string());
+  add_field("SYSLOG_IDENTIFIER", LOG_IDENTIFIER);
+  for (name, value) in fields {
+    if is_valid_journald_field_name(name) {
+      add_field(name, value);
+    }
+  }
+  datagram
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+  use yaml_rust2::YamlLoader;
+
+  fn parse(yaml: &str) -> Result<Vec<AccessLogSink>, Box<dyn Error + Send + Sync>> {
+    parse_access_log_sinks(&YamlLoader::load_from_str(yaml).unwrap()[0])
+  }
+
+  #[test]
+  fn test_parse_access_log_sinks() {
+    let sinks = parse(
+      r#"
+access:
+  - target: file
+    path: /var/log/ferron/access.log
+  - target: stdout
+    format: "$remote_addr $status"
+    sample: 10
+    alwaysLog: ["5xx", 404]
+  - target: syslog
+    address: "127.0.0.1:514"
+    facility: local3
+  - target: syslog
+    address: "logs.example.com:6514"
+    transport: tcp
+    protocol: rfc3164
+  - target: journald
+"#,
+    )
+    .unwrap();
+    assert_eq!(sinks.len(), 5);
+    assert_eq!(
+      sinks[0].target,
+      LogTarget::File("/var/log/ferron/access.log".to_string())
+    );
+    assert_eq!(sinks[1].target, LogTarget::Stdout);
+    assert_eq!(sinks[1].format.as_deref(), Some("$remote_addr $status"));
+    assert_eq!(
+      sinks[2].target,
+      LogTarget::Syslog {
+        address: "127.0.0.1:514".to_string(),
+        transport: SyslogTransport::Udp,
+        facility: 19,
+        protocol: SyslogProtocol::Rfc5424,
+      }
+    );
+    assert_eq!(
+      sinks[3].target,
+      LogTarget::Syslog {
+        address: "logs.example.com:6514".to_string(),
+        transport: SyslogTransport::Tcp,
+        facility: 16,
+        protocol: SyslogProtocol::Rfc3164,
+      }
+    );
+    assert!(sinks[4].has_structured_fields());
+
+    assert!(parse("access:\n  - target: file").is_err());
+    assert!(parse("access:\n  - target: stdout\n    sample: 0").is_err());
+    assert!(parse("access:\n  - target: syslog\n    facility: local9").is_err());
+    assert!(parse("access:\n  - target: syslog\n    transport: quic").is_err());
+    assert!(parse("access:\n  - target: stdout\n    alwaysLog: [\"6xx\"]").is_err());
+  }
+
+  #[test]
+  fn test_parse_error_log_sinks() {
+    let logging = &YamlLoader::load_from_str(
+      "error:\n  - target: stderr\n  - target: syslog\n    facility: daemon\n",
+    )
+    .unwrap()[0];
+    let targets = parse_error_log_sinks(logging).unwrap();
+    assert_eq!(targets[0], LogTarget::Stderr);
+    assert_eq!(
+      targets[1],
+      LogTarget::Syslog {
+        address: "/dev/log".to_string(),
+        transport: SyslogTransport::Unix,
+        facility: 3,
+        protocol: SyslogProtocol::Rfc5424,
+      }
+    );
+    assert!(
+      parse_error_log_sinks(&YamlLoader::load_from_str("error: stderr").unwrap()[0]).is_err()
+    );
+  }
+
+  #[test]
+  fn test_sampling_and_always_log_rules() {
+    let sinks =
+      parse("access:\n  - target: stdout\n    sample: 1000000\n    alwaysLog: [\"5xx\", 404]")
+        .unwrap();
+    assert!(sinks[0].should_log(503));
+    assert!(sinks[0].should_log(404));
+    assert!((0..100).filter(|_| sinks[0].should_log(200)).count() < 100);
+
+    let sinks = parse("access:\n  - target: stdout\n    sample: 1000000").unwrap();
+    assert!(sinks[0].should_log(403));
+    assert!(sinks[0].should_log(500));
+
+    let sinks = parse("access:\n  - target: stdout").unwrap();
+    assert!(sinks[0].should_log(200));
+  }
+
+  #[test]
+  fn test_syslog_messages() {
+    let time = Local.with_ymd_and_hms(2025, 3, 7, 9, 5, 1).unwrap();
+    let message = syslog_message_rfc3164(16, ACCESS_LOG_SEVERITY, &time, "GET /");
+    assert!(message.starts_with("<134>Mar  7 09:05:01 ferron["));
+    assert!(message.ends_with("]: GET /"));
+
+    let message = syslog_message_rfc5424(16, ERROR_LOG_SEVERITY, &time, "error", "Failure");
+    assert!(message.starts_with("<131>1 2025-03-07T09:05:01.000000"));
+    assert!(message.ends_with(&format!(" ferron {} error - Failure", std::process::id())));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_journald_message() {
+    let datagram = journald_message(
+      "GET / 200",
+      ACCESS_LOG_SEVERITY,
+      &[
+        ("HTTP_STATUS".to_string(), "200".to_string()),
+        ("invalid".to_string(), "ignored".to_string()),
+        ("HTTP_USER_AGENT".to_string(), "a\nb".to_string()),
+      ],
+    );
+    let mut expected = b"MESSAGE=GET / 200\nPRIORITY=6\nSYSLOG_IDENTIFIER=ferron\nHTTP_STATUS=200\nHTTP_USER_AGENT\n".to_vec();
+    expected.extend_from_slice(&3u64.to_le_bytes());
+    expected.extend_from_slice(b"a\nb\n");
+    assert_eq!(datagram, expected);
+  }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::ferron_util::metrics::METRICS;
+
+// The source of the error log messages logged by the server itself, rather than by a module
+pub const SERVER_LOG_SOURCE: &str = "server";
+
+// The length of the window, in which the messages are counted for the rate limit
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+// The state of the error log messages from a single source
+struct LogSourceState {
+  last_message: String,
+  last_message_time: Instant,
+  repeats: u64,
+  rate_window_start: Instant,
+  rate_window_messages: u64,
+  rate_limited: u64,
+}
+
+// Deduplicates and rate-limits the error log messages, so that a flood of identical errors (for example,
+// from a broken backend server) doesn't fill up the error log. The identical messages from the same source
+// within the deduplication window are replaced with a "last message repeated N times" summary,
+// and the messages exceeding the per-source rate limit are replaced with a count of suppressed messages.
+// All the messages, including the suppressed ones, are counted in the metrics.
+pub struct LogThrottle {
+  deduplication_window: Option<Duration>,
+  rate_limit: Option<u64>,
+  sources: HashMap<String, LogSourceState>,
+}
+
+impl LogThrottle {
+  pub fn new(deduplication_window: Option<Duration>, rate_limit: Option<u64>) -> Self {
+    Self {
+      deduplication_window,
+      rate_limit,
+      sources: HashMap::new(),
+    }
+  }
+
+  // Process the error log message from the source. Returns the messages to write to the error log.
+  pub fn process(&mut self, source: &str, message: String, now: Instant) -> Vec<String> {
+    METRICS.increment_counter("ferron_error_log_messages_total", &[("source", source)]);
+    let mut messages = Vec::new();
+
+    let state = self
+      .sources
+      .entry(source.to_string())
+      .or_insert_with(|| LogSourceState {
+        last_message: String::new(),
+        last_message_time: now,
+        repeats: 0,
+        rate_window_start: now,
+        rate_window_messages: 0,
+        rate_limited: 0,
+      });
+
+    if let Some(deduplication_window) = self.deduplication_window {
+      if state.last_message == message
+        && now.saturating_duration_since(state.last_message_time) < deduplication_window
+      {
+        state.repeats += 1;
+        METRICS.increment_counter(
+          "ferron_error_log_messages_suppressed_total",
+          &[("source", source), ("reason", "duplicate")],
+        );
+        return messages;
+      }
+    }
+    messages.extend(repeat_summary(source, state));
+
+    if let Some(rate_limit) = self.rate_limit {
+      if now.saturating_duration_since(state.rate_window_start) >= RATE_LIMIT_WINDOW {
+        messages.extend(rate_limit_summary(source, state));
+        state.rate_window_start = now;
+        state.rate_window_messages = 0;
+      }
+      if state.rate_window_messages >= rate_limit {
+        state.rate_limited += 1;
+        METRICS.increment_counter(
+          "ferron_error_log_messages_suppressed_total",
+          &[("source", source), ("reason", "rate_limit")],
+        );
+        return messages;
+      }
+      state.rate_window_messages += 1;
+    }
+
+    state.last_message.clone_from(&message);
+    state.last_message_time = now;
+    messages.push(message);
+    messages
+  }
+
+  // Write out the summaries of the suppressed messages, whose deduplication or rate limit windows have ended.
+  // Returns the messages to write to the error log.
+  pub fn flush_expired(&mut self, now: Instant) -> Vec<String> {
+    let mut messages = Vec::new();
+    for (source, state) in self.sources.iter_mut() {
+      if self
+        .deduplication_window
+        .is_some_and(|deduplication_window| {
+          now.saturating_duration_since(state.last_message_time) >= deduplication_window
+        })
+      {
+        messages.extend(repeat_summary(source, state));
+      }
+      if now.saturating_duration_since(state.rate_window_start) >= RATE_LIMIT_WINDOW {
+        messages.extend(rate_limit_summary(source, state));
+      }
+    }
+    messages
+  }
+}
+
+// The summary of the repeated messages, if there are any
+fn repeat_summary(source: &str, state: &mut LogSourceState) -> Option<String> {
+  let repeats = std::mem::take(&mut state.repeats);
+  (repeats > 0).then(|| {
+    format!(
+      "Last message repeated {} times (source: {})",
+      repeats, source
+    )
+  })
+}
+
+// The summary of the messages suppressed by the rate limit, if there are any
+fn rate_limit_summary(source: &str, state: &mut LogSourceState) -> Option<String> {
+  let rate_limited = std::mem::take(&mut state.rate_limited);
+  (rate_limited > 0).then(|| {
+    format!(
+      "{} messages were suppressed by the rate limit (source: {})",
+      rate_limited, source
+    )
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_deduplication() {
+    let mut throttle = LogThrottle::new(Some(Duration::from_secs(10)), None);
+    let start = Instant::now();
+    let message = || String::from("Bad gateway");
+    assert_eq!(
+      throttle.process("rproxy", message(), start),
+      vec!["Bad gateway"]
+    );
+    assert!(throttle.process("rproxy", message(), start).is_empty());
+    assert!(throttle
+      .process("rproxy", message(), start + Duration::from_secs(1))
+      .is_empty());
+    // The messages from other sources aren't deduplicated with the messages from the first source
+    assert_eq!(
+      throttle.process(SERVER_LOG_SOURCE, message(), start).len(),
+      1
+    );
+    assert_eq!(
+      throttle.process(
+        "rproxy",
+        String::from("Other error"),
+        start + Duration::from_secs(2)
+      ),
+      vec![
+        "Last message repeated 2 times (source: rproxy)",
+        "Other error"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_deduplication_window_expiry() {
+    let mut throttle = LogThrottle::new(Some(Duration::from_secs(10)), None);
+    let start = Instant::now();
+    throttle.process("rproxy", String::from("Bad gateway"), start);
+    throttle.process("rproxy", String::from("Bad gateway"), start);
+    assert!(throttle
+      .flush_expired(start + Duration::from_secs(5))
+      .is_empty());
+    assert_eq!(
+      throttle.flush_expired(start + Duration::from_secs(10)),
+      vec!["Last message repeated 1 times (source: rproxy)"]
+    );
+    assert!(throttle
+      .flush_expired(start + Duration::from_secs(20))
+      .is_empty());
+    assert_eq!(
+      throttle.process(
+        "rproxy",
+        String::from("Bad gateway"),
+        start + Duration::from_secs(20)
+      ),
+      vec!["Bad gateway"]
+    );
+  }
+
+  #[test]
+  fn test_rate_limit() {
+    let mut throttle = LogThrottle::new(None, Some(2));
+    let start = Instant::now();
+    for index in 0..5 {
+      let written = throttle.process("fcgi", format!("Error {}", index), start);
+      assert_eq!(written.len(), if index < 2 { 1 } else { 0 });
+    }
+    assert_eq!(
+      throttle.process(
+        "fcgi",
+        String::from("Error 5"),
+        start + Duration::from_secs(1)
+      ),
+      vec![
+        "3 messages were suppressed by the rate limit (source: fcgi)",
+        "Error 5"
+      ]
+    );
+  }
+}
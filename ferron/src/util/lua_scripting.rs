@@ -0,0 +1,445 @@
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{StatusCode, Uri};
+use mlua::{
+  FromLuaMulti, HookTriggers, Lua, LuaOptions, StdLib, UserData, UserDataFields, UserDataMethods,
+};
+
+// The maximum amount of memory used by the Lua state of a request
+const LUA_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+// The maximum number of Lua VM instructions executed by a single hook invocation,
+// so that a runaway script can't block the worker thread indefinitely
+const LUA_INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+// The number of Lua VM instructions between the instruction limit checks
+const LUA_INSTRUCTION_CHECK_INTERVAL: u32 = 1000;
+
+// The number of Lua VM instructions executed by the current hook invocation
+struct ExecutedInstructions(u64);
+
+// Create a Lua state for running the scripting hooks of a request.
+// Only the libraries without access to the file system and the processes are loaded.
+pub fn create_lua_state() -> mlua::Result<Lua> {
+  let lua = Lua::new_with(
+    StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+    LuaOptions::default(),
+  )?;
+  lua.set_memory_limit(LUA_MEMORY_LIMIT)?;
+  lua.set_app_data(ExecutedInstructions(0));
+  lua.set_hook(
+    HookTriggers::new().every_nth_instruction(LUA_INSTRUCTION_CHECK_INTERVAL),
+    |lua, _debug| {
+      if let Some(mut executed_instructions) = lua.app_data_mut::<ExecutedInstructions>() {
+        executed_instructions.0 += LUA_INSTRUCTION_CHECK_INTERVAL as u64;
+        if executed_instructions.0 > LUA_INSTRUCTION_LIMIT {
+          return Err(mlua::Error::RuntimeError(format!(
+            "the hook has executed more than {} instructions",
+            LUA_INSTRUCTION_LIMIT
+          )));
+        }
+      }
+      Ok(())
+    },
+  );
+  Ok(lua)
+}
+
+// Check if the Lua code of the hook compiles
+pub fn check_lua_hook(name: &str, code: &str) -> mlua::Result<()> {
+  let lua = create_lua_state()?;
+  lua
+    .load(code)
+    .set_name(format!("={}", name))
+    .into_function()?;
+  Ok(())
+}
+
+// Run the Lua code of the hook, returning the values returned by the code
+pub fn run_lua_hook<'lua, R: FromLuaMulti<'lua>>(
+  lua: &'lua Lua,
+  name: &str,
+  code: &str,
+) -> mlua::Result<R> {
+  lua.set_app_data(ExecutedInstructions(0));
+  lua.load(code).set_name(format!("={}", name)).call(())
+}
+
+// A request or a response, whose headers can be accessed from the Lua code
+trait LuaHeaders {
+  fn headers(&self) -> &HeaderMap;
+  fn headers_mut(&mut self) -> &mut HeaderMap;
+}
+
+// Add the header accessing methods ("header", "header_values", "set_header", "add_header" and "remove_header")
+fn add_header_methods<'lua, T: LuaHeaders + 'static, M: UserDataMethods<'lua, T>>(methods: &mut M) {
+  methods.add_method("header", |_, this, name: String| {
+    Ok(
+      this
+        .headers()
+        .get(&name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from),
+    )
+  });
+  methods.add_method("header_values", |_, this, name: String| {
+    Ok(
+      this
+        .headers()
+        .get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(String::from)
+        .collect::<Vec<_>>(),
+    )
+  });
+  methods.add_method_mut("set_header", |_, this, (name, value): (String, String)| {
+    this.headers_mut().insert(
+      HeaderName::from_str(&name).map_err(mlua::Error::external)?,
+      HeaderValue::from_str(&value).map_err(mlua::Error::external)?,
+    );
+    Ok(())
+  });
+  methods.add_method_mut("add_header", |_, this, (name, value): (String, String)| {
+    this.headers_mut().append(
+      HeaderName::from_str(&name).map_err(mlua::Error::external)?,
+      HeaderValue::from_str(&value).map_err(mlua::Error::external)?,
+    );
+    Ok(())
+  });
+  methods.add_method_mut("remove_header", |_, this, name: String| {
+    this.headers_mut().remove(&name);
+    Ok(())
+  });
+}
+
+// The request exposed to the Lua code as the "request" global variable
+#[derive(Clone)]
+pub struct LuaRequest {
+  pub method: String,
+  pub uri: Uri,
+  pub headers: HeaderMap,
+  pub remote_address: String,
+  pub encrypted: bool,
+  pub auth_user: Option<String>,
+}
+
+impl LuaHeaders for LuaRequest {
+  fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  fn headers_mut(&mut self) -> &mut HeaderMap {
+    &mut self.headers
+  }
+}
+
+impl UserData for LuaRequest {
+  fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+    fields.add_field_method_get("method", |_, this| Ok(this.method.clone()));
+    fields.add_field_method_get("uri", |_, this| Ok(this.uri.to_string()));
+    fields.add_field_method_set("uri", |_, this, uri: String| {
+      this.uri = Uri::from_str(&uri).map_err(mlua::Error::external)?;
+      Ok(())
+    });
+    fields.add_field_method_get("path", |_, this| Ok(this.uri.path().to_string()));
+    fields.add_field_method_get("query", |_, this| Ok(this.uri.query().map(String::from)));
+    fields.add_field_method_get("remote_address", |_, this| Ok(this.remote_address.clone()));
+    fields.add_field_method_get("encrypted", |_, this| Ok(this.encrypted));
+    fields.add_field_method_get("auth_user", |_, this| Ok(this.auth_user.clone()));
+  }
+
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    add_header_methods(methods);
+  }
+}
+
+// The response exposed to the Lua code as the "response" global variable
+pub struct LuaResponse {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+}
+
+impl LuaHeaders for LuaResponse {
+  fn headers(&self) -> &HeaderMap {
+    &self.headers
+  }
+
+  fn headers_mut(&mut self) -> &mut HeaderMap {
+    &mut self.headers
+  }
+}
+
+impl UserData for LuaResponse {
+  fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+    fields.add_field_method_get("status", |_, this| Ok(this.status.as_u16()));
+    fields.add_field_method_set("status", |_, this, status: u16| {
+      this.status = StatusCode::from_u16(status).map_err(mlua::Error::external)?;
+      Ok(())
+    });
+  }
+
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    add_header_methods(methods);
+  }
+}
+
+// A body wrapper, which passes the data frames through the Lua body filter.
+// The filter gets the data as the "chunk" global variable, and whether it's the end of the body as the "eof" global variable.
+// The filter returns the replacement of the chunk, or "nil" to leave the chunk unchanged.
+pub struct LuaBodyFilter {
+  inner: BoxBody<Bytes, std::io::Error>,
+  lua: Mutex<Lua>,
+  code: String,
+  trailers: Option<Frame<Bytes>>,
+  finished: bool,
+}
+
+impl LuaBodyFilter {
+  pub fn new(inner: BoxBody<Bytes, std::io::Error>, lua: Lua, code: String) -> Self {
+    Self {
+      inner,
+      lua: Mutex::new(lua),
+      code,
+      trailers: None,
+      finished: false,
+    }
+  }
+
+  fn filter(&mut self, chunk: Bytes, eof: bool) -> Result<Bytes, std::io::Error> {
+    let lua = self.lua.get_mut().unwrap_or_else(|err| err.into_inner());
+    run_lua_body_filter(lua, &self.code, chunk, eof).map_err(std::io::Error::other)
+  }
+}
+
+// Run the body filter for a chunk of the body, or for the end of the body
+fn run_lua_body_filter(lua: &Lua, code: &str, chunk: Bytes, eof: bool) -> mlua::Result<Bytes> {
+  let globals = lua.globals();
+  globals.set("chunk", lua.create_string(&chunk)?)?;
+  globals.set("eof", eof)?;
+  let filtered: Option<mlua::String> = run_lua_hook(lua, "luaBodyFilter", code)?;
+  Ok(match filtered {
+    Some(filtered) => Bytes::copy_from_slice(filtered.as_bytes()),
+    None => chunk,
+  })
+}
+
+impl Body for LuaBodyFilter {
+  type Data = Bytes;
+  type Error = std::io::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    loop {
+      if this.finished {
+        return Poll::Ready(this.trailers.take().map(Ok));
+      }
+      match Pin::new(&mut this.inner).poll_frame(cx) {
+        Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+          Ok(data) => {
+            let filtered = match this.filter(data, false) {
+              Ok(filtered) => filtered,
+              Err(err) => {
+                this.finished = true;
+                return Poll::Ready(Some(Err(err)));
+              }
+            };
+            // Empty chunks are dropped, since the filter might hold back the data until the end of the body
+            if !filtered.is_empty() {
+              return Poll::Ready(Some(Ok(Frame::data(filtered))));
+            }
+          }
+          Err(frame) => {
+            // The trailers end the body, so the filter is run for the end of the body before they're sent
+            this.trailers = Some(frame);
+            this.finished = true;
+            match this.filter(Bytes::new(), true) {
+              Ok(filtered) if !filtered.is_empty() => {
+                return Poll::Ready(Some(Ok(Frame::data(filtered))))
+              }
+              Ok(_) => (),
+              Err(err) => {
+                this.trailers = None;
+                return Poll::Ready(Some(Err(err)));
+              }
+            }
+          }
+        },
+        Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+        Poll::Ready(None) => {
+          this.finished = true;
+          match this.filter(Bytes::new(), true) {
+            Ok(filtered) if !filtered.is_empty() => {
+              return Poll::Ready(Some(Ok(Frame::data(filtered))))
+            }
+            Ok(_) => (),
+            Err(err) => return Poll::Ready(Some(Err(err))),
+          }
+        }
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    // The end of the stream is polled, so that the filter is run for the end of the body
+    self.finished && self.trailers.is_none()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    // The filter can change the length of the body
+    SizeHint::default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, StreamBody};
+
+  fn body_from_frames(frames: Vec<Frame<Bytes>>) -> BoxBody<Bytes, std::io::Error> {
+    StreamBody::new(futures_util::stream::iter(
+      frames.into_iter().map(Ok::<_, std::io::Error>),
+    ))
+    .boxed()
+  }
+
+  #[test]
+  fn test_check_lua_hook() {
+    assert!(check_lua_hook(
+      "luaAccess",
+      "if request.path == '/admin' then return 403 end"
+    )
+    .is_ok());
+    assert!(check_lua_hook("luaAccess", "if request.path == then").is_err());
+  }
+
+  #[test]
+  fn test_sandboxed_libraries() {
+    let lua = create_lua_state().unwrap();
+    let loaded: (bool, bool, bool) = run_lua_hook(
+      &lua,
+      "luaRewrite",
+      "return io ~= nil, os ~= nil, string ~= nil",
+    )
+    .unwrap();
+    assert_eq!(loaded, (false, false, true));
+  }
+
+  #[test]
+  fn test_instruction_limit() {
+    let lua = create_lua_state().unwrap();
+    assert!(run_lua_hook::<()>(&lua, "luaRewrite", "while true do end").is_err());
+    // The limit applies to a single hook invocation
+    assert!(run_lua_hook::<()>(
+      &lua,
+      "luaRewrite",
+      "local a = 0 for i = 1, 1000 do a = a + i end"
+    )
+    .is_ok());
+  }
+
+  #[test]
+  fn test_lua_request() {
+    let lua = create_lua_state().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-example", "old".parse().unwrap());
+    lua
+      .globals()
+      .set(
+        "request",
+        LuaRequest {
+          method: String::from("GET"),
+          uri: Uri::from_static("/old?a=1"),
+          headers,
+          remote_address: String::from("127.0.0.1"),
+          encrypted: false,
+          auth_user: None,
+        },
+      )
+      .unwrap();
+    let query: Option<String> = run_lua_hook(
+      &lua,
+      "luaRewrite",
+      "local query = request.query \
+       request.uri = '/new' \
+       request:set_header('X-Example', request:header('x-example') .. '-new') \
+       return query",
+    )
+    .unwrap();
+    assert_eq!(query.as_deref(), Some("a=1"));
+
+    let request = lua
+      .globals()
+      .get::<_, mlua::AnyUserData>("request")
+      .unwrap()
+      .take::<LuaRequest>()
+      .unwrap();
+    assert_eq!(request.uri, Uri::from_static("/new"));
+    assert_eq!(request.headers.get("x-example").unwrap(), "old-new");
+  }
+
+  #[test]
+  fn test_lua_response_invalid_status() {
+    let lua = create_lua_state().unwrap();
+    lua
+      .globals()
+      .set(
+        "response",
+        LuaResponse {
+          status: StatusCode::OK,
+          headers: HeaderMap::new(),
+        },
+      )
+      .unwrap();
+    assert!(run_lua_hook::<()>(&lua, "luaHeaderFilter", "response.status = 1000").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_lua_body_filter() {
+    let body = LuaBodyFilter::new(
+      body_from_frames(vec![
+        Frame::data(Bytes::from("hello ")),
+        Frame::data(Bytes::from("world")),
+      ]),
+      create_lua_state().unwrap(),
+      String::from("if eof then return '!' end return string.upper(chunk)"),
+    );
+    let collected = body.collect().await.unwrap().to_bytes();
+    assert_eq!(collected, Bytes::from("HELLO WORLD!"));
+  }
+
+  #[tokio::test]
+  async fn test_lua_body_filter_buffering() {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-trailer", "value".parse().unwrap());
+    let body = LuaBodyFilter::new(
+      body_from_frames(vec![
+        Frame::data(Bytes::from("a")),
+        Frame::data(Bytes::from("b")),
+        Frame::trailers(trailers),
+      ]),
+      create_lua_state().unwrap(),
+      String::from(
+        "buffered = (buffered or '') .. chunk \
+         if eof then return string.reverse(buffered) end \
+         return ''",
+      ),
+    );
+    let collected = body.collect().await.unwrap();
+    assert_eq!(
+      collected.trailers().unwrap().get("x-trailer").unwrap(),
+      "value"
+    );
+    assert_eq!(collected.to_bytes(), Bytes::from("ba"));
+  }
+}
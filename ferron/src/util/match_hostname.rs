@@ -21,10 +21,32 @@ pub fn match_hostname(hostname: Option<&str>, req_hostname: Option<&str>) -> boo
   false
 }
 
+// Remove the port from the value of the "Host" header (for example, "example.com:8443" or "[::1]:8443")
+pub fn strip_host_port(host: &str) -> &str {
+  if host.starts_with('[') {
+    return match host.find(']') {
+      Some(bracket_index) => &host[..=bracket_index],
+      None => host,
+    };
+  }
+  match host.rsplit_once(':') {
+    Some((hostname, port)) if port.bytes().all(|b| b.is_ascii_digit()) => hostname,
+    _ => host,
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  #[test]
+  fn should_strip_port_from_host_header() {
+    assert_eq!(strip_host_port("example.com:8443"), "example.com");
+    assert_eq!(strip_host_port("example.com"), "example.com");
+    assert_eq!(strip_host_port("[::1]:8443"), "[::1]");
+    assert_eq!(strip_host_port("[::1]"), "[::1]");
+  }
+
   #[test]
   fn should_return_true_if_hostname_is_undefined() {
     assert!(match_hostname(None, Some("example.com")));
@@ -1,33 +1,138 @@
-pub fn match_location(path: &str, req_path: &str) -> bool {
-  let mut path_without_trailing_slashes = path;
-  while path_without_trailing_slashes.ends_with("/") {
-    path_without_trailing_slashes =
-      &path_without_trailing_slashes[..(path_without_trailing_slashes.len() - 1)];
-  }
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, LazyLock, RwLock};
 
-  let mut path_prepared = path_without_trailing_slashes.to_owned();
-  let mut req_path_prepared = req_path.to_owned();
+use fancy_regex::{Regex, RegexBuilder};
+use yaml_rust2::Yaml;
+
+// The regular expressions of the locations, compiled once, as the locations are matched for every request
+static LOCATION_REGEX_CACHE: LazyLock<RwLock<HashMap<String, Arc<Regex>>>> =
+  LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// Normalize the path for matching, removing the trailing slashes and the repeated slashes
+fn normalize_path(path: &str) -> String {
+  let mut path_prepared = path.trim_end_matches('/').to_owned();
 
   while path_prepared.contains("//") {
     path_prepared = path_prepared.replace("//", "/");
   }
 
+  if cfg!(windows) {
+    path_prepared = path_prepared.to_lowercase();
+  }
+
+  path_prepared
+}
+
+// Obtain the length of the location path prefix matching the request path, or None if the path doesn't match
+fn match_location_prefix(path: &str, req_path: &str) -> Option<usize> {
+  let path_prepared = normalize_path(path);
+  let mut req_path_prepared = req_path.to_owned();
+
   while req_path_prepared.contains("//") {
-    req_path_prepared = path_prepared.replace("//", "/");
+    req_path_prepared = req_path_prepared.replace("//", "/");
   }
 
   if cfg!(windows) {
-    path_prepared = path_prepared.to_lowercase();
     req_path_prepared = req_path_prepared.to_lowercase();
   }
 
-  path_prepared == req_path_prepared
+  if req_path_prepared == path_prepared
     || req_path_prepared.starts_with(&format!("{}/", path_prepared))
+  {
+    Some(path_prepared.len())
+  } else {
+    None
+  }
+}
+
+// Compile the regular expression of a location, or obtain it from the cache
+fn compile_location_regex(pattern: &str) -> Result<Arc<Regex>, Box<dyn Error + Send + Sync>> {
+  if let Ok(cache) = LOCATION_REGEX_CACHE.read() {
+    if let Some(regex) = cache.get(pattern) {
+      return Ok(regex.clone());
+    }
+  }
+  let regex = Arc::new(
+    RegexBuilder::new(pattern)
+      .case_insensitive(cfg!(windows))
+      .build()?,
+  );
+  if let Ok(mut cache) = LOCATION_REGEX_CACHE.write() {
+    cache.insert(pattern.to_string(), regex.clone());
+  }
+  Ok(regex)
+}
+
+// The way a location matches the request path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationMatch {
+  // The location path prefix matches, with the specified length
+  Prefix(usize),
+  // The location regular expression matches
+  Regex,
+}
+
+// The request path matcher of a location, either a path prefix ("path" property) or a regular expression ("pathRegex" property)
+pub enum LocationMatcher {
+  Prefix(String),
+  Regex(Arc<Regex>),
+}
+
+impl LocationMatcher {
+  // Create a location matcher from the location configuration. A location without a path matches every request path.
+  pub fn from_yaml(location: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    if let Some(path_regex) = location["pathRegex"].as_str() {
+      Ok(LocationMatcher::Regex(compile_location_regex(path_regex)?))
+    } else {
+      Ok(LocationMatcher::Prefix(
+        location["path"].as_str().unwrap_or_default().to_string(),
+      ))
+    }
+  }
+
+  pub fn matches(&self, req_path: &str) -> Option<LocationMatch> {
+    match self {
+      LocationMatcher::Prefix(path) => {
+        match_location_prefix(path, req_path).map(LocationMatch::Prefix)
+      }
+      LocationMatcher::Regex(regex) => match regex.is_match(req_path) {
+        Ok(true) => Some(LocationMatch::Regex),
+        _ => None,
+      },
+    }
+  }
+}
+
+// Select the location for the request path from the matching locations (in the configuration order).
+// Like in nginx, the first matching regular expression location wins,
+// and otherwise the location with the longest matching path prefix wins (the first one, in case of a tie).
+pub fn select_location<T>(matches: impl IntoIterator<Item = (T, LocationMatch)>) -> Option<T> {
+  let mut longest_prefix: Option<(T, usize)> = None;
+  for (location, location_match) in matches {
+    match location_match {
+      LocationMatch::Regex => return Some(location),
+      LocationMatch::Prefix(length) => {
+        if longest_prefix
+          .as_ref()
+          .is_none_or(|(_, longest_length)| length > *longest_length)
+        {
+          longest_prefix = Some((location, length));
+        }
+      }
+    }
+  }
+  longest_prefix.map(|(location, _)| location)
 }
 
 #[cfg(test)]
 mod tests {
-  use super::match_location;
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn match_location(path: &str, req_path: &str) -> bool {
+    match_location_prefix(path, req_path).is_some()
+  }
 
   #[test]
   fn test_exact_match() {
@@ -58,6 +163,7 @@ mod tests {
   fn test_multiple_slashes() {
     assert!(match_location("/api//v1", "/api/v1"));
     assert!(match_location("//home///", "/home"));
+    assert!(match_location("/api/v1", "/api//v1/users"));
   }
 
   #[test]
@@ -68,4 +174,41 @@ mod tests {
       assert!(match_location("/Home", "/home"));
     }
   }
+
+  #[test]
+  fn test_select_location() {
+    let locations = YamlLoader::load_from_str(
+      r#"
+      - path: /
+      - path: /api/v1
+      - path: /api
+      - pathRegex: \.php$
+      "#,
+    )
+    .unwrap()[0]
+      .clone();
+    let matchers = locations
+      .as_vec()
+      .unwrap()
+      .iter()
+      .map(|location| LocationMatcher::from_yaml(location).unwrap())
+      .collect::<Vec<_>>();
+    let select = |req_path: &str| {
+      select_location(
+        matchers
+          .iter()
+          .enumerate()
+          .filter_map(|(index, matcher)| matcher.matches(req_path).map(|m| (index, m))),
+      )
+    };
+
+    assert_eq!(select("/api/v1/users"), Some(1));
+    assert_eq!(select("/api/v2"), Some(2));
+    assert_eq!(select("/index.html"), Some(0));
+    assert_eq!(select("/api/v1/index.php"), Some(3));
+    assert!(
+      LocationMatcher::from_yaml(&YamlLoader::load_from_str("pathRegex: \"(\"").unwrap()[0])
+        .is_err()
+    );
+  }
 }
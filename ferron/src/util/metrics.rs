@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::RwLock;
+
+// The process-wide metrics registry
+pub static METRICS: Metrics = Metrics::new();
+
+// The upper bounds (in seconds) of the histogram buckets, suited for the request durations
+const HISTOGRAM_BUCKETS: [f64; 11] = [
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// A histogram with the cumulative bucket counts, the number of the observations and their sum
+struct Histogram {
+  buckets: [AtomicU64; HISTOGRAM_BUCKETS.len()],
+  count: AtomicU64,
+  // The sum is stored as the bits of a floating-point number
+  sum: AtomicU64,
+}
+
+impl Histogram {
+  fn new() -> Self {
+    Self {
+      buckets: Default::default(),
+      count: AtomicU64::new(0),
+      sum: AtomicU64::new(0f64.to_bits()),
+    }
+  }
+
+  fn observe(&self, value: f64) {
+    for (bucket, upper_bound) in self.buckets.iter().zip(HISTOGRAM_BUCKETS) {
+      if value <= upper_bound {
+        bucket.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    self.count.fetch_add(1, Ordering::Relaxed);
+    self
+      .sum
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+        Some((f64::from_bits(sum) + value).to_bits())
+      })
+      .unwrap_or_default();
+  }
+}
+
+// A registry of counters, gauges and histograms, which can be rendered in the Prometheus text exposition format
+pub struct Metrics {
+  counters: RwLock<BTreeMap<(String, String), AtomicU64>>,
+  gauges: RwLock<BTreeMap<(String, String), AtomicI64>>,
+  histograms: RwLock<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Metrics {
+  pub const fn new() -> Self {
+    Self {
+      counters: RwLock::new(BTreeMap::new()),
+      gauges: RwLock::new(BTreeMap::new()),
+      histograms: RwLock::new(BTreeMap::new()),
+    }
+  }
+
+  // Increment a counter with specified labels by one
+  pub fn increment_counter(&self, name: &str, labels: &[(&str, &str)]) {
+    self.add_to_counter(name, labels, 1);
+  }
+
+  // Increment a counter with specified labels by a specified value
+  pub fn add_to_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+    let key = (name.to_string(), format_labels(labels));
+    if let Ok(counters) = self.counters.read() {
+      if let Some(counter) = counters.get(&key) {
+        counter.fetch_add(value, Ordering::Relaxed);
+        return;
+      }
+    }
+    if let Ok(mut counters) = self.counters.write() {
+      counters
+        .entry(key)
+        .or_insert_with(|| AtomicU64::new(0))
+        .fetch_add(value, Ordering::Relaxed);
+    }
+  }
+
+  // Add a (possibly negative) value to a gauge with specified labels
+  pub fn add_to_gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+    let key = (name.to_string(), format_labels(labels));
+    if let Ok(gauges) = self.gauges.read() {
+      if let Some(gauge) = gauges.get(&key) {
+        gauge.fetch_add(value, Ordering::Relaxed);
+        return;
+      }
+    }
+    if let Ok(mut gauges) = self.gauges.write() {
+      gauges
+        .entry(key)
+        .or_insert_with(|| AtomicI64::new(0))
+        .fetch_add(value, Ordering::Relaxed);
+    }
+  }
+
+  // Set a gauge with specified labels to a specified value
+  pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: i64) {
+    let key = (name.to_string(), format_labels(labels));
+    if let Ok(gauges) = self.gauges.read() {
+      if let Some(gauge) = gauges.get(&key) {
+        gauge.store(value, Ordering::Relaxed);
+        return;
+      }
+    }
+    if let Ok(mut gauges) = self.gauges.write() {
+      gauges
+        .entry(key)
+        .or_insert_with(|| AtomicI64::new(0))
+        .store(value, Ordering::Relaxed);
+    }
+  }
+
+  // Record an observation (for example, a duration in seconds) in a histogram with specified labels
+  pub fn observe_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+    let key = (name.to_string(), format_labels(labels));
+    if let Ok(histograms) = self.histograms.read() {
+      if let Some(histogram) = histograms.get(&key) {
+        histogram.observe(value);
+        return;
+      }
+    }
+    if let Ok(mut histograms) = self.histograms.write() {
+      histograms
+        .entry(key)
+        .or_insert_with(Histogram::new)
+        .observe(value);
+    }
+  }
+
+  // Render the metrics in the Prometheus text exposition format
+  pub fn render(&self) -> String {
+    let mut output = String::new();
+    if let Ok(counters) = self.counters.read() {
+      let mut previous_name: Option<&str> = None;
+      for ((name, labels), counter) in counters.iter() {
+        if previous_name != Some(name) {
+          writeln!(output, "# TYPE {} counter", name).unwrap_or_default();
+          previous_name = Some(name);
+        }
+        writeln!(
+          output,
+          "{}{} {}",
+          name,
+          labels,
+          counter.load(Ordering::Relaxed)
+        )
+        .unwrap_or_default();
+      }
+    }
+    if let Ok(gauges) = self.gauges.read() {
+      let mut previous_name: Option<&str> = None;
+      for ((name, labels), gauge) in gauges.iter() {
+        if previous_name != Some(name) {
+          writeln!(output, "# TYPE {} gauge", name).unwrap_or_default();
+          previous_name = Some(name);
+        }
+        writeln!(
+          output,
+          "{}{} {}",
+          name,
+          labels,
+          gauge.load(Ordering::Relaxed)
+        )
+        .unwrap_or_default();
+      }
+    }
+    if let Ok(histograms) = self.histograms.read() {
+      let mut previous_name: Option<&str> = None;
+      for ((name, labels), histogram) in histograms.iter() {
+        if previous_name != Some(name) {
+          writeln!(output, "# TYPE {} histogram", name).unwrap_or_default();
+          previous_name = Some(name);
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        let bucket_counts = histogram
+          .buckets
+          .iter()
+          .map(|bucket| bucket.load(Ordering::Relaxed));
+        for (upper_bound, bucket_count) in HISTOGRAM_BUCKETS
+          .iter()
+          .map(|upper_bound| upper_bound.to_string())
+          .chain([String::from("+Inf")])
+          .zip(bucket_counts.chain([count]))
+        {
+          writeln!(
+            output,
+            "{}_bucket{} {}",
+            name,
+            with_bucket_label(labels, &upper_bound),
+            bucket_count
+          )
+          .unwrap_or_default();
+        }
+        writeln!(
+          output,
+          "{}_sum{} {}",
+          name,
+          labels,
+          f64::from_bits(histogram.sum.load(Ordering::Relaxed))
+        )
+        .unwrap_or_default();
+        writeln!(output, "{}_count{} {}", name, labels, count).unwrap_or_default();
+      }
+    }
+    output
+  }
+}
+
+// Add the "le" label (the upper bound of the histogram bucket) to the formatted labels
+fn with_bucket_label(labels: &str, upper_bound: &str) -> String {
+  match labels.strip_suffix('}') {
+    Some(labels) => format!("{},le=\"{}\"}}", labels, upper_bound),
+    None => format!("{{le=\"{}\"}}", upper_bound),
+  }
+}
+
+// Format the labels as "{name="value",...}", escaping the label values
+fn format_labels(labels: &[(&str, &str)]) -> String {
+  if labels.is_empty() {
+    return String::new();
+  }
+  let formatted_labels = labels
+    .iter()
+    .map(|(name, value)| {
+      format!(
+        "{}=\"{}\"",
+        name,
+        value
+          .replace('\\', "\\\\")
+          .replace('"', "\\\"")
+          .replace('\n', "\\n")
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(",");
+  format!("{{{}}}", formatted_labels)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_counters() {
+    let metrics = Metrics::new();
+    metrics.increment_counter("requests_total", &[("reason", "timeout")]);
+    metrics.increment_counter("requests_total", &[("reason", "timeout")]);
+    metrics.add_to_counter("requests_total", &[("reason", "too_large")], 5);
+    metrics.increment_counter("bans_total", &[]);
+
+    assert_eq!(
+      metrics.render(),
+      "# TYPE bans_total counter\nbans_total 1\n# TYPE requests_total counter\nrequests_total{reason=\"timeout\"} 2\nrequests_total{reason=\"too_large\"} 5\n"
+    );
+  }
+
+  #[test]
+  fn test_gauges() {
+    let metrics = Metrics::new();
+    metrics.add_to_gauge("cache_entries", &[("host", "example.com")], 3);
+    metrics.add_to_gauge("cache_entries", &[("host", "example.com")], -1);
+    metrics.set_gauge("cache_size_bytes", &[], 100);
+    metrics.set_gauge("cache_size_bytes", &[], 50);
+    metrics.increment_counter("cache_requests_total", &[]);
+
+    assert_eq!(
+      metrics.render(),
+      "# TYPE cache_requests_total counter\ncache_requests_total 1\n# TYPE cache_entries gauge\ncache_entries{host=\"example.com\"} 2\n# TYPE cache_size_bytes gauge\ncache_size_bytes 50\n"
+    );
+  }
+
+  #[test]
+  fn test_histograms() {
+    let metrics = Metrics::new();
+    metrics.observe_histogram("request_duration_seconds", &[("host", "example.com")], 0.02);
+    metrics.observe_histogram("request_duration_seconds", &[("host", "example.com")], 3.0);
+    metrics.observe_histogram("request_duration_seconds", &[("host", "example.com")], 20.0);
+
+    let rendered = metrics.render();
+    assert!(rendered.starts_with("# TYPE request_duration_seconds histogram\n"));
+    assert!(
+      rendered.contains("request_duration_seconds_bucket{host=\"example.com\",le=\"0.01\"} 0\n")
+    );
+    assert!(
+      rendered.contains("request_duration_seconds_bucket{host=\"example.com\",le=\"0.025\"} 1\n")
+    );
+    assert!(rendered.contains("request_duration_seconds_bucket{host=\"example.com\",le=\"5\"} 2\n"));
+    assert!(
+      rendered.contains("request_duration_seconds_bucket{host=\"example.com\",le=\"+Inf\"} 3\n")
+    );
+    assert!(rendered.contains("request_duration_seconds_sum{host=\"example.com\"} 23.02\n"));
+    assert!(rendered.contains("request_duration_seconds_count{host=\"example.com\"} 3\n"));
+  }
+
+  #[test]
+  fn test_label_escaping() {
+    assert_eq!(
+      format_labels(&[("module", "a\"b\\c"), ("host", "example.com")]),
+      "{module=\"a\\\"b\\\\c\",host=\"example.com\"}"
+    );
+  }
+}
@@ -0,0 +1,159 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::body::{Body, Buf, Frame, SizeHint};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+// The default time the client can take before the minimum transfer rate is enforced (in milliseconds)
+pub const DEFAULT_MIN_RATE_GRACE_PERIOD: u64 = 5000;
+
+// The minimum transfer rate enforcement for the data sent by the client. Only the time spent waiting for the client counts,
+// so that the client isn't penalized when the server reads the data slower (for example, when a backend server receives it slowly).
+pub struct MinTransferRate {
+  min_rate: u64,
+  // The time the client can spend sending the data received so far, including the grace period
+  allowed: Duration,
+  waited: Duration,
+  waiting_since: Option<Instant>,
+  deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl MinTransferRate {
+  pub fn new(min_rate: u64, grace_period: Duration) -> Self {
+    Self {
+      min_rate,
+      allowed: grace_period,
+      waited: Duration::ZERO,
+      waiting_since: None,
+      deadline: None,
+    }
+  }
+
+  // Count the data received from the client
+  pub fn record(&mut self, bytes: usize) {
+    if let Some(waiting_since) = self.waiting_since.take() {
+      self.waited += waiting_since.elapsed();
+    }
+    self.deadline = None;
+    if self.min_rate > 0 {
+      self.allowed += Duration::from_secs_f64(bytes as f64 / self.min_rate as f64);
+    }
+  }
+
+  // Wait for the client to send more data. Returns true if the client is sending the data slower than the minimum transfer rate.
+  pub fn poll_too_slow(&mut self, cx: &mut Context<'_>) -> bool {
+    let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+    let remaining = self.allowed.saturating_sub(self.waited);
+    let deadline = self
+      .deadline
+      .get_or_insert_with(|| Box::pin(sleep_until(waiting_since + remaining)));
+    deadline.as_mut().poll(cx).is_ready()
+  }
+}
+
+// A request body wrapper, which ends the body early if the client sends it slower than the minimum transfer rate.
+// Like with the request body timeout, the violation is signaled with a shared flag.
+pub struct MinRateBody<B> {
+  inner: B,
+  rate: MinTransferRate,
+  too_slow: Arc<AtomicBool>,
+}
+
+impl<B> MinRateBody<B> {
+  pub fn new(inner: B, rate: MinTransferRate, too_slow: Arc<AtomicBool>) -> Self {
+    Self {
+      inner,
+      rate,
+      too_slow,
+    }
+  }
+}
+
+impl<B: Body + Unpin> Body for MinRateBody<B> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if this.too_slow.load(Ordering::Relaxed) {
+      return Poll::Ready(None);
+    }
+    match Pin::new(&mut this.inner).poll_frame(cx) {
+      Poll::Ready(frame) => {
+        this.rate.record(match &frame {
+          Some(Ok(frame)) => frame.data_ref().map_or(0, |data| data.remaining()),
+          _ => 0,
+        });
+        Poll::Ready(frame)
+      }
+      Poll::Pending => {
+        if this.rate.poll_too_slow(cx) {
+          this.too_slow.store(true, Ordering::Relaxed);
+          Poll::Ready(None)
+        } else {
+          Poll::Pending
+        }
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.too_slow.load(Ordering::Relaxed) || self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures_util::StreamExt;
+  use http_body_util::{BodyExt, StreamBody};
+  use hyper::body::Bytes;
+
+  fn trickled_body(
+    chunk_size: usize,
+    interval: Duration,
+  ) -> StreamBody<impl futures_util::Stream<Item = Result<Frame<Bytes>, std::io::Error>> + Unpin>
+  {
+    StreamBody::new(Box::pin(futures_util::stream::iter(0..10).then(
+      move |_| async move {
+        tokio::time::sleep(interval).await;
+        Ok(Frame::data(Bytes::from(vec![0u8; chunk_size])))
+      },
+    )))
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_body_trickled_too_slowly() {
+    let too_slow = Arc::new(AtomicBool::new(false));
+    let body = MinRateBody::new(
+      trickled_body(10, Duration::from_secs(1)),
+      MinTransferRate::new(100, Duration::from_secs(2)),
+      too_slow.clone(),
+    );
+    assert!(body.collect().await.unwrap().to_bytes().len() < 100);
+    assert!(too_slow.load(Ordering::Relaxed));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_body_sent_fast_enough() {
+    let too_slow = Arc::new(AtomicBool::new(false));
+    let body = MinRateBody::new(
+      trickled_body(200, Duration::from_secs(1)),
+      MinTransferRate::new(100, Duration::from_secs(2)),
+      too_slow.clone(),
+    );
+    assert_eq!(body.collect().await.unwrap().to_bytes().len(), 2000);
+    assert!(!too_slow.load(Ordering::Relaxed));
+  }
+}
@@ -0,0 +1,93 @@
+use ferron_common::{
+  ModuleAbi, MODULE_ABI_VERSION, MODULE_CAPABILITY_BYTE_COUNTERS,
+  MODULE_CAPABILITY_CLIENT_IDENTITY, MODULE_CAPABILITY_CONFIG_VALIDATION,
+  MODULE_CAPABILITY_REQUEST_BODY_BUFFERING, MODULE_CAPABILITY_REQUEST_VARIABLES,
+  MODULE_CAPABILITY_RESPONSE_BODY_FILTERS, MODULE_CAPABILITY_SUBREQUESTS,
+  SUPPORTED_MODULE_CAPABILITIES,
+};
+
+// The names of the module capabilities, used in the error messages
+const MODULE_CAPABILITY_NAMES: [(u64, &str); 7] = [
+  (
+    MODULE_CAPABILITY_CONFIG_VALIDATION,
+    "configuration validation",
+  ),
+  (MODULE_CAPABILITY_REQUEST_VARIABLES, "request variables"),
+  (MODULE_CAPABILITY_BYTE_COUNTERS, "byte counters"),
+  (MODULE_CAPABILITY_CLIENT_IDENTITY, "client identity"),
+  (MODULE_CAPABILITY_SUBREQUESTS, "subrequests"),
+  (
+    MODULE_CAPABILITY_REQUEST_BODY_BUFFERING,
+    "request body buffering",
+  ),
+  (
+    MODULE_CAPABILITY_RESPONSE_BODY_FILTERS,
+    "response body filters",
+  ),
+];
+
+// Check if the module declared by the ABI declaration can be loaded by the server.
+// The module must be built against the same module ABI version, and it can't require the capabilities the server doesn't support.
+pub fn check_module_abi(module_abi: &ModuleAbi) -> Result<(), anyhow::Error> {
+  if module_abi.abi_version != MODULE_ABI_VERSION {
+    Err(anyhow::anyhow!(
+      "The module was built for the module ABI version {}, but the server supports only the version {}. Rebuild the module against the server's version of \"ferron-common\"",
+      module_abi.abi_version,
+      MODULE_ABI_VERSION
+    ))?
+  }
+
+  let unsupported_capabilities = module_abi.capabilities & !SUPPORTED_MODULE_CAPABILITIES;
+  if unsupported_capabilities != 0 {
+    let mut unsupported_capability_names = Vec::new();
+    for (capability, capability_name) in MODULE_CAPABILITY_NAMES {
+      if unsupported_capabilities & capability != 0 {
+        unsupported_capability_names.push(capability_name.to_string());
+      }
+    }
+    let unknown_capabilities = MODULE_CAPABILITY_NAMES
+      .iter()
+      .fold(unsupported_capabilities, |capabilities, (capability, _)| {
+        capabilities & !capability
+      });
+    if unknown_capabilities != 0 {
+      unsupported_capability_names.push(format!("unknown (0x{:x})", unknown_capabilities));
+    }
+    Err(anyhow::anyhow!(
+      "The module requires the capabilities not supported by the server: {}",
+      unsupported_capability_names.join(", ")
+    ))?
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_check_module_abi() {
+    assert!(check_module_abi(&ModuleAbi::new(0)).is_ok());
+    assert!(check_module_abi(&ModuleAbi::new(
+      MODULE_CAPABILITY_CONFIG_VALIDATION | MODULE_CAPABILITY_SUBREQUESTS
+    ))
+    .is_ok());
+  }
+
+  #[test]
+  fn test_check_module_abi_refuses_incompatible_modules() {
+    let mut module_abi = ModuleAbi::new(0);
+    module_abi.abi_version = MODULE_ABI_VERSION + 1;
+    assert!(check_module_abi(&module_abi).is_err());
+
+    let err = check_module_abi(&ModuleAbi::new(
+      MODULE_CAPABILITY_CONFIG_VALIDATION | (1 << 63),
+    ))
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "The module requires the capabilities not supported by the server: unknown (0x8000000000000000)"
+    );
+  }
+}
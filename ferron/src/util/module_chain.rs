@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use ferron_common::ServerConfigRoot;
+use yaml_rust2::Yaml;
+
+// Parse the list of the module names
+fn parse_module_names(modules_yaml: &Yaml) -> Option<Vec<&str>> {
+  modules_yaml
+    .as_vec()?
+    .iter()
+    .map(|module_name| module_name.as_str())
+    .collect()
+}
+
+// Build the chain of the module handlers for the request from the host or location configuration.
+// If the "modules" property is set, only the listed modules run, in the listed order.
+// The modules listed in the "disabledModules" property don't run.
+pub fn select_modules<T>(
+  handlers: Vec<(Arc<str>, T)>,
+  config: &ServerConfigRoot,
+) -> Vec<(Arc<str>, T)> {
+  let modules_yaml = config.get("modules");
+  let mut handlers = match parse_module_names(&modules_yaml) {
+    Some(module_names) => {
+      let mut handlers = handlers.into_iter().map(Some).collect::<Vec<_>>();
+      let mut selected_handlers = Vec::new();
+      for module_name in module_names {
+        if let Some(handler) = handlers
+          .iter_mut()
+          .find(|handler| {
+            handler
+              .as_ref()
+              .is_some_and(|(name, _)| name.as_ref() == module_name)
+          })
+          .and_then(|handler| handler.take())
+        {
+          selected_handlers.push(handler);
+        }
+      }
+      selected_handlers
+    }
+    None => handlers,
+  };
+
+  let disabled_modules_yaml = config.get("disabledModules");
+  if let Some(disabled_module_names) = parse_module_names(&disabled_modules_yaml) {
+    handlers.retain(|(name, _)| !disabled_module_names.contains(&name.as_ref()));
+  }
+
+  handlers
+}
+
+// Validate the "modules" and "disabledModules" properties. The listed modules must be loaded, and can't be repeated.
+pub fn validate_module_lists(
+  config: &ServerConfigRoot,
+  loaded_module_names: &[Arc<str>],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  for property in ["modules", "disabledModules"] {
+    let modules_yaml = config.get(property);
+    if modules_yaml.is_badvalue() {
+      continue;
+    }
+    let module_names = match parse_module_names(&modules_yaml) {
+      Some(module_names) => module_names,
+      None => Err(anyhow::anyhow!("Invalid module list in \"{}\"", property))?,
+    };
+    for (index, module_name) in module_names.iter().enumerate() {
+      if !loaded_module_names
+        .iter()
+        .any(|loaded_module_name| loaded_module_name.as_ref() == *module_name)
+      {
+        Err(anyhow::anyhow!(
+          "The module \"{}\" listed in \"{}\" isn't loaded",
+          module_name,
+          property
+        ))?
+      }
+      if module_names[..index].contains(module_name) {
+        Err(anyhow::anyhow!(
+          "The module \"{}\" is listed in \"{}\" more than once",
+          module_name,
+          property
+        ))?
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(yaml: &str) -> ServerConfigRoot {
+    ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(yaml)
+        .unwrap()
+        .remove(0),
+    )
+  }
+
+  fn handlers() -> Vec<(Arc<str>, u32)> {
+    vec![
+      (Arc::from("url_rewrite"), 1),
+      (Arc::from("rproxy"), 2),
+      (Arc::from("fauth"), 3),
+      (Arc::from("static_file_serving"), 4),
+    ]
+  }
+
+  fn names(handlers: Vec<(Arc<str>, u32)>) -> Vec<String> {
+    handlers
+      .into_iter()
+      .map(|(name, _)| name.to_string())
+      .collect()
+  }
+
+  #[test]
+  fn test_select_modules() {
+    assert_eq!(
+      names(select_modules(handlers(), &config("logFormat: combined"))),
+      vec!["url_rewrite", "rproxy", "fauth", "static_file_serving"]
+    );
+    assert_eq!(
+      names(select_modules(
+        handlers(),
+        &config("modules:\n  - url_rewrite\n  - fauth\n  - rproxy")
+      )),
+      vec!["url_rewrite", "fauth", "rproxy"]
+    );
+    assert_eq!(
+      names(select_modules(
+        handlers(),
+        &config("disabledModules:\n  - rproxy")
+      )),
+      vec!["url_rewrite", "fauth", "static_file_serving"]
+    );
+  }
+
+  #[test]
+  fn test_validate_module_lists() {
+    let loaded_module_names = names(handlers())
+      .into_iter()
+      .map(Arc::from)
+      .collect::<Vec<_>>();
+    assert!(validate_module_lists(
+      &config("modules:\n  - fauth\n  - rproxy"),
+      &loaded_module_names
+    )
+    .is_ok());
+    assert!(validate_module_lists(&config("modules: rproxy"), &loaded_module_names).is_err());
+    assert!(
+      validate_module_lists(&config("disabledModules:\n  - cgi"), &loaded_module_names).is_err()
+    );
+    assert!(validate_module_lists(
+      &config("modules:\n  - fauth\n  - fauth"),
+      &loaded_module_names
+    )
+    .is_err());
+  }
+}
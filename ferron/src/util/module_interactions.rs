@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use ferron_common::ServerConfigRoot;
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::combine_config::combine_scope_config;
+use crate::ferron_util::module_chain::select_modules;
+
+// The roles of the built-in modules in the request processing, used to find the module orders known to cause problems.
+// The module caches the responses sent by the modules after it
+const ROLE_CACHE: u8 = 1 << 0;
+// The module compresses the responses, without varying them on the "Accept-Encoding" header
+const ROLE_COMPRESSION: u8 = 1 << 1;
+// The module sends the responses itself, so the modules after it don't handle the requests
+const ROLE_CONTENT: u8 = 1 << 2;
+// The module forwards the requests to the backend servers
+const ROLE_PROXY: u8 = 1 << 3;
+// The module inspects the requests for attacks or bots
+const ROLE_INSPECTION: u8 = 1 << 4;
+// The module requires the clients to authenticate
+const ROLE_AUTHENTICATION: u8 = 1 << 5;
+
+// Get the roles of the module in the configuration. The roles of the modules not enabled in the configuration
+// (for example, the reverse proxy without the backend servers) and of the external modules aren't known.
+fn module_roles(module_name: &str, config: &ServerConfigRoot) -> u8 {
+  let is_set = |property: &str| !config.get(property).is_badvalue();
+  match module_name {
+    "cache" => ROLE_CACHE,
+    "static_file_serving" if is_set("wwwroot") => match config.get("enableCompression").as_bool() {
+      Some(false) => ROLE_CONTENT,
+      _ => ROLE_CONTENT | ROLE_COMPRESSION,
+    },
+    "rproxy" if is_set("proxyTo") || is_set("secureProxyTo") => ROLE_CONTENT | ROLE_PROXY,
+    "cgi" if is_set("wwwroot") => ROLE_CONTENT,
+    "scgi" if is_set("scgiPath") => ROLE_CONTENT,
+    "fcgi" if is_set("fcgiPath") || is_set("fcgiScriptExtensions") => ROLE_CONTENT,
+    "webdav" if is_set("webdavPaths") => ROLE_CONTENT,
+    "waf" if is_set("wafRuleSets") => ROLE_INSPECTION,
+    "bot_mitigation" if config.get("botMitigation").as_bool() == Some(true) => ROLE_INSPECTION,
+    "fauth" if is_set("authTo") => ROLE_AUTHENTICATION,
+    "non_standard_codes"
+      if config
+        .get("nonStandardCodes")
+        .as_vec()
+        .is_some_and(|non_standard_codes| {
+          non_standard_codes
+            .iter()
+            .any(|non_standard_code| non_standard_code["scode"].as_i64() == Some(401))
+        }) =>
+    {
+      ROLE_AUTHENTICATION
+    }
+    _ => 0,
+  }
+}
+
+// Find the known-bad module combinations in the module chain of the host or the location.
+// The request handlers of the modules run in the chain order, and the response handlers run in the reverse order.
+fn analyze_module_chain(module_names: &[Arc<str>], config: &ServerConfigRoot) -> Vec<String> {
+  let roles = module_names
+    .iter()
+    .map(|module_name| module_roles(module_name, config))
+    .collect::<Vec<_>>();
+  let find_before = |index: usize, role: u8| {
+    module_names[..index]
+      .iter()
+      .zip(roles.iter())
+      .find(|(_, module_roles)| *module_roles & role != 0)
+      .map(|(module_name, _)| module_name)
+  };
+
+  let mut warnings = Vec::new();
+  let cache_varies_on_encoding = config
+    .get("cacheVaryHeaders")
+    .as_vec()
+    .is_some_and(|headers| {
+      headers.iter().any(|header| {
+        header
+          .as_str()
+          .is_some_and(|header| header.eq_ignore_ascii_case("Accept-Encoding"))
+      })
+    });
+  for (index, module_name) in module_names.iter().enumerate() {
+    let module_roles = roles[index];
+    if module_roles & ROLE_COMPRESSION != 0 && !cache_varies_on_encoding {
+      if let Some(cache_module_name) = find_before(index, ROLE_CACHE) {
+        warnings.push(format!(
+          "The \"{}\" module caches the responses compressed by the \"{}\" module, but \"Accept-Encoding\" isn't listed in \"cacheVaryHeaders\", so the compressed responses may be sent to the clients not supporting the compression",
+          cache_module_name, module_name
+        ));
+      }
+    }
+    if module_roles & ROLE_INSPECTION != 0 {
+      if let Some(proxy_module_name) = find_before(index, ROLE_PROXY) {
+        warnings.push(format!(
+          "The \"{}\" module runs after the \"{}\" module, so the proxied requests aren't inspected",
+          module_name, proxy_module_name
+        ));
+      }
+    }
+    if module_roles & ROLE_AUTHENTICATION != 0 {
+      if let Some(content_module_name) = find_before(index, ROLE_CONTENT) {
+        warnings.push(format!(
+          "The \"{}\" module runs after the \"{}\" module, so the responses sent by the \"{}\" module don't require authentication",
+          module_name, content_module_name, content_module_name
+        ));
+      }
+    }
+  }
+  warnings
+}
+
+// Find the known-bad module combinations in the module chains of the global configuration, the hosts, and the locations.
+// The warnings are prefixed with the place, where the module chain is configured.
+pub fn analyze_module_interactions(
+  yaml_config: &Yaml,
+  loaded_module_names: &[Arc<str>],
+) -> Vec<String> {
+  let global_config_root = ServerConfigRoot::new(&yaml_config["global"]);
+  let module_chain = |config: &ServerConfigRoot| {
+    select_modules(
+      loaded_module_names
+        .iter()
+        .map(|module_name| (module_name.clone(), ()))
+        .collect(),
+      config,
+    )
+    .into_iter()
+    .map(|(module_name, _)| module_name)
+    .collect::<Vec<_>>()
+  };
+
+  // The warnings already reported for the global configuration aren't repeated for the hosts,
+  // and the warnings already reported for a host aren't repeated for its locations
+  let mut warnings = Vec::new();
+  let mut report = |scope_name: &str, config: &ServerConfigRoot, parent_warnings: &[String]| {
+    let scope_warnings = analyze_module_chain(&module_chain(config), config);
+    for warning in scope_warnings.iter() {
+      if !parent_warnings.contains(warning) {
+        warnings.push(format!("{}: {}", scope_name, warning));
+      }
+    }
+    scope_warnings
+  };
+
+  let global_warnings = report("global configuration", &global_config_root, &[]);
+  if let Some(hosts) = yaml_config["hosts"].as_vec() {
+    for host in hosts.iter() {
+      let host_hash = match host.as_hash() {
+        Some(host_hash) => host_hash,
+        None => continue,
+      };
+      let host_name = match (host["domain"].as_str(), host["ip"].as_str()) {
+        (Some(domain), Some(ip)) => format!("host \"{}\" ({})", domain, ip),
+        (Some(domain), None) => format!("host \"{}\"", domain),
+        (None, Some(ip)) => format!("host with the IP address {}", ip),
+        (None, None) => String::from("host for all the domains"),
+      };
+      let host_warnings = report(
+        &host_name,
+        &combine_scope_config(&global_config_root, host_hash, None),
+        &global_warnings,
+      );
+      if let Some(locations) = host["locations"].as_vec() {
+        for location in locations.iter() {
+          if let Some(location_hash) = location.as_hash() {
+            let location_path = location["path"]
+              .as_str()
+              .or(location["pathRegex"].as_str())
+              .unwrap_or_default();
+            report(
+              &format!("{}, location \"{}\"", host_name, location_path),
+              &combine_scope_config(&global_config_root, host_hash, Some(location_hash)),
+              &host_warnings,
+            );
+          }
+        }
+      }
+    }
+  }
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn module_names(names: &[&str]) -> Vec<Arc<str>> {
+    names.iter().map(|name| Arc::from(*name)).collect()
+  }
+
+  fn analyze(yaml: &str, names: &[&str]) -> Vec<String> {
+    let yaml_config = yaml_rust2::YamlLoader::load_from_str(yaml)
+      .unwrap()
+      .remove(0);
+    analyze_module_interactions(&yaml_config, &module_names(names))
+  }
+
+  #[test]
+  fn test_compression_after_cache() {
+    let names = ["cache", "waf", "static_file_serving"];
+    let warnings = analyze(
+      "global:\n  wwwroot: /var/www\nhosts:\n  - domain: example.com\n",
+      &names,
+    );
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("global configuration: The \"cache\" module caches"));
+
+    assert!(analyze(
+      "global:\n  wwwroot: /var/www\n  cacheVaryHeaders:\n    - Accept-Encoding\n",
+      &names
+    )
+    .is_empty());
+    assert!(analyze(
+      "global:\n  wwwroot: /var/www\n  enableCompression: false\n",
+      &names
+    )
+    .is_empty());
+  }
+
+  #[test]
+  fn test_inspection_after_proxy() {
+    let names = ["rproxy", "waf", "static_file_serving"];
+    let warnings = analyze(
+      "global:\n  wafRuleSets:\n    - sqli\nhosts:\n  - domain: example.com\n    proxyTo: http://localhost:3000\n",
+      &names,
+    );
+    assert_eq!(
+      warnings,
+      vec!["host \"example.com\": The \"waf\" module runs after the \"rproxy\" module, so the proxied requests aren't inspected"]
+    );
+
+    // The module order can be fixed for the host
+    assert!(analyze(
+      "global:\n  wafRuleSets:\n    - sqli\nhosts:\n  - domain: example.com\n    proxyTo: http://localhost:3000\n    modules:\n      - waf\n      - rproxy\n",
+      &names,
+    )
+    .is_empty());
+  }
+
+  #[test]
+  fn test_authentication_after_content() {
+    let names = ["fauth", "static_file_serving"];
+    let yaml = "global:\n  wwwroot: /var/www\nhosts:\n  - domain: example.com\n    locations:\n      - path: /admin\n        authTo: http://localhost:9000/auth\n        modules:\n          - static_file_serving\n          - fauth\n";
+    let warnings = analyze(yaml, &names);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with(
+      "host \"example.com\", location \"/admin\": The \"fauth\" module runs after the \"static_file_serving\" module"
+    ));
+  }
+}
@@ -0,0 +1,243 @@
+use async_channel::Sender;
+use ferron_common::{ErrorLogger, LogMessage, ServerConfigRoot, ServerModule, TaskScheduler};
+use tokio::time;
+
+use crate::ferron_util::monitored_module::MonitoredModule;
+
+// The maximum time a lifecycle hook of a module can take
+const MODULE_HOOK_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+// The lifecycle hooks of the modules
+#[derive(Clone, Copy)]
+pub enum ModuleLifecycleHook {
+  Startup,
+  ConfigReload,
+  Shutdown,
+}
+
+impl ModuleLifecycleHook {
+  fn name(&self) -> &'static str {
+    match self {
+      ModuleLifecycleHook::Startup => "startup",
+      ModuleLifecycleHook::ConfigReload => "configuration reload",
+      ModuleLifecycleHook::Shutdown => "shutdown",
+    }
+  }
+}
+
+// Run the lifecycle hook of the modules, in the order the modules are loaded. The startup and reload hooks stop
+// at the first failure, which is returned. The shutdown hooks of all the modules are run, and their failures are logged.
+// The modules' background jobs are cancelled before the shutdown hooks run.
+pub async fn run_module_hook(
+  modules: &[MonitoredModule],
+  hook: ModuleLifecycleHook,
+  global_config_root: &ServerConfigRoot,
+  task_scheduler: &TaskScheduler,
+  logger: &Sender<LogMessage>,
+) -> Result<(), anyhow::Error> {
+  let error_logger = ErrorLogger::new(logger.clone());
+  if let ModuleLifecycleHook::Shutdown = hook {
+    task_scheduler.shutdown();
+  }
+  for module in modules.iter() {
+    let hook_future = match hook {
+      ModuleLifecycleHook::Startup => {
+        module.on_startup(global_config_root, &error_logger, task_scheduler)
+      }
+      ModuleLifecycleHook::ConfigReload => {
+        module.on_config_reload(global_config_root, &error_logger, task_scheduler)
+      }
+      ModuleLifecycleHook::Shutdown => module.on_shutdown(&error_logger),
+    };
+    let err = match time::timeout(MODULE_HOOK_TIMEOUT, hook_future).await {
+      Ok(Ok(())) => continue,
+      Ok(Err(err)) => anyhow::anyhow!(
+        "The {} hook of the \"{}\" module failed: {}",
+        hook.name(),
+        module.get_name(),
+        err
+      ),
+      Err(_) => anyhow::anyhow!(
+        "The {} hook of the \"{}\" module timed out",
+        hook.name(),
+        module.get_name()
+      ),
+    };
+    match hook {
+      ModuleLifecycleHook::Shutdown => logger
+        .send(LogMessage::new(err.to_string(), true))
+        .await
+        .unwrap_or_default(),
+      _ => Err(err)?,
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use async_trait::async_trait;
+  use ferron_common::ServerModuleHandlers;
+  use std::error::Error;
+  use std::sync::{Arc, Mutex};
+  use tokio::runtime::Handle;
+
+  // A module recording the hooks it was called with
+  struct RecordingModule {
+    name: &'static str,
+    failing: bool,
+    calls: Arc<Mutex<Vec<String>>>,
+  }
+
+  impl RecordingModule {
+    fn record(&self, hook: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+      self
+        .calls
+        .lock()
+        .unwrap()
+        .push(format!("{} {}", self.name, hook));
+      match self.failing {
+        true => Err(anyhow::anyhow!("{} failed", hook))?,
+        false => Ok(()),
+      }
+    }
+  }
+
+  #[async_trait]
+  impl ServerModule for RecordingModule {
+    fn get_handlers(&self, _handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+      unreachable!("the handlers aren't used by the lifecycle hooks")
+    }
+
+    async fn on_startup(
+      &self,
+      _config: &ServerConfigRoot,
+      _error_logger: &ErrorLogger,
+      _task_scheduler: &TaskScheduler,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+      self.record("startup")
+    }
+
+    async fn on_config_reload(
+      &self,
+      _config: &ServerConfigRoot,
+      _error_logger: &ErrorLogger,
+      _task_scheduler: &TaskScheduler,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+      self.record("reload")
+    }
+
+    async fn on_shutdown(
+      &self,
+      _error_logger: &ErrorLogger,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+      self.record("shutdown")
+    }
+  }
+
+  fn recording_modules(failing_first: bool) -> (Vec<MonitoredModule>, Arc<Mutex<Vec<String>>>) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let modules = [("first", failing_first), ("second", false)]
+      .into_iter()
+      .map(|(name, failing)| {
+        MonitoredModule::wrap(
+          name,
+          Box::new(RecordingModule {
+            name,
+            failing,
+            calls: calls.clone(),
+          }),
+        )
+      })
+      .collect();
+    (modules, calls)
+  }
+
+  fn task_scheduler() -> TaskScheduler {
+    TaskScheduler::new(Handle::current(), ErrorLogger::without_logger())
+  }
+
+  #[tokio::test]
+  async fn test_startup_and_reload_hooks() {
+    let (modules, calls) = recording_modules(false);
+    let (logger, _receive_log) = async_channel::unbounded();
+    let config = ServerConfigRoot::new(&yaml_rust2::Yaml::BadValue);
+    let task_scheduler = task_scheduler();
+    run_module_hook(
+      &modules,
+      ModuleLifecycleHook::Startup,
+      &config,
+      &task_scheduler,
+      &logger,
+    )
+    .await
+    .unwrap();
+    run_module_hook(
+      &modules,
+      ModuleLifecycleHook::ConfigReload,
+      &config,
+      &task_scheduler,
+      &logger,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      *calls.lock().unwrap(),
+      vec![
+        "first startup",
+        "second startup",
+        "first reload",
+        "second reload"
+      ]
+    );
+    assert!(!task_scheduler.is_shut_down());
+  }
+
+  #[tokio::test]
+  async fn test_startup_hook_failure_stops_startup() {
+    let (modules, calls) = recording_modules(true);
+    let (logger, _receive_log) = async_channel::unbounded();
+    let err = run_module_hook(
+      &modules,
+      ModuleLifecycleHook::Startup,
+      &ServerConfigRoot::new(&yaml_rust2::Yaml::BadValue),
+      &task_scheduler(),
+      &logger,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "The startup hook of the \"first\" module failed: startup failed"
+    );
+    assert_eq!(*calls.lock().unwrap(), vec!["first startup"]);
+  }
+
+  #[tokio::test]
+  async fn test_shutdown_hooks_run_for_all_modules() {
+    let (modules, calls) = recording_modules(true);
+    let (logger, receive_log) = async_channel::unbounded();
+    let task_scheduler = task_scheduler();
+    run_module_hook(
+      &modules,
+      ModuleLifecycleHook::Shutdown,
+      &ServerConfigRoot::new(&yaml_rust2::Yaml::BadValue),
+      &task_scheduler,
+      &logger,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      *calls.lock().unwrap(),
+      vec!["first shutdown", "second shutdown"]
+    );
+    assert!(task_scheduler.is_shut_down());
+    let (message, is_error) = receive_log.recv().await.unwrap().get_message();
+    assert!(is_error);
+    assert_eq!(
+      message,
+      "The shutdown hook of the \"first\" module failed: shutdown failed"
+    );
+  }
+}
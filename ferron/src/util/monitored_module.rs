@@ -0,0 +1,359 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ferron_common::{
+  ErrorLogger, HyperResponse, HyperUpgraded, RequestData, ResponseData, ServerConfigRoot,
+  ServerModule, ServerModuleHandlers, SocketData, TaskScheduler,
+};
+use hyper_tungstenite::HyperWebsocket;
+use tokio::runtime::Handle;
+
+use crate::ferron_util::metrics::METRICS;
+
+// A server module wrapper, which names the module and counts the errors returned by the module's handlers,
+// so that a misbehaving module can be identified from the metrics
+pub struct MonitoredModule {
+  name: Arc<str>,
+  inner: Box<dyn ServerModule + Send + Sync>,
+}
+
+impl MonitoredModule {
+  pub fn wrap(name: &str, inner: Box<dyn ServerModule + Send + Sync>) -> Self {
+    Self {
+      name: Arc::from(name),
+      inner,
+    }
+  }
+
+  pub fn get_name(&self) -> Arc<str> {
+    self.name.clone()
+  }
+}
+
+#[async_trait]
+impl ServerModule for MonitoredModule {
+  fn get_handlers(&self, handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+    Box::new(MonitoredModuleHandlers {
+      name: self.name.clone(),
+      inner: self.inner.get_handlers(handle),
+      host: None,
+    })
+  }
+
+  async fn on_startup(
+    &self,
+    config: &ServerConfigRoot,
+    error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self
+      .inner
+      .on_startup(
+        config,
+        &error_logger.with_source(self.name.clone()),
+        &task_scheduler.with_source(self.name.clone()),
+      )
+      .await
+  }
+
+  async fn on_config_reload(
+    &self,
+    config: &ServerConfigRoot,
+    error_logger: &ErrorLogger,
+    task_scheduler: &TaskScheduler,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self
+      .inner
+      .on_config_reload(
+        config,
+        &error_logger.with_source(self.name.clone()),
+        &task_scheduler.with_source(self.name.clone()),
+      )
+      .await
+  }
+
+  async fn on_shutdown(
+    &self,
+    error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self
+      .inner
+      .on_shutdown(&error_logger.with_source(self.name.clone()))
+      .await
+  }
+}
+
+struct MonitoredModuleHandlers {
+  name: Arc<str>,
+  inner: Box<dyn ServerModuleHandlers + Send>,
+  host: Option<String>,
+}
+
+impl MonitoredModuleHandlers {
+  // Remember the host the request is served for, since the response modifying handlers don't receive the configuration
+  fn set_host(&mut self, config: &ServerConfigRoot) {
+    self.host = config.get("domain").as_str().map(String::from);
+  }
+
+  // Attribute the messages logged by the module to the module, so that they are deduplicated and rate-limited separately
+  fn error_logger(&self, error_logger: &ErrorLogger) -> ErrorLogger {
+    error_logger.with_source(self.name.clone())
+  }
+
+  fn count_error<T>(
+    &self,
+    result: Result<T, Box<dyn Error + Send + Sync>>,
+  ) -> Result<T, Box<dyn Error + Send + Sync>> {
+    if result.is_err() {
+      METRICS.increment_counter(
+        "ferron_module_errors_total",
+        &[
+          ("module", &self.name),
+          ("host", self.host.as_deref().unwrap_or("*")),
+        ],
+      );
+    }
+    result
+  }
+}
+
+#[async_trait]
+impl ServerModuleHandlers for MonitoredModuleHandlers {
+  async fn request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    self.set_host(config);
+    let result = self
+      .inner
+      .request_handler(
+        request,
+        config,
+        socket_data,
+        &self.error_logger(error_logger),
+      )
+      .await;
+    self.count_error(result)
+  }
+
+  async fn proxy_request_handler(
+    &mut self,
+    request: RequestData,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+    self.set_host(config);
+    let result = self
+      .inner
+      .proxy_request_handler(
+        request,
+        config,
+        socket_data,
+        &self.error_logger(error_logger),
+      )
+      .await;
+    self.count_error(result)
+  }
+
+  async fn response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    let result = self.inner.response_modifying_handler(response).await;
+    self.count_error(result)
+  }
+
+  async fn proxy_response_modifying_handler(
+    &mut self,
+    response: HyperResponse,
+  ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+    let result = self.inner.proxy_response_modifying_handler(response).await;
+    self.count_error(result)
+  }
+
+  async fn connect_proxy_request_handler(
+    &mut self,
+    upgraded_request: HyperUpgraded,
+    connect_address: &str,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.set_host(config);
+    let result = self
+      .inner
+      .connect_proxy_request_handler(
+        upgraded_request,
+        connect_address,
+        config,
+        socket_data,
+        &self.error_logger(error_logger),
+      )
+      .await;
+    self.count_error(result)
+  }
+
+  fn does_connect_proxy_requests(&mut self) -> bool {
+    self.inner.does_connect_proxy_requests()
+  }
+
+  async fn websocket_request_handler(
+    &mut self,
+    websocket: HyperWebsocket,
+    uri: &hyper::Uri,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+    error_logger: &ErrorLogger,
+  ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    self.set_host(config);
+    let result = self
+      .inner
+      .websocket_request_handler(
+        websocket,
+        uri,
+        config,
+        socket_data,
+        &self.error_logger(error_logger),
+      )
+      .await;
+    self.count_error(result)
+  }
+
+  fn does_websocket_requests(
+    &mut self,
+    config: &ServerConfigRoot,
+    socket_data: &SocketData,
+  ) -> bool {
+    self.inner.does_websocket_requests(config, socket_data)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, Empty};
+  use hyper::Request;
+
+  struct FailingModule;
+
+  impl ServerModule for FailingModule {
+    fn get_handlers(&self, _handle: Handle) -> Box<dyn ServerModuleHandlers + Send> {
+      Box::new(FailingModuleHandlers)
+    }
+  }
+
+  struct FailingModuleHandlers;
+
+  #[async_trait]
+  impl ServerModuleHandlers for FailingModuleHandlers {
+    async fn request_handler(
+      &mut self,
+      _request: RequestData,
+      _config: &ServerConfigRoot,
+      _socket_data: &SocketData,
+      _error_logger: &ErrorLogger,
+    ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+      Err(anyhow::anyhow!("Module failure"))?
+    }
+
+    async fn proxy_request_handler(
+      &mut self,
+      request: RequestData,
+      _config: &ServerConfigRoot,
+      _socket_data: &SocketData,
+      _error_logger: &ErrorLogger,
+    ) -> Result<ResponseData, Box<dyn Error + Send + Sync>> {
+      Ok(ResponseData::builder(request).build())
+    }
+
+    async fn response_modifying_handler(
+      &mut self,
+      _response: HyperResponse,
+    ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+      Err(anyhow::anyhow!("Module failure"))?
+    }
+
+    async fn proxy_response_modifying_handler(
+      &mut self,
+      response: HyperResponse,
+    ) -> Result<HyperResponse, Box<dyn Error + Send + Sync>> {
+      Ok(response)
+    }
+
+    async fn connect_proxy_request_handler(
+      &mut self,
+      _upgraded_request: HyperUpgraded,
+      _connect_address: &str,
+      _config: &ServerConfigRoot,
+      _socket_data: &SocketData,
+      _error_logger: &ErrorLogger,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+      Ok(())
+    }
+
+    fn does_connect_proxy_requests(&mut self) -> bool {
+      false
+    }
+
+    async fn websocket_request_handler(
+      &mut self,
+      _websocket: HyperWebsocket,
+      _uri: &hyper::Uri,
+      _config: &ServerConfigRoot,
+      _socket_data: &SocketData,
+      _error_logger: &ErrorLogger,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+      Ok(())
+    }
+
+    fn does_websocket_requests(
+      &mut self,
+      _config: &ServerConfigRoot,
+      _socket_data: &SocketData,
+    ) -> bool {
+      false
+    }
+  }
+
+  #[tokio::test]
+  async fn test_module_errors_are_counted() {
+    let module = MonitoredModule::wrap("failing_test_module", Box::new(FailingModule));
+    let mut handlers = module.get_handlers(Handle::current());
+    let config = ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str("domain: example.com")
+        .unwrap()
+        .remove(0),
+    );
+    let socket_data = SocketData::new(
+      "127.0.0.1:12345".parse().unwrap(),
+      "127.0.0.1:80".parse().unwrap(),
+      false,
+    );
+    let request = Request::new(Empty::new().map_err(|e| match e {}).boxed());
+
+    assert!(handlers
+      .request_handler(
+        RequestData::new(request, None),
+        &config,
+        &socket_data,
+        &ErrorLogger::without_logger(),
+      )
+      .await
+      .is_err());
+    assert!(handlers
+      .response_modifying_handler(hyper::Response::new(
+        Empty::new().map_err(|e| match e {}).boxed()
+      ))
+      .await
+      .is_err());
+
+    assert!(METRICS.render().contains(
+      "ferron_module_errors_total{module=\"failing_test_module\",host=\"example.com\"} 2"
+    ));
+  }
+}
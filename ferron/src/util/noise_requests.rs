@@ -0,0 +1,116 @@
+use ferron_common::ServerConfigRoot;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::Bytes;
+use hyper::{header, Method, Response, StatusCode};
+use yaml_rust2::Yaml;
+
+use crate::ferron_res::builtin_icon::BUILTIN_ICON;
+
+// The "Cache-Control" header value for the noise request responses, so that the browsers don't ask for them again soon
+const NOISE_RESPONSE_CACHE_CONTROL: &str = "public, max-age=604800";
+
+// The kind of a common noise request, sent by the browsers and the crawlers regardless of the website's content
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NoiseRequestKind {
+  Icon,
+  Other,
+}
+
+impl NoiseRequestKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      NoiseRequestKind::Icon => "icon",
+      NoiseRequestKind::Other => "other",
+    }
+  }
+}
+
+// Check if the request path is a common noise request path, or one of the additional configured paths
+pub fn classify_noise_request(path: &str, additional_paths: &Yaml) -> Option<NoiseRequestKind> {
+  if path == "/favicon.ico"
+    || path
+      .strip_prefix("/apple-touch-icon")
+      .and_then(|rest| rest.strip_suffix(".png"))
+      .is_some_and(|rest| !rest.contains('/'))
+  {
+    Some(NoiseRequestKind::Icon)
+  } else if path == "/browserconfig.xml"
+    || additional_paths
+      .as_vec()
+      .is_some_and(|paths| paths.iter().any(|p| p.as_str() == Some(path)))
+  {
+    Some(NoiseRequestKind::Other)
+  } else {
+    None
+  }
+}
+
+// Answer the noise request, if the noise request handling ("noiseRequests") is enabled. The icon requests get the built-in icon
+// in the "builtin" mode, and the other noise requests (and all of them in the "empty" mode) get the "204 No Content" response.
+pub fn noise_request_response(
+  method: &Method,
+  path: &str,
+  config: &ServerConfigRoot,
+) -> Option<(NoiseRequestKind, Response<BoxBody<Bytes, std::io::Error>>)> {
+  let mode = config.get("noiseRequests");
+  let mode = mode.as_str()?;
+  if method != Method::GET && method != Method::HEAD {
+    return None;
+  }
+  let kind = classify_noise_request(path, &config.get("noiseRequestPaths"))?;
+  let response = match (mode, kind) {
+    ("builtin", NoiseRequestKind::Icon) => Response::builder()
+      .header(header::CONTENT_TYPE, "image/png")
+      .header(header::CACHE_CONTROL, NOISE_RESPONSE_CACHE_CONTROL)
+      .body(
+        Full::new(Bytes::from_static(&BUILTIN_ICON))
+          .map_err(|e| match e {})
+          .boxed(),
+      ),
+    ("builtin", NoiseRequestKind::Other) | ("empty", _) => Response::builder()
+      .status(StatusCode::NO_CONTENT)
+      .header(header::CACHE_CONTROL, NOISE_RESPONSE_CACHE_CONTROL)
+      .body(Empty::new().map_err(|e| match e {}).boxed()),
+    _ => return None,
+  };
+  response.ok().map(|response| (kind, response))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_classify_noise_request() {
+    let additional_paths = Yaml::Array(vec![Yaml::String("/ads.txt".to_string())]);
+    for path in [
+      "/favicon.ico",
+      "/apple-touch-icon.png",
+      "/apple-touch-icon-precomposed.png",
+      "/apple-touch-icon-180x180.png",
+    ] {
+      assert_eq!(
+        classify_noise_request(path, &Yaml::BadValue),
+        Some(NoiseRequestKind::Icon)
+      );
+    }
+    assert_eq!(
+      classify_noise_request("/browserconfig.xml", &Yaml::BadValue),
+      Some(NoiseRequestKind::Other)
+    );
+    assert_eq!(
+      classify_noise_request("/ads.txt", &additional_paths),
+      Some(NoiseRequestKind::Other)
+    );
+    for path in [
+      "/",
+      "/ads.txt",
+      "/favicon.ico/x",
+      "/images/favicon.ico",
+      "/apple-touch-icon/x.png",
+    ] {
+      assert_eq!(classify_noise_request(path, &Yaml::BadValue), None);
+    }
+  }
+}
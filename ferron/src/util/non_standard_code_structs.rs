@@ -1,4 +1,5 @@
 use crate::ferron_util::ip_blocklist::IpBlockList;
+use crate::ferron_util::match_location::LocationMatcher;
 use fancy_regex::Regex;
 
 #[allow(dead_code)]
@@ -62,14 +63,14 @@ impl NonStandardCodesWrap {
 }
 
 pub struct NonStandardCodesLocationWrap {
-  pub path: String,
+  pub matcher: LocationMatcher,
   pub non_standard_codes: Vec<NonStandardCode>,
 }
 
 impl NonStandardCodesLocationWrap {
-  pub fn new(path: String, non_standard_codes: Vec<NonStandardCode>) -> Self {
+  pub fn new(matcher: LocationMatcher, non_standard_codes: Vec<NonStandardCode>) -> Self {
     NonStandardCodesLocationWrap {
-      path,
+      matcher,
       non_standard_codes,
     }
   }
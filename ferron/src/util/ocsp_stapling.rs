@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_channel::Sender;
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time::Instant;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+use crate::ferron_util::metrics::METRICS;
+use ferron_common::LogMessage;
+
+// The default interval, at which the stapled OCSP responses are checked for the refresh
+pub const DEFAULT_OCSP_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+// The maximum interval between the retries of the failed OCSP requests
+const OCSP_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// The OID of the TLS feature extension, and the "status_request" feature (the "OCSP Must-Staple" flag)
+const TLS_FEATURE_OID: &str = "1.3.6.1.5.5.7.1.24";
+const STATUS_REQUEST_FEATURE: u8 = 5;
+
+// Check if the DER-encoded certificate requires the OCSP response to be stapled ("OCSP Must-Staple")
+pub fn is_must_staple(certificate_der: &[u8]) -> bool {
+  let certificate = match X509Certificate::from_der(certificate_der) {
+    Ok((_, certificate)) => certificate,
+    Err(_) => return false,
+  };
+  certificate
+    .extensions()
+    .iter()
+    .filter(|extension| extension.oid.to_id_string() == TLS_FEATURE_OID)
+    .any(|extension| {
+      // The extension value is a DER-encoded sequence of integers
+      let value = extension.value;
+      if value.len() < 2 || value[0] != 0x30 || value[1] as usize != value.len() - 2 {
+        return false;
+      }
+      let mut features = &value[2..];
+      while features.len() >= 3 && features[0] == 0x02 {
+        let length = features[1] as usize;
+        if length == 0 || features.len() < 2 + length {
+          return false;
+        }
+        if length == 1 && features[2] == STATUS_REQUEST_FEATURE {
+          return true;
+        }
+        features = &features[(2 + length)..];
+      }
+      false
+    })
+}
+
+// The SHA-256 fingerprint of the end-entity certificate, identifying the certificate in the stapler
+fn fingerprint(certified_key: &CertifiedKey) -> Option<String> {
+  let certificate = certified_key.cert.first()?;
+  Some(
+    Sha256::digest(certificate)
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect(),
+  )
+}
+
+// An OCSP response obtained for a certificate
+#[derive(Clone, Debug, PartialEq)]
+pub struct OcspStaple {
+  pub response: Vec<u8>,
+  pub this_update: DateTime<Utc>,
+  pub next_update: DateTime<Utc>,
+}
+
+impl OcspStaple {
+  // Check if the response can be stapled at the specified time
+  pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+    now >= self.this_update && now < self.next_update
+  }
+
+  // Check if the response has to be refreshed. The responses are refreshed past the half of their validity,
+  // so that there is plenty of time to retry when the OCSP responder is unavailable.
+  pub fn needs_refresh(&self, now: DateTime<Utc>) -> bool {
+    now >= self.this_update + (self.next_update - self.this_update) / 2
+  }
+
+  // Serialize the response into the format of the on-disk cache
+  pub fn to_cache(&self) -> String {
+    json!({
+      "thisUpdate": self.this_update.to_rfc3339(),
+      "nextUpdate": self.next_update.to_rfc3339(),
+      "response": BASE64_STANDARD.encode(&self.response),
+    })
+    .to_string()
+  }
+
+  // Parse the response from the on-disk cache
+  pub fn from_cache(cache: &str) -> Option<Self> {
+    let cache: serde_json::Value = serde_json::from_str(cache).ok()?;
+    let parse_date = |key: &str| {
+      DateTime::parse_from_rfc3339(cache[key].as_str()?)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+    };
+    Some(Self {
+      response: BASE64_STANDARD.decode(cache["response"].as_str()?).ok()?,
+      this_update: parse_date("thisUpdate")?,
+      next_update: parse_date("nextUpdate")?,
+    })
+  }
+}
+
+// A certificate tracked by the stapler
+struct StapledCertificate {
+  certified_key: Arc<CertifiedKey>,
+  stapled_key: Option<(Arc<CertifiedKey>, OcspStaple)>,
+  must_staple: bool,
+}
+
+// The OCSP stapling certificate resolver. The OCSP responses are obtained in the background,
+// persisted in the cache directory (so that the restarts don't cause unstapled handshakes),
+// and attached to the certificates resolved by the wrapped resolver. The certificates with
+// the "OCSP Must-Staple" flag aren't served without a valid OCSP response.
+pub struct OcspStapler {
+  inner: Arc<dyn ResolvesServerCert>,
+  certificates: Arc<RwLock<HashMap<String, StapledCertificate>>>,
+  sender: mpsc::Sender<Arc<CertifiedKey>>,
+}
+
+impl OcspStapler {
+  // Create a new OCSP stapler and spawn its background task
+  pub fn new(
+    inner: Arc<dyn ResolvesServerCert>,
+    client: ocsp_stapler::Client,
+    cache_directory: Option<PathBuf>,
+    refresh_interval: Duration,
+    logger: Sender<LogMessage>,
+  ) -> Self {
+    let (sender, receiver) = mpsc::channel(1024);
+    let certificates = Arc::new(RwLock::new(HashMap::new()));
+    tokio::spawn(run_ocsp_refresher(
+      OcspRefresher {
+        client,
+        certificates: certificates.clone(),
+        cache_directory,
+        refresh_interval,
+        logger,
+        next_checks: HashMap::new(),
+      },
+      receiver,
+    ));
+    Self {
+      inner,
+      certificates,
+      sender,
+    }
+  }
+
+  // Load the certificate into the stapler before the first TLS handshake, so that it's stapled already
+  pub fn preload(&self, certified_key: Arc<CertifiedKey>) {
+    self.sender.try_send(certified_key).unwrap_or_default();
+  }
+
+  // Attach the OCSP response to the certificate. Returns None if the certificate can't be served.
+  fn staple(&self, certified_key: Arc<CertifiedKey>) -> Option<Arc<CertifiedKey>> {
+    // The certificates without the issuer certificate in the chain can't be checked with OCSP
+    if certified_key.cert.len() < 2 {
+      return Some(certified_key);
+    }
+    let fingerprint = fingerprint(&certified_key)?;
+    let must_staple = match self.certificates.read() {
+      Ok(certificates) => match certificates.get(&fingerprint) {
+        Some(certificate) => {
+          if let Some((stapled_key, staple)) = &certificate.stapled_key {
+            if staple.is_valid(Utc::now()) {
+              METRICS.increment_counter("ferron_ocsp_resolves_total", &[("stapled", "yes")]);
+              return Some(stapled_key.clone());
+            }
+          }
+          Some(certificate.must_staple)
+        }
+        None => None,
+      },
+      Err(_) => return Some(certified_key),
+    };
+    let must_staple = match must_staple {
+      Some(must_staple) => must_staple,
+      None => {
+        // The certificate isn't tracked yet (for example, it has just been obtained with ACME)
+        let must_staple = is_must_staple(&certified_key.cert[0]);
+        self
+          .sender
+          .try_send(certified_key.clone())
+          .unwrap_or_default();
+        must_staple
+      }
+    };
+
+    if must_staple {
+      // The clients are required to reject the "OCSP Must-Staple" certificates without the stapled OCSP response
+      METRICS.increment_counter("ferron_ocsp_must_staple_refusals_total", &[]);
+      None
+    } else {
+      METRICS.increment_counter("ferron_ocsp_resolves_total", &[("stapled", "no")]);
+      Some(certified_key)
+    }
+  }
+}
+
+impl fmt::Debug for OcspStapler {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "OcspStapler")
+  }
+}
+
+impl ResolvesServerCert for OcspStapler {
+  fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+    let certified_key = self.inner.resolve(client_hello)?;
+    self.staple(certified_key)
+  }
+}
+
+// The background task of the OCSP stapler, which obtains and refreshes the OCSP responses
+struct OcspRefresher {
+  client: ocsp_stapler::Client,
+  certificates: Arc<RwLock<HashMap<String, StapledCertificate>>>,
+  cache_directory: Option<PathBuf>,
+  refresh_interval: Duration,
+  logger: Sender<LogMessage>,
+  next_checks: HashMap<String, Instant>,
+}
+
+impl OcspRefresher {
+  async fn log(&self, message: String) {
+    self
+      .logger
+      .send(LogMessage::new(message, true))
+      .await
+      .unwrap_or_default();
+  }
+
+  // Get the path of the cached OCSP response for the certificate
+  fn cache_path(&self, fingerprint: &str) -> Option<PathBuf> {
+    self
+      .cache_directory
+      .as_ref()
+      .map(|cache_directory| cache_directory.join(format!("{}.json", fingerprint)))
+  }
+
+  // Set the OCSP response of the tracked certificate
+  fn set_staple(&self, fingerprint: &str, staple: Option<OcspStaple>) {
+    if let Ok(mut certificates) = self.certificates.write() {
+      if let Some(certificate) = certificates.get_mut(fingerprint) {
+        certificate.stapled_key = staple.map(|staple| {
+          let mut stapled_key = certificate.certified_key.as_ref().clone();
+          stapled_key.ocsp = Some(staple.response.clone());
+          (Arc::new(stapled_key), staple)
+        });
+      }
+    }
+  }
+
+  // Start tracking the certificate, using the cached OCSP response if it's still valid
+  async fn add_certificate(&mut self, certified_key: Arc<CertifiedKey>) {
+    let fingerprint = match fingerprint(&certified_key) {
+      Some(fingerprint) if certified_key.cert.len() >= 2 => fingerprint,
+      _ => return,
+    };
+    if self.next_checks.contains_key(&fingerprint) {
+      return;
+    }
+    let must_staple = is_must_staple(&certified_key.cert[0]);
+    if let Ok(mut certificates) = self.certificates.write() {
+      certificates.insert(
+        fingerprint.clone(),
+        StapledCertificate {
+          certified_key,
+          stapled_key: None,
+          must_staple,
+        },
+      );
+    }
+
+    if let Some(cache_path) = self.cache_path(&fingerprint) {
+      if let Ok(cache) = tokio::fs::read_to_string(&cache_path).await {
+        match OcspStaple::from_cache(&cache) {
+          Some(staple) if staple.is_valid(Utc::now()) => {
+            self.set_staple(&fingerprint, Some(staple))
+          }
+          _ => {
+            // The cached OCSP response is corrupted or has expired
+            tokio::fs::remove_file(&cache_path)
+              .await
+              .unwrap_or_default();
+          }
+        }
+      }
+    }
+
+    self.next_checks.insert(fingerprint.clone(), Instant::now());
+    self.refresh_certificate(&fingerprint).await;
+  }
+
+  // Obtain a new OCSP response for the certificate if it's needed
+  async fn refresh_certificate(&mut self, fingerprint: &str) {
+    let now = Utc::now();
+    let (certified_key, staple, must_staple) = match self.certificates.read() {
+      Ok(certificates) => match certificates.get(fingerprint) {
+        Some(certificate) => (
+          certificate.certified_key.clone(),
+          certificate
+            .stapled_key
+            .as_ref()
+            .map(|(_, staple)| staple.clone()),
+          certificate.must_staple,
+        ),
+        None => return,
+      },
+      Err(_) => return,
+    };
+
+    if staple
+      .as_ref()
+      .is_some_and(|staple| !staple.needs_refresh(now))
+    {
+      self.next_checks.insert(
+        fingerprint.to_string(),
+        Instant::now() + self.refresh_interval,
+      );
+      return;
+    }
+
+    match self
+      .client
+      .query(&certified_key.cert[0], &certified_key.cert[1])
+      .await
+    {
+      Ok(response) => {
+        METRICS.increment_counter("ferron_ocsp_requests_total", &[("status", "ok")]);
+        let staple = OcspStaple {
+          response: response.raw,
+          this_update: response.ocsp_validity.not_before.with_timezone(&Utc),
+          next_update: response.ocsp_validity.not_after.with_timezone(&Utc),
+        };
+        if let Some(cache_path) = self.cache_path(fingerprint) {
+          // The cached response is replaced atomically, so that a crash doesn't leave a partially written file
+          let temporary_path = cache_path.with_extension("json.tmp");
+          let write_result = match tokio::fs::write(&temporary_path, staple.to_cache()).await {
+            Ok(_) => tokio::fs::rename(&temporary_path, &cache_path).await,
+            Err(err) => Err(err),
+          };
+          if let Err(err) = write_result {
+            self
+              .log(format!("Cannot cache the OCSP response: {}", err))
+              .await;
+          }
+        }
+        self.set_staple(fingerprint, Some(staple));
+        self.next_checks.insert(
+          fingerprint.to_string(),
+          Instant::now() + self.refresh_interval,
+        );
+      }
+      Err(err) => {
+        METRICS.increment_counter("ferron_ocsp_requests_total", &[("status", "error")]);
+        let has_valid_staple = staple.as_ref().is_some_and(|staple| staple.is_valid(now));
+        if !has_valid_staple {
+          self.set_staple(fingerprint, None);
+        }
+        self
+          .log(format!(
+            "Cannot obtain the OCSP response for the TLS certificate{}: {:#}",
+            if must_staple && !has_valid_staple {
+              " (the certificate requires OCSP stapling, so it won't be served until the OCSP response is obtained)"
+            } else {
+              ""
+            },
+            err
+          ))
+          .await;
+        self.next_checks.insert(
+          fingerprint.to_string(),
+          Instant::now() + self.refresh_interval.min(OCSP_RETRY_INTERVAL),
+        );
+      }
+    }
+  }
+
+  // Refresh the certificates, which are due to be checked
+  async fn refresh_due_certificates(&mut self) {
+    let now = Instant::now();
+    let due_fingerprints: Vec<String> = self
+      .next_checks
+      .iter()
+      .filter(|(_, next_check)| **next_check <= now)
+      .map(|(fingerprint, _)| fingerprint.clone())
+      .collect();
+    for fingerprint in due_fingerprints {
+      self.refresh_certificate(&fingerprint).await;
+    }
+  }
+}
+
+async fn run_ocsp_refresher(
+  mut refresher: OcspRefresher,
+  mut receiver: Receiver<Arc<CertifiedKey>>,
+) {
+  let mut interval = tokio::time::interval(refresher.refresh_interval.min(OCSP_RETRY_INTERVAL));
+  loop {
+    tokio::select! {
+      _ = interval.tick() => refresher.refresh_due_certificates().await,
+      certified_key = receiver.recv() => match certified_key {
+        Some(certified_key) => refresher.add_certificate(certified_key).await,
+        None => break,
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{CertificateParams, CustomExtension, KeyPair};
+
+  #[test]
+  fn test_must_staple() {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![String::from("example.com")]).unwrap();
+    assert!(!is_must_staple(
+      params.clone().self_signed(&key).unwrap().der()
+    ));
+
+    params
+      .custom_extensions
+      .push(CustomExtension::from_oid_content(
+        &[1, 3, 6, 1, 5, 5, 7, 1, 24],
+        vec![0x30, 0x03, 0x02, 0x01, 0x05],
+      ));
+    assert!(is_must_staple(params.self_signed(&key).unwrap().der()));
+  }
+
+  #[test]
+  fn test_ocsp_staple() {
+    let this_update = DateTime::parse_from_rfc3339("2025-06-01T00:00:00Z")
+      .unwrap()
+      .with_timezone(&Utc);
+    let staple = OcspStaple {
+      response: vec![0x30, 0x03, 0x0a, 0x01, 0x00],
+      this_update,
+      next_update: this_update + chrono::Duration::days(7),
+    };
+    assert!(staple.is_valid(this_update + chrono::Duration::days(1)));
+    assert!(!staple.needs_refresh(this_update + chrono::Duration::days(1)));
+    assert!(staple.needs_refresh(this_update + chrono::Duration::days(4)));
+    assert!(!staple.is_valid(this_update + chrono::Duration::days(7)));
+
+    assert_eq!(OcspStaple::from_cache(&staple.to_cache()), Some(staple));
+    assert_eq!(OcspStaple::from_cache("{\"response\": \"AA==\"}"), None);
+  }
+}
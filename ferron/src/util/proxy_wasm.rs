@@ -0,0 +1,1273 @@
+// A host for the WebAssembly plugins implementing the proxy-wasm ABI (version 0.2), which is also implemented by Envoy.
+// The plugins can inspect and modify the request and response headers, send the local responses and read the properties
+// of the request. The plugins run in an interpreter with a limited amount of fuel for each call and a limited memory size,
+// so a plugin can't corrupt or exhaust the server's memory, or block the thread running it indefinitely.
+//
+// The body callbacks, the timers, the HTTP calls, the shared data and the metrics aren't supported.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ferron_common::ErrorLogLevel;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode, Uri};
+use wasmi::core::ValType;
+use wasmi::{
+  AsContextMut, Caller, Config, Engine, Extern, ExternType, Instance, Linker, Memory, Module,
+  Store, StoreLimits, StoreLimitsBuilder, Val,
+};
+
+// The amount of fuel (roughly, the number of executed instructions) available to a single call of the plugin
+const WASM_FUEL_PER_CALL: u64 = 100_000_000;
+
+// The maximum size of the plugin's memory. An instance of the plugin can't grow its memory beyond it.
+const WASM_MAX_MEMORY_SIZE: usize = 64 * 1024 * 1024;
+
+// The ID of the root context, which is created when the plugin is loaded
+const ROOT_CONTEXT_ID: i32 = 1;
+
+// The WASI error code returned by the unsupported WASI functions ("ENOSYS")
+const WASI_ERRNO_NOSYS: i32 = 52;
+
+// The statuses returned by the host functions
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(i32)]
+enum Status {
+  Ok = 0,
+  NotFound = 1,
+  BadArgument = 2,
+  InvalidMemoryAccess = 6,
+  Unimplemented = 12,
+}
+
+// The header maps and the buffers the plugins can access
+const MAP_TYPE_HTTP_REQUEST_HEADERS: i32 = 0;
+const MAP_TYPE_HTTP_REQUEST_TRAILERS: i32 = 1;
+const MAP_TYPE_HTTP_RESPONSE_HEADERS: i32 = 2;
+const MAP_TYPE_HTTP_RESPONSE_TRAILERS: i32 = 3;
+const BUFFER_TYPE_VM_CONFIGURATION: i32 = 6;
+const BUFFER_TYPE_PLUGIN_CONFIGURATION: i32 = 7;
+
+// The header map of a request or a response, with the pseudo-headers (for example, ":path") first, like in HTTP/2
+pub type HeaderPairs = Vec<(String, Vec<u8>)>;
+
+// The response sent by the plugin instead of passing the request on (or instead of the original response)
+pub struct LocalResponse {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  pub body: Vec<u8>,
+}
+
+// The properties of the request the plugins can read with "proxy_get_property"
+#[derive(Clone, Default)]
+pub struct StreamProperties {
+  pub method: String,
+  pub path: String,
+  pub host: String,
+  pub scheme: String,
+  pub protocol: String,
+  pub source_address: Option<SocketAddr>,
+  pub destination_address: Option<SocketAddr>,
+  pub response_code: Option<u16>,
+}
+
+impl StreamProperties {
+  // Get the value of the property. The numeric properties are encoded as 64-bit little-endian integers, like in Envoy.
+  fn get(&self, path: &[&str]) -> Option<Vec<u8>> {
+    match path {
+      ["request", "method"] => Some(self.method.as_bytes().to_vec()),
+      ["request", "path"] => Some(self.path.as_bytes().to_vec()),
+      ["request", "url_path"] => Some(
+        self
+          .path
+          .split_once('?')
+          .map_or(self.path.as_str(), |(url_path, _)| url_path)
+          .as_bytes()
+          .to_vec(),
+      ),
+      ["request", "host"] => Some(self.host.as_bytes().to_vec()),
+      ["request", "scheme"] => Some(self.scheme.as_bytes().to_vec()),
+      ["request", "protocol"] => Some(self.protocol.as_bytes().to_vec()),
+      ["source", "address"] => self
+        .source_address
+        .map(|address| address.to_string().into_bytes()),
+      ["source", "port"] => self
+        .source_address
+        .map(|address| (address.port() as i64).to_le_bytes().to_vec()),
+      ["destination", "address"] => self
+        .destination_address
+        .map(|address| address.to_string().into_bytes()),
+      ["destination", "port"] => self
+        .destination_address
+        .map(|address| (address.port() as i64).to_le_bytes().to_vec()),
+      ["response", "code"] => self
+        .response_code
+        .map(|response_code| (response_code as i64).to_le_bytes().to_vec()),
+      _ => None,
+    }
+  }
+}
+
+// The state of the host, shared by the host functions called by the plugin
+struct HostState {
+  plugin_name: String,
+  vm_configuration: Vec<u8>,
+  plugin_configuration: Vec<u8>,
+  request_headers: HeaderPairs,
+  response_headers: HeaderPairs,
+  properties: StreamProperties,
+  custom_properties: HashMap<Vec<u8>, Vec<u8>>,
+  local_response: Option<LocalResponse>,
+  log_messages: Vec<(ErrorLogLevel, String)>,
+  limits: StoreLimits,
+}
+
+impl HostState {
+  fn header_map(&mut self, map_type: i32) -> Option<&mut HeaderPairs> {
+    match map_type {
+      MAP_TYPE_HTTP_REQUEST_HEADERS => Some(&mut self.request_headers),
+      MAP_TYPE_HTTP_RESPONSE_HEADERS => Some(&mut self.response_headers),
+      _ => None,
+    }
+  }
+}
+
+// Serialize the header map in the proxy-wasm format: the number of the pairs, the sizes of the names and the values,
+// and the null-terminated names and values, with the numbers encoded as 32-bit little-endian integers
+fn serialize_header_pairs(header_pairs: &[(String, Vec<u8>)]) -> Vec<u8> {
+  let mut serialized = Vec::new();
+  serialized.extend_from_slice(&(header_pairs.len() as u32).to_le_bytes());
+  for (name, value) in header_pairs {
+    serialized.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    serialized.extend_from_slice(&(value.len() as u32).to_le_bytes());
+  }
+  for (name, value) in header_pairs {
+    serialized.extend_from_slice(name.as_bytes());
+    serialized.push(0);
+    serialized.extend_from_slice(value);
+    serialized.push(0);
+  }
+  serialized
+}
+
+// Deserialize the header map in the proxy-wasm format
+fn deserialize_header_pairs(serialized: &[u8]) -> Option<HeaderPairs> {
+  if serialized.is_empty() {
+    return Some(Vec::new());
+  }
+  let read_u32 = |offset: usize| -> Option<usize> {
+    Some(u32::from_le_bytes(serialized.get(offset..offset + 4)?.try_into().ok()?) as usize)
+  };
+  let count = read_u32(0)?;
+  let mut data_offset = 4usize.checked_add(count.checked_mul(8)?)?;
+  let mut header_pairs = Vec::new();
+  for index in 0..count {
+    let name_size = read_u32(4 + index * 8)?;
+    let value_size = read_u32(8 + index * 8)?;
+    let name = serialized.get(data_offset..data_offset.checked_add(name_size)?)?;
+    data_offset = data_offset.checked_add(name_size + 1)?;
+    let value = serialized.get(data_offset..data_offset.checked_add(value_size)?)?;
+    data_offset = data_offset.checked_add(value_size + 1)?;
+    header_pairs.push((String::from_utf8(name.to_vec()).ok()?, value.to_vec()));
+  }
+  Some(header_pairs)
+}
+
+// Convert the header pairs without the pseudo-headers to the header map
+fn header_pairs_to_header_map(
+  header_pairs: &[(String, Vec<u8>)],
+) -> Result<HeaderMap, Box<dyn Error + Send + Sync>> {
+  let mut headers = HeaderMap::new();
+  for (name, value) in header_pairs {
+    if !name.starts_with(':') {
+      headers.append(HeaderName::from_str(name)?, HeaderValue::from_bytes(value)?);
+    }
+  }
+  Ok(headers)
+}
+
+// Get the header pairs of the request, with the ":method", ":path", ":authority" and ":scheme" pseudo-headers.
+// The "Host" header is passed as the ":authority" pseudo-header.
+pub fn request_header_pairs(
+  method: &Method,
+  uri: &Uri,
+  headers: &HeaderMap,
+  scheme: &str,
+) -> HeaderPairs {
+  let mut header_pairs = vec![
+    (String::from(":method"), method.as_str().as_bytes().to_vec()),
+    (
+      String::from(":path"),
+      uri
+        .path_and_query()
+        .map_or(uri.path(), |path_and_query| path_and_query.as_str())
+        .as_bytes()
+        .to_vec(),
+    ),
+  ];
+  if let Some(authority) = headers
+    .get(header::HOST)
+    .map(|host| host.as_bytes().to_vec())
+    .or_else(|| {
+      uri
+        .authority()
+        .map(|authority| authority.as_str().as_bytes().to_vec())
+    })
+  {
+    header_pairs.push((String::from(":authority"), authority));
+  }
+  header_pairs.push((String::from(":scheme"), scheme.as_bytes().to_vec()));
+  for (name, value) in headers {
+    if name != header::HOST {
+      header_pairs.push((name.as_str().to_string(), value.as_bytes().to_vec()));
+    }
+  }
+  header_pairs
+}
+
+// Apply the request header pairs (possibly modified by the plugin) to the request
+pub fn apply_request_header_pairs(
+  header_pairs: &[(String, Vec<u8>)],
+  method: &mut Method,
+  uri: &mut Uri,
+  headers: &mut HeaderMap,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let mut new_headers = header_pairs_to_header_map(header_pairs)?;
+  for (name, value) in header_pairs {
+    match name.as_str() {
+      ":method" => *method = Method::from_bytes(value)?,
+      ":path" => *uri = Uri::from_maybe_shared(value.clone())?,
+      ":authority" => {
+        new_headers.insert(header::HOST, HeaderValue::from_bytes(value)?);
+      }
+      _ => (),
+    }
+  }
+  *headers = new_headers;
+  Ok(())
+}
+
+// Get the header pairs of the response, with the ":status" pseudo-header
+pub fn response_header_pairs(status: StatusCode, headers: &HeaderMap) -> HeaderPairs {
+  let mut header_pairs = vec![(String::from(":status"), status.as_str().as_bytes().to_vec())];
+  for (name, value) in headers {
+    header_pairs.push((name.as_str().to_string(), value.as_bytes().to_vec()));
+  }
+  header_pairs
+}
+
+// Apply the response header pairs (possibly modified by the plugin) to the response
+pub fn apply_response_header_pairs(
+  header_pairs: &[(String, Vec<u8>)],
+  status: &mut StatusCode,
+  headers: &mut HeaderMap,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let new_headers = header_pairs_to_header_map(header_pairs)?;
+  if let Some((_, value)) = header_pairs.iter().find(|(name, _)| name == ":status") {
+    *status = StatusCode::from_bytes(value)?;
+  }
+  *headers = new_headers;
+  Ok(())
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> Option<Memory> {
+  caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+// Check that the range is inside the plugin's memory. The range is checked before a buffer for it is allocated,
+// so the plugin can't make the server allocate more memory than the plugin's memory has.
+fn check_memory_range(
+  caller: &Caller<'_, HostState>,
+  memory: &Memory,
+  pointer: i32,
+  size: i32,
+) -> Result<(), Status> {
+  match (pointer as u32 as usize).checked_add(size as u32 as usize) {
+    Some(end) if end <= memory.data_size(caller) => Ok(()),
+    _ => Err(Status::InvalidMemoryAccess),
+  }
+}
+
+// Read the bytes from the plugin's memory
+fn read_bytes(caller: &Caller<'_, HostState>, pointer: i32, size: i32) -> Result<Vec<u8>, Status> {
+  let memory = memory(caller).ok_or(Status::InvalidMemoryAccess)?;
+  check_memory_range(caller, &memory, pointer, size)?;
+  let mut buffer = vec![0; size as u32 as usize];
+  memory
+    .read(caller, pointer as u32 as usize, &mut buffer)
+    .map_err(|_| Status::InvalidMemoryAccess)?;
+  Ok(buffer)
+}
+
+// Write the bytes to the plugin's memory
+fn write_bytes(
+  caller: &mut Caller<'_, HostState>,
+  pointer: i32,
+  data: &[u8],
+) -> Result<(), Status> {
+  let memory = memory(caller).ok_or(Status::InvalidMemoryAccess)?;
+  memory
+    .write(caller, pointer as u32 as usize, data)
+    .map_err(|_| Status::InvalidMemoryAccess)
+}
+
+// Pass the bytes to the plugin. The memory for the bytes is allocated by the plugin, which takes the ownership of it.
+// The pointer to the bytes and their size are written to the specified locations.
+fn return_bytes(
+  caller: &mut Caller<'_, HostState>,
+  data: &[u8],
+  return_data_pointer: i32,
+  return_size_pointer: i32,
+) -> Result<(), Status> {
+  let data_pointer = match data.is_empty() {
+    true => 0,
+    false => {
+      let allocate = caller
+        .get_export("proxy_on_memory_allocate")
+        .or_else(|| caller.get_export("malloc"))
+        .and_then(Extern::into_func)
+        .and_then(|allocate| allocate.typed::<i32, i32>(&*caller).ok())
+        .ok_or(Status::InvalidMemoryAccess)?;
+      let data_pointer = allocate
+        .call(&mut *caller, data.len() as i32)
+        .map_err(|_| Status::InvalidMemoryAccess)?;
+      if data_pointer == 0 {
+        Err(Status::InvalidMemoryAccess)?
+      }
+      write_bytes(caller, data_pointer, data)?;
+      data_pointer
+    }
+  };
+  write_bytes(caller, return_data_pointer, &data_pointer.to_le_bytes())?;
+  write_bytes(
+    caller,
+    return_size_pointer,
+    &(data.len() as u32).to_le_bytes(),
+  )
+}
+
+fn status_of(result: Result<(), Status>) -> i32 {
+  match result {
+    Ok(()) => Status::Ok as i32,
+    Err(status) => status as i32,
+  }
+}
+
+// Define the proxy-wasm host functions
+fn define_proxy_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+  linker.func_wrap(
+    "env",
+    "proxy_log",
+    |caller: Caller<'_, HostState>, level: i32, message_data: i32, message_size: i32| -> i32 {
+      status_of((|| {
+        let message = read_bytes(&caller, message_data, message_size)?;
+        let level = match level {
+          0 => ErrorLogLevel::Trace,
+          1 => ErrorLogLevel::Debug,
+          2 => ErrorLogLevel::Info,
+          3 => ErrorLogLevel::Warn,
+          _ => ErrorLogLevel::Error,
+        };
+        let message = format!(
+          "WebAssembly plugin \"{}\": {}",
+          caller.data().plugin_name,
+          String::from_utf8_lossy(&message)
+        );
+        let mut caller = caller;
+        caller.data_mut().log_messages.push((level, message));
+        Ok(())
+      })())
+    },
+  )?;
+
+  // The log level filtering is done by the server's error log, so the plugin logs all the messages
+  linker.func_wrap(
+    "env",
+    "proxy_get_log_level",
+    |mut caller: Caller<'_, HostState>, return_level: i32| -> i32 {
+      status_of(write_bytes(&mut caller, return_level, &0i32.to_le_bytes()))
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_get_current_time_nanoseconds",
+    |mut caller: Caller<'_, HostState>, return_time: i32| -> i32 {
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default();
+      status_of(write_bytes(&mut caller, return_time, &now.to_le_bytes()))
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_get_buffer_bytes",
+    |mut caller: Caller<'_, HostState>,
+     buffer_type: i32,
+     start: i32,
+     max_size: i32,
+     return_buffer_data: i32,
+     return_buffer_size: i32|
+     -> i32 {
+      let buffer = match buffer_type {
+        BUFFER_TYPE_VM_CONFIGURATION => caller.data().vm_configuration.clone(),
+        BUFFER_TYPE_PLUGIN_CONFIGURATION => caller.data().plugin_configuration.clone(),
+        _ => return Status::NotFound as i32,
+      };
+      let start = (start as u32 as usize).min(buffer.len());
+      let end = start
+        .saturating_add(max_size as u32 as usize)
+        .min(buffer.len());
+      status_of(return_bytes(
+        &mut caller,
+        &buffer[start..end],
+        return_buffer_data,
+        return_buffer_size,
+      ))
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_get_header_map_pairs",
+    |mut caller: Caller<'_, HostState>,
+     map_type: i32,
+     return_map_data: i32,
+     return_map_size: i32|
+     -> i32 {
+      let serialized = match map_type {
+        MAP_TYPE_HTTP_REQUEST_TRAILERS | MAP_TYPE_HTTP_RESPONSE_TRAILERS => {
+          serialize_header_pairs(&[])
+        }
+        _ => match caller.data_mut().header_map(map_type) {
+          Some(header_pairs) => serialize_header_pairs(header_pairs),
+          None => return Status::BadArgument as i32,
+        },
+      };
+      status_of(return_bytes(
+        &mut caller,
+        &serialized,
+        return_map_data,
+        return_map_size,
+      ))
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_set_header_map_pairs",
+    |mut caller: Caller<'_, HostState>, map_type: i32, map_data: i32, map_size: i32| -> i32 {
+      status_of((|| {
+        let serialized = read_bytes(&caller, map_data, map_size)?;
+        let new_header_pairs = deserialize_header_pairs(&serialized).ok_or(Status::BadArgument)?;
+        let header_pairs = caller
+          .data_mut()
+          .header_map(map_type)
+          .ok_or(Status::BadArgument)?;
+        *header_pairs = new_header_pairs
+          .into_iter()
+          .map(|(name, value)| (name.to_lowercase(), value))
+          .collect();
+        Ok(())
+      })())
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_get_header_map_value",
+    |mut caller: Caller<'_, HostState>,
+     map_type: i32,
+     key_data: i32,
+     key_size: i32,
+     return_value_data: i32,
+     return_value_size: i32|
+     -> i32 {
+      status_of((|| {
+        let key = String::from_utf8_lossy(&read_bytes(&caller, key_data, key_size)?).to_lowercase();
+        let value = match map_type {
+          MAP_TYPE_HTTP_REQUEST_TRAILERS | MAP_TYPE_HTTP_RESPONSE_TRAILERS => None,
+          _ => caller
+            .data_mut()
+            .header_map(map_type)
+            .ok_or(Status::BadArgument)?
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.clone()),
+        };
+        let value = value.ok_or(Status::NotFound)?;
+        return_bytes(&mut caller, &value, return_value_data, return_value_size)
+      })())
+    },
+  )?;
+
+  for (function_name, replace) in [
+    ("proxy_add_header_map_value", false),
+    ("proxy_replace_header_map_value", true),
+  ] {
+    linker.func_wrap(
+      "env",
+      function_name,
+      move |mut caller: Caller<'_, HostState>,
+            map_type: i32,
+            key_data: i32,
+            key_size: i32,
+            value_data: i32,
+            value_size: i32|
+            -> i32 {
+        status_of((|| {
+          let key =
+            String::from_utf8_lossy(&read_bytes(&caller, key_data, key_size)?).to_lowercase();
+          let value = read_bytes(&caller, value_data, value_size)?;
+          let header_pairs = caller
+            .data_mut()
+            .header_map(map_type)
+            .ok_or(Status::BadArgument)?;
+          // The value of the existing header is replaced in place, so that the pseudo-headers stay first
+          match header_pairs.iter().position(|(name, _)| *name == key) {
+            Some(position) if replace => {
+              header_pairs[position].1 = value;
+              let mut index = 0;
+              header_pairs.retain(|(name, _)| {
+                index += 1;
+                index - 1 == position || *name != key
+              });
+            }
+            _ => header_pairs.push((key, value)),
+          }
+          Ok(())
+        })())
+      },
+    )?;
+  }
+
+  linker.func_wrap(
+    "env",
+    "proxy_remove_header_map_value",
+    |mut caller: Caller<'_, HostState>, map_type: i32, key_data: i32, key_size: i32| -> i32 {
+      status_of((|| {
+        let key = String::from_utf8_lossy(&read_bytes(&caller, key_data, key_size)?).to_lowercase();
+        caller
+          .data_mut()
+          .header_map(map_type)
+          .ok_or(Status::BadArgument)?
+          .retain(|(name, _)| *name != key);
+        Ok(())
+      })())
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_get_property",
+    |mut caller: Caller<'_, HostState>,
+     path_data: i32,
+     path_size: i32,
+     return_value_data: i32,
+     return_value_size: i32|
+     -> i32 {
+      status_of((|| {
+        let path = read_bytes(&caller, path_data, path_size)?;
+        // The path segments are separated with the null characters
+        let path_string = String::from_utf8_lossy(&path);
+        let path_segments = path_string
+          .trim_end_matches('\0')
+          .split('\0')
+          .collect::<Vec<_>>();
+        let host_state = caller.data();
+        let value = match path_segments.as_slice() {
+          ["plugin_name"] => Some(host_state.plugin_name.as_bytes().to_vec()),
+          path_segments => host_state
+            .custom_properties
+            .get(&path)
+            .cloned()
+            .or_else(|| host_state.properties.get(path_segments)),
+        };
+        let value = value.ok_or(Status::NotFound)?;
+        return_bytes(&mut caller, &value, return_value_data, return_value_size)
+      })())
+    },
+  )?;
+
+  // The properties set by the plugin can be read back by the plugin
+  linker.func_wrap(
+    "env",
+    "proxy_set_property",
+    |mut caller: Caller<'_, HostState>,
+     path_data: i32,
+     path_size: i32,
+     value_data: i32,
+     value_size: i32|
+     -> i32 {
+      status_of((|| {
+        let path = read_bytes(&caller, path_data, path_size)?;
+        let value = read_bytes(&caller, value_data, value_size)?;
+        caller.data_mut().custom_properties.insert(path, value);
+        Ok(())
+      })())
+    },
+  )?;
+
+  linker.func_wrap(
+    "env",
+    "proxy_send_local_response",
+    |mut caller: Caller<'_, HostState>,
+     status_code: i32,
+     _status_code_details_data: i32,
+     _status_code_details_size: i32,
+     body_data: i32,
+     body_size: i32,
+     additional_headers_map_data: i32,
+     additional_headers_size: i32,
+     _grpc_status: i32|
+     -> i32 {
+      status_of((|| {
+        let status = u16::try_from(status_code)
+          .ok()
+          .and_then(|status_code| StatusCode::from_u16(status_code).ok())
+          .ok_or(Status::BadArgument)?;
+        let body = read_bytes(&caller, body_data, body_size)?;
+        let serialized_headers = read_bytes(
+          &caller,
+          additional_headers_map_data,
+          additional_headers_size,
+        )?;
+        let headers = deserialize_header_pairs(&serialized_headers)
+          .and_then(|header_pairs| header_pairs_to_header_map(&header_pairs).ok())
+          .ok_or(Status::BadArgument)?;
+        caller.data_mut().local_response = Some(LocalResponse {
+          status,
+          headers,
+          body,
+        });
+        Ok(())
+      })())
+    },
+  )?;
+
+  // The streams are processed one callback at a time and can't be paused, so these functions have no effect.
+  // The timers aren't supported, but the plugins commonly set the tick period when they start.
+  linker.func_wrap(
+    "env",
+    "proxy_set_effective_context",
+    |_: Caller<'_, HostState>, _context_id: i32| -> i32 { Status::Ok as i32 },
+  )?;
+  linker.func_wrap(
+    "env",
+    "proxy_continue_stream",
+    |_: Caller<'_, HostState>, _stream_type: i32| -> i32 { Status::Ok as i32 },
+  )?;
+  linker.func_wrap(
+    "env",
+    "proxy_close_stream",
+    |_: Caller<'_, HostState>, _stream_type: i32| -> i32 { Status::Ok as i32 },
+  )?;
+  linker.func_wrap("env", "proxy_done", |_: Caller<'_, HostState>| -> i32 {
+    Status::Ok as i32
+  })?;
+  linker.func_wrap(
+    "env",
+    "proxy_set_tick_period_milliseconds",
+    |_: Caller<'_, HostState>, _tick_period: i32| -> i32 { Status::Ok as i32 },
+  )?;
+
+  Ok(())
+}
+
+// Define the WASI functions used by the standard libraries of the languages compiled to WebAssembly.
+// The plugins can't access the file system and the environment variables.
+fn define_wasi_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::Error> {
+  const WASI: &str = "wasi_snapshot_preview1";
+
+  // The data written to the standard output and the standard error (for example, the panic messages) is logged
+  linker.func_wrap(
+    WASI,
+    "fd_write",
+    |mut caller: Caller<'_, HostState>,
+     fd: i32,
+     iovs: i32,
+     iovs_len: i32,
+     return_written: i32|
+     -> i32 {
+      let result = (|| -> Result<(), Status> {
+        let mut written = Vec::new();
+        for index in 0..iovs_len {
+          let iov = read_bytes(&caller, iovs.wrapping_add(index.wrapping_mul(8)), 8)?;
+          let data_pointer = i32::from_le_bytes([iov[0], iov[1], iov[2], iov[3]]);
+          let data_size = i32::from_le_bytes([iov[4], iov[5], iov[6], iov[7]]);
+          written.extend(read_bytes(&caller, data_pointer, data_size)?);
+        }
+        write_bytes(
+          &mut caller,
+          return_written,
+          &(written.len() as u32).to_le_bytes(),
+        )?;
+        let message = String::from_utf8_lossy(&written).trim_end().to_string();
+        if !message.is_empty() {
+          let message = format!(
+            "WebAssembly plugin \"{}\": {}",
+            caller.data().plugin_name,
+            message
+          );
+          let level = match fd {
+            1 => ErrorLogLevel::Info,
+            _ => ErrorLogLevel::Error,
+          };
+          caller.data_mut().log_messages.push((level, message));
+        }
+        Ok(())
+      })();
+      match result {
+        Ok(()) => 0,
+        Err(_) => WASI_ERRNO_NOSYS,
+      }
+    },
+  )?;
+
+  linker.func_wrap(
+    WASI,
+    "clock_time_get",
+    |mut caller: Caller<'_, HostState>, _clock_id: i32, _precision: i64, return_time: i32| -> i32 {
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default();
+      match write_bytes(&mut caller, return_time, &now.to_le_bytes()) {
+        Ok(()) => 0,
+        Err(_) => WASI_ERRNO_NOSYS,
+      }
+    },
+  )?;
+
+  linker.func_wrap(
+    WASI,
+    "random_get",
+    |mut caller: Caller<'_, HostState>, buffer: i32, buffer_size: i32| -> i32 {
+      let result = memory(&caller)
+        .ok_or(Status::InvalidMemoryAccess)
+        .and_then(|memory| check_memory_range(&caller, &memory, buffer, buffer_size))
+        .and_then(|_| {
+          let mut random_bytes = vec![0u8; buffer_size as u32 as usize];
+          rand::fill(&mut random_bytes[..]);
+          write_bytes(&mut caller, buffer, &random_bytes)
+        });
+      match result {
+        Ok(()) => 0,
+        Err(_) => WASI_ERRNO_NOSYS,
+      }
+    },
+  )?;
+
+  // There are no environment variables and command-line arguments
+  for function_name in ["environ_sizes_get", "args_sizes_get"] {
+    linker.func_wrap(
+      WASI,
+      function_name,
+      |mut caller: Caller<'_, HostState>, return_count: i32, return_size: i32| -> i32 {
+        match write_bytes(&mut caller, return_count, &0u32.to_le_bytes())
+          .and_then(|_| write_bytes(&mut caller, return_size, &0u32.to_le_bytes()))
+        {
+          Ok(()) => 0,
+          Err(_) => WASI_ERRNO_NOSYS,
+        }
+      },
+    )?;
+  }
+  for function_name in ["environ_get", "args_get"] {
+    linker.func_wrap(
+      WASI,
+      function_name,
+      |_: Caller<'_, HostState>, _pointers: i32, _buffer: i32| -> i32 { 0 },
+    )?;
+  }
+
+  linker.func_wrap(
+    WASI,
+    "proc_exit",
+    |_: Caller<'_, HostState>, exit_code: i32| -> Result<(), wasmi::Error> {
+      Err(wasmi::Error::i32_exit(exit_code))
+    },
+  )?;
+
+  Ok(())
+}
+
+// A loaded WebAssembly plugin, with its root context created and configured
+pub struct WasmPlugin {
+  store: Store<HostState>,
+  instance: Instance,
+  next_context_id: i32,
+}
+
+impl WasmPlugin {
+  // Create the engine for the plugins, which limits the amount of work done by a single call of the plugin
+  pub fn engine() -> Engine {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+  }
+
+  // Compile the plugin. The compiled plugin can be instantiated several times.
+  pub fn compile(engine: &Engine, wasm: &[u8]) -> Result<Module, Box<dyn Error + Send + Sync>> {
+    let module = Module::new(engine, wasm)?;
+    if !module.exports().any(|export| {
+      export.name() == "proxy_abi_version_0_2_0" || export.name() == "proxy_abi_version_0_2_1"
+    }) {
+      Err(anyhow::anyhow!(
+        "The WebAssembly module doesn't implement the supported proxy-wasm ABI version (0.2.0 or 0.2.1)"
+      ))?
+    }
+    Ok(module)
+  }
+
+  // Create an instance of the compiled plugin, and start it with the plugin configuration
+  pub fn instantiate(
+    engine: &Engine,
+    module: &Module,
+    plugin_name: &str,
+    plugin_configuration: &str,
+  ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let mut store = Store::new(
+      engine,
+      HostState {
+        plugin_name: plugin_name.to_string(),
+        vm_configuration: Vec::new(),
+        plugin_configuration: plugin_configuration.as_bytes().to_vec(),
+        request_headers: Vec::new(),
+        response_headers: Vec::new(),
+        properties: StreamProperties::default(),
+        custom_properties: HashMap::new(),
+        local_response: None,
+        log_messages: Vec::new(),
+        limits: StoreLimitsBuilder::new()
+          .memory_size(WASM_MAX_MEMORY_SIZE)
+          .build(),
+      },
+    );
+    store.limiter(|host_state| &mut host_state.limits);
+    let mut linker = Linker::new(engine);
+    linker.allow_shadowing(true);
+
+    // The imported functions not defined by the host (for example, the HTTP calls) report that they're unimplemented
+    // when called. The functions defined by the host replace these definitions.
+    for import in module.imports() {
+      if let ExternType::Func(func_type) = import.ty() {
+        let unimplemented_status = match import.module() {
+          "wasi_snapshot_preview1" => WASI_ERRNO_NOSYS,
+          _ => Status::Unimplemented as i32,
+        };
+        let function_name = import.name().to_string();
+        linker.func_new(
+          import.module(),
+          import.name(),
+          func_type.clone(),
+          move |_, _, results| match results {
+            [result] if result.ty() == ValType::I32 => {
+              *result = Val::I32(unimplemented_status);
+              Ok(())
+            }
+            _ => Err(wasmi::Error::new(format!(
+              "The \"{}\" function isn't supported",
+              function_name
+            ))),
+          },
+        )?;
+      }
+    }
+    define_proxy_functions(&mut linker)?;
+    define_wasi_functions(&mut linker)?;
+
+    store.set_fuel(WASM_FUEL_PER_CALL)?;
+    let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+    let mut plugin = Self {
+      store,
+      instance,
+      next_context_id: ROOT_CONTEXT_ID + 1,
+    };
+
+    // The WASI reactors are initialized before any other function is called
+    for initialize_function in ["_initialize", "_start"] {
+      if plugin.has_export(initialize_function) {
+        plugin.call::<(), ()>(initialize_function, ())?;
+        break;
+      }
+    }
+
+    plugin.call::<(i32, i32), ()>("proxy_on_context_create", (ROOT_CONTEXT_ID, 0))?;
+    if plugin.call::<(i32, i32), i32>("proxy_on_vm_start", (ROOT_CONTEXT_ID, 0))? == 0 {
+      Err(anyhow::anyhow!("The WebAssembly plugin failed to start"))?
+    }
+    if plugin.call::<(i32, i32), i32>(
+      "proxy_on_configure",
+      (ROOT_CONTEXT_ID, plugin_configuration.len() as i32),
+    )? == 0
+    {
+      Err(anyhow::anyhow!(
+        "The WebAssembly plugin rejected the plugin configuration"
+      ))?
+    }
+
+    Ok(plugin)
+  }
+
+  fn has_export(&self, name: &str) -> bool {
+    self.instance.get_export(&self.store, name).is_some()
+  }
+
+  // Call the exported function of the plugin with a fresh amount of fuel. If the plugin doesn't export the function,
+  // the default value is returned.
+  fn call<Params: wasmi::WasmParams, Results: wasmi::WasmResults + Default>(
+    &mut self,
+    name: &str,
+    params: Params,
+  ) -> Result<Results, Box<dyn Error + Send + Sync>> {
+    if !self.has_export(name) {
+      return Ok(Results::default());
+    }
+    let function = self
+      .instance
+      .get_typed_func::<Params, Results>(&self.store, name)?;
+    self.store.set_fuel(WASM_FUEL_PER_CALL)?;
+    function
+      .call(self.store.as_context_mut(), params)
+      .map_err(|err| {
+        anyhow::anyhow!("The WebAssembly plugin failed in \"{}\": {}", name, err).into()
+      })
+  }
+
+  // Create the context for a request
+  pub fn create_stream(
+    &mut self,
+    properties: StreamProperties,
+  ) -> Result<i32, Box<dyn Error + Send + Sync>> {
+    let context_id = self.next_context_id;
+    self.next_context_id = match self.next_context_id.checked_add(1) {
+      Some(next_context_id) => next_context_id,
+      None => ROOT_CONTEXT_ID + 1,
+    };
+    let host_state = self.store.data_mut();
+    host_state.properties = properties;
+    host_state.custom_properties.clear();
+    self.call::<(i32, i32), ()>("proxy_on_context_create", (context_id, ROOT_CONTEXT_ID))?;
+    Ok(context_id)
+  }
+
+  // Pass the request headers to the plugin. Returns the local response, if the plugin sent one.
+  pub fn on_request_headers(
+    &mut self,
+    context_id: i32,
+    properties: StreamProperties,
+    header_pairs: &mut HeaderPairs,
+    end_of_stream: bool,
+  ) -> Result<Option<LocalResponse>, Box<dyn Error + Send + Sync>> {
+    let host_state = self.store.data_mut();
+    host_state.properties = properties;
+    host_state.request_headers = std::mem::take(header_pairs);
+    host_state.local_response = None;
+    let result = self.call::<(i32, i32, i32), i32>(
+      "proxy_on_request_headers",
+      (
+        context_id,
+        self.store.data().request_headers.len() as i32,
+        end_of_stream as i32,
+      ),
+    );
+    let host_state = self.store.data_mut();
+    *header_pairs = std::mem::take(&mut host_state.request_headers);
+    // The streams can't be paused, so the returned action is ignored, and the paused streams are continued
+    result?;
+    Ok(host_state.local_response.take())
+  }
+
+  // Pass the response headers to the plugin. Returns the local response, if the plugin sent one.
+  pub fn on_response_headers(
+    &mut self,
+    context_id: i32,
+    properties: StreamProperties,
+    header_pairs: &mut HeaderPairs,
+    end_of_stream: bool,
+  ) -> Result<Option<LocalResponse>, Box<dyn Error + Send + Sync>> {
+    let host_state = self.store.data_mut();
+    host_state.properties = properties;
+    host_state.response_headers = std::mem::take(header_pairs);
+    host_state.local_response = None;
+    let result = self.call::<(i32, i32, i32), i32>(
+      "proxy_on_response_headers",
+      (
+        context_id,
+        self.store.data().response_headers.len() as i32,
+        end_of_stream as i32,
+      ),
+    );
+    let host_state = self.store.data_mut();
+    *header_pairs = std::mem::take(&mut host_state.response_headers);
+    result?;
+    Ok(host_state.local_response.take())
+  }
+
+  // Notify the plugin that the request is complete, and delete the request's context
+  pub fn finish_stream(&mut self, context_id: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let log_result = self.call::<i32, ()>("proxy_on_log", context_id);
+    let done_result = self.call::<i32, i32>("proxy_on_done", context_id);
+    let delete_result = self.call::<i32, ()>("proxy_on_delete", context_id);
+    let host_state = self.store.data_mut();
+    host_state.request_headers.clear();
+    host_state.response_headers.clear();
+    host_state.custom_properties.clear();
+    log_result?;
+    done_result?;
+    delete_result?;
+    Ok(())
+  }
+
+  // Take the messages logged by the plugin since the last call
+  pub fn take_log_messages(&mut self) -> Vec<(ErrorLogLevel, String)> {
+    std::mem::take(&mut self.store.data_mut().log_messages)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A plugin adding the "x-wasm: hello" header to the requests and the responses, and denying the "/blocked" path
+  const TEST_PLUGIN: &str = r#"
+    (module
+      (import "env" "proxy_add_header_map_value" (func $add (param i32 i32 i32 i32 i32) (result i32)))
+      (import "env" "proxy_get_header_map_value" (func $get (param i32 i32 i32 i32 i32) (result i32)))
+      (import "env" "proxy_send_local_response"
+        (func $respond (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+      (import "env" "proxy_log" (func $log (param i32 i32 i32) (result i32)))
+      (import "env" "proxy_http_call" (func $http_call (param i32 i32 i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+      (memory (export "memory") 1)
+      (global $heap (mut i32) (i32.const 1024))
+      (data (i32.const 0) "x-wasm")
+      (data (i32.const 16) "hello")
+      (data (i32.const 32) ":path")
+      (data (i32.const 48) "/blocked")
+      (data (i32.const 64) "denied")
+      (func (export "proxy_abi_version_0_2_1"))
+      (func (export "proxy_on_memory_allocate") (param $size i32) (result i32)
+        (local $pointer i32)
+        (local.set $pointer (global.get $heap))
+        (global.set $heap (i32.add (global.get $heap) (local.get $size)))
+        (local.get $pointer))
+      (func (export "proxy_on_context_create") (param i32 i32))
+      (func (export "proxy_on_vm_start") (param i32 i32) (result i32) (i32.const 1))
+      (func (export "proxy_on_configure") (param i32 i32) (result i32)
+        (drop (call $log (i32.const 2) (i32.const 16) (i32.const 5)))
+        (i32.const 1))
+      (func (export "proxy_on_request_headers") (param i32 i32 i32) (result i32)
+        (drop (call $get (i32.const 0) (i32.const 32) (i32.const 5) (i32.const 200) (i32.const 204)))
+        (if (i32.and
+              (i32.eq (i32.load (i32.const 204)) (i32.const 8))
+              (i64.eq (i64.load (i32.load (i32.const 200))) (i64.load (i32.const 48))))
+          (then
+            (drop (call $respond (i32.const 403) (i32.const 0) (i32.const 0) (i32.const 64) (i32.const 6)
+              (i32.const 0) (i32.const 0) (i32.const -1)))
+            (return (i32.const 1))))
+        (drop (call $add (i32.const 0) (i32.const 0) (i32.const 6) (i32.const 16) (i32.const 5)))
+        (i32.const 0))
+      (func (export "proxy_on_response_headers") (param i32 i32 i32) (result i32)
+        (drop (call $add (i32.const 2) (i32.const 0) (i32.const 6) (i32.const 16) (i32.const 5)))
+        (i32.const 0))
+      (func (export "proxy_on_done") (param i32) (result i32)
+        (if (i32.ne
+              (call $http_call (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+                (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))
+              (i32.const 12))
+          (then (unreachable)))
+        (i32.const 1))
+    )
+  "#;
+
+  fn load_test_plugin(wat: &str) -> Result<WasmPlugin, Box<dyn Error + Send + Sync>> {
+    let engine = WasmPlugin::engine();
+    let module = WasmPlugin::compile(&engine, &wat::parse_str(wat).unwrap())?;
+    WasmPlugin::instantiate(&engine, &module, "test", "")
+  }
+
+  #[test]
+  fn test_header_pairs_serialization() {
+    let header_pairs = vec![
+      (String::from(":path"), b"/".to_vec()),
+      (String::from("x-example"), b"value".to_vec()),
+    ];
+    let serialized = serialize_header_pairs(&header_pairs);
+    assert_eq!(deserialize_header_pairs(&serialized), Some(header_pairs));
+    assert_eq!(deserialize_header_pairs(&serialized[..20]), None);
+  }
+
+  #[test]
+  fn test_request_header_pairs() {
+    let mut method = Method::GET;
+    let mut uri = Uri::from_static("/index.html?a=1");
+    let mut headers = HeaderMap::new();
+    headers.insert(header::HOST, "example.com".parse().unwrap());
+    headers.insert("x-example", "value".parse().unwrap());
+    let mut header_pairs = request_header_pairs(&method, &uri, &headers, "https");
+    assert_eq!(
+      header_pairs
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>(),
+      vec![":method", ":path", ":authority", ":scheme", "x-example"]
+    );
+
+    header_pairs[1].1 = b"/other.html".to_vec();
+    header_pairs[2].1 = b"example.org".to_vec();
+    apply_request_header_pairs(&header_pairs, &mut method, &mut uri, &mut headers).unwrap();
+    assert_eq!(uri, Uri::from_static("/other.html"));
+    assert_eq!(headers.get(header::HOST).unwrap(), "example.org");
+    assert_eq!(headers.get("x-example").unwrap(), "value");
+  }
+
+  #[test]
+  fn test_plugin_headers() {
+    let mut plugin = load_test_plugin(TEST_PLUGIN).unwrap();
+    let log_messages = plugin.take_log_messages();
+    assert_eq!(log_messages.len(), 1);
+    assert_eq!(log_messages[0].1, "WebAssembly plugin \"test\": hello");
+
+    let context_id = plugin.create_stream(StreamProperties::default()).unwrap();
+    let mut header_pairs = request_header_pairs(
+      &Method::GET,
+      &Uri::from_static("/"),
+      &HeaderMap::new(),
+      "http",
+    );
+    let local_response = plugin
+      .on_request_headers(
+        context_id,
+        StreamProperties::default(),
+        &mut header_pairs,
+        true,
+      )
+      .unwrap();
+    assert!(local_response.is_none());
+    assert!(header_pairs.contains(&(String::from("x-wasm"), b"hello".to_vec())));
+
+    let mut header_pairs = response_header_pairs(StatusCode::OK, &HeaderMap::new());
+    plugin
+      .on_response_headers(
+        context_id,
+        StreamProperties::default(),
+        &mut header_pairs,
+        false,
+      )
+      .unwrap();
+    let mut status = StatusCode::OK;
+    let mut headers = HeaderMap::new();
+    apply_response_header_pairs(&header_pairs, &mut status, &mut headers).unwrap();
+    assert_eq!(headers.get("x-wasm").unwrap(), "hello");
+
+    // The unsupported HTTP call reports that it's unimplemented
+    plugin.finish_stream(context_id).unwrap();
+  }
+
+  #[test]
+  fn test_plugin_local_response() {
+    let mut plugin = load_test_plugin(TEST_PLUGIN).unwrap();
+    let context_id = plugin.create_stream(StreamProperties::default()).unwrap();
+    let mut header_pairs = request_header_pairs(
+      &Method::GET,
+      &Uri::from_static("/blocked"),
+      &HeaderMap::new(),
+      "http",
+    );
+    let local_response = plugin
+      .on_request_headers(
+        context_id,
+        StreamProperties::default(),
+        &mut header_pairs,
+        true,
+      )
+      .unwrap()
+      .unwrap();
+    assert_eq!(local_response.status, StatusCode::FORBIDDEN);
+    assert_eq!(local_response.body, b"denied");
+  }
+
+  #[test]
+  fn test_plugin_abi_version() {
+    assert!(load_test_plugin("(module (memory (export \"memory\") 1))").is_err());
+  }
+
+  #[test]
+  fn test_plugin_fuel_limit() {
+    let mut plugin = load_test_plugin(
+      r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "proxy_abi_version_0_2_0"))
+          (func (export "proxy_on_vm_start") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_configure") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_request_headers") (param i32 i32 i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 0))
+        )
+      "#,
+    )
+    .unwrap();
+    let context_id = plugin.create_stream(StreamProperties::default()).unwrap();
+    let mut header_pairs = Vec::new();
+    assert!(plugin
+      .on_request_headers(
+        context_id,
+        StreamProperties::default(),
+        &mut header_pairs,
+        true
+      )
+      .is_err());
+  }
+
+  #[test]
+  fn test_plugin_memory_limits() {
+    // The plugin can't make the server allocate a buffer larger than the plugin's memory
+    let mut plugin = load_test_plugin(
+      r#"
+        (module
+          (import "env" "proxy_log" (func $log (param i32 i32 i32) (result i32)))
+          (import "wasi_snapshot_preview1" "random_get" (func $random (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "proxy_abi_version_0_2_0"))
+          (func (export "proxy_on_vm_start") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_configure") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_request_headers") (param i32 i32 i32) (result i32)
+            (if (i32.ne (call $log (i32.const 2) (i32.const 0) (i32.const -1)) (i32.const 6))
+              (then (unreachable)))
+            (if (i32.eqz (call $random (i32.const 0) (i32.const -1)))
+              (then (unreachable)))
+            (i32.const 0))
+        )
+      "#,
+    )
+    .unwrap();
+    let context_id = plugin.create_stream(StreamProperties::default()).unwrap();
+    let mut header_pairs = Vec::new();
+    plugin
+      .on_request_headers(
+        context_id,
+        StreamProperties::default(),
+        &mut header_pairs,
+        true,
+      )
+      .unwrap();
+
+    // The plugin's memory can't exceed the limit
+    assert!(load_test_plugin(
+      r#"
+        (module
+          (memory (export "memory") 1025)
+          (func (export "proxy_abi_version_0_2_0"))
+        )
+      "#,
+    )
+    .is_err());
+  }
+}
@@ -107,7 +107,7 @@ mod tests {
       _cx: &mut Context<'_>,
       _buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-      Poll::Ready(Err(io::Error::new(io::ErrorKind::Other, "read error")))
+      Poll::Ready(Err(io::Error::other("read error")))
     }
   }
 
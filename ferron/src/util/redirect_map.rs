@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use hyper::StatusCode;
+
+// A table of exact-path redirects, loaded from a file with "source,target[,status code]" lines
+pub struct RedirectMap {
+  redirects: HashMap<String, (String, StatusCode)>,
+}
+
+impl RedirectMap {
+  // Load the redirect map from a text. Empty lines and lines beginning with "#" are ignored.
+  pub fn load_from_text(text: &str) -> Result<Self, anyhow::Error> {
+    let mut redirects = HashMap::new();
+    for (line_index, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let mut fields = line.splitn(3, ',').map(str::trim);
+      let (source, target) = match (fields.next(), fields.next()) {
+        (Some(source), Some(target)) if source.starts_with('/') && !target.is_empty() => {
+          (source, target)
+        }
+        _ => Err(anyhow::anyhow!(
+          "Invalid redirect map entry at line {}",
+          line_index + 1
+        ))?,
+      };
+      let status_code = match fields.next() {
+        Some(status_code) => match status_code.parse::<u16>() {
+          Ok(status_code @ (301 | 302 | 303 | 307 | 308)) => StatusCode::from_u16(status_code)?,
+          _ => Err(anyhow::anyhow!(
+            "Invalid redirect status code at line {}",
+            line_index + 1
+          ))?,
+        },
+        None => StatusCode::MOVED_PERMANENTLY,
+      };
+
+      redirects.insert(source.to_string(), (target.to_string(), status_code));
+    }
+
+    Ok(Self { redirects })
+  }
+
+  // Get the redirect target and the status code for a request path
+  pub fn get(&self, path: &str) -> Option<(&str, StatusCode)> {
+    self
+      .redirects
+      .get(path)
+      .map(|(target, status_code)| (target.as_str(), *status_code))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_load_redirect_map() {
+    let redirect_map = RedirectMap::load_from_text(
+      "# Old blog
+/old-post,/blog/new-post
+/legacy/page.html, https://example.com/page, 302
+
+/products.php,/products,308",
+    )
+    .unwrap();
+
+    assert_eq!(redirect_map.redirects.len(), 3);
+    assert_eq!(
+      redirect_map.get("/old-post"),
+      Some(("/blog/new-post", StatusCode::MOVED_PERMANENTLY))
+    );
+    assert_eq!(
+      redirect_map.get("/legacy/page.html"),
+      Some(("https://example.com/page", StatusCode::FOUND))
+    );
+    assert_eq!(redirect_map.get("/old-post/"), None);
+  }
+
+  #[test]
+  fn test_invalid_redirect_map() {
+    assert!(RedirectMap::load_from_text("/a,/b\n/c").is_err());
+    assert!(RedirectMap::load_from_text("/a,/b,200").is_err());
+    assert!(RedirectMap::load_from_text("a,/b").is_err());
+  }
+}
@@ -0,0 +1,251 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http_body_util::{combinators::BoxBody, BodyExt};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::metrics::METRICS;
+
+// The default maximum time a response waits for the earlier responses on the connection (in milliseconds)
+const DEFAULT_RESPONSE_QUEUE_TIMEOUT: u64 = 30000;
+
+// The fairness controls of the responses sent on an HTTP/2 connection, so that a single connection
+// with many heavy streams can't monopolize the write bandwidth of the worker.
+// The number of the responses in flight is limited, and the response bodies are split into chunks
+// of at most the per-stream buffered bytes limit. The HTTP/2 connection buffers at most that many bytes for a stream,
+// and sends the buffered data of the streams in the round-robin order, so a stream with a large body
+// can't get ahead of the other streams by more than a chunk.
+pub struct ResponseFairness {
+  in_flight: Option<Arc<Semaphore>>,
+  queue_timeout: Duration,
+  max_stream_buffered_bytes: Option<usize>,
+}
+
+// The response waited for the earlier responses on the connection for too long
+#[derive(Debug)]
+pub struct ResponseQueueTimeout;
+
+impl ResponseFairness {
+  // Create the fairness controls for a connection from the "maxInFlightResponses", "maxSendBufferSize"
+  // and "responseQueueTimeout" HTTP/2 settings. Returns None if no controls are configured.
+  pub fn from_http2_settings(http2_settings: &Yaml) -> Option<Arc<Self>> {
+    let in_flight = http2_settings["maxInFlightResponses"]
+      .as_i64()
+      .map(|max_in_flight_responses| Arc::new(Semaphore::new(max_in_flight_responses as usize)));
+    let max_stream_buffered_bytes = http2_settings["maxSendBufferSize"]
+      .as_i64()
+      .map(|max_send_buffer_size| max_send_buffer_size as usize);
+    if in_flight.is_none() && max_stream_buffered_bytes.is_none() {
+      return None;
+    }
+    Some(Arc::new(Self {
+      in_flight,
+      queue_timeout: Duration::from_millis(
+        http2_settings["responseQueueTimeout"]
+          .as_i64()
+          .map(|timeout| timeout as u64)
+          .unwrap_or(DEFAULT_RESPONSE_QUEUE_TIMEOUT),
+      ),
+      max_stream_buffered_bytes,
+    }))
+  }
+
+  // The maximum number of the bytes buffered for a stream
+  pub fn max_stream_buffered_bytes(&self) -> Option<usize> {
+    self.max_stream_buffered_bytes
+  }
+
+  // Wait until the number of the responses in flight on the connection is below the limit. The responses above the limit
+  // wait in the arrival order, and fail after the queue timeout. The wait is cancelled when the request is dropped.
+  pub async fn acquire_response_slot(
+    &self,
+  ) -> Result<Option<OwnedSemaphorePermit>, ResponseQueueTimeout> {
+    let in_flight = match &self.in_flight {
+      Some(in_flight) => in_flight.clone(),
+      None => return Ok(None),
+    };
+    if let Ok(permit) = in_flight.clone().try_acquire_owned() {
+      return Ok(Some(permit));
+    }
+    METRICS.increment_counter("ferron_queued_responses_total", &[]);
+    match tokio::time::timeout(self.queue_timeout, in_flight.acquire_owned()).await {
+      Ok(Ok(permit)) => Ok(Some(permit)),
+      _ => {
+        METRICS.increment_counter("ferron_response_queue_timeouts_total", &[]);
+        Err(ResponseQueueTimeout)
+      }
+    }
+  }
+
+  // Wrap the response body, so that it's sent in chunks taking turns with the other response bodies on the connection
+  pub fn fair_body(self: &Arc<Self>, body: BoxBody<Bytes, io::Error>) -> BoxBody<Bytes, io::Error> {
+    match self.max_stream_buffered_bytes {
+      Some(max_chunk_size) => FairBody {
+        inner: body,
+        max_chunk_size: max_chunk_size.max(1),
+        pending: None,
+      }
+      .boxed(),
+      None => body,
+    }
+  }
+}
+
+// A response body sending at most the per-stream buffered bytes limit at once. The HTTP/2 connection polls the body
+// for the next chunk only after the previous chunk is sent, and the streams are sent in turns by the connection.
+struct FairBody {
+  inner: BoxBody<Bytes, io::Error>,
+  max_chunk_size: usize,
+  pending: Option<Bytes>,
+}
+
+impl Body for FairBody {
+  type Data = Bytes;
+  type Error = io::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if this.pending.is_none() {
+      match Pin::new(&mut this.inner).poll_frame(cx) {
+        Poll::Ready(Some(Ok(frame))) => match frame.into_data() {
+          Ok(data) if data.is_empty() => return Poll::Ready(Some(Ok(Frame::data(data)))),
+          Ok(data) => this.pending = Some(data),
+          Err(frame) => return Poll::Ready(Some(Ok(frame))),
+        },
+        other => return other,
+      }
+    }
+
+    let mut data = this.pending.take().unwrap_or_default();
+    if data.len() > this.max_chunk_size {
+      this.pending = Some(data.split_off(this.max_chunk_size));
+    }
+    Poll::Ready(Some(Ok(Frame::data(data))))
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.pending.is_none() && self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    let mut size_hint = self.inner.size_hint();
+    if let Some(pending) = &self.pending {
+      let pending_length = pending.len() as u64;
+      size_hint.set_lower(size_hint.lower() + pending_length);
+      if let Some(upper) = size_hint.upper() {
+        size_hint.set_upper(upper + pending_length);
+      }
+    }
+    size_hint
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{Empty, Full};
+  use hyper::body::Incoming;
+  use hyper::service::service_fn;
+  use hyper::{Request, Response};
+  use hyper_util::rt::{TokioExecutor, TokioIo};
+  use std::convert::Infallible;
+  use yaml_rust2::YamlLoader;
+
+  fn fairness(settings: &str) -> Arc<ResponseFairness> {
+    ResponseFairness::from_http2_settings(&YamlLoader::load_from_str(settings).unwrap()[0]).unwrap()
+  }
+
+  #[test]
+  fn test_no_fairness_controls() {
+    assert!(ResponseFairness::from_http2_settings(&Yaml::BadValue).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_response_queue_timeout() {
+    let fairness = fairness("maxInFlightResponses: 1\nresponseQueueTimeout: 50");
+    let permit = fairness.acquire_response_slot().await.unwrap();
+    assert!(permit.is_some());
+    assert!(fairness.acquire_response_slot().await.is_err());
+    drop(permit);
+    assert!(fairness.acquire_response_slot().await.unwrap().is_some());
+  }
+
+  #[tokio::test]
+  async fn test_round_robin_chunks() {
+    let fairness = fairness("maxSendBufferSize: 4096");
+    let sent_chunks = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let service = {
+      let sent_chunks = sent_chunks.clone();
+      service_fn(move |request: Request<Incoming>| {
+        let stream_name = if request.uri().path() == "/a" {
+          'A'
+        } else {
+          'B'
+        };
+        let sent_chunks = sent_chunks.clone();
+        let body = Full::new(Bytes::from(vec![stream_name as u8; 262144]))
+          .map_err(|e| match e {})
+          .boxed();
+        let body = fairness
+          .fair_body(body)
+          .map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+              sent_chunks.lock().unwrap().push((stream_name, data.len()));
+            }
+            frame
+          })
+          .boxed();
+        async move { Ok::<_, Infallible>(Response::new(body)) }
+      })
+    };
+
+    // Two heavy streams are sent concurrently on a real HTTP/2 connection
+    let (client_io, server_io) = tokio::io::duplex(16384);
+    let mut server_builder = hyper::server::conn::http2::Builder::new(TokioExecutor::new());
+    server_builder.max_send_buf_size(4096);
+    tokio::spawn(server_builder.serve_connection(TokioIo::new(server_io), service));
+    let (sender, connection) =
+      hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(client_io))
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    let receive = |path: &'static str| {
+      let mut sender = sender.clone();
+      async move {
+        let request = Request::builder()
+          .uri(format!("http://localhost{}", path))
+          .body(Empty::<Bytes>::new())
+          .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        response.into_body().collect().await.unwrap().to_bytes()
+      }
+    };
+    let (first_body, second_body) = tokio::join!(receive("/a"), receive("/b"));
+    assert_eq!(first_body, Bytes::from(vec![b'A'; 262144]));
+    assert_eq!(second_body, Bytes::from(vec![b'B'; 262144]));
+
+    // The streams take turns, sending at most the per-stream limit at once
+    let sent_chunks = sent_chunks.lock().unwrap();
+    assert!(sent_chunks.iter().all(|(_, length)| *length <= 4096));
+    let first_chunk = |name| {
+      sent_chunks
+        .iter()
+        .position(|(stream_name, _)| *stream_name == name)
+    };
+    let last_chunk = |name| {
+      sent_chunks
+        .iter()
+        .rposition(|(stream_name, _)| *stream_name == name)
+    };
+    assert!(first_chunk('B') < last_chunk('A'));
+    assert!(first_chunk('A') < last_chunk('B'));
+  }
+}
@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct RetryBudgetWindow {
+  start: Instant,
+  requests: u64,
+  retries: u64,
+}
+
+// Limits the retries to a percentage of the requests, so that the retries don't multiply the load
+// on the backend servers when most of the requests fail. A minimum number of retries is always allowed in each window,
+// so that the requests can be retried when there is little traffic.
+pub struct RetryBudget {
+  percent: u64,
+  min_retries: u64,
+  window: Duration,
+  state: Mutex<RetryBudgetWindow>,
+}
+
+impl RetryBudget {
+  pub fn new(percent: u64, min_retries: u64, window: Duration) -> Self {
+    Self {
+      percent,
+      min_retries,
+      window,
+      state: Mutex::new(RetryBudgetWindow {
+        start: Instant::now(),
+        requests: 0,
+        retries: 0,
+      }),
+    }
+  }
+
+  // Record a request, which counts towards the retry budget
+  pub fn record_request(&self) {
+    if let Ok(mut state) = self.state.lock() {
+      self.reset_expired(&mut state);
+      state.requests += 1;
+    }
+  }
+
+  // Try to spend a retry from the budget. Returns false, if the budget is exhausted.
+  pub fn try_retry(&self) -> bool {
+    let Ok(mut state) = self.state.lock() else {
+      return false;
+    };
+    self.reset_expired(&mut state);
+    if state.retries < self.min_retries
+      || state.retries.saturating_mul(100) < state.requests.saturating_mul(self.percent)
+    {
+      state.retries += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  // Start a new window, if the current one has ended
+  fn reset_expired(&self, state: &mut RetryBudgetWindow) {
+    if state.start.elapsed() >= self.window {
+      state.start = Instant::now();
+      state.requests = 0;
+      state.retries = 0;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_min_retries() {
+    let budget = RetryBudget::new(0, 2, Duration::from_secs(60));
+    assert!(budget.try_retry());
+    assert!(budget.try_retry());
+    assert!(!budget.try_retry());
+  }
+
+  #[test]
+  fn test_retry_percent() {
+    let budget = RetryBudget::new(20, 0, Duration::from_secs(60));
+    assert!(!budget.try_retry());
+    for _ in 0..10 {
+      budget.record_request();
+    }
+    assert!(budget.try_retry());
+    assert!(budget.try_retry());
+    assert!(!budget.try_retry());
+    for _ in 0..5 {
+      budget.record_request();
+    }
+    assert!(budget.try_retry());
+    assert!(!budget.try_retry());
+  }
+
+  #[test]
+  fn test_window_reset() {
+    let budget = RetryBudget::new(0, 1, Duration::from_millis(50));
+    assert!(budget.try_retry());
+    assert!(!budget.try_retry());
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(budget.try_retry());
+  }
+}
@@ -1,46 +1,114 @@
 use crate::ferron_util::match_hostname::match_hostname;
-use rustls::{server::ResolvesServerCert, sign::CertifiedKey};
+use rustls::{server::ResolvesServerCert, sign::CertifiedKey, SignatureAlgorithm, SignatureScheme};
 use std::{collections::HashMap, sync::Arc};
 
 #[derive(Debug)]
 pub struct CustomSniResolver {
-  fallback_cert_key: Option<Arc<CertifiedKey>>,
-  cert_keys: HashMap<String, Arc<CertifiedKey>>,
+  fallback_cert_keys: Vec<Arc<CertifiedKey>>,
+  cert_keys: HashMap<String, Vec<Arc<CertifiedKey>>>,
 }
 
 impl CustomSniResolver {
   pub fn new() -> Self {
     CustomSniResolver {
-      fallback_cert_key: None,
+      fallback_cert_keys: Vec::new(),
       cert_keys: HashMap::new(),
     }
   }
 
+  // Add a fallback certificate. Multiple certificates (for example, ECDSA and RSA ones) can be added.
   pub fn load_fallback_cert_key(&mut self, fallback_cert_key: Arc<CertifiedKey>) {
-    self.fallback_cert_key = Some(fallback_cert_key);
+    self.fallback_cert_keys.push(fallback_cert_key);
   }
 
+  // Add a certificate for the host. Multiple certificates (for example, ECDSA and RSA ones) can be added.
   pub fn load_host_cert_key(&mut self, host: &str, cert_key: Arc<CertifiedKey>) {
-    self.cert_keys.insert(String::from(host), cert_key);
+    self
+      .cert_keys
+      .entry(String::from(host))
+      .or_default()
+      .push(cert_key);
   }
 }
 
+// Select the certificate with the key the client can verify signatures of.
+// The non-RSA (for example, ECDSA) certificates are preferred, because of the smaller and faster handshakes,
+// while the RSA certificates are used for older clients. If the client supports none, the first certificate is used.
+fn select_cert_key(
+  cert_keys: &[Arc<CertifiedKey>],
+  signature_schemes: &[SignatureScheme],
+) -> Option<Arc<CertifiedKey>> {
+  let is_supported =
+    |cert_key: &&Arc<CertifiedKey>| cert_key.key.choose_scheme(signature_schemes).is_some();
+  cert_keys
+    .iter()
+    .filter(is_supported)
+    .find(|cert_key| cert_key.key.algorithm() != SignatureAlgorithm::RSA)
+    .or_else(|| cert_keys.iter().find(is_supported))
+    .or(cert_keys.first())
+    .cloned()
+}
+
 impl ResolvesServerCert for CustomSniResolver {
   fn resolve(
     &self,
     client_hello: rustls::server::ClientHello<'_>,
   ) -> Option<Arc<rustls::sign::CertifiedKey>> {
     let hostname = client_hello.server_name();
+    let signature_schemes = client_hello.signature_schemes();
     if let Some(hostname) = hostname {
       let keys_iterator = self.cert_keys.keys();
       for configured_hostname in keys_iterator {
         if match_hostname(Some(configured_hostname), Some(hostname)) {
-          return self.cert_keys.get(configured_hostname).cloned();
+          return self
+            .cert_keys
+            .get(configured_hostname)
+            .and_then(|cert_keys| select_cert_key(cert_keys, signature_schemes));
         }
       }
-      self.fallback_cert_key.clone()
+      select_cert_key(&self.fallback_cert_keys, signature_schemes)
     } else {
-      self.fallback_cert_key.clone()
+      select_cert_key(&self.fallback_cert_keys, signature_schemes)
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ED25519};
+  use rustls::crypto::ring::sign::any_supported_type;
+  use rustls_pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+  fn cert_key(key: &KeyPair) -> Arc<CertifiedKey> {
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    Arc::new(CertifiedKey::new(
+      Vec::new(),
+      any_supported_type(&key_der).unwrap(),
+    ))
+  }
+
+  #[test]
+  fn test_select_cert_key() {
+    let ed25519 = cert_key(&KeyPair::generate_for(&PKCS_ED25519).unwrap());
+    let ecdsa = cert_key(&KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap());
+    let cert_keys = vec![ed25519.clone(), ecdsa.clone()];
+
+    let selected = select_cert_key(&cert_keys, &[SignatureScheme::ECDSA_NISTP256_SHA256]).unwrap();
+    assert!(Arc::ptr_eq(&selected, &ecdsa));
+    let selected = select_cert_key(
+      &cert_keys,
+      &[
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ED25519,
+      ],
+    )
+    .unwrap();
+    assert!(Arc::ptr_eq(&selected, &ed25519));
+
+    // The client doesn't support any of the keys, so the first certificate is used
+    let selected = select_cert_key(&cert_keys, &[SignatureScheme::RSA_PSS_SHA256]).unwrap();
+    assert!(Arc::ptr_eq(&selected, &ed25519));
+    assert!(select_cert_key(&[], &[SignatureScheme::ED25519]).is_none());
+  }
+}
@@ -0,0 +1,159 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+use ferron_common::ServerConfigRoot;
+
+use crate::ferron_util::dns_resolver::lookup_host;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+
+// The networks the outbound connections aren't allowed to, unless they're allowed with the "ssrfAllowedNetworks" property.
+// These are the private, loopback, link-local (including the cloud metadata endpoints), multicast and other special-purpose networks.
+// The NAT64 and 6to4 networks are blocked too, since their addresses embed IPv4 addresses, which can be private.
+const BLOCKED_NETWORKS: [&str; 19] = [
+  "0.0.0.0/8",
+  "10.0.0.0/8",
+  "100.64.0.0/10",
+  "127.0.0.0/8",
+  "169.254.0.0/16",
+  "172.16.0.0/12",
+  "192.0.0.0/24",
+  "192.168.0.0/16",
+  "198.18.0.0/15",
+  "224.0.0.0/4",
+  "240.0.0.0/4",
+  "::/128",
+  "::1/128",
+  "64:ff9b::/96",
+  "64:ff9b:1::/48",
+  "2002::/16",
+  "fc00::/7",
+  "fe80::/10",
+  "ff00::/8",
+];
+
+static BLOCKED_NETWORKS_TRIE: LazyLock<IpPrefixTrie> = LazyLock::new(|| {
+  let mut blocked_networks = IpPrefixTrie::new();
+  for network in BLOCKED_NETWORKS {
+    blocked_networks.insert_cidr(network);
+  }
+  blocked_networks
+});
+
+// A guard against the server-side request forgery, which validates the addresses of the outbound connections
+// made on behalf of the clients (the forward proxy) or to the configured services (the forwarded authentication)
+pub struct SsrfGuard {
+  allowed_networks: IpPrefixTrie,
+}
+
+impl SsrfGuard {
+  // Create a guard from the "ssrfProtection" and "ssrfAllowedNetworks" properties. The guard is enabled by default.
+  pub fn from_config(config: &ServerConfigRoot) -> Option<Self> {
+    if config.get("ssrfProtection").as_bool() == Some(false) {
+      return None;
+    }
+    let mut allowed_networks = IpPrefixTrie::new();
+    if let Some(networks) = config.get("ssrfAllowedNetworks").as_vec() {
+      for network in networks.iter().filter_map(|network| network.as_str()) {
+        allowed_networks.insert_cidr(network);
+      }
+    }
+    Some(Self { allowed_networks })
+  }
+
+  // Check if the connections to an IP address are allowed
+  pub fn is_allowed(&self, ip: IpAddr) -> bool {
+    !BLOCKED_NETWORKS_TRIE.contains(ip) || self.allowed_networks.contains(ip)
+  }
+
+  // Resolve the host name (or parse the IP literal) and validate the address before connecting to it, so that
+  // the address connected to is the validated one. The "PermissionDenied" error is returned for the blocked addresses.
+  pub async fn resolve(&self, host: &str, port: u16) -> io::Result<SocketAddr> {
+    let ip_literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses = match ip_literal.parse::<IpAddr>() {
+      Ok(ip) => vec![SocketAddr::new(ip, port)],
+      Err(_) => lookup_host(host, port).await?,
+    };
+    if addresses.is_empty() {
+      return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+    addresses
+      .iter()
+      .find(|address| self.is_allowed(address.ip()))
+      .copied()
+      .ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::PermissionDenied,
+          format!(
+            "The connection to \"{}\" ({}) is blocked by the SSRF protection",
+            host,
+            addresses[0].ip()
+          ),
+        )
+      })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn guard(allowed_networks: &[&str]) -> SsrfGuard {
+    let mut allowed_networks_trie = IpPrefixTrie::new();
+    for network in allowed_networks {
+      allowed_networks_trie.insert_cidr(network);
+    }
+    SsrfGuard {
+      allowed_networks: allowed_networks_trie,
+    }
+  }
+
+  #[test]
+  fn test_blocked_addresses() {
+    let guard = guard(&[]);
+    for ip in [
+      "10.1.2.3",
+      "127.0.0.1",
+      "169.254.169.254",
+      "172.31.0.1",
+      "192.168.1.1",
+      "::1",
+      "::ffff:192.168.1.1",
+      "fd00:ec2::254",
+      "fe80::1",
+      "224.0.0.251",
+      "239.255.255.250",
+      "64:ff9b::a9fe:a9fe",
+      "64:ff9b:1::a00:1",
+      "2002:a9fe:a9fe::1",
+      "ff02::1",
+    ] {
+      assert!(!guard.is_allowed(ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in ["93.184.216.34", "172.32.0.1", "2606:4700::1111"] {
+      assert!(guard.is_allowed(ip.parse().unwrap()), "{}", ip);
+    }
+  }
+
+  #[test]
+  fn test_allowed_networks() {
+    let guard = guard(&["10.0.0.0/24", "::1"]);
+    assert!(guard.is_allowed("10.0.0.5".parse().unwrap()));
+    assert!(guard.is_allowed("::1".parse().unwrap()));
+    assert!(!guard.is_allowed("10.0.1.5".parse().unwrap()));
+    assert!(!guard.is_allowed("127.0.0.1".parse().unwrap()));
+  }
+
+  #[tokio::test]
+  async fn test_resolve_ip_literal() {
+    let guard = guard(&[]);
+    assert_eq!(
+      guard.resolve("[2606:4700::1111]", 443).await.unwrap(),
+      "[2606:4700::1111]:443".parse().unwrap()
+    );
+    assert_eq!(
+      guard.resolve("127.0.0.1", 80).await.unwrap_err().kind(),
+      io::ErrorKind::PermissionDenied
+    );
+  }
+}
@@ -0,0 +1,794 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use yaml_rust2::Yaml;
+
+// The process-wide storage of the cache, the rate limits, and the temporary bans. The storage is in-memory by default,
+// and can be persisted on the disk or shared between the servers through Redis (the "storageBackend" global property).
+pub static STORAGE: LazyLock<Storage> = LazyLock::new(Storage::new);
+
+// How often the expired entries are removed from the in-memory storage
+const MEMORY_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+// The default address of the Redis server
+const DEFAULT_REDIS_ADDRESS: &str = "127.0.0.1:6379";
+
+// The maximum time of connecting to the Redis server and of executing a single command
+const REDIS_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The number of the keys requested from the Redis server at once while scanning the keys
+const REDIS_SCAN_COUNT: &str = "1000";
+
+type StorageResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// A key-value storage backend. The stored values can expire after their time to live.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+  // Get the value stored under the key, if the value exists and hasn't expired
+  async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>>;
+
+  // Store the value under the key. The value expires after the time to live, if it's specified.
+  async fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> StorageResult<()>;
+
+  // Delete the value stored under the key
+  async fn delete(&self, key: &str) -> StorageResult<()>;
+
+  // Get the keys and the values of the unexpired entries with the keys starting with the prefix
+  async fn scan(&self, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>>;
+
+  // Increment the counter stored under the key and return its new value.
+  // A new counter expires after the time to live. The counters are stored as decimal numbers.
+  async fn increment(&self, key: &str, ttl: Duration) -> StorageResult<u64>;
+
+  // Check if the stored entries outlive the server process, or are shared with other servers
+  fn is_shared(&self) -> bool;
+}
+
+// The process-wide storage, which delegates to the configured backend
+pub struct Storage {
+  backend: RwLock<Arc<dyn StorageBackend>>,
+}
+
+impl Storage {
+  pub fn new() -> Self {
+    Self {
+      backend: RwLock::new(Arc::new(MemoryStorage::new())),
+    }
+  }
+
+  // Replace the storage backend. The entries stored in the previous backend aren't moved to the new one.
+  pub fn configure(&self, backend: Arc<dyn StorageBackend>) {
+    match self.backend.write() {
+      Ok(mut current_backend) => *current_backend = backend,
+      Err(err) => *err.into_inner() = backend,
+    }
+  }
+
+  fn backend(&self) -> Arc<dyn StorageBackend> {
+    match self.backend.read() {
+      Ok(backend) => backend.clone(),
+      Err(err) => err.into_inner().clone(),
+    }
+  }
+}
+
+#[async_trait]
+impl StorageBackend for Storage {
+  async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+    self.backend().get(key).await
+  }
+
+  async fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> StorageResult<()> {
+    self.backend().set(key, value, ttl).await
+  }
+
+  async fn delete(&self, key: &str) -> StorageResult<()> {
+    self.backend().delete(key).await
+  }
+
+  async fn scan(&self, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>> {
+    self.backend().scan(prefix).await
+  }
+
+  async fn increment(&self, key: &str, ttl: Duration) -> StorageResult<u64> {
+    self.backend().increment(key, ttl).await
+  }
+
+  fn is_shared(&self) -> bool {
+    self.backend().is_shared()
+  }
+}
+
+// Create the storage backend from the global configuration
+pub fn create_storage_backend(global_config: &Yaml) -> StorageResult<Arc<dyn StorageBackend>> {
+  match global_config["storageBackend"].as_str() {
+    None | Some("memory") => Ok(Arc::new(MemoryStorage::new())),
+    Some("disk") => match global_config["storageDirectory"].as_str() {
+      Some(directory) => Ok(Arc::new(DiskStorage::new(Path::new(directory))?)),
+      None => Err(anyhow::anyhow!(
+        "The storage directory must be specified for the disk storage"
+      ))?,
+    },
+    Some("redis") => Ok(Arc::new(RedisStorage::new(
+      global_config["storageRedisAddress"]
+        .as_str()
+        .unwrap_or(DEFAULT_REDIS_ADDRESS),
+      global_config["storageRedisPassword"].as_str(),
+      global_config["storageRedisDatabase"].as_i64(),
+    ))),
+    Some(backend) => Err(anyhow::anyhow!("Unsupported storage backend: {}", backend))?,
+  }
+}
+
+// Get the current time as the number of milliseconds since the Unix epoch
+pub fn unix_time_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_millis() as u64)
+    .unwrap_or(0)
+}
+
+// Parse the counter stored as a decimal number
+fn parse_counter(value: &[u8]) -> StorageResult<u64> {
+  match std::str::from_utf8(value)
+    .ok()
+    .and_then(|value| value.parse::<u64>().ok())
+  {
+    Some(counter) => Ok(counter),
+    None => Err(anyhow::anyhow!("The stored value isn't a counter"))?,
+  }
+}
+
+// The in-memory entries, with their expiration times
+struct MemoryEntries {
+  entries: HashMap<String, (Vec<u8>, Option<Instant>)>,
+  next_cleanup: Instant,
+}
+
+impl MemoryEntries {
+  // Remove the expired entries, so that the map doesn't grow indefinitely
+  fn cleanup(&mut self, now: Instant) {
+    if now >= self.next_cleanup {
+      self
+        .entries
+        .retain(|_, (_, expires_at)| is_live(*expires_at, now));
+      self.next_cleanup = now + MEMORY_CLEANUP_INTERVAL;
+    }
+  }
+}
+
+fn is_live(expires_at: Option<Instant>, now: Instant) -> bool {
+  expires_at.is_none_or(|expires_at| expires_at > now)
+}
+
+// The storage backend keeping the entries in the memory of the server process
+pub struct MemoryStorage {
+  entries: Mutex<MemoryEntries>,
+}
+
+impl MemoryStorage {
+  pub fn new() -> Self {
+    Self {
+      entries: Mutex::new(MemoryEntries {
+        entries: HashMap::new(),
+        next_cleanup: Instant::now() + MEMORY_CLEANUP_INTERVAL,
+      }),
+    }
+  }
+
+  fn lock(&self) -> StorageResult<MutexGuard<'_, MemoryEntries>> {
+    match self.entries.lock() {
+      Ok(entries) => Ok(entries),
+      Err(_) => Err(anyhow::anyhow!("The in-memory storage is poisoned"))?,
+    }
+  }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+  async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+    let now = Instant::now();
+    Ok(
+      self
+        .lock()?
+        .entries
+        .get(key)
+        .filter(|(_, expires_at)| is_live(*expires_at, now))
+        .map(|(value, _)| value.clone()),
+    )
+  }
+
+  async fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> StorageResult<()> {
+    let now = Instant::now();
+    let mut entries = self.lock()?;
+    entries.cleanup(now);
+    entries
+      .entries
+      .insert(key.to_string(), (value.to_vec(), ttl.map(|ttl| now + ttl)));
+    Ok(())
+  }
+
+  async fn delete(&self, key: &str) -> StorageResult<()> {
+    self.lock()?.entries.remove(key);
+    Ok(())
+  }
+
+  async fn scan(&self, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>> {
+    let now = Instant::now();
+    Ok(
+      self
+        .lock()?
+        .entries
+        .iter()
+        .filter(|(key, (_, expires_at))| key.starts_with(prefix) && is_live(*expires_at, now))
+        .map(|(key, (value, _))| (key.clone(), value.clone()))
+        .collect(),
+    )
+  }
+
+  async fn increment(&self, key: &str, ttl: Duration) -> StorageResult<u64> {
+    let now = Instant::now();
+    let mut entries = self.lock()?;
+    entries.cleanup(now);
+    let (counter, expires_at) = match entries.entries.get(key) {
+      Some((value, expires_at)) if is_live(*expires_at, now) => {
+        (parse_counter(value)? + 1, *expires_at)
+      }
+      _ => (1, Some(now + ttl)),
+    };
+    entries.entries.insert(
+      key.to_string(),
+      (counter.to_string().into_bytes(), expires_at),
+    );
+    Ok(counter)
+  }
+
+  fn is_shared(&self) -> bool {
+    false
+  }
+}
+
+// The counter used to name the files being written, so that the concurrent writes don't overwrite each other's files
+static DISK_WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// The storage backend keeping the entries in the files in a directory, so that the entries survive the server restarts.
+// Each entry is stored in a file named after the SHA-256 hash of the key. The file contains the expiration time
+// (in milliseconds since the Unix epoch, or zero if the entry doesn't expire), the length of the key, the key, and the value.
+pub struct DiskStorage {
+  directory: PathBuf,
+  // The counters are incremented one at a time, since incrementing a counter takes reading and writing its file
+  increment_lock: tokio::sync::Mutex<()>,
+}
+
+impl DiskStorage {
+  pub fn new(directory: &Path) -> StorageResult<Self> {
+    std::fs::create_dir_all(directory)?;
+    Ok(Self {
+      directory: directory.to_path_buf(),
+      increment_lock: tokio::sync::Mutex::new(()),
+    })
+  }
+
+  fn entry_path(&self, key: &str) -> PathBuf {
+    let key_hash = Sha256::digest(key.as_bytes())
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect::<String>();
+    self.directory.join(key_hash)
+  }
+
+  // Read the entry from the file. The expired entries are deleted.
+  async fn read_entry(&self, path: &Path) -> StorageResult<Option<(String, Vec<u8>)>> {
+    let contents = match tokio::fs::read(path).await {
+      Ok(contents) => contents,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => Err(err)?,
+    };
+    let (expires_at, key, value) = match parse_disk_entry(&contents) {
+      Some(entry) => entry,
+      None => Err(anyhow::anyhow!(
+        "Invalid storage entry file: {}",
+        path.display()
+      ))?,
+    };
+    if expires_at != 0 && expires_at <= unix_time_millis() {
+      match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err)?,
+        _ => (),
+      }
+      return Ok(None);
+    }
+    Ok(Some((key, value.to_vec())))
+  }
+
+  // Write the entry to a new file, and move it in place of the previous file, so that the entry is never read half-written
+  async fn write_entry(&self, key: &str, value: &[u8], expires_at: u64) -> StorageResult<()> {
+    let mut contents = Vec::with_capacity(12 + key.len() + value.len());
+    contents.extend_from_slice(&expires_at.to_be_bytes());
+    contents.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    contents.extend_from_slice(key.as_bytes());
+    contents.extend_from_slice(value);
+
+    let path = self.entry_path(key);
+    let temporary_path = self.directory.join(format!(
+      ".{}.{}.tmp",
+      std::process::id(),
+      DISK_WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    tokio::fs::write(&temporary_path, contents).await?;
+    if let Err(err) = tokio::fs::rename(&temporary_path, &path).await {
+      tokio::fs::remove_file(&temporary_path)
+        .await
+        .unwrap_or_default();
+      Err(err)?
+    }
+    Ok(())
+  }
+}
+
+// Parse the contents of the entry file into the expiration time, the key, and the value
+fn parse_disk_entry(contents: &[u8]) -> Option<(u64, String, &[u8])> {
+  let expires_at = u64::from_be_bytes(contents.get(0..8)?.try_into().ok()?);
+  let key_length = u32::from_be_bytes(contents.get(8..12)?.try_into().ok()?) as usize;
+  let key = String::from_utf8(contents.get(12..12 + key_length)?.to_vec()).ok()?;
+  Some((expires_at, key, &contents[12 + key_length..]))
+}
+
+#[async_trait]
+impl StorageBackend for DiskStorage {
+  async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+    Ok(
+      self
+        .read_entry(&self.entry_path(key))
+        .await?
+        .filter(|(entry_key, _)| entry_key == key)
+        .map(|(_, value)| value),
+    )
+  }
+
+  async fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> StorageResult<()> {
+    let expires_at = match ttl {
+      Some(ttl) => unix_time_millis() + (ttl.as_millis() as u64).max(1),
+      None => 0,
+    };
+    self.write_entry(key, value, expires_at).await
+  }
+
+  async fn delete(&self, key: &str) -> StorageResult<()> {
+    match tokio::fs::remove_file(self.entry_path(key)).await {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err)?,
+      _ => Ok(()),
+    }
+  }
+
+  async fn scan(&self, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    let mut directory = tokio::fs::read_dir(&self.directory).await?;
+    while let Some(directory_entry) = directory.next_entry().await? {
+      // Skip the files being written
+      if directory_entry
+        .file_name()
+        .to_string_lossy()
+        .starts_with('.')
+      {
+        continue;
+      }
+      // The invalid entry files don't prevent reading the other entries
+      if let Ok(Some((key, value))) = self.read_entry(&directory_entry.path()).await {
+        if key.starts_with(prefix) {
+          entries.push((key, value));
+        }
+      }
+    }
+    Ok(entries)
+  }
+
+  async fn increment(&self, key: &str, ttl: Duration) -> StorageResult<u64> {
+    let _increment_lock = self.increment_lock.lock().await;
+    let path = self.entry_path(key);
+    let (counter, expires_at) = match tokio::fs::read(&path).await {
+      Ok(contents) => match parse_disk_entry(&contents) {
+        Some((expires_at, entry_key, value))
+          if entry_key == key && (expires_at == 0 || expires_at > unix_time_millis()) =>
+        {
+          (parse_counter(value)? + 1, expires_at)
+        }
+        _ => (1, unix_time_millis() + (ttl.as_millis() as u64).max(1)),
+      },
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+        (1, unix_time_millis() + (ttl.as_millis() as u64).max(1))
+      }
+      Err(err) => Err(err)?,
+    };
+    self
+      .write_entry(key, counter.to_string().as_bytes(), expires_at)
+      .await?;
+    Ok(counter)
+  }
+
+  fn is_shared(&self) -> bool {
+    true
+  }
+}
+
+// A value of the Redis serialization protocol (RESP)
+#[derive(Debug, PartialEq, Eq)]
+enum RespValue {
+  String(Vec<u8>),
+  Error(String),
+  Integer(i64),
+  Array(Vec<RespValue>),
+  Null,
+}
+
+// Encode the command as an array of bulk strings
+fn encode_resp_command(arguments: &[&[u8]]) -> Vec<u8> {
+  let mut command = format!("*{}\r\n", arguments.len()).into_bytes();
+  for argument in arguments {
+    command.extend_from_slice(format!("${}\r\n", argument.len()).as_bytes());
+    command.extend_from_slice(argument);
+    command.extend_from_slice(b"\r\n");
+  }
+  command
+}
+
+// Read a RESP value sent by the Redis server
+fn read_resp_value<'a, R>(
+  reader: &'a mut R,
+) -> Pin<Box<dyn Future<Output = Result<RespValue, std::io::Error>> + Send + 'a>>
+where
+  R: AsyncBufRead + Unpin + Send,
+{
+  Box::pin(async move {
+    let invalid_data = || {
+      std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "Invalid response from Redis",
+      )
+    };
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).await?;
+    if !line.ends_with(b"\r\n") {
+      Err(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "The connection to Redis was closed",
+      ))?
+    }
+    line.truncate(line.len() - 2);
+    let (value_type, value) = match line.split_first() {
+      Some(line) => line,
+      None => Err(invalid_data())?,
+    };
+    let parse_length = || {
+      std::str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .ok_or_else(invalid_data)
+    };
+    match value_type {
+      b'+' => Ok(RespValue::String(value.to_vec())),
+      b'-' => Ok(RespValue::Error(
+        String::from_utf8_lossy(value).into_owned(),
+      )),
+      b':' => Ok(RespValue::Integer(parse_length()?)),
+      b'$' => match parse_length()? {
+        length if length < 0 => Ok(RespValue::Null),
+        length => {
+          let mut string = vec![0u8; length as usize + 2];
+          reader.read_exact(&mut string).await?;
+          if !string.ends_with(b"\r\n") {
+            Err(invalid_data())?
+          }
+          string.truncate(length as usize);
+          Ok(RespValue::String(string))
+        }
+      },
+      b'*' => match parse_length()? {
+        length if length < 0 => Ok(RespValue::Null),
+        length => {
+          let mut array = Vec::with_capacity((length as usize).min(1024));
+          for _ in 0..length {
+            array.push(read_resp_value(reader).await?);
+          }
+          Ok(RespValue::Array(array))
+        }
+      },
+      _ => Err(invalid_data())?,
+    }
+  })
+}
+
+// Escape the glob-style pattern characters, so that the prefix can be used in the "MATCH" option of the "SCAN" command
+fn escape_redis_pattern(prefix: &str) -> String {
+  let mut pattern = String::with_capacity(prefix.len() + 1);
+  for character in prefix.chars() {
+    if matches!(character, '*' | '?' | '[' | ']' | '\\') {
+      pattern.push('\\');
+    }
+    pattern.push(character);
+  }
+  pattern.push('*');
+  pattern
+}
+
+// The storage backend keeping the entries in a Redis server, so that the entries are shared between the servers
+pub struct RedisStorage {
+  address: String,
+  password: Option<String>,
+  database: Option<i64>,
+  connection: tokio::sync::Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisStorage {
+  pub fn new(address: &str, password: Option<&str>, database: Option<i64>) -> Self {
+    Self {
+      address: address.to_string(),
+      password: password.map(|password| password.to_string()),
+      database,
+      connection: tokio::sync::Mutex::new(None),
+    }
+  }
+
+  // Connect to the Redis server, authenticate, and select the database
+  async fn connect(&self) -> Result<BufStream<TcpStream>, std::io::Error> {
+    let mut connection = BufStream::new(TcpStream::connect(&self.address).await?);
+    if let Some(password) = &self.password {
+      exchange_resp_command(&mut connection, &[b"AUTH", password.as_bytes()])
+        .await?
+        .map_err(std::io::Error::other)?;
+    }
+    if let Some(database) = self.database {
+      exchange_resp_command(
+        &mut connection,
+        &[b"SELECT", database.to_string().as_bytes()],
+      )
+      .await?
+      .map_err(std::io::Error::other)?;
+    }
+    Ok(connection)
+  }
+
+  // Execute the command. The connection is reused by the next commands, and it's reestablished once if it was closed.
+  async fn command(&self, arguments: &[&[u8]]) -> StorageResult<RespValue> {
+    let mut connection = self.connection.lock().await;
+    let mut is_reused = connection.is_some();
+    loop {
+      let result = tokio::time::timeout(REDIS_TIMEOUT, async {
+        let stream = match connection.take() {
+          Some(stream) => stream,
+          None => self.connect().await?,
+        };
+        exchange_resp_command(connection.insert(stream), arguments).await
+      })
+      .await
+      .unwrap_or_else(|_| {
+        Err(std::io::Error::new(
+          std::io::ErrorKind::TimedOut,
+          "The Redis command timed out",
+        ))
+      });
+      match result {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(message)) => Err(anyhow::anyhow!("Redis error: {}", message))?,
+        Err(err) => {
+          *connection = None;
+          if !is_reused {
+            Err(anyhow::anyhow!("Cannot communicate with Redis: {}", err))?
+          }
+          is_reused = false;
+        }
+      }
+    }
+  }
+}
+
+// Send the command and read the response. The error responses sent by Redis are returned as the inner errors.
+async fn exchange_resp_command(
+  connection: &mut BufStream<TcpStream>,
+  arguments: &[&[u8]],
+) -> Result<Result<RespValue, String>, std::io::Error> {
+  connection
+    .write_all(&encode_resp_command(arguments))
+    .await?;
+  connection.flush().await?;
+  match read_resp_value(connection).await? {
+    RespValue::Error(message) => Ok(Err(message)),
+    value => Ok(Ok(value)),
+  }
+}
+
+#[async_trait]
+impl StorageBackend for RedisStorage {
+  async fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+    match self.command(&[b"GET", key.as_bytes()]).await? {
+      RespValue::String(value) => Ok(Some(value)),
+      RespValue::Null => Ok(None),
+      _ => Err(anyhow::anyhow!("Unexpected response from Redis"))?,
+    }
+  }
+
+  async fn set(&self, key: &str, value: &[u8], ttl: Option<Duration>) -> StorageResult<()> {
+    match ttl {
+      Some(ttl) => {
+        let ttl_millis = (ttl.as_millis() as u64).max(1).to_string();
+        self
+          .command(&[b"SET", key.as_bytes(), value, b"PX", ttl_millis.as_bytes()])
+          .await?
+      }
+      None => self.command(&[b"SET", key.as_bytes(), value]).await?,
+    };
+    Ok(())
+  }
+
+  async fn delete(&self, key: &str) -> StorageResult<()> {
+    self.command(&[b"DEL", key.as_bytes()]).await?;
+    Ok(())
+  }
+
+  async fn scan(&self, prefix: &str) -> StorageResult<Vec<(String, Vec<u8>)>> {
+    let pattern = escape_redis_pattern(prefix);
+    let mut cursor = b"0".to_vec();
+    let mut entries = Vec::new();
+    loop {
+      let (next_cursor, keys) = match self
+        .command(&[
+          b"SCAN",
+          &cursor,
+          b"MATCH",
+          pattern.as_bytes(),
+          b"COUNT",
+          REDIS_SCAN_COUNT.as_bytes(),
+        ])
+        .await?
+      {
+        RespValue::Array(mut response) if response.len() == 2 => {
+          match (response.remove(0), response.remove(0)) {
+            (RespValue::String(next_cursor), RespValue::Array(keys)) => (next_cursor, keys),
+            _ => Err(anyhow::anyhow!("Unexpected response from Redis"))?,
+          }
+        }
+        _ => Err(anyhow::anyhow!("Unexpected response from Redis"))?,
+      };
+
+      let keys = keys
+        .into_iter()
+        .filter_map(|key| match key {
+          RespValue::String(key) => String::from_utf8(key).ok(),
+          _ => None,
+        })
+        .collect::<Vec<_>>();
+      if !keys.is_empty() {
+        let mut arguments: Vec<&[u8]> = vec![b"MGET"];
+        arguments.extend(keys.iter().map(|key| key.as_bytes()));
+        if let RespValue::Array(values) = self.command(&arguments).await? {
+          // The values of the keys expired since they were scanned are null
+          for (key, value) in keys.into_iter().zip(values) {
+            if let RespValue::String(value) = value {
+              entries.push((key, value));
+            }
+          }
+        }
+      }
+
+      if next_cursor == b"0" {
+        break;
+      }
+      cursor = next_cursor;
+    }
+    Ok(entries)
+  }
+
+  async fn increment(&self, key: &str, ttl: Duration) -> StorageResult<u64> {
+    let counter = match self.command(&[b"INCR", key.as_bytes()]).await? {
+      RespValue::Integer(counter) if counter > 0 => counter as u64,
+      _ => Err(anyhow::anyhow!("Unexpected response from Redis"))?,
+    };
+    if counter == 1 {
+      let ttl_millis = (ttl.as_millis() as u64).max(1).to_string();
+      self
+        .command(&[b"PEXPIRE", key.as_bytes(), ttl_millis.as_bytes()])
+        .await?;
+    }
+    Ok(counter)
+  }
+
+  fn is_shared(&self) -> bool {
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn test_backend(backend: &dyn StorageBackend) {
+    backend.set("ferron:a:1", b"one", None).await.unwrap();
+    backend
+      .set("ferron:a:2", b"two", Some(Duration::from_secs(60)))
+      .await
+      .unwrap();
+    backend
+      .set("ferron:b:1", b"three", Some(Duration::from_millis(10)))
+      .await
+      .unwrap();
+    assert_eq!(
+      backend.get("ferron:a:1").await.unwrap(),
+      Some(b"one".to_vec())
+    );
+    assert_eq!(backend.get("ferron:c").await.unwrap(), None);
+
+    let mut entries = backend.scan("ferron:a:").await.unwrap();
+    entries.sort();
+    assert_eq!(
+      entries,
+      vec![
+        ("ferron:a:1".to_string(), b"one".to_vec()),
+        ("ferron:a:2".to_string(), b"two".to_vec())
+      ]
+    );
+
+    backend.delete("ferron:a:1").await.unwrap();
+    assert_eq!(backend.get("ferron:a:1").await.unwrap(), None);
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(backend.get("ferron:b:1").await.unwrap(), None);
+    assert!(backend.scan("ferron:b:").await.unwrap().is_empty());
+
+    let ttl = Duration::from_millis(10);
+    assert_eq!(backend.increment("ferron:counter", ttl).await.unwrap(), 1);
+    assert_eq!(backend.increment("ferron:counter", ttl).await.unwrap(), 2);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(backend.increment("ferron:counter", ttl).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_memory_storage() {
+    test_backend(&MemoryStorage::new()).await;
+  }
+
+  #[tokio::test]
+  async fn test_disk_storage() {
+    let directory =
+      std::env::temp_dir().join(format!("ferron-storage-test-{}", std::process::id()));
+    test_backend(&DiskStorage::new(&directory).unwrap()).await;
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_resp_protocol() {
+    assert_eq!(
+      encode_resp_command(&[b"GET", b"key"]),
+      b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec()
+    );
+
+    let mut response: &[u8] = b"*2\r\n$1\r\n0\r\n*2\r\n$3\r\na\r\n\r\n$-1\r\n:5\r\n-ERR wrong\r\n";
+    assert_eq!(
+      read_resp_value(&mut response).await.unwrap(),
+      RespValue::Array(vec![
+        RespValue::String(b"0".to_vec()),
+        RespValue::Array(vec![RespValue::String(b"a\r\n".to_vec()), RespValue::Null])
+      ])
+    );
+    assert_eq!(
+      read_resp_value(&mut response).await.unwrap(),
+      RespValue::Integer(5)
+    );
+    assert_eq!(
+      read_resp_value(&mut response).await.unwrap(),
+      RespValue::Error("ERR wrong".to_string())
+    );
+    assert!(read_resp_value(&mut response).await.is_err());
+
+    assert_eq!(escape_redis_pattern("ferron:[a]*"), "ferron:\\[a\\]\\**");
+  }
+}
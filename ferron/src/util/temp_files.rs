@@ -0,0 +1,620 @@
+use std::error::Error;
+use std::io::{self, Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use ferron_common::{
+  BufferedBodyStorage, BufferedRequestBody, RequestBodyBuffer, RequestBodyTooLarge,
+};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+use tokio::sync::Mutex;
+
+use crate::ferron_util::metrics::METRICS;
+
+// The prefix of the names of the temporary files, used to find the files left behind by a crashed server
+const TEMP_FILE_PREFIX: &str = "ferron-";
+
+// The default size of the buffered body kept in memory. Larger bodies are spilled to the temporary files.
+pub const DEFAULT_BODY_MEMORY_LIMIT: usize = 65536;
+
+// The process-wide temporary file manager, used by the modules spilling the buffered bodies to disk
+pub static TEMP_FILES: LazyLock<TempFiles> = LazyLock::new(TempFiles::new);
+
+// The configuration of the temporary files
+#[derive(Clone, Debug, PartialEq)]
+struct TempFilesConfig {
+  directory: PathBuf,
+  max_size: Option<u64>,
+  sync: bool,
+}
+
+// The temporary file manager, which creates the temporary files in a dedicated directory
+// and limits the total size of the temporary files
+pub struct TempFiles {
+  config: RwLock<TempFilesConfig>,
+  used_size: Arc<AtomicU64>,
+}
+
+impl TempFiles {
+  fn new() -> Self {
+    Self {
+      config: RwLock::new(TempFilesConfig {
+        directory: default_directory(),
+        max_size: None,
+        sync: false,
+      }),
+      used_size: Arc::new(AtomicU64::new(0)),
+    }
+  }
+
+  // Configure the directory of the temporary files, the maximum total size of the temporary files (None disables the limit),
+  // and whether the data is flushed to disk before the file is read back. The directory is created if it doesn't exist,
+  // and the temporary files left behind by a crashed server are removed.
+  pub fn configure(
+    &self,
+    directory: Option<&Path>,
+    max_size: Option<u64>,
+    sync: bool,
+  ) -> io::Result<()> {
+    let directory = directory
+      .map(PathBuf::from)
+      .unwrap_or_else(default_directory);
+    prepare_directory(&directory)?;
+    if let Ok(mut config) = self.config.write() {
+      *config = TempFilesConfig {
+        directory,
+        max_size,
+        sync,
+      };
+    }
+    Ok(())
+  }
+
+  // Create a temporary file. The file is removed from the directory right after it's created (or it's never linked into
+  // the directory, if O_TMPFILE is supported), so it's deleted by the operating system when it's closed, even if
+  // the server crashes. The purpose (for example, "cache") is used as the label of the metrics.
+  pub async fn create(&self, purpose: &'static str) -> io::Result<TempFile> {
+    let config = self
+      .config
+      .read()
+      .map_err(|_| io::Error::other("The temporary file configuration is unavailable"))?
+      .clone();
+    // The directory is created if it doesn't exist, for example, if the server didn't configure the temporary files
+    let file = match create_unlinked_file(&config.directory).await {
+      Err(err) if err.kind() == io::ErrorKind::NotFound => {
+        create_directory(&config.directory)?;
+        create_unlinked_file(&config.directory).await?
+      }
+      result => result?,
+    };
+    METRICS.increment_counter("ferron_temp_files_created_total", &[("purpose", purpose)]);
+    METRICS.add_to_gauge("ferron_temp_files", &[("purpose", purpose)], 1);
+    Ok(TempFile {
+      file,
+      size: 0,
+      used_size: self.used_size.clone(),
+      max_size: config.max_size,
+      sync: config.sync,
+      purpose,
+    })
+  }
+}
+
+// Get the default directory of the temporary files. The runtime directory passed by systemd is preferred, if it's owned
+// by the server's user. Otherwise, each user has a separate directory in the shared temporary directory.
+fn default_directory() -> PathBuf {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    let runtime_directory = std::env::var_os("RUNTIME_DIRECTORY")
+      .and_then(|runtime_directories| std::env::split_paths(&runtime_directories).next());
+    if let Some(runtime_directory) = runtime_directory {
+      if std::fs::symlink_metadata(&runtime_directory)
+        .is_ok_and(|metadata| metadata.is_dir() && metadata.uid() == unsafe { libc::geteuid() })
+      {
+        return runtime_directory.join("temp");
+      }
+    }
+    std::env::temp_dir().join(format!("ferron-{}", unsafe { libc::geteuid() }))
+  }
+  #[cfg(not(unix))]
+  std::env::temp_dir().join("ferron")
+}
+
+// Create the directory of the temporary files, accessible only by the server's user,
+// and remove the temporary files left behind by a crashed server
+fn prepare_directory(directory: &Path) -> io::Result<()> {
+  create_directory(directory)?;
+  for entry in std::fs::read_dir(directory)? {
+    let entry = entry?;
+    if entry
+      .file_name()
+      .to_str()
+      .is_some_and(|file_name| file_name.starts_with(TEMP_FILE_PREFIX))
+      && entry.file_type().is_ok_and(|file_type| file_type.is_file())
+    {
+      std::fs::remove_file(entry.path()).unwrap_or_default();
+    }
+  }
+  Ok(())
+}
+
+// Create the directory of the temporary files (and its parents), if it doesn't exist, and make it accessible only by
+// the server's user. A symbolic link or a directory owned by another user is refused, since the directory can be
+// in a location writable by other users.
+fn create_directory(directory: &Path) -> io::Result<()> {
+  let mut directory_builder = std::fs::DirBuilder::new();
+  directory_builder.recursive(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::DirBuilderExt;
+    directory_builder.mode(0o700);
+  }
+  directory_builder.create(directory)?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let metadata = std::fs::symlink_metadata(directory)?;
+    if metadata.file_type().is_symlink() {
+      Err(io::Error::other(format!(
+        "The temporary file directory \"{}\" is a symbolic link",
+        directory.display()
+      )))?
+    } else if !metadata.is_dir() {
+      Err(io::Error::other(format!(
+        "The temporary file directory \"{}\" isn't a directory",
+        directory.display()
+      )))?
+    } else if metadata.uid() != unsafe { libc::geteuid() } {
+      Err(io::Error::other(format!(
+        "The temporary file directory \"{}\" isn't owned by the server's user",
+        directory.display()
+      )))?
+    }
+    std::fs::set_permissions(directory, std::fs::Permissions::from_mode(0o700))?;
+  }
+  Ok(())
+}
+
+// Create an anonymous file in the directory, using O_TMPFILE if the file system supports it
+#[cfg(target_os = "linux")]
+async fn create_unlinked_file(directory: &Path) -> io::Result<File> {
+  let mut open_options = tokio::fs::OpenOptions::new();
+  open_options
+    .read(true)
+    .write(true)
+    .mode(0o600)
+    .custom_flags(libc::O_TMPFILE | libc::O_EXCL);
+  match open_options.open(directory).await {
+    Ok(file) => Ok(file),
+    Err(_) => create_named_file(directory).await,
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn create_unlinked_file(directory: &Path) -> io::Result<File> {
+  create_named_file(directory).await
+}
+
+// Create a file with a random name, and unlink it, so that only the server can access it through the open file.
+// On Windows, the open file can't be unlinked, so it's deleted when it's closed.
+async fn create_named_file(directory: &Path) -> io::Result<File> {
+  let path = directory.join(format!(
+    "{}{}-{:016x}.tmp",
+    TEMP_FILE_PREFIX,
+    std::process::id(),
+    rand::random::<u64>()
+  ));
+  let mut open_options = tokio::fs::OpenOptions::new();
+  open_options.read(true).write(true).create_new(true);
+  #[cfg(unix)]
+  open_options.mode(0o600);
+  #[cfg(windows)]
+  {
+    // FILE_FLAG_DELETE_ON_CLOSE
+    open_options.custom_flags(0x04000000);
+  }
+  let file = open_options.open(&path).await?;
+  #[cfg(unix)]
+  tokio::fs::remove_file(&path).await?;
+  Ok(file)
+}
+
+// A temporary file, which counts towards the total size of the temporary files until it's dropped.
+// The data is written with "write_all", and after "finish" is called, the file can be read from the start.
+pub struct TempFile {
+  file: File,
+  size: u64,
+  used_size: Arc<AtomicU64>,
+  max_size: Option<u64>,
+  sync: bool,
+  purpose: &'static str,
+}
+
+impl TempFile {
+  // Append the data to the file. Fails without writing anything if the total size of the temporary files would exceed the limit.
+  pub async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+    let length = data.len() as u64;
+    let max_size = self.max_size;
+    if self
+      .used_size
+      .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used_size| {
+        let new_used_size = used_size.checked_add(length)?;
+        match max_size {
+          Some(max_size) if new_used_size > max_size => None,
+          _ => Some(new_used_size),
+        }
+      })
+      .is_err()
+    {
+      METRICS.increment_counter(
+        "ferron_temp_files_quota_exceeded_total",
+        &[("purpose", self.purpose)],
+      );
+      Err(io::Error::other(
+        "The maximum total size of the temporary files is exceeded",
+      ))?
+    }
+    self.size += length;
+    METRICS.add_to_gauge(
+      "ferron_temp_files_bytes",
+      &[("purpose", self.purpose)],
+      length as i64,
+    );
+    self.file.write_all(data).await
+  }
+
+  // Finish writing the file, flush it to disk if configured, and rewind it to the start
+  pub async fn finish(&mut self) -> io::Result<()> {
+    self.file.flush().await?;
+    if self.sync {
+      self.file.sync_data().await?;
+    }
+    self.file.seek(SeekFrom::Start(0)).await?;
+    Ok(())
+  }
+
+  // Get the size of the data written to the file
+  pub fn size(&self) -> u64 {
+    self.size
+  }
+
+  // Read a part of the finished file. The part is shorter than the requested length only at the end of the file.
+  pub async fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Bytes> {
+    self.file.seek(SeekFrom::Start(offset)).await?;
+    let mut buffer = vec![0u8; length];
+    let mut read_length = 0;
+    while read_length < length {
+      match self.file.read(&mut buffer[read_length..]).await? {
+        0 => break,
+        chunk_length => read_length += chunk_length,
+      }
+    }
+    buffer.truncate(read_length);
+    Ok(Bytes::from(buffer))
+  }
+}
+
+impl AsyncRead for TempFile {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.file).poll_read(cx, buf)
+  }
+}
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    self.used_size.fetch_sub(self.size, Ordering::Relaxed);
+    METRICS.add_to_gauge(
+      "ferron_temp_files_bytes",
+      &[("purpose", self.purpose)],
+      -(self.size as i64),
+    );
+    METRICS.add_to_gauge("ferron_temp_files", &[("purpose", self.purpose)], -1);
+  }
+}
+
+// A body read to the end, kept in memory if it's small, or in a temporary file otherwise
+pub enum BufferedBody {
+  Memory(Bytes),
+  File(TempFile),
+}
+
+impl BufferedBody {
+  // Get the size of the body
+  pub fn size(&self) -> u64 {
+    match self {
+      Self::Memory(data) => data.len() as u64,
+      Self::File(temp_file) => temp_file.size(),
+    }
+  }
+
+  // Get the reader of the body
+  pub fn into_reader(self) -> Pin<Box<dyn AsyncRead + Send>> {
+    match self {
+      Self::Memory(data) => Box::pin(Cursor::new(data)),
+      Self::File(temp_file) => Box::pin(temp_file),
+    }
+  }
+}
+
+// Read the body to the end. If the body is larger than the memory limit, it's spilled to a temporary file.
+pub async fn buffer_body<B>(
+  body: B,
+  memory_limit: usize,
+  purpose: &'static str,
+) -> Result<BufferedBody, Box<dyn Error + Send + Sync>>
+where
+  B: Body<Data = Bytes> + Unpin,
+  B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+  buffer_body_with_limit(body, memory_limit, None, purpose).await
+}
+
+// Read the body to the end, failing if the body is larger than the maximum size (None disables the limit).
+// If the body is larger than the memory limit, it's spilled to a temporary file.
+pub async fn buffer_body_with_limit<B>(
+  mut body: B,
+  memory_limit: usize,
+  max_size: Option<u64>,
+  purpose: &'static str,
+) -> Result<BufferedBody, Box<dyn Error + Send + Sync>>
+where
+  B: Body<Data = Bytes> + Unpin,
+  B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+  let mut buffer = Vec::new();
+  let mut temp_file: Option<TempFile> = None;
+  let mut size = 0u64;
+  while let Some(frame) = body.frame().await {
+    if let Ok(data) = frame.map_err(Into::into)?.into_data() {
+      size += data.len() as u64;
+      if let Some(max_size) = max_size {
+        if size > max_size {
+          Err(RequestBodyTooLarge { max_size })?
+        }
+      }
+      match &mut temp_file {
+        Some(temp_file) => temp_file.write_all(&data).await?,
+        None => {
+          buffer.extend_from_slice(&data);
+          if buffer.len() > memory_limit {
+            let mut new_temp_file = TEMP_FILES.create(purpose).await?;
+            new_temp_file.write_all(&buffer).await?;
+            buffer = Vec::new();
+            temp_file = Some(new_temp_file);
+          }
+        }
+      }
+    }
+  }
+  match temp_file {
+    Some(mut temp_file) => {
+      temp_file.finish().await?;
+      Ok(BufferedBody::File(temp_file))
+    }
+    None => Ok(BufferedBody::Memory(Bytes::from(buffer))),
+  }
+}
+
+// The storage of the buffered request bodies spilled to the temporary files
+struct TempFileBodyStorage {
+  temp_file: Mutex<TempFile>,
+  size: u64,
+}
+
+#[async_trait]
+impl BufferedBodyStorage for TempFileBodyStorage {
+  fn size(&self) -> u64 {
+    self.size
+  }
+
+  async fn read_at(&self, offset: u64, length: usize) -> io::Result<Bytes> {
+    self.temp_file.lock().await.read_at(offset, length).await
+  }
+}
+
+// Create the buffer of the request bodies inspected by the modules. The bodies larger than the memory limit
+// are spilled to the temporary files, which are removed once the request and its buffered body are dropped.
+pub fn request_body_buffer() -> RequestBodyBuffer {
+  RequestBodyBuffer::new(|body, max_size| async move {
+    match buffer_body_with_limit(
+      body,
+      DEFAULT_BODY_MEMORY_LIMIT,
+      Some(max_size),
+      "request_body",
+    )
+    .await?
+    {
+      BufferedBody::Memory(data) => Ok(BufferedRequestBody::from_bytes(data)),
+      BufferedBody::File(temp_file) => Ok(BufferedRequestBody::from_storage(Arc::new(
+        TempFileBodyStorage {
+          size: temp_file.size(),
+          temp_file: Mutex::new(temp_file),
+        },
+      ))),
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::AsyncReadExt;
+
+  fn test_directory(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "ferron-temp-files-test-{}-{}",
+      name,
+      std::process::id()
+    ))
+  }
+
+  #[tokio::test]
+  async fn test_temp_file_write_and_read() {
+    let directory = test_directory("read");
+    let temp_files = TempFiles::new();
+    temp_files.configure(Some(&directory), None, true).unwrap();
+
+    let mut temp_file = temp_files.create("test").await.unwrap();
+    temp_file.write_all(b"Hello, ").await.unwrap();
+    temp_file.write_all(b"world!").await.unwrap();
+    temp_file.finish().await.unwrap();
+    assert_eq!(temp_file.size(), 13);
+    assert_eq!(temp_files.used_size.load(Ordering::Relaxed), 13);
+
+    let mut contents = String::new();
+    temp_file.read_to_string(&mut contents).await.unwrap();
+    assert_eq!(contents, "Hello, world!");
+
+    // The file isn't visible in the directory
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+    drop(temp_file);
+    assert_eq!(temp_files.used_size.load(Ordering::Relaxed), 0);
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_temp_file_quota() {
+    let directory = test_directory("quota");
+    let temp_files = TempFiles::new();
+    temp_files
+      .configure(Some(&directory), Some(10), false)
+      .unwrap();
+
+    let mut first_temp_file = temp_files.create("test").await.unwrap();
+    let mut second_temp_file = temp_files.create("test").await.unwrap();
+    first_temp_file.write_all(b"123456").await.unwrap();
+    assert!(second_temp_file.write_all(b"123456").await.is_err());
+    second_temp_file.write_all(b"1234").await.unwrap();
+
+    // The space is released when the file is dropped
+    drop(first_temp_file);
+    second_temp_file.write_all(b"123456").await.unwrap();
+    assert_eq!(temp_files.used_size.load(Ordering::Relaxed), 10);
+    drop(second_temp_file);
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[test]
+  fn test_leftover_temp_files_are_removed() {
+    let directory = test_directory("cleanup");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("ferron-1-0000000000000000.tmp"), b"leftover").unwrap();
+    std::fs::write(directory.join("unrelated.txt"), b"unrelated").unwrap();
+
+    TempFiles::new()
+      .configure(Some(&directory), None, false)
+      .unwrap();
+    assert!(!directory.join("ferron-1-0000000000000000.tmp").exists());
+    assert!(directory.join("unrelated.txt").exists());
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_unsafe_directories_are_refused() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let directory = test_directory("unsafe");
+    let target_directory = test_directory("unsafe-target");
+    std::fs::create_dir_all(&target_directory).unwrap();
+    std::os::unix::fs::symlink(&target_directory, &directory).unwrap();
+    assert!(TempFiles::new()
+      .configure(Some(&directory), None, false)
+      .is_err());
+    std::fs::remove_file(&directory).unwrap();
+
+    // The directory created before is made accessible only by the server's user
+    std::fs::set_permissions(&target_directory, std::fs::Permissions::from_mode(0o777)).unwrap();
+    TempFiles::new()
+      .configure(Some(&target_directory), None, false)
+      .unwrap();
+    let metadata = std::fs::symlink_metadata(&target_directory).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+
+    // The directory owned by another user is refused
+    if unsafe { libc::geteuid() } == 0 {
+      std::os::unix::fs::chown(&target_directory, Some(65534), Some(65534)).unwrap();
+      assert!(TempFiles::new()
+        .configure(Some(&target_directory), None, false)
+        .is_err());
+    }
+    std::fs::remove_dir_all(&target_directory).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_default_directory_is_per_user() {
+    if std::env::var_os("RUNTIME_DIRECTORY").is_none() {
+      assert_eq!(
+        default_directory(),
+        std::env::temp_dir().join(format!("ferron-{}", unsafe { libc::geteuid() }))
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn test_buffer_body_spills_to_temp_file() {
+    let body = http_body_util::Full::new(Bytes::from_static(b"small body"));
+    let buffered_body = buffer_body(body, 1024, "test").await.unwrap();
+    assert!(matches!(buffered_body, BufferedBody::Memory(_)));
+    assert_eq!(buffered_body.size(), 10);
+
+    let body = http_body_util::Full::new(Bytes::from(vec![b'a'; 2048]));
+    let buffered_body = buffer_body(body, 1024, "test").await.unwrap();
+    assert!(matches!(buffered_body, BufferedBody::File(_)));
+    assert_eq!(buffered_body.size(), 2048);
+    let mut contents = Vec::new();
+    buffered_body
+      .into_reader()
+      .read_to_end(&mut contents)
+      .await
+      .unwrap();
+    assert_eq!(contents, vec![b'a'; 2048]);
+  }
+
+  #[tokio::test]
+  async fn test_request_body_buffer() {
+    let request_body_buffer = request_body_buffer();
+    let data = (0..DEFAULT_BODY_MEMORY_LIMIT * 3)
+      .map(|index| (index % 251) as u8)
+      .collect::<Vec<_>>();
+
+    let body = http_body_util::Full::new(Bytes::from(data.clone()))
+      .map_err(|e| match e {})
+      .boxed();
+    let buffered_body = request_body_buffer
+      .buffer(body, data.len() as u64)
+      .await
+      .unwrap();
+    assert!(buffered_body.as_bytes().is_none());
+    assert_eq!(buffered_body.size(), data.len() as u64);
+    assert_eq!(
+      buffered_body.read_to_bytes(100).await.unwrap(),
+      Bytes::from(data[..100].to_vec())
+    );
+
+    // The body stored in the temporary file can be replayed more than once
+    for _ in 0..2 {
+      let replayed_data = buffered_body.to_body().collect().await.unwrap().to_bytes();
+      assert_eq!(replayed_data, Bytes::from(data.clone()));
+    }
+
+    let body = http_body_util::Full::new(Bytes::from(data.clone()))
+      .map_err(|e| match e {})
+      .boxed();
+    let err = request_body_buffer.buffer(body, 1000).await.err().unwrap();
+    assert!(err.downcast_ref::<RequestBodyTooLarge>().is_some());
+  }
+}
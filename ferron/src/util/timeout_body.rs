@@ -0,0 +1,96 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::time::{sleep, Sleep};
+
+// A request body wrapper, which ends the body early if it isn't received in time.
+// Since the body error type can't be constructed, the timeout is signaled with a shared flag instead.
+pub struct TimeoutBody<B> {
+  inner: B,
+  deadline: Pin<Box<Sleep>>,
+  timed_out: Arc<AtomicBool>,
+}
+
+impl<B> TimeoutBody<B> {
+  pub fn new(inner: B, timeout: Duration, timed_out: Arc<AtomicBool>) -> Self {
+    Self {
+      inner,
+      deadline: Box::pin(sleep(timeout)),
+      timed_out,
+    }
+  }
+}
+
+impl<B: Body + Unpin> Body for TimeoutBody<B> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if this.timed_out.load(Ordering::Relaxed) {
+      return Poll::Ready(None);
+    }
+    match Pin::new(&mut this.inner).poll_frame(cx) {
+      Poll::Ready(frame) => Poll::Ready(frame),
+      Poll::Pending => {
+        if this.deadline.as_mut().poll(cx).is_ready() {
+          this.timed_out.store(true, Ordering::Relaxed);
+          Poll::Ready(None)
+        } else {
+          Poll::Pending
+        }
+      }
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.timed_out.load(Ordering::Relaxed) || self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, StreamBody};
+  use hyper::body::Bytes;
+
+  #[tokio::test]
+  async fn test_body_timeout() {
+    let stream = futures_util::stream::pending::<Result<Frame<Bytes>, std::io::Error>>();
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let body = TimeoutBody::new(
+      StreamBody::new(stream),
+      Duration::from_millis(10),
+      timed_out.clone(),
+    );
+    assert!(body.collect().await.unwrap().to_bytes().is_empty());
+    assert!(timed_out.load(Ordering::Relaxed));
+  }
+
+  #[tokio::test]
+  async fn test_body_received_in_time() {
+    let stream = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(Frame::data(
+      Bytes::from("body"),
+    ))]);
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let body = TimeoutBody::new(
+      StreamBody::new(stream),
+      Duration::from_secs(10),
+      timed_out.clone(),
+    );
+    assert_eq!(body.collect().await.unwrap().to_bytes(), "body");
+    assert!(!timed_out.load(Ordering::Relaxed));
+  }
+}
@@ -0,0 +1,493 @@
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::task::AtomicWaker;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::ferron_util::admin_api::{ActiveGuard, SERVER_STATS};
+use crate::ferron_util::metrics::METRICS;
+use crate::ferron_util::min_transfer_rate::MinTransferRate;
+
+// The error returned when the client doesn't send the request header in time
+#[derive(Debug)]
+pub struct HeaderReadTimeoutError;
+
+impl fmt::Display for HeaderReadTimeoutError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "request header read timed out")
+  }
+}
+
+impl std::error::Error for HeaderReadTimeoutError {}
+
+// The state of the requests on a connection, shared between the connection stream and the request handlers
+pub struct ConnectionActivity {
+  in_flight: AtomicUsize,
+  upgraded: AtomicBool,
+  read_waker: AtomicWaker,
+  reap_reason: OnceLock<ReapReason>,
+}
+
+impl ConnectionActivity {
+  pub fn new() -> Arc<Self> {
+    Arc::new(Self {
+      in_flight: AtomicUsize::new(0),
+      upgraded: AtomicBool::new(false),
+      read_waker: AtomicWaker::new(),
+      reap_reason: OnceLock::new(),
+    })
+  }
+
+  // Mark a request as in flight, until the returned guard is dropped (after the response is sent)
+  pub fn start_request(self: &Arc<Self>) -> RequestGuard {
+    self.in_flight.fetch_add(1, Ordering::Relaxed);
+    RequestGuard {
+      activity: self.clone(),
+    }
+  }
+
+  // Mark the connection as upgraded (for example to a WebSocket connection), which disables the read timeouts
+  pub fn mark_upgraded(&self) {
+    self.upgraded.store(true, Ordering::Relaxed);
+  }
+
+  // The reason the connection was closed for being idle, if it was
+  pub fn reap_reason(&self) -> Option<ReapReason> {
+    self.reap_reason.get().copied()
+  }
+}
+
+// The idle timeout, after which the connection was closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReapReason {
+  // No request was received after the connection was accepted
+  PreRequest,
+  // No further request was received after the last response was sent
+  KeepAlive,
+  // No data was sent in either direction over the upgraded (for example WebSocket) connection
+  WebSocket,
+}
+
+impl ReapReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::PreRequest => "pre_request",
+      Self::KeepAlive => "keep_alive",
+      Self::WebSocket => "websocket",
+    }
+  }
+}
+
+pub struct RequestGuard {
+  activity: Arc<ConnectionActivity>,
+}
+
+impl Drop for RequestGuard {
+  fn drop(&mut self) {
+    // Wake the connection stream, so that the keep-alive timeout starts after the last response is sent
+    if self.activity.in_flight.fetch_sub(1, Ordering::Relaxed) == 1 {
+      self.activity.read_waker.wake();
+    }
+  }
+}
+
+// Timeouts applied by the connection stream
+#[derive(Clone, Copy, Default)]
+pub struct StreamTimeouts {
+  pub header_read: Option<Duration>,
+  // The idle timeout before the first request on the connection
+  pub pre_request_idle: Option<Duration>,
+  pub keep_alive: Option<Duration>,
+  // The idle timeout of the upgraded connections, reset by the data sent in either direction
+  pub web_socket_idle: Option<Duration>,
+  pub write: Option<Duration>,
+  // The minimum rate (in bytes per second) the request header has to be sent at, after the grace period
+  pub header_min_rate: Option<u64>,
+  pub min_rate_grace_period: Duration,
+}
+
+#[derive(PartialEq)]
+enum ReadPhase {
+  Idle,
+  Header,
+  Busy,
+  Upgraded,
+}
+
+// A connection stream wrapper, which closes idle connections (before the first request, between the requests and after the upgrade),
+// and fails when the request header isn't received in time (or is trickled too slowly) or when writing to the client stalls
+pub struct TimeoutStream<S> {
+  inner: S,
+  activity: Arc<ConnectionActivity>,
+  timeouts: StreamTimeouts,
+  read_phase: ReadPhase,
+  read_deadline: Option<Pin<Box<Sleep>>>,
+  header_rate: Option<MinTransferRate>,
+  write_deadline: Option<Pin<Box<Sleep>>>,
+  is_http2: Option<bool>,
+  idle_closed: bool,
+  served_request: bool,
+  idle_guard: Option<ActiveGuard>,
+  upgraded_guard: Option<ActiveGuard>,
+}
+
+impl<S> TimeoutStream<S> {
+  pub fn new(inner: S, activity: Arc<ConnectionActivity>, timeouts: StreamTimeouts) -> Self {
+    Self {
+      inner,
+      activity,
+      timeouts,
+      read_phase: ReadPhase::Idle,
+      read_deadline: timeouts
+        .pre_request_idle
+        .map(|timeout| Box::pin(sleep(timeout))),
+      header_rate: None,
+      write_deadline: None,
+      is_http2: None,
+      idle_closed: false,
+      served_request: false,
+      idle_guard: Some(SERVER_STATS.start_idle_connection()),
+      upgraded_guard: None,
+    }
+  }
+
+  // Update the read phase, based on the requests in flight on the connection
+  fn update_read_phase(&mut self) {
+    if self.activity.upgraded.load(Ordering::Relaxed) {
+      if self.read_phase != ReadPhase::Upgraded {
+        self.read_phase = ReadPhase::Upgraded;
+        self.read_deadline = self
+          .timeouts
+          .web_socket_idle
+          .map(|timeout| Box::pin(sleep(timeout)));
+        self.header_rate = None;
+        self.idle_guard = None;
+        self.upgraded_guard = Some(SERVER_STATS.start_upgraded_connection());
+      }
+    } else if self.activity.in_flight.load(Ordering::Relaxed) > 0 {
+      self.read_phase = ReadPhase::Busy;
+      self.read_deadline = None;
+      self.header_rate = None;
+      self.served_request = true;
+      self.idle_guard = None;
+    } else if self.read_phase == ReadPhase::Busy {
+      self.read_phase = ReadPhase::Idle;
+      self.read_deadline = self
+        .timeouts
+        .keep_alive
+        .map(|timeout| Box::pin(sleep(timeout)));
+      self.idle_guard = Some(SERVER_STATS.start_idle_connection());
+    }
+  }
+
+  // Reset the idle timeout of the upgraded connection, when the data is sent in either direction
+  fn record_upgraded_activity(&mut self) {
+    if self.read_phase == ReadPhase::Upgraded {
+      if let (Some(read_deadline), Some(timeout)) =
+        (self.read_deadline.as_mut(), self.timeouts.web_socket_idle)
+      {
+        read_deadline.as_mut().reset(Instant::now() + timeout);
+      }
+    }
+  }
+
+  // Close the idle connection, recording the reason. The reading side reports the end of the stream.
+  fn reap(&mut self, reason: ReapReason) {
+    self.idle_closed = true;
+    self.idle_guard = None;
+    self.upgraded_guard = None;
+    if self.activity.reap_reason.set(reason).is_ok() {
+      SERVER_STATS.record_reaped_connection();
+      METRICS.increment_counter(
+        "ferron_reaped_connections_total",
+        &[("reason", reason.as_str())],
+      );
+    }
+  }
+
+  fn poll_write_deadline(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+    if let Some(timeout) = self.timeouts.write {
+      let write_deadline = self
+        .write_deadline
+        .get_or_insert_with(|| Box::pin(sleep(timeout)));
+      if write_deadline.as_mut().poll(cx).is_ready() {
+        self.write_deadline = None;
+        return Poll::Ready(io::Error::new(
+          io::ErrorKind::TimedOut,
+          "response write timed out",
+        ));
+      }
+    }
+    Poll::Pending
+  }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    if this.idle_closed {
+      return Poll::Ready(Ok(()));
+    }
+    this.update_read_phase();
+
+    let filled_before = buf.filled().len();
+    match Pin::new(&mut this.inner).poll_read(cx, buf) {
+      Poll::Ready(result) => {
+        let received = &buf.filled()[filled_before..];
+        if !received.is_empty() {
+          // HTTP/2 connections have their own framing, so only the keep-alive timeout applies to them
+          let is_http2 = *this
+            .is_http2
+            .get_or_insert_with(|| received.starts_with(b"PRI * HTTP/2.0"));
+          if this.read_phase == ReadPhase::Idle
+            && !is_http2
+            && !this.activity.upgraded.load(Ordering::Relaxed)
+          {
+            this.read_phase = ReadPhase::Header;
+            this.idle_guard = None;
+            this.read_deadline = this
+              .timeouts
+              .header_read
+              .map(|timeout| Box::pin(sleep(timeout)));
+            this.header_rate = this
+              .timeouts
+              .header_min_rate
+              .map(|min_rate| MinTransferRate::new(min_rate, this.timeouts.min_rate_grace_period));
+          }
+        }
+        if !received.is_empty() {
+          this.record_upgraded_activity();
+        }
+        if let Some(header_rate) = this.header_rate.as_mut() {
+          header_rate.record(received.len());
+        }
+        Poll::Ready(result)
+      }
+      Poll::Pending => {
+        this.activity.read_waker.register(cx.waker());
+        if let Some(read_deadline) = this.read_deadline.as_mut() {
+          if read_deadline.as_mut().poll(cx).is_ready() {
+            this.read_deadline = None;
+            match this.read_phase {
+              // Close the idle connection
+              ReadPhase::Idle => {
+                this.reap(if this.served_request {
+                  ReapReason::KeepAlive
+                } else {
+                  ReapReason::PreRequest
+                });
+                return Poll::Ready(Ok(()));
+              }
+              ReadPhase::Upgraded => {
+                this.reap(ReapReason::WebSocket);
+                return Poll::Ready(Ok(()));
+              }
+              ReadPhase::Header => {
+                return Poll::Ready(Err(io::Error::new(
+                  io::ErrorKind::TimedOut,
+                  HeaderReadTimeoutError,
+                )))
+              }
+              ReadPhase::Busy => (),
+            }
+          }
+        }
+        if let Some(header_rate) = this.header_rate.as_mut() {
+          if header_rate.poll_too_slow(cx) {
+            this.header_rate = None;
+            return Poll::Ready(Err(io::Error::new(
+              io::ErrorKind::TimedOut,
+              HeaderReadTimeoutError,
+            )));
+          }
+        }
+        Poll::Pending
+      }
+    }
+  }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    match Pin::new(&mut this.inner).poll_write(cx, buf) {
+      Poll::Ready(result) => {
+        this.write_deadline = None;
+        if result.as_ref().is_ok_and(|written| *written > 0) {
+          this.record_upgraded_activity();
+        }
+        Poll::Ready(result)
+      }
+      Poll::Pending => this.poll_write_deadline(cx).map(Err),
+    }
+  }
+
+  fn poll_write_vectored(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    bufs: &[IoSlice<'_>],
+  ) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    match Pin::new(&mut this.inner).poll_write_vectored(cx, bufs) {
+      Poll::Ready(result) => {
+        this.write_deadline = None;
+        if result.as_ref().is_ok_and(|written| *written > 0) {
+          this.record_upgraded_activity();
+        }
+        Poll::Ready(result)
+      }
+      Poll::Pending => this.poll_write_deadline(cx).map(Err),
+    }
+  }
+
+  fn is_write_vectored(&self) -> bool {
+    self.inner.is_write_vectored()
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    match Pin::new(&mut this.inner).poll_flush(cx) {
+      Poll::Ready(result) => {
+        this.write_deadline = None;
+        Poll::Ready(result)
+      }
+      Poll::Pending => this.poll_write_deadline(cx).map(Err),
+    }
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+  fn timeouts() -> StreamTimeouts {
+    StreamTimeouts {
+      header_read: Some(Duration::from_millis(50)),
+      pre_request_idle: Some(Duration::from_millis(50)),
+      keep_alive: Some(Duration::from_millis(50)),
+      write: Some(Duration::from_millis(50)),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_idle_connection_is_closed() {
+    let (server, _client) = duplex(64);
+    let activity = ConnectionActivity::new();
+    let mut stream = TimeoutStream::new(server, activity.clone(), timeouts());
+    let mut buffer = [0u8; 16];
+    assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    assert_eq!(activity.reap_reason(), Some(ReapReason::PreRequest));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_idle_upgraded_connection_is_closed() {
+    let (server, mut client) = duplex(64);
+    let activity = ConnectionActivity::new();
+    let timeouts = StreamTimeouts {
+      web_socket_idle: Some(Duration::from_secs(10)),
+      ..Default::default()
+    };
+    let mut stream = TimeoutStream::new(server, activity.clone(), timeouts);
+    let request_guard = activity.start_request();
+    activity.mark_upgraded();
+    drop(request_guard);
+    tokio::spawn(async move {
+      // The data sent by the client resets the idle timeout
+      for _ in 0..3 {
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        client.write_all(b"X").await.unwrap();
+      }
+      tokio::time::sleep(Duration::from_secs(60)).await;
+    });
+    let start = Instant::now();
+    let mut buffer = [0u8; 16];
+    while stream.read(&mut buffer).await.unwrap() > 0 {}
+    assert!(start.elapsed() >= Duration::from_secs(28));
+    assert_eq!(activity.reap_reason(), Some(ReapReason::WebSocket));
+  }
+
+  #[tokio::test]
+  async fn test_slow_header_read() {
+    let (server, mut client) = duplex(64);
+    let mut stream = TimeoutStream::new(server, ConnectionActivity::new(), timeouts());
+    client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    let mut buffer = [0u8; 64];
+    assert!(stream.read(&mut buffer).await.unwrap() > 0);
+    let err = stream.read(&mut buffer).await.unwrap_err();
+    assert!(err
+      .get_ref()
+      .is_some_and(|err| err.is::<HeaderReadTimeoutError>()));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_trickled_header_read() {
+    let (server, mut client) = duplex(64);
+    let timeouts = StreamTimeouts {
+      header_read: Some(Duration::from_secs(60)),
+      header_min_rate: Some(100),
+      min_rate_grace_period: Duration::from_secs(1),
+      ..Default::default()
+    };
+    let mut stream = TimeoutStream::new(server, ConnectionActivity::new(), timeouts);
+    tokio::spawn(async move {
+      for _ in 0..30 {
+        if client.write_all(b"X").await.is_err() {
+          break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+      }
+    });
+    let mut buffer = [0u8; 64];
+    let err = loop {
+      match stream.read(&mut buffer).await {
+        Ok(_) => continue,
+        Err(err) => break err,
+      }
+    };
+    assert!(err
+      .get_ref()
+      .is_some_and(|err| err.is::<HeaderReadTimeoutError>()));
+  }
+
+  #[tokio::test]
+  async fn test_no_read_timeout_while_request_is_in_flight() {
+    let (server, mut client) = duplex(64);
+    let activity = ConnectionActivity::new();
+    let mut stream = TimeoutStream::new(server, activity.clone(), timeouts());
+    let request_guard = activity.start_request();
+    let read_result = tokio::time::timeout(Duration::from_millis(150), async {
+      let mut buffer = [0u8; 64];
+      stream.read(&mut buffer).await
+    })
+    .await;
+    assert!(read_result.is_err());
+    drop(request_guard);
+    client.write_all(b"GET").await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_write_timeout() {
+    let (server, _client) = duplex(4);
+    let mut stream = TimeoutStream::new(server, ConnectionActivity::new(), timeouts());
+    let err = stream.write_all(b"HTTP/1.1 200 OK\r\n").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+  }
+}
@@ -0,0 +1,304 @@
+use std::error::Error;
+
+use rustls::crypto::ring::cipher_suite::*;
+use rustls::crypto::ring::kx_group::{SECP256R1, SECP384R1, X25519};
+use rustls::crypto::SupportedKxGroup;
+use rustls::version::{TLS12, TLS13};
+use rustls::{ProtocolVersion, ServerConnection, SupportedCipherSuite, SupportedProtocolVersion};
+use yaml_rust2::Yaml;
+
+use crate::ferron_util::client_auth::{has_client_auth_options, ClientAuthConfig};
+
+// The protocols offered with ALPN by default
+pub const DEFAULT_ALPN_PROTOCOLS: [&str; 3] = ["h2", "http/1.1", "http/1.0"];
+
+// The TLS protocol versions supported by the server, from the oldest to the newest
+const TLS_VERSIONS: [&str; 2] = ["TLSv1.2", "TLSv1.3"];
+
+static TLS12_ONLY: [&SupportedProtocolVersion; 1] = [&TLS12];
+static TLS12_AND_TLS13: [&SupportedProtocolVersion; 2] = [&TLS12, &TLS13];
+static TLS13_ONLY: [&SupportedProtocolVersion; 1] = [&TLS13];
+
+// Get the cipher suite by its IANA name
+pub fn cipher_suite_by_name(name: &str) -> Option<SupportedCipherSuite> {
+  match name {
+    "TLS_AES_128_GCM_SHA256" => Some(TLS13_AES_128_GCM_SHA256),
+    "TLS_AES_256_GCM_SHA384" => Some(TLS13_AES_256_GCM_SHA384),
+    "TLS_CHACHA20_POLY1305_SHA256" => Some(TLS13_CHACHA20_POLY1305_SHA256),
+    "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256" => Some(TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256),
+    "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384" => Some(TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384),
+    "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256" => {
+      Some(TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256)
+    }
+    "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256" => Some(TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256),
+    "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384" => Some(TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384),
+    "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256" => {
+      Some(TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256)
+    }
+    _ => None,
+  }
+}
+
+// The parameters negotiated for the TLS session, available to the requests sent over the connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsSession {
+  // The TLS protocol version (for example, "TLSv1.3")
+  pub protocol: &'static str,
+  // The IANA name of the cipher suite (for example, "TLS_AES_128_GCM_SHA256")
+  pub cipher: String,
+}
+
+impl TlsSession {
+  pub fn from_connection(connection: &ServerConnection) -> Option<Self> {
+    let protocol = match connection.protocol_version()? {
+      ProtocolVersion::TLSv1_2 => TLS_VERSIONS[0],
+      ProtocolVersion::TLSv1_3 => TLS_VERSIONS[1],
+      _ => return None,
+    };
+    let cipher_suite = connection.negotiated_cipher_suite()?.suite();
+    let cipher = match cipher_suite.as_str() {
+      // The TLS 1.3 cipher suites are named without the protocol version by IANA
+      Some(cipher) => cipher.replacen("TLS13_", "TLS_", 1),
+      None => format!("{:?}", cipher_suite),
+    };
+    Some(Self { protocol, cipher })
+  }
+}
+
+// The TLS configuration presets, named after the Mozilla's server side TLS recommendations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsPreset {
+  // TLS 1.3 only
+  Modern,
+  // TLS 1.2 and TLS 1.3 with the forward-secret AEAD cipher suites
+  Intermediate,
+  // The widest compatibility. The TLS versions older than TLS 1.2 and the CBC cipher suites aren't implemented,
+  // so this preset enables the same parameters as the "intermediate" preset.
+  Old,
+}
+
+impl TlsPreset {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "modern" => Some(TlsPreset::Modern),
+      "intermediate" => Some(TlsPreset::Intermediate),
+      "old" => Some(TlsPreset::Old),
+      _ => None,
+    }
+  }
+
+  // Get the cipher suites of the preset, in the order of preference
+  pub fn cipher_suites(&self) -> Vec<SupportedCipherSuite> {
+    let mut cipher_suites = vec![
+      TLS13_AES_128_GCM_SHA256,
+      TLS13_AES_256_GCM_SHA384,
+      TLS13_CHACHA20_POLY1305_SHA256,
+    ];
+    if *self != TlsPreset::Modern {
+      cipher_suites.extend([
+        TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+      ]);
+    }
+    cipher_suites
+  }
+
+  // Get the key exchange groups of the preset, in the order of preference
+  pub fn kx_groups(&self) -> Vec<&'static dyn SupportedKxGroup> {
+    vec![X25519, SECP256R1, SECP384R1]
+  }
+
+  // Get the minimum TLS version of the preset
+  pub fn min_version(&self) -> &'static str {
+    match self {
+      TlsPreset::Modern => "TLSv1.3",
+      TlsPreset::Intermediate | TlsPreset::Old => "TLSv1.2",
+    }
+  }
+}
+
+// Get the TLS protocol versions between the minimum and the maximum version (both inclusive)
+pub fn protocol_versions(
+  min_version: Option<&str>,
+  max_version: Option<&str>,
+) -> Result<&'static [&'static SupportedProtocolVersion], Box<dyn Error + Send + Sync>> {
+  let min_version_index = match min_version {
+    Some(min_version) => TLS_VERSIONS
+      .iter()
+      .position(|version| *version == min_version)
+      .ok_or(anyhow::anyhow!("Invalid minimum TLS version"))?,
+    None => 0,
+  };
+  let max_version_index = match max_version {
+    Some(max_version) => TLS_VERSIONS
+      .iter()
+      .position(|version| *version == max_version)
+      .ok_or(anyhow::anyhow!("Invalid maximum TLS version"))?,
+    None => TLS_VERSIONS.len() - 1,
+  };
+  match (min_version_index, max_version_index) {
+    (0, 0) => Ok(&TLS12_ONLY),
+    (0, 1) => Ok(&TLS12_AND_TLS13),
+    (1, 1) => Ok(&TLS13_ONLY),
+    _ => Err(anyhow::anyhow!(
+      "The maximum TLS version is older than the minimum TLS version"
+    ))?,
+  }
+}
+
+// The TLS parameters overridden for an SNI host name. The parameters, which aren't overridden,
+// are inherited from the global configuration (and from the listener configuration for the client authentication).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsPolicy {
+  // The minimum TLS version
+  pub min_version: Option<String>,
+  // The maximum TLS version
+  pub max_version: Option<String>,
+  // The names of the enabled cipher suites
+  pub cipher_suites: Option<Vec<String>>,
+  // The protocols offered with ALPN, in the order of preference
+  pub alpn_protocols: Option<Vec<String>>,
+  // The TLS client authentication configuration
+  pub client_auth: Option<ClientAuthConfig>,
+}
+
+impl TlsPolicy {
+  // Parse the TLS policy overrides from the SNI host configuration
+  pub fn from_yaml(sni_config: &Yaml) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    let string_option = |option: &str, error: &str| match &sni_config[option] {
+      Yaml::BadValue => Ok(None),
+      Yaml::String(value) => Ok(Some(value.clone())),
+      _ => Err(anyhow::anyhow!(error.to_string())),
+    };
+    let string_list_option = |option: &str, error: &str| match &sni_config[option] {
+      Yaml::BadValue => Ok(None),
+      Yaml::Array(values) if !values.is_empty() => values
+        .iter()
+        .map(|value| value.as_str().map(String::from))
+        .collect::<Option<Vec<_>>>()
+        .map(Some)
+        .ok_or(anyhow::anyhow!(error.to_string())),
+      _ => Err(anyhow::anyhow!(error.to_string())),
+    };
+
+    let tls_policy = Self {
+      min_version: string_option("tlsMinVersion", "Invalid minimum TLS version")?,
+      max_version: string_option("tlsMaxVersion", "Invalid maximum TLS version")?,
+      cipher_suites: string_list_option("cipherSuite", "Invalid cipher suite configuration")?,
+      alpn_protocols: string_list_option("alpnProtocols", "Invalid ALPN protocol list")?,
+      client_auth: match has_client_auth_options(sni_config) {
+        true => Some(ClientAuthConfig::from_yaml(sni_config)?),
+        false => None,
+      },
+    };
+
+    protocol_versions(
+      tls_policy.min_version.as_deref(),
+      tls_policy.max_version.as_deref(),
+    )?;
+    if let Some(cipher_suites) = &tls_policy.cipher_suites {
+      for cipher_suite in cipher_suites {
+        if cipher_suite_by_name(cipher_suite).is_none() {
+          Err(anyhow::anyhow!(
+            "The \"{}\" cipher suite is not supported",
+            cipher_suite
+          ))?
+        }
+      }
+    }
+    if let Some(alpn_protocols) = &tls_policy.alpn_protocols {
+      for alpn_protocol in alpn_protocols {
+        if !DEFAULT_ALPN_PROTOCOLS.contains(&alpn_protocol.as_str()) {
+          Err(anyhow::anyhow!(
+            "The \"{}\" ALPN protocol is not supported",
+            alpn_protocol
+          ))?
+        }
+      }
+    }
+
+    Ok(tls_policy)
+  }
+
+  // Check if any TLS parameter is overridden
+  pub fn is_override(&self) -> bool {
+    *self != Self::default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  #[test]
+  fn test_protocol_versions() {
+    assert_eq!(protocol_versions(None, None).unwrap().len(), 2);
+    assert_eq!(
+      protocol_versions(Some("TLSv1.3"), None).unwrap()[0].version,
+      TLS13.version
+    );
+    assert_eq!(
+      protocol_versions(None, Some("TLSv1.2")).unwrap()[0].version,
+      TLS12.version
+    );
+    assert!(protocol_versions(Some("TLSv1.3"), Some("TLSv1.2")).is_err());
+    assert!(protocol_versions(Some("TLSv1.1"), None).is_err());
+  }
+
+  #[test]
+  fn test_tls_presets() {
+    let modern = TlsPreset::from_name("modern").unwrap();
+    assert_eq!(modern.cipher_suites().len(), 3);
+    assert!(modern
+      .cipher_suites()
+      .iter()
+      .all(|cipher_suite| cipher_suite.version().version == TLS13.version));
+    assert_eq!(
+      protocol_versions(Some(modern.min_version()), None).unwrap()[0].version,
+      TLS13.version
+    );
+    let intermediate = TlsPreset::from_name("intermediate").unwrap();
+    assert_eq!(intermediate.cipher_suites().len(), 9);
+    assert_eq!(intermediate.min_version(), "TLSv1.2");
+    assert_eq!(TlsPreset::from_name("old"), Some(TlsPreset::Old));
+    assert_eq!(TlsPreset::from_name("legacy"), None);
+  }
+
+  #[test]
+  fn test_tls_policy() {
+    let config = YamlLoader::load_from_str(
+      "cert: cert.pem\nkey: key.pem\ntlsMinVersion: TLSv1.3\nalpnProtocols: [http/1.1]\nuseClientCertificate: true\n",
+    )
+    .unwrap()
+    .remove(0);
+    let tls_policy = TlsPolicy::from_yaml(&config).unwrap();
+    assert!(tls_policy.is_override());
+    assert_eq!(tls_policy.min_version.as_deref(), Some("TLSv1.3"));
+    assert_eq!(
+      tls_policy.alpn_protocols,
+      Some(vec!["http/1.1".to_string()])
+    );
+    assert!(tls_policy
+      .client_auth
+      .is_some_and(|client_auth| client_auth.use_system_store));
+
+    let config = YamlLoader::load_from_str("cert: cert.pem\nkey: key.pem\n")
+      .unwrap()
+      .remove(0);
+    assert!(!TlsPolicy::from_yaml(&config).unwrap().is_override());
+
+    let config = YamlLoader::load_from_str("cipherSuite: [TLS_RSA_WITH_RC4_128_SHA]\n")
+      .unwrap()
+      .remove(0);
+    assert!(TlsPolicy::from_yaml(&config).is_err());
+    let config = YamlLoader::load_from_str("alpnProtocols: [spdy/3]\n")
+      .unwrap()
+      .remove(0);
+    assert!(TlsPolicy::from_yaml(&config).is_err());
+  }
+}
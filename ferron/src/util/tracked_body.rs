@@ -0,0 +1,57 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::body::{Body, Frame, SizeHint};
+
+// A response body wrapper, which keeps a value (for example a request guard) alive until the body is dropped
+pub struct TrackedBody<B, T> {
+  inner: B,
+  _tracked: T,
+}
+
+impl<B, T> TrackedBody<B, T> {
+  pub fn new(inner: B, tracked: T) -> Self {
+    Self {
+      inner,
+      _tracked: tracked,
+    }
+  }
+}
+
+impl<B: Body + Unpin, T: Unpin> Body for TrackedBody<B, T> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, Full};
+  use hyper::body::Bytes;
+  use std::sync::Arc;
+
+  #[tokio::test]
+  async fn test_tracked_body() {
+    let tracked = Arc::new(());
+    let body = TrackedBody::new(Full::new(Bytes::from("body")), tracked.clone());
+    assert_eq!(body.size_hint().exact(), Some(4));
+    assert_eq!(Arc::strong_count(&tracked), 2);
+    assert_eq!(body.collect().await.unwrap().to_bytes(), "body");
+    assert_eq!(Arc::strong_count(&tracked), 1);
+  }
+}
@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use crate::ferron_util::admin_api::is_backend_drained;
+use crate::ferron_util::anti_xss::anti_xss;
+
+// The maximum number of the ejections kept in the history of a backend server
+const MAX_EJECTION_HISTORY: usize = 10;
+
+// The maximum number of the tracked backend servers, limiting the memory used for the backend server URLs with variables
+const MAX_TRACKED_BACKENDS: usize = 1024;
+
+// The health of the reverse proxy backend servers, reported in the admin API
+static UPSTREAM_HEALTH: RwLock<BTreeMap<String, BackendHealth>> = RwLock::new(BTreeMap::new());
+
+// An ejection of a backend server from the load balancing, after it has failed too many times
+struct Ejection {
+  ejected_at: DateTime<Local>,
+  restored_at: Option<DateTime<Local>>,
+  failures: u64,
+}
+
+// The health of a reverse proxy backend server, based on the outcomes of the proxied requests
+#[derive(Default)]
+struct BackendHealth {
+  consecutive_failures: u64,
+  total_requests: u64,
+  total_failures: u64,
+  last_checked: Option<DateTime<Local>>,
+  last_latency: Option<Duration>,
+  last_error: Option<String>,
+  ejections: VecDeque<Ejection>,
+}
+
+impl BackendHealth {
+  fn is_ejected(&self) -> bool {
+    self
+      .ejections
+      .back()
+      .is_some_and(|ejection| ejection.restored_at.is_none())
+  }
+
+  fn restore(&mut self) {
+    if let Some(ejection) = self.ejections.back_mut() {
+      if ejection.restored_at.is_none() {
+        ejection.restored_at = Some(Local::now());
+      }
+    }
+  }
+
+  fn state(&self, backend: &str) -> &'static str {
+    if is_backend_drained(backend) {
+      "drained"
+    } else if self.is_ejected() {
+      "ejected"
+    } else if self.consecutive_failures > 0 {
+      "failing"
+    } else {
+      "healthy"
+    }
+  }
+}
+
+// Update the health of the backend server. The new backend servers aren't tracked above the limit.
+fn update_backend_health(backend: &str, update: impl FnOnce(&mut BackendHealth)) {
+  let mut upstream_health = match UPSTREAM_HEALTH.write() {
+    Ok(upstream_health) => upstream_health,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if let Some(backend_health) = upstream_health.get_mut(backend) {
+    update(backend_health);
+  } else if upstream_health.len() < MAX_TRACKED_BACKENDS {
+    update(upstream_health.entry(backend.to_string()).or_default());
+  }
+}
+
+// Record the proxied request, which has reached the backend server
+pub fn record_backend_success(backend: &str, latency: Duration) {
+  update_backend_health(backend, |backend_health| {
+    backend_health.consecutive_failures = 0;
+    backend_health.total_requests += 1;
+    backend_health.last_checked = Some(Local::now());
+    backend_health.last_latency = Some(latency);
+    backend_health.restore();
+  });
+}
+
+// Record the proxied request, for which the backend server couldn't be reached or didn't respond in time
+pub fn record_backend_failure(backend: &str, latency: Duration, error: &str) {
+  update_backend_health(backend, |backend_health| {
+    backend_health.consecutive_failures += 1;
+    backend_health.total_requests += 1;
+    backend_health.total_failures += 1;
+    backend_health.last_checked = Some(Local::now());
+    backend_health.last_latency = Some(latency);
+    backend_health.last_error = Some(error.to_string());
+  });
+}
+
+// Record the backend server being skipped by the load balancer because of too many failures
+pub fn record_backend_ejection(backend: &str, failures: u64) {
+  update_backend_health(backend, |backend_health| {
+    if backend_health.is_ejected() {
+      return;
+    }
+    if backend_health.ejections.len() >= MAX_EJECTION_HISTORY {
+      backend_health.ejections.pop_front();
+    }
+    backend_health.ejections.push_back(Ejection {
+      ejected_at: Local::now(),
+      restored_at: None,
+      failures,
+    });
+  });
+}
+
+// Record the backend server being chosen by the load balancer again
+pub fn record_backend_restoration(backend: &str) {
+  let is_ejected = match UPSTREAM_HEALTH.read() {
+    Ok(upstream_health) => upstream_health
+      .get(backend)
+      .is_some_and(BackendHealth::is_ejected),
+    Err(poisoned) => poisoned
+      .into_inner()
+      .get(backend)
+      .is_some_and(BackendHealth::is_ejected),
+  };
+  if is_ejected {
+    update_backend_health(backend, BackendHealth::restore);
+  }
+}
+
+// Get the health of the reverse proxy backend servers, reported in the admin API
+pub fn upstream_health_json() -> Value {
+  let upstream_health = match UPSTREAM_HEALTH.read() {
+    Ok(upstream_health) => upstream_health,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  Value::Array(
+    upstream_health
+      .iter()
+      .map(|(backend, backend_health)| {
+        json!({
+          "backend": backend,
+          "state": backend_health.state(backend),
+          "consecutiveFailures": backend_health.consecutive_failures,
+          "totalRequests": backend_health.total_requests,
+          "totalFailures": backend_health.total_failures,
+          "lastChecked": backend_health.last_checked.map(|time| time.to_rfc3339()),
+          "lastLatencyMs": backend_health
+            .last_latency
+            .map(|latency| latency.as_secs_f64() * 1000.0),
+          "lastError": backend_health.last_error,
+          "ejections": backend_health
+            .ejections
+            .iter()
+            .map(|ejection| {
+              json!({
+                "ejectedAt": ejection.ejected_at.to_rfc3339(),
+                "restoredAt": ejection.restored_at.map(|time| time.to_rfc3339()),
+                "failures": ejection.failures,
+              })
+            })
+            .collect::<Vec<_>>(),
+        })
+      })
+      .collect(),
+  )
+}
+
+// Get the health of the reverse proxy backend servers as an HTML table, shown on the admin API status page.
+// The backend servers that aren't healthy are listed first, so that the misbehaving ones are seen at a glance.
+pub fn upstream_health_html() -> String {
+  let upstream_health = match UPSTREAM_HEALTH.read() {
+    Ok(upstream_health) => upstream_health,
+    Err(poisoned) => poisoned.into_inner(),
+  };
+  if upstream_health.is_empty() {
+    return String::from("<p>No backend servers were proxied to yet.</p>");
+  }
+  let mut backends = upstream_health
+    .iter()
+    .map(|(backend, backend_health)| (backend, backend_health, backend_health.state(backend)))
+    .collect::<Vec<_>>();
+  backends.sort_by_key(|(_, _, state)| *state == "healthy");
+
+  let format_time = |time: Option<DateTime<Local>>| match time {
+    Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+    None => String::from("-"),
+  };
+  let rows = backends
+    .iter()
+    .map(|(backend, backend_health, state)| {
+      let ejections = backend_health
+        .ejections
+        .iter()
+        .rev()
+        .map(|ejection| {
+          format!(
+            "{} ({} failures, {})",
+            format_time(Some(ejection.ejected_at)),
+            ejection.failures,
+            match ejection.restored_at {
+              Some(restored_at) => format!("restored {}", format_time(Some(restored_at))),
+              None => String::from("not restored"),
+            }
+          )
+        })
+        .collect::<Vec<_>>();
+      format!(
+        "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{} / {}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        state,
+        anti_xss(backend),
+        state,
+        backend_health.consecutive_failures,
+        backend_health.total_failures,
+        backend_health.total_requests,
+        format_time(backend_health.last_checked),
+        match backend_health.last_latency {
+          Some(latency) => format!("{:.1} ms", latency.as_secs_f64() * 1000.0),
+          None => String::from("-"),
+        },
+        anti_xss(backend_health.last_error.as_deref().unwrap_or("-")),
+        match ejections.is_empty() {
+          true => String::from("-"),
+          false => ejections.join("<br>"),
+        }
+      )
+    })
+    .collect::<String>();
+  format!(
+    "<table><tr><th>Backend server</th><th>State</th><th>Consecutive failures</th><th>Failures / requests</th><th>Last checked</th><th>Last latency</th><th>Last error</th><th>Ejections</th></tr>{}</table>",
+    rows
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn backend_health_json(backend: &str) -> Value {
+    match upstream_health_json() {
+      Value::Array(backends) => backends
+        .into_iter()
+        .find(|backend_json| backend_json["backend"] == backend)
+        .unwrap_or_default(),
+      _ => Value::Null,
+    }
+  }
+
+  #[test]
+  fn test_upstream_health() {
+    let backend = "http://health-test.invalid:8080";
+    record_backend_failure(backend, Duration::from_millis(5), "error");
+    record_backend_failure(backend, Duration::from_millis(3000), "timeout");
+    let backend_json = backend_health_json(backend);
+    assert_eq!(backend_json["state"], "failing");
+    assert_eq!(backend_json["consecutiveFailures"], 2);
+    assert_eq!(backend_json["lastError"], "timeout");
+    assert_eq!(backend_json["lastLatencyMs"], 3000.0);
+
+    record_backend_ejection(backend, 3);
+    record_backend_ejection(backend, 4);
+    let ejected_backend_json = backend_health_json(backend);
+    assert_eq!(ejected_backend_json["state"], "ejected");
+    assert_eq!(
+      ejected_backend_json["ejections"].as_array().unwrap().len(),
+      1
+    );
+    assert_eq!(ejected_backend_json["ejections"][0]["failures"], 3);
+
+    record_backend_success(backend, Duration::from_millis(10));
+    let restored_backend_json = backend_health_json(backend);
+    assert_eq!(restored_backend_json["state"], "healthy");
+    assert_eq!(restored_backend_json["consecutiveFailures"], 0);
+    assert_eq!(restored_backend_json["totalRequests"], 3);
+    assert_eq!(restored_backend_json["totalFailures"], 2);
+    assert!(restored_backend_json["ejections"][0]["restoredAt"].is_string());
+  }
+
+  #[test]
+  fn test_upstream_health_html() {
+    let backend = "http://html-test.invalid:8080";
+    record_backend_failure(backend, Duration::from_millis(5), "<connection refused>");
+    record_backend_ejection(backend, 1);
+    let html = upstream_health_html();
+    let row = html.split("<tr").find(|row| row.contains(backend)).unwrap();
+    assert!(row.starts_with(" class=\"ejected\""));
+    assert!(row.contains("<td>5.0 ms</td>"));
+    assert!(!row.contains("<connection refused>"));
+    assert!(row.contains("1 failures, not restored"));
+  }
+}
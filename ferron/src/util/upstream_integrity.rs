@@ -0,0 +1,382 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use base64::{engine::general_purpose, Engine};
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::{header, HeaderMap, StatusCode};
+use sha2::{Digest, Sha256, Sha512};
+
+// The digest algorithm used in the "Content-Digest" or "Digest" header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DigestAlgorithm {
+  Sha256,
+  Sha512,
+}
+
+// The digest of the response content declared by the backend server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpectedDigest {
+  algorithm: DigestAlgorithm,
+  value: Vec<u8>,
+}
+
+enum DigestHasher {
+  Sha256(Sha256),
+  Sha512(Sha512),
+}
+
+impl DigestHasher {
+  fn new(algorithm: DigestAlgorithm) -> Self {
+    match algorithm {
+      DigestAlgorithm::Sha256 => DigestHasher::Sha256(Sha256::new()),
+      DigestAlgorithm::Sha512 => DigestHasher::Sha512(Sha512::new()),
+    }
+  }
+
+  fn update(&mut self, data: &[u8]) {
+    match self {
+      DigestHasher::Sha256(hasher) => hasher.update(data),
+      DigestHasher::Sha512(hasher) => hasher.update(data),
+    }
+  }
+
+  fn finalize(self) -> Vec<u8> {
+    match self {
+      DigestHasher::Sha256(hasher) => hasher.finalize().to_vec(),
+      DigestHasher::Sha512(hasher) => hasher.finalize().to_vec(),
+    }
+  }
+}
+
+// Parse the digest of the response content from the "Content-Digest" header (RFC 9530, for example
+// "sha-256=:<base64>:"), or from the legacy "Digest" header (RFC 3230, for example "SHA-256=<base64>").
+// The legacy digest covers the whole representation, so it isn't used for the partial content responses.
+// If several supported digests are declared, the strongest one is used.
+pub fn parse_expected_digest(headers: &HeaderMap, partial_content: bool) -> Option<ExpectedDigest> {
+  let mut digests = Vec::new();
+  for header_value in headers.get_all("content-digest") {
+    for digest in header_value.to_str().unwrap_or_default().split(',') {
+      if let Some((algorithm, value)) = digest.trim().split_once('=') {
+        if let Some(value) = value
+          .trim()
+          .strip_prefix(':')
+          .and_then(|value| value.strip_suffix(':'))
+        {
+          digests.push((algorithm.trim().to_lowercase(), value.to_string()));
+        }
+      }
+    }
+  }
+  if digests.is_empty() && !partial_content {
+    for header_value in headers.get_all("digest") {
+      for digest in header_value.to_str().unwrap_or_default().split(',') {
+        if let Some((algorithm, value)) = digest.trim().split_once('=') {
+          digests.push((algorithm.trim().to_lowercase(), value.trim().to_string()));
+        }
+      }
+    }
+  }
+
+  let mut expected_digest: Option<ExpectedDigest> = None;
+  for (algorithm, value) in digests {
+    let algorithm = match algorithm.as_str() {
+      "sha-256" => DigestAlgorithm::Sha256,
+      "sha-512" => DigestAlgorithm::Sha512,
+      _ => continue,
+    };
+    if expected_digest
+      .as_ref()
+      .is_some_and(|expected_digest| expected_digest.algorithm == DigestAlgorithm::Sha512)
+    {
+      continue;
+    }
+    if let Ok(value) = general_purpose::STANDARD.decode(value) {
+      expected_digest = Some(ExpectedDigest { algorithm, value });
+    }
+  }
+  expected_digest
+}
+
+// Check if the response to the request with the method has a body, whose integrity can be verified
+pub fn has_verifiable_body(is_head_request: bool, status: StatusCode) -> bool {
+  !is_head_request
+    && !status.is_informational()
+    && status != StatusCode::NO_CONTENT
+    && status != StatusCode::NOT_MODIFIED
+}
+
+// A response body wrapper, which verifies that the backend server sent as much content as declared
+// in the "Content-Length" header, and that the content matches the declared digest. If the verification fails,
+// the body ends with an error instead of the last data frame, so the client's connection is aborted
+// and the truncated or corrupted response isn't cached. The digest can also be declared in the trailers.
+pub struct IntegrityBody {
+  inner: BoxBody<Bytes, std::io::Error>,
+  expected_length: Option<u64>,
+  expected_digest: Option<ExpectedDigest>,
+  hasher: Option<DigestHasher>,
+  received: u64,
+  finished: bool,
+  on_failure: Option<Box<dyn FnOnce(String) + Send + Sync>>,
+}
+
+impl IntegrityBody {
+  pub fn new(
+    inner: BoxBody<Bytes, std::io::Error>,
+    headers: &HeaderMap,
+    partial_content: bool,
+    on_failure: impl FnOnce(String) + Send + Sync + 'static,
+  ) -> Self {
+    let expected_digest = parse_expected_digest(headers, partial_content);
+    // The content is hashed only if the digest is declared in the headers, or announced in the "Trailer" header
+    let digest_in_trailers = headers.get_all(header::TRAILER).iter().any(|trailer| {
+      trailer
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .any(|field_name| field_name.trim().eq_ignore_ascii_case("content-digest"))
+    });
+    let hasher = match &expected_digest {
+      Some(expected_digest) => Some(DigestHasher::new(expected_digest.algorithm)),
+      None if digest_in_trailers => Some(DigestHasher::new(DigestAlgorithm::Sha256)),
+      None => None,
+    };
+    Self {
+      inner,
+      expected_length: headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse().ok()),
+      hasher,
+      expected_digest,
+      received: 0,
+      finished: false,
+      on_failure: Some(Box::new(on_failure)),
+    }
+  }
+
+  fn fail(&mut self, reason: String) -> std::io::Error {
+    self.finished = true;
+    if let Some(on_failure) = self.on_failure.take() {
+      on_failure(reason.clone());
+    }
+    std::io::Error::other(reason)
+  }
+
+  // Verify the content once it's received in full. Returns the reason, if the verification failed.
+  fn verify_content(&mut self) -> Option<String> {
+    self.finished = true;
+    if let Some(expected_length) = self.expected_length {
+      if self.received != expected_length {
+        return Some(format!(
+          "the backend server sent {} bytes of the {} bytes declared in the Content-Length header",
+          self.received, expected_length
+        ));
+      }
+    }
+    match (&self.expected_digest, self.hasher.take()) {
+      (Some(expected_digest), Some(hasher)) => {
+        (hasher.finalize() != expected_digest.value).then(|| {
+          String::from(
+            "the response content doesn't match the digest declared by the backend server",
+          )
+        })
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Body for IntegrityBody {
+  type Data = Bytes;
+  type Error = std::io::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.get_mut();
+    if this.finished {
+      return Pin::new(&mut this.inner).poll_frame(cx);
+    }
+    match Pin::new(&mut this.inner).poll_frame(cx) {
+      Poll::Ready(Some(Ok(frame))) => {
+        if let Some(data) = frame.data_ref() {
+          this.received += data.len() as u64;
+          if let Some(hasher) = &mut this.hasher {
+            hasher.update(data);
+          }
+          match this.expected_length {
+            Some(expected_length) if this.received > expected_length => {
+              let reason = format!(
+                "the backend server sent more than {} bytes declared in the Content-Length header",
+                expected_length
+              );
+              return Poll::Ready(Some(Err(this.fail(reason))));
+            }
+            // The last data frame is verified before it's passed on, since the response might be considered
+            // complete (for example, by the cache) once the declared length is reached
+            Some(expected_length) if this.received == expected_length => {
+              if let Some(reason) = this.verify_content() {
+                return Poll::Ready(Some(Err(this.fail(reason))));
+              }
+            }
+            _ => (),
+          }
+        } else if let Some(trailers) = frame.trailers_ref() {
+          if this.expected_digest.is_none() {
+            this.expected_digest = parse_expected_digest(trailers, false);
+            // The content was hashed with SHA-256, so the SHA-512 digest declared in the trailers can't be verified
+            if this
+              .expected_digest
+              .as_ref()
+              .is_some_and(|expected_digest| expected_digest.algorithm != DigestAlgorithm::Sha256)
+            {
+              this.expected_digest = None;
+            }
+          }
+          if let Some(reason) = this.verify_content() {
+            return Poll::Ready(Some(Err(this.fail(reason))));
+          }
+        }
+        Poll::Ready(Some(Ok(frame)))
+      }
+      Poll::Ready(Some(Err(err))) => {
+        let reason = format!(
+          "the response from the backend server was interrupted: {}",
+          err
+        );
+        this.fail(reason);
+        Poll::Ready(Some(Err(err)))
+      }
+      Poll::Ready(None) => match this.verify_content() {
+        Some(reason) => Poll::Ready(Some(Err(this.fail(reason)))),
+        None => Poll::Ready(None),
+      },
+      Poll::Pending => Poll::Pending,
+    }
+  }
+
+  fn is_end_stream(&self) -> bool {
+    // The end of the stream is polled, so that the content is verified
+    self.finished && self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use http_body_util::{BodyExt, StreamBody};
+  use std::sync::{Arc, Mutex};
+
+  fn body_from_frames(frames: Vec<Frame<Bytes>>) -> BoxBody<Bytes, std::io::Error> {
+    StreamBody::new(futures_util::stream::iter(
+      frames.into_iter().map(Ok::<_, std::io::Error>),
+    ))
+    .boxed()
+  }
+
+  fn sha256_digest(content: &[u8]) -> String {
+    general_purpose::STANDARD.encode(Sha256::digest(content))
+  }
+
+  #[test]
+  fn test_parse_expected_digest() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      "content-digest",
+      format!("md5=:AAAA:, sha-256=:{}:", sha256_digest(b"hello"))
+        .parse()
+        .unwrap(),
+    );
+    let expected_digest = parse_expected_digest(&headers, false).unwrap();
+    assert_eq!(expected_digest.algorithm, DigestAlgorithm::Sha256);
+    assert_eq!(expected_digest.value, Sha256::digest(b"hello").to_vec());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      "digest",
+      format!("SHA-256={}", sha256_digest(b"hello"))
+        .parse()
+        .unwrap(),
+    );
+    assert!(parse_expected_digest(&headers, false).is_some());
+    assert!(parse_expected_digest(&headers, true).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_integrity_body() {
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let record_failure = |failures: &Arc<Mutex<Vec<String>>>| {
+      let failures = failures.clone();
+      move |reason| failures.lock().unwrap().push(reason)
+    };
+
+    // The complete response with the matching digest
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_LENGTH, "11".parse().unwrap());
+    headers.insert(
+      "content-digest",
+      format!("sha-256=:{}:", sha256_digest(b"hello world"))
+        .parse()
+        .unwrap(),
+    );
+    let body = IntegrityBody::new(
+      body_from_frames(vec![
+        Frame::data(Bytes::from("hello ")),
+        Frame::data(Bytes::from("world")),
+      ]),
+      &headers,
+      false,
+      record_failure(&failures),
+    );
+    assert_eq!(body.collect().await.unwrap().to_bytes(), "hello world");
+    assert!(failures.lock().unwrap().is_empty());
+
+    // The truncated response
+    let body = IntegrityBody::new(
+      body_from_frames(vec![Frame::data(Bytes::from("hello "))]),
+      &headers,
+      false,
+      record_failure(&failures),
+    );
+    assert!(body.collect().await.is_err());
+    assert_eq!(failures.lock().unwrap().len(), 1);
+
+    // The corrupted response
+    let body = IntegrityBody::new(
+      body_from_frames(vec![Frame::data(Bytes::from("hello_world"))]),
+      &headers,
+      false,
+      record_failure(&failures),
+    );
+    assert!(body.collect().await.is_err());
+    assert_eq!(failures.lock().unwrap().len(), 2);
+
+    // The digest declared in the trailers
+    let mut trailer_headers = HeaderMap::new();
+    trailer_headers.insert(header::TRAILER, "Content-Digest".parse().unwrap());
+    let mut trailers = HeaderMap::new();
+    trailers.insert(
+      "content-digest",
+      format!("sha-256=:{}:", sha256_digest(b"other"))
+        .parse()
+        .unwrap(),
+    );
+    let body = IntegrityBody::new(
+      body_from_frames(vec![
+        Frame::data(Bytes::from("hello")),
+        Frame::trailers(trailers),
+      ]),
+      &trailer_headers,
+      false,
+      record_failure(&failures),
+    );
+    assert!(body.collect().await.is_err());
+    assert_eq!(failures.lock().unwrap().len(), 3);
+  }
+}
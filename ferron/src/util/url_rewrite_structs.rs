@@ -1,5 +1,8 @@
 use fancy_regex::Regex;
 
+use crate::ferron_util::expression::Expression;
+use crate::ferron_util::match_location::LocationMatcher;
+
 pub struct UrlRewriteMapEntry {
   pub regex: Regex,
   pub replacement: String,
@@ -7,6 +10,7 @@ pub struct UrlRewriteMapEntry {
   pub is_not_file: bool,
   pub last: bool,
   pub allow_double_slashes: bool,
+  pub condition: Option<Expression>,
 }
 
 impl UrlRewriteMapEntry {
@@ -17,6 +21,7 @@ impl UrlRewriteMapEntry {
     is_not_file: bool,
     last: bool,
     allow_double_slashes: bool,
+    condition: Option<Expression>,
   ) -> Self {
     UrlRewriteMapEntry {
       regex,
@@ -25,6 +30,7 @@ impl UrlRewriteMapEntry {
       is_not_file,
       last,
       allow_double_slashes,
+      condition,
     }
   }
 }
@@ -53,12 +59,15 @@ impl UrlRewriteMapWrap {
 }
 
 pub struct UrlRewriteMapLocationWrap {
-  pub path: String,
+  pub matcher: LocationMatcher,
   pub rewrite_map: Vec<UrlRewriteMapEntry>,
 }
 
 impl UrlRewriteMapLocationWrap {
-  pub fn new(path: String, rewrite_map: Vec<UrlRewriteMapEntry>) -> Self {
-    UrlRewriteMapLocationWrap { path, rewrite_map }
+  pub fn new(matcher: LocationMatcher, rewrite_map: Vec<UrlRewriteMapEntry>) -> Self {
+    UrlRewriteMapLocationWrap {
+      matcher,
+      rewrite_map,
+    }
   }
 }
@@ -0,0 +1,112 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Create the HMAC-SHA256 instance for a URL path with an expiration timestamp.
+// The same message format is used by the "ferron-urlsign" utility.
+fn url_signature_mac(secret: &[u8], path: &str, expires: u64) -> HmacSha256 {
+  let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC can take a key of any size");
+  mac.update(format!("{}\n{}", path, expires).as_bytes());
+  mac
+}
+
+// Verify the hexadecimal signature of a URL path in constant time
+pub fn verify_url_signature(secret: &[u8], path: &str, expires: u64, signature: &str) -> bool {
+  let signature_bytes = match decode_hex(signature) {
+    Some(signature_bytes) => signature_bytes,
+    None => return false,
+  };
+  url_signature_mac(secret, path, expires)
+    .verify_slice(&signature_bytes)
+    .is_ok()
+}
+
+// Extract the signature and the expiration timestamp from the query string
+pub fn parse_signature_query(query: &str) -> (Option<&str>, Option<u64>) {
+  let mut signature = None;
+  let mut expires = None;
+  for query_part in query.split('&') {
+    match query_part.split_once('=') {
+      Some(("sig", value)) => signature = Some(value),
+      Some(("exp", value)) => expires = value.parse::<u64>().ok(),
+      _ => (),
+    }
+  }
+  (signature, expires)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+  if !hex.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sign_url_path(secret: &[u8], path: &str, expires: u64) -> String {
+    url_signature_mac(secret, path, expires)
+      .finalize()
+      .into_bytes()
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect()
+  }
+
+  #[test]
+  fn test_sign_and_verify() {
+    let signature = sign_url_path(b"secret", "/downloads/file.zip", 1700000000);
+    assert_eq!(signature.len(), 64);
+    assert!(verify_url_signature(
+      b"secret",
+      "/downloads/file.zip",
+      1700000000,
+      &signature
+    ));
+  }
+
+  #[test]
+  fn test_verify_rejects_tampering() {
+    let signature = sign_url_path(b"secret", "/downloads/file.zip", 1700000000);
+    assert!(!verify_url_signature(
+      b"secret",
+      "/downloads/other.zip",
+      1700000000,
+      &signature
+    ));
+    assert!(!verify_url_signature(
+      b"secret",
+      "/downloads/file.zip",
+      1800000000,
+      &signature
+    ));
+    assert!(!verify_url_signature(
+      b"other-secret",
+      "/downloads/file.zip",
+      1700000000,
+      &signature
+    ));
+    assert!(!verify_url_signature(
+      b"secret",
+      "/downloads/file.zip",
+      1700000000,
+      "not-hex"
+    ));
+  }
+
+  #[test]
+  fn test_parse_signature_query() {
+    assert_eq!(
+      parse_signature_query("exp=1700000000&sig=abcd&foo=bar"),
+      (Some("abcd"), Some(1700000000))
+    );
+    assert_eq!(parse_signature_query("foo=bar"), (None, None));
+    assert_eq!(parse_signature_query("exp=soon"), (None, None));
+  }
+}
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use ferron_common::ServerConfigRoot;
+use tokio::fs;
+
+// The default directory containing the users' home directories
+const DEFAULT_USER_DIRECTORY_HOME: &str = "/home";
+
+// The result of mapping a "/~user/..." request path to the user's public directory
+#[derive(Debug, PartialEq)]
+pub enum UserDirectory<'a> {
+  // The request path doesn't point to a user directory, or the user directories are disabled
+  None,
+  // The user name is invalid, or the user isn't allowed to serve files
+  Denied,
+  // The request path points into a user directory. The relative path is still URL-encoded.
+  Resolved {
+    root: PathBuf,
+    relative_path: &'a str,
+  },
+}
+
+// Check if the user name is safe to use as a path component
+fn is_valid_user_name(user_name: &str) -> bool {
+  !user_name.is_empty()
+    && user_name.len() <= 32
+    && !user_name.starts_with(['.', '-'])
+    && user_name
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'-' | b'.'))
+}
+
+fn user_list_contains(config: &ServerConfigRoot, property: &str, user_name: &str) -> Option<bool> {
+  config
+    .get(property)
+    .as_vec()
+    .map(|users| users.iter().any(|user| user.as_str() == Some(user_name)))
+}
+
+// Map the request path to the user's public directory, like "/~alice/page.html" to "/home/alice/public_html/page.html"
+pub fn resolve_user_directory<'a>(
+  config: &ServerConfigRoot,
+  request_path: &'a str,
+) -> UserDirectory<'a> {
+  let public_directory = match config.get("userDirectory").as_str() {
+    Some(public_directory) => public_directory.to_string(),
+    None => return UserDirectory::None,
+  };
+
+  let path_without_slash = match request_path.strip_prefix('/') {
+    Some(path) => path,
+    None => return UserDirectory::None,
+  };
+  let (user_segment, relative_path) = match path_without_slash.split_once('/') {
+    Some((user_segment, relative_path)) => (user_segment, relative_path),
+    None => (path_without_slash, ""),
+  };
+  let user_name = match urlencoding::decode(user_segment) {
+    Ok(user_segment) => match user_segment.strip_prefix('~') {
+      Some(user_name) => user_name.to_string(),
+      None => return UserDirectory::None,
+    },
+    Err(_) => return UserDirectory::None,
+  };
+
+  // The root user's home directory is never served
+  if !is_valid_user_name(&user_name)
+    || user_name == "root"
+    || user_list_contains(config, "userDirectoryEnabledUsers", &user_name) == Some(false)
+    || user_list_contains(config, "userDirectoryDisabledUsers", &user_name) == Some(true)
+  {
+    return UserDirectory::Denied;
+  }
+
+  let home = config.get("userDirectoryHome");
+  UserDirectory::Resolved {
+    root: Path::new(home.as_str().unwrap_or(DEFAULT_USER_DIRECTORY_HOME))
+      .join(user_name)
+      .join(public_directory),
+    relative_path: relative_path.trim_start_matches('/'),
+  }
+}
+
+// Check if the path is inside of the user directory after resolving the symbolic links,
+// so that the users can't publish the files outside of their public directories
+pub async fn is_inside_user_directory(root: &Path, path: &Path) -> bool {
+  match (fs::canonicalize(root).await, fs::canonicalize(path).await) {
+    (Ok(root), Ok(path)) => path.starts_with(root),
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(yaml: &str) -> ServerConfigRoot {
+    ServerConfigRoot::new(
+      &yaml_rust2::YamlLoader::load_from_str(yaml)
+        .unwrap()
+        .remove(0),
+    )
+  }
+
+  #[test]
+  fn test_resolve_user_directory() {
+    let config = config("userDirectory: public_html");
+    assert_eq!(
+      resolve_user_directory(&config, "/~alice/docs/a%20b.html"),
+      UserDirectory::Resolved {
+        root: PathBuf::from("/home/alice/public_html"),
+        relative_path: "docs/a%20b.html"
+      }
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/%7Ebob"),
+      UserDirectory::Resolved {
+        root: PathBuf::from("/home/bob/public_html"),
+        relative_path: ""
+      }
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/alice/index.html"),
+      UserDirectory::None
+    );
+    assert_eq!(
+      resolve_user_directory(&ServerConfigRoot::from_hash(Default::default()), "/~alice/"),
+      UserDirectory::None
+    );
+  }
+
+  #[test]
+  fn test_denied_users() {
+    let config = config(
+      "userDirectory: www\nuserDirectoryHome: /srv/users\nuserDirectoryEnabledUsers: [alice, bob]\nuserDirectoryDisabledUsers: [bob]",
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/~alice/"),
+      UserDirectory::Resolved {
+        root: PathBuf::from("/srv/users/alice/www"),
+        relative_path: ""
+      }
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/~bob/"),
+      UserDirectory::Denied
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/~carol/"),
+      UserDirectory::Denied
+    );
+    let config = self::config("userDirectory: public_html");
+    assert_eq!(
+      resolve_user_directory(&config, "/~root/"),
+      UserDirectory::Denied
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/~../etc"),
+      UserDirectory::Denied
+    );
+    assert_eq!(
+      resolve_user_directory(&config, "/~a%2Fb/"),
+      UserDirectory::Denied
+    );
+  }
+
+  #[tokio::test]
+  async fn test_is_inside_user_directory() {
+    let root =
+      std::env::temp_dir().join(format!("ferron-user-directory-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("public")).await.unwrap();
+    fs::write(root.join("secret.txt"), "secret").await.unwrap();
+    fs::write(root.join("public/index.html"), "index")
+      .await
+      .unwrap();
+
+    let inside =
+      is_inside_user_directory(&root.join("public"), &root.join("public/index.html")).await;
+    let outside =
+      is_inside_user_directory(&root.join("public"), &root.join("public/../secret.txt")).await;
+    fs::remove_dir_all(&root).await.unwrap();
+
+    assert!(inside);
+    assert!(!outside);
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn test_language_variant_link_outside_user_directory() {
+    use crate::ferron_util::language_negotiation::get_language_variant_path;
+
+    let root = std::env::temp_dir().join(format!(
+      "ferron-user-directory-variant-test-{}",
+      std::process::id()
+    ));
+    fs::create_dir_all(root.join("public")).await.unwrap();
+    fs::write(root.join("secret.html"), "secret").await.unwrap();
+    fs::write(root.join("public/index.html"), "index")
+      .await
+      .unwrap();
+    // The language variant of a file in the directory is a link to a file outside of it
+    std::os::unix::fs::symlink(root.join("secret.html"), root.join("public/index.de.html"))
+      .unwrap();
+
+    let file_path = root.join("public/index.html");
+    let variant_path = get_language_variant_path(&file_path, "de").unwrap();
+    let file_inside = is_inside_user_directory(&root.join("public"), &file_path).await;
+    let variant_inside = is_inside_user_directory(&root.join("public"), &variant_path).await;
+    fs::remove_dir_all(&root).await.unwrap();
+
+    assert!(file_inside);
+    assert!(!variant_inside);
+  }
+}
@@ -1,10 +1,33 @@
-use ferron_common::ServerConfigRoot;
+use fancy_regex::Regex;
+use ferron_common::{ErrorLogLevel, ServerConfigRoot};
 use hyper::header::{HeaderName, HeaderValue};
+use std::collections::HashSet;
 use std::error::Error;
 use std::net::IpAddr;
 use std::str::FromStr;
 use yaml_rust2::Yaml;
 
+use crate::ferron_util::access_policy::{validate_access_policy, AccessAction};
+use crate::ferron_util::admin_api::parse_admin_address;
+use crate::ferron_util::cache_prewarm::PrewarmJob;
+use crate::ferron_util::client_auth::ClientAuthConfig;
+use crate::ferron_util::client_hints::is_device_class_list;
+use crate::ferron_util::deployment::parse_deployment_targets;
+use crate::ferron_util::dns_resolver::parse_dns_upstream;
+use crate::ferron_util::experiments::parse_experiments;
+use crate::ferron_util::expression::Expression;
+use crate::ferron_util::forward_proxy_auth::parse_destination_rules;
+use crate::ferron_util::forwarded::ClientIpHeader;
+use crate::ferron_util::ip_prefix_trie::IpPrefixTrie;
+use crate::ferron_util::load_config::ConfigOrigins;
+use crate::ferron_util::load_listeners::ListenerFamily;
+use crate::ferron_util::load_tls::certificate_key_paths;
+use crate::ferron_util::log_file::is_valid_log_file_path;
+use crate::ferron_util::log_sinks::{parse_access_log_sinks, parse_error_log_sinks};
+use crate::ferron_util::tls_policy::{TlsPolicy, TlsPreset};
+use crate::ferron_util::upstream_proxy::UpstreamProxy;
+use crate::ferron_util::waf::{is_builtin_waf_rule_set, parse_waf_rule_set};
+
 fn validate_ip(ip: &str) -> bool {
   let _: IpAddr = match ip.parse() {
     Ok(addr) => addr,
@@ -13,6 +36,26 @@ fn validate_ip(ip: &str) -> bool {
   true
 }
 
+// Check if the value is a path or a non-empty list of paths
+fn is_path_or_path_list(value: &Yaml) -> bool {
+  match value {
+    Yaml::String(_) => true,
+    Yaml::Array(paths) => !paths.is_empty() && paths.iter().all(|path| path.as_str().is_some()),
+    _ => false,
+  }
+}
+
+// Check if the value is a list of ISO 3166-1 alpha-2 country codes
+fn is_country_code_list(value: &Yaml) -> bool {
+  value.as_vec().is_some_and(|countries| {
+    countries.iter().all(|country| {
+      country.as_str().is_some_and(|country| {
+        country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic())
+      })
+    })
+  })
+}
+
 // Internal configuration file validators
 pub fn validate_config(
   config: &ServerConfigRoot,
@@ -57,6 +100,79 @@ pub fn validate_config(
     }
   }
 
+  if !config.get("pathRegex").is_badvalue() {
+    if !is_location {
+      Err(anyhow::anyhow!(
+        "Location path configuration is only allowed in location configuration"
+      ))?;
+    }
+    if !config.get("path").is_badvalue() {
+      Err(anyhow::anyhow!(
+        "A location can't have both a path and a path regular expression specified"
+      ))?;
+    }
+    match config.get("pathRegex").as_str() {
+      Some(path_regex) => {
+        if let Err(err) = Regex::new(path_regex) {
+          Err(anyhow::anyhow!(
+            "Invalid location path regular expression: {}",
+            err
+          ))?;
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid location path regular expression"))?,
+    }
+  }
+
+  if !config.get("country").is_badvalue() {
+    if is_global {
+      Err(anyhow::anyhow!(
+        "Country matching configuration is not allowed in global configuration"
+      ))?;
+    }
+    let country_yaml = config.get("country");
+    let countries = match country_yaml.as_vec() {
+      Some(countries) => countries.clone(),
+      None => vec![country_yaml.clone()],
+    };
+    for country in countries {
+      if !country.as_str().is_some_and(|country| {
+        country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic())
+      }) {
+        Err(anyhow::anyhow!("Invalid country code"))?;
+      }
+    }
+  }
+
+  if !config.get("deviceClass").is_badvalue() {
+    if is_global {
+      Err(anyhow::anyhow!(
+        "Device class matching configuration is not allowed in global configuration"
+      ))?;
+    }
+    if !is_device_class_list(&config.get("deviceClass")) {
+      Err(anyhow::anyhow!(
+        "Invalid device class (the device class must be \"mobile\", \"tablet\" or \"desktop\")"
+      ))?;
+    }
+  }
+
+  if !config.get("clientHints").is_badvalue() {
+    match config.get("clientHints").as_vec() {
+      Some(client_hints) => {
+        for client_hint in client_hints {
+          if client_hint
+            .as_str()
+            .is_none_or(|client_hint| HeaderName::from_str(client_hint).is_err())
+          {
+            Err(anyhow::anyhow!("Invalid client hint header name"))?;
+          }
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid client hints configuration"))?,
+    }
+  }
+
   if !config.get("locations").is_badvalue() && is_location {
     Err(anyhow::anyhow!("Nested locations are not allowed"))?;
   }
@@ -109,6 +225,447 @@ pub fn validate_config(
     }
   }
 
+  if !config.get("listeners").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Listener configuration is not allowed in host configuration"
+      ))?
+    }
+    if let Some(listeners) = config.get("listeners").as_vec() {
+      let listeners_iter = listeners.iter();
+      for listener_yaml in listeners_iter {
+        if !listener_yaml.is_hash() {
+          Err(anyhow::anyhow!("Invalid listener configuration"))?
+        }
+        let family = match listener_yaml["family"].as_str() {
+          Some(family) => match ListenerFamily::parse(family) {
+            Some(family) => Some(family),
+            None => Err(anyhow::anyhow!("Invalid listener address family"))?,
+          },
+          None if !listener_yaml["family"].is_badvalue() => {
+            Err(anyhow::anyhow!("Invalid listener address family"))?
+          }
+          None => None,
+        };
+        if !listener_yaml["address"].is_badvalue() {
+          if let Some(address) = listener_yaml["address"].as_str() {
+            // The listener address is either an IP address or a host name
+            if let Ok(ip) = address.parse::<IpAddr>() {
+              if family.is_some_and(|family| !family.allows(&ip)) {
+                Err(anyhow::anyhow!(
+                  "The listener address doesn't match the listener address family"
+                ))?
+              }
+            } else if address.is_empty()
+              || !address
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+            {
+              Err(anyhow::anyhow!("Invalid listener address"))?
+            }
+          } else {
+            Err(anyhow::anyhow!("Invalid listener address"))?
+          }
+        }
+        if !listener_yaml["port"].is_badvalue() {
+          if let Some(port) = listener_yaml["port"].as_i64() {
+            if !(0..=65535).contains(&port) {
+              Err(anyhow::anyhow!("Invalid listener port"))?
+            }
+          } else {
+            Err(anyhow::anyhow!("Invalid listener port"))?
+          }
+        }
+        if !listener_yaml["secure"].is_badvalue() && listener_yaml["secure"].as_bool().is_none() {
+          Err(anyhow::anyhow!(
+            "Invalid listener HTTPS enabling option value"
+          ))?
+        }
+        if !listener_yaml["enableHTTP2"].is_badvalue()
+          && listener_yaml["enableHTTP2"].as_bool().is_none()
+        {
+          Err(anyhow::anyhow!(
+            "Invalid listener HTTP/2 enabling option value"
+          ))?
+        }
+        if !listener_yaml["bindDevice"].is_badvalue()
+          && listener_yaml["bindDevice"].as_str().is_none()
+        {
+          Err(anyhow::anyhow!("Invalid listener network device"))?
+        }
+        if let Err(err) = ClientAuthConfig::from_yaml(listener_yaml) {
+          Err(anyhow::anyhow!(
+            "Invalid listener client authentication configuration: {}",
+            err
+          ))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid listener configuration"))?
+    }
+  }
+
+  if !config.get("user").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "User configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("user").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid user"))?
+    }
+  }
+
+  if !config.get("group").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Group configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("group").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid group"))?
+    }
+  }
+
+  if !config.get("chroot").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Chroot configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("chroot").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid chroot directory"))?
+    }
+  }
+
+  if !config.get("geoipDatabase").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "GeoIP database configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("geoipDatabase").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid GeoIP database path"))?
+    }
+  }
+
+  if !config.get("geoipAsnDatabase").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "GeoIP ASN database configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("geoipAsnDatabase").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid GeoIP ASN database path"))?
+    }
+  }
+
+  if !config.get("geoipReloadInterval").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "GeoIP database reload interval configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("geoipReloadInterval")
+      .as_i64()
+      .is_none_or(|reload_interval| reload_interval <= 0)
+    {
+      Err(anyhow::anyhow!("Invalid GeoIP database reload interval"))?
+    }
+  }
+
+  if !config.get("geoipAllowCountries").is_badvalue()
+    && !is_country_code_list(&config.get("geoipAllowCountries"))
+  {
+    Err(anyhow::anyhow!("Invalid GeoIP allowed country list"))?
+  }
+
+  if !config.get("geoipDenyCountries").is_badvalue()
+    && !is_country_code_list(&config.get("geoipDenyCountries"))
+  {
+    Err(anyhow::anyhow!("Invalid GeoIP denied country list"))?
+  }
+
+  if !config.get("accessPolicy").is_badvalue() {
+    validate_access_policy(&config.get("accessPolicy"))?;
+  }
+
+  if !config.get("accessPolicyDefault").is_badvalue()
+    && config
+      .get("accessPolicyDefault")
+      .as_str()
+      .and_then(AccessAction::parse)
+      .is_none()
+  {
+    Err(anyhow::anyhow!("Invalid default access policy action"))?
+  }
+
+  if !config.get("logAccessDecisions").is_badvalue()
+    && config.get("logAccessDecisions").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid access decision logging enabling option value"
+    ))?
+  }
+
+  if !config.get("logGeoIpFields").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Access log GeoIP field configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("logGeoIpFields").as_bool().is_none() {
+      Err(anyhow::anyhow!(
+        "Invalid access log GeoIP field enabling option value"
+      ))?
+    }
+  }
+
+  if !config.get("workerProcesses").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Worker process configuration is not allowed in host configuration"
+      ))?
+    }
+    if let Some(worker_processes) = config.get("workerProcesses").as_i64() {
+      if worker_processes < 0 {
+        Err(anyhow::anyhow!("Invalid number of worker processes"))?
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid number of worker processes"))?
+    }
+  }
+
+  if !config.get("metricsPath").is_badvalue() && config.get("metricsPath").as_str().is_none() {
+    Err(anyhow::anyhow!("Invalid metrics endpoint path"))?
+  }
+
+  for auto_ban_property in ["autoBanThreshold", "autoBanWindow", "autoBanDuration"] {
+    if !config.get(auto_ban_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Auto-ban configuration is not allowed in host configuration"
+        ))?
+      }
+      if let Some(auto_ban_value) = config.get(auto_ban_property).as_i64() {
+        if auto_ban_value < 0 {
+          Err(anyhow::anyhow!("Invalid auto-ban configuration"))?
+        }
+      } else {
+        Err(anyhow::anyhow!("Invalid auto-ban configuration"))?
+      }
+    }
+  }
+
+  if !config.get("autoBanMode").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Auto-ban configuration is not allowed in host configuration"
+      ))?
+    }
+    if !matches!(
+      config.get("autoBanMode").as_str(),
+      Some("enforce" | "observe")
+    ) {
+      Err(anyhow::anyhow!("Invalid auto-ban mode"))?
+    }
+  }
+
+  for limit_property in [
+    "maxConnections",
+    "maxConnectionsPerIP",
+    "maxRequests",
+    "maxRequestsPerIP",
+  ] {
+    if !config.get(limit_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Connection and request limits are not allowed in host configuration"
+        ))?
+      }
+      if let Some(limit) = config.get(limit_property).as_i64() {
+        if limit < 0 {
+          Err(anyhow::anyhow!("Invalid connection or request limit"))?
+        }
+      } else {
+        Err(anyhow::anyhow!("Invalid connection or request limit"))?
+      }
+    }
+  }
+
+  for limit_mode_property in ["connectionLimitMode", "requestLimitMode"] {
+    if !config.get(limit_mode_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Connection and request limits are not allowed in host configuration"
+        ))?
+      }
+      if !matches!(
+        config.get(limit_mode_property).as_str(),
+        Some("enforce" | "observe")
+      ) {
+        Err(anyhow::anyhow!("Invalid connection or request limit mode"))?
+      }
+    }
+  }
+
+  for blocking_threads_property in ["maxBlockingThreads", "moduleBlockingThreads"] {
+    if !config.get(blocking_threads_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Blocking thread pool configuration is not allowed in host configuration"
+        ))?
+      }
+      if config
+        .get(blocking_threads_property)
+        .as_i64()
+        .is_none_or(|value| value <= 0)
+      {
+        Err(anyhow::anyhow!("Invalid blocking thread pool size"))?
+      }
+    }
+  }
+
+  if !config.get("tempDirectory").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Temporary file directory configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("tempDirectory").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid temporary file directory"))?
+    }
+  }
+
+  if !config.get("maxTempFilesSize").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Temporary file size limit configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("maxTempFilesSize")
+      .as_i64()
+      .is_none_or(|value| value < 0)
+    {
+      Err(anyhow::anyhow!(
+        "Invalid maximum total size of temporary files"
+      ))?
+    }
+  }
+
+  if !config.get("tempFilesSync").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Temporary file synchronization configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("tempFilesSync").as_bool().is_none() {
+      Err(anyhow::anyhow!(
+        "Invalid temporary file synchronization option"
+      ))?
+    }
+  }
+
+  if !config.get("storageBackend").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Storage backend configuration is not allowed in host configuration"
+      ))?
+    }
+    match config.get("storageBackend").as_str() {
+      Some("memory") | Some("redis") => (),
+      Some("disk") => {
+        if config.get("storageDirectory").is_badvalue() {
+          Err(anyhow::anyhow!(
+            "The storage directory must be specified for the disk storage"
+          ))?
+        }
+      }
+      _ => Err(anyhow::anyhow!("Invalid storage backend"))?,
+    }
+  }
+
+  for storage_string_property in [
+    "storageDirectory",
+    "storageRedisAddress",
+    "storageRedisPassword",
+  ] {
+    if !config.get(storage_string_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Storage backend configuration is not allowed in host configuration"
+        ))?
+      }
+      if config.get(storage_string_property).as_str().is_none() {
+        Err(anyhow::anyhow!(
+          "Invalid storage backend option \"{}\"",
+          storage_string_property
+        ))?
+      }
+    }
+  }
+
+  if !config.get("storageRedisDatabase").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Storage backend configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("storageRedisDatabase")
+      .as_i64()
+      .is_none_or(|value| value < 0)
+    {
+      Err(anyhow::anyhow!("Invalid Redis database number"))?
+    }
+  }
+
+  for fair_queueing_property in ["fairQueueingRate", "fairQueueingMaxRequests"] {
+    if !config.get(fair_queueing_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Fair queueing configuration is not allowed in host configuration"
+        ))?
+      }
+      if config
+        .get(fair_queueing_property)
+        .as_i64()
+        .is_none_or(|value| value <= 0)
+      {
+        Err(anyhow::anyhow!("Invalid fair queueing configuration"))?
+      }
+    }
+  }
+
+  if !config.get("fairShareWeight").is_badvalue()
+    && config
+      .get("fairShareWeight")
+      .as_i64()
+      .is_none_or(|weight| weight <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid fair share weight"))?
+  }
+
+  for bandwidth_limit_property in [
+    "downloadLimitPerConnection",
+    "downloadLimitPerIp",
+    "downloadLimitPerHost",
+    "uploadLimitPerConnection",
+    "uploadLimitPerIp",
+    "uploadLimitPerHost",
+  ] {
+    if !config.get(bandwidth_limit_property).is_badvalue()
+      && config
+        .get(bandwidth_limit_property)
+        .as_i64()
+        .is_none_or(|value| value <= 0)
+    {
+      Err(anyhow::anyhow!("Invalid bandwidth limit"))?
+    }
+  }
+
   if !config.get("secure").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
@@ -121,9 +678,10 @@ pub fn validate_config(
   }
 
   if !config.get("enableHTTP2").is_badvalue() {
-    if !is_global {
+    // HTTP/2 is negotiated during the TLS handshake, so it can be enabled per host, but not per location
+    if is_location {
       Err(anyhow::anyhow!(
-        "HTTP/2 enabling configuration is not allowed in host configuration"
+        "HTTP/2 enabling configuration is not allowed in location configuration"
       ))?
     }
     if config.get("enableHTTP2").as_bool().is_none() {
@@ -137,29 +695,275 @@ pub fn validate_config(
         "Log file configuration is not allowed in host configuration"
       ))?
     }
-    if config.get("logFilePath").as_str().is_none() {
+    if !config
+      .get("logFilePath")
+      .as_str()
+      .is_some_and(is_valid_log_file_path)
+    {
       Err(anyhow::anyhow!("Invalid log file path"))?
     }
   }
 
+  if !config.get("logFormat").is_badvalue() && config.get("logFormat").as_str().is_none() {
+    Err(anyhow::anyhow!("Invalid access log format"))?
+  }
+
+  if !config.get("logging").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Logging configuration is not allowed in host configuration"
+      ))?
+    }
+    parse_access_log_sinks(&config.get("logging"))?;
+    parse_error_log_sinks(&config.get("logging"))?;
+  }
+
+  if !config.get("logIpAnonymization").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Access log IP address anonymization configuration is not allowed in host configuration"
+      ))?
+    }
+    if !matches!(
+      config.get("logIpAnonymization").as_str(),
+      Some("none") | Some("truncate") | Some("hash")
+    ) {
+      Err(anyhow::anyhow!(
+        "Invalid access log IP address anonymization mode (must be \"none\", \"truncate\" or \"hash\")"
+      ))?
+    }
+  }
+
+  if !config.get("logOmitQueryString").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Access log query string omission configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("logOmitQueryString").as_bool().is_none() {
+      Err(anyhow::anyhow!(
+        "Invalid access log query string omission option value"
+      ))?
+    }
+  }
+
+  if !config.get("logRedactQueryParameters").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Access log query parameter redaction configuration is not allowed in host configuration"
+      ))?
+    }
+    if let Some(parameters) = config.get("logRedactQueryParameters").as_vec() {
+      for parameter in parameters {
+        if parameter.as_str().is_none() {
+          Err(anyhow::anyhow!(
+            "Invalid access log redacted query parameter name"
+          ))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "Invalid access log redacted query parameters configuration"
+      ))?
+    }
+  }
+
+  if !config.get("logOmitUserAgent").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Access log user agent omission configuration is not allowed in host configuration"
+      ))?
+    }
+    if config.get("logOmitUserAgent").as_bool().is_none() {
+      Err(anyhow::anyhow!(
+        "Invalid access log user agent omission option value"
+      ))?
+    }
+  }
+
+  if !config.get("resolver").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "DNS resolver configuration is not allowed in host configuration"
+      ))?
+    }
+    let resolver_yaml = config.get("resolver");
+    if resolver_yaml.as_hash().is_none() {
+      Err(anyhow::anyhow!("Invalid DNS resolver configuration"))?
+    }
+    if let Some(servers) = resolver_yaml["servers"].as_vec() {
+      if servers.is_empty() {
+        Err(anyhow::anyhow!(
+          "The DNS resolver configuration doesn't specify any DNS servers"
+        ))?
+      }
+      for server in servers {
+        match server.as_str() {
+          Some(server) => {
+            parse_dns_upstream(server)?;
+          }
+          None => Err(anyhow::anyhow!("Invalid DNS server"))?,
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "The DNS resolver configuration doesn't specify any DNS servers"
+      ))?
+    }
+    if !resolver_yaml["timeout"].is_badvalue()
+      && resolver_yaml["timeout"]
+        .as_i64()
+        .is_none_or(|timeout| timeout <= 0)
+    {
+      Err(anyhow::anyhow!("Invalid DNS resolver timeout"))?
+    }
+    if !resolver_yaml["negativeCacheTTL"].is_badvalue()
+      && resolver_yaml["negativeCacheTTL"]
+        .as_i64()
+        .is_none_or(|ttl| ttl < 0)
+    {
+      Err(anyhow::anyhow!("Invalid DNS resolver negative cache TTL"))?
+    }
+  }
+
+  if !config.get("adminApi").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Admin API configuration is not allowed in host configuration"
+      ))?
+    }
+    let admin_api_yaml = config.get("adminApi");
+    if admin_api_yaml.as_hash().is_none() {
+      Err(anyhow::anyhow!("Invalid admin API configuration"))?
+    }
+    match admin_api_yaml["listen"].as_str() {
+      Some(address) => {
+        parse_admin_address(address)?;
+      }
+      None => Err(anyhow::anyhow!(
+        "The admin API listen address isn't specified"
+      ))?,
+    }
+    if admin_api_yaml["token"]
+      .as_str()
+      .is_none_or(|token| token.is_empty())
+    {
+      Err(anyhow::anyhow!("The admin API token isn't specified"))?
+    }
+    parse_deployment_targets(&admin_api_yaml)?;
+  }
+
+  if !config.get("cachePrewarmJobs").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Cache prewarming jobs configuration is not allowed in host configuration"
+      ))?
+    }
+    match config.get("cachePrewarmJobs").as_vec() {
+      Some(prewarm_jobs) => {
+        let mut job_names = HashSet::new();
+        for prewarm_job_yaml in prewarm_jobs {
+          let prewarm_job = PrewarmJob::from_yaml(prewarm_job_yaml)?;
+          if !job_names.insert(prewarm_job.name.clone()) {
+            Err(anyhow::anyhow!(
+              "Duplicate cache prewarming job name: {}",
+              prewarm_job.name
+            ))?
+          }
+        }
+      }
+      None => Err(anyhow::anyhow!(
+        "Invalid cache prewarming jobs configuration"
+      ))?,
+    }
+  }
+
   if !config.get("errorLogFilePath").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
         "Error log file configuration is not allowed in host configuration"
       ))?
     }
-    if config.get("errorLogFilePath").as_str().is_none() {
+    if !config
+      .get("errorLogFilePath")
+      .as_str()
+      .is_some_and(is_valid_log_file_path)
+    {
       Err(anyhow::anyhow!("Invalid error log file path"))?
     }
   }
 
+  if !config.get("errorLogDeduplicationWindow").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Error log deduplication configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("errorLogDeduplicationWindow")
+      .as_i64()
+      .is_none_or(|window| window < 0)
+    {
+      Err(anyhow::anyhow!("Invalid error log deduplication window"))?
+    }
+  }
+
+  if !config.get("errorLogRateLimit").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Error log rate limit configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("errorLogRateLimit")
+      .as_i64()
+      .is_none_or(|rate_limit| rate_limit <= 0)
+    {
+      Err(anyhow::anyhow!("Invalid error log rate limit"))?
+    }
+  }
+
+  if !config.get("errorLogLevel").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Error log level configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("errorLogLevel")
+      .as_str()
+      .and_then(ErrorLogLevel::parse)
+      .is_none()
+    {
+      Err(anyhow::anyhow!("Invalid error log level"))?
+    }
+  }
+
+  if !config.get("moduleLogLevels").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Module log level configuration is not allowed in host configuration"
+      ))?
+    }
+    match config.get("moduleLogLevels").as_hash() {
+      Some(module_log_levels) => {
+        for (module, level) in module_log_levels {
+          if module.as_str().is_none() || level.as_str().and_then(ErrorLogLevel::parse).is_none() {
+            Err(anyhow::anyhow!("Invalid module log level"))?
+          }
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid module log levels"))?,
+    }
+  }
+
   if !config.get("cert").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
         "TLS certificate configuration is not allowed in host configuration"
       ))?
     }
-    if config.get("cert").as_str().is_none() {
+    if !is_path_or_path_list(&config.get("cert")) {
       Err(anyhow::anyhow!("Invalid TLS certificate path"))?
     }
   }
@@ -170,9 +974,16 @@ pub fn validate_config(
         "Private key configuration is not allowed in host configuration"
       ))?
     }
-    if config.get("key").as_str().is_none() {
+    if !is_path_or_path_list(&config.get("key")) {
       Err(anyhow::anyhow!("Invalid private key path"))?
     }
+    if !config.get("cert").is_badvalue()
+      && certificate_key_paths(&config.get("cert"), &config.get("key")).is_none()
+    {
+      Err(anyhow::anyhow!(
+        "The TLS certificate and private key paths must be both single paths or lists of the same length"
+      ))?
+    }
   }
 
   if !config.get("sni").is_badvalue() {
@@ -185,18 +996,36 @@ pub fn validate_config(
       let sni_hostnames = sni.keys();
       for sni_hostname_unknown in sni_hostnames {
         if let Some(sni_hostname) = sni_hostname_unknown.as_str() {
-          if sni[sni_hostname_unknown]["cert"].as_str().is_none() {
+          if !is_path_or_path_list(&sni[sni_hostname_unknown]["cert"]) {
             Err(anyhow::anyhow!(
               "Invalid SNI TLS certificate path for \"{}\"",
               sni_hostname
             ))?
           }
-          if sni[sni_hostname_unknown]["key"].as_str().is_none() {
+          if !is_path_or_path_list(&sni[sni_hostname_unknown]["key"]) {
             Err(anyhow::anyhow!(
               "Invalid SNI private key certificate path for \"{}\"",
               sni_hostname
             ))?
           }
+          if certificate_key_paths(
+            &sni[sni_hostname_unknown]["cert"],
+            &sni[sni_hostname_unknown]["key"],
+          )
+          .is_none()
+          {
+            Err(anyhow::anyhow!(
+              "The SNI TLS certificate and private key paths for \"{}\" must be both single paths or lists of the same length",
+              sni_hostname
+            ))?
+          }
+          if let Err(err) = TlsPolicy::from_yaml(&sni[sni_hostname_unknown]) {
+            Err(anyhow::anyhow!(
+              "Invalid TLS policy for \"{}\": {}",
+              sni_hostname,
+              err
+            ))?
+          }
         } else {
           Err(anyhow::anyhow!("Invalid SNI hostname"))?
         }
@@ -239,6 +1068,28 @@ pub fn validate_config(
         }
       }
 
+      if let Some(max_send_buffer_size) = config.get("http2Options")["maxSendBufferSize"].as_i64() {
+        if !(1..=4_294_967_295).contains(&max_send_buffer_size) {
+          Err(anyhow::anyhow!("Invalid HTTP/2 max send buffer size"))?
+        }
+      }
+
+      if let Some(max_in_flight_responses) =
+        config.get("http2Options")["maxInFlightResponses"].as_i64()
+      {
+        if !(1..=1_000_000).contains(&max_in_flight_responses) {
+          Err(anyhow::anyhow!("Invalid HTTP/2 max in-flight responses"))?
+        }
+      }
+
+      if let Some(response_queue_timeout) =
+        config.get("http2Options")["responseQueueTimeout"].as_i64()
+      {
+        if response_queue_timeout <= 0 {
+          Err(anyhow::anyhow!("Invalid HTTP/2 response queue timeout"))?
+        }
+      }
+
       if !config.get("http2Options")["enableConnectProtocol"].is_badvalue()
         && config.get("http2Options")["enableConnectProtocol"]
           .as_bool()
@@ -253,17 +1104,58 @@ pub fn validate_config(
     }
   }
 
-  if !config.get("useClientCertificate").is_badvalue() {
-    if !is_global {
-      Err(anyhow::anyhow!(
-        "Client certificate verfication enabling option is not allowed in host configuration"
-      ))?
-    }
-    if config.get("useClientCertificate").as_bool().is_none() {
-      Err(anyhow::anyhow!(
-        "Invalid client certificate verification enabling option value"
-      ))?
-    }
+  if !config.get("useClientCertificate").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Client certificate verfication enabling option is not allowed in host configuration"
+      ))?
+    }
+    if config.get("useClientCertificate").as_bool().is_none() {
+      Err(anyhow::anyhow!(
+        "Invalid client certificate verification enabling option value"
+      ))?
+    }
+  }
+
+  if !config.get("clientCertificateCA").is_badvalue() && !is_global {
+    Err(anyhow::anyhow!(
+      "Client certificate authority configuration is not allowed in host configuration"
+    ))?
+  }
+
+  if !config.get("clientCertificateCRL").is_badvalue() && !is_global {
+    Err(anyhow::anyhow!(
+      "Client certificate revocation list configuration is not allowed in host configuration"
+    ))?
+  }
+
+  if !config.get("clientCertificateMode").is_badvalue() && !is_global {
+    Err(anyhow::anyhow!(
+      "Client certificate mode configuration is not allowed in host configuration"
+    ))?
+  }
+
+  if !config.get("clientPublicKeyPins").is_badvalue() && !is_global {
+    Err(anyhow::anyhow!(
+      "Client public key pin configuration is not allowed in host configuration"
+    ))?
+  }
+
+  if !config.get("clientRawPublicKeys").is_badvalue() && !is_global {
+    Err(anyhow::anyhow!(
+      "Client raw public key enabling option is not allowed in host configuration"
+    ))?
+  }
+
+  if is_global {
+    ClientAuthConfig::parse(
+      &config.get("useClientCertificate"),
+      &config.get("clientCertificateCA"),
+      &config.get("clientCertificateCRL"),
+      &config.get("clientCertificateMode"),
+      &config.get("clientPublicKeyPins"),
+      &config.get("clientRawPublicKeys"),
+    )?;
   }
 
   if !config.get("cipherSuite").is_badvalue() {
@@ -302,6 +1194,22 @@ pub fn validate_config(
     }
   }
 
+  if !config.get("tlsPreset").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "TLS preset is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("tlsPreset")
+      .as_str()
+      .and_then(TlsPreset::from_name)
+      .is_none()
+    {
+      Err(anyhow::anyhow!("Invalid TLS preset"))?
+    }
+  }
+
   if !config.get("tlsMinVersion").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
@@ -337,6 +1245,32 @@ pub fn validate_config(
     }
   }
 
+  if !config.get("ocspCacheDirectory").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "OCSP response cache directory is not allowed in host configuration"
+      ))?
+    }
+    if config.get("ocspCacheDirectory").as_str().is_none() {
+      Err(anyhow::anyhow!("Invalid OCSP response cache directory"))?
+    }
+  }
+
+  if !config.get("ocspRefreshInterval").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "OCSP response refresh interval is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("ocspRefreshInterval")
+      .as_i64()
+      .is_none_or(|refresh_interval| refresh_interval <= 0)
+    {
+      Err(anyhow::anyhow!("Invalid OCSP response refresh interval"))?
+    }
+  }
+
   if !config.get("serverAdministratorEmail").is_badvalue()
     && config.get("serverAdministratorEmail").as_str().is_none()
   {
@@ -353,6 +1287,65 @@ pub fn validate_config(
     ))?
   }
 
+  if !config.get("trustedProxies").is_badvalue() {
+    if let Some(trusted_proxies) = config.get("trustedProxies").as_vec() {
+      let mut trusted_proxies_trie = IpPrefixTrie::new();
+      for trusted_proxy in trusted_proxies {
+        if !trusted_proxy
+          .as_str()
+          .is_some_and(|trusted_proxy| trusted_proxies_trie.insert_cidr(trusted_proxy))
+        {
+          Err(anyhow::anyhow!(
+            "Invalid trusted proxy address or CIDR range"
+          ))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid trusted proxies configuration"))?
+    }
+  }
+
+  if !config.get("trustedClientIpHeader").is_badvalue()
+    && config
+      .get("trustedClientIpHeader")
+      .as_str()
+      .and_then(|header_name| ClientIpHeader::from_config(Some(header_name)))
+      .is_none()
+  {
+    Err(anyhow::anyhow!("Invalid trusted client IP header"))?
+  }
+
+  if !config.get("verboseErrorNetworks").is_badvalue() {
+    if let Some(verbose_error_networks) = config.get("verboseErrorNetworks").as_vec() {
+      let mut verbose_error_networks_trie = IpPrefixTrie::new();
+      for verbose_error_network in verbose_error_networks {
+        if !verbose_error_network
+          .as_str()
+          .is_some_and(|verbose_error_network| {
+            verbose_error_networks_trie.insert_cidr(verbose_error_network)
+          })
+        {
+          Err(anyhow::anyhow!(
+            "Invalid verbose error page network address or CIDR range"
+          ))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "Invalid verbose error page networks configuration"
+      ))?
+    }
+  }
+
+  if !config.get("verboseErrorToken").is_badvalue()
+    && config
+      .get("verboseErrorToken")
+      .as_str()
+      .is_none_or(|token| token.is_empty())
+  {
+    Err(anyhow::anyhow!("Invalid verbose error page debug token"))?
+  }
+
   if !config.get("disableNonEncryptedServer").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
@@ -389,6 +1382,55 @@ pub fn validate_config(
     }
   }
 
+  if !config.get("blocklistSources").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Block list sources configuration is not allowed in host configuration"
+      ))?
+    }
+    if let Some(blocklist_sources) = config.get("blocklistSources").as_vec() {
+      let blocklist_sources_iter = blocklist_sources.iter();
+      for blocklist_source_yaml in blocklist_sources_iter {
+        if !blocklist_source_yaml.is_hash() {
+          Err(anyhow::anyhow!("Invalid block list source"))?
+        }
+        match (
+          blocklist_source_yaml["url"].as_str(),
+          blocklist_source_yaml["file"].as_str(),
+        ) {
+          (Some(url), None) => {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+              Err(anyhow::anyhow!(
+                "Block list source URLs must use HTTP or HTTPS"
+              ))?
+            }
+          }
+          (None, Some(_)) => (),
+          _ => Err(anyhow::anyhow!(
+            "A block list source must have either an URL or a file path specified"
+          ))?,
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid block list sources configuration"))?
+    }
+  }
+
+  if !config.get("blocklistRefreshInterval").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Block list refresh interval configuration is not allowed in host configuration"
+      ))?
+    }
+    if let Some(refresh_interval) = config.get("blocklistRefreshInterval").as_i64() {
+      if refresh_interval < 1 {
+        Err(anyhow::anyhow!("Invalid block list refresh interval"))?
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid block list refresh interval"))?
+    }
+  }
+
   if !config.get("environmentVariables").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
@@ -479,12 +1521,265 @@ pub fn validate_config(
         {
           Err(anyhow::anyhow!("Invalid URL rewrite map"))?
         }
+        if !rewrite_map_entry_yaml["condition"].is_badvalue() {
+          match rewrite_map_entry_yaml["condition"].as_str() {
+            Some(condition) => {
+              if let Err(err) = Expression::compile(condition) {
+                Err(anyhow::anyhow!(
+                  "Invalid URL rewrite condition \"{}\": {}",
+                  condition,
+                  err
+                ))?
+              }
+            }
+            None => Err(anyhow::anyhow!("Invalid URL rewrite map"))?,
+          }
+        }
       }
     } else {
       Err(anyhow::anyhow!("Invalid URL rewrite map"))?
     }
   }
 
+  if !config.get("headerRules").is_badvalue() {
+    if let Some(header_rules) = config.get("headerRules").as_vec() {
+      for header_rule_yaml in header_rules {
+        if !header_rule_yaml.is_hash() {
+          Err(anyhow::anyhow!("Invalid header rules"))?
+        }
+        if !header_rule_yaml["condition"].is_badvalue() {
+          match header_rule_yaml["condition"].as_str() {
+            Some(condition) => {
+              if let Err(err) = Expression::compile(condition) {
+                Err(anyhow::anyhow!(
+                  "Invalid header rule condition \"{}\": {}",
+                  condition,
+                  err
+                ))?
+              }
+            }
+            None => Err(anyhow::anyhow!("Invalid header rules"))?,
+          }
+        }
+        for headers_key in [
+          "setHeaders",
+          "addHeaders",
+          "setRequestHeaders",
+          "addRequestHeaders",
+        ] {
+          if !header_rule_yaml[headers_key].is_badvalue() {
+            match header_rule_yaml[headers_key].as_hash() {
+              Some(headers) => {
+                for (header_name, header_value) in headers {
+                  match (header_name.as_str(), header_value.as_str()) {
+                    (Some(header_name), Some(header_value))
+                      if HeaderName::from_str(header_name).is_ok()
+                        && HeaderValue::from_str(header_value).is_ok() => {}
+                    _ => Err(anyhow::anyhow!("Invalid header rules"))?,
+                  }
+                }
+              }
+              None => Err(anyhow::anyhow!("Invalid header rules"))?,
+            }
+          }
+        }
+        for remove_headers_key in ["removeHeaders", "removeRequestHeaders"] {
+          if !header_rule_yaml[remove_headers_key].is_badvalue() {
+            match header_rule_yaml[remove_headers_key].as_vec() {
+              Some(remove_headers) => {
+                if remove_headers.iter().any(|header_name| {
+                  header_name
+                    .as_str()
+                    .is_none_or(|header_name| HeaderName::from_str(header_name).is_err())
+                }) {
+                  Err(anyhow::anyhow!("Invalid header rules"))?
+                }
+              }
+              None => Err(anyhow::anyhow!("Invalid header rules"))?,
+            }
+          }
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid header rules"))?
+    }
+  }
+
+  if !config.get("botMitigation").is_badvalue() && config.get("botMitigation").as_bool().is_none() {
+    Err(anyhow::anyhow!(
+      "Invalid bot mitigation enabling option value"
+    ))?
+  }
+
+  if !config.get("botUserAgents").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Bot user agent patterns are not allowed in host configuration"
+      ))?
+    }
+    match config.get("botUserAgents").as_vec() {
+      Some(user_agent_patterns) => {
+        for user_agent_pattern in user_agent_patterns {
+          match user_agent_pattern.as_str() {
+            Some(user_agent_pattern) => {
+              if let Err(err) = Regex::new(user_agent_pattern) {
+                Err(anyhow::anyhow!("Invalid bot user agent pattern: {}", err))?
+              }
+            }
+            None => Err(anyhow::anyhow!("Invalid bot user agent pattern"))?,
+          }
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid bot user agent pattern list"))?,
+    }
+  }
+
+  if !config.get("botAction").is_badvalue()
+    && !matches!(
+      config.get("botAction").as_str(),
+      Some("block" | "tarpit" | "cookieChallenge" | "javascriptChallenge" | "ban")
+    )
+  {
+    Err(anyhow::anyhow!("Invalid bot mitigation action"))?
+  }
+
+  if !config.get("botMode").is_badvalue()
+    && !matches!(config.get("botMode").as_str(), Some("enforce" | "observe"))
+  {
+    Err(anyhow::anyhow!("Invalid bot mitigation mode"))?
+  }
+
+  if !config.get("botNotFoundThreshold").is_badvalue()
+    && config
+      .get("botNotFoundThreshold")
+      .as_i64()
+      .is_none_or(|threshold| threshold <= 0)
+  {
+    Err(anyhow::anyhow!(
+      "Invalid bot \"404 Not Found\" response threshold"
+    ))?
+  }
+
+  if !config.get("botNotFoundWindow").is_badvalue()
+    && config
+      .get("botNotFoundWindow")
+      .as_i64()
+      .is_none_or(|value| value <= 0)
+  {
+    Err(anyhow::anyhow!(
+      "Invalid bot \"404 Not Found\" response window"
+    ))?
+  }
+
+  if !config.get("botTarpitDelay").is_badvalue()
+    && config
+      .get("botTarpitDelay")
+      .as_i64()
+      .is_none_or(|value| value <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid bot tarpit delay"))?
+  }
+
+  if !config.get("botPenaltyDuration").is_badvalue()
+    && config
+      .get("botPenaltyDuration")
+      .as_i64()
+      .is_none_or(|value| value <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid bot penalty duration"))?
+  }
+
+  if !config.get("botChallengeValidity").is_badvalue()
+    && config
+      .get("botChallengeValidity")
+      .as_i64()
+      .is_none_or(|value| value <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid bot challenge validity"))?
+  }
+
+  if !config.get("experiments").is_badvalue() {
+    parse_experiments(&config.get("experiments"))?;
+  }
+
+  if !config.get("setVariables").is_badvalue() {
+    match config.get("setVariables").as_hash() {
+      Some(variables) => {
+        for (name, value) in variables {
+          if !name.as_str().is_some_and(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+          }) {
+            Err(anyhow::anyhow!("Invalid request variable name"))?
+          }
+          if value.as_str().is_none() {
+            Err(anyhow::anyhow!("Invalid request variable value"))?
+          }
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid request variables"))?,
+    }
+  }
+
+  if !config.get("wafRules").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "WAF rule set configuration is not allowed in host configuration"
+      ))?
+    }
+    match config.get("wafRules").as_hash() {
+      Some(waf_rules) => {
+        for (rule_set_name, rule_set_yaml) in waf_rules {
+          let rule_set_name = rule_set_name
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid WAF rule set name"))?;
+          if is_builtin_waf_rule_set(rule_set_name) {
+            Err(anyhow::anyhow!(
+              "The \"{}\" WAF rule set is a built-in rule set",
+              rule_set_name
+            ))?
+          }
+          parse_waf_rule_set(rule_set_yaml).map_err(|err| {
+            anyhow::anyhow!("Invalid \"{}\" WAF rule set: {}", rule_set_name, err)
+          })?;
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid WAF rule sets"))?,
+    }
+  }
+
+  if !config.get("wafRuleSets").is_badvalue()
+    && config
+      .get("wafRuleSets")
+      .as_vec()
+      .is_none_or(|rule_set_names| rule_set_names.iter().any(|name| name.as_str().is_none()))
+  {
+    Err(anyhow::anyhow!("Invalid WAF rule set list"))?
+  }
+
+  if !config.get("wafMode").is_badvalue()
+    && !matches!(config.get("wafMode").as_str(), Some("block" | "detect"))
+  {
+    Err(anyhow::anyhow!("Invalid WAF mode"))?
+  }
+
+  if !config.get("wafScoreThreshold").is_badvalue()
+    && config
+      .get("wafScoreThreshold")
+      .as_i64()
+      .is_none_or(|score_threshold| score_threshold <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid WAF anomaly score threshold"))?
+  }
+
+  if !config.get("wafBodyInspectionLimit").is_badvalue()
+    && config
+      .get("wafBodyInspectionLimit")
+      .as_i64()
+      .is_none_or(|body_inspection_limit| body_inspection_limit < 0)
+  {
+    Err(anyhow::anyhow!("Invalid WAF body inspection limit"))?
+  }
+
   if !config.get("enableRewriteLogging").is_badvalue()
     && config.get("enableRewriteLogging").as_bool().is_none()
   {
@@ -517,9 +1812,123 @@ pub fn validate_config(
         if user_yaml["pass"].as_str().is_none() {
           Err(anyhow::anyhow!("Invalid user configuration"))?
         }
+        if !user_yaml["digestHash"].is_badvalue()
+          && user_yaml["digestHash"].as_str().is_none_or(|digest_hash| {
+            digest_hash.len() != 64 || !digest_hash.bytes().all(|b| b.is_ascii_hexdigit())
+          })
+        {
+          Err(anyhow::anyhow!("Invalid user Digest authentication hash"))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid user configuration"))?
+    }
+  }
+
+  if !config.get("upstreamProxy").is_badvalue() {
+    match config.get("upstreamProxy").as_str() {
+      Some(upstream_proxy) => {
+        UpstreamProxy::parse(upstream_proxy)?;
+      }
+      None => Err(anyhow::anyhow!("Invalid upstream proxy URL"))?,
+    }
+  }
+
+  if !config.get("noiseRequests").is_badvalue()
+    && !matches!(
+      config.get("noiseRequests").as_str(),
+      Some("builtin") | Some("empty")
+    )
+  {
+    Err(anyhow::anyhow!(
+      "Invalid noise request handling mode (expected \"builtin\" or \"empty\")"
+    ))?
+  }
+
+  if !config.get("noiseRequestPaths").is_badvalue()
+    && config
+      .get("noiseRequestPaths")
+      .as_vec()
+      .is_none_or(|paths| {
+        paths
+          .iter()
+          .any(|path| path.as_str().is_none_or(|path| !path.starts_with('/')))
+      })
+  {
+    Err(anyhow::anyhow!("Invalid noise request paths"))?
+  }
+
+  if !config.get("logNoiseRequests").is_badvalue()
+    && config.get("logNoiseRequests").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid noise request logging enabling option value"
+    ))?
+  }
+
+  if !config.get("forwardProxyAuthentication").is_badvalue()
+    && config.get("forwardProxyAuthentication").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid forward proxy authentication enabling option value"
+    ))?
+  }
+
+  if !config.get("forwardProxyRealm").is_badvalue()
+    && config.get("forwardProxyRealm").as_str().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid forward proxy realm"))?
+  }
+
+  parse_destination_rules(&config.get("forwardProxyAllowedDestinations"))?;
+  parse_destination_rules(&config.get("forwardProxyDeniedDestinations"))?;
+
+  if !config
+    .get("forwardProxyMaxConnectionsPerUser")
+    .is_badvalue()
+    && config
+      .get("forwardProxyMaxConnectionsPerUser")
+      .as_i64()
+      .is_none_or(|max_connections| max_connections < 1)
+  {
+    Err(anyhow::anyhow!(
+      "Invalid maximum number of forward proxy connections per user"
+    ))?
+  }
+
+  if !config.get("forwardProxyBandwidthPerUser").is_badvalue()
+    && config
+      .get("forwardProxyBandwidthPerUser")
+      .as_i64()
+      .is_none_or(|bandwidth| bandwidth < 1)
+  {
+    Err(anyhow::anyhow!("Invalid forward proxy bandwidth per user"))?
+  }
+
+  if !config.get("ssrfProtection").is_badvalue() && config.get("ssrfProtection").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid SSRF protection enabling option"))?
+  }
+
+  if !config.get("ssrfAllowedNetworks").is_badvalue() {
+    if let Some(ssrf_allowed_networks) = config.get("ssrfAllowedNetworks").as_vec() {
+      let mut ssrf_allowed_networks_trie = IpPrefixTrie::new();
+      for ssrf_allowed_network in ssrf_allowed_networks {
+        if !ssrf_allowed_network
+          .as_str()
+          .is_some_and(|ssrf_allowed_network| {
+            ssrf_allowed_networks_trie.insert_cidr(ssrf_allowed_network)
+          })
+        {
+          Err(anyhow::anyhow!(
+            "Invalid SSRF protection allowed network address or CIDR range"
+          ))?
+        }
       }
     } else {
-      Err(anyhow::anyhow!("Invalid user configuration"))?
+      Err(anyhow::anyhow!(
+        "Invalid SSRF protection allowed networks configuration"
+      ))?
     }
   }
 
@@ -648,18 +2057,264 @@ pub fn validate_config(
     Err(anyhow::anyhow!("Invalid ETag enabling option"))?
   }
 
+  if !config.get("immutableAssets").is_badvalue()
+    && config.get("immutableAssets").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid immutable asset serving enabling option"
+    ))?
+  }
+
+  if !config.get("immutableAssetPattern").is_badvalue() {
+    match config.get("immutableAssetPattern").as_str() {
+      Some(immutable_asset_pattern) => {
+        if let Err(err) = Regex::new(immutable_asset_pattern) {
+          Err(anyhow::anyhow!(
+            "Invalid immutable asset file name pattern: {}",
+            err
+          ))?;
+        }
+      }
+      None => Err(anyhow::anyhow!("Invalid immutable asset file name pattern"))?,
+    }
+  }
+
+  if !config.get("immutableAssetMaxAge").is_badvalue()
+    && config
+      .get("immutableAssetMaxAge")
+      .as_i64()
+      .is_none_or(|max_age| max_age < 0)
+  {
+    Err(anyhow::anyhow!("Invalid immutable asset maximum age"))?
+  }
+
   if !config.get("enableCompression").is_badvalue()
     && config.get("enableCompression").as_bool().is_none()
   {
     Err(anyhow::anyhow!("Invalid HTTP compression enabling option"))?
   }
 
+  if !config.get("enableFileReadCoalescing").is_badvalue()
+    && config.get("enableFileReadCoalescing").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid file read coalescing enabling option"
+    ))?
+  }
+
   if !config.get("enableDirectoryListing").is_badvalue()
     && config.get("enableDirectoryListing").as_bool().is_none()
   {
     Err(anyhow::anyhow!("Invalid directory listing enabling option"))?
   }
 
+  if !config.get("respond").is_badvalue() {
+    let respond_yaml = config.get("respond");
+    let status_code = match respond_yaml.as_i64() {
+      Some(status_code) => Some(status_code),
+      None => {
+        if respond_yaml.as_hash().is_none() {
+          Err(anyhow::anyhow!("Invalid static response configuration"))?
+        }
+        if !respond_yaml["body"].is_badvalue() && respond_yaml["body"].as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid static response body"))?
+        }
+        if !respond_yaml["headers"].is_badvalue() {
+          if let Some(headers) = respond_yaml["headers"].as_hash() {
+            for (header_name, header_value) in headers.iter() {
+              if header_name.as_str().is_none() || header_value.as_str().is_none() {
+                Err(anyhow::anyhow!("Invalid static response header"))?
+              }
+            }
+          } else {
+            Err(anyhow::anyhow!("Invalid static response headers"))?
+          }
+        }
+        match respond_yaml["status"].is_badvalue() {
+          true => None,
+          false => match respond_yaml["status"].as_i64() {
+            Some(status_code) => Some(status_code),
+            None => Err(anyhow::anyhow!("Invalid static response status code"))?,
+          },
+        }
+      }
+    };
+    if let Some(status_code) = status_code {
+      if !(100..=599).contains(&status_code) {
+        Err(anyhow::anyhow!("Invalid static response status code"))?
+      }
+    }
+  }
+
+  if !config.get("debugRouting").is_badvalue() && config.get("debugRouting").as_bool().is_none() {
+    Err(anyhow::anyhow!("Invalid routing debugging enabling option"))?
+  }
+
+  if !config.get("serverTiming").is_badvalue() && config.get("serverTiming").as_bool().is_none() {
+    Err(anyhow::anyhow!(
+      "Invalid Server-Timing header enabling option"
+    ))?
+  }
+
+  if !config.get("redirectMap").is_badvalue() && config.get("redirectMap").as_str().is_none() {
+    Err(anyhow::anyhow!("Invalid redirect map path"))?
+  }
+
+  if !config.get("directoryListingTemplate").is_badvalue()
+    && config.get("directoryListingTemplate").as_str().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid directory listing template path"))?
+  }
+
+  if !config.get("fileBufferSize").is_badvalue()
+    && config
+      .get("fileBufferSize")
+      .as_i64()
+      .is_none_or(|buffer_size| buffer_size <= 0)
+  {
+    Err(anyhow::anyhow!("Invalid file buffer size"))?
+  }
+
+  if !config.get("userDirectory").is_badvalue() {
+    if let Some(user_directory) = config.get("userDirectory").as_str() {
+      if user_directory.is_empty()
+        || user_directory.starts_with('/')
+        || user_directory.split('/').any(|component| component == "..")
+      {
+        Err(anyhow::anyhow!("Invalid user directory name"))?
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid user directory name"))?
+    }
+  }
+
+  if !config.get("userDirectoryHome").is_badvalue()
+    && config.get("userDirectoryHome").as_str().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid user home directory path"))?
+  }
+
+  for user_list_property in ["userDirectoryEnabledUsers", "userDirectoryDisabledUsers"] {
+    if !config.get(user_list_property).is_badvalue() {
+      if let Some(users) = config.get(user_list_property).as_vec() {
+        if users.iter().any(|user| user.as_str().is_none()) {
+          Err(anyhow::anyhow!("Invalid user directory user name"))?
+        }
+      } else {
+        Err(anyhow::anyhow!("Invalid user directory user list"))?
+      }
+    }
+  }
+
+  if !config.get("signedUrlSecret").is_badvalue()
+    && config.get("signedUrlSecret").as_str().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid signed URL secret"))?
+  }
+
+  if !config.get("hotlinkAllowedOrigins").is_badvalue() {
+    if let Some(allowed_origins) = config.get("hotlinkAllowedOrigins").as_vec() {
+      let allowed_origins_iter = allowed_origins.iter();
+      for allowed_origin_yaml in allowed_origins_iter {
+        if allowed_origin_yaml.as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid hotlink protection allowed origin"))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "Invalid hotlink protection allowed origins configuration"
+      ))?
+    }
+  }
+
+  if !config.get("hotlinkProtectionExtensions").is_badvalue() {
+    if let Some(extensions) = config.get("hotlinkProtectionExtensions").as_vec() {
+      let extensions_iter = extensions.iter();
+      for extension_yaml in extensions_iter {
+        if extension_yaml.as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid hotlink protection file extension"))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "Invalid hotlink protection file extensions configuration"
+      ))?
+    }
+  }
+
+  if !config.get("hotlinkAllowEmptyReferer").is_badvalue()
+    && config.get("hotlinkAllowEmptyReferer").as_bool().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid hotlink protection empty Referer allowing option"
+    ))?
+  }
+
+  if !config.get("hotlinkRedirect").is_badvalue()
+    && config.get("hotlinkRedirect").as_str().is_none()
+  {
+    Err(anyhow::anyhow!(
+      "Invalid hotlink protection placeholder URL"
+    ))?
+  }
+
+  if !config.get("hotlinkTokenSecret").is_badvalue()
+    && config.get("hotlinkTokenSecret").as_str().is_none()
+  {
+    Err(anyhow::anyhow!("Invalid hotlink protection token secret"))?
+  }
+
+  if !config.get("languageVariants").is_badvalue() {
+    if let Some(language_variants) = config.get("languageVariants").as_vec() {
+      let language_variants_iter = language_variants.iter();
+      for language_variant_yaml in language_variants_iter {
+        match language_variant_yaml.as_str() {
+          Some(language_variant) => {
+            if language_variant.is_empty()
+              || !language_variant
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+              Err(anyhow::anyhow!(
+                "Invalid language variant: \"{}\"",
+                language_variant
+              ))?
+            }
+          }
+          None => Err(anyhow::anyhow!("Invalid language variants configuration"))?,
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid language variants configuration"))?
+    }
+  }
+
+  if !config.get("languageVariantCountryHeader").is_badvalue() {
+    if let Some(header_name) = config.get("languageVariantCountryHeader").as_str() {
+      if HeaderName::from_str(header_name).is_err() {
+        Err(anyhow::anyhow!(
+          "Invalid language variant country header name"
+        ))?
+      }
+    } else {
+      Err(anyhow::anyhow!(
+        "Invalid language variant country header name"
+      ))?
+    }
+  }
+
+  if !config.get("languageVariantCountries").is_badvalue() {
+    if let Some(language_variant_countries) = config.get("languageVariantCountries").as_hash() {
+      for (country_yaml, language_yaml) in language_variant_countries.iter() {
+        if country_yaml.as_str().is_none() || language_yaml.as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid language variant country mapping"))?
+        }
+      }
+    } else {
+      Err(anyhow::anyhow!("Invalid language variant country mapping"))?
+    }
+  }
+
   if !config.get("enableAutomaticTLS").is_badvalue() {
     if !is_global {
       Err(anyhow::anyhow!(
@@ -743,6 +2398,69 @@ pub fn validate_config(
     }
   }
 
+  for timeout_property in [
+    "tlsHandshakeTimeout",
+    "headerReadTimeout",
+    "requestBodyTimeout",
+    "handlerTimeout",
+    "responseWriteTimeout",
+    "keepAliveTimeout",
+    "preRequestIdleTimeout",
+    "webSocketIdleTimeout",
+    "drainTimeout",
+  ] {
+    if !config.get(timeout_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Server timeout configuration is not allowed in host configuration"
+        ))?
+      }
+      if !config.get(timeout_property).is_null() {
+        if let Some(timeout) = config.get(timeout_property).as_i64() {
+          if timeout < 0 {
+            Err(anyhow::anyhow!("Invalid server timeout"))?
+          }
+        } else {
+          Err(anyhow::anyhow!("Invalid server timeout"))?
+        }
+      }
+    }
+  }
+
+  for min_rate_property in ["requestHeaderMinRate", "requestBodyMinRate"] {
+    if !config.get(min_rate_property).is_badvalue() {
+      if !is_global {
+        Err(anyhow::anyhow!(
+          "Minimum transfer rate configuration is not allowed in host configuration"
+        ))?
+      }
+      if config
+        .get(min_rate_property)
+        .as_i64()
+        .is_none_or(|min_rate| min_rate <= 0)
+      {
+        Err(anyhow::anyhow!("Invalid minimum transfer rate"))?
+      }
+    }
+  }
+
+  if !config.get("minRateGracePeriod").is_badvalue() {
+    if !is_global {
+      Err(anyhow::anyhow!(
+        "Minimum transfer rate grace period configuration is not allowed in host configuration"
+      ))?
+    }
+    if config
+      .get("minRateGracePeriod")
+      .as_i64()
+      .is_none_or(|grace_period| grace_period < 0)
+    {
+      Err(anyhow::anyhow!(
+        "Invalid minimum transfer rate grace period"
+      ))?
+    }
+  }
+
   for module_optional_builtin in modules_optional_builtin.iter() {
     match module_optional_builtin as &str {
       "rproxy" => {
@@ -823,6 +2541,107 @@ pub fn validate_config(
           }
         }
 
+        if !config.get("proxyMaxIdleConnections").is_badvalue() {
+          if !is_global {
+            Err(anyhow::anyhow!(
+              "Maximum idle proxy connections configuration is not allowed in host configuration"
+            ))?
+          }
+          if config
+            .get("proxyMaxIdleConnections")
+            .as_i64()
+            .is_none_or(|max_idle_connections| max_idle_connections < 0)
+          {
+            Err(anyhow::anyhow!(
+              "Invalid maximum idle proxy connections value"
+            ))?
+          }
+        }
+
+        if !config.get("proxyIdleTimeout").is_badvalue() {
+          if !is_global {
+            Err(anyhow::anyhow!(
+              "Proxy idle connection timeout configuration is not allowed in host configuration"
+            ))?
+          }
+          if !config.get("proxyIdleTimeout").is_null()
+            && config
+              .get("proxyIdleTimeout")
+              .as_i64()
+              .is_none_or(|idle_timeout| idle_timeout < 0)
+          {
+            Err(anyhow::anyhow!(
+              "Invalid proxy idle connection timeout value"
+            ))?
+          }
+        }
+
+        if !config.get("proxyMaxRequestsPerConnection").is_badvalue() {
+          if !is_global {
+            Err(anyhow::anyhow!(
+              "Maximum requests per proxy connection configuration is not allowed in host configuration"
+            ))?
+          }
+          if config
+            .get("proxyMaxRequestsPerConnection")
+            .as_i64()
+            .is_none_or(|max_requests| max_requests <= 0)
+          {
+            Err(anyhow::anyhow!(
+              "Invalid maximum requests per proxy connection value"
+            ))?
+          }
+        }
+
+        if !config.get("proxyMaxRetries").is_badvalue()
+          && config
+            .get("proxyMaxRetries")
+            .as_i64()
+            .is_none_or(|max_retries| max_retries < 0)
+        {
+          Err(anyhow::anyhow!("Invalid maximum proxy retries value"))?
+        }
+
+        if !config.get("proxyRetryOn").is_badvalue() {
+          if let Some(retry_on) = config.get("proxyRetryOn").as_vec() {
+            for condition in retry_on {
+              if !condition.as_str().is_some_and(|condition| {
+                matches!(condition, "error" | "timeout" | "502" | "503" | "504")
+              }) {
+                Err(anyhow::anyhow!(
+                  "Invalid proxy retry condition (only \"error\", \"timeout\", \"502\", \"503\" and \"504\" are supported)"
+                ))?
+              }
+            }
+          } else {
+            Err(anyhow::anyhow!("Invalid proxy retry conditions"))?
+          }
+        }
+
+        if !config.get("proxyTryTimeout").is_badvalue()
+          && config
+            .get("proxyTryTimeout")
+            .as_i64()
+            .is_none_or(|try_timeout| try_timeout <= 0)
+        {
+          Err(anyhow::anyhow!("Invalid proxy per-try timeout value"))?
+        }
+
+        if !config.get("proxyRetryBudget").is_badvalue() {
+          if !is_global {
+            Err(anyhow::anyhow!(
+              "Proxy retry budget configuration is not allowed in host configuration"
+            ))?
+          }
+          if config
+            .get("proxyRetryBudget")
+            .as_i64()
+            .is_none_or(|percent| percent < 0)
+          {
+            Err(anyhow::anyhow!("Invalid proxy retry budget value"))?
+          }
+        }
+
         if !config
           .get("disableProxyCertificateVerification")
           .is_badvalue()
@@ -835,6 +2654,17 @@ pub fn validate_config(
             "Invalid proxy certificate verification disabling option value"
           ))?
         }
+
+        if !config.get("proxyVerifyResponseIntegrity").is_badvalue()
+          && config
+            .get("proxyVerifyResponseIntegrity")
+            .as_bool()
+            .is_none()
+        {
+          Err(anyhow::anyhow!(
+            "Invalid proxy response integrity verification enabling option value"
+          ))?
+        }
       }
       "cache" => {
         if !config.get("cacheVaryHeaders").is_badvalue() {
@@ -879,6 +2709,20 @@ pub fn validate_config(
             Err(anyhow::anyhow!("Invalid maximum cache response size"))?
           }
         }
+
+        if !config.get("enableEsi").is_badvalue() && config.get("enableEsi").as_bool().is_none() {
+          Err(anyhow::anyhow!(
+            "Invalid ESI processing enabling option value"
+          ))?
+        }
+
+        if !config.get("cacheResumeFills").is_badvalue()
+          && config.get("cacheResumeFills").as_bool().is_none()
+        {
+          Err(anyhow::anyhow!(
+            "Invalid interrupted cache fill resuming option value"
+          ))?
+        }
       }
       "cgi" => {
         if !config.get("cgiScriptExtensions").is_badvalue() {
@@ -984,6 +2828,59 @@ pub fn validate_config(
           }
         }
       }
+      "webdav" if !config.get("webdavPaths").is_badvalue() => {
+        if let Some(webdav_paths) = config.get("webdavPaths").as_vec() {
+          for webdav_path_yaml in webdav_paths.iter() {
+            if !webdav_path_yaml
+              .as_str()
+              .is_some_and(|webdav_path| webdav_path.starts_with('/'))
+            {
+              Err(anyhow::anyhow!("Invalid WebDAV path"))?
+            }
+          }
+        } else {
+          Err(anyhow::anyhow!("Invalid WebDAV paths configuration"))?
+        }
+      }
+      "lua" => {
+        if !config.get("luaRewrite").is_badvalue() && config.get("luaRewrite").as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid Lua rewrite hook"))?
+        }
+
+        if !config.get("luaAccess").is_badvalue() && config.get("luaAccess").as_str().is_none() {
+          Err(anyhow::anyhow!("Invalid Lua access hook"))?
+        }
+
+        if !config.get("luaHeaderFilter").is_badvalue()
+          && config.get("luaHeaderFilter").as_str().is_none()
+        {
+          Err(anyhow::anyhow!("Invalid Lua header filter hook"))?
+        }
+
+        if !config.get("luaBodyFilter").is_badvalue()
+          && config.get("luaBodyFilter").as_str().is_none()
+        {
+          Err(anyhow::anyhow!("Invalid Lua body filter hook"))?
+        }
+      }
+      "wasm" if !config.get("wasmModules").is_badvalue() => {
+        if let Some(wasm_modules) = config.get("wasmModules").as_vec() {
+          for wasm_module in wasm_modules.iter() {
+            if wasm_module["path"].as_str().is_none() {
+              Err(anyhow::anyhow!("Invalid WebAssembly module path"))?
+            }
+            if !wasm_module["configuration"].is_badvalue()
+              && wasm_module["configuration"].as_str().is_none()
+            {
+              Err(anyhow::anyhow!(
+                "Invalid WebAssembly module plugin configuration"
+              ))?
+            }
+          }
+        } else {
+          Err(anyhow::anyhow!("Invalid WebAssembly modules configuration"))?
+        }
+      }
       _ => (),
     }
   }
@@ -991,25 +2888,51 @@ pub fn validate_config(
   Ok(())
 }
 
+// The configuration to validate, whether it's global, whether it's a location, and where it's defined
+type PreparedConfig = (Yaml, bool, bool, Option<String>);
+
+// Prepare the configuration for validation. Each configuration is returned along with the description
+// of the place in the configuration files, where it's defined.
 pub fn prepare_config_for_validation(
   config: &Yaml,
-) -> Result<impl Iterator<Item = (Yaml, bool, bool)>, Box<dyn Error + Send + Sync>> {
+  config_origins: &ConfigOrigins,
+) -> Result<impl Iterator<Item = PreparedConfig>, Box<dyn Error + Send + Sync>> {
   let mut vector = Vec::new();
   if let Some(global_config) = config["global"].as_hash() {
     let global_config_yaml = Yaml::Hash(global_config.clone());
-    vector.push(global_config_yaml);
+    let origin = match config_origins.global.is_empty() {
+      true => None,
+      false => Some(format!(
+        "the global configuration at {}",
+        config_origins
+          .global
+          .iter()
+          .map(|origin| origin.to_string())
+          .collect::<Vec<_>>()
+          .join(", ")
+      )),
+    };
+    vector.push((global_config_yaml, origin));
   }
 
   let mut vector2 = Vec::new();
   let mut vector3 = Vec::new();
   if !config["hosts"].is_badvalue() {
     if let Some(hosts) = config["hosts"].as_vec() {
-      for host in hosts.iter() {
+      for (host_index, host) in hosts.iter().enumerate() {
+        let host_origin = config_origins.hosts.get(host_index);
         if let Some(locations) = host["locations"].as_vec() {
-          vector3.append(&mut locations.clone());
+          for (location_index, location) in locations.iter().enumerate() {
+            let origin = host_origin
+              .and_then(|host_origin| host_origin.locations.get(location_index))
+              .map(|origin| format!("the location configuration at {}", origin));
+            vector3.push((location.clone(), origin));
+          }
         }
+        let origin = host_origin
+          .map(|host_origin| format!("the host configuration at {}", host_origin.origin));
+        vector2.push((host.clone(), origin));
       }
-      vector2 = hosts.clone();
     } else {
       return Err(anyhow::anyhow!("Invalid virtual host configuration").into());
     }
@@ -1017,9 +2940,17 @@ pub fn prepare_config_for_validation(
 
   let iter = vector
     .into_iter()
-    .map(|item| (item, true, false))
-    .chain(vector2.into_iter().map(|item| (item, false, false)))
-    .chain(vector3.into_iter().map(|item| (item, false, true)));
+    .map(|(item, origin)| (item, true, false, origin))
+    .chain(
+      vector2
+        .into_iter()
+        .map(|(item, origin)| (item, false, false, origin)),
+    )
+    .chain(
+      vector3
+        .into_iter()
+        .map(|(item, origin)| (item, false, true, origin)),
+    );
 
   Ok(iter)
 }
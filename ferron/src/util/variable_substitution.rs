@@ -0,0 +1,75 @@
+// Substitute the variables ("$name" or "${name}") in the template with the values from the resolver.
+// The unknown variables are left as-is, and "$$" is replaced with a literal "$".
+pub fn substitute_variables(template: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+  let mut substituted = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(dollar_index) = rest.find('$') {
+    substituted.push_str(&rest[..dollar_index]);
+    let after_dollar = &rest[(dollar_index + 1)..];
+
+    if let Some(after_escape) = after_dollar.strip_prefix('$') {
+      substituted.push('$');
+      rest = after_escape;
+      continue;
+    }
+
+    let (name, variable_length) = match after_dollar.strip_prefix('{') {
+      Some(after_brace) => match after_brace.find('}') {
+        Some(brace_index) => (&after_brace[..brace_index], brace_index + 2),
+        None => ("", 0),
+      },
+      None => {
+        let name_length = after_dollar
+          .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+          .unwrap_or(after_dollar.len());
+        (&after_dollar[..name_length], name_length)
+      }
+    };
+
+    match resolve(name) {
+      Some(value) if !name.is_empty() => {
+        substituted.push_str(&value);
+        rest = &after_dollar[variable_length..];
+      }
+      _ => {
+        substituted.push('$');
+        rest = after_dollar;
+      }
+    }
+  }
+  substituted.push_str(rest);
+  substituted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn resolve(name: &str) -> Option<String> {
+    match name {
+      "host" => Some("example.com".to_string()),
+      "remote_addr" => Some("192.0.2.1".to_string()),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn test_substitute_variables() {
+    assert_eq!(
+      substitute_variables("for=$remote_addr;host=${host}s", resolve),
+      "for=192.0.2.1;host=example.coms"
+    );
+    assert_eq!(
+      substitute_variables("no variables", resolve),
+      "no variables"
+    );
+  }
+
+  #[test]
+  fn test_unknown_and_escaped_variables() {
+    assert_eq!(
+      substitute_variables("$unknown ${unknown} $$host $ ${host", resolve),
+      "$unknown ${unknown} $host $ ${host"
+    );
+  }
+}
@@ -0,0 +1,715 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use fancy_regex::{Regex, RegexBuilder};
+use yaml_rust2::Yaml;
+
+// The score of the rules in the built-in rule sets. It's equal to the default anomaly score threshold,
+// so that a single match blocks the request, unless the threshold is raised.
+const BUILTIN_RULE_SCORE: u64 = 5;
+
+// The default anomaly score, at which the request is blocked
+pub const DEFAULT_SCORE_THRESHOLD: u64 = 5;
+
+// The built-in rule sets as (rule set name, [(rule ID, message, targets, transforms, pattern)])
+#[allow(clippy::type_complexity)]
+const BUILTIN_RULE_SETS: &[(&str, &[(u64, &str, &[&str], &[&str], &str)])] = &[
+  (
+    "sqli",
+    &[
+      (
+        1001,
+        "SQL injection: UNION SELECT",
+        &["args", "body"],
+        &["compressWhitespace", "lowercase"],
+        r"\bunion( all)? select\b",
+      ),
+      (
+        1002,
+        "SQL injection: tautology",
+        &["args", "body"],
+        &["compressWhitespace", "lowercase"],
+        r#"['"`] ?(or|and) ?['"`]?(\w+)['"`]? ?= ?['"`]?\2\b"#,
+      ),
+      (
+        1003,
+        "SQL injection: stacked query",
+        &["args", "body"],
+        &["compressWhitespace", "lowercase"],
+        r"; ?(drop|delete|insert|update|alter|create|truncate|exec) ",
+      ),
+      (
+        1004,
+        "SQL injection: time-based blind injection",
+        &["args", "body"],
+        &["compressWhitespace", "lowercase"],
+        r"\b(sleep|benchmark|pg_sleep) ?\(|\bwaitfor delay\b",
+      ),
+      (
+        1005,
+        "SQL injection: database schema access",
+        &["args", "body"],
+        &["lowercase"],
+        r"\binformation_schema\b|\bpg_catalog\b|\bsqlite_master\b",
+      ),
+    ],
+  ),
+  (
+    "xss",
+    &[
+      (
+        2001,
+        "XSS: script tag",
+        &["path", "args", "body"],
+        &["htmlEntityDecode", "lowercase"],
+        r"<script[\s/>]",
+      ),
+      (
+        2002,
+        "XSS: JavaScript URL",
+        &["args", "body"],
+        &["htmlEntityDecode", "removeWhitespace", "lowercase"],
+        r"javascript:",
+      ),
+      (
+        2003,
+        "XSS: event handler attribute",
+        &["path", "args", "body"],
+        &["htmlEntityDecode", "lowercase"],
+        r"<[a-z][^>]*[\s/]on[a-z]+\s*=",
+      ),
+      (
+        2004,
+        "XSS: embedded content tag",
+        &["path", "args", "body"],
+        &["htmlEntityDecode", "lowercase"],
+        r"<(iframe|object|embed|svg|base)[\s/>]",
+      ),
+    ],
+  ),
+  (
+    "pathTraversal",
+    &[
+      (
+        3001,
+        "Path traversal: parent directory reference",
+        &["path", "args"],
+        &["urlDecode"],
+        r"(^|[\\/])\.\.([\\/]|$)",
+      ),
+      (
+        3002,
+        "Path traversal: sensitive file access",
+        &["path", "args"],
+        &["urlDecode", "normalizePath", "lowercase"],
+        r"/etc/(passwd|shadow|hosts)\b|/proc/self/|\bboot\.ini\b|\bwin\.ini\b",
+      ),
+    ],
+  ),
+];
+
+// A part of the request inspected by a WAF rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum WafTarget {
+  Method,
+  Path,
+  Query,
+  Args,
+  Headers,
+  Header(String),
+  Body,
+}
+
+impl WafTarget {
+  fn parse(target: &str) -> Option<Self> {
+    match target {
+      "method" => Some(Self::Method),
+      "path" => Some(Self::Path),
+      "query" => Some(Self::Query),
+      "args" => Some(Self::Args),
+      "headers" => Some(Self::Headers),
+      "body" => Some(Self::Body),
+      _ => target
+        .strip_prefix("header:")
+        .filter(|header_name| !header_name.is_empty())
+        .map(|header_name| Self::Header(header_name.to_lowercase())),
+    }
+  }
+
+  // Check if the rule target covers the inspected value
+  fn covers(&self, value: &WafValue) -> bool {
+    match (self, &value.target) {
+      (Self::Header(header_name), Self::Headers) => value.name.as_deref() == Some(header_name),
+      (target, value_target) => target == value_target,
+    }
+  }
+}
+
+// A transformation applied to the inspected value before matching the rule pattern, defeating simple evasions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WafTransform {
+  UrlDecode,
+  Lowercase,
+  HtmlEntityDecode,
+  CompressWhitespace,
+  RemoveWhitespace,
+  NormalizePath,
+}
+
+impl WafTransform {
+  fn parse(transform: &str) -> Option<Self> {
+    match transform {
+      "urlDecode" => Some(Self::UrlDecode),
+      "lowercase" => Some(Self::Lowercase),
+      "htmlEntityDecode" => Some(Self::HtmlEntityDecode),
+      "compressWhitespace" => Some(Self::CompressWhitespace),
+      "removeWhitespace" => Some(Self::RemoveWhitespace),
+      "normalizePath" => Some(Self::NormalizePath),
+      _ => None,
+    }
+  }
+
+  fn apply(self, value: String) -> String {
+    match self {
+      Self::UrlDecode => String::from_utf8_lossy(&urlencoding::decode_binary(
+        value.replace('+', " ").as_bytes(),
+      ))
+      .into_owned(),
+      Self::Lowercase => value.to_lowercase(),
+      Self::HtmlEntityDecode => html_entity_decode(&value),
+      Self::CompressWhitespace => value.split_whitespace().collect::<Vec<_>>().join(" "),
+      Self::RemoveWhitespace => value.split_whitespace().collect(),
+      Self::NormalizePath => normalize_path(&value),
+    }
+  }
+}
+
+// Decode the HTML entities, which are commonly used to hide the markup
+fn html_entity_decode(value: &str) -> String {
+  let mut decoded = String::with_capacity(value.len());
+  let mut rest = value;
+  while let Some(ampersand_index) = rest.find('&') {
+    decoded.push_str(&rest[..ampersand_index]);
+    rest = &rest[ampersand_index..];
+    let entity_end = rest
+      .char_indices()
+      .take(12)
+      .find(|(_, c)| *c == ';')
+      .map(|(index, _)| index);
+    let decoded_entity = entity_end.and_then(|entity_end| {
+      let entity = &rest[1..entity_end];
+      let decoded_char = match entity {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "colon" => Some(':'),
+        "sol" => Some('/'),
+        _ => match entity.strip_prefix('#') {
+          Some(code) => match code.strip_prefix(['x', 'X']) {
+            Some(hex_code) => u32::from_str_radix(hex_code, 16).ok(),
+            None => code.parse().ok(),
+          }
+          .and_then(char::from_u32),
+          None => None,
+        },
+      };
+      decoded_char.map(|decoded_char| (decoded_char, entity_end))
+    });
+    match decoded_entity {
+      Some((decoded_char, entity_end)) => {
+        decoded.push(decoded_char);
+        rest = &rest[(entity_end + 1)..];
+      }
+      None => {
+        decoded.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  decoded.push_str(rest);
+  decoded
+}
+
+// Normalize the path, converting the backslashes to slashes, and resolving the "." and ".." segments
+fn normalize_path(value: &str) -> String {
+  let value = value.replace('\\', "/");
+  let mut segments: Vec<&str> = Vec::new();
+  for segment in value.split('/') {
+    match segment {
+      "" | "." => (),
+      ".." => {
+        segments.pop();
+      }
+      segment => segments.push(segment),
+    }
+  }
+  let mut normalized = segments.join("/");
+  if value.starts_with('/') {
+    normalized.insert(0, '/');
+  }
+  normalized
+}
+
+// The action performed when the WAF rule matches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WafAction {
+  Block,
+  Log,
+  Score(u64),
+}
+
+impl WafAction {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Block => "block",
+      Self::Log => "log",
+      Self::Score(_) => "score",
+    }
+  }
+}
+
+// A WAF rule, matching a regular expression against the transformed values of the inspected request parts
+pub struct WafRule {
+  pub id: u64,
+  pub message: String,
+  targets: Vec<WafTarget>,
+  transforms: Vec<WafTransform>,
+  pattern: Regex,
+  pub action: WafAction,
+  // In the observe mode, the matches are logged and counted, but the action isn't enforced
+  pub observe: bool,
+}
+
+impl WafRule {
+  // Parse the WAF rule from the YAML configuration
+  pub fn from_yaml(rule_yaml: &Yaml) -> Result<Self, anyhow::Error> {
+    if rule_yaml.as_hash().is_none() {
+      Err(anyhow::anyhow!("Invalid WAF rule"))?
+    }
+    let id = rule_yaml["id"]
+      .as_i64()
+      .and_then(|id| u64::try_from(id).ok())
+      .ok_or_else(|| anyhow::anyhow!("Invalid or missing WAF rule ID"))?;
+
+    let mut targets = Vec::new();
+    for target_yaml in rule_yaml["targets"].as_vec().unwrap_or(&Vec::new()) {
+      targets.push(
+        target_yaml
+          .as_str()
+          .and_then(WafTarget::parse)
+          .ok_or_else(|| anyhow::anyhow!("Invalid target of the WAF rule {}", id))?,
+      );
+    }
+    if targets.is_empty() {
+      Err(anyhow::anyhow!("The WAF rule {} has no targets", id))?
+    }
+
+    let mut transforms = Vec::new();
+    if !rule_yaml["transforms"].is_badvalue() {
+      for transform_yaml in rule_yaml["transforms"]
+        .as_vec()
+        .ok_or_else(|| anyhow::anyhow!("Invalid transforms of the WAF rule {}", id))?
+      {
+        transforms.push(
+          transform_yaml
+            .as_str()
+            .and_then(WafTransform::parse)
+            .ok_or_else(|| anyhow::anyhow!("Invalid transform of the WAF rule {}", id))?,
+        );
+      }
+    }
+
+    let pattern = rule_yaml["pattern"]
+      .as_str()
+      .ok_or_else(|| anyhow::anyhow!("Invalid or missing pattern of the WAF rule {}", id))?;
+    let pattern = RegexBuilder::new(pattern)
+      .build()
+      .map_err(|err| anyhow::anyhow!("Invalid pattern of the WAF rule {}: {}", id, err))?;
+
+    let action = match rule_yaml["action"].as_str() {
+      Some("block") | None => WafAction::Block,
+      Some("log") => WafAction::Log,
+      Some("score") => WafAction::Score(match rule_yaml["score"].as_i64() {
+        Some(score) if score > 0 => score as u64,
+        None => 1,
+        _ => Err(anyhow::anyhow!("Invalid score of the WAF rule {}", id))?,
+      }),
+      _ => Err(anyhow::anyhow!("Invalid action of the WAF rule {}", id))?,
+    };
+    let observe = match rule_yaml["mode"].as_str() {
+      Some("enforce") | None => false,
+      Some("observe") => true,
+      _ => Err(anyhow::anyhow!("Invalid mode of the WAF rule {}", id))?,
+    };
+
+    Ok(Self {
+      id,
+      message: rule_yaml["message"]
+        .as_str()
+        .map(String::from)
+        .unwrap_or_else(|| format!("WAF rule {}", id)),
+      targets,
+      transforms,
+      pattern,
+      action,
+      observe,
+    })
+  }
+
+  // Check if the rule needs the request body to be inspected
+  pub fn inspects_body(&self) -> bool {
+    self
+      .targets
+      .iter()
+      .any(|target| matches!(target, WafTarget::Body | WafTarget::Args))
+  }
+
+  // Match the rule against the value. Returns true if the rule targets the value and its pattern matches.
+  fn matches(&self, value: &WafValue) -> bool {
+    if !self.targets.iter().any(|target| target.covers(value)) {
+      return false;
+    }
+    let transformed_value = self
+      .transforms
+      .iter()
+      .fold(value.value.clone(), |transformed_value, transform| {
+        transform.apply(transformed_value)
+      });
+    self.pattern.is_match(&transformed_value).unwrap_or(false)
+  }
+}
+
+// Parse the WAF rule set (a list of rules) from the YAML configuration
+pub fn parse_waf_rule_set(rule_set_yaml: &Yaml) -> Result<Vec<WafRule>, anyhow::Error> {
+  let mut rules: Vec<WafRule> = Vec::new();
+  for rule_yaml in rule_set_yaml
+    .as_vec()
+    .ok_or_else(|| anyhow::anyhow!("Invalid WAF rule set"))?
+  {
+    let rule = WafRule::from_yaml(rule_yaml)?;
+    if rules
+      .iter()
+      .any(|existing_rule| existing_rule.id == rule.id)
+    {
+      Err(anyhow::anyhow!("Duplicate WAF rule ID {}", rule.id))?
+    }
+    rules.push(rule);
+  }
+  Ok(rules)
+}
+
+// Check if the WAF rule set name is the name of a built-in rule set
+pub fn is_builtin_waf_rule_set(name: &str) -> bool {
+  BUILTIN_RULE_SETS
+    .iter()
+    .any(|(builtin_name, _)| *builtin_name == name)
+}
+
+// Load the built-in rule sets and the rule sets from the "wafRules" global configuration property
+pub fn load_waf_rule_sets(
+  waf_rules_yaml: &Yaml,
+) -> Result<HashMap<String, Arc<Vec<WafRule>>>, anyhow::Error> {
+  let mut rule_sets = HashMap::new();
+  for (name, builtin_rules) in BUILTIN_RULE_SETS {
+    let mut rules = Vec::new();
+    for (id, message, targets, transforms, pattern) in builtin_rules.iter() {
+      rules.push(WafRule {
+        id: *id,
+        message: message.to_string(),
+        targets: targets.iter().filter_map(|t| WafTarget::parse(t)).collect(),
+        transforms: transforms
+          .iter()
+          .filter_map(|t| WafTransform::parse(t))
+          .collect(),
+        pattern: Regex::new(pattern)?,
+        action: WafAction::Score(BUILTIN_RULE_SCORE),
+        observe: false,
+      });
+    }
+    rule_sets.insert(name.to_string(), Arc::new(rules));
+  }
+
+  if let Some(waf_rules) = waf_rules_yaml.as_hash() {
+    for (name, rule_set_yaml) in waf_rules {
+      let name = name
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid WAF rule set name"))?;
+      if is_builtin_waf_rule_set(name) {
+        Err(anyhow::anyhow!(
+          "The \"{}\" WAF rule set is a built-in rule set",
+          name
+        ))?
+      }
+      let rules = parse_waf_rule_set(rule_set_yaml)
+        .map_err(|err| anyhow::anyhow!("Invalid \"{}\" WAF rule set: {}", name, err))?;
+      rule_sets.insert(name.to_string(), Arc::new(rules));
+    }
+  }
+  Ok(rule_sets)
+}
+
+// A value of the inspected request part. The header values and the arguments are named.
+pub struct WafValue {
+  target: WafTarget,
+  name: Option<String>,
+  value: String,
+}
+
+impl WafValue {
+  pub fn new(target: WafTarget, name: Option<&str>, value: String) -> Self {
+    Self {
+      target,
+      name: name.map(|name| name.to_lowercase()),
+      value,
+    }
+  }
+
+  // Describe the inspected request part for the log messages
+  pub fn describe(&self) -> String {
+    let target = match self.target {
+      WafTarget::Method => "method",
+      WafTarget::Path => "path",
+      WafTarget::Query => "query string",
+      WafTarget::Args => "argument",
+      WafTarget::Headers | WafTarget::Header(_) => "header",
+      WafTarget::Body => "body",
+    };
+    match &self.name {
+      Some(name) => format!("{} \"{}\"", target, name),
+      None => target.to_string(),
+    }
+  }
+}
+
+// Parse the URL-encoded arguments (from the query string or the form body) into the values
+pub fn parse_waf_args(encoded_args: &str) -> Vec<WafValue> {
+  encoded_args
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| {
+      let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+      let decode = |encoded: &str| {
+        String::from_utf8_lossy(&urlencoding::decode_binary(
+          encoded.replace('+', " ").as_bytes(),
+        ))
+        .into_owned()
+      };
+      WafValue::new(WafTarget::Args, Some(&decode(name)), decode(value))
+    })
+    .collect()
+}
+
+// A match of a WAF rule against the request
+pub struct WafMatch {
+  pub rule_id: u64,
+  pub message: String,
+  pub action: WafAction,
+  pub observe: bool,
+  pub location: String,
+}
+
+// The inspection of a single request, accumulating the anomaly score across the inspection phases
+pub struct WafInspection {
+  rules: Vec<Arc<Vec<WafRule>>>,
+  score_threshold: u64,
+  score: u64,
+}
+
+impl WafInspection {
+  pub fn new(rules: Vec<Arc<Vec<WafRule>>>, score_threshold: u64) -> Self {
+    Self {
+      rules,
+      score_threshold,
+      score: 0,
+    }
+  }
+
+  // Check if any rule needs the request body to be inspected
+  pub fn inspects_body(&self) -> bool {
+    self
+      .rules
+      .iter()
+      .any(|rules| rules.iter().any(|rule| rule.inspects_body()))
+  }
+
+  // Inspect the values. Returns the rule matches and whether the request has to be blocked.
+  // Each rule matches at most once for the inspected values.
+  pub fn inspect(&mut self, values: &[WafValue]) -> (Vec<WafMatch>, bool) {
+    let mut matches = Vec::new();
+    let mut blocked = false;
+    for rule in self.rules.iter().flat_map(|rules| rules.iter()) {
+      if let Some(value) = values.iter().find(|value| rule.matches(value)) {
+        match rule.action {
+          _ if rule.observe => (),
+          WafAction::Block => blocked = true,
+          WafAction::Score(score) => self.score += score,
+          WafAction::Log => (),
+        }
+        matches.push(WafMatch {
+          rule_id: rule.id,
+          message: rule.message.clone(),
+          action: rule.action,
+          observe: rule.observe,
+          location: value.describe(),
+        });
+      }
+    }
+    (matches, blocked || self.score >= self.score_threshold)
+  }
+
+  pub fn score(&self) -> u64 {
+    self.score
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use yaml_rust2::YamlLoader;
+
+  fn builtin_inspection(rule_set_names: &[&str]) -> WafInspection {
+    let rule_sets = load_waf_rule_sets(&Yaml::BadValue).unwrap();
+    WafInspection::new(
+      rule_set_names
+        .iter()
+        .map(|name| rule_sets[*name].clone())
+        .collect(),
+      DEFAULT_SCORE_THRESHOLD,
+    )
+  }
+
+  #[test]
+  fn test_transforms() {
+    assert_eq!(
+      WafTransform::UrlDecode.apply(String::from("%2e%2E%2Fetc+x")),
+      "../etc x"
+    );
+    assert_eq!(
+      WafTransform::HtmlEntityDecode.apply(String::from("&lt;a&#x3E;&#106; &unknown; &")),
+      "<a>j &unknown; &"
+    );
+    assert_eq!(
+      WafTransform::CompressWhitespace.apply(String::from(" union \t\n select ")),
+      "union select"
+    );
+    assert_eq!(
+      WafTransform::NormalizePath.apply(String::from("/var/www/..\\..//etc/./passwd")),
+      "/etc/passwd"
+    );
+  }
+
+  #[test]
+  fn test_builtin_rule_sets() {
+    let mut inspection = builtin_inspection(&["sqli", "xss", "pathTraversal"]);
+    let (matches, blocked) = inspection.inspect(&parse_waf_args("q=hello+world&page=2"));
+    assert!(matches.is_empty());
+    assert!(!blocked);
+
+    for (target, value) in [
+      (WafTarget::Args, "1 UNION\n ALL SELECT password FROM users"),
+      (WafTarget::Args, "' or 'a'='a"),
+      (WafTarget::Args, "1; DROP TABLE users"),
+      (WafTarget::Body, "<img src=x onerror=alert(1)>"),
+      (WafTarget::Args, "java\tscript&colon;alert(1)"),
+      (WafTarget::Path, "/static/..%2f..%2fetc/passwd"),
+    ] {
+      let mut inspection = builtin_inspection(&["sqli", "xss", "pathTraversal"]);
+      let (matches, blocked) =
+        inspection.inspect(&[WafValue::new(target, None, value.to_string())]);
+      assert!(!matches.is_empty(), "{} isn't detected", value);
+      assert!(blocked);
+    }
+  }
+
+  #[test]
+  fn test_custom_rules() {
+    let rules_yaml = YamlLoader::load_from_str(
+      "custom:\n\
+       \x20 - id: 1\n    targets: [\"header:User-Agent\"]\n    pattern: \"(?i)sqlmap\"\n    action: score\n    score: 3\n\
+       \x20 - id: 2\n    targets: [method]\n    pattern: \"^TRACE$\"\n    action: log\n\
+       \x20 - id: 3\n    targets: [query]\n    pattern: \"debug=1\"\n",
+    )
+    .unwrap()
+    .remove(0);
+    let rule_sets = load_waf_rule_sets(&rules_yaml).unwrap();
+    let mut inspection = WafInspection::new(vec![rule_sets["custom"].clone()], 5);
+    assert!(!inspection.inspects_body());
+
+    let (matches, blocked) = inspection.inspect(&[
+      WafValue::new(WafTarget::Method, None, String::from("TRACE")),
+      WafValue::new(
+        WafTarget::Headers,
+        Some("User-Agent"),
+        String::from("sqlmap/1.7"),
+      ),
+    ]);
+    assert_eq!(matches.len(), 2);
+    assert!(!blocked);
+    assert_eq!(inspection.score(), 3);
+    assert_eq!(matches[0].location, "header \"user-agent\"");
+
+    let (_, blocked) = inspection.inspect(&[WafValue::new(
+      WafTarget::Headers,
+      Some("User-Agent"),
+      String::from("sqlmap"),
+    )]);
+    assert!(blocked);
+
+    let (matches, blocked) =
+      WafInspection::new(vec![rule_sets["custom"].clone()], 5).inspect(&[WafValue::new(
+        WafTarget::Query,
+        None,
+        String::from("a=1&debug=1"),
+      )]);
+    assert_eq!(matches[0].action, WafAction::Block);
+    assert!(blocked);
+  }
+
+  #[test]
+  fn test_observed_rules() {
+    let rules_yaml = YamlLoader::load_from_str(
+      "custom:\n\
+       \x20 - id: 1\n    targets: [query]\n    pattern: \"debug=1\"\n    mode: observe\n\
+       \x20 - id: 2\n    targets: [query]\n    pattern: \"admin=1\"\n    action: score\n    score: 5\n    mode: observe\n\
+       \x20 - id: 3\n    targets: [query]\n    pattern: \"trace=1\"\n    mode: enforce\n",
+    )
+    .unwrap()
+    .remove(0);
+    let rule_sets = load_waf_rule_sets(&rules_yaml).unwrap();
+    let mut inspection = WafInspection::new(vec![rule_sets["custom"].clone()], 5);
+    let (matches, blocked) = inspection.inspect(&[WafValue::new(
+      WafTarget::Query,
+      None,
+      String::from("debug=1&admin=1"),
+    )]);
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|waf_match| waf_match.observe));
+    assert!(!blocked);
+    assert_eq!(inspection.score(), 0);
+
+    let (matches, blocked) = inspection.inspect(&[WafValue::new(
+      WafTarget::Query,
+      None,
+      String::from("debug=1&trace=1"),
+    )]);
+    assert!(!matches[1].observe);
+    assert!(blocked);
+  }
+
+  #[test]
+  fn test_invalid_rules() {
+    for rules in [
+      "sqli: []",
+      "custom: [{id: 1, targets: [cookies], pattern: a}]",
+      "custom: [{id: 1, targets: [path], pattern: \"(\"}]",
+      "custom: [{id: 1, targets: [path], pattern: a}, {id: 1, targets: [path], pattern: b}]",
+      "custom: [{id: 1, targets: [path], pattern: a, action: drop}]",
+      "custom: [{id: 1, targets: [path], pattern: a, mode: dryRun}]",
+    ] {
+      let rules_yaml = YamlLoader::load_from_str(rules).unwrap().remove(0);
+      assert!(load_waf_rule_sets(&rules_yaml).is_err(), "{}", rules);
+    }
+  }
+}
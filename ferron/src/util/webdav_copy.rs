@@ -0,0 +1,151 @@
+use std::io;
+use std::path::Path;
+
+use tokio::fs;
+
+// The maximum depth of the directory trees copied by WebDAV
+pub const MAX_COPY_DEPTH: usize = 64;
+
+// Copy a file or a directory. If "recursive" is false, only the directory itself is created.
+// The symbolic links aren't followed, so that a link can't copy the files from outside the WebDAV share
+// nor make the copy loop. The links and the special files (for example, FIFOs) in the directory tree are skipped,
+// and copying a link itself fails.
+pub async fn copy_resource(
+  source: &Path,
+  destination: &Path,
+  recursive: bool,
+) -> Result<(), io::Error> {
+  let source_metadata = fs::symlink_metadata(source).await?;
+  if source_metadata.is_file() {
+    fs::copy(source, destination).await?;
+    return Ok(());
+  } else if !source_metadata.is_dir() {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      "Only the files and the directories can be copied",
+    ));
+  }
+
+  fs::create_dir(destination).await?;
+  if !recursive {
+    return Ok(());
+  }
+
+  // Copy the directory tree iteratively, so that deeply nested directories don't overflow the stack
+  let mut directories = vec![(source.to_path_buf(), destination.to_path_buf(), 1)];
+  while let Some((source_directory, destination_directory, depth)) = directories.pop() {
+    let mut entries = fs::read_dir(&source_directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+      let destination_entry = destination_directory.join(entry.file_name());
+      let file_type = entry.file_type().await?;
+      if file_type.is_dir() {
+        if depth >= MAX_COPY_DEPTH {
+          return Err(io::Error::other("The directory tree is too deep to copy"));
+        }
+        fs::create_dir(&destination_entry).await?;
+        directories.push((entry.path(), destination_entry, depth + 1));
+      } else if file_type.is_file() {
+        fs::copy(entry.path(), destination_entry).await?;
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn test_directory(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+      "ferron-webdav-copy-{}-{}",
+      name,
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+  }
+
+  #[tokio::test]
+  async fn test_copy_directory_tree() {
+    let path = test_directory("tree");
+    std::fs::create_dir_all(path.join("source/nested")).unwrap();
+    std::fs::write(path.join("source/file.txt"), "file").unwrap();
+    std::fs::write(path.join("source/nested/nested.txt"), "nested").unwrap();
+
+    copy_resource(&path.join("source"), &path.join("copy"), true)
+      .await
+      .unwrap();
+    assert_eq!(
+      std::fs::read_to_string(path.join("copy/nested/nested.txt")).unwrap(),
+      "nested"
+    );
+
+    copy_resource(&path.join("source"), &path.join("shallow"), false)
+      .await
+      .unwrap();
+    assert_eq!(std::fs::read_dir(path.join("shallow")).unwrap().count(), 0);
+    std::fs::remove_dir_all(&path).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn test_copy_skips_symlinks() {
+    let path = test_directory("symlinks");
+    std::fs::create_dir_all(path.join("source")).unwrap();
+    std::fs::create_dir_all(path.join("outside")).unwrap();
+    std::fs::write(path.join("outside/secret.txt"), "secret").unwrap();
+    std::fs::write(path.join("source/file.txt"), "file").unwrap();
+    // A link to the directory itself would make the copy loop, and the other links point outside the share
+    std::os::unix::fs::symlink(path.join("source"), path.join("source/loop")).unwrap();
+    std::os::unix::fs::symlink(path.join("outside"), path.join("source/outside")).unwrap();
+    std::os::unix::fs::symlink(
+      path.join("outside/secret.txt"),
+      path.join("source/secret.txt"),
+    )
+    .unwrap();
+
+    copy_resource(&path.join("source"), &path.join("copy"), true)
+      .await
+      .unwrap();
+    let mut copied = std::fs::read_dir(path.join("copy"))
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect::<Vec<_>>();
+    copied.sort();
+    assert_eq!(copied, vec!["file.txt"]);
+
+    assert_eq!(
+      copy_resource(
+        &path.join("source/secret.txt"),
+        &path.join("secret.txt"),
+        true
+      )
+      .await
+      .unwrap_err()
+      .kind(),
+      io::ErrorKind::InvalidInput
+    );
+    assert!(!path.join("secret.txt").exists());
+    std::fs::remove_dir_all(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_copy_depth_is_bounded() {
+    let path = test_directory("depth");
+    let mut deep_path = path.join("source");
+    for _ in 0..MAX_COPY_DEPTH {
+      deep_path.push("d");
+    }
+    std::fs::create_dir_all(&deep_path).unwrap();
+
+    assert!(
+      copy_resource(&path.join("source"), &path.join("copy"), true)
+        .await
+        .is_err()
+    );
+    std::fs::remove_dir_all(&path).unwrap();
+  }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// A WebDAV write lock on a resource
+#[derive(Clone, Debug)]
+pub struct WebDavLock {
+  pub token: String,
+  pub path: String,
+  pub exclusive: bool,
+  pub depth_infinity: bool,
+  pub owner: Option<String>,
+  pub timeout: Duration,
+  expires: Instant,
+}
+
+// Check if a path is the same as, or is inside of the other path. The paths don't have trailing slashes.
+pub fn is_same_or_descendant(path: &str, ancestor: &str) -> bool {
+  path == ancestor
+    || (path.starts_with(ancestor) && path[ancestor.len()..].starts_with('/'))
+    || ancestor.is_empty()
+}
+
+// The table of the WebDAV locks, keyed by the lock token
+#[derive(Default)]
+pub struct WebDavLocks {
+  locks: HashMap<String, WebDavLock>,
+}
+
+impl WebDavLocks {
+  pub fn new() -> Self {
+    Self {
+      locks: HashMap::new(),
+    }
+  }
+
+  fn remove_expired(&mut self) {
+    let now = Instant::now();
+    self.locks.retain(|_, lock| lock.expires > now);
+  }
+
+  // Check if a lock applies to a resource, either directly or through a depth-infinity lock on a parent collection
+  fn lock_covers(lock: &WebDavLock, path: &str) -> bool {
+    lock.path == path || (lock.depth_infinity && is_same_or_descendant(path, &lock.path))
+  }
+
+  // Get the locks applying to a resource
+  pub fn get_locks(&mut self, path: &str) -> Vec<WebDavLock> {
+    self.remove_expired();
+    self
+      .locks
+      .values()
+      .filter(|lock| Self::lock_covers(lock, path))
+      .cloned()
+      .collect()
+  }
+
+  // Create a new lock. Returns None if the lock conflicts with the existing locks.
+  pub fn lock(
+    &mut self,
+    path: &str,
+    exclusive: bool,
+    depth_infinity: bool,
+    owner: Option<String>,
+    timeout: Duration,
+  ) -> Option<WebDavLock> {
+    self.remove_expired();
+    let conflicts = self.locks.values().any(|lock| {
+      (exclusive || lock.exclusive)
+        && (Self::lock_covers(lock, path)
+          || (depth_infinity && is_same_or_descendant(&lock.path, path)))
+    });
+    if conflicts {
+      return None;
+    }
+
+    let lock = WebDavLock {
+      token: format!("opaquelocktoken:{}", generate_uuid()),
+      path: path.to_string(),
+      exclusive,
+      depth_infinity,
+      owner,
+      timeout,
+      expires: Instant::now() + timeout,
+    };
+    self.locks.insert(lock.token.clone(), lock.clone());
+    Some(lock)
+  }
+
+  // Refresh a lock applying to a resource, using one of the submitted lock tokens
+  pub fn refresh(
+    &mut self,
+    path: &str,
+    tokens: &[String],
+    timeout: Duration,
+  ) -> Option<WebDavLock> {
+    self.remove_expired();
+    let token = tokens.iter().find(|token| {
+      self
+        .locks
+        .get(*token)
+        .is_some_and(|lock| Self::lock_covers(lock, path))
+    })?;
+    let lock = self.locks.get_mut(token)?;
+    lock.timeout = timeout;
+    lock.expires = Instant::now() + timeout;
+    Some(lock.clone())
+  }
+
+  // Remove a lock applying to a resource. Returns false if there is no such lock.
+  pub fn unlock(&mut self, path: &str, token: &str) -> bool {
+    self.remove_expired();
+    match self.locks.get(token) {
+      Some(lock) if Self::lock_covers(lock, path) => {
+        self.locks.remove(token);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // Check if a resource can be modified with the submitted lock tokens.
+  // If "include_descendants" is true, the locks on the resources inside of the collection are checked too.
+  pub fn can_modify(&mut self, path: &str, tokens: &[String], include_descendants: bool) -> bool {
+    self.remove_expired();
+    self.locks.values().all(|lock| {
+      let applies = Self::lock_covers(lock, path)
+        || (include_descendants && is_same_or_descendant(&lock.path, path));
+      !applies || tokens.contains(&lock.token)
+    })
+  }
+
+  // Remove the locks on a resource and the resources inside of it, after it's deleted or moved
+  pub fn remove_locks(&mut self, path: &str) {
+    self
+      .locks
+      .retain(|_, lock| !is_same_or_descendant(&lock.path, path));
+  }
+}
+
+// Generate a random (version 4) UUID for a lock token
+fn generate_uuid() -> String {
+  let mut bytes: [u8; 16] = rand::random();
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex = bytes
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect::<String>();
+  format!(
+    "{}-{}-{}-{}-{}",
+    &hex[0..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..32]
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TIMEOUT: Duration = Duration::from_secs(60);
+
+  #[test]
+  fn test_exclusive_lock_conflicts() {
+    let mut locks = WebDavLocks::new();
+    let lock = locks.lock("/dav/docs", true, true, None, TIMEOUT).unwrap();
+    assert!(lock.token.starts_with("opaquelocktoken:"));
+    assert!(locks
+      .lock("/dav/docs/a.txt", false, false, None, TIMEOUT)
+      .is_none());
+    assert!(locks.lock("/dav", true, true, None, TIMEOUT).is_none());
+    assert!(locks
+      .lock("/dav/other", true, true, None, TIMEOUT)
+      .is_some());
+  }
+
+  #[test]
+  fn test_shared_locks() {
+    let mut locks = WebDavLocks::new();
+    assert!(locks
+      .lock("/dav/a.txt", false, false, None, TIMEOUT)
+      .is_some());
+    assert!(locks
+      .lock("/dav/a.txt", false, false, None, TIMEOUT)
+      .is_some());
+    assert!(locks
+      .lock("/dav/a.txt", true, false, None, TIMEOUT)
+      .is_none());
+    assert_eq!(locks.get_locks("/dav/a.txt").len(), 2);
+  }
+
+  #[test]
+  fn test_can_modify_with_tokens() {
+    let mut locks = WebDavLocks::new();
+    let lock = locks.lock("/dav/docs", true, true, None, TIMEOUT).unwrap();
+    assert!(!locks.can_modify("/dav/docs/a.txt", &[], false));
+    assert!(locks.can_modify("/dav/docs/a.txt", std::slice::from_ref(&lock.token), false));
+    assert!(locks.can_modify("/dav", &[], false));
+    assert!(!locks.can_modify("/dav", &[], true));
+    assert!(!locks.unlock("/dav/other", &lock.token));
+    assert!(locks.unlock("/dav/docs/a.txt", &lock.token));
+    assert!(locks.can_modify("/dav/docs/a.txt", &[], false));
+  }
+
+  #[test]
+  fn test_lock_expiration_and_refresh() {
+    let mut locks = WebDavLocks::new();
+    let lock = locks
+      .lock("/dav/a.txt", true, false, None, Duration::from_millis(10))
+      .unwrap();
+    assert!(locks
+      .refresh(
+        "/dav/a.txt",
+        std::slice::from_ref(&lock.token),
+        Duration::from_millis(10)
+      )
+      .is_some());
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(locks.get_locks("/dav/a.txt").is_empty());
+    assert!(locks
+      .refresh("/dav/a.txt", &[lock.token], TIMEOUT)
+      .is_none());
+  }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+// An XML element with a resolved namespace, as used in WebDAV request bodies
+#[derive(Clone, Debug, PartialEq)]
+pub struct XmlElement {
+  pub namespace: String,
+  pub name: String,
+  pub text: String,
+  pub children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+  // Find the first child element with a specified namespace and name
+  pub fn child(&self, namespace: &str, name: &str) -> Option<&XmlElement> {
+    self
+      .children
+      .iter()
+      .find(|child| child.namespace == namespace && child.name == name)
+  }
+
+  pub fn is(&self, namespace: &str, name: &str) -> bool {
+    self.namespace == namespace && self.name == name
+  }
+
+  // Serialize the element, declaring its namespace (even an empty one) as the default one
+  pub fn to_xml_string(&self) -> String {
+    let mut xml = format!("<{} xmlns=\"{}\"", self.name, escape_xml(&self.namespace));
+    if self.text.is_empty() && self.children.is_empty() {
+      xml.push_str("/>");
+      return xml;
+    }
+    xml.push('>');
+    xml.push_str(&escape_xml(&self.text));
+    for child in self.children.iter() {
+      xml.push_str(&child.to_xml_string());
+    }
+    xml.push_str(&format!("</{}>", self.name));
+    xml
+  }
+}
+
+// Escape the text for use in XML text nodes and attribute values
+pub fn escape_xml(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> Result<String, anyhow::Error> {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut remaining = text;
+  while let Some(ampersand_index) = remaining.find('&') {
+    unescaped.push_str(&remaining[..ampersand_index]);
+    remaining = &remaining[ampersand_index + 1..];
+    let semicolon_index = remaining
+      .find(';')
+      .ok_or(anyhow::anyhow!("Unterminated XML entity"))?;
+    let entity = &remaining[..semicolon_index];
+    let character = match entity {
+      "amp" => '&',
+      "lt" => '<',
+      "gt" => '>',
+      "quot" => '"',
+      "apos" => '\'',
+      _ => {
+        let code_point = if let Some(hex) = entity
+          .strip_prefix("#x")
+          .or_else(|| entity.strip_prefix("#X"))
+        {
+          u32::from_str_radix(hex, 16)?
+        } else if let Some(decimal) = entity.strip_prefix('#') {
+          decimal.parse::<u32>()?
+        } else {
+          Err(anyhow::anyhow!("Unknown XML entity: {}", entity))?
+        };
+        char::from_u32(code_point).ok_or(anyhow::anyhow!("Invalid XML character reference"))?
+      }
+    };
+    unescaped.push(character);
+    remaining = &remaining[semicolon_index + 1..];
+  }
+  unescaped.push_str(remaining);
+  Ok(unescaped)
+}
+
+struct OpenElement {
+  prefixed_name: String,
+  element: XmlElement,
+  namespaces: HashMap<String, String>,
+}
+
+// Find the end of the tag, skipping the ">" characters in the quoted attribute values
+fn find_tag_end(tag: &str) -> Option<usize> {
+  let mut quote = None;
+  for (index, character) in tag.char_indices() {
+    match (quote, character) {
+      (None, '>') => return Some(index),
+      (None, '"' | '\'') => quote = Some(character),
+      (Some(opening_quote), _) if opening_quote == character => quote = None,
+      _ => (),
+    }
+  }
+  None
+}
+
+// Parse the tag contents (without the angle brackets) into the name and the attributes
+#[allow(clippy::type_complexity)]
+fn parse_tag(tag: &str) -> Result<(&str, Vec<(&str, String)>), anyhow::Error> {
+  let tag = tag.trim();
+  let name_end = tag
+    .find(|character: char| character.is_whitespace())
+    .unwrap_or(tag.len());
+  let name = &tag[..name_end];
+  if name.is_empty() {
+    Err(anyhow::anyhow!("Empty XML tag name"))?
+  }
+
+  let mut attributes = Vec::new();
+  let mut remaining = tag[name_end..].trim_start();
+  while !remaining.is_empty() {
+    let equals_index = remaining
+      .find('=')
+      .ok_or(anyhow::anyhow!("Invalid XML attribute"))?;
+    let attribute_name = remaining[..equals_index].trim();
+    remaining = remaining[equals_index + 1..].trim_start();
+    let quote = match remaining.chars().next() {
+      Some(quote @ ('"' | '\'')) => quote,
+      _ => Err(anyhow::anyhow!("Unquoted XML attribute value"))?,
+    };
+    let value_end = remaining[1..]
+      .find(quote)
+      .ok_or(anyhow::anyhow!("Unterminated XML attribute value"))?;
+    attributes.push((attribute_name, unescape_xml(&remaining[1..value_end + 1])?));
+    remaining = remaining[value_end + 2..].trim_start();
+  }
+
+  Ok((name, attributes))
+}
+
+// Parse an XML document into the tree of elements with resolved namespaces. Only the elements and the text are kept.
+pub fn parse_xml(document: &str) -> Result<XmlElement, anyhow::Error> {
+  let mut stack: Vec<OpenElement> = Vec::new();
+  let mut root = None;
+  let mut remaining = document;
+
+  while !remaining.is_empty() {
+    let tag_start = match remaining.find('<') {
+      Some(tag_start) => tag_start,
+      None => {
+        if !remaining.trim().is_empty() {
+          Err(anyhow::anyhow!("Text outside of the XML root element"))?
+        }
+        break;
+      }
+    };
+    let text = &remaining[..tag_start];
+    if let Some(open_element) = stack.last_mut() {
+      open_element.element.text.push_str(&unescape_xml(text)?);
+    } else if !text.trim().is_empty() {
+      Err(anyhow::anyhow!("Text outside of the XML root element"))?
+    }
+    remaining = &remaining[tag_start..];
+
+    if let Some(after_comment_start) = remaining.strip_prefix("<!--") {
+      let comment_end = after_comment_start
+        .find("-->")
+        .ok_or(anyhow::anyhow!("Unterminated XML comment"))?;
+      remaining = &after_comment_start[comment_end + 3..];
+    } else if let Some(after_cdata_start) = remaining.strip_prefix("<![CDATA[") {
+      let cdata_end = after_cdata_start
+        .find("]]>")
+        .ok_or(anyhow::anyhow!("Unterminated XML CDATA section"))?;
+      match stack.last_mut() {
+        Some(open_element) => open_element
+          .element
+          .text
+          .push_str(&after_cdata_start[..cdata_end]),
+        None => Err(anyhow::anyhow!(
+          "CDATA section outside of the XML root element"
+        ))?,
+      }
+      remaining = &after_cdata_start[cdata_end + 3..];
+    } else if remaining.starts_with("<?") {
+      let declaration_end = remaining
+        .find("?>")
+        .ok_or(anyhow::anyhow!("Unterminated XML processing instruction"))?;
+      remaining = &remaining[declaration_end + 2..];
+    } else if remaining.starts_with("<!") {
+      // Document type declarations aren't supported, so that entity expansion attacks aren't possible
+      Err(anyhow::anyhow!(
+        "XML document type declarations aren't supported"
+      ))?
+    } else {
+      let tag_end = find_tag_end(remaining).ok_or(anyhow::anyhow!("Unterminated XML tag"))?;
+      let tag = &remaining[1..tag_end];
+      remaining = &remaining[tag_end + 1..];
+
+      if let Some(end_tag_name) = tag.strip_prefix('/') {
+        let open_element = stack
+          .pop()
+          .ok_or(anyhow::anyhow!("Unexpected XML end tag"))?;
+        if open_element.prefixed_name != end_tag_name.trim() {
+          Err(anyhow::anyhow!("Mismatched XML end tag"))?
+        }
+        let element = open_element.element;
+        match stack.last_mut() {
+          Some(parent) => parent.element.children.push(element),
+          None => root = Some(element),
+        }
+        continue;
+      }
+
+      let (tag, self_closing) = match tag.strip_suffix('/') {
+        Some(tag) => (tag, true),
+        None => (tag, false),
+      };
+      let (prefixed_name, attributes) = parse_tag(tag)?;
+
+      // Resolve the namespace declarations, inheriting the ones from the parent element
+      let mut namespaces = stack
+        .last()
+        .map(|parent| parent.namespaces.clone())
+        .unwrap_or_default();
+      for (attribute_name, attribute_value) in attributes {
+        if attribute_name == "xmlns" {
+          namespaces.insert(String::new(), attribute_value);
+        } else if let Some(prefix) = attribute_name.strip_prefix("xmlns:") {
+          namespaces.insert(prefix.to_string(), attribute_value);
+        }
+      }
+      let (prefix, name) = match prefixed_name.split_once(':') {
+        Some((prefix, name)) => (Some(prefix.to_string()), name),
+        None => (None, prefixed_name),
+      };
+      let namespace = match namespaces.get(prefix.as_deref().unwrap_or("")) {
+        Some(namespace) => namespace.clone(),
+        None if prefix.is_none() => String::new(),
+        None => Err(anyhow::anyhow!("Undeclared XML namespace prefix"))?,
+      };
+
+      let open_element = OpenElement {
+        prefixed_name: prefixed_name.to_string(),
+        element: XmlElement {
+          namespace,
+          name: name.to_string(),
+          text: String::new(),
+          children: Vec::new(),
+        },
+        namespaces,
+      };
+
+      if root.is_some() {
+        Err(anyhow::anyhow!("Multiple XML root elements"))?
+      }
+      if self_closing {
+        match stack.last_mut() {
+          Some(parent) => parent.element.children.push(open_element.element),
+          None => root = Some(open_element.element),
+        }
+      } else {
+        stack.push(open_element);
+      }
+    }
+  }
+
+  if !stack.is_empty() {
+    Err(anyhow::anyhow!("Unclosed XML element"))?
+  }
+  let mut root = root.ok_or(anyhow::anyhow!("Missing XML root element"))?;
+  trim_whitespace_text(&mut root);
+  Ok(root)
+}
+
+// Remove the whitespace-only text between the child elements
+fn trim_whitespace_text(element: &mut XmlElement) {
+  if element.text.trim().is_empty() {
+    element.text.clear();
+  }
+  for child in element.children.iter_mut() {
+    trim_whitespace_text(child);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_propfind() {
+    let root = parse_xml(
+      "<?xml version=\"1.0\" encoding=\"utf-8\" ?>
+<D:propfind xmlns:D=\"DAV:\">
+  <!-- A comment -->
+  <D:prop xmlns:R=\"http://ns.example.com/boxschema/\">
+    <R:bigbox/>
+    <D:getcontentlength />
+  </D:prop>
+</D:propfind>",
+    )
+    .unwrap();
+
+    assert!(root.is("DAV:", "propfind"));
+    let prop = root.child("DAV:", "prop").unwrap();
+    assert_eq!(prop.children.len(), 2);
+    assert!(prop.children[0].is("http://ns.example.com/boxschema/", "bigbox"));
+    assert!(prop.children[1].is("DAV:", "getcontentlength"));
+  }
+
+  #[test]
+  fn test_parse_text_and_default_namespace() {
+    let root = parse_xml(
+      "<propertyupdate xmlns=\"DAV:\"><set><prop><Z:Author xmlns:Z=\"http://ns.example.com/z/\">Jim &amp; Roy &#x263A;</Z:Author><note><![CDATA[<b>bold</b>]]></note></prop></set></propertyupdate>",
+    )
+    .unwrap();
+
+    let prop = root
+      .child("DAV:", "set")
+      .and_then(|set| set.child("DAV:", "prop"))
+      .unwrap();
+    assert_eq!(prop.children[0].text, "Jim & Roy \u{263A}");
+    assert_eq!(prop.children[1].text, "<b>bold</b>");
+    assert_eq!(
+      prop.children[0].to_xml_string(),
+      "<Author xmlns=\"http://ns.example.com/z/\">Jim &amp; Roy \u{263A}</Author>"
+    );
+  }
+
+  #[test]
+  fn test_invalid_xml() {
+    assert!(parse_xml("<a><b></a>").is_err());
+    assert!(parse_xml("<a>").is_err());
+    assert!(parse_xml("<x:a/>").is_err());
+    assert!(parse_xml("<!DOCTYPE a [<!ENTITY x \"y\">]><a/>").is_err());
+    assert!(parse_xml("<a/><b/>").is_err());
+  }
+}